@@ -0,0 +1,50 @@
+//! Captures the git commit and build time for `GET /version`.
+//!
+//! `GIT_COMMIT` overrides the commit (Docker builds have no `.git`), and
+//! `SOURCE_DATE_EPOCH` the build time, for reproducible builds.
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+fn git(args: &[&str]) -> Option<String> {
+    let out = Command::new("git").args(args).output().ok()?;
+    if !out.status.success() {
+        return None;
+    }
+    let text = String::from_utf8(out.stdout).ok()?;
+    Some(text.trim().to_string()).filter(|t| !t.is_empty())
+}
+
+fn main() {
+    println!("cargo:rerun-if-env-changed=GIT_COMMIT");
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+    println!("cargo:rerun-if-changed=src");
+    println!("cargo:rerun-if-changed=migrations");
+    println!("cargo:rerun-if-changed=Cargo.toml");
+
+    // Rebuild when HEAD moves to another commit
+    if let Some(git_dir) = git(&["rev-parse", "--absolute-git-dir"]) {
+        println!("cargo:rerun-if-changed={}/HEAD", git_dir);
+        if let Some(head_ref) = git(&["symbolic-ref", "-q", "HEAD"]) {
+            println!("cargo:rerun-if-changed={}/{}", git_dir, head_ref);
+        }
+    }
+
+    let commit = std::env::var("GIT_COMMIT")
+        .ok()
+        .filter(|c| !c.trim().is_empty())
+        .or_else(|| git(&["rev-parse", "HEAD"]))
+        .unwrap_or_else(|| "unknown".to_string());
+
+    let built_at = std::env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|v| v.trim().parse::<u64>().ok())
+        .unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default()
+        });
+
+    println!("cargo:rustc-env=SOUNDSENSE_GIT_COMMIT={}", commit);
+    println!("cargo:rustc-env=SOUNDSENSE_BUILD_EPOCH={}", built_at);
+}
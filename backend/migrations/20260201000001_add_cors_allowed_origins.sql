@@ -0,0 +1,19 @@
+-- Migration: Browser origin allowlist for CORS
+-- Date: 2026-02-01
+
+CREATE TABLE IF NOT EXISTS cors_allowed_origins (
+    origin TEXT PRIMARY KEY,  -- Exact origin or wildcard pattern (*.hospital.example.com)
+    description TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+
+    CONSTRAINT origin_not_empty CHECK (LENGTH(TRIM(origin)) > 0)
+);
+
+-- Default development origins (local frontend and GitHub Codespaces)
+INSERT INTO cors_allowed_origins (origin, description) VALUES
+    ('http://localhost:5173', 'Local frontend (docker-compose)'),
+    ('http://127.0.0.1:5173', 'Local frontend (docker-compose)'),
+    ('https://*.app.github.dev', 'GitHub Codespaces forwarded ports')
+ON CONFLICT (origin) DO NOTHING;
+
+COMMENT ON TABLE cors_allowed_origins IS 'Origins permitted to make cross-origin requests to the API';
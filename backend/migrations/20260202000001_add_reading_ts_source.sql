@@ -0,0 +1,10 @@
+-- Migration: Record whether a reading's timestamp came from the device or the server
+-- Date: 2026-02-02
+
+ALTER TABLE sensor_readings
+    ADD COLUMN IF NOT EXISTS ts_source TEXT NOT NULL DEFAULT 'device';
+
+ALTER TABLE sensor_readings
+    ADD CONSTRAINT ts_source_valid CHECK (ts_source IN ('device', 'server'));
+
+COMMENT ON COLUMN sensor_readings.ts_source IS 'device = timestamp sent by the sensor, server = assigned on ingest';
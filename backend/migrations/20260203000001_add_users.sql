@@ -0,0 +1,17 @@
+-- Migration: User accounts with hashed passwords
+-- Date: 2026-02-03
+
+CREATE TABLE IF NOT EXISTS users (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    username TEXT NOT NULL UNIQUE,
+    password_hash TEXT NOT NULL,  -- bcrypt hash, never the password itself
+    role VARCHAR(50) NOT NULL,
+    must_change_password BOOLEAN NOT NULL DEFAULT FALSE,  -- Set by admin reset; login yields a restricted token
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    password_changed_at TIMESTAMPTZ,
+
+    CONSTRAINT username_not_empty CHECK (LENGTH(TRIM(username)) > 0),
+    CONSTRAINT role_valid CHECK (role IN ('admin', 'user'))
+);
+
+COMMENT ON TABLE users IS 'Dashboard user accounts (devices authenticate with device tokens instead)';
@@ -0,0 +1,18 @@
+-- Migration: Inventory of issued tokens for session listing and revocation
+-- Date: 2026-02-04
+
+CREATE TABLE IF NOT EXISTS issued_tokens (
+    jti TEXT PRIMARY KEY,  -- JWT ID claim
+    subject VARCHAR(255) NOT NULL,  -- JWT subject (username or device_<id>)
+    role VARCHAR(50) NOT NULL,
+    device_id VARCHAR(255),
+    issued_at TIMESTAMPTZ NOT NULL,
+    expires_at TIMESTAMPTZ NOT NULL,
+    last_seen_at TIMESTAMPTZ,  -- Updated lazily, at most once a minute
+    revoked_at TIMESTAMPTZ
+);
+
+CREATE INDEX idx_issued_tokens_subject ON issued_tokens (subject);
+CREATE INDEX idx_issued_tokens_expires_at ON issued_tokens (expires_at);
+
+COMMENT ON TABLE issued_tokens IS 'Locally issued JWTs; rows are deleted once expired';
@@ -0,0 +1,19 @@
+-- Migration: Track ML model training jobs and their progress
+-- Date: 2026-02-05
+
+CREATE TABLE IF NOT EXISTS training_jobs (
+    job_id UUID PRIMARY KEY,
+    started_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    status VARCHAR(20) NOT NULL CHECK (status IN ('in_progress', 'completed', 'failed')),
+    progress_percent REAL NOT NULL DEFAULT 0,
+    message TEXT NOT NULL DEFAULT '',
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_training_jobs_started_at ON training_jobs (started_at DESC);
+
+-- At most one job may be running at a time
+CREATE UNIQUE INDEX idx_training_jobs_single_in_progress
+    ON training_jobs ((TRUE)) WHERE status = 'in_progress';
+
+COMMENT ON TABLE training_jobs IS 'ML training runs triggered via POST /api/ml/train';
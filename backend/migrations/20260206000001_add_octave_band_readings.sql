@@ -0,0 +1,26 @@
+-- Migration: Store octave-band spectra from professional microphone modules
+-- Date: 2026-02-06
+
+CREATE TABLE IF NOT EXISTS octave_band_readings (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    patient_id VARCHAR(255) NOT NULL,
+    device_id VARCHAR(255) NOT NULL,
+    timestamp TIMESTAMPTZ NOT NULL,
+    band_63hz DOUBLE PRECISION NOT NULL,
+    band_125hz DOUBLE PRECISION NOT NULL,
+    band_250hz DOUBLE PRECISION NOT NULL,
+    band_500hz DOUBLE PRECISION NOT NULL,
+    band_1khz DOUBLE PRECISION NOT NULL,
+    band_2khz DOUBLE PRECISION NOT NULL,
+    band_4khz DOUBLE PRECISION NOT NULL,
+    band_8khz DOUBLE PRECISION NOT NULL,
+    overall_db DOUBLE PRECISION NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+
+    CONSTRAINT octave_patient_id_not_empty CHECK (LENGTH(TRIM(patient_id)) > 0),
+    CONSTRAINT octave_device_id_not_empty CHECK (LENGTH(TRIM(device_id)) > 0)
+);
+
+CREATE INDEX idx_octave_band_readings_patient_ts ON octave_band_readings (patient_id, timestamp);
+
+COMMENT ON TABLE octave_band_readings IS 'Per-octave sound levels (dB), 63 Hz to 8 kHz';
@@ -0,0 +1,13 @@
+-- Migration: Let clinicians amend readings (e.g. mark them entered-in-error) instead of deleting them
+-- Date: 2026-02-07
+
+ALTER TABLE sensor_readings
+    ADD COLUMN IF NOT EXISTS status VARCHAR(20) NOT NULL DEFAULT 'final',
+    ADD COLUMN IF NOT EXISTS note TEXT;
+
+ALTER TABLE sensor_readings
+    ADD CONSTRAINT reading_status_valid
+    CHECK (status IN ('final', 'amended', 'cancelled', 'entered-in-error'));
+
+COMMENT ON COLUMN sensor_readings.status IS 'FHIR Observation status; entered-in-error readings are excluded from statistics and model data';
+COMMENT ON COLUMN sensor_readings.note IS 'Reason given when the reading was amended';
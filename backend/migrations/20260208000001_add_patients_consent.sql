@@ -0,0 +1,32 @@
+-- Migration: Patient registry with consent status, and a quarantine for
+-- readings of patients without consent
+-- Date: 2026-02-08
+
+CREATE TABLE IF NOT EXISTS patients (
+    patient_id VARCHAR(255) PRIMARY KEY,
+    label VARCHAR(255),
+    consent_status VARCHAR(20) NOT NULL DEFAULT 'pending',
+    consented_at TIMESTAMPTZ,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+
+    CONSTRAINT patients_patient_id_not_empty CHECK (LENGTH(TRIM(patient_id)) > 0),
+    CONSTRAINT patients_consent_status_valid
+        CHECK (consent_status IN ('pending', 'granted', 'revoked'))
+);
+
+-- Readings accepted from patients without consent when CONSENT_MODE=quarantine.
+-- Kept apart from sensor_readings so no query can pick them up.
+CREATE TABLE IF NOT EXISTS quarantined_readings (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    patient_id VARCHAR(255) NOT NULL,
+    kind VARCHAR(32) NOT NULL,
+    payload JSONB NOT NULL,
+    reason VARCHAR(20) NOT NULL,
+    received_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_quarantined_readings_patient ON quarantined_readings (patient_id, received_at);
+
+COMMENT ON TABLE patients IS 'Known patients; readings are only stored once consent_status is granted';
+COMMENT ON TABLE quarantined_readings IS 'Readings received without patient consent (payload as ingested)';
+COMMENT ON COLUMN quarantined_readings.reason IS 'Consent status at ingest: pending, revoked or unknown';
@@ -0,0 +1,18 @@
+-- Migration: Hearing protectors that can be recommended for a noise dose
+-- Date: 2026-02-09
+
+CREATE TABLE IF NOT EXISTS hearing_protectors (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    name TEXT NOT NULL,
+    nrr_db INTEGER NOT NULL,
+    category TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+
+    CONSTRAINT hearing_protectors_name_not_empty CHECK (LENGTH(TRIM(name)) > 0),
+    CONSTRAINT hearing_protectors_nrr_valid CHECK (nrr_db BETWEEN 0 AND 50)
+);
+
+CREATE INDEX idx_hearing_protectors_nrr ON hearing_protectors (nrr_db);
+
+COMMENT ON TABLE hearing_protectors IS 'Protectors recommended by /api/analysis/recommendation';
+COMMENT ON COLUMN hearing_protectors.nrr_db IS 'Labelled Noise Reduction Rating; derated as (nrr_db - 7) / 2';
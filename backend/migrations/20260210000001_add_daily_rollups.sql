@@ -0,0 +1,25 @@
+-- Migration: Per-patient daily aggregates of sensor readings
+-- Date: 2026-02-10
+
+CREATE TABLE IF NOT EXISTS daily_rollups (
+    patient_id VARCHAR(255) NOT NULL,
+    code VARCHAR(50) NOT NULL,
+    date DATE NOT NULL,
+    count BIGINT NOT NULL,
+    mean DOUBLE PRECISION NOT NULL,
+    max DOUBLE PRECISION NOT NULL,
+    p95 DOUBLE PRECISION NOT NULL,
+    minutes_above_threshold BIGINT NOT NULL,
+    computed_at TIMESTAMPTZ NOT NULL,
+
+    PRIMARY KEY (patient_id, code, date)
+);
+
+CREATE INDEX idx_daily_rollups_date ON daily_rollups (date);
+
+-- Finds readings that arrived after their day was rolled up
+CREATE INDEX idx_sensor_readings_created_at ON sensor_readings (created_at);
+
+COMMENT ON TABLE daily_rollups IS 'Aggregates per patient, signal and local day (ROLLUP_TIMEZONE); entered-in-error readings excluded';
+COMMENT ON COLUMN daily_rollups.minutes_above_threshold IS 'Clock minutes with at least one decibel reading above 85 dB';
+COMMENT ON COLUMN daily_rollups.computed_at IS 'Readings created after this are not included yet';
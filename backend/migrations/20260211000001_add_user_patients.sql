@@ -0,0 +1,13 @@
+-- Migration: Patients each dashboard user may see
+-- Date: 2026-02-11
+
+CREATE TABLE IF NOT EXISTS user_patients (
+    user_id TEXT NOT NULL,
+    patient_id TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+
+    PRIMARY KEY (user_id, patient_id)
+);
+
+COMMENT ON TABLE user_patients IS 'Patients a user-role account may read; copied into the JWT at login';
+COMMENT ON COLUMN user_patients.user_id IS 'users.id';
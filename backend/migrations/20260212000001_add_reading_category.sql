@@ -0,0 +1,11 @@
+-- Migration: FHIR Observation category of each reading
+-- Date: 2026-02-12
+
+-- Existing readings are all sound levels, whose default category is activity
+ALTER TABLE sensor_readings
+    ADD COLUMN IF NOT EXISTS category JSONB NOT NULL DEFAULT '[{"coding": [{"system": "http://terminology.hl7.org/CodeSystem/observation-category", "code": "activity", "display": "Activity"}], "text": "Activity"}]';
+
+-- Serves `category @> ...` filters
+CREATE INDEX IF NOT EXISTS idx_sensor_readings_category ON sensor_readings USING GIN (category jsonb_path_ops);
+
+COMMENT ON COLUMN sensor_readings.category IS 'FHIR Observation.category (array of CodeableConcept)';
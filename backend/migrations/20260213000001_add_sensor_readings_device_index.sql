@@ -0,0 +1,4 @@
+-- Migration: Per-device lookups of sensor readings (device ingest statistics)
+-- Date: 2026-02-13
+
+CREATE INDEX IF NOT EXISTS idx_sensor_readings_device_id ON sensor_readings (device_id, timestamp DESC);
@@ -0,0 +1,7 @@
+-- Migration: Soft deletion of sensor readings, so deleting an Observation
+-- keeps its PHI history for the audit trail
+-- Date: 2026-02-14
+
+ALTER TABLE sensor_readings ADD COLUMN IF NOT EXISTS deleted_at TIMESTAMPTZ;
+
+COMMENT ON COLUMN sensor_readings.deleted_at IS 'When the reading was deleted; deleted readings are only listed for admins';
@@ -0,0 +1,9 @@
+-- Migration: Where each sensor reading was taken, for the noise map
+-- Date: 2026-02-15
+
+ALTER TABLE sensor_readings ADD COLUMN IF NOT EXISTS location VARCHAR(255);
+
+CREATE INDEX IF NOT EXISTS idx_sensor_readings_location
+    ON sensor_readings (timestamp, location) WHERE location IS NOT NULL;
+
+COMMENT ON COLUMN sensor_readings.location IS 'Location reported by the device (metadata.location), e.g. a ward';
@@ -0,0 +1,28 @@
+-- Migration: Device calibration checks against a reference tone
+-- Date: 2026-02-16
+
+CREATE TABLE IF NOT EXISTS calibration_verifications (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    device_id VARCHAR(255) NOT NULL,
+    verified_at TIMESTAMPTZ NOT NULL,
+    reference_db DOUBLE PRECISION NOT NULL,
+    measured_avg_db DOUBLE PRECISION NOT NULL,
+    deviation_db DOUBLE PRECISION NOT NULL,
+    passed BOOLEAN NOT NULL
+);
+
+CREATE INDEX idx_calibration_verifications_device
+    ON calibration_verifications (device_id, verified_at DESC);
+
+-- Devices have no registry of their own; this holds their latest outcome
+CREATE TABLE IF NOT EXISTS device_calibration (
+    device_id VARCHAR(255) PRIMARY KEY,
+    calibration_status TEXT NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+
+    CONSTRAINT device_calibration_status_valid CHECK (calibration_status IN ('verified', 'failed'))
+);
+
+COMMENT ON TABLE calibration_verifications IS 'Results of POST /api/calibrate/device/{id}/verify';
+COMMENT ON COLUMN calibration_verifications.deviation_db IS 'measured_avg_db - reference_db';
+COMMENT ON TABLE device_calibration IS 'Calibration status of each device after its latest verification';
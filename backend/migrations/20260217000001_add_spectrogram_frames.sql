@@ -0,0 +1,22 @@
+-- Migration: FFT frames from professional sound level meters
+-- Date: 2026-02-17
+
+CREATE TABLE IF NOT EXISTS spectrogram_frames (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    patient_id VARCHAR(255) NOT NULL,
+    device_id VARCHAR(255) NOT NULL,
+    timestamp TIMESTAMPTZ NOT NULL,
+    sample_rate_hz INTEGER NOT NULL,
+    frame_duration_ms INTEGER NOT NULL,
+    frequency_bins FLOAT4[] NOT NULL,
+    magnitudes FLOAT4[] NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+
+    CONSTRAINT spectrogram_patient_id_not_empty CHECK (LENGTH(TRIM(patient_id)) > 0),
+    CONSTRAINT spectrogram_device_id_not_empty CHECK (LENGTH(TRIM(device_id)) > 0),
+    CONSTRAINT spectrogram_bins_match CHECK (cardinality(frequency_bins) = cardinality(magnitudes))
+);
+
+CREATE INDEX idx_spectrogram_frames_patient_ts ON spectrogram_frames (patient_id, timestamp);
+
+COMMENT ON TABLE spectrogram_frames IS 'FFT magnitude per frequency bin, one row per frame';
@@ -0,0 +1,21 @@
+-- Migration: Per-device secrets and challenge-response onboarding
+-- Date: 2026-02-18
+
+CREATE TABLE IF NOT EXISTS devices (
+    device_id VARCHAR(255) PRIMARY KEY,
+    device_secret_hash TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    secret_rotated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- At most one open challenge per device; answering it deletes the row
+CREATE TABLE IF NOT EXISTS pending_challenges (
+    device_id VARCHAR(255) PRIMARY KEY REFERENCES devices (device_id) ON DELETE CASCADE,
+    nonce TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL,
+    expires_at TIMESTAMPTZ NOT NULL
+);
+
+COMMENT ON TABLE devices IS 'Devices provisioned with their own secret';
+COMMENT ON COLUMN devices.device_secret_hash IS 'Hex SHA-256 of the device secret; also the HMAC key, so a credential';
+COMMENT ON TABLE pending_challenges IS 'Nonces handed out by POST /auth/device/challenge, valid until expires_at';
@@ -0,0 +1,20 @@
+-- Migration: Validation rules enabled per deployment
+-- Date: 2026-02-19
+
+CREATE TYPE validation_severity AS ENUM ('error', 'warning');
+
+CREATE TABLE IF NOT EXISTS validation_rules (
+    id BIGSERIAL PRIMARY KEY,
+    rule_name TEXT NOT NULL,
+    config JSONB NOT NULL DEFAULT '{}',
+    active BOOLEAN NOT NULL DEFAULT TRUE,
+    severity validation_severity NOT NULL DEFAULT 'error',
+
+    CONSTRAINT validation_rules_name_known CHECK (
+        rule_name IN ('finite_value', 'range', 'unit_whitelist', 'future_ts', 'patient_exists')
+    )
+);
+
+COMMENT ON TABLE validation_rules IS 'Checks run on every ingested reading, in id order; loaded at startup';
+COMMENT ON COLUMN validation_rules.config IS 'range: {"min", "max"}; unit_whitelist: {"units": [...]}; future_ts: {"max_skew_secs"}';
+COMMENT ON COLUMN validation_rules.severity IS 'error refuses the reading; warning stores it and reports the finding';
@@ -0,0 +1,7 @@
+-- Migration: Read-only viewer accounts for dashboards
+-- Date: 2026-02-20
+
+ALTER TABLE users DROP CONSTRAINT IF EXISTS role_valid;
+ALTER TABLE users ADD CONSTRAINT role_valid CHECK (role IN ('admin', 'user', 'viewer'));
+
+COMMENT ON COLUMN users.role IS 'admin, user, or viewer (read-only; refused every write)';
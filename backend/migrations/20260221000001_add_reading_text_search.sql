@@ -0,0 +1,18 @@
+-- Migration: Full-text search over reading notes and metadata
+-- Date: 2026-02-21
+
+-- Metadata as sent by the device or researcher (tags, location, ...)
+ALTER TABLE sensor_readings ADD COLUMN IF NOT EXISTS metadata JSONB NOT NULL DEFAULT '{}';
+
+ALTER TABLE sensor_readings
+    ADD COLUMN IF NOT EXISTS search_document TSVECTOR
+    GENERATED ALWAYS AS (
+        to_tsvector('english'::REGCONFIG, COALESCE(note, '') || ' ' || metadata::TEXT)
+    ) STORED;
+
+-- Serves `search_document @@ plainto_tsquery(...)`
+CREATE INDEX IF NOT EXISTS idx_sensor_readings_search_document
+    ON sensor_readings USING GIN (search_document);
+
+COMMENT ON COLUMN sensor_readings.metadata IS 'Reading metadata as ingested; tags are the strings in its tags array';
+COMMENT ON COLUMN sensor_readings.search_document IS 'English tsvector of note and metadata, for GET /api/fhir/Observation?_text=';
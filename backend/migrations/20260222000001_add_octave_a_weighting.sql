@@ -0,0 +1,6 @@
+-- Migration: Keep the A-weighted level of octave-band readings
+-- Date: 2026-02-22
+
+ALTER TABLE octave_band_readings ADD COLUMN IF NOT EXISTS calibrated_value DOUBLE PRECISION;
+
+COMMENT ON COLUMN octave_band_readings.calibrated_value IS 'A-weighted level of the bands, dB(A); NULL unless ENABLE_A_WEIGHTING was on';
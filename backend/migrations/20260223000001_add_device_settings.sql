@@ -0,0 +1,16 @@
+-- Migration: Per-device lifecycle status and offline grace period
+-- Date: 2026-02-23
+
+CREATE TABLE IF NOT EXISTS device_settings (
+    device_id VARCHAR(255) PRIMARY KEY,
+    status VARCHAR(20) NOT NULL DEFAULT 'active',
+    offline_grace_secs BIGINT,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+
+    CONSTRAINT device_settings_status_known CHECK (status IN ('active', 'decommissioned')),
+    CONSTRAINT device_settings_grace_positive CHECK (offline_grace_secs IS NULL OR offline_grace_secs > 0)
+);
+
+COMMENT ON TABLE device_settings IS 'Set by PUT /api/devices/{id}; devices without a row are active with the default grace period';
+COMMENT ON COLUMN device_settings.status IS 'decommissioned devices never raise offline alerts';
+COMMENT ON COLUMN device_settings.offline_grace_secs IS 'Silence tolerated before DeviceOffline; NULL uses DEVICE_OFFLINE_GRACE_SECS';
@@ -0,0 +1,22 @@
+-- Migration: Plain-English advice for weekly patient summaries
+-- Date: 2026-02-24
+
+CREATE TABLE IF NOT EXISTS recommendations (
+    id BIGSERIAL PRIMARY KEY,
+    min_dose_percent DOUBLE PRECISION NOT NULL,
+    max_dose_percent DOUBLE PRECISION,
+    recommendation_text TEXT NOT NULL,
+
+    CONSTRAINT recommendations_range_valid CHECK (
+        min_dose_percent >= 0 AND (max_dose_percent IS NULL OR max_dose_percent > min_dose_percent)
+    )
+);
+
+INSERT INTO recommendations (min_dose_percent, max_dose_percent, recommendation_text) VALUES
+    (0, 50, 'Your noise exposure this week was well within safe limits. Keep it up.'),
+    (50, 100, 'Your noise exposure stayed within the daily limit, but came close to it. Take breaks from loud places where you can.'),
+    (100, 200, 'Your noise exposure was above the recommended daily limit. Wear hearing protection in loud places and spend less time there.'),
+    (200, NULL, 'Your noise exposure was well above the safe limit. Wear hearing protection whenever you are somewhere loud, and talk to your audiologist.');
+
+COMMENT ON TABLE recommendations IS 'Advice in weekly patient summaries, chosen by the week''s average daily NIOSH dose';
+COMMENT ON COLUMN recommendations.max_dose_percent IS 'Exclusive upper bound; NULL for no upper bound';
@@ -0,0 +1,9 @@
+-- Migration: Per-device decimation interval
+-- Date: 2026-02-25
+
+ALTER TABLE device_settings
+    ADD COLUMN IF NOT EXISTS decimation_interval_ms BIGINT,
+    ADD CONSTRAINT device_settings_decimation_non_negative
+        CHECK (decimation_interval_ms IS NULL OR decimation_interval_ms >= 0);
+
+COMMENT ON COLUMN device_settings.decimation_interval_ms IS 'Keep at most one reading per interval per code, 0 keeps all; NULL uses DECIMATION_INTERVAL_MS';
@@ -0,0 +1,21 @@
+-- Migration: Factory keys and nonce replay protection for IoT devices
+-- Date: 2026-02-26
+
+CREATE TABLE IF NOT EXISTS iot_devices (
+    device_id VARCHAR(255) PRIMARY KEY,
+    factory_key_hash TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- Nonces answered through POST /auth/iot/token, kept until they expire
+CREATE TABLE IF NOT EXISTS used_nonces (
+    nonce TEXT PRIMARY KEY,
+    device_id VARCHAR(255) NOT NULL,
+    expires_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_used_nonces_expires_at ON used_nonces (expires_at);
+
+COMMENT ON TABLE iot_devices IS 'Devices provisioned by POST /api/admin/iot-devices';
+COMMENT ON COLUMN iot_devices.factory_key_hash IS 'Hex SHA-256 of the factory key; also the HMAC key, so a credential';
+COMMENT ON TABLE used_nonces IS 'Redeemed IoT nonces; a nonce found here is a replay. Rows are deleted once expired';
@@ -0,0 +1,21 @@
+-- Migration: Alert suppression windows
+-- Date: 2026-02-27
+
+CREATE TABLE IF NOT EXISTS suppression_windows (
+    id UUID PRIMARY KEY,
+    patient_id TEXT NOT NULL,
+    device_id TEXT,
+    start_at TIMESTAMPTZ NOT NULL,
+    end_at TIMESTAMPTZ NOT NULL,
+    reason TEXT NOT NULL,
+    created_by TEXT NOT NULL,
+    CHECK (end_at > start_at)
+);
+
+CREATE INDEX IF NOT EXISTS idx_suppression_windows_patient ON suppression_windows (patient_id, end_at);
+CREATE INDEX IF NOT EXISTS idx_suppression_windows_device ON suppression_windows (device_id, end_at)
+    WHERE device_id IS NOT NULL;
+
+COMMENT ON TABLE suppression_windows IS 'Periods during which a patient''s anomaly alerts are not raised; deleted once ended';
+COMMENT ON COLUMN suppression_windows.device_id IS 'Optional; also silences this device''s offline and recovered alerts';
+COMMENT ON COLUMN suppression_windows.created_by IS 'Subject of the token that created the window';
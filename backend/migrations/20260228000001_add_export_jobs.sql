@@ -0,0 +1,26 @@
+-- Migration: Track bulk FHIR export jobs
+-- Date: 2026-02-28
+
+CREATE TABLE IF NOT EXISTS export_jobs (
+    job_id UUID PRIMARY KEY,
+    -- Subject and role of the token that started the export
+    owner TEXT NOT NULL,
+    role VARCHAR(20) NOT NULL,
+    -- Patients the export was limited to; NULL for all
+    patients TEXT[],
+    since TIMESTAMPTZ,
+    request TEXT NOT NULL,
+    transaction_time TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    status VARCHAR(20) NOT NULL
+        CHECK (status IN ('in_progress', 'completed', 'failed', 'cancelled')),
+    exported BIGINT NOT NULL DEFAULT 0,
+    -- Observations in each output file, in file order
+    file_counts BIGINT[] NOT NULL DEFAULT '{}',
+    error TEXT,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_export_jobs_transaction_time
+    ON export_jobs (transaction_time DESC);
+
+COMMENT ON TABLE export_jobs IS 'Bulk FHIR $export jobs; output files live in BULK_EXPORT_DIR';
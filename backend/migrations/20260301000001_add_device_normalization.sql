@@ -0,0 +1,22 @@
+-- Migration: Per-device output scales and the raw value of normalized readings
+-- Date: 2026-03-01
+
+ALTER TABLE device_settings
+    ADD COLUMN IF NOT EXISTS scale_min DOUBLE PRECISION,
+    ADD COLUMN IF NOT EXISTS scale_max DOUBLE PRECISION,
+    ADD COLUMN IF NOT EXISTS target_min DOUBLE PRECISION,
+    ADD COLUMN IF NOT EXISTS target_max DOUBLE PRECISION,
+    ADD CONSTRAINT device_settings_profile_complete CHECK (
+        (scale_min IS NULL) = (scale_max IS NULL)
+        AND (scale_min IS NULL) = (target_min IS NULL)
+        AND (scale_min IS NULL) = (target_max IS NULL)
+    ),
+    ADD CONSTRAINT device_settings_profile_valid CHECK (
+        scale_min IS NULL OR (scale_min <> scale_max AND target_min < target_max)
+    );
+
+ALTER TABLE sensor_readings ADD COLUMN IF NOT EXISTS raw_value DOUBLE PRECISION;
+
+COMMENT ON COLUMN device_settings.scale_min IS 'Device output mapped onto target_min; NULL (with the other bounds) stores readings as sent';
+COMMENT ON COLUMN device_settings.scale_max IS 'Device output mapped onto target_max';
+COMMENT ON COLUMN sensor_readings.raw_value IS 'Value as sent by the device; value holds it normalized by the device profile. NULL for readings stored before normalization existed';
@@ -0,0 +1,14 @@
+-- Migration: Store monthly compliance reports
+-- Date: 2026-03-02
+
+CREATE TABLE IF NOT EXISTS compliance_reports (
+    -- First day of the reported UTC month
+    month DATE PRIMARY KEY,
+    report JSONB NOT NULL,
+    generated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+
+    CONSTRAINT compliance_reports_month_first CHECK (EXTRACT(DAY FROM month) = 1)
+);
+
+COMMENT ON TABLE compliance_reports IS 'Audit activity of each finished month, for HIPAA 164.312(b) review; see GET /api/admin/compliance-report/{year}/{month}';
+
@@ -0,0 +1,14 @@
+-- Migration: Key device challenges by nonce
+-- Date: 2026-03-03
+
+-- A device may have several challenges open, so anyone asking for a
+-- challenge in its name no longer replaces the one it is answering. Open
+-- challenges live 60 seconds; dropping them only makes devices ask again.
+DELETE FROM pending_challenges;
+ALTER TABLE pending_challenges DROP CONSTRAINT pending_challenges_pkey;
+ALTER TABLE pending_challenges ADD PRIMARY KEY (nonce);
+
+CREATE INDEX IF NOT EXISTS idx_pending_challenges_device_id ON pending_challenges (device_id);
+CREATE INDEX IF NOT EXISTS idx_pending_challenges_expires_at ON pending_challenges (expires_at);
+
+COMMENT ON TABLE pending_challenges IS 'Nonces handed out by POST /auth/device/challenge, valid until expires_at; several per device may be open';
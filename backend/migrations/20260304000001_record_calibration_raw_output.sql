@@ -0,0 +1,13 @@
+-- Migration: Record the raw output of devices under calibration
+-- Date: 2026-03-04
+
+-- A verification now also averages the device's raw readings, which makes
+-- it the reference raw readings are converted to decibels by. Devices that
+-- only send raw output have no decibel average to compare.
+ALTER TABLE calibration_verifications
+    ADD COLUMN IF NOT EXISTS measured_avg_raw DOUBLE PRECISION,
+    ALTER COLUMN measured_avg_db DROP NOT NULL,
+    ALTER COLUMN deviation_db DROP NOT NULL;
+
+COMMENT ON COLUMN calibration_verifications.measured_avg_raw IS 'Average raw output during the reference tone; NULL without raw readings';
+COMMENT ON COLUMN calibration_verifications.measured_avg_db IS 'Average decibel reading during the reference tone; NULL without decibel readings';
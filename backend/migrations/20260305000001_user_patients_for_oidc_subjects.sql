@@ -0,0 +1,4 @@
+-- Migration: Assign patients to OIDC subjects as well as local users
+-- Date: 2026-03-05
+
+COMMENT ON COLUMN user_patients.user_id IS 'users.id, or the sub claim of an OIDC subject';
@@ -0,0 +1,216 @@
+/// A-Weighting
+///
+/// Audiologists report levels in dB(A): band levels corrected for how
+/// sensitive human hearing is at each frequency, then summed on an energy
+/// basis. The correction is the A-weighting curve of IEC 61672-1, which is
+/// 0 dB at 1 kHz and strongly attenuates low frequencies.
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::domain::models::{OCTAVE_BAND_CENTERS_HZ, OCTAVE_BAND_COUNT, OCTAVE_LEVEL_RANGE_DB};
+
+/// Pole frequencies of the A-weighting filter (Hz), IEC 61672-1 Annex E
+const F1: f64 = 20.598_997;
+const F2: f64 = 107.652_65;
+const F3: f64 = 737.862_23;
+const F4: f64 = 12_194.217;
+
+/// Normalizes the curve to 0 dB at 1 kHz
+const A1000: f64 = -2.000;
+
+/// Frequencies the A-weighting curve is specified for (IEC 61672-1 Table 3).
+/// Far outside it the correction's intermediate terms underflow or overflow
+/// and the result is not a number.
+pub const A_WEIGHT_FREQUENCY_RANGE_HZ: std::ops::RangeInclusive<f64> = 10.0..=20_000.0;
+
+/// A-weighting correction (dB) to add to a level measured at `frequency_hz`
+pub fn a_weight_correction(frequency_hz: f64) -> f64 {
+    let f2 = frequency_hz * frequency_hz;
+    let response = (F4 * F4 * f2 * f2)
+        / ((f2 + F1 * F1) * ((f2 + F2 * F2) * (f2 + F3 * F3)).sqrt() * (f2 + F4 * F4));
+    20.0 * response.log10() - A1000
+}
+
+/// Energy sum of levels in dB
+pub fn combine_levels(levels: impl IntoIterator<Item = f64>) -> f64 {
+    let power: f64 = levels.into_iter().map(|l| 10f64.powf(l / 10.0)).sum();
+    10.0 * power.log10()
+}
+
+/// Whether `ENABLE_A_WEIGHTING=true` asks for octave-band readings to be
+/// stored with their A-weighted level
+pub fn a_weighting_from_env() -> bool {
+    std::env::var("ENABLE_A_WEIGHTING")
+        .map(|v| v.eq_ignore_ascii_case("true"))
+        .unwrap_or(false)
+}
+
+/// A-weighted level (dB(A)) of an octave-band reading's bands
+pub fn octave_a_weighted(bands: &[f64; OCTAVE_BAND_COUNT]) -> f64 {
+    AWeightRequest {
+        bands: *bands,
+        frequencies: OCTAVE_BAND_CENTERS_HZ,
+    }
+    .weigh()
+    .level_db_a
+}
+
+/// Band levels to A-weight
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct AWeightRequest {
+    /// Band levels in dB
+    pub bands: [f64; OCTAVE_BAND_COUNT],
+    /// Center frequency of each band in Hz (default the octave bands,
+    /// 63 Hz to 8 kHz)
+    #[serde(default = "octave_centers")]
+    pub frequencies: [f64; OCTAVE_BAND_COUNT],
+}
+
+fn octave_centers() -> [f64; OCTAVE_BAND_COUNT] {
+    OCTAVE_BAND_CENTERS_HZ
+}
+
+/// A-weighted bands and their combined level
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct AWeightedLevel {
+    /// Energy sum of the weighted bands
+    pub level_db_a: f64,
+    /// Each band level plus its A-weighting correction
+    pub per_band_weighted: [f64; OCTAVE_BAND_COUNT],
+}
+
+impl AWeightRequest {
+    /// Refuse levels and frequencies whose weighted sum would not be a
+    /// finite number; NaN and infinities fail the range checks too
+    pub fn validate(&self) -> Result<(), String> {
+        let (min, max) = (OCTAVE_LEVEL_RANGE_DB.start(), OCTAVE_LEVEL_RANGE_DB.end());
+        if let Some(i) = self
+            .bands
+            .iter()
+            .position(|b| !OCTAVE_LEVEL_RANGE_DB.contains(b))
+        {
+            return Err(format!("band {} must be between {} and {} dB", i, min, max));
+        }
+        let (min, max) = (
+            A_WEIGHT_FREQUENCY_RANGE_HZ.start(),
+            A_WEIGHT_FREQUENCY_RANGE_HZ.end(),
+        );
+        if let Some(i) = self
+            .frequencies
+            .iter()
+            .position(|f| !A_WEIGHT_FREQUENCY_RANGE_HZ.contains(f))
+        {
+            return Err(format!(
+                "frequency {} must be between {} and {} Hz",
+                i, min, max
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn weigh(&self) -> AWeightedLevel {
+        let mut per_band_weighted = self.bands;
+        for (level, f) in per_band_weighted.iter_mut().zip(self.frequencies) {
+            *level += a_weight_correction(f);
+        }
+        AWeightedLevel {
+            level_db_a: combine_levels(per_band_weighted),
+            per_band_weighted,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f64, expected: f64, tolerance: f64) {
+        assert!(
+            (actual - expected).abs() <= tolerance,
+            "{} is not within {} of {}",
+            actual,
+            tolerance,
+            expected
+        );
+    }
+
+    #[test]
+    fn test_corrections_match_iec_61672_table() {
+        // IEC 61672-1 Table 3 at the exact base-10 frequencies
+        // (f = 1000 * 10^(n/10)), which the nominal ones round
+        let table = [
+            (10f64.powf(1.0), -70.4),
+            (10f64.powf(1.8), -26.2),
+            (10f64.powf(2.1), -16.1),
+            (10f64.powf(2.4), -8.6),
+            (10f64.powf(2.7), -3.2),
+            (1000.0, 0.0),
+            (10f64.powf(3.3), 1.2),
+            (10f64.powf(3.6), 1.0),
+            (10f64.powf(3.9), -1.1),
+            (10f64.powf(4.2), -6.6),
+        ];
+        for (f, expected) in table {
+            assert_close(a_weight_correction(f), expected, 0.05);
+        }
+        assert_close(a_weight_correction(1000.0), 0.0, 0.001);
+    }
+
+    #[test]
+    fn test_levels_combine_by_energy() {
+        assert_close(combine_levels([60.0, 60.0]), 63.01, 0.01);
+        assert_close(combine_levels([70.0, 50.0]), 70.04, 0.01);
+    }
+
+    #[test]
+    fn test_flat_spectrum_is_weighted_per_band() {
+        let request = AWeightRequest {
+            bands: [70.0; OCTAVE_BAND_COUNT],
+            frequencies: OCTAVE_BAND_CENTERS_HZ,
+        };
+        let weighted = request.weigh();
+        // 63 Hz is attenuated by about 26 dB, 1 kHz not at all
+        assert_close(weighted.per_band_weighted[0], 43.8, 0.1);
+        assert_close(weighted.per_band_weighted[4], 70.0, 0.01);
+        // Dominated by the 1-4 kHz bands
+        assert_close(weighted.level_db_a, 76.96, 0.01);
+        assert_eq!(octave_a_weighted(&request.bands), weighted.level_db_a);
+
+        let bad = AWeightRequest {
+            frequencies: [0.0; OCTAVE_BAND_COUNT],
+            ..request
+        };
+        assert!(bad.validate().is_err());
+    }
+
+    #[test]
+    fn test_inputs_that_weigh_to_no_number_are_refused() {
+        let valid = AWeightRequest {
+            bands: [70.0; OCTAVE_BAND_COUNT],
+            frequencies: OCTAVE_BAND_CENTERS_HZ,
+        };
+        assert!(valid.validate().is_ok());
+
+        for band in [f64::NAN, f64::INFINITY, f64::NEG_INFINITY, 1e300] {
+            let mut bad = valid.clone();
+            bad.bands[3] = band;
+            assert!(bad.validate().is_err(), "band {}", band);
+        }
+        // Tiny and huge frequencies weigh to -inf or NaN
+        for frequency in [f64::NAN, f64::INFINITY, 1e-200, 1e200] {
+            let mut bad = valid.clone();
+            bad.frequencies[3] = frequency;
+            assert!(!bad.weigh().per_band_weighted[3].is_finite());
+            assert!(bad.validate().is_err(), "frequency {}", frequency);
+        }
+
+        // At the ends of the accepted ranges the level is still a number
+        let mut edge = valid.clone();
+        edge.bands = [*OCTAVE_LEVEL_RANGE_DB.end(); OCTAVE_BAND_COUNT];
+        edge.frequencies[0] = *A_WEIGHT_FREQUENCY_RANGE_HZ.start();
+        edge.frequencies[7] = *A_WEIGHT_FREQUENCY_RANGE_HZ.end();
+        assert!(edge.validate().is_ok());
+        assert!(edge.weigh().level_db_a.is_finite());
+    }
+}
@@ -0,0 +1,305 @@
+/// Alert Suppression Windows
+///
+/// A patient somewhere known to be loud, such as during a hearing test,
+/// would only page staff for nothing. From `start_at` to `end_at` a
+/// suppression window keeps the patient's anomalies off `/ws/anomalies` and
+/// out of the dashboard's alerts; their readings are still stored and
+/// streamed live. A window naming a device also silences that device's
+/// offline and recovered alerts. Windows are deleted once they have ended.
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::db::Database;
+use crate::errors::AppError;
+
+/// A period during which a patient's alerts are not raised
+#[derive(Debug, Clone, Serialize, ToSchema, sqlx::FromRow)]
+pub struct SuppressionWindow {
+    pub id: Uuid,
+    pub patient_id: String,
+    /// Also silences this device's offline and recovered alerts
+    pub device_id: Option<String>,
+    pub start_at: DateTime<Utc>,
+    pub end_at: DateTime<Utc>,
+    pub reason: String,
+    /// Subject of the token that created the window
+    pub created_by: String,
+}
+
+impl SuppressionWindow {
+    /// Whether the window covers `now`, both ends included
+    pub fn is_active(&self, now: DateTime<Utc>) -> bool {
+        self.start_at <= now && now <= self.end_at
+    }
+}
+
+/// A window as created or replaced through the API
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct SuppressionRequest {
+    pub patient_id: String,
+    #[serde(default)]
+    pub device_id: Option<String>,
+    /// Defaults to now
+    #[serde(default)]
+    pub start_at: Option<DateTime<Utc>>,
+    pub end_at: DateTime<Utc>,
+    pub reason: String,
+}
+
+impl SuppressionRequest {
+    /// Check the request, with `now` as its start if it names none
+    pub fn validate(&self, now: DateTime<Utc>) -> Result<(), String> {
+        if self.patient_id.trim().is_empty() {
+            return Err("patient_id required".into());
+        }
+        if self
+            .device_id
+            .as_deref()
+            .is_some_and(|d| d.trim().is_empty())
+        {
+            return Err("device_id must not be empty".into());
+        }
+        if self.reason.trim().is_empty() {
+            return Err("reason required".into());
+        }
+        if self.start_at.unwrap_or(now) >= self.end_at {
+            return Err("end_at must be after start_at".into());
+        }
+        Ok(())
+    }
+
+    /// The window with `id`, created by `created_by`, starting now unless
+    /// the request says otherwise
+    pub fn into_window(
+        self,
+        id: Uuid,
+        created_by: String,
+        now: DateTime<Utc>,
+    ) -> SuppressionWindow {
+        SuppressionWindow {
+            id,
+            patient_id: self.patient_id.trim().to_string(),
+            device_id: self.device_id.map(|d| d.trim().to_string()),
+            start_at: self.start_at.unwrap_or(now),
+            end_at: self.end_at,
+            reason: self.reason.trim().to_string(),
+            created_by,
+        }
+    }
+}
+
+/// Suppression windows, in the database or (without one) in memory
+#[derive(Debug)]
+pub struct AlertSuppressions {
+    db: Option<Database>,
+    /// Windows without a database
+    windows: RwLock<Vec<SuppressionWindow>>,
+}
+
+impl AlertSuppressions {
+    pub fn new(db: Option<Database>) -> Self {
+        Self {
+            db,
+            windows: RwLock::new(Vec::new()),
+        }
+    }
+
+    pub async fn create(&self, window: &SuppressionWindow) -> Result<(), AppError> {
+        match &self.db {
+            Some(db) => db.insert_suppression_window(window).await,
+            None => {
+                self.windows.write().await.push(window.clone());
+                Ok(())
+            }
+        }
+    }
+
+    /// Every window, earliest start first
+    pub async fn list(&self) -> Result<Vec<SuppressionWindow>, AppError> {
+        if let Some(db) = &self.db {
+            return db.list_suppression_windows().await;
+        }
+        let mut windows = self.windows.read().await.clone();
+        windows.sort_by_key(|w| w.start_at);
+        Ok(windows)
+    }
+
+    /// Windows covering now, earliest start first
+    pub async fn active(&self) -> Result<Vec<SuppressionWindow>, AppError> {
+        if let Some(db) = &self.db {
+            return db.active_suppression_windows().await;
+        }
+        let now = Utc::now();
+        let mut windows: Vec<_> = self
+            .windows
+            .read()
+            .await
+            .iter()
+            .filter(|w| w.is_active(now))
+            .cloned()
+            .collect();
+        windows.sort_by_key(|w| w.start_at);
+        Ok(windows)
+    }
+
+    pub async fn get(&self, id: Uuid) -> Result<Option<SuppressionWindow>, AppError> {
+        match &self.db {
+            Some(db) => db.suppression_window(id).await,
+            None => Ok(self
+                .windows
+                .read()
+                .await
+                .iter()
+                .find(|w| w.id == id)
+                .cloned()),
+        }
+    }
+
+    /// Replace the window with `window.id`; `false` if there is none
+    pub async fn update(&self, window: &SuppressionWindow) -> Result<bool, AppError> {
+        if let Some(db) = &self.db {
+            return db.update_suppression_window(window).await;
+        }
+        let mut windows = self.windows.write().await;
+        match windows.iter_mut().find(|w| w.id == window.id) {
+            Some(existing) => {
+                *existing = window.clone();
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Delete a window; `false` if there is none with `id`
+    pub async fn delete(&self, id: Uuid) -> Result<bool, AppError> {
+        if let Some(db) = &self.db {
+            return db.delete_suppression_window(id).await;
+        }
+        let mut windows = self.windows.write().await;
+        let before = windows.len();
+        windows.retain(|w| w.id != id);
+        Ok(windows.len() < before)
+    }
+
+    /// Whether `patient_id`'s alerts are suppressed right now. Alerts are
+    /// raised if this cannot be told.
+    pub async fn is_patient_suppressed(&self, patient_id: &str) -> bool {
+        let suppressed = match &self.db {
+            Some(db) => db.patient_suppressed(patient_id).await,
+            None => {
+                let now = Utc::now();
+                Ok(self
+                    .windows
+                    .read()
+                    .await
+                    .iter()
+                    .any(|w| w.patient_id == patient_id && w.is_active(now)))
+            }
+        };
+        suppressed.unwrap_or_else(|e| {
+            tracing::warn!(error = ?e, patient_id, "Failed to check alert suppression, alerting");
+            false
+        })
+    }
+
+    /// Whether `device_id`'s alerts are suppressed right now. Alerts are
+    /// raised if this cannot be told.
+    pub async fn is_device_suppressed(&self, device_id: &str) -> bool {
+        let suppressed = match &self.db {
+            Some(db) => db.device_suppressed(device_id).await,
+            None => {
+                let now = Utc::now();
+                Ok(self
+                    .windows
+                    .read()
+                    .await
+                    .iter()
+                    .any(|w| w.device_id.as_deref() == Some(device_id) && w.is_active(now)))
+            }
+        };
+        suppressed.unwrap_or_else(|e| {
+            tracing::warn!(error = ?e, device_id, "Failed to check alert suppression, alerting");
+            false
+        })
+    }
+
+    /// Delete windows that have ended; returns how many
+    pub async fn cleanup_expired(&self) -> Result<u64, AppError> {
+        if let Some(db) = &self.db {
+            return db.delete_expired_suppression_windows().await;
+        }
+        let now = Utc::now();
+        let mut windows = self.windows.write().await;
+        let before = windows.len();
+        windows.retain(|w| w.end_at >= now);
+        Ok((before - windows.len()) as u64)
+    }
+
+    /// Delete ended windows every `interval` in the background
+    pub fn spawn_cleanup(self: &Arc<Self>, interval: std::time::Duration) {
+        let store = self.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                match store.cleanup_expired().await {
+                    Ok(removed) if removed > 0 => {
+                        tracing::debug!(removed, "Removed ended alert suppression windows")
+                    }
+                    Ok(_) => {}
+                    Err(e) => tracing::warn!(error = ?e, "Alert suppression cleanup failed"),
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    fn window(
+        patient_id: &str,
+        device_id: Option<&str>,
+        start: i64,
+        end: i64,
+    ) -> SuppressionWindow {
+        let now = Utc::now();
+        SuppressionWindow {
+            id: Uuid::new_v4(),
+            patient_id: patient_id.into(),
+            device_id: device_id.map(Into::into),
+            start_at: now + Duration::minutes(start),
+            end_at: now + Duration::minutes(end),
+            reason: "hearing test".into(),
+            created_by: "admin".into(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_only_active_windows_suppress() {
+        let store = AlertSuppressions::new(None);
+        store
+            .create(&window("p1", Some("d1"), -10, 10))
+            .await
+            .unwrap();
+        store.create(&window("p2", None, 5, 10)).await.unwrap();
+        store.create(&window("p3", None, -20, -10)).await.unwrap();
+
+        assert!(store.is_patient_suppressed("p1").await);
+        assert!(store.is_device_suppressed("d1").await);
+        assert!(!store.is_patient_suppressed("p2").await);
+        assert!(!store.is_patient_suppressed("p3").await);
+        assert!(!store.is_device_suppressed("d2").await);
+        assert_eq!(store.active().await.unwrap().len(), 1);
+
+        assert_eq!(store.cleanup_expired().await.unwrap(), 1);
+        assert_eq!(store.list().await.unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,354 @@
+/// Anomaly Scoring Module
+///
+/// Scores observations as they are broadcast and publishes only the anomalous
+/// ones, for the `/ws/anomalies` stream. The built-in detector compares each
+/// value with a rolling baseline of the same patient and signal (z-score); when
+/// `ANOMALY_SCORER=ml` the ML service's isolation forest decides instead, with
+/// the built-in verdict as fallback if the service can't answer.
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use utoipa::ToSchema;
+
+use crate::fhir::{subject_patient_id, FhirObservation};
+use crate::ml_client::{MlClient, ScoreSample};
+
+/// Readings kept per stream for the baseline
+const DEFAULT_WINDOW: usize = 100;
+
+/// Readings needed before a stream is scored at all
+const DEFAULT_MIN_SAMPLES: usize = 20;
+
+/// Distance from the baseline mean, in standard deviations, that counts as anomalous
+const DEFAULT_Z_THRESHOLD: f64 = 3.0;
+
+/// Most recent readings sent to the ML service (it derives rolling features)
+const ML_CONTEXT: usize = 5;
+
+/// Streams tracked at once; beyond this an arbitrary stream is forgotten
+const MAX_STREAMS: usize = 10_000;
+
+/// Anomalies remembered for dashboards; older ones are forgotten
+const LOG_RETENTION_HOURS: i64 = 24;
+
+/// Anomalies remembered at once, however recent
+const LOG_MAX_EVENTS: usize = 10_000;
+
+/// An observation judged anomalous, as sent to `/ws/anomalies` subscribers
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct AnomalyEvent {
+    pub observation_id: String,
+    pub subject: String,
+    pub code: String,
+    pub value: f64,
+    pub unit: String,
+    pub effective_date_time: DateTime<Utc>,
+    /// z-score for the built-in detector, isolation forest score for the ML service
+    pub score: f64,
+    pub category: String,
+    /// `builtin` or `ml`
+    pub detector: &'static str,
+}
+
+impl AnomalyEvent {
+    fn new(obs: &FhirObservation, score: f64, category: String, detector: &'static str) -> Self {
+        Self {
+            observation_id: obs.id.clone(),
+            subject: obs.subject.reference.clone(),
+            code: stream_code(obs).to_string(),
+            value: obs.value_quantity.value,
+            unit: obs.value_quantity.unit.clone(),
+            effective_date_time: obs.effective_date_time,
+            score,
+            category,
+            detector,
+        }
+    }
+}
+
+/// Anomalies raised over the last day, oldest first, so dashboards can
+/// show a patient's alerts without subscribing to the stream
+#[derive(Debug, Default)]
+pub struct AnomalyLog(Mutex<VecDeque<AnomalyEvent>>);
+
+impl AnomalyLog {
+    pub fn record(&self, event: AnomalyEvent) {
+        let mut events = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        let cutoff = Utc::now() - chrono::Duration::hours(LOG_RETENTION_HOURS);
+        while events
+            .front()
+            .is_some_and(|e| e.effective_date_time < cutoff || events.len() >= LOG_MAX_EVENTS)
+        {
+            events.pop_front();
+        }
+        events.push_back(event);
+    }
+
+    /// Anomalies of `patient_id`'s readings taken since `since`
+    pub fn for_patient(&self, patient_id: &str, since: DateTime<Utc>) -> Vec<AnomalyEvent> {
+        let events = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        events
+            .iter()
+            .filter(|e| e.effective_date_time >= since)
+            .filter(|e| subject_patient_id(&e.subject) == Some(patient_id))
+            .cloned()
+            .collect()
+    }
+
+    /// Drop every anomaly of `patient_id`'s readings
+    pub fn forget_patient(&self, patient_id: &str) {
+        let mut events = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        events.retain(|e| subject_patient_id(&e.subject) != Some(patient_id));
+    }
+}
+
+fn stream_code(obs: &FhirObservation) -> &str {
+    obs.code
+        .coding
+        .first()
+        .map(|c| c.code.as_str())
+        .unwrap_or("")
+}
+
+/// Outcome of the built-in detector for one observation
+#[derive(Debug)]
+pub struct Assessment {
+    /// `None` until the stream has enough history
+    pub z_score: Option<f64>,
+    /// The stream's latest readings, this one included, oldest first
+    pub context: Vec<ScoreSample>,
+}
+
+/// Rolling z-score detector, one baseline per patient and signal
+#[derive(Debug)]
+pub struct AnomalyDetector {
+    streams: Mutex<HashMap<String, VecDeque<ScoreSample>>>,
+    window: usize,
+    min_samples: usize,
+    z_threshold: f64,
+}
+
+impl AnomalyDetector {
+    pub fn new(window: usize, min_samples: usize, z_threshold: f64) -> Self {
+        Self {
+            streams: Mutex::new(HashMap::new()),
+            window: window.max(2),
+            min_samples: min_samples.clamp(2, window.max(2)),
+            z_threshold,
+        }
+    }
+
+    /// Record an observation and score it against the stream's prior readings
+    pub fn observe(&self, obs: &FhirObservation) -> Assessment {
+        let key = format!("{}|{}", obs.subject.reference, stream_code(obs));
+        let sample = ScoreSample {
+            value: obs.value_quantity.value,
+            timestamp: obs.effective_date_time,
+        };
+
+        let Ok(mut streams) = self.streams.lock() else {
+            return Assessment {
+                z_score: None,
+                context: vec![sample],
+            };
+        };
+        if streams.len() >= MAX_STREAMS && !streams.contains_key(&key) {
+            if let Some(victim) = streams.keys().next().cloned() {
+                streams.remove(&victim);
+            }
+        }
+
+        let history = streams.entry(key).or_default();
+        let z_score = (history.len() >= self.min_samples).then(|| z_score(history, sample.value));
+
+        if history.len() >= self.window {
+            history.pop_front();
+        }
+        history.push_back(sample);
+
+        let skip = history.len().saturating_sub(ML_CONTEXT);
+        Assessment {
+            z_score,
+            context: history.iter().skip(skip).cloned().collect(),
+        }
+    }
+
+    /// Whether a z-score counts as anomalous
+    pub fn is_anomalous(&self, z_score: f64) -> bool {
+        z_score.abs() >= self.z_threshold
+    }
+}
+
+/// Signed distance of `value` from the history's mean in standard deviations.
+/// The deviation is floored at 1% of the mean so a flat baseline doesn't turn
+/// every tiny change into an anomaly.
+fn z_score(history: &VecDeque<ScoreSample>, value: f64) -> f64 {
+    let n = history.len() as f64;
+    let mean = history.iter().map(|s| s.value).sum::<f64>() / n;
+    let variance = history
+        .iter()
+        .map(|s| (s.value - mean).powi(2))
+        .sum::<f64>()
+        / n;
+    let std_dev = variance.sqrt().max(mean.abs() * 0.01).max(f64::EPSILON);
+    (value - mean) / std_dev
+}
+
+/// Decides which observations are anomalous
+#[derive(Debug)]
+pub struct AnomalyScorer {
+    detector: AnomalyDetector,
+    ml_client: Option<Arc<MlClient>>,
+}
+
+impl AnomalyScorer {
+    pub fn new(detector: AnomalyDetector, ml_client: Option<Arc<MlClient>>) -> Self {
+        Self {
+            detector,
+            ml_client,
+        }
+    }
+
+    /// The built-in detector with its default settings
+    pub fn builtin() -> Self {
+        Self::new(
+            AnomalyDetector::new(DEFAULT_WINDOW, DEFAULT_MIN_SAMPLES, DEFAULT_Z_THRESHOLD),
+            None,
+        )
+    }
+
+    /// Create from `ANOMALY_SCORER` (`builtin` (default), `ml` or `off`),
+    /// `ANOMALY_Z_THRESHOLD` and `ANOMALY_MIN_SAMPLES`. `ml` without a
+    /// configured ML service falls back to the built-in detector.
+    pub fn from_env(ml_client: Option<Arc<MlClient>>) -> Option<Self> {
+        let mode = std::env::var("ANOMALY_SCORER").unwrap_or_else(|_| "builtin".into());
+        let ml_client = match mode.to_ascii_lowercase().as_str() {
+            "off" => return None,
+            "ml" => {
+                if ml_client.is_none() {
+                    tracing::warn!(
+                        "ANOMALY_SCORER=ml but ML_SERVICE_URL is not set; using built-in detector"
+                    );
+                }
+                ml_client
+            }
+            _ => None,
+        };
+
+        let z_threshold = std::env::var("ANOMALY_Z_THRESHOLD")
+            .ok()
+            .and_then(|v| v.parse::<f64>().ok())
+            .filter(|v| *v > 0.0)
+            .unwrap_or(DEFAULT_Z_THRESHOLD);
+        let min_samples = std::env::var("ANOMALY_MIN_SAMPLES")
+            .ok()
+            .and_then(|v| v.parse::<usize>().ok())
+            .unwrap_or(DEFAULT_MIN_SAMPLES);
+
+        Some(Self::new(
+            AnomalyDetector::new(DEFAULT_WINDOW, min_samples, z_threshold),
+            ml_client,
+        ))
+    }
+
+    /// Whether scoring involves a call to the ML service
+    pub fn uses_ml(&self) -> bool {
+        self.ml_client.is_some()
+    }
+
+    /// Built-in scoring only; cheap enough to run inline
+    pub fn score_builtin(&self, obs: &FhirObservation) -> (Option<AnomalyEvent>, Assessment) {
+        let assessment = self.detector.observe(obs);
+        let event = assessment
+            .z_score
+            .filter(|z| self.detector.is_anomalous(*z))
+            .map(|z| {
+                let category = if z > 0.0 {
+                    "above_baseline"
+                } else {
+                    "below_baseline"
+                };
+                AnomalyEvent::new(obs, z, category.into(), "builtin")
+            });
+        (event, assessment)
+    }
+
+    /// Score an observation, asking the ML service when configured
+    pub async fn score(&self, obs: &FhirObservation) -> Option<AnomalyEvent> {
+        let (builtin, assessment) = self.score_builtin(obs);
+        let Some(client) = &self.ml_client else {
+            return builtin;
+        };
+
+        match client.score_readings(&assessment.context).await {
+            Ok(score) if score.is_anomaly => Some(AnomalyEvent::new(
+                obs,
+                score.anomaly_score,
+                score.category,
+                "ml",
+            )),
+            Ok(_) => None,
+            Err(e) => {
+                tracing::debug!(
+                    "ML anomaly scoring unavailable, using built-in verdict: {}",
+                    e
+                );
+                builtin
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::models::{SensorReading, SignalCode, TimestampSource};
+
+    fn obs(patient_id: &str, value: f64) -> FhirObservation {
+        FhirObservation::from_reading(SensorReading {
+            patient_id: patient_id.into(),
+            device_id: "d1".into(),
+            code: SignalCode::Sound,
+            value,
+            unit: "raw".into(),
+            ts: Utc::now(),
+            ts_source: TimestampSource::Device,
+            metadata: Default::default(),
+        })
+    }
+
+    #[test]
+    fn test_outlier_is_flagged_after_warmup() {
+        let scorer = AnomalyScorer::new(AnomalyDetector::new(100, 10, 3.0), None);
+
+        for i in 0..30 {
+            let (event, _) = scorer.score_builtin(&obs("p1", 200.0 + (i % 5) as f64));
+            assert!(event.is_none());
+        }
+
+        let (event, assessment) = scorer.score_builtin(&obs("p1", 900.0));
+        let event = event.unwrap();
+        assert_eq!(event.subject, "Patient/p1");
+        assert_eq!(event.category, "above_baseline");
+        assert!(event.score > 3.0);
+        assert_eq!(assessment.context.len(), ML_CONTEXT);
+        assert_eq!(assessment.context.last().unwrap().value, 900.0);
+
+        // Baselines are per patient; a new one has too little history to judge
+        assert!(scorer.score_builtin(&obs("p2", 5000.0)).0.is_none());
+    }
+
+    #[test]
+    fn test_flat_baseline_tolerates_small_changes() {
+        let detector = AnomalyDetector::new(100, 10, 3.0);
+        for _ in 0..20 {
+            detector.observe(&obs("p1", 100.0));
+        }
+
+        let z = detector.observe(&obs("p1", 101.0)).z_score.unwrap();
+        assert!(!detector.is_anomalous(z));
+        let z = detector.observe(&obs("p1", 80.0)).z_score.unwrap();
+        assert!(detector.is_anomalous(z));
+    }
+}
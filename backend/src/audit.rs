@@ -5,6 +5,7 @@
 use chrono::Utc;
 use serde::{Deserialize, Serialize};
 use sqlx::PgPool;
+use utoipa::ToSchema;
 use uuid::Uuid;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -181,48 +182,63 @@ impl AuditLogger {
         entry.log(&self.pool).await
     }
 
-    /// Query audit logs for a specific patient (for patient access reports)
+    /// Query audit logs for a specific patient (for patient access reports),
+    /// newest first, `limit` entries after the `after` cursor; `None` if the
+    /// cursor is not one of the patient's entries
     pub async fn get_patient_access_log(
         &self,
         patient_id: &str,
         limit: i64,
-    ) -> Result<Vec<AuditLogSummary>, sqlx::Error> {
-        let logs = sqlx::query_as::<_, AuditLogSummary>(
-            r#"
-            SELECT 
-                id,
-                timestamp,
-                user_id,
-                user_role,
-                action,
-                resource_type,
-                patient_id,
-                status_code,
-                CASE 
-                    WHEN error_message IS NOT NULL THEN 'Error occurred'
-                    ELSE 'Success'
-                END as outcome
-            FROM audit_log_summary
-            WHERE patient_id = $1
-            ORDER BY timestamp DESC
-            LIMIT $2
-            "#,
-        )
-        .bind(patient_id)
-        .bind(limit)
-        .fetch_all(&self.pool)
-        .await?;
-
-        Ok(logs)
+        after: Option<Uuid>,
+    ) -> Result<Option<AuditPage>, sqlx::Error> {
+        self.page(AuditSubject::Patient, patient_id, limit, after)
+            .await
     }
 
-    /// Query audit logs for a specific user
+    /// Query audit logs for a specific user, newest first, `limit` entries
+    /// after the `after` cursor; `None` if the cursor is not one of the
+    /// user's entries
     pub async fn get_user_activity_log(
         &self,
         user_id: &str,
         limit: i64,
-    ) -> Result<Vec<AuditLogSummary>, sqlx::Error> {
-        let logs = sqlx::query_as::<_, AuditLogSummary>(
+        after: Option<Uuid>,
+    ) -> Result<Option<AuditPage>, sqlx::Error> {
+        self.page(AuditSubject::User, user_id, limit, after).await
+    }
+
+    /// One page of the entries of `subject` `value`. Pages are keyed on
+    /// (timestamp, id) of the last entry seen rather than an offset, so
+    /// entries logged while paging shift nothing: they precede the first
+    /// page. `None` for a cursor that names no entry of `value`, such as a
+    /// made-up one or one whose entry has since been purged, rather than an
+    /// empty page that reads as the end of the log.
+    async fn page(
+        &self,
+        subject: AuditSubject,
+        value: &str,
+        limit: i64,
+        after: Option<Uuid>,
+    ) -> Result<Option<AuditPage>, sqlx::Error> {
+        let column = subject.column();
+        let after = match after {
+            Some(id) => {
+                let timestamp: Option<chrono::DateTime<Utc>> = sqlx::query_scalar(&format!(
+                    "SELECT timestamp FROM audit_logs WHERE id = $1 AND {column} = $2"
+                ))
+                .bind(id)
+                .bind(value)
+                .fetch_optional(&self.pool)
+                .await?;
+                match timestamp {
+                    Some(timestamp) => Some((timestamp, id)),
+                    None => return Ok(None),
+                }
+            }
+            None => None,
+        };
+        // One more than asked for tells whether another page follows
+        let mut entries = sqlx::query_as::<_, AuditLogSummary>(&format!(
             r#"
             SELECT 
                 id,
@@ -233,26 +249,70 @@ impl AuditLogger {
                 resource_type,
                 patient_id,
                 status_code,
-                CASE 
-                    WHEN error_message IS NOT NULL THEN 'Error occurred'
-                    ELSE 'Success'
-                END as outcome
+                outcome
             FROM audit_log_summary
-            WHERE user_id = $1
-            ORDER BY timestamp DESC
-            LIMIT $2
-            "#,
-        )
-        .bind(user_id)
-        .bind(limit)
+            WHERE {column} = $1
+              AND ($2::timestamptz IS NULL OR (timestamp, id) < ($2, $3))
+            ORDER BY timestamp DESC, id DESC
+            LIMIT $4
+            "#
+        ))
+        .bind(value)
+        .bind(after.map(|(timestamp, _)| timestamp))
+        .bind(after.map(|(_, id)| id))
+        .bind(limit + 1)
         .fetch_all(&self.pool)
         .await?;
 
-        Ok(logs)
+        let next_cursor = if entries.len() as i64 > limit {
+            entries.truncate(limit as usize);
+            entries.last().map(|entry| entry.id)
+        } else {
+            None
+        };
+
+        let total_count: i64 = sqlx::query_scalar(&format!(
+            "SELECT COUNT(*) FROM audit_logs WHERE {column} = $1"
+        ))
+        .bind(value)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(Some(AuditPage {
+            entries,
+            next_cursor,
+            total_count,
+        }))
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+/// Whose entries an audit log query returns
+#[derive(Debug, Clone, Copy)]
+enum AuditSubject {
+    Patient,
+    User,
+}
+
+impl AuditSubject {
+    fn column(self) -> &'static str {
+        match self {
+            AuditSubject::Patient => "patient_id",
+            AuditSubject::User => "user_id",
+        }
+    }
+}
+
+/// One page of audit log entries, newest first
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct AuditPage {
+    pub entries: Vec<AuditLogSummary>,
+    /// Pass as `after` for the next page; `None` on the last
+    pub next_cursor: Option<Uuid>,
+    /// Entries across all pages
+    pub total_count: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow, ToSchema)]
 pub struct AuditLogSummary {
     pub id: Uuid,
     pub timestamp: chrono::DateTime<Utc>,
@@ -1,21 +1,136 @@
 /// JWT Authentication Module
 ///
 /// Handles JWT token creation, validation, and user authentication.
-use actix_web::{dev::ServiceRequest, Error, HttpMessage};
+use actix_web::error::InternalError;
+use actix_web::http::header;
+use actix_web::{dev::ServiceRequest, web, Error, HttpMessage, HttpResponse};
 use actix_web_httpauth::extractors::bearer::BearerAuth;
 use chrono::{Duration, Utc};
-use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
+use jsonwebtoken::errors::ErrorKind;
+use jsonwebtoken::{decode, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use utoipa::ToSchema;
+
+use crate::oidc::OidcValidator;
+use crate::security::DEFAULT_JWT_SECRET;
+use crate::sessions::SessionStore;
+
+/// Scope of a restricted token that may only call the password-change endpoint
+pub const SCOPE_PASSWORD_CHANGE: &str = "password_change";
+
+/// Path the password-change scope is limited to
+const PASSWORD_CHANGE_PATH: &str = "/api/auth/password";
+
+/// Scope of the service tokens the backend sends to the ML service; they are
+/// signed with our key but never accepted by our own API
+pub const SCOPE_ML_SERVICE: &str = "ml_service";
+
+/// Realm named in `WWW-Authenticate` challenges
+pub const AUTH_REALM: &str = "soundsense";
+
+/// `WWW-Authenticate` value of a 401 (RFC 6750). `rejection` describes a
+/// token that was presented and refused; without one the client is only
+/// told how to authenticate.
+pub fn bearer_challenge(rejection: Option<&str>) -> String {
+    match rejection {
+        None => format!("Bearer realm=\"{}\"", AUTH_REALM),
+        Some(description) => format!(
+            "Bearer realm=\"{}\", error=\"invalid_token\", error_description=\"{}\"",
+            AUTH_REALM, description
+        ),
+    }
+}
+
+/// Why a token failed validation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TokenError {
+    /// Well-formed and correctly signed, but past its `exp`
+    Expired,
+    /// Decodes, but the signature, algorithm or a claim check fails
+    Invalid(String),
+    /// Not a decodable JWT at all
+    Malformed(String),
+}
+
+impl TokenError {
+    /// Short description for the client, as sent in the `WWW-Authenticate`
+    /// challenge and the response body
+    pub fn description(&self) -> &'static str {
+        match self {
+            TokenError::Expired => "Token expired",
+            TokenError::Invalid(_) => "Invalid token",
+            TokenError::Malformed(_) => "Malformed token",
+        }
+    }
+}
+
+impl From<jsonwebtoken::errors::Error> for TokenError {
+    fn from(e: jsonwebtoken::errors::Error) -> Self {
+        match e.kind() {
+            ErrorKind::ExpiredSignature => TokenError::Expired,
+            ErrorKind::InvalidToken
+            | ErrorKind::Base64(_)
+            | ErrorKind::Json(_)
+            | ErrorKind::Utf8(_) => TokenError::Malformed(e.to_string()),
+            _ => TokenError::Invalid(e.to_string()),
+        }
+    }
+}
+
+impl std::fmt::Display for TokenError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TokenError::Expired => write!(f, "{}", self.description()),
+            TokenError::Invalid(e) | TokenError::Malformed(e) => {
+                write!(f, "{}: {}", self.description(), e)
+            }
+        }
+    }
+}
+
+/// What a token may do; each role grants a fixed set, see `Claims::can`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Permission {
+    /// Read readings, statistics and reports (`admin`, `user`, `viewer`)
+    ReadData,
+    /// Submit readings (`admin`, `user`, `device`)
+    WriteReadings,
+    /// Amend stored Observations (`admin`, `user`)
+    AmendReadings,
+    /// Administer users, patients, devices and the server, delete readings
+    /// and train models (`admin`)
+    Admin,
+}
+
+impl Permission {
+    /// What the permission allows, for error messages
+    pub fn describe(&self) -> &'static str {
+        match self {
+            Permission::ReadData => "read patient data",
+            Permission::WriteReadings => "submit readings",
+            Permission::AmendReadings => "amend readings",
+            Permission::Admin => "administer the server",
+        }
+    }
+}
 
 /// JWT Claims structure
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
 pub struct Claims {
     pub sub: String,  // Subject (user ID or identifier)
     pub exp: i64,     // Expiration time (Unix timestamp)
     pub iat: i64,     // Issued at (Unix timestamp)
-    pub role: String, // User role (admin, user, device, etc.)
+    pub role: String, // User role (admin, user, viewer, device)
     #[serde(skip_serializing_if = "Option::is_none")]
     pub device_id: Option<String>, // For device authentication
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jti: Option<String>, // Token ID, used for the session inventory and revocation
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>, // Restricts the token to a single purpose (e.g. password change)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub permitted_patients: Option<Vec<String>>, // Patients a `user` may read; unrestricted if absent
 }
 
 impl Claims {
@@ -35,97 +150,514 @@ impl Claims {
             iat: now.timestamp(),
             role,
             device_id,
+            jti: Some(uuid::Uuid::new_v4().to_string()),
+            scope: None,
+            permitted_patients: None,
+        }
+    }
+
+    /// Restrict the token to a single purpose
+    pub fn with_scope(mut self, scope: &str) -> Self {
+        self.scope = Some(scope.to_string());
+        self
+    }
+
+    /// Limit a `user` or `viewer` token to these patients (an empty list
+    /// allows none)
+    pub fn with_permitted_patients(mut self, patients: Vec<String>) -> Self {
+        self.permitted_patients = Some(patients);
+        self
+    }
+
+    /// Patients the caller is limited to, or `None` if it may read all of them.
+    /// Only `user` and `viewer` tokens are ever limited.
+    pub fn patient_filter(&self) -> Option<&[String]> {
+        match &self.permitted_patients {
+            Some(patients) if matches!(self.role.as_str(), "user" | "viewer") => Some(patients),
+            _ => None,
         }
     }
 
+    /// Whether the token's role grants `permission`; unknown roles grant nothing
+    pub fn can(&self, permission: Permission) -> bool {
+        use Permission::*;
+        match self.role.as_str() {
+            "admin" => true,
+            "user" => matches!(permission, ReadData | WriteReadings | AmendReadings),
+            "viewer" => permission == ReadData,
+            "device" => permission == WriteReadings,
+            _ => false,
+        }
+    }
+
+    /// Read-only tokens (`viewer`) are refused every write with 403
+    pub fn is_read_only(&self) -> bool {
+        !self.can(Permission::WriteReadings) && self.can(Permission::ReadData)
+    }
+
+    /// Whether the caller may read the data of `patient_id`
+    pub fn may_read_patient(&self, patient_id: &str) -> bool {
+        self.patient_filter()
+            .is_none_or(|patients| patients.iter().any(|p| p == patient_id))
+    }
+
     /// Check if token is expired
     pub fn is_expired(&self) -> bool {
         Utc::now().timestamp() > self.exp
     }
 }
 
+/// Longest token lifetime accepted, in hours (ten years)
+pub const MAX_EXPIRY_HOURS: i64 = 87_600;
+
+/// Longest refresh token lifetime accepted, in days (ten years)
+pub const MAX_REFRESH_EXPIRY_DAYS: i64 = 3_650;
+
+/// Lifetime in hours of the tokens issued for each role, and in days of
+/// refresh tokens
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct JwtExpiryConfig {
+    pub admin_hours: i64,
+    /// Also applies to viewers
+    pub user_hours: i64,
+    pub device_hours: i64,
+    pub refresh_days: i64,
+}
+
+impl Default for JwtExpiryConfig {
+    fn default() -> Self {
+        Self {
+            admin_hours: 1,
+            user_hours: 8,
+            device_hours: 8760,
+            refresh_days: 30,
+        }
+    }
+}
+
+impl JwtExpiryConfig {
+    /// Read `JWT_EXPIRY_ADMIN_HOURS` (default 1), `JWT_EXPIRY_USER_HOURS`
+    /// (default 8), `JWT_EXPIRY_DEVICE_HOURS` (default 8760, a year) and
+    /// `JWT_REFRESH_EXPIRY_DAYS` (default 30). Set values must be whole
+    /// numbers from 1 to ten years' worth; anything else is an error.
+    pub fn from_env() -> Result<Self, String> {
+        Self::from_vars(|name| std::env::var(name).ok())
+    }
+
+    /// As `from_env`, looking variables up with `var`
+    pub fn from_vars(var: impl Fn(&str) -> Option<String>) -> Result<Self, String> {
+        let read = |name: &str, default: i64, max: i64| match var(name) {
+            None => Ok(default),
+            Some(v) => v
+                .trim()
+                .parse::<i64>()
+                .ok()
+                .filter(|n| (1..=max).contains(n))
+                .ok_or_else(|| {
+                    format!(
+                        "{} must be a whole number from 1 to {}, got '{}'",
+                        name, max, v
+                    )
+                }),
+        };
+        let defaults = Self::default();
+        Ok(Self {
+            admin_hours: read(
+                "JWT_EXPIRY_ADMIN_HOURS",
+                defaults.admin_hours,
+                MAX_EXPIRY_HOURS,
+            )?,
+            user_hours: read(
+                "JWT_EXPIRY_USER_HOURS",
+                defaults.user_hours,
+                MAX_EXPIRY_HOURS,
+            )?,
+            device_hours: read(
+                "JWT_EXPIRY_DEVICE_HOURS",
+                defaults.device_hours,
+                MAX_EXPIRY_HOURS,
+            )?,
+            refresh_days: read(
+                "JWT_REFRESH_EXPIRY_DAYS",
+                defaults.refresh_days,
+                MAX_REFRESH_EXPIRY_DAYS,
+            )?,
+        })
+    }
+
+    /// Hours a token for `role` is valid; roles other than admin and device
+    /// get the user lifetime
+    pub fn for_role(&self, role: &str) -> i64 {
+        match role {
+            "admin" => self.admin_hours,
+            "device" => self.device_hours,
+            _ => self.user_hours,
+        }
+    }
+}
+
+/// How locally issued tokens are signed
+#[derive(Clone)]
+pub enum JwtAlgorithm {
+    /// HMAC-SHA256 with a secret shared by issuer and verifier
+    HS256(String),
+    /// RSA-SHA256; verifiers only need the public key. Both keys are PEM.
+    RS256 {
+        private_key: Vec<u8>,
+        public_key: Vec<u8>,
+    },
+}
+
+impl std::fmt::Debug for JwtAlgorithm {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Never print key material
+        f.write_str(self.name())
+    }
+}
+
+impl JwtAlgorithm {
+    /// Read `JWT_ALGORITHM` (`HS256`, the default, or `RS256`). HS256 signs
+    /// with `JWT_SECRET`; RS256 loads the PEM files at `JWT_PRIVATE_KEY_PATH`
+    /// and `JWT_PUBLIC_KEY_PATH`.
+    pub fn from_env() -> Result<Self, String> {
+        match std::env::var("JWT_ALGORITHM") {
+            Err(_) => Ok(Self::hs256_from_env()),
+            Ok(name) if name.eq_ignore_ascii_case("HS256") => Ok(Self::hs256_from_env()),
+            Ok(name) if name.eq_ignore_ascii_case("RS256") => Self::rs256_from_env(),
+            Ok(name) => Err(format!(
+                "JWT_ALGORITHM must be HS256 or RS256, got '{}'",
+                name
+            )),
+        }
+    }
+
+    fn hs256_from_env() -> Self {
+        Self::HS256(std::env::var("JWT_SECRET").unwrap_or_else(|_| DEFAULT_JWT_SECRET.to_string()))
+    }
+
+    fn rs256_from_env() -> Result<Self, String> {
+        let read = |variable: &str| {
+            let path = std::env::var(variable)
+                .map_err(|_| format!("{} must be set when JWT_ALGORITHM=RS256", variable))?;
+            std::fs::read(&path).map_err(|e| format!("{}: cannot read {}: {}", variable, path, e))
+        };
+        let algorithm = Self::RS256 {
+            private_key: read("JWT_PRIVATE_KEY_PATH")?,
+            public_key: read("JWT_PUBLIC_KEY_PATH")?,
+        };
+
+        // Catch unusable keys at startup rather than on the first login
+        algorithm.encoding_key()?;
+        algorithm.decoding_key()?;
+        Ok(algorithm)
+    }
+
+    /// `HS256` or `RS256`
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::HS256(_) => "HS256",
+            Self::RS256 { .. } => "RS256",
+        }
+    }
+
+    fn header_algorithm(&self) -> Algorithm {
+        match self {
+            Self::HS256(_) => Algorithm::HS256,
+            Self::RS256 { .. } => Algorithm::RS256,
+        }
+    }
+
+    fn encoding_key(&self) -> Result<EncodingKey, String> {
+        match self {
+            Self::HS256(secret) => Ok(EncodingKey::from_secret(secret.as_bytes())),
+            Self::RS256 { private_key, .. } => EncodingKey::from_rsa_pem(private_key)
+                .map_err(|e| format!("Invalid RSA private key: {}", e)),
+        }
+    }
+
+    fn decoding_key(&self) -> Result<DecodingKey, String> {
+        match self {
+            Self::HS256(secret) => Ok(DecodingKey::from_secret(secret.as_bytes())),
+            Self::RS256 { public_key, .. } => DecodingKey::from_rsa_pem(public_key)
+                .map_err(|e| format!("Invalid RSA public key: {}", e)),
+        }
+    }
+}
+
+/// Parse `JWT_KEYS`: comma-separated `kid:secret` HS256 keys. Secrets may
+/// contain `:` but not `,`.
+pub fn parse_jwt_keys(spec: &str) -> Result<HashMap<String, JwtAlgorithm>, String> {
+    let mut keys = HashMap::new();
+    for pair in spec.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+        let (kid, secret) = pair
+            .split_once(':')
+            .filter(|(kid, secret)| !kid.trim().is_empty() && !secret.is_empty())
+            .ok_or_else(|| format!("JWT_KEYS entry '{}' is not kid:secret", pair))?;
+        let kid = kid.trim().to_string();
+        if keys
+            .insert(kid.clone(), JwtAlgorithm::HS256(secret.to_string()))
+            .is_some()
+        {
+            return Err(format!("JWT_KEYS names kid '{}' twice", kid));
+        }
+    }
+    if keys.is_empty() {
+        return Err("JWT_KEYS holds no keys".into());
+    }
+    Ok(keys)
+}
+
 /// JWT token manager
+///
+/// With several keys, each is named by a key id (`kid`). New tokens are
+/// signed with the current key and carry its `kid` in the header; a token
+/// is checked with the key its `kid` names, so tokens signed with an older
+/// key stay valid while that key remains configured. Tokens without a `kid`
+/// are checked with the current key.
 pub struct JwtManager {
-    secret: String,
+    /// Signs new tokens
+    algorithm: JwtAlgorithm,
+    /// `kid` of the current key, if keys are named
+    kid: Option<String>,
+    /// Every accepted key by `kid`, the current one included
+    keys: HashMap<String, JwtAlgorithm>,
 }
 
 impl JwtManager {
-    /// Create new JWT manager with secret key
-    pub fn new(secret: String) -> Self {
-        Self { secret }
+    /// Create new JWT manager signing with `algorithm`
+    pub fn new(algorithm: JwtAlgorithm) -> Self {
+        Self {
+            algorithm,
+            kid: None,
+            keys: HashMap::new(),
+        }
+    }
+
+    /// Create a manager accepting all of `keys`, signing with `current_kid`
+    pub fn with_keys(
+        keys: HashMap<String, JwtAlgorithm>,
+        current_kid: &str,
+    ) -> Result<Self, String> {
+        let algorithm = keys
+            .get(current_kid)
+            .cloned()
+            .ok_or_else(|| format!("current kid '{}' is not among the keys", current_kid))?;
+        Ok(Self {
+            algorithm,
+            kid: Some(current_kid.to_string()),
+            keys,
+        })
+    }
+
+    /// Algorithm and keys tokens are signed with
+    pub fn algorithm(&self) -> &JwtAlgorithm {
+        &self.algorithm
+    }
+
+    /// `kid` put in new tokens' headers, if keys are named
+    pub fn current_kid(&self) -> Option<&str> {
+        self.kid.as_deref()
+    }
+
+    /// Manager for the algorithm and keys configured in the environment:
+    /// `JWT_KEYS` with `JWT_CURRENT_KID` for rotatable HS256 keys, otherwise
+    /// the single key `JwtAlgorithm::from_env` reads
+    pub fn from_env() -> Result<Self, String> {
+        let Ok(spec) = std::env::var("JWT_KEYS") else {
+            return JwtAlgorithm::from_env().map(Self::new);
+        };
+        if std::env::var("JWT_ALGORITHM").is_ok_and(|a| !a.eq_ignore_ascii_case("HS256")) {
+            return Err(
+                "JWT_KEYS holds HS256 secrets; it cannot be combined with JWT_ALGORITHM=RS256"
+                    .into(),
+            );
+        }
+        let current_kid = std::env::var("JWT_CURRENT_KID")
+            .map_err(|_| "JWT_CURRENT_KID must be set when JWT_KEYS is".to_string())?;
+        Self::with_keys(parse_jwt_keys(&spec)?, &current_kid)
     }
 
     /// Generate JWT token
     pub fn generate_token(&self, claims: Claims) -> Result<String, String> {
-        let encoding_key = EncodingKey::from_secret(self.secret.as_bytes());
+        let encoding_key = self.algorithm.encoding_key()?;
+        let mut header = Header::new(self.algorithm.header_algorithm());
+        header.kid = self.kid.clone();
 
-        encode(&Header::default(), &claims, &encoding_key)
+        encode(&header, &claims, &encoding_key)
             .map_err(|e| format!("Failed to generate token: {}", e))
     }
 
-    /// Validate and decode JWT token
-    pub fn validate_token(&self, token: &str) -> Result<Claims, String> {
-        let decoding_key = DecodingKey::from_secret(self.secret.as_bytes());
-        let validation = Validation::default();
+    /// Validate and decode JWT token. The key is picked by the token's `kid`;
+    /// only tokens signed with that key's algorithm are accepted.
+    pub fn validate_token(&self, token: &str) -> Result<Claims, TokenError> {
+        let header = jsonwebtoken::decode_header(token)?;
+        let algorithm = match header.kid.as_deref() {
+            None => &self.algorithm,
+            Some(kid) => self
+                .keys
+                .get(kid)
+                .ok_or_else(|| TokenError::Invalid(format!("unknown key id '{}'", kid)))?,
+        };
+        let decoding_key = algorithm.decoding_key().map_err(TokenError::Invalid)?;
+        let validation = Validation::new(algorithm.header_algorithm());
 
         decode::<Claims>(token, &decoding_key, &validation)
             .map(|data| data.claims)
-            .map_err(|e| format!("Invalid token: {}", e))
+            .map_err(TokenError::from)
     }
 
     /// Extract token from Bearer header
     pub fn extract_bearer_token(auth_header: &str) -> Option<String> {
-        if auth_header.starts_with("Bearer ") {
-            Some(auth_header[7..].to_string())
-        } else {
-            None
-        }
+        auth_header.strip_prefix("Bearer ").map(|t| t.to_string())
     }
 }
 
 /// Middleware validator for JWT tokens
+///
+/// Tokens from the configured OIDC issuer (if any) are validated against the
+/// provider's JWKS; everything else is treated as a locally issued token.
 pub async fn jwt_validator(
     req: ServiceRequest,
     credentials: BearerAuth,
 ) -> Result<ServiceRequest, (Error, ServiceRequest)> {
-    let secret = std::env::var("JWT_SECRET")
-        .unwrap_or_else(|_| "default_secret_change_in_production".to_string());
+    match authenticate(req.request(), credentials.token()).await {
+        Ok(claims) => {
+            // Attach claims to request extensions for later use
+            req.extensions_mut().insert(claims);
+            Ok(req)
+        }
+        Err(e) => Err((e, req)),
+    }
+}
+
+/// Claims of `token` presented with `req`, or the 401 (403 for a
+/// password-change token off its endpoint) refusing it
+async fn authenticate(req: &actix_web::HttpRequest, token: &str) -> Result<Claims, Error> {
+    if let Some(oidc) = req.app_data::<web::Data<Arc<OidcValidator>>>().cloned() {
+        if oidc.handles(token) {
+            return match oidc.validate(token).await {
+                Ok(claims) => {
+                    tracing::debug!(
+                        "Authenticated OIDC request from user: {}, role: {}",
+                        claims.sub,
+                        claims.role
+                    );
+                    Ok(claims)
+                }
+                Err(e) => {
+                    tracing::warn!("Invalid OIDC token: {}", e);
+                    Err(reject_token("Invalid token"))
+                }
+            };
+        }
+    }
 
-    let jwt_manager = JwtManager::new(secret);
+    // Built once at startup and shared by all requests
+    let Some(jwt_manager) = req.app_data::<web::Data<Arc<JwtManager>>>().cloned() else {
+        tracing::error!("No JWT manager configured");
+        return Err(actix_web::error::ErrorInternalServerError(
+            "Authentication unavailable",
+        ));
+    };
 
-    match jwt_manager.validate_token(credentials.token()) {
+    match jwt_manager.validate_token(token) {
         Ok(claims) => {
             // Check if token is expired
             if claims.is_expired() {
                 tracing::warn!("Expired token attempt for user: {}", claims.sub);
-                return Err((actix_web::error::ErrorUnauthorized("Token expired"), req));
+                return Err(reject_token("Token expired"));
             }
 
-            // Attach claims to request extensions for later use
-            req.extensions_mut().insert(claims.clone());
+            // Revoked tokens are rejected; usage is recorded in the background
+            if let (Some(sessions), Some(jti)) = (
+                req.app_data::<web::Data<Arc<SessionStore>>>(),
+                claims.jti.as_deref(),
+            ) {
+                if sessions.is_revoked(jti) {
+                    tracing::warn!("Revoked token used by {}", claims.sub);
+                    return Err(reject_token("Token revoked"));
+                }
+                sessions.touch(jti);
+            }
+
+            // Service tokens minted for the ML service are only good there
+            if claims.scope.as_deref() == Some(SCOPE_ML_SERVICE) {
+                tracing::warn!("ML service token presented to the API by {}", claims.sub);
+                return Err(reject_token("Token not valid for this API"));
+            }
+
+            // Tokens issued for a forced password rotation only unlock that endpoint
+            if claims.scope.as_deref() == Some(SCOPE_PASSWORD_CHANGE)
+                && req.path() != PASSWORD_CHANGE_PATH
+            {
+                tracing::warn!(
+                    "Password-change token used by {} on {}",
+                    claims.sub,
+                    req.path()
+                );
+                return Err(actix_web::error::ErrorForbidden("Password change required"));
+            }
 
             tracing::debug!(
                 "Authenticated request from user: {}, role: {}",
                 claims.sub,
                 claims.role
             );
-            Ok(req)
+            Ok(claims)
         }
         Err(e) => {
-            tracing::warn!("Invalid token: {}", e);
-            Err((actix_web::error::ErrorUnauthorized("Invalid token"), req))
+            tracing::warn!("Rejected token: {}", e);
+            Err(reject_token(e.description()))
         }
     }
 }
 
+/// 401 for a refused token, with `description` as the body and in the
+/// `WWW-Authenticate` challenge
+fn reject_token(description: &'static str) -> Error {
+    let response = HttpResponse::Unauthorized()
+        .insert_header((
+            header::WWW_AUTHENTICATE,
+            bearer_challenge(Some(description)),
+        ))
+        .body(description);
+    InternalError::from_response(description, response).into()
+}
+
+/// Claims of the bearer token sent to a public endpoint, accepted or
+/// refused as `jwt_validator` would; `None` without a token or when it is
+/// refused, rather than a 401
+pub async fn optional_claims(req: &actix_web::HttpRequest) -> Option<Claims> {
+    use actix_web::FromRequest;
+
+    let credentials = BearerAuth::extract(req).await.ok()?;
+    authenticate(req, credentials.token()).await.ok()
+}
+
+/// Claims of the token in the `Authorization` header or, for browsers that
+/// can't set headers on a WebSocket handshake, the `access_token` query
+/// parameter; the 401 refusing it, or asking for one
+pub async fn require_token(
+    req: &actix_web::HttpRequest,
+    access_token: Option<&str>,
+) -> Result<Claims, Error> {
+    use actix_web::FromRequest;
+
+    let header = BearerAuth::extract(req).await.ok();
+    match header.as_ref().map(|h| h.token()).or(access_token) {
+        Some(token) => authenticate(req, token).await,
+        None => Err(reject_token("Token required")),
+    }
+}
+
 /// Helper to extract claims from request
 pub fn get_claims_from_request(req: &actix_web::HttpRequest) -> Option<Claims> {
     req.extensions().get::<Claims>().cloned()
 }
 
-/// Check if user has required role
+/// Check if user has required role; admins pass every check
 pub fn has_role(claims: &Claims, required_role: &str) -> bool {
-    claims.role == required_role || claims.role == "admin"
+    claims.role == required_role || claims.can(Permission::Admin)
 }
 
 #[cfg(test)]
@@ -134,7 +666,7 @@ mod tests {
 
     #[test]
     fn test_jwt_generation_and_validation() {
-        let manager = JwtManager::new("test_secret".to_string());
+        let manager = JwtManager::new(JwtAlgorithm::HS256("test_secret".to_string()));
         let claims = Claims::new("test_user".to_string(), "user".to_string(), None, 24);
 
         let token = manager.generate_token(claims.clone()).unwrap();
@@ -144,6 +676,105 @@ mod tests {
         assert_eq!(validated_claims.role, claims.role);
     }
 
+    #[test]
+    fn test_rs256_generation_and_validation() {
+        let rs256 = JwtAlgorithm::RS256 {
+            private_key: include_bytes!("../tests/fixtures/test_rsa_private.pem").to_vec(),
+            public_key: include_bytes!("../tests/fixtures/test_rsa_public.pem").to_vec(),
+        };
+        let manager = JwtManager::new(rs256);
+        let claims = Claims::new("test_user".to_string(), "admin".to_string(), None, 24);
+
+        let token = manager.generate_token(claims.clone()).unwrap();
+        let header = jsonwebtoken::decode_header(&token).unwrap();
+        assert_eq!(header.alg, Algorithm::RS256);
+
+        let validated_claims = manager.validate_token(&token).unwrap();
+        assert_eq!(validated_claims.sub, claims.sub);
+        assert_eq!(validated_claims.role, claims.role);
+
+        // Neither algorithm accepts the other's tokens
+        let hs256 = JwtManager::new(JwtAlgorithm::HS256("test_secret".to_string()));
+        assert!(matches!(
+            hs256.validate_token(&token),
+            Err(TokenError::Invalid(_))
+        ));
+        let hs256_token = hs256.generate_token(claims).unwrap();
+        assert!(matches!(
+            manager.validate_token(&hs256_token),
+            Err(TokenError::Invalid(_))
+        ));
+    }
+
+    #[test]
+    fn test_rotated_keys_are_picked_by_kid() {
+        let keys = parse_jwt_keys("2026-01:old-secret, 2026-02:new:secret").unwrap();
+        let before = JwtManager::with_keys(keys.clone(), "2026-01").unwrap();
+        let claims = Claims::new("test_user".to_string(), "user".to_string(), None, 24);
+        let old_token = before.generate_token(claims.clone()).unwrap();
+        assert_eq!(
+            jsonwebtoken::decode_header(&old_token)
+                .unwrap()
+                .kid
+                .as_deref(),
+            Some("2026-01")
+        );
+
+        // After rotation new tokens use the current kid, old ones still validate
+        let after = JwtManager::with_keys(keys, "2026-02").unwrap();
+        let new_token = after.generate_token(claims).unwrap();
+        assert_eq!(
+            jsonwebtoken::decode_header(&new_token)
+                .unwrap()
+                .kid
+                .as_deref(),
+            Some("2026-02")
+        );
+        assert_eq!(after.validate_token(&old_token).unwrap().sub, "test_user");
+        assert_eq!(after.validate_token(&new_token).unwrap().sub, "test_user");
+
+        // Once the old key is dropped its tokens are refused
+        let keys = parse_jwt_keys("2026-02:new:secret").unwrap();
+        let retired = JwtManager::with_keys(keys, "2026-02").unwrap();
+        assert!(matches!(
+            retired.validate_token(&old_token),
+            Err(TokenError::Invalid(_))
+        ));
+        assert!(retired.validate_token(&new_token).is_ok());
+
+        // Tokens without a kid are checked with the current key
+        let unnamed = JwtManager::new(JwtAlgorithm::HS256("new:secret".to_string()))
+            .generate_token(Claims::new(
+                "legacy".to_string(),
+                "user".to_string(),
+                None,
+                1,
+            ))
+            .unwrap();
+        assert_eq!(retired.validate_token(&unnamed).unwrap().sub, "legacy");
+    }
+
+    #[test]
+    fn test_jwt_keys_parsing() {
+        assert_eq!(parse_jwt_keys("a:1,b:2").unwrap().len(), 2);
+        assert!(parse_jwt_keys("").is_err());
+        assert!(parse_jwt_keys("no-secret").is_err());
+        assert!(parse_jwt_keys(":secret").is_err());
+        assert!(parse_jwt_keys("a:1,a:2").is_err());
+        assert!(JwtManager::with_keys(parse_jwt_keys("a:1").unwrap(), "b").is_err());
+    }
+
+    #[test]
+    fn test_rs256_rejects_unusable_keys() {
+        let manager = JwtManager::new(JwtAlgorithm::RS256 {
+            private_key: b"not a key".to_vec(),
+            public_key: b"not a key".to_vec(),
+        });
+        let claims = Claims::new("test_user".to_string(), "user".to_string(), None, 24);
+        assert!(manager.generate_token(claims).is_err());
+        assert!(manager.validate_token("a.b.c").is_err());
+    }
+
     #[test]
     fn test_token_expiration_check() {
         let claims = Claims::new("test_user".to_string(), "user".to_string(), None, 24);
@@ -151,6 +782,135 @@ mod tests {
         assert!(!claims.is_expired());
     }
 
+    #[test]
+    fn test_each_role_gets_its_configured_expiry() {
+        let expiry = JwtExpiryConfig {
+            admin_hours: 2,
+            user_hours: 12,
+            device_hours: 48,
+            ..JwtExpiryConfig::default()
+        };
+        assert_eq!(expiry.for_role("admin"), 2);
+        assert_eq!(expiry.for_role("user"), 12);
+        assert_eq!(expiry.for_role("viewer"), 12);
+        assert_eq!(expiry.for_role("device"), 48);
+
+        let defaults = JwtExpiryConfig::default();
+        assert_eq!(defaults.for_role("admin"), 1);
+        assert_eq!(defaults.for_role("user"), 8);
+        assert_eq!(defaults.for_role("device"), 8760);
+
+        for role in ["admin", "user", "device"] {
+            let hours = expiry.for_role(role);
+            let claims = Claims::new("someone".into(), role.into(), None, hours);
+            assert_eq!(claims.exp - claims.iat, hours * 3600);
+            assert!(!claims.is_expired());
+        }
+        assert!(Claims::new("someone".into(), "admin".into(), None, -1).is_expired());
+    }
+
+    #[test]
+    fn test_expiry_settings_are_bounded() {
+        let vars = |pairs: &'static [(&'static str, &'static str)]| {
+            move |name: &str| {
+                pairs
+                    .iter()
+                    .find(|(n, _)| *n == name)
+                    .map(|(_, v)| v.to_string())
+            }
+        };
+
+        assert_eq!(
+            JwtExpiryConfig::from_vars(vars(&[])),
+            Ok(JwtExpiryConfig::default())
+        );
+        let expiry = JwtExpiryConfig::from_vars(vars(&[
+            ("JWT_EXPIRY_ADMIN_HOURS", " 2 "),
+            ("JWT_EXPIRY_DEVICE_HOURS", "87600"),
+            ("JWT_REFRESH_EXPIRY_DAYS", "7"),
+        ]))
+        .unwrap();
+        assert_eq!(expiry.admin_hours, 2);
+        assert_eq!(expiry.user_hours, 8);
+        assert_eq!(expiry.device_hours, MAX_EXPIRY_HOURS);
+        assert_eq!(expiry.refresh_days, 7);
+
+        // Duration::hours panics far below i64::MAX, so huge values never
+        // get that far
+        for (name, value) in [
+            ("JWT_EXPIRY_ADMIN_HOURS", "0"),
+            ("JWT_EXPIRY_USER_HOURS", "-8"),
+            ("JWT_EXPIRY_DEVICE_HOURS", "9223372036854775807"),
+            ("JWT_EXPIRY_DEVICE_HOURS", "87601"),
+            ("JWT_REFRESH_EXPIRY_DAYS", "3651"),
+            ("JWT_REFRESH_EXPIRY_DAYS", "thirty"),
+        ] {
+            let err = JwtExpiryConfig::from_vars(|n: &str| (n == name).then(|| value.to_string()))
+                .unwrap_err();
+            assert!(err.contains(name), "{}", err);
+        }
+    }
+
+    #[test]
+    fn test_token_errors_are_told_apart() {
+        let manager = JwtManager::new(JwtAlgorithm::HS256("test_secret".to_string()));
+
+        // Past the validation leeway
+        let claims = Claims::new("test_user".to_string(), "user".to_string(), None, -1);
+        let expired = manager.generate_token(claims).unwrap();
+        assert_eq!(
+            manager.validate_token(&expired).unwrap_err(),
+            TokenError::Expired
+        );
+
+        let claims = Claims::new("test_user".to_string(), "user".to_string(), None, 1);
+        let token = manager.generate_token(claims).unwrap();
+
+        // Signed with another secret
+        let other = JwtManager::new(JwtAlgorithm::HS256("other_secret".to_string()));
+        assert!(matches!(
+            other.validate_token(&token),
+            Err(TokenError::Invalid(_))
+        ));
+
+        // Payload swapped for another one: the signature no longer matches
+        let parts: Vec<&str> = token.split('.').collect();
+        let admin = manager
+            .generate_token(Claims::new("test_user".into(), "admin".into(), None, 1))
+            .unwrap();
+        let forged = format!(
+            "{}.{}.{}",
+            parts[0],
+            admin.split('.').nth(1).unwrap(),
+            parts[2]
+        );
+        assert!(matches!(
+            manager.validate_token(&forged),
+            Err(TokenError::Invalid(_))
+        ));
+
+        // The signature is checked before the payload is decoded, so only a
+        // broken header or shape makes a token malformed
+        let tampered = format!("{}x!.{}.{}", parts[0], parts[1], parts[2]);
+        assert!(matches!(
+            manager.validate_token(&tampered),
+            Err(TokenError::Malformed(_))
+        ));
+        assert!(matches!(
+            manager.validate_token("not-a-jwt"),
+            Err(TokenError::Malformed(_))
+        ));
+    }
+
+    #[test]
+    fn test_bearer_challenge() {
+        assert_eq!(bearer_challenge(None), "Bearer realm=\"soundsense\"");
+        assert_eq!(
+            bearer_challenge(Some("Token expired")),
+            "Bearer realm=\"soundsense\", error=\"invalid_token\", error_description=\"Token expired\""
+        );
+    }
+
     #[test]
     fn test_extract_bearer_token() {
         let header = "Bearer eyJhbGciOiJIUzI1NiIsInR5cCI6IkpXVCJ9";
@@ -176,4 +936,50 @@ mod tests {
         assert!(has_role(&admin_claims, "admin"));
         assert!(has_role(&admin_claims, "user")); // Admin can access user routes
     }
+
+    #[test]
+    fn test_role_permissions() {
+        use Permission::*;
+        let claims = |role: &str| Claims::new("someone".to_string(), role.to_string(), None, 24);
+        let all = [ReadData, WriteReadings, AmendReadings, Admin];
+        let granted = |role: &str| -> Vec<Permission> {
+            all.into_iter().filter(|p| claims(role).can(*p)).collect()
+        };
+
+        assert_eq!(granted("admin"), all);
+        assert_eq!(granted("user"), [ReadData, WriteReadings, AmendReadings]);
+        assert_eq!(granted("viewer"), [ReadData]);
+        assert_eq!(granted("device"), [WriteReadings]);
+        assert!(granted("auditor").is_empty());
+
+        assert!(claims("viewer").is_read_only());
+        assert!(!claims("user").is_read_only());
+        assert!(!claims("device").is_read_only());
+        assert!(!has_role(&claims("viewer"), "user"));
+    }
+
+    #[test]
+    fn test_permitted_patients_only_limit_users() {
+        let user = Claims::new("user1".to_string(), "user".to_string(), None, 24)
+            .with_permitted_patients(vec!["p1".to_string()]);
+        assert!(user.may_read_patient("p1"));
+        assert!(!user.may_read_patient("p2"));
+
+        let nobody = Claims::new("user2".to_string(), "user".to_string(), None, 24)
+            .with_permitted_patients(Vec::new());
+        assert_eq!(nobody.patient_filter(), Some(&[][..]));
+        assert!(!nobody.may_read_patient("p1"));
+
+        // Tokens without the claim, and other roles, are not limited
+        let legacy = Claims::new("user3".to_string(), "user".to_string(), None, 24);
+        assert!(legacy.patient_filter().is_none());
+        let admin = Claims::new("admin1".to_string(), "admin".to_string(), None, 24)
+            .with_permitted_patients(Vec::new());
+        assert!(admin.may_read_patient("p2"));
+
+        // Viewers are limited like users
+        let viewer = Claims::new("viewer1".to_string(), "viewer".to_string(), None, 24)
+            .with_permitted_patients(vec!["p1".to_string()]);
+        assert!(!viewer.may_read_patient("p2"));
+    }
 }
@@ -4,7 +4,15 @@ use reqwest::Client;
 use std::time::Duration;
 use tokio::time::sleep;
 
-use soundsense_backend::domain::models::{SensorReading, SignalCode};
+use soundsense_backend::domain::models::{
+    normalize_unit, IngestReceipt, SensorReading, SignalCode, TimestampSource,
+};
+
+/// The part of the ingest response the simulator reports
+#[derive(serde::Deserialize)]
+struct IngestAck {
+    receipt: IngestReceipt,
+}
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -12,8 +20,10 @@ async fn main() -> Result<()> {
     // Locally:  "http://127.0.0.1:8080"
     let base = std::env::var("BASE_URL").unwrap_or_else(|_| "http://127.0.0.1:8080".into());
     let token = std::env::var("INGEST_TOKEN").ok();
+    // Same default as the serial reader, so both sources report one unit
+    let unit = normalize_unit(&std::env::var("SIMULATOR_UNIT").unwrap_or_else(|_| "raw".into()));
 
-    eprintln!("simulator starting. BASE_URL={base}");
+    eprintln!("simulator starting. BASE_URL={base} unit={unit}");
 
     let client = Client::builder()
         .timeout(Duration::from_secs(3))
@@ -29,8 +39,10 @@ async fn main() -> Result<()> {
             device_id: "simulator-1".into(),
             code: SignalCode::Sound, // keep canonical
             value: rng.gen_range(150.0..260.0),
-            unit: "au".into(),
+            unit: unit.clone(),
             ts: now,
+            ts_source: TimestampSource::Device,
+            metadata: Default::default(),
         };
 
         let url = format!("{}/ingest", base);
@@ -46,7 +58,15 @@ async fn main() -> Result<()> {
             Ok(resp) => {
                 let status = resp.status();
                 if status.is_success() {
-                    eprintln!("sent ok: value={} status={}", reading.value, status);
+                    match resp.json::<IngestAck>().await {
+                        Ok(IngestAck { receipt }) => eprintln!(
+                            "sent ok: value={} status={} id={} storage={:?} persisted={}",
+                            reading.value, status, receipt.id, receipt.storage, receipt.persisted
+                        ),
+                        Err(_) => {
+                            eprintln!("sent ok: value={} status={}", reading.value, status)
+                        }
+                    }
                 } else {
                     let body = resp.text().await.unwrap_or_default();
                     eprintln!("sent failed: status={} body={}", status, body);
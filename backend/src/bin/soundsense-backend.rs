@@ -1,12 +1,37 @@
-use actix_cors::Cors;
 use actix_web::{middleware, web, App, HttpServer};
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
 use std::time::Duration;
 use tokio::sync::Mutex;
 
-use soundsense_backend::db::Database;
+use soundsense_backend::acoustics;
+use soundsense_backend::bulk_export::BulkExports;
+use soundsense_backend::calibration;
+use soundsense_backend::compliance::ComplianceReportGenerator;
+use soundsense_backend::consent::ConsentMode;
+use soundsense_backend::cors::CorsAllowlist;
+use soundsense_backend::db::{self, Database};
+use soundsense_backend::decimation::Decimator;
+use soundsense_backend::device_watchdog::DeviceWatchdog;
+use soundsense_backend::dlq::{DeadLetterQueue, InsertRetry};
 use soundsense_backend::domain::store::AppState;
-use soundsense_backend::{routes, serial_ingest, telemetry::init_tracing};
+use soundsense_backend::fhir;
+use soundsense_backend::ingest_queue::IngestQueue;
+use soundsense_backend::iot_auth::{self, IotAuthStore};
+use soundsense_backend::load_shed::IngestLimiter;
+use soundsense_backend::log_sampling;
+use soundsense_backend::ml_cache::MlResultCache;
+use soundsense_backend::normalization::Normalizer;
+use soundsense_backend::oidc::{OidcConfig, OidcValidator};
+use soundsense_backend::rollups::RollupJob;
+use soundsense_backend::routes::{self, RouteDeps};
+use soundsense_backend::security::validate_security_config;
+use soundsense_backend::self_check;
+use soundsense_backend::serial_ingest::{SerialConfig, SerialStatus};
+use soundsense_backend::sessions::SessionStore;
+use soundsense_backend::snapshot::SnapshotManager;
+use soundsense_backend::stream_ingest::StreamIngestConfig;
+use soundsense_backend::validation::{self, ValidationPipeline};
+use soundsense_backend::{serial_ingest, telemetry::init_tracing};
 
 fn get_arg_value(flag: &str) -> Option<String> {
     let mut args = std::env::args();
@@ -18,9 +43,109 @@ fn get_arg_value(flag: &str) -> Option<String> {
     None
 }
 
+fn has_flag(flag: &str) -> bool {
+    std::env::args().any(|a| a == flag)
+}
+
+/// Serial line settings: --baud 115200 or SERIAL_BAUD, and
+/// --serial-timeout-ms or SERIAL_TIMEOUT_MS
+fn serial_config() -> Result<SerialConfig, String> {
+    SerialConfig::parse(
+        get_arg_value("--baud")
+            .or_else(|| std::env::var("SERIAL_BAUD").ok())
+            .as_deref(),
+        get_arg_value("--serial-timeout-ms")
+            .or_else(|| std::env::var("SERIAL_TIMEOUT_MS").ok())
+            .as_deref(),
+    )
+}
+
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
+    // --check tries the configuration and everything it points at, prints
+    // the report (as JSON with --json) and exits 0 if all of it works.
+    // Logging stays off so the report is all that's printed.
+    if has_flag("--check") {
+        let serial_port = get_arg_value("--serial").or_else(|| std::env::var("SERIAL_PORT").ok());
+        let report = self_check::check_environment(serial_port.as_deref(), serial_config()).await;
+        if has_flag("--json") {
+            println!("{}", serde_json::to_string_pretty(&report)?);
+        } else {
+            println!("{}", report);
+        }
+        std::process::exit(if report.ok { 0 } else { 1 });
+    }
+
     init_tracing();
+    let log_sample_rate = log_sampling::init_from_env();
+    if log_sample_rate > 1 {
+        tracing::info!(
+            rate = log_sample_rate,
+            "Logging 1 in {} hot-path debug events",
+            log_sample_rate
+        );
+    }
+
+    // --migrate-only applies pending migrations to DATABASE_URL and exits
+    // without starting the server, so a deploy can migrate ahead of the
+    // rollout; exits 1 if there is no database or a migration fails
+    if has_flag("--migrate-only") {
+        let Ok(database_url) = std::env::var("DATABASE_URL") else {
+            tracing::error!("--migrate-only needs DATABASE_URL");
+            std::process::exit(1);
+        };
+        match db::migrate_database_url(&database_url).await {
+            Ok(applied) if applied.is_empty() => {
+                tracing::info!("Database schema is up to date, no migrations applied");
+            }
+            Ok(applied) => {
+                tracing::info!(versions = ?applied, "Applied {} database migrations", applied.len());
+            }
+            Err(e) => {
+                tracing::error!(error = %e, "Failed to run database migrations");
+                std::process::exit(1);
+            }
+        }
+        return Ok(());
+    }
+
+    let subject_base = fhir::init_subject_base_from_env();
+    if subject_base != fhir::DEFAULT_SUBJECT_BASE {
+        tracing::info!(%subject_base, "FHIR subject references use a configured base");
+    }
+    match fhir::init_extra_codings_from_env() {
+        Ok(0) => {}
+        Ok(count) => tracing::info!(count, "Publishing extra FHIR codings for signal codes"),
+        Err(e) => {
+            tracing::error!("{}", e);
+            return Err(std::io::Error::other(e));
+        }
+    }
+
+    // Default or weak secrets are logged; STRICT_SECURITY=true makes them fatal
+    if let Err(e) = validate_security_config() {
+        tracing::error!("{}", e);
+        return Err(std::io::Error::other(e));
+    }
+
+    // The WebSocket hub, ML client and token manager are shared across HTTP
+    // workers, so every live client sees readings regardless of which worker
+    // accepted them. An unknown JWT_ALGORITHM or unreadable RS256 keys would
+    // fail every login, and a misspelt PUBLIC_INGEST could leave the
+    // unauthenticated route open, so refuse to start.
+    let mut deps = match RouteDeps::from_env() {
+        Ok(deps) => {
+            tracing::info!(
+                "Signing tokens with {}",
+                deps.jwt_manager.algorithm().name()
+            );
+            deps
+        }
+        Err(e) => {
+            tracing::error!("{}", e);
+            return Err(std::io::Error::other(e));
+        }
+    };
 
     // host/port for the HTTP server binding
     let host = std::env::var("HOST").unwrap_or_else(|_| "0.0.0.0".to_string());
@@ -33,6 +158,15 @@ async fn main() -> std::io::Result<()> {
     // 1) CLI: --serial COM6
     // 2) ENV: SERIAL_PORT=COM6
     let serial_port = get_arg_value("--serial").or_else(|| std::env::var("SERIAL_PORT").ok());
+    // Line settings the same way. A wrong baud rate reads as garbage, so a
+    // malformed one refuses to start.
+    let serial_config = match serial_config() {
+        Ok(config) => config,
+        Err(e) => {
+            tracing::error!("{}", e);
+            return Err(std::io::Error::other(e));
+        }
+    };
 
     // token optional
     let token = std::env::var("INGEST_TOKEN").ok();
@@ -43,6 +177,49 @@ async fn main() -> std::io::Result<()> {
     let ingest_url =
         std::env::var("INGEST_URL").unwrap_or_else(|_| format!("http://127.0.0.1:{}/ingest", port));
 
+    let consent_mode = ConsentMode::from_env();
+    let calibration_tolerance = calibration::tolerance_from_env();
+    let a_weighting = acoustics::a_weighting_from_env();
+    let allowed_signal_codes = match validation::allowed_signal_codes_from_env() {
+        Ok(codes) => codes,
+        Err(e) => {
+            tracing::error!("{}", e);
+            return Err(std::io::Error::other(e));
+        }
+    };
+    let nonce_key = match iot_auth::nonce_key_from_env() {
+        Ok(key) => key,
+        Err(e) => {
+            tracing::error!("{}", e);
+            return Err(std::io::Error::other(e));
+        }
+    };
+    if nonce_key.is_none() {
+        tracing::warn!(
+            "IOT_NONCE_KEY not set; IoT nonces only work on the process that issued them"
+        );
+    }
+    let iot_auth = |db: Option<Database>| {
+        let store = IotAuthStore::new(db);
+        Arc::new(match nonce_key {
+            Some(key) => store.with_nonce_key(key),
+            None => store,
+        })
+    };
+    let in_memory_state = || {
+        web::Data::new(Arc::new(Mutex::new(
+            AppState::new_demo()
+                .with_iot_auth(iot_auth(None))
+                .with_consent_mode(consent_mode)
+                .with_calibration_tolerance(calibration_tolerance)
+                .with_a_weighting(a_weighting)
+                .with_decimator(Decimator::from_env())
+                .with_validation(
+                    ValidationPipeline::default().with_allowed_signal_codes(&allowed_signal_codes),
+                ),
+        )))
+    };
+
     // Initialize database connection if DATABASE_URL is provided
     let state = if let Ok(database_url) = std::env::var("DATABASE_URL") {
         tracing::info!("Connecting to database...");
@@ -57,64 +234,321 @@ async fn main() -> std::io::Result<()> {
                 tracing::info!("Database connected successfully");
 
                 // Run migrations
-                match sqlx::migrate!("./migrations").run(&pool).await {
-                    Ok(_) => {
-                        tracing::info!("Database migrations completed successfully");
+                match db::migrate(&pool).await {
+                    Ok(applied) => {
+                        tracing::info!(
+                            applied = applied.len(),
+                            "Database migrations completed successfully"
+                        );
                         let db = Database::new(pool);
-                        web::Data::new(Arc::new(Mutex::new(AppState::with_database(db))))
+                        RollupJob::from_env(db.clone()).await.spawn();
+                        // Each month's audit activity, reported after it ends
+                        ComplianceReportGenerator::new(db.clone()).spawn();
+                        // A rule that can't be built would let readings through
+                        // unchecked, so refuse to start
+                        let validation = match ValidationPipeline::load(&db).await {
+                            Ok(validation) => {
+                                validation.with_allowed_signal_codes(&allowed_signal_codes)
+                            }
+                            Err(e) => {
+                                tracing::error!("{}", e);
+                                return Err(std::io::Error::other(e));
+                            }
+                        };
+                        if !validation.is_empty() {
+                            tracing::info!("Validation rules enabled: {:?}", validation);
+                        }
+
+                        // Intervals and scales set per device with PUT
+                        // /api/devices/{id}
+                        let mut decimator = Decimator::from_env();
+                        let mut normalizer = Normalizer::default();
+                        match db.list_device_settings().await {
+                            Ok(settings) => {
+                                for s in settings {
+                                    decimator.set_interval(&s.device_id, s.decimation_interval_ms);
+                                    normalizer.set_profile(&s.device_id, s.profile());
+                                }
+                            }
+                            Err(e) => {
+                                tracing::warn!(error = %e, "Failed to load device settings");
+                            }
+                        }
+
+                        let mut state = AppState::with_database(db.clone())
+                            .with_iot_auth(iot_auth(Some(db.clone())))
+                            .with_consent_mode(consent_mode)
+                            .with_calibration_tolerance(calibration_tolerance)
+                            .with_a_weighting(a_weighting)
+                            .with_decimator(decimator)
+                            .with_normalizer(normalizer)
+                            .with_validation(validation)
+                            .with_insert_retry(InsertRetry::from_env());
+
+                        // Writes still failing after the retries are parked
+                        // on disk and replayed later
+                        match DeadLetterQueue::from_env() {
+                            Ok(dlq) => {
+                                let dlq = Arc::new(dlq);
+                                dlq.spawn_retry(db, DeadLetterQueue::retry_interval_from_env());
+                                state = state.with_dlq(dlq);
+                            }
+                            Err(e) => {
+                                tracing::error!(error = %e, "Failed to open dead-letter queue");
+                            }
+                        }
+
+                        web::Data::new(Arc::new(Mutex::new(state)))
                     }
                     Err(e) => {
                         tracing::error!(error = %e, "Failed to run database migrations");
                         tracing::warn!("Falling back to in-memory storage");
-                        web::Data::new(Arc::new(Mutex::new(AppState::new_demo())))
+                        in_memory_state()
                     }
                 }
             }
             Err(e) => {
                 tracing::error!(error = %e, "Failed to connect to database");
                 tracing::warn!("Falling back to in-memory storage");
-                web::Data::new(Arc::new(Mutex::new(AppState::new_demo())))
+                in_memory_state()
             }
         }
     } else {
         tracing::info!("DATABASE_URL not set, using in-memory storage only");
-        web::Data::new(Arc::new(Mutex::new(AppState::new_demo())))
+        in_memory_state()
     };
 
+    // Without a database, readings are snapshotted to SNAPSHOT_PATH so a
+    // restart with LOAD_SNAPSHOT=true picks up where the last one left off
+    let snapshots = match SnapshotManager::from_env().map(Arc::new) {
+        Some(_) if state.lock().await.has_database() => {
+            tracing::info!("SNAPSHOT_PATH ignored: readings are kept in the database");
+            None
+        }
+        Some(snapshots) => {
+            if SnapshotManager::load_from_env() {
+                match snapshots.load().await {
+                    Ok(Some(readings)) => {
+                        tracing::info!(
+                            count = readings.len(),
+                            path = %snapshots.path().display(),
+                            "Restored readings from snapshot"
+                        );
+                        state.lock().await.restore_from_snapshot(readings);
+                    }
+                    Ok(None) => tracing::info!(
+                        path = %snapshots.path().display(),
+                        "No snapshot to restore yet"
+                    ),
+                    // Starting empty would overwrite the file with the next
+                    // snapshot, so leave it for the operator to look at
+                    Err(e) => {
+                        tracing::error!(
+                            error = %e,
+                            path = %snapshots.path().display(),
+                            "Failed to restore snapshot"
+                        );
+                        return Err(e);
+                    }
+                }
+            }
+            snapshots.spawn(
+                state.get_ref().clone(),
+                SnapshotManager::interval_from_env(),
+            );
+            Some(snapshots)
+        }
+        None => None,
+    };
+
+    // CORS allowlist: loaded from the database and refreshed periodically.
+    // CORS_DEV_MODE=true reverts to allow-any for local development.
+    let cors_allowlist = CorsAllowlist::from_env();
+    if cors_allowlist.is_dev_mode() {
+        tracing::warn!("CORS_DEV_MODE enabled: accepting requests from any origin");
+    } else if let Some(db) = state.lock().await.database() {
+        cors_allowlist.reload(&db).await;
+
+        let refresh_secs = std::env::var("CORS_REFRESH_SECS")
+            .ok()
+            .and_then(|v| v.parse::<u64>().ok())
+            .unwrap_or(300);
+        cors_allowlist.spawn_refresh(db, Duration::from_secs(refresh_secs));
+    } else {
+        tracing::warn!("CORS allowlist has no database; cross-origin requests will be rejected");
+    }
+
+    // Optional external identity provider (e.g. Keycloak). Local HS256 tokens
+    // keep working alongside; tokens are routed by their issuer.
+    // Users and viewers it signs in read the patients assigned to their sub
+    let users = state.lock().await.users();
+    let oidc = match OidcConfig::from_env().map(OidcValidator::new).transpose() {
+        Ok(validator) => validator.map(|v| Arc::new(v.with_users(users))),
+        Err(e) => {
+            tracing::error!("{}", e);
+            return Err(std::io::Error::other(e));
+        }
+    };
+    if let Some(validator) = &oidc {
+        tracing::info!(issuer = %validator.issuer(), "OIDC token validation enabled");
+        if let Err(e) = validator.refresh().await {
+            tracing::warn!(error = %e, "Initial OIDC JWKS fetch failed; will retry in background");
+        }
+        validator.spawn_refresh();
+    }
+
+    // Inventory of issued tokens; revoked tokens are loaded up front and
+    // expired entries cleaned up periodically
+    let sessions = Arc::new(SessionStore::new(state.lock().await.database()));
+    if let Err(e) = sessions.reload_revoked().await {
+        tracing::warn!(error = ?e, "Failed to load revoked tokens");
+    }
+    sessions.spawn_cleanup(Duration::from_secs(600));
+
+    // Used IoT nonces only matter until they expire
+    state
+        .lock()
+        .await
+        .iot_auth()
+        .spawn_cleanup(Duration::from_secs(60));
+
+    // Anomalies and device alerts are checked against suppression windows
+    // before they are raised, and audited once they are; ended windows are
+    // deleted periodically
+    let alert_suppressions = state.lock().await.alert_suppressions();
+    alert_suppressions.spawn_cleanup(Duration::from_secs(600));
+    deps.hub = deps
+        .hub
+        .with_suppressions(alert_suppressions)
+        .with_audit(state.lock().await.users());
+
+    // A training run cannot outlive the backend's poller; don't let one left
+    // in progress block new runs
+    let training_jobs = state.lock().await.training_jobs();
+    match training_jobs.fail_interrupted().await {
+        Ok(n) if n > 0 => tracing::warn!(count = n, "Marked interrupted training jobs failed"),
+        Ok(_) => {}
+        Err(e) => tracing::warn!(error = ?e, "Failed to check for interrupted training jobs"),
+    }
+
+    // Bulk exports are recorded in the database; ones a previous run left
+    // in progress never finished their files
+    deps.exports = Arc::new(BulkExports::from_env().with_database(state.lock().await.database()));
+    match deps.exports.fail_interrupted().await {
+        Ok(n) if n > 0 => tracing::warn!(count = n, "Marked interrupted bulk exports failed"),
+        Ok(_) => {}
+        Err(e) => tracing::warn!(error = ?e, "Failed to check for interrupted bulk exports"),
+    }
+
+    // Last good ML results, served as stale while the ML service is down
+    let ml_cache = MlResultCache::from_env().map(Arc::new);
+
+    // Ingest requests are enqueued and persisted by a single background worker,
+    // which publishes them to the shared hub (also scoring them for the
+    // anomaly stream).
+    let (ingest_queue, ingest_worker) = IngestQueue::from_env();
+    ingest_worker.spawn(state.get_ref().clone(), deps.hub.clone());
+
+    // Devices silent past their grace period raise alerts on /ws/alerts
+    DeviceWatchdog::from_env().spawn(
+        state.get_ref().clone(),
+        deps.hub.clone(),
+        DeviceWatchdog::interval_from_env(),
+    );
+
+    // Concurrent ingest requests are capped process-wide; excess gets 429
+    let ingest_limiter = IngestLimiter::from_env();
+
+    // Line limits, batch size and WebSocket subsampling of NDJSON backfills
+    let stream_ingest = StreamIngestConfig::from_env();
+
+    // Responses are gzip/deflate/brotli/zstd encoded when the client accepts
+    // it. WebSocket upgrades are never encoded, so live streams are unaffected.
+    let compression = std::env::var("COMPRESSION_ENABLED")
+        .map(|v| !v.eq_ignore_ascii_case("false"))
+        .unwrap_or(true);
+    if !compression {
+        tracing::info!("Response compression disabled");
+    }
+
     tracing::info!(%host, %port, "starting backend");
 
-    // Start serial ingest thread (only if serial provided)
-    if let Some(serial_port) = serial_port.clone() {
+    if deps.public_ingest.is_enabled() {
+        tracing::warn!(
+            "PUBLIC INGEST ENABLED: POST /ingest stores readings without authentication. \
+             This is meant for demos and the simulator; set PUBLIC_INGEST=disabled in production"
+        );
+    } else {
+        tracing::info!("Public ingest disabled: POST /ingest answers 404");
+        if serial_port.is_some() && std::env::var("INGEST_URL").is_err() {
+            tracing::warn!(
+                "Serial readings go to {}; set INGEST_URL to /api/ingest and INGEST_TOKEN",
+                ingest_url
+            );
+        }
+    }
+
+    // Start serial ingest thread (only if serial provided). Its status is
+    // served at /api/serial/status and summarised in /healthz.
+    let serial_status = serial_port
+        .as_ref()
+        .map(|port| Arc::new(RwLock::new(SerialStatus::new(port))));
+    if let (Some(serial_port), Some(status)) = (serial_port.clone(), serial_status.clone()) {
         let token = token.clone();
         let ingest_url = ingest_url.clone();
 
         std::thread::spawn(move || {
-            eprintln!("Serial ingest starting on {serial_port} -> {ingest_url}");
-            if let Err(e) = serial_ingest::run_serial_to_ingest(
+            tracing::info!(
+                port = %serial_port,
+                baud = serial_config.baud,
+                %ingest_url,
+                "Serial ingest starting"
+            );
+            serial_ingest::run_serial_to_ingest(
                 &serial_port,
-                9600,
+                serial_config,
                 &ingest_url,
                 token.as_deref(),
-            ) {
-                eprintln!("serial ingest error: {e:?}");
-            }
+                status,
+            );
         });
     } else {
-        eprintln!("No serial port provided. Run with: --serial COM6  (or set SERIAL_PORT=COM6)");
+        tracing::info!(
+            "No serial port provided. Run with: --serial COM6  (or set SERIAL_PORT=COM6)"
+        );
     }
 
     HttpServer::new(move || {
-        let cors = Cors::default()
-            .allow_any_origin()
-            .allow_any_method()
-            .allow_any_header()
-            .max_age(3600);
+        let cors = cors_allowlist.build_cors();
 
         App::new()
             .app_data(state.clone())
+            .app_data(web::Data::new(cors_allowlist.clone()))
+            .app_data(web::Data::new(sessions.clone()))
+            .wrap(middleware::Condition::new(
+                compression,
+                middleware::Compress::default(),
+            ))
             .wrap(cors)
             .wrap(middleware::Logger::default())
-            .configure(routes::configure)
+            .configure(|cfg| {
+                if let Some(validator) = &oidc {
+                    cfg.app_data(web::Data::new(validator.clone()));
+                }
+                if let Some(cache) = &ml_cache {
+                    cfg.app_data(web::Data::new(cache.clone()));
+                }
+                if let Some(snapshots) = &snapshots {
+                    cfg.app_data(web::Data::from(snapshots.clone()));
+                }
+                if let Some(status) = &serial_status {
+                    cfg.app_data(web::Data::from(status.clone()));
+                }
+            })
+            .configure(|cfg| routes::configure_with(cfg, &deps))
+            .app_data(web::Data::new(ingest_queue.clone()))
+            .app_data(web::Data::new(ingest_limiter.clone()))
+            .app_data(web::Data::new(stream_ingest))
     })
     .bind((host.as_str(), port))?
     .run()
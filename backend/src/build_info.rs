@@ -0,0 +1,36 @@
+/// Build Information
+///
+/// Which build is running, for `GET /version`. The commit and build time
+/// are captured by `build.rs`; see there for the overrides used by Docker
+/// and reproducible builds.
+use chrono::{DateTime, Utc};
+
+/// Crate version
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Commit the binary was built from; `unknown` outside a git checkout
+pub const GIT_COMMIT: &str = env!("SOUNDSENSE_GIT_COMMIT");
+
+/// Build time as Unix seconds
+const BUILD_EPOCH: &str = env!("SOUNDSENSE_BUILD_EPOCH");
+
+/// When the binary was built
+pub fn built_at() -> Option<DateTime<Utc>> {
+    BUILD_EPOCH
+        .parse::<i64>()
+        .ok()
+        .and_then(|secs| DateTime::from_timestamp(secs, 0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build_info_is_captured() {
+        assert!(!GIT_COMMIT.is_empty());
+        let built_at = built_at().unwrap();
+        assert!(built_at <= Utc::now());
+        assert!(built_at.timestamp() > 0);
+    }
+}
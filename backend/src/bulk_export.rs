@@ -0,0 +1,567 @@
+/// Bulk FHIR Export
+///
+/// `POST /api/fhir/$export` (and, for admins, `GET
+/// /api/fhir/Observation/$export`) starts a job that writes Observations to
+/// gzip-compressed NDJSON files under `BULK_EXPORT_DIR`, one page at a time,
+/// so neither the request nor the server holds the whole result. Each file
+/// holds at most 100k Observations. Clients poll the job's status URL until
+/// it lists the output files, as in the asynchronous SMART Bulk Data
+/// pattern, and cancel a job by deleting that URL. Jobs are recorded in the
+/// database when there is one, and the patients an export included are
+/// written to the audit log.
+use chrono::{DateTime, Utc};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::Serialize;
+use std::collections::{BTreeSet, HashMap};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::sync::{Mutex, RwLock};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::audit::{AuditAction, AuditLogEntry};
+use crate::db::Database;
+use crate::domain::models::ReadingFilter;
+use crate::domain::store::AppState;
+use crate::errors::AppError;
+use crate::fhir::FhirObservation;
+
+/// Readings read and written per step
+const DEFAULT_PAGE_SIZE: usize = 1000;
+
+/// Observations per output file
+const DEFAULT_FILE_SIZE: usize = 100_000;
+
+/// Jobs kept; the oldest finished one and its files go first
+const MAX_JOBS: usize = 100;
+
+/// Name of output file `part` (counting from 1) of a job
+pub fn output_file_name(part: usize) -> String {
+    format!("Observation-{}.ndjson.gz", part)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportStatus {
+    InProgress,
+    Completed,
+    Failed,
+    /// Deleted while in progress; only ever seen in the database
+    Cancelled,
+}
+
+impl ExportStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ExportStatus::InProgress => "in_progress",
+            ExportStatus::Completed => "completed",
+            ExportStatus::Failed => "failed",
+            ExportStatus::Cancelled => "cancelled",
+        }
+    }
+}
+
+/// An export and its progress
+#[derive(Debug, Clone)]
+pub struct ExportJob {
+    pub job_id: Uuid,
+    /// Subject of the token that started it; only they and admins may poll
+    pub owner: String,
+    /// Role of that token, for the audit log
+    pub role: String,
+    /// Patients the export is limited to, as the caller's token was
+    pub patients: Option<Vec<String>>,
+    /// Only readings taken at or after this time
+    pub since: Option<DateTime<Utc>>,
+    /// The kick-off request URL, echoed in the completion manifest
+    pub request: String,
+    /// When the export started
+    pub transaction_time: DateTime<Utc>,
+    pub status: ExportStatus,
+    /// Observations written so far
+    pub exported: u64,
+    /// Observations in each output file so far, in file order
+    pub files: Vec<u64>,
+    /// Patients whose readings were written so far
+    pub included: BTreeSet<String>,
+    pub error: Option<String>,
+}
+
+impl ExportJob {
+    /// Whether the token subject `sub` with `role` may see this job
+    pub fn visible_to(&self, sub: &str, role: &str) -> bool {
+        role == "admin" || self.owner == sub
+    }
+
+    /// Whether `name` is one of the job's output files
+    pub fn has_file(&self, name: &str) -> bool {
+        (1..=self.files.len()).any(|part| output_file_name(part) == name)
+    }
+}
+
+/// Body of a finished export's status response
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportManifest {
+    pub transaction_time: DateTime<Utc>,
+    pub request: String,
+    /// Output files need the same bearer token as the API
+    pub requires_access_token: bool,
+    pub output: Vec<ExportOutput>,
+    pub error: Vec<ExportOutput>,
+}
+
+/// One file of an export
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ExportOutput {
+    /// FHIR resource type of every line
+    #[serde(rename = "type")]
+    pub resource_type: String,
+    pub url: String,
+    pub count: u64,
+}
+
+impl ExportManifest {
+    /// Manifest of a completed `job` polled at `status_url`, under which its
+    /// files are served
+    pub fn new(job: &ExportJob, status_url: &str) -> Self {
+        Self {
+            transaction_time: job.transaction_time,
+            request: job.request.clone(),
+            requires_access_token: true,
+            output: job
+                .files
+                .iter()
+                .enumerate()
+                .map(|(i, count)| ExportOutput {
+                    resource_type: "Observation".into(),
+                    url: format!("{}/{}", status_url, output_file_name(i + 1)),
+                    count: *count,
+                })
+                .collect(),
+            error: Vec::new(),
+        }
+    }
+}
+
+/// Export jobs of this process, and the directory their files go to
+#[derive(Debug)]
+pub struct BulkExports {
+    dir: PathBuf,
+    page_size: usize,
+    file_size: usize,
+    /// Where jobs are recorded, if anywhere
+    db: Option<Database>,
+    jobs: RwLock<HashMap<Uuid, ExportJob>>,
+}
+
+impl Default for BulkExports {
+    fn default() -> Self {
+        Self::new(std::env::temp_dir().join("soundsense-exports"))
+    }
+}
+
+impl BulkExports {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            page_size: DEFAULT_PAGE_SIZE,
+            file_size: DEFAULT_FILE_SIZE,
+            db: None,
+            jobs: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Write exports under `BULK_EXPORT_DIR` (default: a directory in the
+    /// system temp dir)
+    pub fn from_env() -> Self {
+        match std::env::var("BULK_EXPORT_DIR") {
+            Ok(dir) if !dir.trim().is_empty() => Self::new(dir),
+            _ => Self::default(),
+        }
+    }
+
+    /// Directory the job directories are created in
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Read and write `page_size` readings per step instead of 1000
+    pub fn with_page_size(mut self, page_size: usize) -> Self {
+        self.page_size = page_size.max(1);
+        self
+    }
+
+    /// Start a new output file every `file_size` Observations instead of
+    /// every 100k
+    pub fn with_file_size(mut self, file_size: usize) -> Self {
+        self.file_size = file_size.max(1);
+        self
+    }
+
+    /// Record jobs in `db`'s `export_jobs` table
+    pub fn with_database(mut self, db: Option<Database>) -> Self {
+        self.db = db;
+        self
+    }
+
+    /// Output file `name` of `job_id`
+    pub fn output_path(&self, job_id: Uuid, name: &str) -> PathBuf {
+        self.job_dir(job_id).join(name)
+    }
+
+    fn job_dir(&self, job_id: Uuid) -> PathBuf {
+        self.dir.join(job_id.to_string())
+    }
+
+    pub async fn get(&self, job_id: Uuid) -> Option<ExportJob> {
+        self.jobs.read().await.get(&job_id).cloned()
+    }
+
+    /// Record a job for `owner` and run it in the background; 409 if they
+    /// have one in progress already
+    pub async fn start(
+        self: &Arc<Self>,
+        state: Arc<Mutex<AppState>>,
+        owner: &str,
+        role: &str,
+        patients: Option<Vec<String>>,
+        since: Option<DateTime<Utc>>,
+        request: String,
+    ) -> Result<ExportJob, AppError> {
+        let job = ExportJob {
+            job_id: Uuid::new_v4(),
+            owner: owner.to_string(),
+            role: role.to_string(),
+            patients,
+            since,
+            request,
+            transaction_time: Utc::now(),
+            status: ExportStatus::InProgress,
+            exported: 0,
+            files: Vec::new(),
+            included: BTreeSet::new(),
+            error: None,
+        };
+
+        {
+            let mut jobs = self.jobs.write().await;
+            if jobs
+                .values()
+                .any(|j| j.owner == owner && j.status == ExportStatus::InProgress)
+            {
+                return Err(AppError::Conflict(
+                    "an export is already in progress".into(),
+                ));
+            }
+            if jobs.len() >= MAX_JOBS {
+                self.evict_oldest_finished(&mut jobs);
+            }
+            jobs.insert(job.job_id, job.clone());
+        }
+        if let Some(db) = &self.db {
+            if let Err(e) = db.insert_export_job(&job).await {
+                self.jobs.write().await.remove(&job.job_id);
+                return Err(e);
+            }
+        }
+
+        let exports = self.clone();
+        let started = job.clone();
+        tokio::spawn(async move {
+            let job_id = started.job_id;
+            let outcome = exports.run(&state, &started).await;
+            let finished = {
+                let mut jobs = exports.jobs.write().await;
+                let Some(job) = jobs.get_mut(&job_id) else {
+                    tracing::info!(%job_id, "Bulk export cancelled");
+                    exports.remove_files(job_id);
+                    return;
+                };
+                match &outcome {
+                    Ok(_) => {
+                        job.status = ExportStatus::Completed;
+                        tracing::info!(%job_id, exported = job.exported, "Bulk export finished");
+                    }
+                    Err(e) => {
+                        tracing::error!(%job_id, error = %e, "Bulk export failed");
+                        job.status = ExportStatus::Failed;
+                        job.error = Some(e.to_string());
+                    }
+                }
+                job.clone()
+            };
+            exports.record(&finished).await;
+            if let Ok(patients) = outcome {
+                exports.audit(&state, &finished, patients).await;
+            }
+        });
+
+        Ok(job)
+    }
+
+    /// Cancel `job_id` if it is running, or delete its files if it has
+    /// finished; `false` if there is no such job
+    pub async fn cancel(&self, job_id: Uuid) -> bool {
+        let Some(mut job) = self.jobs.write().await.remove(&job_id) else {
+            return false;
+        };
+        if job.status == ExportStatus::InProgress {
+            // The job stops after its current page and removes its files
+            job.status = ExportStatus::Cancelled;
+            self.record(&job).await;
+        } else {
+            self.remove_files(job_id);
+        }
+        true
+    }
+
+    /// Cancel or delete every job that has written readings of
+    /// `patient_id`, so an erased patient's data doesn't live on in export
+    /// files; returns how many jobs went
+    pub async fn forget_patient(&self, patient_id: &str) -> usize {
+        let job_ids: Vec<Uuid> = self
+            .jobs
+            .read()
+            .await
+            .values()
+            .filter(|j| j.included.contains(patient_id))
+            .map(|j| j.job_id)
+            .collect();
+        let mut forgotten = 0;
+        for job_id in job_ids {
+            if self.cancel(job_id).await {
+                forgotten += 1;
+            }
+        }
+        forgotten
+    }
+
+    /// Fail jobs left in progress by a previous run of the backend; their
+    /// files were never finished
+    pub async fn fail_interrupted(&self) -> Result<u64, AppError> {
+        match &self.db {
+            Some(db) => {
+                db.fail_in_progress_export_jobs("backend restarted while exporting")
+                    .await
+            }
+            None => Ok(0),
+        }
+    }
+
+    /// Page through the job's readings, appending each page to its files;
+    /// returns the patients whose readings were written
+    async fn run(
+        &self,
+        state: &Mutex<AppState>,
+        job: &ExportJob,
+    ) -> Result<BTreeSet<String>, AppError> {
+        let dir = self.job_dir(job.job_id);
+        let file_size = self.file_size;
+        let mut writer = tokio::task::spawn_blocking(move || {
+            std::fs::create_dir_all(&dir)?;
+            Ok(OutputWriter::new(dir, file_size))
+        })
+        .await
+        .map_err(|_| AppError::Internal)?
+        .map_err(io_error)?;
+
+        let filter = ReadingFilter {
+            patients: job.patients.as_deref(),
+            ..Default::default()
+        };
+        let mut patients = BTreeSet::new();
+        // Nil sorts first, so every reading at `since` is included
+        let mut after = job.since.map(|since| (since, Uuid::nil()));
+        loop {
+            let page = state
+                .lock()
+                .await
+                .readings_after(after, self.page_size, &filter)
+                .await?;
+            let Some(last) = page.last() else {
+                break;
+            };
+            after = Some((last.reading.ts, last.id));
+            let count = page.len();
+
+            let mut lines = Vec::with_capacity(count);
+            for stored in page {
+                if !patients.contains(&stored.reading.patient_id) {
+                    patients.insert(stored.reading.patient_id.clone());
+                }
+                let mut line = serde_json::to_vec(&FhirObservation::from_stored(stored))
+                    .map_err(|_| AppError::Internal)?;
+                line.push(b'\n');
+                lines.push(line);
+            }
+            writer = tokio::task::spawn_blocking(move || {
+                for line in &lines {
+                    writer.write_line(line)?;
+                }
+                Ok(writer)
+            })
+            .await
+            .map_err(|_| AppError::Internal)?
+            .map_err(io_error)?;
+
+            match self.jobs.write().await.get_mut(&job.job_id) {
+                Some(job) => {
+                    job.exported += count as u64;
+                    job.files = writer.counts.clone();
+                    job.included.clone_from(&patients);
+                }
+                // Cancelled
+                None => break,
+            }
+            if count < self.page_size {
+                break;
+            }
+        }
+
+        tokio::task::spawn_blocking(move || writer.finish_file())
+            .await
+            .map_err(|_| AppError::Internal)?
+            .map_err(io_error)?;
+        Ok(patients)
+    }
+
+    /// Store a job's status in the database, if there is one
+    async fn record(&self, job: &ExportJob) {
+        if let Some(db) = &self.db {
+            if let Err(e) = db.update_export_job(job).await {
+                tracing::warn!(job_id = %job.job_id, error = ?e, "Failed to record bulk export");
+            }
+        }
+    }
+
+    /// Audit a finished export: one entry listing the patients it included,
+    /// and one per patient so it shows in their access reports
+    async fn audit(&self, state: &Mutex<AppState>, job: &ExportJob, patients: BTreeSet<String>) {
+        let users = state.lock().await.users();
+        let entry = AuditLogEntry::new(AuditAction::Read, "Observation".to_string())
+            .with_user(job.owner.clone(), job.role.clone())
+            .with_resource_id(job.job_id.to_string())
+            .with_status_code(200);
+
+        users
+            .audit(
+                AuditLogEntry {
+                    resource_type: "BulkExport".to_string(),
+                    ..entry.clone()
+                }
+                .with_metadata(serde_json::json!({
+                    "request": job.request,
+                    "exported": job.exported,
+                    "patients": patients,
+                })),
+            )
+            .await;
+        for patient_id in patients {
+            users.audit(entry.clone().with_patient_id(patient_id)).await;
+        }
+    }
+
+    fn evict_oldest_finished(&self, jobs: &mut HashMap<Uuid, ExportJob>) {
+        let oldest = jobs
+            .values()
+            .filter(|j| j.status != ExportStatus::InProgress)
+            .min_by_key(|j| j.transaction_time)
+            .map(|j| j.job_id);
+        if let Some(job_id) = oldest {
+            jobs.remove(&job_id);
+            self.remove_files(job_id);
+        }
+    }
+
+    fn remove_files(&self, job_id: Uuid) {
+        match std::fs::remove_dir_all(self.job_dir(job_id)) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => tracing::warn!(%job_id, error = %e, "Failed to remove bulk export files"),
+        }
+    }
+}
+
+/// The gzip-compressed NDJSON files of one job, each started once the
+/// previous one is full
+struct OutputWriter {
+    dir: PathBuf,
+    file_size: usize,
+    file: Option<GzEncoder<std::fs::File>>,
+    /// Observations in each file so far
+    counts: Vec<u64>,
+}
+
+impl OutputWriter {
+    fn new(dir: PathBuf, file_size: usize) -> Self {
+        Self {
+            dir,
+            file_size,
+            file: None,
+            counts: Vec::new(),
+        }
+    }
+
+    /// Append one NDJSON line, starting the next file if this one is full
+    fn write_line(&mut self, line: &[u8]) -> std::io::Result<()> {
+        let full = self
+            .counts
+            .last()
+            .is_some_and(|count| *count >= self.file_size as u64);
+        if self.file.is_none() || full {
+            self.finish_file()?;
+            let path = self.dir.join(output_file_name(self.counts.len() + 1));
+            let file = std::fs::File::create(path)?;
+            self.file = Some(GzEncoder::new(file, Compression::default()));
+            self.counts.push(0);
+        }
+        if let Some(file) = &mut self.file {
+            file.write_all(line)?;
+        }
+        if let Some(count) = self.counts.last_mut() {
+            *count += 1;
+        }
+        Ok(())
+    }
+
+    /// Complete the current file, if any
+    fn finish_file(&mut self) -> std::io::Result<()> {
+        match self.file.take() {
+            Some(file) => file.finish()?.sync_all(),
+            None => Ok(()),
+        }
+    }
+}
+
+fn io_error(e: std::io::Error) -> AppError {
+    tracing::error!(error = %e, "Failed to write bulk export");
+    AppError::Internal
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+
+    #[test]
+    fn test_output_is_split_into_gzip_files() {
+        let dir = std::env::temp_dir().join(format!("soundsense-writer-test-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut writer = OutputWriter::new(dir.clone(), 2);
+        for i in 0..5 {
+            writer.write_line(format!("{}\n", i).as_bytes()).unwrap();
+        }
+        writer.finish_file().unwrap();
+        assert_eq!(writer.counts, vec![2, 2, 1]);
+
+        let mut text = String::new();
+        let file = std::fs::File::open(dir.join(output_file_name(3))).unwrap();
+        GzDecoder::new(file).read_to_string(&mut text).unwrap();
+        assert_eq!(text, "4\n");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
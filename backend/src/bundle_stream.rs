@@ -0,0 +1,82 @@
+/// Streamed FHIR Bundles
+///
+/// `GET /api/fhir/Observation/stream` sends a Bundle of any size without
+/// building it in memory: the opening of the Bundle goes out at once, then
+/// each page of entries as it is read from the database, and `total` last.
+use actix_web::web::Bytes;
+use futures_util::Stream;
+
+use crate::db::ReadingCursor;
+use crate::domain::models::StoredReading;
+use crate::errors::AppError;
+use crate::fhir::{FhirBundleEntry, FhirObservation};
+
+/// Readings read and sent per step
+pub const DEFAULT_PAGE_SIZE: usize = 500;
+
+const BUNDLE_OPEN: &[u8] = br#"{"resourceType":"Bundle","type":"collection","entry":["#;
+
+/// Where the readings of a streamed Bundle come from
+#[derive(Debug)]
+pub enum ObservationPages {
+    Database(Box<ReadingCursor>),
+    /// Without a database: the matching in-memory readings, newest first
+    Memory {
+        readings: std::vec::IntoIter<StoredReading>,
+        page_size: usize,
+    },
+}
+
+impl ObservationPages {
+    /// The next page of readings; empty once all were returned
+    pub async fn next_page(&mut self) -> Result<Vec<StoredReading>, AppError> {
+        match self {
+            ObservationPages::Database(cursor) => cursor.next_page().await,
+            ObservationPages::Memory {
+                readings,
+                page_size,
+            } => Ok(readings.by_ref().take(*page_size).collect()),
+        }
+    }
+}
+
+/// Body chunks of a collection Bundle of the readings in `pages`, one chunk
+/// per page, closed with `total`
+pub fn bundle_chunks(
+    total: usize,
+    pages: ObservationPages,
+) -> impl Stream<Item = Result<Bytes, AppError>> {
+    // (pages, entries written so far); `None` once the Bundle is closed
+    let start = Some((pages, None::<usize>));
+    futures_util::stream::try_unfold(start, move |state| async move {
+        let Some((mut pages, written)) = state else {
+            return Ok(None);
+        };
+        let Some(written) = written else {
+            return Ok(Some((
+                Bytes::from_static(BUNDLE_OPEN),
+                Some((pages, Some(0))),
+            )));
+        };
+
+        let page = pages.next_page().await?;
+        if page.is_empty() {
+            let close = format!(r#"],"total":{}}}"#, total);
+            return Ok(Some((Bytes::from(close), None)));
+        }
+
+        let count = page.len();
+        let mut chunk = Vec::new();
+        for (i, stored) in page.into_iter().enumerate() {
+            if written + i > 0 {
+                chunk.push(b',');
+            }
+            let entry = FhirBundleEntry::observation(FhirObservation::from_stored(stored));
+            serde_json::to_writer(&mut chunk, &entry).map_err(|_| AppError::Internal)?;
+        }
+        Ok(Some((
+            Bytes::from(chunk),
+            Some((pages, Some(written + count))),
+        )))
+    })
+}
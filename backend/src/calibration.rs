@@ -0,0 +1,314 @@
+/// Device Calibration Verification
+///
+/// Audiometers are checked against a 1 kHz reference tone of known level.
+/// A verification listens to the device for a short window while the tone
+/// plays and compares the average of its decibel readings with the reference.
+/// The device passes when the deviation is within `CALIBRATION_TOLERANCE_DB`
+/// (default 1.5 dB); its calibration status is that of its latest check.
+///
+/// The average of the device's raw readings in the window is recorded too:
+/// it is the output at the reference level, which raw readings are
+/// converted to decibels by (see `units`). A device sending only raw output
+/// has nothing to compare, and passes if that average is usable.
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::domain::models::{is_decibel_unit, normalize_unit, StoredReading};
+use crate::units::RAW_UNIT;
+
+/// Default largest deviation from the reference that still passes, in dB
+pub const DEFAULT_TOLERANCE_DB: f64 = 1.5;
+
+/// Longest listening window; the request is held open for its duration
+pub const MAX_DURATION_SECS: u32 = 120;
+
+/// Read `CALIBRATION_TOLERANCE_DB` (a positive number of dB; default 1.5)
+pub fn tolerance_from_env() -> f64 {
+    std::env::var("CALIBRATION_TOLERANCE_DB")
+        .ok()
+        .and_then(|v| v.parse::<f64>().ok())
+        .filter(|v| v.is_finite() && *v > 0.0)
+        .unwrap_or(DEFAULT_TOLERANCE_DB)
+}
+
+/// A verification run as requested by an admin
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct VerifyRequest {
+    /// Level of the reference tone, in dB
+    pub reference_db: f64,
+    /// How long to collect readings, in seconds
+    pub duration_secs: u32,
+}
+
+impl VerifyRequest {
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.reference_db.is_finite() || !(0.0..=200.0).contains(&self.reference_db) {
+            return Err("reference_db must be between 0 and 200".into());
+        }
+        if !(1..=MAX_DURATION_SECS).contains(&self.duration_secs) {
+            return Err(format!(
+                "duration_secs must be between 1 and {}",
+                MAX_DURATION_SECS
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Outcome of one verification
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct CalibrationVerification {
+    pub device_id: String,
+    /// End of the listening window
+    pub verified_at: DateTime<Utc>,
+    pub reference_db: f64,
+    /// `null` if the device sent no decibel readings
+    pub measured_avg_db: Option<f64>,
+    /// `measured_avg_db - reference_db`
+    pub deviation_db: Option<f64>,
+    /// Average raw output at the reference level; `null` if the device sent
+    /// no raw readings
+    pub measured_avg_raw: Option<f64>,
+    pub passed: bool,
+}
+
+impl CalibrationVerification {
+    /// Compare the average of the decibel readings in `measured` (not
+    /// empty) with the reference, or without any, check that the raw
+    /// average can serve as the reference output
+    pub fn evaluate(
+        device_id: &str,
+        verified_at: DateTime<Utc>,
+        reference_db: f64,
+        measured: &WindowValues,
+        tolerance_db: f64,
+    ) -> Self {
+        let measured_avg_db = average(&measured.decibel);
+        let deviation_db = measured_avg_db.map(|avg| avg - reference_db);
+        let measured_avg_raw = average(&measured.raw);
+        let passed = match deviation_db {
+            Some(deviation) => deviation.abs() <= tolerance_db,
+            None => measured_avg_raw.is_some_and(|raw| raw > 0.0),
+        };
+        Self {
+            device_id: device_id.to_string(),
+            verified_at,
+            reference_db,
+            measured_avg_db,
+            deviation_db,
+            measured_avg_raw,
+            passed,
+        }
+    }
+}
+
+/// Mean of `values`; `None` if there are none
+fn average(values: &[f64]) -> Option<f64> {
+    (!values.is_empty()).then(|| values.iter().sum::<f64>() / values.len() as f64)
+}
+
+/// A device's calibration status and its past verifications
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct CalibrationHistory {
+    pub device_id: String,
+    /// `verified` or `failed` after the latest check; `null` if never checked
+    pub calibration_status: Option<String>,
+    /// Newest first
+    pub verifications: Vec<CalibrationVerification>,
+}
+
+impl CalibrationHistory {
+    pub fn new(device_id: &str, verifications: Vec<CalibrationVerification>) -> Self {
+        Self {
+            device_id: device_id.to_string(),
+            calibration_status: verifications
+                .first()
+                .map(|v| calibration_status(v.passed).to_string()),
+            verifications,
+        }
+    }
+}
+
+/// Device status recorded after a verification
+pub fn calibration_status(passed: bool) -> &'static str {
+    if passed {
+        "verified"
+    } else {
+        "failed"
+    }
+}
+
+/// Values a device sent during a verification window
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct WindowValues {
+    /// Of readings in decibels
+    pub decibel: Vec<f64>,
+    /// Of readings in the device's raw output
+    pub raw: Vec<f64>,
+}
+
+impl WindowValues {
+    pub fn is_empty(&self) -> bool {
+        self.decibel.is_empty() && self.raw.is_empty()
+    }
+}
+
+/// Values of `device_id`'s usable decibel and raw readings taken from
+/// `start` to `end` (inclusive). Mirrors the query used with a database.
+pub fn window_values<'a>(
+    device_id: &str,
+    readings: impl IntoIterator<Item = &'a StoredReading>,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+) -> WindowValues {
+    let mut values = WindowValues::default();
+    for r in readings.into_iter().filter(|r| {
+        r.is_usable()
+            && r.reading.device_id == device_id
+            && r.reading.ts >= start
+            && r.reading.ts <= end
+    }) {
+        if is_decibel_unit(&r.reading.unit) {
+            values.decibel.push(r.reading.value);
+        } else if normalize_unit(&r.reading.unit) == RAW_UNIT {
+            values.raw.push(r.reading.value);
+        }
+    }
+    values
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::models::{SensorReading, SignalCode, TimestampSource};
+    use chrono::{Duration, TimeZone};
+
+    fn decibels(values: &[f64]) -> WindowValues {
+        WindowValues {
+            decibel: values.to_vec(),
+            raw: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_pass_fail_against_tolerance() {
+        let at = Utc.with_ymd_and_hms(2026, 3, 1, 12, 0, 0).unwrap();
+        let evaluate = |values: &[f64], tolerance| {
+            CalibrationVerification::evaluate("d1", at, 94.0, &decibels(values), tolerance)
+        };
+
+        let v = evaluate(&[94.5, 95.5], 1.5);
+        assert_eq!(v.measured_avg_db, Some(95.0));
+        assert_eq!(v.deviation_db, Some(1.0));
+        assert_eq!(v.measured_avg_raw, None);
+        assert!(v.passed);
+
+        // Exactly at the tolerance still passes, in either direction
+        assert!(evaluate(&[92.5], 1.5).passed);
+        assert!(evaluate(&[95.5], 1.5).passed);
+
+        let v = evaluate(&[91.0, 93.0], 1.5);
+        assert_eq!(v.deviation_db, Some(-2.0));
+        assert!(!v.passed);
+
+        // A tighter tolerance fails what the default passes
+        assert!(!evaluate(&[95.0], 0.5).passed);
+    }
+
+    #[test]
+    fn test_raw_output_is_recorded() {
+        let at = Utc.with_ymd_and_hms(2026, 3, 1, 12, 0, 0).unwrap();
+        let both = WindowValues {
+            decibel: vec![97.0],
+            raw: vec![400.0, 600.0],
+        };
+        let v = CalibrationVerification::evaluate("d1", at, 94.0, &both, 1.5);
+        assert_eq!(v.measured_avg_raw, Some(500.0));
+        // Judged by the decibel readings alone
+        assert!(!v.passed);
+
+        // Raw output only: nothing to compare, usable as a reference
+        let raw_only = WindowValues {
+            decibel: Vec::new(),
+            raw: vec![500.0],
+        };
+        let v = CalibrationVerification::evaluate("d1", at, 94.0, &raw_only, 1.5);
+        assert_eq!((v.measured_avg_db, v.deviation_db), (None, None));
+        assert_eq!(v.measured_avg_raw, Some(500.0));
+        assert!(v.passed);
+
+        let silent = WindowValues {
+            decibel: Vec::new(),
+            raw: vec![0.0],
+        };
+        assert!(!CalibrationVerification::evaluate("d1", at, 94.0, &silent, 1.5).passed);
+    }
+
+    #[test]
+    fn test_history_status_follows_latest_check() {
+        let at = Utc.with_ymd_and_hms(2026, 3, 1, 12, 0, 0).unwrap();
+        let failed = CalibrationVerification::evaluate("d1", at, 94.0, &decibels(&[90.0]), 1.5);
+        let passed = CalibrationVerification::evaluate(
+            "d1",
+            at + Duration::days(1),
+            94.0,
+            &decibels(&[94.0]),
+            1.5,
+        );
+
+        let history = CalibrationHistory::new("d1", vec![passed, failed]);
+        assert_eq!(history.calibration_status.as_deref(), Some("verified"));
+        assert!(CalibrationHistory::new("d1", vec![])
+            .calibration_status
+            .is_none());
+    }
+
+    #[test]
+    fn test_window_values() {
+        let t0 = Utc.with_ymd_and_hms(2026, 3, 1, 12, 0, 0).unwrap();
+        let reading = |device_id: &str, value: f64, unit: &str, ts| {
+            StoredReading::new(SensorReading {
+                patient_id: "p1".into(),
+                device_id: device_id.into(),
+                code: SignalCode::Sound,
+                value,
+                unit: unit.into(),
+                ts,
+                ts_source: TimestampSource::Device,
+                metadata: Default::default(),
+            })
+        };
+        let readings = [
+            reading("d1", 94.0, "dB", t0),
+            reading("d1", 95.0, "dB", t0 + Duration::seconds(10)),
+            reading("d1", 512.0, "raw", t0),
+            reading("d1", 510.0, "au", t0 + Duration::seconds(5)),
+            // Other device, other unit, outside the window
+            reading("d2", 60.0, "dB", t0),
+            reading("d1", 1.0, "Pa", t0),
+            reading("d1", 40.0, "dB", t0 - Duration::seconds(1)),
+        ];
+
+        let values = window_values("d1", &readings, t0, t0 + Duration::seconds(10));
+        assert_eq!(values.decibel, [94.0, 95.0]);
+        assert_eq!(values.raw, [512.0, 510.0]);
+    }
+
+    #[test]
+    fn test_request_validation() {
+        let mut req = VerifyRequest {
+            reference_db: 94.0,
+            duration_secs: 10,
+        };
+        assert!(req.validate().is_ok());
+        req.duration_secs = 0;
+        assert!(req.validate().is_err());
+        req.duration_secs = MAX_DURATION_SECS + 1;
+        assert!(req.validate().is_err());
+        req.duration_secs = 10;
+        req.reference_db = f64::NAN;
+        assert!(req.validate().is_err());
+    }
+}
@@ -0,0 +1,363 @@
+/// Monthly Compliance Reports
+///
+/// HIPAA §164.312(b) calls for regular review of system activity. A report
+/// sums up one UTC calendar month of the audit log: who accessed what, how
+/// often access was denied, trainings, exports and alerts. It also lists
+/// suspicious events for review: a user denied access more than five times
+/// in a day, readings from devices never registered, provisioned or
+/// configured, and stretches of more than an hour without audit entries.
+/// A background job stores the report of each month shortly after it ends;
+/// reports need a database.
+use chrono::{DateTime, Datelike, Duration, NaiveDate, SecondsFormat, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::db::Database;
+use crate::errors::AppError;
+
+/// Denied accesses of one user in a day past which they are suspicious
+pub const MAX_DENIED_PER_DAY: i64 = 5;
+
+/// Longest stretch without audit entries that is not suspicious
+pub const MAX_AUDIT_GAP: Duration = Duration::hours(1);
+
+/// How long after the month ends its report is generated, so late audit
+/// entries are in
+const RUN_DELAY: Duration = Duration::minutes(5);
+
+/// Activity of one month, for review
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct ComplianceReport {
+    /// `YYYY-MM`
+    pub month: String,
+    /// Readings taken during the month
+    pub total_observations: u64,
+    /// Patients whose data was read
+    pub unique_patients_accessed: u64,
+    /// Accounts that acted, devices and denied attempts not included
+    pub unique_users: u64,
+    /// Denied accesses and failed logins
+    pub failed_auth_count: u64,
+    pub ml_trainings: u64,
+    /// Bulk exports that completed
+    pub exports_performed: u64,
+    /// Anomalies and device alerts raised, suppressed ones not included
+    pub alerts_fired: u64,
+    pub suspicious_events: Vec<String>,
+    pub generated_at: DateTime<Utc>,
+}
+
+/// Denied accesses of one user on one UTC day
+#[derive(Debug, Clone, PartialEq, sqlx::FromRow)]
+pub struct DeniedAccesses {
+    pub user_id: String,
+    pub day: NaiveDate,
+    pub count: i64,
+}
+
+/// Readings one device sent during the month
+#[derive(Debug, Clone, PartialEq, sqlx::FromRow)]
+pub struct DeviceReadings {
+    pub device_id: String,
+    pub readings: i64,
+    /// Has a secret, was provisioned, or has settings
+    pub registered: bool,
+}
+
+/// What the database tells about a month
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ComplianceEvidence {
+    pub total_observations: i64,
+    pub unique_patients_accessed: i64,
+    pub unique_users: i64,
+    pub failed_auth_count: i64,
+    pub ml_trainings: i64,
+    pub exports_performed: i64,
+    pub alerts_fired: i64,
+    pub denied: Vec<DeniedAccesses>,
+    pub devices: Vec<DeviceReadings>,
+    /// Minutes with at least one audit entry, in order
+    pub audit_minutes: Vec<DateTime<Utc>>,
+}
+
+/// First instant of `year`-`month` and of the month after, UTC
+pub fn month_bounds(year: i32, month: u32) -> Result<(DateTime<Utc>, DateTime<Utc>), String> {
+    let first = NaiveDate::from_ymd_opt(year, month, 1)
+        .ok_or_else(|| format!("no month {} in {}", month, year))?;
+    let next = first
+        .checked_add_months(chrono::Months::new(1))
+        .ok_or_else(|| format!("no month {} in {}", month, year))?;
+    let at_midnight = |day: NaiveDate| Utc.from_utc_datetime(&day.and_time(Default::default()));
+    Ok((at_midnight(first), at_midnight(next)))
+}
+
+/// Users denied access more than `MAX_DENIED_PER_DAY` times in a day
+pub fn denied_bursts(denied: &[DeniedAccesses]) -> Vec<String> {
+    let mut bursts: Vec<_> = denied
+        .iter()
+        .filter(|d| d.count > MAX_DENIED_PER_DAY)
+        .collect();
+    bursts.sort_by(|a, b| (a.day, &a.user_id).cmp(&(b.day, &b.user_id)));
+    bursts
+        .into_iter()
+        .map(|d| {
+            format!(
+                "{} was denied access {} times on {}",
+                d.user_id, d.count, d.day
+            )
+        })
+        .collect()
+}
+
+/// Devices that sent readings without being known
+pub fn unregistered_devices(devices: &[DeviceReadings]) -> Vec<String> {
+    let mut unknown: Vec<_> = devices.iter().filter(|d| !d.registered).collect();
+    unknown.sort_by(|a, b| a.device_id.cmp(&b.device_id));
+    unknown
+        .into_iter()
+        .map(|d| {
+            format!(
+                "{} readings from unregistered device {}",
+                d.readings, d.device_id
+            )
+        })
+        .collect()
+}
+
+/// Stretches from `start` to `end` longer than `MAX_AUDIT_GAP` without an
+/// entry in `minutes` (sorted; to the minute)
+pub fn audit_gaps(
+    minutes: &[DateTime<Utc>],
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+) -> Vec<(DateTime<Utc>, DateTime<Utc>)> {
+    let mut gaps = Vec::new();
+    let mut last = start;
+    for &minute in minutes.iter().chain(std::iter::once(&end)) {
+        if minute - last > MAX_AUDIT_GAP {
+            gaps.push((last, minute));
+        }
+        last = last.max(minute);
+    }
+    gaps
+}
+
+impl ComplianceReport {
+    /// The report of the month from `start` to `end`, checked for audit
+    /// gaps up to `now` if the month has not ended yet
+    pub fn from_evidence(
+        evidence: ComplianceEvidence,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        now: DateTime<Utc>,
+    ) -> Self {
+        let count = |n: i64| n.max(0) as u64;
+        let at = |t: DateTime<Utc>| t.to_rfc3339_opts(SecondsFormat::Secs, true);
+        let mut suspicious_events = denied_bursts(&evidence.denied);
+        suspicious_events.extend(unregistered_devices(&evidence.devices));
+        suspicious_events.extend(
+            audit_gaps(&evidence.audit_minutes, start, end.min(now))
+                .into_iter()
+                .map(|(from, to)| format!("No audit log entries from {} to {}", at(from), at(to))),
+        );
+
+        Self {
+            month: start.format("%Y-%m").to_string(),
+            total_observations: count(evidence.total_observations),
+            unique_patients_accessed: count(evidence.unique_patients_accessed),
+            unique_users: count(evidence.unique_users),
+            failed_auth_count: count(evidence.failed_auth_count),
+            ml_trainings: count(evidence.ml_trainings),
+            exports_performed: count(evidence.exports_performed),
+            alerts_fired: count(evidence.alerts_fired),
+            suspicious_events,
+            generated_at: now,
+        }
+    }
+}
+
+/// Builds and stores monthly reports
+pub struct ComplianceReportGenerator {
+    db: Database,
+}
+
+impl ComplianceReportGenerator {
+    pub fn new(db: Database) -> Self {
+        Self { db }
+    }
+
+    /// Report of `year`-`month` as of now. Months that have ended are
+    /// reported once and stored; the current one is reported afresh.
+    pub async fn generate(&self, year: i32, month: u32) -> Result<ComplianceReport, AppError> {
+        let (start, end) = month_bounds(year, month).map_err(AppError::BadRequest)?;
+        let now = Utc::now();
+        if start > now {
+            return Err(AppError::BadRequest(format!(
+                "{}-{:02} has not started yet",
+                year, month
+            )));
+        }
+        if end <= now {
+            if let Some(report) = self.db.compliance_report(start.date_naive()).await? {
+                return Ok(report);
+            }
+        }
+
+        let evidence = self.db.compliance_evidence(start, end).await?;
+        let report = ComplianceReport::from_evidence(evidence, start, end, now);
+        if end <= now {
+            self.db
+                .insert_compliance_report(start.date_naive(), &report)
+                .await?;
+        }
+        Ok(report)
+    }
+
+    /// Report last month now if it has not been yet, then each month
+    /// shortly after it ends
+    pub fn spawn(self) {
+        tokio::spawn(async move {
+            loop {
+                let now = Utc::now();
+                let this_month = NaiveDate::from_ymd_opt(now.year(), now.month(), 1)
+                    .expect("first of the month exists");
+                let last_month = this_month - chrono::Months::new(1);
+                let wait = match self.generate(last_month.year(), last_month.month()).await {
+                    Ok(report) => {
+                        tracing::info!(
+                            month = %report.month,
+                            suspicious_events = report.suspicious_events.len(),
+                            "Compliance report ready"
+                        );
+                        let (_, next_month) = month_bounds(now.year(), now.month())
+                            .expect("the current month exists");
+                        (next_month + RUN_DELAY - Utc::now())
+                            .to_std()
+                            .unwrap_or_default()
+                    }
+                    // Database unavailable; try again in an hour
+                    Err(e) => {
+                        tracing::warn!(error = ?e, "Compliance report generation failed");
+                        std::time::Duration::from_secs(3600)
+                    }
+                };
+                tokio::time::sleep(wait).await;
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(day: u32, hour: u32, minute: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2026, 3, day, hour, minute, 0).unwrap()
+    }
+
+    #[test]
+    fn test_more_than_five_denials_a_day_are_suspicious() {
+        let day = |d| NaiveDate::from_ymd_opt(2026, 3, d).unwrap();
+        let denied = [
+            DeniedAccesses {
+                user_id: "mallory".into(),
+                day: day(4),
+                count: 6,
+            },
+            DeniedAccesses {
+                user_id: "alice".into(),
+                day: day(2),
+                count: 5,
+            },
+            DeniedAccesses {
+                user_id: "bob".into(),
+                day: day(1),
+                count: 40,
+            },
+        ];
+        assert_eq!(
+            denied_bursts(&denied),
+            [
+                "bob was denied access 40 times on 2026-03-01",
+                "mallory was denied access 6 times on 2026-03-04",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_only_unregistered_devices_are_suspicious() {
+        let devices = [
+            DeviceReadings {
+                device_id: "rogue".into(),
+                readings: 12,
+                registered: false,
+            },
+            DeviceReadings {
+                device_id: "ward-3".into(),
+                readings: 9000,
+                registered: true,
+            },
+        ];
+        assert_eq!(
+            unregistered_devices(&devices),
+            ["12 readings from unregistered device rogue"]
+        );
+    }
+
+    #[test]
+    fn test_audit_gaps_over_an_hour_including_the_month_edges() {
+        let (start, end) = (at(1, 0, 0), at(2, 0, 0));
+        // Busy all day: no gaps
+        let busy: Vec<_> = (0..24 * 2)
+            .map(|i| start + Duration::minutes(30 * i))
+            .collect();
+        assert!(audit_gaps(&busy, start, end).is_empty());
+
+        // Exactly an hour apart is not a gap; more is
+        let minutes = [at(1, 0, 50), at(1, 1, 50), at(1, 3, 0), at(1, 23, 30)];
+        assert_eq!(
+            audit_gaps(&minutes, start, end),
+            [(at(1, 1, 50), at(1, 3, 0)), (at(1, 3, 0), at(1, 23, 30))]
+        );
+
+        // Nothing at all: the whole period, but only up to `end`
+        assert_eq!(audit_gaps(&[], start, end), [(start, end)]);
+        assert!(audit_gaps(&[], start, start + Duration::minutes(59)).is_empty());
+    }
+
+    #[test]
+    fn test_report_of_a_month() {
+        let (start, end) = month_bounds(2026, 2).unwrap();
+        assert_eq!(start, Utc.with_ymd_and_hms(2026, 2, 1, 0, 0, 0).unwrap());
+        assert_eq!(end, at(1, 0, 0));
+        assert!(month_bounds(2026, 13).is_err());
+
+        let minutes: Vec<_> = (0..28 * 24)
+            .map(|h| start + Duration::hours(h))
+            .filter(|t| t.day() != 14)
+            .collect();
+        let evidence = ComplianceEvidence {
+            total_observations: 1200,
+            unique_users: 3,
+            failed_auth_count: 7,
+            denied: vec![DeniedAccesses {
+                user_id: "mallory".into(),
+                day: NaiveDate::from_ymd_opt(2026, 2, 20).unwrap(),
+                count: 7,
+            }],
+            audit_minutes: minutes,
+            ..Default::default()
+        };
+        let report = ComplianceReport::from_evidence(evidence, start, end, end + Duration::days(1));
+        assert_eq!(report.month, "2026-02");
+        assert_eq!(report.total_observations, 1200);
+        assert_eq!(report.failed_auth_count, 7);
+        assert_eq!(
+            report.suspicious_events,
+            [
+                "mallory was denied access 7 times on 2026-02-20",
+                "No audit log entries from 2026-02-13T23:00:00Z to 2026-02-15T00:00:00Z",
+            ]
+        );
+    }
+}
@@ -0,0 +1,120 @@
+/// Patient Consent Enforcement
+///
+/// Readings are only stored for patients whose consent is `granted`. How
+/// other readings are handled depends on `CONSENT_MODE`: `strict` (default)
+/// rejects them with 403, `quarantine` accepts them into a separate store
+/// that no query reads, and `off` stores everything as before.
+use crate::domain::models::ConsentStatus;
+use crate::errors::AppError;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ConsentMode {
+    /// No consent check
+    #[default]
+    Off,
+    /// Reject readings of patients without consent
+    Strict,
+    /// Accept them into quarantine instead of storing them
+    Quarantine,
+}
+
+/// What to do with an incoming reading
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Admission {
+    Store,
+    /// Quarantine; carries the reason recorded with the reading
+    Quarantine(&'static str),
+}
+
+impl ConsentMode {
+    /// Read `CONSENT_MODE` (`strict`, `quarantine` or `off`; default `strict`)
+    pub fn from_env() -> Self {
+        match std::env::var("CONSENT_MODE")
+            .unwrap_or_default()
+            .to_ascii_lowercase()
+            .as_str()
+        {
+            "off" => {
+                tracing::warn!("CONSENT_MODE=off: readings are stored without a consent check");
+                Self::Off
+            }
+            "quarantine" => Self::Quarantine,
+            "" | "strict" => Self::Strict,
+            other => {
+                tracing::warn!("Unknown CONSENT_MODE '{}', using strict", other);
+                Self::Strict
+            }
+        }
+    }
+
+    /// Decide on a reading for `patient_id`, whose consent is `status`
+    /// (`None` for a patient that isn't registered)
+    pub fn admit(
+        &self,
+        patient_id: &str,
+        status: Option<ConsentStatus>,
+    ) -> Result<Admission, AppError> {
+        let reason = match status {
+            _ if *self == Self::Off => return Ok(Admission::Store),
+            Some(ConsentStatus::Granted) => return Ok(Admission::Store),
+            Some(status) => status.as_str(),
+            None => "unknown",
+        };
+
+        match self {
+            Self::Quarantine => Ok(Admission::Quarantine(reason)),
+            _ => Err(AppError::Forbidden(format!(
+                "no consent on record for patient {} ({})",
+                patient_id, reason
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_admission_by_mode() {
+        let granted = Some(ConsentStatus::Granted);
+        let revoked = Some(ConsentStatus::Revoked);
+
+        assert_eq!(
+            ConsentMode::Strict.admit("p1", granted).unwrap(),
+            Admission::Store
+        );
+        assert!(matches!(
+            ConsentMode::Strict.admit("p1", revoked),
+            Err(AppError::Forbidden(_))
+        ));
+        assert!(matches!(
+            ConsentMode::Strict.admit("p1", None),
+            Err(AppError::Forbidden(_))
+        ));
+
+        assert_eq!(
+            ConsentMode::Quarantine.admit("p1", granted).unwrap(),
+            Admission::Store
+        );
+        assert_eq!(
+            ConsentMode::Quarantine.admit("p1", revoked).unwrap(),
+            Admission::Quarantine("revoked")
+        );
+        assert_eq!(
+            ConsentMode::Quarantine
+                .admit("p1", Some(ConsentStatus::Pending))
+                .unwrap(),
+            Admission::Quarantine("pending")
+        );
+        assert_eq!(
+            ConsentMode::Quarantine.admit("p1", None).unwrap(),
+            Admission::Quarantine("unknown")
+        );
+
+        assert_eq!(
+            ConsentMode::Off.admit("p1", None).unwrap(),
+            Admission::Store
+        );
+    }
+}
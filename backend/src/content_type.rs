@@ -0,0 +1,162 @@
+/// Content Negotiation Module
+///
+/// The JSON endpoints only speak JSON. A body sent as anything else, or
+/// without a `Content-Type`, is refused with 415 before a handler tries to
+/// parse it, rather than surfacing as a confusing parse error; a client
+/// whose `Accept` rules out JSON gets 406. Routes that consume or produce
+/// another type (NDJSON backfills, PDF reports, export files) are exempt
+/// from the matching check. WebSocket endpoints are registered outside the
+/// routes this wraps.
+use actix_web::body::{EitherBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::{header, Method};
+use actix_web::middleware::Next;
+use actix_web::Error;
+
+use crate::errors::AppError;
+
+/// Media types accepted as request bodies
+const JSON_BODY_TYPES: [&str; 2] = ["application/json", "application/fhir+json"];
+
+/// Routes whose bodies are not JSON
+const NON_JSON_BODY_ROUTES: [&str; 1] = ["/api/ingest/stream"];
+
+/// Routes whose responses are not JSON
+const NON_JSON_RESPONSE_ROUTES: [&str; 2] = [
+    "/api/reports/patient-summary/{patient_id}.pdf",
+    "/api/fhir/$export/{job_id}/{file}",
+];
+
+/// The media type of a header value, without its parameters, lowercased
+fn essence(value: &str) -> String {
+    value
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase()
+}
+
+/// Whether `content_type` names a JSON body
+pub fn is_json_content_type(content_type: &str) -> bool {
+    JSON_BODY_TYPES.contains(&essence(content_type).as_str())
+}
+
+/// Whether an `Accept` header value admits a JSON response: some range in
+/// it, with a non-zero quality, matches `application/json`, or a `+json`
+/// type such as `application/fhir+json`
+pub fn accepts_json(accept: &str) -> bool {
+    accept.split(',').any(|range| {
+        let mut parts = range.split(';');
+        let media = parts.next().unwrap_or_default().trim().to_ascii_lowercase();
+        let refused = parts.any(|param| {
+            param
+                .split_once('=')
+                .filter(|(name, _)| name.trim().eq_ignore_ascii_case("q"))
+                .and_then(|(_, q)| q.trim().parse::<f32>().ok())
+                .is_some_and(|q| q <= 0.0)
+        });
+        !refused
+            && match media.split_once('/') {
+                Some(("*", "*")) => true,
+                Some(("application", subtype)) => {
+                    subtype == "*" || subtype == "json" || subtype.ends_with("+json")
+                }
+                _ => false,
+            }
+    })
+}
+
+/// Whether the request carries a body: a non-zero `Content-Length`, or a
+/// chunked one
+fn has_body(req: &ServiceRequest) -> bool {
+    let headers = req.headers();
+    headers.contains_key(header::TRANSFER_ENCODING)
+        || headers
+            .get(header::CONTENT_LENGTH)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.trim().parse::<u64>().ok())
+            .is_some_and(|length| length > 0)
+}
+
+/// Refuse what the route can't read or answer: 415 for a POST, PUT or
+/// PATCH body that isn't JSON, 406 for an `Accept` without JSON in it.
+/// Requests without a body, or without an `Accept`, pass.
+fn check(req: &ServiceRequest) -> Result<(), AppError> {
+    let pattern = req.match_pattern().unwrap_or_default();
+
+    let sends_body = matches!(*req.method(), Method::POST | Method::PUT | Method::PATCH);
+    if sends_body && has_body(req) && !NON_JSON_BODY_ROUTES.contains(&pattern.as_str()) {
+        let content_type = req
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .map(|v| v.to_str().unwrap_or_default());
+        match content_type {
+            None => {
+                return Err(AppError::UnsupportedMediaType(
+                    "Content-Type must be application/json".into(),
+                ))
+            }
+            Some(content_type) if !is_json_content_type(content_type) => {
+                return Err(AppError::UnsupportedMediaType(format!(
+                    "Content-Type must be application/json, got '{}'",
+                    content_type
+                )))
+            }
+            Some(_) => {}
+        }
+    }
+
+    if let Some(accept) = req.headers().get(header::ACCEPT) {
+        let accept = accept.to_str().unwrap_or_default();
+        if !NON_JSON_RESPONSE_ROUTES.contains(&pattern.as_str()) && !accepts_json(accept) {
+            return Err(AppError::NotAcceptable(format!(
+                "responses are application/json, Accept is '{}'",
+                accept
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Middleware for the JSON endpoints: the `/api` scope and the public
+/// routes taking JSON bodies
+pub async fn require_json<B: MessageBody + 'static>(
+    req: ServiceRequest,
+    next: Next<B>,
+) -> Result<ServiceResponse<EitherBody<B>>, Error> {
+    if let Err(e) = check(&req) {
+        return Ok(req.error_response(e).map_into_right_body());
+    }
+    Ok(next.call(req).await?.map_into_left_body())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json_content_types() {
+        assert!(is_json_content_type("application/json"));
+        assert!(is_json_content_type("Application/JSON; charset=utf-8"));
+        assert!(is_json_content_type("application/fhir+json"));
+        assert!(!is_json_content_type("text/plain"));
+        assert!(!is_json_content_type("application/x-ndjson"));
+        assert!(!is_json_content_type(""));
+    }
+
+    #[test]
+    fn test_accept_ranges() {
+        assert!(accepts_json("application/json"));
+        assert!(accepts_json("*/*"));
+        assert!(accepts_json("application/*"));
+        assert!(accepts_json("application/fhir+json;q=0.9"));
+        assert!(accepts_json(
+            "text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8"
+        ));
+        assert!(!accepts_json("text/html"));
+        assert!(!accepts_json("application/xml, text/csv"));
+        assert!(!accepts_json("application/json;q=0, text/plain"));
+        assert!(!accepts_json(""));
+    }
+}
@@ -0,0 +1,243 @@
+/// CORS Policy Module
+///
+/// Holds the allowlist of browser origins permitted to call the API. Origins are
+/// loaded from the `cors_allowed_origins` table at startup and refreshed
+/// periodically, so administrators can manage them without a restart.
+use actix_cors::Cors;
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use crate::db::Database;
+
+/// A single allowlist entry
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct CorsOrigin {
+    pub origin: String,
+    pub description: Option<String>,
+}
+
+/// Shared, runtime-updatable CORS allowlist
+#[derive(Debug, Clone)]
+pub struct CorsAllowlist {
+    dev_mode: bool,
+    origins: Arc<RwLock<Vec<CorsOrigin>>>,
+}
+
+impl CorsAllowlist {
+    pub fn new(origins: Vec<CorsOrigin>, dev_mode: bool) -> Self {
+        Self {
+            dev_mode,
+            origins: Arc::new(RwLock::new(origins)),
+        }
+    }
+
+    /// Build an empty allowlist, honouring `CORS_DEV_MODE=true`
+    pub fn from_env() -> Self {
+        let dev_mode = std::env::var("CORS_DEV_MODE")
+            .map(|v| v.eq_ignore_ascii_case("true"))
+            .unwrap_or(false);
+        Self::new(Vec::new(), dev_mode)
+    }
+
+    /// In dev mode every origin is accepted
+    pub fn is_dev_mode(&self) -> bool {
+        self.dev_mode
+    }
+
+    pub fn origins(&self) -> Vec<CorsOrigin> {
+        self.origins.read().map(|o| o.clone()).unwrap_or_default()
+    }
+
+    /// Replace the whole list (used by the periodic refresh)
+    pub fn replace(&self, origins: Vec<CorsOrigin>) {
+        if let Ok(mut current) = self.origins.write() {
+            *current = origins;
+        }
+    }
+
+    /// Add or update a single entry
+    pub fn add(&self, entry: CorsOrigin) {
+        if let Ok(mut current) = self.origins.write() {
+            current.retain(|o| !o.origin.eq_ignore_ascii_case(&entry.origin));
+            current.push(entry);
+        }
+    }
+
+    /// Check an `Origin` header value against the allowlist
+    pub fn is_allowed(&self, origin: &str) -> bool {
+        if self.dev_mode {
+            return true;
+        }
+
+        self.origins
+            .read()
+            .map(|list| list.iter().any(|o| origin_matches(&o.origin, origin)))
+            .unwrap_or(false)
+    }
+
+    /// Build the actix CORS middleware backed by this allowlist
+    pub fn build_cors(&self) -> Cors {
+        let cors = if self.dev_mode {
+            Cors::default().allow_any_origin()
+        } else {
+            let allowlist = self.clone();
+            Cors::default().allowed_origin_fn(move |origin, _req| {
+                origin
+                    .to_str()
+                    .map(|o| allowlist.is_allowed(o))
+                    .unwrap_or(false)
+            })
+        };
+
+        // Let browser clients read the pagination and caching headers
+        cors.allow_any_method()
+            .allow_any_header()
+            .expose_headers(["ETag", "Link", "X-Total-Count"])
+            .max_age(3600)
+    }
+
+    /// Reload the allowlist from the database
+    pub async fn reload(&self, db: &Database) {
+        match db.list_cors_origins().await {
+            Ok(origins) => {
+                tracing::debug!(count = origins.len(), "Reloaded CORS allowlist");
+                self.replace(origins);
+            }
+            Err(e) => {
+                tracing::warn!(error = ?e, "Failed to reload CORS allowlist, keeping previous list");
+            }
+        }
+    }
+
+    /// Periodically reload the allowlist from the database
+    pub fn spawn_refresh(&self, db: Database, interval: Duration) {
+        let allowlist = self.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            // The first tick completes immediately; the list was loaded at startup
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                allowlist.reload(&db).await;
+            }
+        });
+    }
+}
+
+/// Split an origin (or pattern) into optional scheme and the `host[:port]` part
+fn split_scheme(value: &str) -> (Option<&str>, &str) {
+    match value.split_once("://") {
+        Some((scheme, rest)) => (Some(scheme), rest),
+        None => (None, value),
+    }
+}
+
+/// Match an origin against an allowlist pattern.
+///
+/// Patterns are either exact origins (`https://app.example.com`) or wildcard
+/// subdomains (`*.hospital.example.com`, optionally with scheme and port).
+/// A wildcard matches one or more subdomain labels but not the bare domain.
+pub fn origin_matches(pattern: &str, origin: &str) -> bool {
+    let pattern = pattern.trim().trim_end_matches('/').to_ascii_lowercase();
+    let origin = origin.trim().trim_end_matches('/').to_ascii_lowercase();
+
+    if pattern == origin {
+        return true;
+    }
+
+    let (pattern_scheme, pattern_authority) = split_scheme(&pattern);
+    let (origin_scheme, origin_authority) = split_scheme(&origin);
+
+    if let Some(scheme) = pattern_scheme {
+        if origin_scheme != Some(scheme) {
+            return false;
+        }
+    }
+
+    let Some(suffix) = pattern_authority.strip_prefix("*.") else {
+        // No wildcard: a scheme-less pattern matches the authority exactly
+        return pattern_scheme.is_none() && pattern_authority == origin_authority;
+    };
+
+    match origin_authority.strip_suffix(suffix) {
+        Some(prefix) => prefix.len() > 1 && prefix.ends_with('.'),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exact_origin_match() {
+        assert!(origin_matches(
+            "https://dashboard.example.com",
+            "https://dashboard.example.com"
+        ));
+        assert!(origin_matches(
+            "https://Dashboard.example.com/",
+            "https://dashboard.example.com"
+        ));
+        assert!(!origin_matches(
+            "https://dashboard.example.com",
+            "http://dashboard.example.com"
+        ));
+    }
+
+    #[test]
+    fn test_wildcard_subdomain_match() {
+        let pattern = "*.hospital.example.com";
+
+        assert!(origin_matches(
+            pattern,
+            "https://ward3.hospital.example.com"
+        ));
+        assert!(origin_matches(pattern, "http://a.b.hospital.example.com"));
+        assert!(!origin_matches(
+            pattern,
+            "https://ward3.hospital.example.com:8443"
+        ));
+        assert!(!origin_matches(pattern, "https://hospital.example.com"));
+        assert!(!origin_matches(pattern, "https://evilhospital.example.com"));
+        assert!(!origin_matches(
+            pattern,
+            "https://hospital.example.com.evil.org"
+        ));
+    }
+
+    #[test]
+    fn test_wildcard_with_scheme_and_port() {
+        let pattern = "https://*.hospital.example.com:8443";
+
+        assert!(origin_matches(
+            pattern,
+            "https://ward3.hospital.example.com:8443"
+        ));
+        assert!(!origin_matches(
+            pattern,
+            "http://ward3.hospital.example.com:8443"
+        ));
+        assert!(!origin_matches(
+            pattern,
+            "https://ward3.hospital.example.com"
+        ));
+    }
+
+    #[test]
+    fn test_allowlist_dev_mode_and_updates() {
+        let allowlist = CorsAllowlist::new(Vec::new(), false);
+        assert!(!allowlist.is_allowed("https://ward3.hospital.example.com"));
+
+        allowlist.add(CorsOrigin {
+            origin: "*.hospital.example.com".into(),
+            description: None,
+        });
+        assert!(allowlist.is_allowed("https://ward3.hospital.example.com"));
+        assert!(!allowlist.is_allowed("https://example.org"));
+
+        let dev = CorsAllowlist::new(Vec::new(), true);
+        assert!(dev.is_allowed("https://example.org"));
+    }
+}
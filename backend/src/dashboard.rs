@@ -0,0 +1,515 @@
+/// Patient Dashboard
+///
+/// Everything a clinical dashboard shows for one patient in a single call:
+/// the latest reading, this hour's level, today's noise dose, recent
+/// anomaly alerts, the devices reporting for the patient and hourly levels
+/// over the last day. The readings come from one read transaction; alerts
+/// from the anomalies the WebSocket hub has raised since startup.
+///
+/// Dashboards poll, so each patient's result is cached for
+/// `DASHBOARD_CACHE_SECS` (default 5), and each account may ask for
+/// `DASHBOARD_RATE_LIMIT_PER_MIN` (default 120) a minute, counted apart
+/// from ingest.
+use chrono::{DateTime, Duration, DurationRound, Utc};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Instant;
+use utoipa::ToSchema;
+
+use crate::anomaly::AnomalyEvent;
+use crate::device_watchdog::{DeviceSettings, DeviceStatus, DeviceWatchdog};
+use crate::domain::models::{SensorReading, TimestampSource};
+use crate::errors::AppError;
+use crate::hearing::niosh_dose_percent;
+use crate::units::{TargetUnit, UnitConversion, CONVERSION_NONE};
+
+/// Hours in `trend_24h`
+pub const TREND_HOURS: i64 = 24;
+
+/// Alerts raised this long ago are still active (minutes)
+pub const ACTIVE_ALERT_MINUTES: i64 = 15;
+
+const DEFAULT_CACHE_SECS: u64 = 5;
+const DEFAULT_RATE_LIMIT_PER_MIN: u32 = 120;
+
+/// How far the latest reading can be trusted
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ReadingQuality {
+    /// Recent and timestamped by the device
+    Good,
+    /// Recent, but timestamped by the server on arrival
+    EstimatedTime,
+    /// Older than the device's offline grace period
+    Stale,
+}
+
+/// The patient's most recent usable reading
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct LastReading {
+    pub value: f64,
+    pub unit: String,
+    pub ts: DateTime<Utc>,
+    pub device_id: String,
+    pub quality: ReadingQuality,
+    /// `none` when the value could not be converted to the unit asked for
+    /// and is given as stored
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub conversion: Option<&'static str>,
+}
+
+/// A device that reported for the patient in the last day
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct DashboardDevice {
+    pub device_id: String,
+    pub status: DeviceStatus,
+    pub last_seen_at: DateTime<Utc>,
+    /// Heard from within its offline grace period
+    pub online: bool,
+}
+
+/// Level of one clock hour
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, ToSchema)]
+pub struct HourlyLevel {
+    /// Start of the hour
+    pub ts: DateTime<Utc>,
+    /// Energy average of the hour's decibel readings; `null` without any
+    pub avg_db: Option<f64>,
+}
+
+/// What the store knows about a patient's last day, for `PatientDashboard`
+#[derive(Debug, Clone, Default)]
+pub struct DashboardReadings {
+    pub last_reading: Option<SensorReading>,
+    /// Energy average of each clock hour with decibel readings, by hour start
+    pub hourly_db: Vec<(DateTime<Utc>, f64)>,
+    /// TWA of today's (UTC) decibel readings
+    pub today_twa_db: Option<f64>,
+    /// Latest reading of each device that reported for the patient
+    pub device_last_seen: Vec<(String, DateTime<Utc>)>,
+}
+
+/// Start of the `TREND_HOURS` window ending with the hour of `now`
+pub fn trend_start(now: DateTime<Utc>) -> DateTime<Utc> {
+    hour_of(now) - Duration::hours(TREND_HOURS - 1)
+}
+
+/// Start of the UTC day of `now`
+pub fn day_start(now: DateTime<Utc>) -> DateTime<Utc> {
+    now.date_naive()
+        .and_hms_opt(0, 0, 0)
+        .unwrap_or_default()
+        .and_utc()
+}
+
+/// Start of the clock hour of `ts`
+pub fn hour_of(ts: DateTime<Utc>) -> DateTime<Utc> {
+    ts.duration_trunc(Duration::hours(1)).unwrap_or(ts)
+}
+
+/// The current state of one patient
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct PatientDashboard {
+    pub patient_id: String,
+    /// `null` if the patient has no usable readings
+    pub last_reading: Option<LastReading>,
+    /// Energy average of this clock hour's decibel readings; `null` without
+    /// any
+    pub current_hour_avg: Option<f64>,
+    /// NIOSH dose of today's TWA (UTC day); 0 without decibel readings
+    pub noise_dose_today_percent: f64,
+    /// Anomalies raised for readings taken today
+    pub alert_count_today: usize,
+    /// Anomalies of the last `ACTIVE_ALERT_MINUTES`, newest first
+    pub active_alerts: Vec<AnomalyEvent>,
+    pub device_status: Vec<DashboardDevice>,
+    /// One entry per hour of the last day, oldest first, ending with the
+    /// current hour
+    pub trend_24h: Vec<HourlyLevel>,
+    pub generated_at: DateTime<Utc>,
+}
+
+impl PatientDashboard {
+    /// Assemble the dashboard from the patient's readings, the anomalies
+    /// raised for them today and the device settings, as of `now`, with the
+    /// last reading converted by `units` if given
+    pub fn build(
+        patient_id: &str,
+        now: DateTime<Utc>,
+        readings: DashboardReadings,
+        mut alerts_today: Vec<AnomalyEvent>,
+        settings: &HashMap<String, DeviceSettings>,
+        default_grace: std::time::Duration,
+        units: Option<&UnitConversion>,
+    ) -> Self {
+        let grace_of = |device_id: &str| {
+            let secs = settings
+                .get(device_id)
+                .and_then(|s| s.offline_grace_secs)
+                .unwrap_or(default_grace.as_secs());
+            Duration::seconds(i64::try_from(secs).unwrap_or(i64::MAX / 1000))
+        };
+
+        let last_reading = readings.last_reading.map(|r| {
+            let quality = if now - r.ts > grace_of(&r.device_id) {
+                ReadingQuality::Stale
+            } else if r.ts_source == TimestampSource::Server {
+                ReadingQuality::EstimatedTime
+            } else {
+                ReadingQuality::Good
+            };
+            let (value, unit, conversion) = match units {
+                Some(units) => match units.convert(r.value, &r.unit, &r.device_id) {
+                    Some(value) => (value, units.target.as_str().to_string(), None),
+                    None => (r.value, r.unit, Some(CONVERSION_NONE)),
+                },
+                None => (r.value, r.unit, None),
+            };
+            LastReading {
+                value,
+                unit,
+                ts: r.ts,
+                device_id: r.device_id,
+                quality,
+                conversion,
+            }
+        });
+
+        let hourly: HashMap<_, _> = readings.hourly_db.into_iter().collect();
+        let start = trend_start(now);
+        let trend_24h: Vec<_> = (0..TREND_HOURS)
+            .map(|i| {
+                let ts = start + Duration::hours(i);
+                HourlyLevel {
+                    ts,
+                    avg_db: hourly.get(&ts).copied(),
+                }
+            })
+            .collect();
+
+        let device_status = readings
+            .device_last_seen
+            .into_iter()
+            .map(|(device_id, last_seen_at)| DashboardDevice {
+                status: settings
+                    .get(&device_id)
+                    .map(|s| s.status)
+                    .unwrap_or_default(),
+                online: now - last_seen_at <= grace_of(&device_id),
+                device_id,
+                last_seen_at,
+            })
+            .collect();
+
+        let active_since = now - Duration::minutes(ACTIVE_ALERT_MINUTES);
+        alerts_today.sort_by_key(|a| std::cmp::Reverse(a.effective_date_time));
+        let alert_count_today = alerts_today.len();
+        let active_alerts = alerts_today
+            .into_iter()
+            .filter(|a| a.effective_date_time >= active_since)
+            .collect();
+
+        Self {
+            patient_id: patient_id.to_string(),
+            last_reading,
+            current_hour_avg: trend_24h.last().and_then(|h| h.avg_db),
+            noise_dose_today_percent: readings.today_twa_db.map_or(0.0, niosh_dose_percent),
+            alert_count_today,
+            active_alerts,
+            device_status,
+            trend_24h,
+            generated_at: now,
+        }
+    }
+}
+
+/// A patient, and the unit asked for with its strictness
+type CacheKey = (String, Option<(TargetUnit, bool)>);
+
+/// Dashboard settings, cache and per-account request counts
+#[derive(Debug)]
+pub struct Dashboards {
+    cache_ttl: std::time::Duration,
+    rate_limit_per_min: u32,
+    /// Default silence before a device counts as offline
+    pub offline_grace: std::time::Duration,
+    cache: Mutex<HashMap<CacheKey, (Instant, PatientDashboard)>>,
+    /// Requests per account in the current minute, and when it started
+    windows: Mutex<HashMap<String, (Instant, u32)>>,
+}
+
+impl Default for Dashboards {
+    fn default() -> Self {
+        Self::new(
+            std::time::Duration::from_secs(DEFAULT_CACHE_SECS),
+            DEFAULT_RATE_LIMIT_PER_MIN,
+            DeviceWatchdog::default_grace(),
+        )
+    }
+}
+
+impl Dashboards {
+    /// Cache results for `cache_ttl` (zero disables the cache) and allow
+    /// `rate_limit_per_min` requests per account a minute
+    pub fn new(
+        cache_ttl: std::time::Duration,
+        rate_limit_per_min: u32,
+        offline_grace: std::time::Duration,
+    ) -> Self {
+        Self {
+            cache_ttl,
+            rate_limit_per_min,
+            offline_grace,
+            cache: Mutex::new(HashMap::new()),
+            windows: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Create from `DASHBOARD_CACHE_SECS`, `DASHBOARD_RATE_LIMIT_PER_MIN`
+    /// and `DEVICE_OFFLINE_GRACE_SECS`
+    pub fn from_env() -> Self {
+        let cache_secs = std::env::var("DASHBOARD_CACHE_SECS")
+            .ok()
+            .and_then(|v| v.parse::<u64>().ok())
+            .unwrap_or(DEFAULT_CACHE_SECS);
+        let rate_limit = std::env::var("DASHBOARD_RATE_LIMIT_PER_MIN")
+            .ok()
+            .and_then(|v| v.parse::<u32>().ok())
+            .filter(|v| *v > 0)
+            .unwrap_or(DEFAULT_RATE_LIMIT_PER_MIN);
+        Self::new(
+            std::time::Duration::from_secs(cache_secs),
+            rate_limit,
+            DeviceWatchdog::grace_from_env(),
+        )
+    }
+
+    /// Count a request by `account`; 429 with the seconds until its minute
+    /// is over once it has used up the limit
+    pub fn check_rate(&self, account: &str) -> Result<(), AppError> {
+        self.check_rate_at(account, Instant::now())
+    }
+
+    fn check_rate_at(&self, account: &str, now: Instant) -> Result<(), AppError> {
+        let minute = std::time::Duration::from_secs(60);
+        let mut windows = self.windows.lock().unwrap_or_else(|e| e.into_inner());
+        windows.retain(|_, (started, _)| now.duration_since(*started) < minute);
+        let (started, count) = windows.entry(account.to_string()).or_insert((now, 0));
+        if *count >= self.rate_limit_per_min {
+            let retry_after = minute.saturating_sub(now.duration_since(*started));
+            return Err(AppError::TooManyRequests(retry_after.as_secs().max(1)));
+        }
+        *count += 1;
+        Ok(())
+    }
+
+    /// The dashboard of `patient_id` in `unit` computed within the cache
+    /// lifetime
+    pub fn cached(
+        &self,
+        patient_id: &str,
+        unit: Option<(TargetUnit, bool)>,
+    ) -> Option<PatientDashboard> {
+        let cache = self.cache.lock().unwrap_or_else(|e| e.into_inner());
+        cache
+            .get(&(patient_id.to_string(), unit))
+            .filter(|(at, _)| at.elapsed() < self.cache_ttl)
+            .map(|(_, dashboard)| dashboard.clone())
+    }
+
+    pub fn store(&self, dashboard: &PatientDashboard, unit: Option<(TargetUnit, bool)>) {
+        if self.cache_ttl.is_zero() {
+            return;
+        }
+        let mut cache = self.cache.lock().unwrap_or_else(|e| e.into_inner());
+        cache.retain(|_, (at, _)| at.elapsed() < self.cache_ttl);
+        cache.insert(
+            (dashboard.patient_id.clone(), unit),
+            (Instant::now(), dashboard.clone()),
+        );
+    }
+
+    /// Drop the cached dashboards of `patient_id`
+    pub fn invalidate(&self, patient_id: &str) {
+        let mut cache = self.cache.lock().unwrap_or_else(|e| e.into_inner());
+        cache.retain(|(id, _), _| id != patient_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::models::SignalCode;
+    use chrono::TimeZone;
+
+    fn reading(ts: DateTime<Utc>, ts_source: TimestampSource) -> SensorReading {
+        SensorReading {
+            patient_id: "p1".into(),
+            device_id: "d1".into(),
+            code: SignalCode::Sound,
+            value: 72.0,
+            unit: "dB".into(),
+            ts,
+            ts_source,
+            metadata: Default::default(),
+        }
+    }
+
+    fn anomaly(at: DateTime<Utc>) -> AnomalyEvent {
+        AnomalyEvent {
+            observation_id: "o1".into(),
+            subject: "Patient/p1".into(),
+            code: "sound".into(),
+            value: 110.0,
+            unit: "dB".into(),
+            effective_date_time: at,
+            score: 4.2,
+            category: "spike".into(),
+            detector: "builtin",
+        }
+    }
+
+    #[test]
+    fn test_dashboard_fills_every_hour_and_splits_alerts() {
+        let now = Utc.with_ymd_and_hms(2026, 3, 2, 14, 20, 0).unwrap();
+        let readings = DashboardReadings {
+            last_reading: Some(reading(now - Duration::minutes(1), TimestampSource::Device)),
+            hourly_db: vec![
+                (hour_of(now), 70.0),
+                (hour_of(now) - Duration::hours(23), 60.0),
+                // Outside the window
+                (hour_of(now) - Duration::hours(24), 99.0),
+            ],
+            today_twa_db: Some(85.0),
+            device_last_seen: vec![
+                ("d1".into(), now - Duration::minutes(1)),
+                ("d2".into(), now - Duration::hours(2)),
+            ],
+        };
+        let alerts = vec![
+            anomaly(now - Duration::hours(3)),
+            anomaly(now - Duration::minutes(5)),
+        ];
+
+        let dashboard = PatientDashboard::build(
+            "p1",
+            now,
+            readings,
+            alerts,
+            &HashMap::new(),
+            std::time::Duration::from_secs(300),
+            None,
+        );
+
+        assert_eq!(dashboard.trend_24h.len(), TREND_HOURS as usize);
+        assert_eq!(dashboard.trend_24h[0].avg_db, Some(60.0));
+        assert_eq!(dashboard.trend_24h[1].avg_db, None);
+        assert_eq!(dashboard.trend_24h[23].ts, hour_of(now));
+        assert_eq!(dashboard.current_hour_avg, Some(70.0));
+        assert!((dashboard.noise_dose_today_percent - 100.0).abs() < 1e-9);
+        assert_eq!(dashboard.alert_count_today, 2);
+        assert_eq!(dashboard.active_alerts.len(), 1);
+        assert_eq!(
+            dashboard.last_reading.unwrap().quality,
+            ReadingQuality::Good
+        );
+        let online: Vec<_> = dashboard.device_status.iter().map(|d| d.online).collect();
+        assert_eq!(online, [true, false]);
+    }
+
+    #[test]
+    fn test_reading_quality() {
+        let now = Utc.with_ymd_and_hms(2026, 3, 2, 14, 20, 0).unwrap();
+        let quality = |ts, source| {
+            PatientDashboard::build(
+                "p1",
+                now,
+                DashboardReadings {
+                    last_reading: Some(reading(ts, source)),
+                    ..Default::default()
+                },
+                Vec::new(),
+                &HashMap::new(),
+                std::time::Duration::from_secs(300),
+                None,
+            )
+            .last_reading
+            .unwrap()
+            .quality
+        };
+
+        assert_eq!(
+            quality(now, TimestampSource::Server),
+            ReadingQuality::EstimatedTime
+        );
+        assert_eq!(
+            quality(now - Duration::hours(1), TimestampSource::Device),
+            ReadingQuality::Stale
+        );
+    }
+
+    #[test]
+    fn test_last_reading_in_requested_unit() {
+        let now = Utc.with_ymd_and_hms(2026, 3, 2, 14, 20, 0).unwrap();
+        let last_reading = |unit: &str, units: &UnitConversion| {
+            let mut r = reading(now, TimestampSource::Device);
+            r.value = 1000.0;
+            r.unit = unit.into();
+            let readings = DashboardReadings {
+                last_reading: Some(r),
+                ..Default::default()
+            };
+            let duration = std::time::Duration::from_secs(300);
+            PatientDashboard::build(
+                "p1",
+                now,
+                readings,
+                vec![],
+                &HashMap::new(),
+                duration,
+                Some(units),
+            )
+            .last_reading
+            .unwrap()
+        };
+        let units = UnitConversion {
+            target: TargetUnit::Decibel,
+            strict: false,
+            calibrations: HashMap::from([(
+                "d1".to_string(),
+                crate::units::RawCalibration {
+                    reference_db: 94.0,
+                    reference_raw: 100.0,
+                },
+            )]),
+        };
+
+        let converted = last_reading("raw", &units);
+        assert_eq!((converted.value, converted.unit.as_str()), (114.0, "dB"));
+        assert_eq!(converted.conversion, None);
+        let marked = last_reading("dBA", &units);
+        assert_eq!((marked.value, marked.unit.as_str()), (1000.0, "dBA"));
+        assert_eq!(marked.conversion, Some(CONVERSION_NONE));
+    }
+
+    #[test]
+    fn test_rate_limit_per_account() {
+        let dashboards = Dashboards::new(
+            std::time::Duration::from_secs(5),
+            2,
+            std::time::Duration::from_secs(300),
+        );
+        let t0 = Instant::now();
+        assert!(dashboards.check_rate_at("alice", t0).is_ok());
+        assert!(dashboards.check_rate_at("alice", t0).is_ok());
+        assert!(matches!(
+            dashboards.check_rate_at("alice", t0),
+            Err(AppError::TooManyRequests(60))
+        ));
+        // Others have their own allowance, and the minute runs out
+        assert!(dashboards.check_rate_at("bob", t0).is_ok());
+        let later = t0 + std::time::Duration::from_secs(61);
+        assert!(dashboards.check_rate_at("alice", later).is_ok());
+    }
+}
@@ -1,20 +1,484 @@
-use crate::domain::models::{SensorReading, SignalCode};
+use crate::alert_suppression::SuppressionWindow;
+use crate::bulk_export::ExportJob;
+use crate::calibration::{calibration_status, CalibrationVerification, WindowValues};
+use crate::compliance::{ComplianceEvidence, ComplianceReport, DeniedAccesses, DeviceReadings};
+use crate::cors::CorsOrigin;
+use crate::dashboard::DashboardReadings;
+use crate::device_auth::Challenge;
+use crate::device_stats::DeviceTotals;
+use crate::device_watchdog::{DeviceSettings, DeviceStatus};
+use crate::domain::models::{
+    ConsentStatus, OctaveBandReading, OctaveSpectrum, Patient, PatientErasure, PopulationCounts,
+    ReadingFilter, ReadingQuery, SensorReading, SignalCode, StoredReading, TimestampSource,
+    DECIBEL_UNITS, NOISE_EXPOSURE_LIMIT_DB, OCTAVE_BAND_COUNT,
+};
 use crate::errors::AppError;
-use chrono::{DateTime, Utc};
-use sqlx::postgres::PgPool;
-use sqlx::Row;
+use crate::fhir::{ObservationCategory, ObservationStatus};
+use crate::hearing::HearingProtector;
+use crate::hourly_pattern::HourStats;
+use crate::metrics::{self, METRICS};
+use crate::ml_client::{Analysis, AnalysisWindow, ScoreSample};
+use crate::noise_map::LocationNoise;
+use crate::reports::{DailyExposure, PeakReading, Recommendation};
+use crate::rollups::DailyRollup;
+use crate::sessions::IssuedToken;
+use crate::spectrogram::SpectrogramFrame;
+use crate::text_search::like_prefix;
+use crate::training::TrainingJob;
+use crate::units::{
+    unit_aliases, RawCalibration, TargetUnit, UnitConversion, RAW_UNIT, REFERENCE_PRESSURE_PA,
+};
+use crate::users::User;
+use crate::validation::{RuleConfig, Severity};
+use chrono::{DateTime, NaiveDate, Utc};
+use sqlx::migrate::{Migrate, MigrateError, Migrator};
+use sqlx::postgres::{PgPool, PgPoolOptions, PgRow, Postgres};
+use sqlx::{Row, Transaction};
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::time::Duration;
 use uuid::Uuid;
 
+/// Most readings `insert_readings` takes at once (14 binds each)
+pub const MAX_INSERT_ROWS: usize = 1000;
+
+/// `octave_band_readings` band columns, in `OctaveBandReading::bands` order
+const OCTAVE_BAND_COLUMNS: [&str; OCTAVE_BAND_COUNT] = [
+    "band_63hz",
+    "band_125hz",
+    "band_250hz",
+    "band_500hz",
+    "band_1khz",
+    "band_2khz",
+    "band_4khz",
+    "band_8khz",
+];
+
+/// `sensor_readings` columns read back by `stored_reading`
+const READING_COLUMNS: &str =
+    "id, patient_id, device_id, code, value, unit, timestamp, ts_source, status, note, category::TEXT AS category, metadata::TEXT AS metadata, deleted_at, raw_value";
+
+/// Condition that a `sensor_readings` row's unit is `unit` as
+/// `normalize_unit` reads it. Only ever built from constants.
+fn unit_is_sql(unit: &str) -> String {
+    let aliases: Vec<_> = unit_aliases(unit)
+        .iter()
+        .map(|alias| format!("'{}'", alias))
+        .collect();
+    let exact = format!("TRIM(sensor_readings.unit) = '{}'", unit);
+    if aliases.is_empty() {
+        exact
+    } else {
+        format!(
+            "(LOWER(TRIM(sensor_readings.unit)) IN ({}) OR {})",
+            aliases.join(", "),
+            exact
+        )
+    }
+}
+
+/// Level in dB of a `sensor_readings` row as `UnitConversion` converts it,
+/// raw output by its device's latest usable calibration (see
+/// `RawCalibration::latest`); NULL if it can't be
+fn decibel_value_sql() -> String {
+    format!(
+        "(CASE \
+           WHEN {decibel} THEN sensor_readings.value \
+           WHEN {pascal} AND sensor_readings.value > 0 \
+             THEN 20 * LOG(sensor_readings.value / {reference_pa:?}) \
+           WHEN {raw} AND sensor_readings.value > 0 THEN ( \
+             SELECT c.reference_db + 20 * LOG(sensor_readings.value / c.measured_avg_raw) \
+             FROM calibration_verifications c \
+             WHERE c.device_id = sensor_readings.device_id \
+               AND c.passed AND c.measured_avg_raw > 0 \
+             ORDER BY c.verified_at DESC LIMIT 1) \
+         END)",
+        decibel = unit_is_sql(TargetUnit::Decibel.as_str()),
+        pascal = unit_is_sql("Pa"),
+        reference_pa = REFERENCE_PRESSURE_PA,
+        raw = unit_is_sql(RAW_UNIT),
+    )
+}
+
+/// `WHERE` clause for a reading filter, with parameters from `$1` in the
+/// order code, category, patients; returns it with the number of parameters
+fn reading_conditions(filter: &ReadingFilter<'_>) -> (String, usize) {
+    let mut conditions = Vec::new();
+    if filter.code.is_some() {
+        conditions.push(format!("code = ${}", conditions.len() + 1));
+    }
+    if filter.category.is_some() {
+        conditions.push(format!("category @> ${}::JSONB", conditions.len() + 1));
+    }
+    if filter.patients.is_some() {
+        conditions.push(format!("patient_id = ANY(${})", conditions.len() + 1));
+    }
+    let params = conditions.len();
+    if !filter.include_deleted {
+        conditions.push("deleted_at IS NULL".to_string());
+    }
+    if let Some(units) = filter.units.filter(|units| units.strict) {
+        conditions.push(match units.target {
+            TargetUnit::Decibel => format!("{} IS NOT NULL", decibel_value_sql()),
+        });
+    }
+
+    if conditions.is_empty() {
+        (String::new(), 0)
+    } else {
+        (format!("WHERE {}", conditions.join(" AND ")), params)
+    }
+}
+
+/// Direction `keyset_page` walks readings in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum KeysetOrder {
+    /// Oldest first
+    Ascending,
+    /// Newest first
+    Descending,
+}
+
+/// Up to `limit` readings matching `filter` ordered by (effective time, id),
+/// starting after `after` in that order
+async fn keyset_page(
+    tx: &mut Transaction<'static, Postgres>,
+    filter: &ReadingFilter<'_>,
+    after: Option<(DateTime<Utc>, Uuid)>,
+    limit: usize,
+    order: KeysetOrder,
+) -> Result<Vec<StoredReading>, sqlx::Error> {
+    let (comparison, direction) = match order {
+        KeysetOrder::Ascending => (">", "ASC"),
+        KeysetOrder::Descending => ("<", "DESC"),
+    };
+    let (where_clause, params) = reading_conditions(filter);
+    let mut sql = format!(
+        "SELECT {} FROM sensor_readings {}",
+        READING_COLUMNS, where_clause
+    );
+    if after.is_some() {
+        sql.push_str(if where_clause.is_empty() {
+            " WHERE "
+        } else {
+            " AND "
+        });
+        sql.push_str(&format!(
+            "(timestamp, id) {} (${}, ${})",
+            comparison,
+            params + 1,
+            params + 2
+        ));
+    }
+    let limit_param = params + if after.is_some() { 3 } else { 1 };
+    sql.push_str(&format!(
+        " ORDER BY timestamp {direction}, id {direction} LIMIT ${limit_param}"
+    ));
+
+    let mut query = sqlx::query(&sql);
+    if let Some(code) = filter.code {
+        query = query.bind(code.as_str());
+    }
+    if let Some(category) = filter.category_json() {
+        query = query.bind(category);
+    }
+    if let Some(patients) = filter.patients {
+        query = query.bind(patients);
+    }
+    if let Some((ts, id)) = after {
+        query = query.bind(ts).bind(id);
+    }
+    let rows = query.bind(limit as i64).fetch_all(&mut **tx).await?;
+    Ok(rows.iter().filter_map(stored_reading).collect())
+}
+
+/// Number of readings matching `filter`
+async fn count_matching(
+    tx: &mut Transaction<'static, Postgres>,
+    filter: &ReadingFilter<'_>,
+) -> Result<usize, sqlx::Error> {
+    let (where_clause, _) = reading_conditions(filter);
+    let sql = format!("SELECT COUNT(*) FROM sensor_readings {}", where_clause);
+    let mut query = sqlx::query_scalar::<_, i64>(&sql);
+    if let Some(code) = filter.code {
+        query = query.bind(code.as_str());
+    }
+    if let Some(category) = filter.category_json() {
+        query = query.bind(category);
+    }
+    if let Some(patients) = filter.patients {
+        query = query.bind(patients);
+    }
+    Ok(query.fetch_one(&mut **tx).await? as usize)
+}
+
+/// Readings of a query read a page at a time from the snapshot its
+/// transaction sees. The transaction keeps a pooled connection until the
+/// pages run out or the cursor is dropped (say, by a client hanging up
+/// mid-response), which rolls it back and hands the connection back.
+#[derive(Debug)]
+pub struct ReadingCursor {
+    tx: Option<Transaction<'static, Postgres>>,
+    query: ReadingQuery,
+    /// Last reading returned
+    after: Option<(DateTime<Utc>, Uuid)>,
+    page_size: usize,
+    timeout_ms: u64,
+}
+
+impl ReadingCursor {
+    /// The next page of readings; empty once all were returned
+    pub async fn next_page(&mut self) -> Result<Vec<StoredReading>, AppError> {
+        let Some(tx) = self.tx.as_mut() else {
+            return Ok(Vec::new());
+        };
+        let page = with_deadline(
+            QueryKind::Read,
+            self.timeout_ms,
+            keyset_page(
+                tx,
+                &self.query.filter(),
+                self.after,
+                self.page_size,
+                KeysetOrder::Descending,
+            ),
+        )
+        .await
+        .inspect_err(|e| tracing::error!(error = %e, "Failed to read from reading cursor"))?;
+
+        if let Some(last) = page.last() {
+            self.after = Some((last.reading.ts, last.id));
+        }
+        if page.len() < self.page_size {
+            // Nothing was written; rolling back just frees the connection
+            self.tx = None;
+        }
+        Ok(page)
+    }
+}
+
+/// Decode a `sensor_readings` row selected with `READING_COLUMNS`. Rows with
+/// an unknown code or status are skipped with a warning and counted in
+/// `soundsense_db_rows_skipped_total`.
+fn stored_reading(row: &PgRow) -> Option<StoredReading> {
+    let code_str: String = row.get("code");
+    let ts_source = match row.get::<String, _>("ts_source").as_str() {
+        "server" => TimestampSource::Server,
+        _ => TimestampSource::Device,
+    };
+
+    let code = code_str
+        .parse::<SignalCode>()
+        .inspect_err(|_| {
+            metrics::inc(&METRICS.db_rows_unknown_code);
+            tracing::warn!(code = %code_str, "Unknown code in database");
+        })
+        .ok()?;
+    let status = ObservationStatus::try_from(row.get::<String, _>("status"))
+        .inspect_err(|e| {
+            metrics::inc(&METRICS.db_rows_unknown_status);
+            tracing::warn!(error = %e, "Unknown status in database")
+        })
+        .ok()?;
+
+    let category = observation_category(&row.get::<String, _>("category")).unwrap_or_else(|| {
+        tracing::warn!("Unknown category in database, using the signal default");
+        ObservationCategory::for_signal(&code)
+    });
+
+    Some(StoredReading {
+        id: row.get("id"),
+        reading: SensorReading {
+            patient_id: row.get("patient_id"),
+            device_id: row.get("device_id"),
+            code,
+            value: row.get("value"),
+            unit: row.get("unit"),
+            ts: row.get("timestamp"),
+            ts_source,
+            metadata: serde_json::from_str(&row.get::<String, _>("metadata")).unwrap_or_default(),
+        },
+        status,
+        note: row.get("note"),
+        category,
+        deleted_at: row.get("deleted_at"),
+        raw_value: row.get("raw_value"),
+    })
+}
+
+/// Drop the daily rollups a changed reading (a `READING_COLUMNS` row) may
+/// have counted towards, so the next rollup run recomputes them from the
+/// readings left. Rollup days are local to `ROLLUP_TIMEZONE`, which isn't
+/// known here, so the reading's UTC day and the days either side go.
+async fn invalidate_rollups(
+    tx: &mut Transaction<'static, Postgres>,
+    row: &PgRow,
+) -> Result<(), sqlx::Error> {
+    let day = row.try_get::<DateTime<Utc>, _>("timestamp")?.date_naive();
+    sqlx::query(
+        r#"
+        DELETE FROM daily_rollups
+        WHERE patient_id = $1 AND code = $2
+          AND date BETWEEN $3::DATE - 1 AND $3::DATE + 1
+        "#,
+    )
+    .bind(row.try_get::<String, _>("patient_id")?)
+    .bind(row.try_get::<String, _>("code")?)
+    .bind(day)
+    .execute(&mut **tx)
+    .await?;
+    Ok(())
+}
+
+/// Decode a `patients` row
+fn patient(row: &PgRow) -> Result<Patient, sqlx::Error> {
+    Ok(Patient {
+        patient_id: row.try_get("patient_id")?,
+        label: row.try_get("label")?,
+        consent_status: ConsentStatus::try_from(row.try_get::<String, _>("consent_status")?)
+            .map_err(|e| sqlx::Error::Decode(e.into()))?,
+        consented_at: row.try_get("consented_at")?,
+        updated_at: row.try_get("updated_at")?,
+    })
+}
+
+/// Decode a `device_settings` row
+fn device_settings(row: &PgRow) -> Result<DeviceSettings, sqlx::Error> {
+    Ok(DeviceSettings {
+        device_id: row.try_get("device_id")?,
+        status: DeviceStatus::try_from(row.try_get::<String, _>("status")?)
+            .map_err(|e| sqlx::Error::Decode(e.into()))?,
+        offline_grace_secs: row
+            .try_get::<Option<i64>, _>("offline_grace_secs")?
+            .map(|s| s as u64),
+        decimation_interval_ms: row
+            .try_get::<Option<i64>, _>("decimation_interval_ms")?
+            .map(|ms| ms as u64),
+        scale_min: row.try_get("scale_min")?,
+        scale_max: row.try_get("scale_max")?,
+        target_min: row.try_get("target_min")?,
+        target_max: row.try_get("target_max")?,
+        updated_at: row.try_get("updated_at")?,
+    })
+}
+
+/// Category of a `sensor_readings.category` value: the code of its first
+/// observation-category coding
+fn observation_category(json: &str) -> Option<ObservationCategory> {
+    let categories: Vec<serde_json::Value> = serde_json::from_str(json).ok()?;
+    categories
+        .iter()
+        .flat_map(|c| c["coding"].as_array().into_iter().flatten())
+        .filter(|coding| coding["system"] == ObservationCategory::SYSTEM)
+        .find_map(|coding| ObservationCategory::try_from(coding["code"].as_str()?.to_string()).ok())
+}
+
+/// Kind of database operation, used to pick a statement timeout
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QueryKind {
+    Read,
+    Write,
+    /// Month-wide aggregates behind compliance reports
+    Report,
+}
+
+impl std::fmt::Display for QueryKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            QueryKind::Read => write!(f, "read"),
+            QueryKind::Write => write!(f, "write"),
+            QueryKind::Report => write!(f, "report"),
+        }
+    }
+}
+
+/// The schema migrations in `migrations/`, embedded at build time
+pub static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+/// Apply the migrations in `MIGRATOR` not yet recorded in
+/// `_sqlx_migrations`, creating that table on first use, and return their
+/// versions, oldest first; none when the schema is current, so running it
+/// again is a no-op. Each migration runs in its own transaction, and sqlx
+/// holds an advisory lock meanwhile, so servers starting together migrate
+/// once. Fails, leaving the migrations before it applied, if a migration
+/// fails, if one was edited after being applied (checksum mismatch) or if
+/// the database records a version this build doesn't know. Run at startup
+/// and by `--migrate-only`.
+pub async fn migrate(pool: &PgPool) -> Result<Vec<i64>, MigrateError> {
+    let applied: HashSet<i64> = {
+        let mut conn = pool.acquire().await?;
+        conn.ensure_migrations_table().await?;
+        conn.list_applied_migrations()
+            .await?
+            .into_iter()
+            .map(|m| m.version)
+            .collect()
+    };
+    MIGRATOR.run(pool).await?;
+    Ok(MIGRATOR
+        .iter()
+        .filter(|m| !m.migration_type.is_down_migration() && !applied.contains(&m.version))
+        .map(|m| m.version)
+        .collect())
+}
+
+/// Connect to `database_url` with a single connection, migrate as `migrate`
+/// does and close the pool again, whether or not that succeeded: the work
+/// of `--migrate-only`, for deploy pipelines migrating ahead of a rollout.
+/// Fails without migrating if no connection is made within three seconds.
+pub async fn migrate_database_url(database_url: &str) -> Result<Vec<i64>, MigrateError> {
+    let pool = PgPoolOptions::new()
+        .max_connections(1)
+        .acquire_timeout(Duration::from_secs(3))
+        .connect(database_url)
+        .await?;
+    let result = migrate(&pool).await;
+    pool.close().await;
+    result
+}
+
 /// Database wrapper for PostgreSQL operations
 #[derive(Debug, Clone)]
 pub struct Database {
     pool: PgPool,
+    read_timeout_ms: u64,
+    write_timeout_ms: u64,
+    report_timeout_ms: u64,
 }
 
 impl Database {
-    /// Create a new database instance from a connection pool
+    /// Create a new database instance from a connection pool.
+    /// Statement timeouts come from `DB_READ_TIMEOUT_MS` (default 5000),
+    /// `DB_WRITE_TIMEOUT_MS` (default 2000) and, for compliance reports,
+    /// `DB_REPORT_TIMEOUT_MS` (default 120000).
     pub fn new(pool: PgPool) -> Self {
-        Self { pool }
+        let env_ms = |name: &str, default: u64| {
+            std::env::var(name)
+                .ok()
+                .and_then(|v| v.parse::<u64>().ok())
+                .filter(|v| *v > 0)
+                .unwrap_or(default)
+        };
+
+        Self {
+            pool,
+            read_timeout_ms: env_ms("DB_READ_TIMEOUT_MS", 5000),
+            write_timeout_ms: env_ms("DB_WRITE_TIMEOUT_MS", 2000),
+            report_timeout_ms: env_ms("DB_REPORT_TIMEOUT_MS", 120_000),
+        }
+    }
+
+    /// Override the statement timeouts
+    pub fn with_timeouts(mut self, read_timeout_ms: u64, write_timeout_ms: u64) -> Self {
+        self.read_timeout_ms = read_timeout_ms;
+        self.write_timeout_ms = write_timeout_ms;
+        self
+    }
+
+    /// Override the statement timeout of compliance report queries
+    pub fn with_report_timeout(mut self, report_timeout_ms: u64) -> Self {
+        self.report_timeout_ms = report_timeout_ms;
+        self
     }
 
     /// Get a reference to the connection pool (for audit logging)
@@ -22,130 +486,3112 @@ impl Database {
         &self.pool
     }
 
-    /// Insert a sensor reading into the database
-    pub async fn insert_reading(&self, reading: &SensorReading) -> Result<Uuid, AppError> {
+    /// Statement timeout for an operation type
+    pub fn timeout_for(&self, kind: QueryKind) -> u64 {
+        match kind {
+            QueryKind::Read => self.read_timeout_ms,
+            QueryKind::Write => self.write_timeout_ms,
+            QueryKind::Report => self.report_timeout_ms,
+        }
+    }
+
+    /// Run a query inside a transaction with `SET LOCAL statement_timeout`
+    /// applied, so a slow query is cancelled server-side instead of holding a
+    /// pool connection. The closure receives the transaction and hands it back
+    /// with its result; the transaction is committed on success.
+    pub async fn execute_with_timeout<T, F, Fut>(
+        &self,
+        kind: QueryKind,
+        op: F,
+    ) -> Result<T, AppError>
+    where
+        F: FnOnce(Transaction<'static, Postgres>) -> Fut,
+        Fut: Future<Output = Result<(T, Transaction<'static, Postgres>), sqlx::Error>>,
+    {
+        let timeout_ms = self.timeout_for(kind);
+
+        with_deadline(kind, timeout_ms, async {
+            let mut tx = self.pool.begin().await?;
+            // SET does not accept bind parameters; timeout_ms is a u64
+            sqlx::query(&format!("SET LOCAL statement_timeout = {}", timeout_ms))
+                .execute(&mut *tx)
+                .await?;
+
+            let (result, tx) = op(tx).await?;
+            tx.commit().await?;
+            Ok(result)
+        })
+        .await
+    }
+
+    /// Insert a sensor reading into the database under its record id. A
+    /// reading whose id is stored already is left as it is, so inserting
+    /// one again (replaying the dead-letter queue, say) is harmless.
+    pub async fn insert_reading(&self, stored: &StoredReading) -> Result<Uuid, AppError> {
+        let reading = &stored.reading;
         tracing::debug!(
+            id = %stored.id,
             patient_id = %reading.patient_id,
             device_id = %reading.device_id,
             value = reading.value,
             "Inserting sensor reading"
         );
 
-        let code_str = match reading.code {
-            SignalCode::Sound => "sound",
-        };
+        let category = serde_json::json!([stored.category.to_code()]).to_string();
+        // Validated on ingest
+        let location = reading.location().ok().flatten();
+        let metadata = serde_json::Value::Object(reading.metadata.clone()).to_string();
 
-        let id = sqlx::query_scalar::<_, Uuid>(
-            r#"
-            INSERT INTO sensor_readings (patient_id, device_id, code, value, unit, timestamp)
-            VALUES ($1, $2, $3, $4, $5, $6)
-            RETURNING id
-            "#,
-        )
-        .bind(&reading.patient_id)
-        .bind(&reading.device_id)
-        .bind(code_str)
-        .bind(reading.value)
-        .bind(&reading.unit)
-        .bind(reading.ts)
-        .fetch_one(&self.pool)
-        .await
-        .map_err(|e| {
-            tracing::error!(error = %e, "Failed to insert sensor reading");
-            AppError::Internal
-        })?;
+        let id = self
+            .execute_with_timeout(QueryKind::Write, |mut tx| async move {
+                let inserted = sqlx::query_scalar::<_, Uuid>(
+                    r#"
+                    INSERT INTO sensor_readings
+                        (id, patient_id, device_id, code, value, unit, timestamp, ts_source, status, note, category, location, metadata, raw_value)
+                    VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11::JSONB, $12, $13::JSONB, $14)
+                    ON CONFLICT (id) DO NOTHING
+                    RETURNING id
+                    "#,
+                )
+                .bind(stored.id)
+                .bind(&reading.patient_id)
+                .bind(&reading.device_id)
+                .bind(reading.code.as_str())
+                .bind(reading.value)
+                .bind(&reading.unit)
+                .bind(reading.ts)
+                .bind(reading.ts_source.as_str())
+                .bind(stored.status.as_str())
+                .bind(&stored.note)
+                .bind(&category)
+                .bind(location)
+                .bind(&metadata)
+                .bind(stored.raw_value.unwrap_or(reading.value))
+                .fetch_optional(&mut *tx)
+                .await?;
+                if inserted.is_none() {
+                    tracing::debug!(id = %stored.id, "Sensor reading stored already");
+                }
+                Ok((stored.id, tx))
+            })
+            .await
+            .inspect_err(|e| tracing::error!(error = %e, "Failed to insert sensor reading"))?;
 
         tracing::debug!(id = %id, "Successfully inserted sensor reading");
         Ok(id)
     }
 
-    /// Get recent sensor readings with optional code filter
+    /// Insert several sensor readings in one statement, all or none. At most
+    /// `MAX_INSERT_ROWS` per call, which keeps the bind count within
+    /// Postgres' limit.
+    pub async fn insert_readings(&self, stored: &[StoredReading]) -> Result<(), AppError> {
+        if stored.is_empty() {
+            return Ok(());
+        }
+        if stored.len() > MAX_INSERT_ROWS {
+            return Err(AppError::BadRequest(format!(
+                "at most {} readings per insert",
+                MAX_INSERT_ROWS
+            )));
+        }
+        tracing::debug!(count = stored.len(), "Inserting sensor readings");
+
+        self.execute_with_timeout(QueryKind::Write, |mut tx| async move {
+            let mut query = sqlx::QueryBuilder::<Postgres>::new(
+                "INSERT INTO sensor_readings \
+                 (id, patient_id, device_id, code, value, unit, timestamp, ts_source, status, note, category, location, metadata, raw_value) ",
+            );
+            query.push_values(stored, |mut row, stored| {
+                let reading = &stored.reading;
+                row.push_bind(stored.id)
+                    .push_bind(&reading.patient_id)
+                    .push_bind(&reading.device_id)
+                    .push_bind(reading.code.as_str())
+                    .push_bind(reading.value)
+                    .push_bind(&reading.unit)
+                    .push_bind(reading.ts)
+                    .push_bind(reading.ts_source.as_str())
+                    .push_bind(stored.status.as_str())
+                    .push_bind(&stored.note)
+                    .push_bind(serde_json::json!([stored.category.to_code()]).to_string())
+                    .push_unseparated("::JSONB")
+                    // Validated on ingest
+                    .push_bind(reading.location().ok().flatten())
+                    .push_bind(serde_json::Value::Object(reading.metadata.clone()).to_string())
+                    .push_unseparated("::JSONB")
+                    .push_bind(stored.raw_value.unwrap_or(reading.value));
+            });
+            query.build().execute(&mut *tx).await?;
+            Ok(((), tx))
+        })
+        .await
+        .inspect_err(|e| tracing::error!(error = %e, "Failed to insert sensor readings"))
+    }
+
+    /// Get recent sensor readings matching `filter`, skipping the `offset` newest
     pub async fn get_recent_readings(
         &self,
         limit: usize,
-        code_filter: Option<&str>,
-    ) -> Result<Vec<SensorReading>, AppError> {
-        tracing::debug!(limit = limit, code_filter = ?code_filter, "Fetching recent readings");
+        offset: usize,
+        filter: &ReadingFilter<'_>,
+    ) -> Result<Vec<StoredReading>, AppError> {
+        tracing::debug!(limit = limit, offset = offset, filter = ?filter, "Fetching recent readings");
 
-        let rows = if let Some(code) = code_filter {
-            sqlx::query(
+        let rows = self
+            .execute_with_timeout(QueryKind::Read, |mut tx| async move {
+                let (where_clause, params) = reading_conditions(filter);
+                let sql = format!(
+                    "SELECT {} FROM sensor_readings {} ORDER BY timestamp DESC LIMIT ${} OFFSET ${}",
+                    READING_COLUMNS,
+                    where_clause,
+                    params + 1,
+                    params + 2
+                );
+                let mut query = sqlx::query(&sql);
+                if let Some(code) = filter.code {
+                    query = query.bind(code.as_str());
+                }
+                if let Some(category) = filter.category_json() {
+                    query = query.bind(category);
+                }
+                if let Some(patients) = filter.patients {
+                    query = query.bind(patients);
+                }
+                let rows = query
+                    .bind(i64::try_from(limit).unwrap_or(i64::MAX))
+                    .bind(i64::try_from(offset).unwrap_or(i64::MAX))
+                    .fetch_all(&mut *tx)
+                    .await?;
+                Ok((rows, tx))
+            })
+            .await
+            .inspect_err(|e| tracing::error!(error = %e, "Failed to fetch sensor readings"))?;
+
+        let readings: Vec<StoredReading> = rows.iter().filter_map(stored_reading).collect();
+
+        tracing::debug!(
+            count = readings.len(),
+            "Successfully fetched sensor readings"
+        );
+        Ok(readings)
+    }
+
+    /// Up to `limit` readings matching `filter` in (effective time, id)
+    /// order, starting after `after`. Unlike offset paging, readings
+    /// inserted meanwhile never shift a page, so a bulk export walking
+    /// through millions of rows neither repeats nor skips any.
+    pub async fn readings_after(
+        &self,
+        after: Option<(DateTime<Utc>, Uuid)>,
+        limit: usize,
+        filter: &ReadingFilter<'_>,
+    ) -> Result<Vec<StoredReading>, AppError> {
+        self.execute_with_timeout(QueryKind::Read, |mut tx| async move {
+            let page = keyset_page(&mut tx, filter, after, limit, KeysetOrder::Ascending).await?;
+            Ok((page, tx))
+        })
+        .await
+        .inspect_err(|e| tracing::error!(error = %e, "Failed to fetch sensor readings for export"))
+    }
+
+    /// Sensor readings matching `filter` whose note or metadata contain the
+    /// words of `terms` (see `text_search::search_terms`), best `ts_rank`
+    /// first, with that rank
+    pub async fn search_readings(
+        &self,
+        terms: &str,
+        limit: usize,
+        filter: &ReadingFilter<'_>,
+    ) -> Result<Vec<(StoredReading, f64)>, AppError> {
+        tracing::debug!(limit = limit, filter = ?filter, "Searching sensor readings");
+
+        let rows = self
+            .execute_with_timeout(QueryKind::Read, |mut tx| async move {
+                let (where_clause, params) = reading_conditions(filter);
+                let matches = format!(
+                    "search_document @@ plainto_tsquery('english', ${})",
+                    params + 1
+                );
+                let where_clause = if where_clause.is_empty() {
+                    format!("WHERE {}", matches)
+                } else {
+                    format!("{} AND {}", where_clause, matches)
+                };
+                let sql = format!(
+                    "SELECT {}, ts_rank(search_document, plainto_tsquery('english', ${})) AS rank \
+                     FROM sensor_readings {} ORDER BY rank DESC, timestamp DESC LIMIT ${}",
+                    READING_COLUMNS,
+                    params + 1,
+                    where_clause,
+                    params + 2
+                );
+                let mut query = sqlx::query(&sql);
+                if let Some(code) = filter.code {
+                    query = query.bind(code.as_str());
+                }
+                if let Some(category) = filter.category_json() {
+                    query = query.bind(category);
+                }
+                if let Some(patients) = filter.patients {
+                    query = query.bind(patients);
+                }
+                let rows = query
+                    .bind(terms)
+                    .bind(limit as i64)
+                    .fetch_all(&mut *tx)
+                    .await?;
+                Ok((rows, tx))
+            })
+            .await
+            .inspect_err(|e| tracing::error!(error = %e, "Failed to search sensor readings"))?;
+
+        Ok(rows
+            .iter()
+            .filter_map(|row| Some((stored_reading(row)?, row.get::<f32, _>("rank") as f64)))
+            .collect())
+    }
+
+    /// Distinct tags of readings matching `filter` that start with `prefix`
+    /// (ignoring case), in order, at most `limit`
+    pub async fn suggest_tags(
+        &self,
+        prefix: &str,
+        limit: usize,
+        filter: &ReadingFilter<'_>,
+    ) -> Result<Vec<String>, AppError> {
+        let pattern = like_prefix(prefix);
+        self.execute_with_timeout(QueryKind::Read, |mut tx| async move {
+            let (where_clause, params) = reading_conditions(filter);
+            // Metadata without a tags array expands to no rows
+            let sql = format!(
+                "SELECT DISTINCT element #>> '{{}}' AS tag \
+                 FROM sensor_readings, jsonb_array_elements( \
+                     CASE WHEN jsonb_typeof(metadata->'tags') = 'array' THEN metadata->'tags' END \
+                 ) AS element \
+                 {} {} jsonb_typeof(element) = 'string' AND element #>> '{{}}' ILIKE ${} \
+                 ORDER BY tag LIMIT ${}",
+                where_clause,
+                if where_clause.is_empty() {
+                    "WHERE"
+                } else {
+                    "AND"
+                },
+                params + 1,
+                params + 2
+            );
+            let mut query = sqlx::query_scalar(&sql);
+            if let Some(code) = filter.code {
+                query = query.bind(code.as_str());
+            }
+            if let Some(category) = filter.category_json() {
+                query = query.bind(category);
+            }
+            if let Some(patients) = filter.patients {
+                query = query.bind(patients);
+            }
+            let tags = query
+                .bind(&pattern)
+                .bind(limit as i64)
+                .fetch_all(&mut *tx)
+                .await?;
+            Ok((tags, tx))
+        })
+        .await
+        .inspect_err(|e| tracing::error!(error = %e, "Failed to suggest tags"))
+    }
+
+    /// Number of sensor readings matching `filter`
+    pub async fn count_readings(&self, filter: &ReadingFilter<'_>) -> Result<usize, AppError> {
+        self.execute_with_timeout(QueryKind::Read, |mut tx| async move {
+            let count = count_matching(&mut tx, filter).await?;
+            Ok((count, tx))
+        })
+        .await
+        .inspect_err(|e| tracing::error!(error = %e, "Failed to count sensor readings"))
+    }
+
+    /// Count the readings matching `query` and open a cursor over them,
+    /// newest first. Both run in one repeatable-read snapshot, so the cursor
+    /// returns exactly as many readings as counted.
+    pub async fn open_reading_cursor(
+        &self,
+        query: ReadingQuery,
+        page_size: usize,
+    ) -> Result<(usize, ReadingCursor), AppError> {
+        let timeout_ms = self.timeout_for(QueryKind::Read);
+        let (total, tx) = with_deadline(QueryKind::Read, timeout_ms, async {
+            let mut tx = self.pool.begin().await?;
+            sqlx::query("SET TRANSACTION ISOLATION LEVEL REPEATABLE READ, READ ONLY")
+                .execute(&mut *tx)
+                .await?;
+            sqlx::query(&format!("SET LOCAL statement_timeout = {}", timeout_ms))
+                .execute(&mut *tx)
+                .await?;
+            let total = count_matching(&mut tx, &query.filter()).await?;
+            Ok((total, tx))
+        })
+        .await
+        .inspect_err(|e| tracing::error!(error = %e, "Failed to open reading cursor"))?;
+
+        Ok((
+            total,
+            ReadingCursor {
+                tx: Some(tx),
+                query,
+                after: None,
+                page_size: page_size.max(1),
+                timeout_ms,
+            },
+        ))
+    }
+
+    /// How many readings of a device are stored, and their time span
+    pub async fn device_totals(&self, device_id: &str) -> Result<DeviceTotals, AppError> {
+        let device_id = device_id.to_string();
+        self.execute_with_timeout(QueryKind::Read, |mut tx| async move {
+            let row = sqlx::query(
                 r#"
-                SELECT patient_id, device_id, code, value, unit, timestamp
+                SELECT COUNT(*) AS total, MIN(timestamp) AS first, MAX(timestamp) AS last
                 FROM sensor_readings
-                WHERE code = $1
-                ORDER BY timestamp DESC
-                LIMIT $2
+                WHERE device_id = $1 AND deleted_at IS NULL
                 "#,
             )
-            .bind(code)
-            .bind(limit as i64)
-            .fetch_all(&self.pool)
+            .bind(&device_id)
+            .fetch_one(&mut *tx)
+            .await?;
+            let totals = DeviceTotals {
+                total_readings: row.get("total"),
+                first_reading_at: row.get("first"),
+                last_reading_at: row.get("last"),
+            };
+            Ok((totals, tx))
+        })
+        .await
+        .inspect_err(|e| tracing::error!(error = %e, "Failed to fetch device totals"))
+    }
+
+    /// Find a sensor reading by record id
+    pub async fn get_reading(&self, id: Uuid) -> Result<Option<StoredReading>, AppError> {
+        let row = self
+            .execute_with_timeout(QueryKind::Read, |mut tx| async move {
+                let row = sqlx::query(&format!(
+                    "SELECT {} FROM sensor_readings WHERE id = $1",
+                    READING_COLUMNS
+                ))
+                .bind(id)
+                .fetch_optional(&mut *tx)
+                .await?;
+                Ok((row, tx))
+            })
             .await
-        } else {
+            .inspect_err(|e| tracing::error!(error = %e, "Failed to fetch sensor reading"))?;
+
+        Ok(row.as_ref().and_then(stored_reading))
+    }
+
+    /// Change a reading's status, provided it is still `expected`. A note of
+    /// `None` keeps the existing one. Returns `None` if the reading is gone or
+    /// its status changed in the meantime.
+    pub async fn amend_reading(
+        &self,
+        id: Uuid,
+        expected: ObservationStatus,
+        status: ObservationStatus,
+        note: Option<&str>,
+    ) -> Result<Option<StoredReading>, AppError> {
+        let row = self
+            .execute_with_timeout(QueryKind::Write, |mut tx| async move {
+                let row = sqlx::query(&format!(
+                    r#"
+                    UPDATE sensor_readings
+                    SET status = $3, note = COALESCE($4, note)
+                    WHERE id = $1 AND status = $2 AND deleted_at IS NULL
+                    RETURNING {}
+                    "#,
+                    READING_COLUMNS
+                ))
+                .bind(id)
+                .bind(expected.as_str())
+                .bind(status.as_str())
+                .bind(note)
+                .fetch_optional(&mut *tx)
+                .await?;
+                if let Some(row) = &row {
+                    invalidate_rollups(&mut tx, row).await?;
+                }
+                Ok((row, tx))
+            })
+            .await
+            .inspect_err(|e| tracing::error!(error = %e, "Failed to amend sensor reading"))?;
+
+        Ok(row.as_ref().and_then(stored_reading))
+    }
+
+    /// Mark a reading deleted; the row stays for the audit trail. Returns
+    /// `None` if there is no such reading or it was deleted already.
+    pub async fn soft_delete_reading(&self, id: Uuid) -> Result<Option<StoredReading>, AppError> {
+        let row = self
+            .execute_with_timeout(QueryKind::Write, |mut tx| async move {
+                let row = sqlx::query(&format!(
+                    r#"
+                    UPDATE sensor_readings
+                    SET deleted_at = NOW()
+                    WHERE id = $1 AND deleted_at IS NULL
+                    RETURNING {}
+                    "#,
+                    READING_COLUMNS
+                ))
+                .bind(id)
+                .fetch_optional(&mut *tx)
+                .await?;
+                if let Some(row) = &row {
+                    invalidate_rollups(&mut tx, row).await?;
+                }
+                Ok((row, tx))
+            })
+            .await
+            .inspect_err(|e| tracing::error!(error = %e, "Failed to delete sensor reading"))?;
+
+        Ok(row.as_ref().and_then(stored_reading))
+    }
+
+    /// Erase every reading of `patient_id`, `chunk` rows per transaction so
+    /// no single statement holds its locks for long, then drop the
+    /// registry rows of devices left without readings
+    pub async fn erase_patient(
+        &self,
+        patient_id: &str,
+        chunk: i64,
+    ) -> Result<PatientErasure, AppError> {
+        let mut erasure = PatientErasure::default();
+        let mut devices = std::collections::BTreeSet::new();
+
+        for table in [
+            "sensor_readings",
+            "octave_band_readings",
+            "spectrogram_frames",
+        ] {
+            loop {
+                let device_ids = self
+                    .execute_with_timeout(QueryKind::Write, |mut tx| async move {
+                        let ids = sqlx::query_scalar::<_, String>(&format!(
+                            r#"
+                            DELETE FROM {table}
+                            WHERE id IN (SELECT id FROM {table} WHERE patient_id = $1 LIMIT $2)
+                            RETURNING device_id
+                            "#
+                        ))
+                        .bind(patient_id)
+                        .bind(chunk)
+                        .fetch_all(&mut *tx)
+                        .await?;
+                        Ok((ids, tx))
+                    })
+                    .await
+                    .inspect_err(
+                        |e| tracing::error!(error = %e, table, "Failed to erase patient rows"),
+                    )?;
+
+                if table == "sensor_readings" {
+                    erasure.readings += device_ids.len() as u64;
+                }
+                let done = (device_ids.len() as i64) < chunk;
+                devices.extend(device_ids);
+                if done {
+                    break;
+                }
+            }
+        }
+
+        let devices: Vec<String> = devices.into_iter().collect();
+        erasure.devices = self
+            .execute_with_timeout(QueryKind::Write, |mut tx| async move {
+                for table in ["quarantined_readings", "daily_rollups"] {
+                    sqlx::query(&format!("DELETE FROM {table} WHERE patient_id = $1"))
+                        .bind(patient_id)
+                        .execute(&mut *tx)
+                        .await?;
+                }
+
+                // Devices still reporting for someone else stay registered
+                let orphaned = sqlx::query_scalar::<_, String>(
+                    r#"
+                    SELECT d.device_id
+                    FROM UNNEST($1::TEXT[]) AS d (device_id)
+                    WHERE NOT EXISTS (SELECT 1 FROM sensor_readings s WHERE s.device_id = d.device_id)
+                      AND NOT EXISTS (SELECT 1 FROM octave_band_readings o WHERE o.device_id = d.device_id)
+                      AND NOT EXISTS (SELECT 1 FROM spectrogram_frames f WHERE f.device_id = d.device_id)
+                    "#,
+                )
+                .bind(&devices)
+                .fetch_all(&mut *tx)
+                .await?;
+                for table in ["devices", "device_settings"] {
+                    sqlx::query(&format!("DELETE FROM {table} WHERE device_id = ANY($1)"))
+                        .bind(&orphaned)
+                        .execute(&mut *tx)
+                        .await?;
+                }
+                Ok((orphaned, tx))
+            })
+            .await
+            .inspect_err(|e| tracing::error!(error = %e, "Failed to erase patient registry rows"))?;
+
+        Ok(erasure)
+    }
+
+    /// List all allowed CORS origins
+    pub async fn list_cors_origins(&self) -> Result<Vec<CorsOrigin>, AppError> {
+        self.execute_with_timeout(QueryKind::Read, |mut tx| async move {
+            let origins = sqlx::query_as::<_, CorsOrigin>(
+                r#"
+                SELECT origin, description
+                FROM cors_allowed_origins
+                ORDER BY origin
+                "#,
+            )
+            .fetch_all(&mut *tx)
+            .await?;
+            Ok((origins, tx))
+        })
+        .await
+        .inspect_err(|e| tracing::error!(error = %e, "Failed to fetch CORS origins"))
+    }
+
+    /// Insert (or update the description of) an allowed CORS origin
+    pub async fn insert_cors_origin(&self, entry: &CorsOrigin) -> Result<(), AppError> {
+        self.execute_with_timeout(QueryKind::Write, |mut tx| async move {
             sqlx::query(
                 r#"
-                SELECT patient_id, device_id, code, value, unit, timestamp
-                FROM sensor_readings
-                ORDER BY timestamp DESC
-                LIMIT $1
+                INSERT INTO cors_allowed_origins (origin, description)
+                VALUES ($1, $2)
+                ON CONFLICT (origin) DO UPDATE SET description = EXCLUDED.description
                 "#,
             )
-            .bind(limit as i64)
-            .fetch_all(&self.pool)
-            .await
-        }
-        .map_err(|e| {
-            tracing::error!(error = %e, "Failed to fetch sensor readings");
-            AppError::Internal
-        })?;
+            .bind(&entry.origin)
+            .bind(&entry.description)
+            .execute(&mut *tx)
+            .await?;
+            Ok(((), tx))
+        })
+        .await
+        .inspect_err(|e| tracing::error!(error = %e, "Failed to insert CORS origin"))
+    }
 
-        let readings: Vec<SensorReading> = rows
-            .into_iter()
-            .filter_map(|row| {
-                let patient_id: String = row.get("patient_id");
-                let device_id: String = row.get("device_id");
-                let code_str: String = row.get("code");
-                let value: f64 = row.get("value");
-                let unit: String = row.get("unit");
-                let ts: DateTime<Utc> = row.get("timestamp");
-
-                // Convert string back to enum
-                let code = match code_str.as_str() {
-                    "sound" => SignalCode::Sound,
-                    _ => {
-                        tracing::warn!(code = %code_str, "Unknown code in database");
-                        return None;
-                    }
-                };
+    /// Look up a user by username
+    pub async fn find_user_by_username(&self, username: &str) -> Result<Option<User>, AppError> {
+        self.execute_with_timeout(QueryKind::Read, |mut tx| async move {
+            let user = sqlx::query_as::<_, User>(
+                r#"
+                SELECT id, username, password_hash, role, must_change_password, created_at, password_changed_at
+                FROM users
+                WHERE username = $1
+                "#,
+            )
+            .bind(username)
+            .fetch_optional(&mut *tx)
+            .await?;
+            Ok((user, tx))
+        })
+        .await
+        .inspect_err(|e| tracing::error!(error = %e, "Failed to fetch user"))
+    }
 
-                Some(SensorReading {
-                    patient_id,
-                    device_id,
-                    code,
-                    value,
-                    unit,
-                    ts,
-                })
-            })
-            .collect();
+    /// Look up a user by id
+    pub async fn get_user(&self, id: Uuid) -> Result<Option<User>, AppError> {
+        self.execute_with_timeout(QueryKind::Read, |mut tx| async move {
+            let user = sqlx::query_as::<_, User>(
+                r#"
+                SELECT id, username, password_hash, role, must_change_password, created_at, password_changed_at
+                FROM users
+                WHERE id = $1
+                "#,
+            )
+            .bind(id)
+            .fetch_optional(&mut *tx)
+            .await?;
+            Ok((user, tx))
+        })
+        .await
+        .inspect_err(|e| tracing::error!(error = %e, "Failed to fetch user"))
+    }
 
-        tracing::debug!(
-            count = readings.len(),
-            "Successfully fetched sensor readings"
-        );
-        Ok(readings)
+    /// List all users ordered by username
+    pub async fn list_users(&self) -> Result<Vec<User>, AppError> {
+        self.execute_with_timeout(QueryKind::Read, |mut tx| async move {
+            let users = sqlx::query_as::<_, User>(
+                r#"
+                SELECT id, username, password_hash, role, must_change_password, created_at, password_changed_at
+                FROM users
+                ORDER BY username
+                "#,
+            )
+            .fetch_all(&mut *tx)
+            .await?;
+            Ok((users, tx))
+        })
+        .await
+        .inspect_err(|e| tracing::error!(error = %e, "Failed to list users"))
     }
 
-    /// Health check - verify database connection is alive
-    pub async fn health_check(&self) -> Result<(), AppError> {
-        sqlx::query("SELECT 1")
-            .fetch_one(&self.pool)
-            .await
-            .map_err(|e| {
-                tracing::error!(error = %e, "Database health check failed");
-                AppError::Internal
-            })?;
+    /// Insert a new user; returns `false` if the username is already taken
+    pub async fn insert_user(&self, user: &User) -> Result<bool, AppError> {
+        self.execute_with_timeout(QueryKind::Write, |mut tx| async move {
+            let result = sqlx::query(
+                r#"
+                INSERT INTO users (id, username, password_hash, role, must_change_password, created_at, password_changed_at)
+                VALUES ($1, $2, $3, $4, $5, $6, $7)
+                ON CONFLICT (username) DO NOTHING
+                "#,
+            )
+            .bind(user.id)
+            .bind(&user.username)
+            .bind(&user.password_hash)
+            .bind(&user.role)
+            .bind(user.must_change_password)
+            .bind(user.created_at)
+            .bind(user.password_changed_at)
+            .execute(&mut *tx)
+            .await?;
+            Ok((result.rows_affected() == 1, tx))
+        })
+        .await
+        .inspect_err(|e| tracing::error!(error = %e, "Failed to insert user"))
+    }
 
-        Ok(())
+    /// Replace a user's password hash and rotation flag
+    pub async fn update_user_password(
+        &self,
+        id: Uuid,
+        password_hash: &str,
+        must_change_password: bool,
+    ) -> Result<bool, AppError> {
+        self.execute_with_timeout(QueryKind::Write, |mut tx| async move {
+            let result = sqlx::query(
+                r#"
+                UPDATE users
+                SET password_hash = $2, must_change_password = $3, password_changed_at = NOW()
+                WHERE id = $1
+                "#,
+            )
+            .bind(id)
+            .bind(password_hash)
+            .bind(must_change_password)
+            .execute(&mut *tx)
+            .await?;
+            Ok((result.rows_affected() == 1, tx))
+        })
+        .await
+        .inspect_err(|e| tracing::error!(error = %e, "Failed to update user password"))
+    }
+
+    /// Patients an account (a user's id or an OIDC subject) may read,
+    /// ordered by patient id
+    pub async fn user_patients(&self, account: &str) -> Result<Vec<String>, AppError> {
+        let account = account.to_string();
+        self.execute_with_timeout(QueryKind::Read, |mut tx| async move {
+            let patients: Vec<String> = sqlx::query_scalar(
+                "SELECT patient_id FROM user_patients WHERE user_id = $1 ORDER BY patient_id",
+            )
+            .bind(&account)
+            .fetch_all(&mut *tx)
+            .await?;
+            Ok((patients, tx))
+        })
+        .await
+        .inspect_err(|e| tracing::error!(error = %e, "Failed to fetch user patients"))
+    }
+
+    /// Add a patient to an account's list; returns `false` if it was already
+    /// there
+    pub async fn add_user_patient(
+        &self,
+        account: &str,
+        patient_id: &str,
+    ) -> Result<bool, AppError> {
+        self.execute_with_timeout(QueryKind::Write, |mut tx| async move {
+            let result = sqlx::query(
+                r#"
+                INSERT INTO user_patients (user_id, patient_id)
+                VALUES ($1, $2)
+                ON CONFLICT (user_id, patient_id) DO NOTHING
+                "#,
+            )
+            .bind(account)
+            .bind(patient_id)
+            .execute(&mut *tx)
+            .await?;
+            Ok((result.rows_affected() == 1, tx))
+        })
+        .await
+        .inspect_err(|e| tracing::error!(error = %e, "Failed to add user patient"))
+    }
+
+    /// Record an issued token
+    pub async fn insert_issued_token(&self, token: &IssuedToken) -> Result<(), AppError> {
+        self.execute_with_timeout(QueryKind::Write, |mut tx| async move {
+            sqlx::query(
+                r#"
+                INSERT INTO issued_tokens (jti, subject, role, device_id, issued_at, expires_at)
+                VALUES ($1, $2, $3, $4, $5, $6)
+                ON CONFLICT (jti) DO NOTHING
+                "#,
+            )
+            .bind(&token.jti)
+            .bind(&token.subject)
+            .bind(&token.role)
+            .bind(&token.device_id)
+            .bind(token.issued_at)
+            .bind(token.expires_at)
+            .execute(&mut *tx)
+            .await?;
+            Ok(((), tx))
+        })
+        .await
+        .inspect_err(|e| tracing::error!(error = %e, "Failed to record issued token"))
+    }
+
+    /// Unexpired tokens, newest first, optionally for one subject
+    pub async fn list_issued_tokens(
+        &self,
+        subject: Option<&str>,
+    ) -> Result<Vec<IssuedToken>, AppError> {
+        self.execute_with_timeout(QueryKind::Read, |mut tx| async move {
+            let tokens = sqlx::query_as::<_, IssuedToken>(
+                r#"
+                SELECT jti, subject, role, device_id, issued_at, expires_at, last_seen_at, revoked_at
+                FROM issued_tokens
+                WHERE expires_at > NOW() AND ($1::TEXT IS NULL OR subject = $1)
+                ORDER BY issued_at DESC
+                "#,
+            )
+            .bind(subject)
+            .fetch_all(&mut *tx)
+            .await?;
+            Ok((tokens, tx))
+        })
+        .await
+        .inspect_err(|e| tracing::error!(error = %e, "Failed to list issued tokens"))
+    }
+
+    /// Mark a token revoked; returns `false` if it is unknown
+    pub async fn revoke_issued_token(&self, jti: &str) -> Result<bool, AppError> {
+        self.execute_with_timeout(QueryKind::Write, |mut tx| async move {
+            let result = sqlx::query(
+                r#"
+                UPDATE issued_tokens
+                SET revoked_at = COALESCE(revoked_at, NOW())
+                WHERE jti = $1
+                "#,
+            )
+            .bind(jti)
+            .execute(&mut *tx)
+            .await?;
+            Ok((result.rows_affected() == 1, tx))
+        })
+        .await
+        .inspect_err(|e| tracing::error!(error = %e, "Failed to revoke token"))
+    }
+
+    /// Update a token's last-seen time
+    pub async fn touch_issued_token(
+        &self,
+        jti: &str,
+        seen_at: DateTime<Utc>,
+    ) -> Result<(), AppError> {
+        self.execute_with_timeout(QueryKind::Write, |mut tx| async move {
+            sqlx::query("UPDATE issued_tokens SET last_seen_at = $2 WHERE jti = $1")
+                .bind(jti)
+                .bind(seen_at)
+                .execute(&mut *tx)
+                .await?;
+            Ok(((), tx))
+        })
+        .await
+    }
+
+    /// Ids of revoked, unexpired tokens
+    pub async fn list_revoked_jtis(&self) -> Result<Vec<String>, AppError> {
+        self.execute_with_timeout(QueryKind::Read, |mut tx| async move {
+            let jtis = sqlx::query_scalar::<_, String>(
+                r#"
+                SELECT jti FROM issued_tokens
+                WHERE revoked_at IS NOT NULL AND expires_at > NOW()
+                "#,
+            )
+            .fetch_all(&mut *tx)
+            .await?;
+            Ok((jtis, tx))
+        })
+        .await
+        .inspect_err(|e| tracing::error!(error = %e, "Failed to load revoked tokens"))
+    }
+
+    /// Delete expired tokens, returning how many were removed
+    pub async fn delete_expired_tokens(&self) -> Result<u64, AppError> {
+        self.execute_with_timeout(QueryKind::Write, |mut tx| async move {
+            let result = sqlx::query("DELETE FROM issued_tokens WHERE expires_at <= NOW()")
+                .execute(&mut *tx)
+                .await?;
+            Ok((result.rows_affected(), tx))
+        })
+        .await
+        .inspect_err(|e| tracing::error!(error = %e, "Failed to delete expired tokens"))
+    }
+
+    /// Basic statistics of the readings taken from `start` up to `end`
+    /// (excluded), aggregated in the database
+    pub async fn reading_stats(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> Result<Option<Analysis>, AppError> {
+        self.execute_with_timeout(QueryKind::Read, |mut tx| async move {
+            let summary = sqlx::query(
+                r#"
+                SELECT COUNT(*) AS total, AVG(value) AS avg, STDDEV_SAMP(value) AS std,
+                       MIN(value) AS min, MAX(value) AS max
+                FROM sensor_readings
+                WHERE timestamp >= $1 AND timestamp < $2 AND status <> 'entered-in-error'
+                  AND deleted_at IS NULL
+                "#,
+            )
+            .bind(start)
+            .bind(end)
+            .fetch_one(&mut *tx)
+            .await?;
+
+            let total: i64 = summary.try_get("total")?;
+            if total == 0 {
+                return Ok((None, tx));
+            }
+
+            // Hours ordered by average level, quietest first
+            let hours = sqlx::query_scalar::<_, i32>(
+                r#"
+                SELECT EXTRACT(HOUR FROM timestamp AT TIME ZONE 'UTC')::INT AS hour
+                FROM sensor_readings
+                WHERE timestamp >= $1 AND timestamp < $2 AND status <> 'entered-in-error'
+                  AND deleted_at IS NULL
+                GROUP BY hour
+                ORDER BY AVG(value), hour
+                "#,
+            )
+            .bind(start)
+            .bind(end)
+            .fetch_all(&mut *tx)
+            .await?;
+
+            let analysis = Analysis {
+                total_readings: total as usize,
+                avg_level: summary
+                    .try_get::<Option<f64>, _>("avg")?
+                    .unwrap_or_default(),
+                std_level: summary
+                    .try_get::<Option<f64>, _>("std")?
+                    .unwrap_or_default(),
+                min_level: summary
+                    .try_get::<Option<f64>, _>("min")?
+                    .unwrap_or_default(),
+                max_level: summary
+                    .try_get::<Option<f64>, _>("max")?
+                    .unwrap_or_default(),
+                anomaly_count: 0,
+                anomaly_percentage: 0.0,
+                peak_hour: hours.last().copied().unwrap_or_default(),
+                quietest_hour: hours.first().copied().unwrap_or_default(),
+            };
+            Ok((Some(analysis), tx))
+        })
+        .await
+        .inspect_err(|e| tracing::error!(error = %e, "Failed to compute reading statistics"))
+    }
+
+    /// Up to `limit` of a patient's usable readings between `start` and
+    /// `end`, oldest first
+    pub async fn patient_readings(
+        &self,
+        patient_id: &str,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        limit: usize,
+    ) -> Result<Vec<SensorReading>, AppError> {
+        let patient_id = patient_id.to_string();
+
+        let rows = self
+            .execute_with_timeout(QueryKind::Read, |mut tx| async move {
+                let rows = sqlx::query(&format!(
+                    r#"
+                    SELECT {} FROM sensor_readings
+                    WHERE patient_id = $1 AND timestamp >= $2 AND timestamp <= $3
+                      AND status <> 'entered-in-error'
+                      AND deleted_at IS NULL
+                    ORDER BY timestamp
+                    LIMIT $4
+                    "#,
+                    READING_COLUMNS
+                ))
+                .bind(&patient_id)
+                .bind(start)
+                .bind(end)
+                .bind(limit as i64)
+                .fetch_all(&mut *tx)
+                .await?;
+                Ok((rows, tx))
+            })
+            .await
+            .inspect_err(|e| tracing::error!(error = %e, "Failed to fetch patient readings"))?;
+
+        Ok(rows
+            .iter()
+            .filter_map(stored_reading)
+            .map(|r| r.reading)
+            .collect())
+    }
+
+    /// A patient's usable readings in `window`, oldest first
+    pub async fn patient_samples(
+        &self,
+        patient_id: &str,
+        window: &AnalysisWindow,
+    ) -> Result<Vec<ScoreSample>, AppError> {
+        let (after, before, limit) = window.bounds(Utc::now());
+        let patient_id = patient_id.to_string();
+
+        self.execute_with_timeout(QueryKind::Read, |mut tx| async move {
+            let mut samples = sqlx::query(
+                r#"
+                SELECT value, timestamp
+                FROM sensor_readings
+                WHERE patient_id = $1 AND status <> 'entered-in-error'
+                  AND deleted_at IS NULL
+                  AND ($2::TIMESTAMPTZ IS NULL OR timestamp >= $2)
+                  AND ($3::TIMESTAMPTZ IS NULL OR timestamp < $3)
+                ORDER BY timestamp DESC
+                LIMIT $4
+                "#,
+            )
+            .bind(&patient_id)
+            .bind(after)
+            .bind(before)
+            .bind(limit as i64)
+            .fetch_all(&mut *tx)
+            .await?
+            .iter()
+            .map(|row| {
+                Ok(ScoreSample {
+                    value: row.try_get("value")?,
+                    timestamp: row.try_get("timestamp")?,
+                })
+            })
+            .collect::<Result<Vec<_>, sqlx::Error>>()?;
+            samples.reverse();
+            Ok((samples, tx))
+        })
+        .await
+        .inspect_err(|e| tracing::error!(error = %e, "Failed to fetch patient readings"))
+    }
+
+    /// Patients with readings between `start` and `end`, per signal code, and
+    /// how many of them have a time-weighted average above the exposure limit
+    /// (see `PopulationCounts::from_readings`)
+    pub async fn population_counts(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> Result<HashMap<&'static str, PopulationCounts>, AppError> {
+        let units: Vec<String> = DECIBEL_UNITS.iter().map(|u| u.to_string()).collect();
+
+        let rows = self
+            .execute_with_timeout(QueryKind::Read, |mut tx| async move {
+                let rows = sqlx::query(
+                    r#"
+                    SELECT code,
+                           COUNT(*) AS patients,
+                           COUNT(twa_db) AS measured,
+                           COUNT(*) FILTER (WHERE twa_db > $4) AS over_limit
+                    FROM (
+                        SELECT code, patient_id,
+                               10 * LOG(AVG(CASE WHEN LOWER(unit) = ANY($3)
+                                                 THEN POWER(10, value / 10) END)) AS twa_db
+                        FROM sensor_readings
+                        WHERE timestamp >= $1 AND timestamp <= $2
+                          AND status <> 'entered-in-error'
+                          AND deleted_at IS NULL
+                        GROUP BY code, patient_id
+                    ) per_patient
+                    GROUP BY code
+                    "#,
+                )
+                .bind(start)
+                .bind(end)
+                .bind(&units)
+                .bind(NOISE_EXPOSURE_LIMIT_DB)
+                .fetch_all(&mut *tx)
+                .await?
+                .iter()
+                .map(|row| {
+                    Ok((
+                        row.try_get::<String, _>("code")?,
+                        PopulationCounts {
+                            patients: row.try_get("patients")?,
+                            measured: row.try_get("measured")?,
+                            over_limit: row.try_get("over_limit")?,
+                        },
+                    ))
+                })
+                .collect::<Result<Vec<_>, sqlx::Error>>()?;
+                Ok((rows, tx))
+            })
+            .await
+            .inspect_err(|e| tracing::error!(error = %e, "Failed to count exposure populations"))?;
+
+        let mut counts = HashMap::new();
+        for (code, population) in rows {
+            match code.parse::<SignalCode>() {
+                Ok(signal) => {
+                    counts.insert(signal.as_str(), population);
+                }
+                Err(_) => tracing::warn!(code = %code, "Unknown code in database"),
+            }
+        }
+        Ok(counts)
+    }
+
+    pub async fn insert_octave_band_reading(
+        &self,
+        reading: &OctaveBandReading,
+    ) -> Result<Uuid, AppError> {
+        self.execute_with_timeout(QueryKind::Write, |mut tx| async move {
+            let b = &reading.bands;
+            let id = sqlx::query_scalar::<_, Uuid>(
+                r#"
+                INSERT INTO octave_band_readings (
+                    patient_id, device_id, timestamp,
+                    band_63hz, band_125hz, band_250hz, band_500hz,
+                    band_1khz, band_2khz, band_4khz, band_8khz, overall_db, calibrated_value
+                )
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13)
+                RETURNING id
+                "#,
+            )
+            .bind(&reading.patient_id)
+            .bind(&reading.device_id)
+            .bind(reading.ts)
+            .bind(b[0])
+            .bind(b[1])
+            .bind(b[2])
+            .bind(b[3])
+            .bind(b[4])
+            .bind(b[5])
+            .bind(b[6])
+            .bind(b[7])
+            .bind(reading.overall_db)
+            .bind(reading.calibrated_value)
+            .fetch_one(&mut *tx)
+            .await?;
+            Ok((id, tx))
+        })
+        .await
+        .inspect_err(|e| tracing::error!(error = %e, "Failed to insert octave band reading"))
+    }
+
+    pub async fn insert_spectrogram_frame(
+        &self,
+        frame: &SpectrogramFrame,
+    ) -> Result<Uuid, AppError> {
+        self.execute_with_timeout(QueryKind::Write, |mut tx| async move {
+            let id = sqlx::query_scalar::<_, Uuid>(
+                r#"
+                INSERT INTO spectrogram_frames (
+                    patient_id, device_id, timestamp, sample_rate_hz, frame_duration_ms,
+                    frequency_bins, magnitudes
+                )
+                VALUES ($1, $2, $3, $4, $5, $6, $7)
+                RETURNING id
+                "#,
+            )
+            .bind(&frame.patient_id)
+            .bind(&frame.device_id)
+            .bind(frame.ts)
+            .bind(frame.sample_rate_hz as i32)
+            .bind(frame.frame_duration_ms as i32)
+            .bind(&frame.frequency_bins)
+            .bind(&frame.magnitudes)
+            .fetch_one(&mut *tx)
+            .await?;
+            Ok((id, tx))
+        })
+        .await
+        .inspect_err(|e| tracing::error!(error = %e, "Failed to insert spectrogram frame"))
+    }
+
+    /// A patient's frames from `start` to `end` (inclusive), oldest first
+    pub async fn spectrogram_frames(
+        &self,
+        patient_id: &str,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        max_frames: usize,
+    ) -> Result<Vec<SpectrogramFrame>, AppError> {
+        let patient_id = patient_id.to_string();
+
+        self.execute_with_timeout(QueryKind::Read, |mut tx| async move {
+            let rows = sqlx::query(
+                r#"
+                SELECT patient_id, device_id, timestamp, sample_rate_hz, frame_duration_ms,
+                       frequency_bins, magnitudes
+                FROM spectrogram_frames
+                WHERE patient_id = $1 AND timestamp >= $2 AND timestamp <= $3
+                ORDER BY timestamp
+                LIMIT $4
+                "#,
+            )
+            .bind(&patient_id)
+            .bind(start)
+            .bind(end)
+            .bind(max_frames as i64)
+            .fetch_all(&mut *tx)
+            .await?;
+
+            let frames = rows
+                .iter()
+                .map(|row| {
+                    Ok(SpectrogramFrame {
+                        patient_id: row.try_get("patient_id")?,
+                        device_id: row.try_get("device_id")?,
+                        ts: row.try_get("timestamp")?,
+                        sample_rate_hz: row.try_get::<i32, _>("sample_rate_hz")? as u32,
+                        frame_duration_ms: row.try_get::<i32, _>("frame_duration_ms")? as u32,
+                        frequency_bins: row.try_get("frequency_bins")?,
+                        magnitudes: row.try_get("magnitudes")?,
+                    })
+                })
+                .collect::<Result<Vec<_>, sqlx::Error>>()?;
+            Ok((frames, tx))
+        })
+        .await
+        .inspect_err(|e| tracing::error!(error = %e, "Failed to fetch spectrogram frames"))
+    }
+
+    /// Ids of every registered patient
+    pub async fn list_patient_ids(&self) -> Result<Vec<String>, AppError> {
+        self.execute_with_timeout(QueryKind::Read, |mut tx| async move {
+            let rows = sqlx::query("SELECT patient_id FROM patients")
+                .fetch_all(&mut *tx)
+                .await?;
+            let ids = rows
+                .iter()
+                .map(|row| row.try_get("patient_id"))
+                .collect::<Result<_, _>>()?;
+            Ok((ids, tx))
+        })
+        .await
+        .inspect_err(|e| tracing::error!(error = %e, "Failed to fetch patient ids"))
+    }
+
+    /// Active validation rules, in the order they were added
+    pub async fn list_validation_rules(&self) -> Result<Vec<RuleConfig>, AppError> {
+        self.execute_with_timeout(QueryKind::Read, |mut tx| async move {
+            let rows = sqlx::query(
+                r#"
+                SELECT rule_name, config::TEXT AS config, severity::TEXT AS severity
+                FROM validation_rules
+                WHERE active
+                ORDER BY id
+                "#,
+            )
+            .fetch_all(&mut *tx)
+            .await?;
+
+            let rules = rows
+                .iter()
+                .map(|row| {
+                    Ok(RuleConfig {
+                        rule_name: row.try_get("rule_name")?,
+                        config: serde_json::from_str(row.try_get("config")?)
+                            .map_err(|e| sqlx::Error::Decode(e.into()))?,
+                        severity: Severity::try_from(row.try_get::<&str, _>("severity")?)
+                            .map_err(|e| sqlx::Error::Decode(e.into()))?,
+                    })
+                })
+                .collect::<Result<_, sqlx::Error>>()?;
+            Ok((rules, tx))
+        })
+        .await
+        .inspect_err(|e| tracing::error!(error = %e, "Failed to fetch validation rules"))
+    }
+
+    pub async fn get_patient(&self, patient_id: &str) -> Result<Option<Patient>, AppError> {
+        let patient_id = patient_id.to_string();
+
+        self.execute_with_timeout(QueryKind::Read, |mut tx| async move {
+            let row = sqlx::query(
+                r#"
+                SELECT patient_id, label, consent_status, consented_at, updated_at
+                FROM patients
+                WHERE patient_id = $1
+                "#,
+            )
+            .bind(&patient_id)
+            .fetch_optional(&mut *tx)
+            .await?;
+
+            let patient = row.as_ref().map(patient).transpose()?;
+            Ok((patient, tx))
+        })
+        .await
+        .inspect_err(|e| tracing::error!(error = %e, "Failed to fetch patient"))
+    }
+
+    /// The registered patients among `patient_ids`, by id, in one query
+    pub async fn get_patients(
+        &self,
+        patient_ids: &[String],
+    ) -> Result<HashMap<String, Patient>, AppError> {
+        let patient_ids = patient_ids.to_vec();
+
+        self.execute_with_timeout(QueryKind::Read, |mut tx| async move {
+            let patients = sqlx::query(
+                r#"
+                SELECT patient_id, label, consent_status, consented_at, updated_at
+                FROM patients
+                WHERE patient_id = ANY($1)
+                "#,
+            )
+            .bind(&patient_ids)
+            .fetch_all(&mut *tx)
+            .await?
+            .iter()
+            .map(|row| patient(row).map(|p| (p.patient_id.clone(), p)))
+            .collect::<Result<HashMap<_, _>, sqlx::Error>>()?;
+            Ok((patients, tx))
+        })
+        .await
+        .inspect_err(|e| tracing::error!(error = %e, "Failed to fetch patients"))
+    }
+
+    /// Insert or replace a patient's registration
+    pub async fn upsert_patient(&self, patient: &Patient) -> Result<(), AppError> {
+        self.execute_with_timeout(QueryKind::Write, |mut tx| async move {
+            sqlx::query(
+                r#"
+                INSERT INTO patients (patient_id, label, consent_status, consented_at, updated_at)
+                VALUES ($1, $2, $3, $4, $5)
+                ON CONFLICT (patient_id) DO UPDATE
+                SET label = EXCLUDED.label,
+                    consent_status = EXCLUDED.consent_status,
+                    consented_at = EXCLUDED.consented_at,
+                    updated_at = EXCLUDED.updated_at
+                "#,
+            )
+            .bind(&patient.patient_id)
+            .bind(&patient.label)
+            .bind(patient.consent_status.as_str())
+            .bind(patient.consented_at)
+            .bind(patient.updated_at)
+            .execute(&mut *tx)
+            .await?;
+            Ok(((), tx))
+        })
+        .await
+        .inspect_err(|e| tracing::error!(error = %e, "Failed to store patient"))
+    }
+
+    /// Consent status of each registered patient among `patient_ids`
+    pub async fn consent_statuses(
+        &self,
+        patient_ids: &[&str],
+    ) -> Result<HashMap<String, ConsentStatus>, AppError> {
+        let patient_ids: Vec<String> = patient_ids.iter().map(|id| id.to_string()).collect();
+
+        self.execute_with_timeout(QueryKind::Read, |mut tx| async move {
+            let statuses = sqlx::query(
+                "SELECT patient_id, consent_status FROM patients WHERE patient_id = ANY($1)",
+            )
+            .bind(&patient_ids)
+            .fetch_all(&mut *tx)
+            .await?
+            .iter()
+            .map(|row| {
+                let status = ConsentStatus::try_from(row.try_get::<String, _>("consent_status")?)
+                    .map_err(|e| sqlx::Error::Decode(e.into()))?;
+                Ok((row.try_get("patient_id")?, status))
+            })
+            .collect::<Result<HashMap<_, _>, sqlx::Error>>()?;
+            Ok((statuses, tx))
+        })
+        .await
+        .inspect_err(|e| tracing::error!(error = %e, "Failed to fetch consent statuses"))
+    }
+
+    /// Keep a reading received without consent, apart from all other readings
+    pub async fn insert_quarantined_reading(
+        &self,
+        patient_id: &str,
+        kind: &str,
+        payload: &serde_json::Value,
+        reason: &str,
+    ) -> Result<Uuid, AppError> {
+        let (patient_id, kind, reason) =
+            (patient_id.to_string(), kind.to_string(), reason.to_string());
+        let payload = payload.to_string();
+
+        self.execute_with_timeout(QueryKind::Write, |mut tx| async move {
+            let id = sqlx::query_scalar::<_, Uuid>(
+                r#"
+                INSERT INTO quarantined_readings (patient_id, kind, payload, reason)
+                VALUES ($1, $2, $3::JSONB, $4)
+                RETURNING id
+                "#,
+            )
+            .bind(&patient_id)
+            .bind(&kind)
+            .bind(&payload)
+            .bind(&reason)
+            .fetch_one(&mut *tx)
+            .await?;
+            Ok((id, tx))
+        })
+        .await
+        .inspect_err(|e| tracing::error!(error = %e, "Failed to quarantine reading"))
+    }
+
+    /// Time-weighted average (energy average) of a patient's usable decibel
+    /// readings from `start` (inclusive) to `end` (exclusive); `None` without any
+    pub async fn patient_twa(
+        &self,
+        patient_id: &str,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> Result<Option<f64>, AppError> {
+        let patient_id = patient_id.to_string();
+        let units: Vec<String> = DECIBEL_UNITS.iter().map(|u| u.to_string()).collect();
+
+        self.execute_with_timeout(QueryKind::Read, |mut tx| async move {
+            let twa = sqlx::query_scalar::<_, Option<f64>>(
+                r#"
+                SELECT 10 * LOG(AVG(POWER(10, value / 10)))
+                FROM sensor_readings
+                WHERE patient_id = $1 AND timestamp >= $2 AND timestamp < $3
+                  AND LOWER(unit) = ANY($4)
+                  AND status <> 'entered-in-error'
+                  AND deleted_at IS NULL
+                "#,
+            )
+            .bind(&patient_id)
+            .bind(start)
+            .bind(end)
+            .bind(&units)
+            .fetch_one(&mut *tx)
+            .await?;
+            Ok((twa, tx))
+        })
+        .await
+        .inspect_err(|e| tracing::error!(error = %e, "Failed to compute patient TWA"))
+    }
+
+    /// A patient's usable readings in `unit` (ignoring case), or converted
+    /// to it if it is a `TargetUnit`, from `start` to `end` by hour of the
+    /// day at `offset`. The offset is added as an interval: `AT TIME ZONE
+    /// '+03:00'` would read it POSIX-style, as three hours west of UTC.
+    pub async fn hourly_stats(
+        &self,
+        patient_id: &str,
+        unit: &str,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        offset: chrono::FixedOffset,
+    ) -> Result<Vec<HourStats>, AppError> {
+        let patient_id = patient_id.to_string();
+        let target = unit.parse::<TargetUnit>().ok();
+        let unit = unit.to_lowercase();
+        let offset_secs = offset.local_minus_utc();
+        let (level, unit_condition) = match target {
+            Some(TargetUnit::Decibel) => (decibel_value_sql(), ""),
+            None => ("value".to_string(), "AND LOWER(unit) = $5"),
+        };
+
+        self.execute_with_timeout(QueryKind::Read, |mut tx| async move {
+            let sql = format!(
+                r#"
+                SELECT EXTRACT(HOUR FROM (timestamp AT TIME ZONE 'UTC')
+                                         + $4 * INTERVAL '1 second')::INT AS hour,
+                       AVG(level) AS mean, MAX(level) AS max, COUNT(*) AS count
+                FROM (
+                    SELECT timestamp, {} AS level
+                    FROM sensor_readings
+                    WHERE patient_id = $1 AND timestamp >= $2 AND timestamp <= $3
+                      {}
+                      AND status <> 'entered-in-error'
+                      AND deleted_at IS NULL
+                ) readings
+                WHERE level IS NOT NULL
+                GROUP BY hour
+                ORDER BY hour
+                "#,
+                level, unit_condition
+            );
+            let mut query = sqlx::query(&sql)
+                .bind(&patient_id)
+                .bind(start)
+                .bind(end)
+                .bind(offset_secs);
+            if target.is_none() {
+                query = query.bind(&unit);
+            }
+            let hours = query
+                .fetch_all(&mut *tx)
+                .await?
+                .iter()
+                .map(|row| {
+                    Ok(HourStats {
+                        hour: row.try_get::<i32, _>("hour")? as u32,
+                        mean: row.try_get("mean")?,
+                        max: row.try_get("max")?,
+                        count: row.try_get("count")?,
+                    })
+                })
+                .collect::<Result<Vec<_>, sqlx::Error>>()?;
+            Ok((hours, tx))
+        })
+        .await
+        .inspect_err(|e| tracing::error!(error = %e, "Failed to compute hourly pattern"))
+    }
+
+    /// Everything a patient's dashboard needs, read in one transaction:
+    /// the latest usable reading (that `conversion` keeps, if given), the
+    /// level of each hour since `since`, the TWA since `day_start` and the
+    /// devices heard from since `since`
+    pub async fn dashboard_readings(
+        &self,
+        patient_id: &str,
+        since: DateTime<Utc>,
+        day_start: DateTime<Utc>,
+        conversion: Option<&UnitConversion>,
+    ) -> Result<DashboardReadings, AppError> {
+        let patient_id = patient_id.to_string();
+        let units: Vec<String> = DECIBEL_UNITS.iter().map(|u| u.to_string()).collect();
+        let convertible = match conversion.filter(|c| c.strict).map(|c| c.target) {
+            Some(TargetUnit::Decibel) => format!("AND {} IS NOT NULL", decibel_value_sql()),
+            None => String::new(),
+        };
+
+        self.execute_with_timeout(QueryKind::Read, |mut tx| async move {
+            let last_reading = sqlx::query(&format!(
+                r#"
+                SELECT {} FROM sensor_readings
+                WHERE patient_id = $1
+                  AND status <> 'entered-in-error'
+                  AND deleted_at IS NULL
+                  {}
+                ORDER BY timestamp DESC
+                LIMIT 1
+                "#,
+                READING_COLUMNS, convertible
+            ))
+            .bind(&patient_id)
+            .fetch_optional(&mut *tx)
+            .await?
+            .as_ref()
+            .and_then(stored_reading)
+            .map(|r| r.reading);
+
+            let hourly_db = sqlx::query(
+                r#"
+                SELECT DATE_TRUNC('hour', timestamp AT TIME ZONE 'UTC') AS hour,
+                       10 * LOG(AVG(POWER(10, value / 10))) AS avg_db
+                FROM sensor_readings
+                WHERE patient_id = $1 AND timestamp >= $2
+                  AND LOWER(unit) = ANY($3)
+                  AND status <> 'entered-in-error'
+                  AND deleted_at IS NULL
+                GROUP BY hour
+                ORDER BY hour
+                "#,
+            )
+            .bind(&patient_id)
+            .bind(since)
+            .bind(&units)
+            .fetch_all(&mut *tx)
+            .await?
+            .iter()
+            .map(|row| {
+                let hour: chrono::NaiveDateTime = row.try_get("hour")?;
+                Ok((hour.and_utc(), row.try_get("avg_db")?))
+            })
+            .collect::<Result<Vec<_>, sqlx::Error>>()?;
+
+            let today_twa_db = sqlx::query_scalar::<_, Option<f64>>(
+                r#"
+                SELECT 10 * LOG(AVG(POWER(10, value / 10)))
+                FROM sensor_readings
+                WHERE patient_id = $1 AND timestamp >= $2
+                  AND LOWER(unit) = ANY($3)
+                  AND status <> 'entered-in-error'
+                  AND deleted_at IS NULL
+                "#,
+            )
+            .bind(&patient_id)
+            .bind(day_start)
+            .bind(&units)
+            .fetch_one(&mut *tx)
+            .await?;
+
+            let device_last_seen = sqlx::query(
+                r#"
+                SELECT device_id, MAX(timestamp) AS last_seen_at
+                FROM sensor_readings
+                WHERE patient_id = $1 AND timestamp >= $2
+                  AND status <> 'entered-in-error'
+                  AND deleted_at IS NULL
+                GROUP BY device_id
+                ORDER BY device_id
+                "#,
+            )
+            .bind(&patient_id)
+            .bind(since)
+            .fetch_all(&mut *tx)
+            .await?
+            .iter()
+            .map(|row| Ok((row.try_get("device_id")?, row.try_get("last_seen_at")?)))
+            .collect::<Result<Vec<_>, sqlx::Error>>()?;
+
+            Ok((
+                DashboardReadings {
+                    last_reading,
+                    hourly_db,
+                    today_twa_db,
+                    device_last_seen,
+                },
+                tx,
+            ))
+        })
+        .await
+        .inspect_err(|e| tracing::error!(error = %e, "Failed to read dashboard data"))
+    }
+
+    /// TWA of each UTC day from `start` to `end` (exclusive) with usable
+    /// decibel readings of the patient, oldest first
+    pub async fn patient_daily_twas(
+        &self,
+        patient_id: &str,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> Result<Vec<DailyExposure>, AppError> {
+        let patient_id = patient_id.to_string();
+        let units: Vec<String> = DECIBEL_UNITS.iter().map(|u| u.to_string()).collect();
+
+        self.execute_with_timeout(QueryKind::Read, |mut tx| async move {
+            let days = sqlx::query(
+                r#"
+                SELECT (timestamp AT TIME ZONE 'UTC')::DATE AS day,
+                       10 * LOG(AVG(POWER(10, value / 10))) AS twa_db
+                FROM sensor_readings
+                WHERE patient_id = $1 AND timestamp >= $2 AND timestamp < $3
+                  AND LOWER(unit) = ANY($4)
+                  AND status <> 'entered-in-error'
+                  AND deleted_at IS NULL
+                GROUP BY day
+                ORDER BY day
+                "#,
+            )
+            .bind(&patient_id)
+            .bind(start)
+            .bind(end)
+            .bind(&units)
+            .fetch_all(&mut *tx)
+            .await?
+            .iter()
+            .map(|row| {
+                Ok(DailyExposure {
+                    date: row.try_get("day")?,
+                    twa_db: row.try_get("twa_db")?,
+                })
+            })
+            .collect::<Result<Vec<_>, sqlx::Error>>()?;
+            Ok((days, tx))
+        })
+        .await
+        .inspect_err(|e| tracing::error!(error = %e, "Failed to compute daily TWAs"))
+    }
+
+    /// The patient's loudest usable decibel reading from `start` to `end`
+    /// (exclusive); the earliest one on a tie
+    pub async fn patient_peak(
+        &self,
+        patient_id: &str,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> Result<Option<PeakReading>, AppError> {
+        let patient_id = patient_id.to_string();
+        let units: Vec<String> = DECIBEL_UNITS.iter().map(|u| u.to_string()).collect();
+
+        self.execute_with_timeout(QueryKind::Read, |mut tx| async move {
+            let row = sqlx::query(
+                r#"
+                SELECT value, timestamp
+                FROM sensor_readings
+                WHERE patient_id = $1 AND timestamp >= $2 AND timestamp < $3
+                  AND LOWER(unit) = ANY($4)
+                  AND status <> 'entered-in-error'
+                  AND deleted_at IS NULL
+                ORDER BY value DESC, timestamp
+                LIMIT 1
+                "#,
+            )
+            .bind(&patient_id)
+            .bind(start)
+            .bind(end)
+            .bind(&units)
+            .fetch_optional(&mut *tx)
+            .await?;
+
+            let peak = match row {
+                Some(row) => Some(PeakReading {
+                    value_db: row.try_get("value")?,
+                    at: row.try_get("timestamp")?,
+                }),
+                None => None,
+            };
+            Ok((peak, tx))
+        })
+        .await
+        .inspect_err(|e| tracing::error!(error = %e, "Failed to fetch peak reading"))
+    }
+
+    /// Advice for weekly summaries, by dose range
+    pub async fn list_recommendations(&self) -> Result<Vec<Recommendation>, AppError> {
+        self.execute_with_timeout(QueryKind::Read, |mut tx| async move {
+            let recommendations = sqlx::query(
+                r#"
+                SELECT min_dose_percent, max_dose_percent, recommendation_text
+                FROM recommendations
+                ORDER BY min_dose_percent
+                "#,
+            )
+            .fetch_all(&mut *tx)
+            .await?
+            .iter()
+            .map(|row| {
+                Ok(Recommendation {
+                    min_dose_percent: row.try_get("min_dose_percent")?,
+                    max_dose_percent: row.try_get("max_dose_percent")?,
+                    text: row.try_get("recommendation_text")?,
+                })
+            })
+            .collect::<Result<Vec<_>, sqlx::Error>>()?;
+            Ok((recommendations, tx))
+        })
+        .await
+        .inspect_err(|e| tracing::error!(error = %e, "Failed to fetch recommendations"))
+    }
+
+    /// Usable decibel readings from `start` to `end` (inclusive) per
+    /// location, skipping locations with fewer than `min_count` readings
+    pub async fn noise_map(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        min_count: usize,
+    ) -> Result<Vec<LocationNoise>, AppError> {
+        let units: Vec<String> = DECIBEL_UNITS.iter().map(|u| u.to_string()).collect();
+
+        self.execute_with_timeout(QueryKind::Read, |mut tx| async move {
+            let rows = sqlx::query(
+                r#"
+                SELECT location, AVG(value) AS avg_db, MAX(value) AS max_db,
+                       COUNT(*) AS count, COUNT(DISTINCT patient_id) AS patient_count
+                FROM sensor_readings
+                WHERE timestamp >= $1 AND timestamp <= $2
+                  AND location IS NOT NULL
+                  AND LOWER(unit) = ANY($3)
+                  AND status <> 'entered-in-error'
+                  AND deleted_at IS NULL
+                GROUP BY location
+                HAVING COUNT(*) >= $4
+                ORDER BY location
+                "#,
+            )
+            .bind(start)
+            .bind(end)
+            .bind(&units)
+            .bind(i64::try_from(min_count).unwrap_or(i64::MAX))
+            .fetch_all(&mut *tx)
+            .await?;
+
+            let map = rows
+                .iter()
+                .map(|row| LocationNoise {
+                    location: row.get("location"),
+                    avg_db: row.get("avg_db"),
+                    max_db: row.get("max_db"),
+                    count: row.get("count"),
+                    patient_count: row.get("patient_count"),
+                })
+                .collect();
+            Ok((map, tx))
+        })
+        .await
+        .inspect_err(|e| tracing::error!(error = %e, "Failed to build noise map"))
+    }
+
+    /// The next midnight in `timezone` (an IANA name); fails for unknown zones
+    pub async fn next_local_midnight(&self, timezone: &str) -> Result<DateTime<Utc>, AppError> {
+        let timezone = timezone.to_string();
+
+        self.execute_with_timeout(QueryKind::Read, |mut tx| async move {
+            let midnight = sqlx::query_scalar::<_, DateTime<Utc>>(
+                "SELECT (date_trunc('day', NOW() AT TIME ZONE $1) + INTERVAL '1 day') AT TIME ZONE $1",
+            )
+            .bind(&timezone)
+            .fetch_one(&mut *tx)
+            .await?;
+            Ok((midnight, tx))
+        })
+        .await
+    }
+
+    /// Complete local days with readings since `since` that have no rollup
+    /// yet, or received readings after their rollup was computed
+    pub async fn stale_rollup_days(
+        &self,
+        timezone: &str,
+        since: DateTime<Utc>,
+    ) -> Result<Vec<NaiveDate>, AppError> {
+        let timezone = timezone.to_string();
+
+        self.execute_with_timeout(QueryKind::Read, |mut tx| async move {
+            let days = sqlx::query_scalar::<_, NaiveDate>(
+                r#"
+                SELECT DISTINCT (r.timestamp AT TIME ZONE $1)::DATE AS day
+                FROM sensor_readings r
+                LEFT JOIN daily_rollups d
+                  ON d.patient_id = r.patient_id AND d.code = r.code
+                 AND d.date = (r.timestamp AT TIME ZONE $1)::DATE
+                WHERE r.timestamp >= $2
+                  AND (r.timestamp AT TIME ZONE $1)::DATE < (NOW() AT TIME ZONE $1)::DATE
+                  AND r.status <> 'entered-in-error'
+                  AND r.deleted_at IS NULL
+                  AND (d.computed_at IS NULL OR r.created_at > d.computed_at)
+                ORDER BY day
+                "#,
+            )
+            .bind(&timezone)
+            .bind(since)
+            .fetch_all(&mut *tx)
+            .await?;
+            Ok((days, tx))
+        })
+        .await
+        .inspect_err(|e| tracing::error!(error = %e, "Failed to find days to roll up"))
+    }
+
+    /// (Re)compute the rollups of every patient for one local day; returns
+    /// the number of rollups written
+    pub async fn upsert_daily_rollups(
+        &self,
+        timezone: &str,
+        date: NaiveDate,
+    ) -> Result<u64, AppError> {
+        let timezone = timezone.to_string();
+        let units: Vec<String> = DECIBEL_UNITS.iter().map(|u| u.to_string()).collect();
+
+        self.execute_with_timeout(QueryKind::Write, |mut tx| async move {
+            // computed_at lags NOW() so readings from transactions still in
+            // flight when this one started count as late and trigger a recompute
+            let result = sqlx::query(
+                r#"
+                INSERT INTO daily_rollups
+                    (patient_id, code, date, count, mean, max, p95, minutes_above_threshold, computed_at)
+                SELECT patient_id, code, $2, COUNT(*), AVG(value), MAX(value),
+                       percentile_cont(0.95) WITHIN GROUP (ORDER BY value),
+                       COUNT(DISTINCT date_trunc('minute', timestamp))
+                           FILTER (WHERE LOWER(unit) = ANY($3) AND value > $4),
+                       NOW() - INTERVAL '1 minute'
+                FROM sensor_readings
+                WHERE timestamp >= $2::TIMESTAMP AT TIME ZONE $1
+                  AND timestamp < ($2 + 1)::TIMESTAMP AT TIME ZONE $1
+                  AND status <> 'entered-in-error'
+                  AND deleted_at IS NULL
+                GROUP BY patient_id, code
+                ON CONFLICT (patient_id, code, date) DO UPDATE
+                SET count = EXCLUDED.count,
+                    mean = EXCLUDED.mean,
+                    max = EXCLUDED.max,
+                    p95 = EXCLUDED.p95,
+                    minutes_above_threshold = EXCLUDED.minutes_above_threshold,
+                    computed_at = EXCLUDED.computed_at
+                "#,
+            )
+            .bind(&timezone)
+            .bind(date)
+            .bind(&units)
+            .bind(NOISE_EXPOSURE_LIMIT_DB)
+            .execute(&mut *tx)
+            .await?;
+            Ok((result.rows_affected(), tx))
+        })
+        .await
+        .inspect_err(|e| tracing::error!(error = %e, %date, "Failed to roll up readings"))
+    }
+
+    /// A patient's rollups from `from` to `to` (inclusive), oldest first
+    pub async fn daily_rollups(
+        &self,
+        patient_id: &str,
+        from: NaiveDate,
+        to: NaiveDate,
+    ) -> Result<Vec<DailyRollup>, AppError> {
+        let patient_id = patient_id.to_string();
+
+        self.execute_with_timeout(QueryKind::Read, |mut tx| async move {
+            let rollups = sqlx::query(
+                r#"
+                SELECT patient_id, code, date, count, mean, max, p95,
+                       minutes_above_threshold, computed_at
+                FROM daily_rollups
+                WHERE patient_id = $1 AND date >= $2 AND date <= $3
+                ORDER BY date, code
+                "#,
+            )
+            .bind(&patient_id)
+            .bind(from)
+            .bind(to)
+            .fetch_all(&mut *tx)
+            .await?
+            .iter()
+            .map(|row| {
+                Ok(DailyRollup {
+                    patient_id: row.try_get("patient_id")?,
+                    code: row.try_get("code")?,
+                    date: row.try_get("date")?,
+                    count: row.try_get("count")?,
+                    mean: row.try_get("mean")?,
+                    max: row.try_get("max")?,
+                    p95: row.try_get("p95")?,
+                    minutes_above_threshold: row.try_get("minutes_above_threshold")?,
+                    computed_at: row.try_get("computed_at")?,
+                })
+            })
+            .collect::<Result<Vec<_>, sqlx::Error>>()?;
+            Ok((rollups, tx))
+        })
+        .await
+        .inspect_err(|e| tracing::error!(error = %e, "Failed to fetch daily rollups"))
+    }
+
+    /// All hearing protectors, weakest first
+    pub async fn list_hearing_protectors(&self) -> Result<Vec<HearingProtector>, AppError> {
+        self.execute_with_timeout(QueryKind::Read, |mut tx| async move {
+            let protectors = sqlx::query_as::<_, HearingProtector>(
+                "SELECT id, name, nrr_db, category FROM hearing_protectors ORDER BY nrr_db, name",
+            )
+            .fetch_all(&mut *tx)
+            .await?;
+            Ok((protectors, tx))
+        })
+        .await
+        .inspect_err(|e| tracing::error!(error = %e, "Failed to list hearing protectors"))
+    }
+
+    pub async fn insert_hearing_protector(
+        &self,
+        protector: &HearingProtector,
+    ) -> Result<(), AppError> {
+        self.execute_with_timeout(QueryKind::Write, |mut tx| async move {
+            sqlx::query(
+                "INSERT INTO hearing_protectors (id, name, nrr_db, category) VALUES ($1, $2, $3, $4)",
+            )
+            .bind(protector.id)
+            .bind(&protector.name)
+            .bind(protector.nrr_db)
+            .bind(&protector.category)
+            .execute(&mut *tx)
+            .await?;
+            Ok(((), tx))
+        })
+        .await
+        .inspect_err(|e| tracing::error!(error = %e, "Failed to insert hearing protector"))
+    }
+
+    /// Replace a protector; returns `false` if there is none with its id
+    pub async fn update_hearing_protector(
+        &self,
+        protector: &HearingProtector,
+    ) -> Result<bool, AppError> {
+        self.execute_with_timeout(QueryKind::Write, |mut tx| async move {
+            let result = sqlx::query(
+                "UPDATE hearing_protectors SET name = $2, nrr_db = $3, category = $4 WHERE id = $1",
+            )
+            .bind(protector.id)
+            .bind(&protector.name)
+            .bind(protector.nrr_db)
+            .bind(&protector.category)
+            .execute(&mut *tx)
+            .await?;
+            Ok((result.rows_affected() == 1, tx))
+        })
+        .await
+        .inspect_err(|e| tracing::error!(error = %e, "Failed to update hearing protector"))
+    }
+
+    /// Delete a protector; returns `false` if there is none with `id`
+    pub async fn delete_hearing_protector(&self, id: Uuid) -> Result<bool, AppError> {
+        self.execute_with_timeout(QueryKind::Write, |mut tx| async move {
+            let result = sqlx::query("DELETE FROM hearing_protectors WHERE id = $1")
+                .bind(id)
+                .execute(&mut *tx)
+                .await?;
+            Ok((result.rows_affected() == 1, tx))
+        })
+        .await
+        .inspect_err(|e| tracing::error!(error = %e, "Failed to delete hearing protector"))
+    }
+
+    /// Values of `device_id`'s usable decibel and raw readings taken from
+    /// `start` to `end` (inclusive)
+    pub async fn device_window_values(
+        &self,
+        device_id: &str,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> Result<WindowValues, AppError> {
+        let device_id = device_id.to_string();
+        let units: Vec<String> = DECIBEL_UNITS.iter().map(|u| u.to_string()).collect();
+
+        self.execute_with_timeout(QueryKind::Read, |mut tx| async move {
+            let rows = sqlx::query(&format!(
+                r#"
+                SELECT value, LOWER(unit) = ANY($4) AS decibel
+                FROM sensor_readings
+                WHERE device_id = $1 AND timestamp >= $2 AND timestamp <= $3
+                  AND (LOWER(unit) = ANY($4) OR {})
+                  AND status <> 'entered-in-error'
+                  AND deleted_at IS NULL
+                ORDER BY timestamp
+                "#,
+                unit_is_sql(RAW_UNIT)
+            ))
+            .bind(&device_id)
+            .bind(start)
+            .bind(end)
+            .bind(&units)
+            .fetch_all(&mut *tx)
+            .await?;
+
+            let mut values = WindowValues::default();
+            for row in &rows {
+                let value: f64 = row.try_get("value")?;
+                if row.try_get("decibel")? {
+                    values.decibel.push(value);
+                } else {
+                    values.raw.push(value);
+                }
+            }
+            Ok((values, tx))
+        })
+        .await
+        .inspect_err(|e| tracing::error!(error = %e, "Failed to fetch device readings"))
+    }
+
+    /// Store a verification and set the device's calibration status to match
+    pub async fn insert_calibration_verification(
+        &self,
+        verification: &CalibrationVerification,
+    ) -> Result<(), AppError> {
+        self.execute_with_timeout(QueryKind::Write, |mut tx| async move {
+            sqlx::query(
+                r#"
+                INSERT INTO calibration_verifications
+                    (device_id, verified_at, reference_db, measured_avg_db, deviation_db,
+                     measured_avg_raw, passed)
+                VALUES ($1, $2, $3, $4, $5, $6, $7)
+                "#,
+            )
+            .bind(&verification.device_id)
+            .bind(verification.verified_at)
+            .bind(verification.reference_db)
+            .bind(verification.measured_avg_db)
+            .bind(verification.deviation_db)
+            .bind(verification.measured_avg_raw)
+            .bind(verification.passed)
+            .execute(&mut *tx)
+            .await?;
+
+            sqlx::query(
+                r#"
+                INSERT INTO device_calibration (device_id, calibration_status, updated_at)
+                VALUES ($1, $2, NOW())
+                ON CONFLICT (device_id)
+                DO UPDATE SET calibration_status = EXCLUDED.calibration_status,
+                              updated_at = EXCLUDED.updated_at
+                "#,
+            )
+            .bind(&verification.device_id)
+            .bind(calibration_status(verification.passed))
+            .execute(&mut *tx)
+            .await?;
+            Ok(((), tx))
+        })
+        .await
+        .inspect_err(|e| tracing::error!(error = %e, "Failed to store calibration verification"))
+    }
+
+    /// Past verifications of a device, newest first
+    pub async fn calibration_verifications(
+        &self,
+        device_id: &str,
+    ) -> Result<Vec<CalibrationVerification>, AppError> {
+        let device_id = device_id.to_string();
+        self.execute_with_timeout(QueryKind::Read, |mut tx| async move {
+            let rows = sqlx::query(
+                r#"
+                SELECT device_id, verified_at, reference_db, measured_avg_db, deviation_db,
+                       measured_avg_raw, passed
+                FROM calibration_verifications
+                WHERE device_id = $1
+                ORDER BY verified_at DESC
+                "#,
+            )
+            .bind(&device_id)
+            .fetch_all(&mut *tx)
+            .await?;
+
+            let verifications = rows
+                .iter()
+                .map(|row| CalibrationVerification {
+                    device_id: row.get("device_id"),
+                    verified_at: row.get("verified_at"),
+                    reference_db: row.get("reference_db"),
+                    measured_avg_db: row.get("measured_avg_db"),
+                    deviation_db: row.get("deviation_db"),
+                    measured_avg_raw: row.get("measured_avg_raw"),
+                    passed: row.get("passed"),
+                })
+                .collect();
+            Ok((verifications, tx))
+        })
+        .await
+        .inspect_err(|e| tracing::error!(error = %e, "Failed to list calibration verifications"))
+    }
+
+    /// Each device's raw output reference from its latest verification
+    /// that has one (see `RawCalibration::latest`)
+    pub async fn raw_calibrations(&self) -> Result<HashMap<String, RawCalibration>, AppError> {
+        self.execute_with_timeout(QueryKind::Read, |mut tx| async move {
+            let rows = sqlx::query(
+                r#"
+                SELECT DISTINCT ON (device_id) device_id, reference_db, measured_avg_raw
+                FROM calibration_verifications
+                WHERE passed AND measured_avg_raw > 0
+                ORDER BY device_id, verified_at DESC
+                "#,
+            )
+            .fetch_all(&mut *tx)
+            .await?;
+
+            let calibrations = rows
+                .iter()
+                .map(|row| {
+                    let calibration = RawCalibration {
+                        reference_db: row.try_get("reference_db")?,
+                        reference_raw: row.try_get("measured_avg_raw")?,
+                    };
+                    Ok((row.try_get("device_id")?, calibration))
+                })
+                .collect::<Result<HashMap<_, _>, sqlx::Error>>()?;
+            Ok((calibrations, tx))
+        })
+        .await
+        .inspect_err(|e| tracing::error!(error = %e, "Failed to read device calibrations"))
+    }
+
+    /// Calibration status recorded by the device's latest verification
+    pub async fn device_calibration_status(
+        &self,
+        device_id: &str,
+    ) -> Result<Option<String>, AppError> {
+        let device_id = device_id.to_string();
+        self.execute_with_timeout(QueryKind::Read, |mut tx| async move {
+            let status = sqlx::query_scalar::<_, String>(
+                "SELECT calibration_status FROM device_calibration WHERE device_id = $1",
+            )
+            .bind(&device_id)
+            .fetch_optional(&mut *tx)
+            .await?;
+            Ok((status, tx))
+        })
+        .await
+        .inspect_err(|e| tracing::error!(error = %e, "Failed to fetch calibration status"))
+    }
+
+    /// Store a device's secret hash, replacing its previous secret and any
+    /// challenge issued under it
+    pub async fn upsert_device_secret(
+        &self,
+        device_id: &str,
+        secret_hash: &str,
+    ) -> Result<(), AppError> {
+        let (device_id, secret_hash) = (device_id.to_string(), secret_hash.to_string());
+        self.execute_with_timeout(QueryKind::Write, |mut tx| async move {
+            sqlx::query(
+                r#"
+                INSERT INTO devices (device_id, device_secret_hash)
+                VALUES ($1, $2)
+                ON CONFLICT (device_id)
+                DO UPDATE SET device_secret_hash = EXCLUDED.device_secret_hash,
+                              secret_rotated_at = NOW()
+                "#,
+            )
+            .bind(&device_id)
+            .bind(&secret_hash)
+            .execute(&mut *tx)
+            .await?;
+
+            sqlx::query("DELETE FROM pending_challenges WHERE device_id = $1")
+                .bind(&device_id)
+                .execute(&mut *tx)
+                .await?;
+            Ok(((), tx))
+        })
+        .await
+        .inspect_err(|e| tracing::error!(error = %e, "Failed to store device secret"))
+    }
+
+    /// Whether `device_id` has a secret
+    pub async fn device_provisioned(&self, device_id: &str) -> Result<bool, AppError> {
+        let device_id = device_id.to_string();
+        self.execute_with_timeout(QueryKind::Read, |mut tx| async move {
+            let provisioned =
+                sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM devices WHERE device_id = $1)")
+                    .bind(&device_id)
+                    .fetch_one(&mut *tx)
+                    .await?;
+            Ok((provisioned, tx))
+        })
+        .await
+        .inspect_err(|e| tracing::error!(error = %e, "Failed to look up device secret"))
+    }
+
+    /// Open `challenge` alongside the device's other open ones, dropping
+    /// expired challenges of any device. Returns false (storing nothing) if
+    /// the device has no secret.
+    pub async fn insert_device_challenge(&self, challenge: &Challenge) -> Result<bool, AppError> {
+        let challenge = challenge.clone();
+        self.execute_with_timeout(QueryKind::Write, |mut tx| async move {
+            sqlx::query("DELETE FROM pending_challenges WHERE expires_at <= NOW()")
+                .execute(&mut *tx)
+                .await?;
+            let stored = sqlx::query(
+                r#"
+                INSERT INTO pending_challenges (device_id, nonce, created_at, expires_at)
+                SELECT device_id, $2, $3, $4 FROM devices WHERE device_id = $1
+                "#,
+            )
+            .bind(&challenge.device_id)
+            .bind(&challenge.nonce)
+            .bind(challenge.created_at)
+            .bind(challenge.expires_at)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected()
+                > 0;
+            Ok((stored, tx))
+        })
+        .await
+        .inspect_err(|e| tracing::error!(error = %e, "Failed to store device challenge"))
+    }
+
+    /// Remove and return the device's open challenge with `nonce`, with the
+    /// device's secret hash, so each challenge can be answered only once
+    pub async fn take_device_challenge(
+        &self,
+        device_id: &str,
+        nonce: &str,
+    ) -> Result<Option<(Challenge, String)>, AppError> {
+        let device_id = device_id.to_string();
+        let nonce = nonce.to_string();
+        self.execute_with_timeout(QueryKind::Write, |mut tx| async move {
+            let row = sqlx::query(
+                r#"
+                WITH taken AS (
+                    DELETE FROM pending_challenges WHERE device_id = $1 AND nonce = $2
+                    RETURNING device_id, nonce, created_at, expires_at
+                )
+                SELECT t.device_id, t.nonce, t.created_at, t.expires_at, d.device_secret_hash
+                FROM taken t JOIN devices d ON d.device_id = t.device_id
+                "#,
+            )
+            .bind(&device_id)
+            .bind(&nonce)
+            .fetch_optional(&mut *tx)
+            .await?;
+
+            let taken = row.map(|row| {
+                (
+                    Challenge {
+                        device_id: row.get("device_id"),
+                        nonce: row.get("nonce"),
+                        created_at: row.get("created_at"),
+                        expires_at: row.get("expires_at"),
+                    },
+                    row.get("device_secret_hash"),
+                )
+            });
+            Ok((taken, tx))
+        })
+        .await
+        .inspect_err(|e| tracing::error!(error = %e, "Failed to take device challenge"))
+    }
+
+    /// Register an IoT device with its factory key hash. Returns false
+    /// (changing nothing) if the device is registered already.
+    pub async fn insert_iot_device(
+        &self,
+        device_id: &str,
+        factory_key_hash: &str,
+    ) -> Result<bool, AppError> {
+        let (device_id, factory_key_hash) = (device_id.to_string(), factory_key_hash.to_string());
+        self.execute_with_timeout(QueryKind::Write, |mut tx| async move {
+            let created = sqlx::query(
+                r#"
+                INSERT INTO iot_devices (device_id, factory_key_hash)
+                VALUES ($1, $2)
+                ON CONFLICT (device_id) DO NOTHING
+                "#,
+            )
+            .bind(&device_id)
+            .bind(&factory_key_hash)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected()
+                > 0;
+            Ok((created, tx))
+        })
+        .await
+        .inspect_err(|e| tracing::error!(error = %e, "Failed to register IoT device"))
+    }
+
+    pub async fn iot_factory_key_hash(&self, device_id: &str) -> Result<Option<String>, AppError> {
+        let device_id = device_id.to_string();
+        self.execute_with_timeout(QueryKind::Read, |mut tx| async move {
+            let hash =
+                sqlx::query_scalar("SELECT factory_key_hash FROM iot_devices WHERE device_id = $1")
+                    .bind(&device_id)
+                    .fetch_optional(&mut *tx)
+                    .await?;
+            Ok((hash, tx))
+        })
+        .await
+        .inspect_err(|e| tracing::error!(error = %e, "Failed to look up IoT device"))
+    }
+
+    /// Record a redeemed IoT nonce. Returns false if it was recorded
+    /// already, i.e. the answer is a replay.
+    pub async fn insert_used_nonce(
+        &self,
+        nonce: &str,
+        device_id: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<bool, AppError> {
+        let (nonce, device_id) = (nonce.to_string(), device_id.to_string());
+        self.execute_with_timeout(QueryKind::Write, |mut tx| async move {
+            let inserted = sqlx::query(
+                r#"
+                INSERT INTO used_nonces (nonce, device_id, expires_at)
+                VALUES ($1, $2, $3)
+                ON CONFLICT (nonce) DO NOTHING
+                "#,
+            )
+            .bind(&nonce)
+            .bind(&device_id)
+            .bind(expires_at)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected()
+                > 0;
+            Ok((inserted, tx))
+        })
+        .await
+        .inspect_err(|e| tracing::error!(error = %e, "Failed to record used nonce"))
+    }
+
+    /// Forget used nonces that expired before `now`
+    pub async fn delete_expired_nonces(&self, now: DateTime<Utc>) -> Result<u64, AppError> {
+        self.execute_with_timeout(QueryKind::Write, |mut tx| async move {
+            let removed = sqlx::query("DELETE FROM used_nonces WHERE expires_at <= $1")
+                .bind(now)
+                .execute(&mut *tx)
+                .await?
+                .rows_affected();
+            Ok((removed, tx))
+        })
+        .await
+        .inspect_err(|e| tracing::error!(error = %e, "Failed to delete expired nonces"))
+    }
+
+    pub async fn insert_suppression_window(
+        &self,
+        window: &SuppressionWindow,
+    ) -> Result<(), AppError> {
+        self.execute_with_timeout(QueryKind::Write, |mut tx| async move {
+            sqlx::query(
+                r#"
+                INSERT INTO suppression_windows
+                    (id, patient_id, device_id, start_at, end_at, reason, created_by)
+                VALUES ($1, $2, $3, $4, $5, $6, $7)
+                "#,
+            )
+            .bind(window.id)
+            .bind(&window.patient_id)
+            .bind(&window.device_id)
+            .bind(window.start_at)
+            .bind(window.end_at)
+            .bind(&window.reason)
+            .bind(&window.created_by)
+            .execute(&mut *tx)
+            .await?;
+            Ok(((), tx))
+        })
+        .await
+        .inspect_err(|e| tracing::error!(error = %e, "Failed to insert suppression window"))
+    }
+
+    /// Every suppression window, earliest start first
+    pub async fn list_suppression_windows(&self) -> Result<Vec<SuppressionWindow>, AppError> {
+        self.execute_with_timeout(QueryKind::Read, |mut tx| async move {
+            let windows = sqlx::query_as::<_, SuppressionWindow>(
+                r#"
+                SELECT id, patient_id, device_id, start_at, end_at, reason, created_by
+                FROM suppression_windows
+                ORDER BY start_at, id
+                "#,
+            )
+            .fetch_all(&mut *tx)
+            .await?;
+            Ok((windows, tx))
+        })
+        .await
+        .inspect_err(|e| tracing::error!(error = %e, "Failed to list suppression windows"))
+    }
+
+    /// Suppression windows covering now, earliest start first
+    pub async fn active_suppression_windows(&self) -> Result<Vec<SuppressionWindow>, AppError> {
+        self.execute_with_timeout(QueryKind::Read, |mut tx| async move {
+            let windows = sqlx::query_as::<_, SuppressionWindow>(
+                r#"
+                SELECT id, patient_id, device_id, start_at, end_at, reason, created_by
+                FROM suppression_windows
+                WHERE NOW() BETWEEN start_at AND end_at
+                ORDER BY start_at, id
+                "#,
+            )
+            .fetch_all(&mut *tx)
+            .await?;
+            Ok((windows, tx))
+        })
+        .await
+        .inspect_err(|e| tracing::error!(error = %e, "Failed to list active suppression windows"))
+    }
+
+    pub async fn suppression_window(
+        &self,
+        id: Uuid,
+    ) -> Result<Option<SuppressionWindow>, AppError> {
+        self.execute_with_timeout(QueryKind::Read, |mut tx| async move {
+            let window = sqlx::query_as::<_, SuppressionWindow>(
+                r#"
+                SELECT id, patient_id, device_id, start_at, end_at, reason, created_by
+                FROM suppression_windows
+                WHERE id = $1
+                "#,
+            )
+            .bind(id)
+            .fetch_optional(&mut *tx)
+            .await?;
+            Ok((window, tx))
+        })
+        .await
+        .inspect_err(|e| tracing::error!(error = %e, "Failed to fetch suppression window"))
+    }
+
+    /// Replace a suppression window; returns `false` if there is none with
+    /// its id
+    pub async fn update_suppression_window(
+        &self,
+        window: &SuppressionWindow,
+    ) -> Result<bool, AppError> {
+        self.execute_with_timeout(QueryKind::Write, |mut tx| async move {
+            let result = sqlx::query(
+                r#"
+                UPDATE suppression_windows
+                SET patient_id = $2, device_id = $3, start_at = $4, end_at = $5, reason = $6
+                WHERE id = $1
+                "#,
+            )
+            .bind(window.id)
+            .bind(&window.patient_id)
+            .bind(&window.device_id)
+            .bind(window.start_at)
+            .bind(window.end_at)
+            .bind(&window.reason)
+            .execute(&mut *tx)
+            .await?;
+            Ok((result.rows_affected() == 1, tx))
+        })
+        .await
+        .inspect_err(|e| tracing::error!(error = %e, "Failed to update suppression window"))
+    }
+
+    /// Delete a suppression window; returns `false` if there is none with `id`
+    pub async fn delete_suppression_window(&self, id: Uuid) -> Result<bool, AppError> {
+        self.execute_with_timeout(QueryKind::Write, |mut tx| async move {
+            let result = sqlx::query("DELETE FROM suppression_windows WHERE id = $1")
+                .bind(id)
+                .execute(&mut *tx)
+                .await?;
+            Ok((result.rows_affected() == 1, tx))
+        })
+        .await
+        .inspect_err(|e| tracing::error!(error = %e, "Failed to delete suppression window"))
+    }
+
+    /// Whether a suppression window of `patient_id` covers now
+    pub async fn patient_suppressed(&self, patient_id: &str) -> Result<bool, AppError> {
+        self.execute_with_timeout(QueryKind::Read, |mut tx| async move {
+            let count: i64 = sqlx::query_scalar(
+                r#"
+                SELECT COUNT(*) FROM suppression_windows
+                WHERE patient_id = $1 AND NOW() BETWEEN start_at AND end_at
+                "#,
+            )
+            .bind(patient_id)
+            .fetch_one(&mut *tx)
+            .await?;
+            Ok((count > 0, tx))
+        })
+        .await
+        .inspect_err(|e| tracing::error!(error = %e, "Failed to check patient suppression"))
+    }
+
+    /// Whether a suppression window naming `device_id` covers now
+    pub async fn device_suppressed(&self, device_id: &str) -> Result<bool, AppError> {
+        self.execute_with_timeout(QueryKind::Read, |mut tx| async move {
+            let count: i64 = sqlx::query_scalar(
+                r#"
+                SELECT COUNT(*) FROM suppression_windows
+                WHERE device_id = $1 AND NOW() BETWEEN start_at AND end_at
+                "#,
+            )
+            .bind(device_id)
+            .fetch_one(&mut *tx)
+            .await?;
+            Ok((count > 0, tx))
+        })
+        .await
+        .inspect_err(|e| tracing::error!(error = %e, "Failed to check device suppression"))
+    }
+
+    /// Delete suppression windows that have ended; returns how many
+    pub async fn delete_expired_suppression_windows(&self) -> Result<u64, AppError> {
+        self.execute_with_timeout(QueryKind::Write, |mut tx| async move {
+            let removed = sqlx::query("DELETE FROM suppression_windows WHERE end_at < NOW()")
+                .execute(&mut *tx)
+                .await?
+                .rows_affected();
+            Ok((removed, tx))
+        })
+        .await
+        .inspect_err(|e| tracing::error!(error = %e, "Failed to delete ended suppression windows"))
+    }
+
+    /// Insert or replace a device's monitoring settings
+    pub async fn upsert_device_settings(&self, settings: &DeviceSettings) -> Result<(), AppError> {
+        let settings = settings.clone();
+        self.execute_with_timeout(QueryKind::Write, |mut tx| async move {
+            sqlx::query(
+                r#"
+                INSERT INTO device_settings
+                    (device_id, status, offline_grace_secs, decimation_interval_ms,
+                     scale_min, scale_max, target_min, target_max, updated_at)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+                ON CONFLICT (device_id) DO UPDATE
+                SET status = EXCLUDED.status,
+                    offline_grace_secs = EXCLUDED.offline_grace_secs,
+                    decimation_interval_ms = EXCLUDED.decimation_interval_ms,
+                    scale_min = EXCLUDED.scale_min,
+                    scale_max = EXCLUDED.scale_max,
+                    target_min = EXCLUDED.target_min,
+                    target_max = EXCLUDED.target_max,
+                    updated_at = EXCLUDED.updated_at
+                "#,
+            )
+            .bind(&settings.device_id)
+            .bind(settings.status.as_str())
+            .bind(settings.offline_grace_secs.map(|s| s as i64))
+            .bind(settings.decimation_interval_ms.map(|ms| ms as i64))
+            .bind(settings.scale_min)
+            .bind(settings.scale_max)
+            .bind(settings.target_min)
+            .bind(settings.target_max)
+            .bind(settings.updated_at)
+            .execute(&mut *tx)
+            .await?;
+            Ok(((), tx))
+        })
+        .await
+        .inspect_err(|e| tracing::error!(error = %e, "Failed to store device settings"))
+    }
+
+    pub async fn list_device_settings(&self) -> Result<Vec<DeviceSettings>, AppError> {
+        self.execute_with_timeout(QueryKind::Read, |mut tx| async move {
+            let settings = sqlx::query(
+                r#"
+                SELECT device_id, status, offline_grace_secs, decimation_interval_ms,
+                       scale_min, scale_max, target_min, target_max, updated_at
+                FROM device_settings
+                "#,
+            )
+            .fetch_all(&mut *tx)
+            .await?
+            .iter()
+            .map(device_settings)
+            .collect::<Result<Vec<_>, sqlx::Error>>()?;
+            Ok((settings, tx))
+        })
+        .await
+        .inspect_err(|e| tracing::error!(error = %e, "Failed to fetch device settings"))
+    }
+
+    /// Settings of `device_id`, if it has any
+    pub async fn get_device_settings(
+        &self,
+        device_id: &str,
+    ) -> Result<Option<DeviceSettings>, AppError> {
+        let device_id = device_id.to_string();
+        self.execute_with_timeout(QueryKind::Read, |mut tx| async move {
+            let settings = sqlx::query(
+                r#"
+                SELECT device_id, status, offline_grace_secs, decimation_interval_ms,
+                       scale_min, scale_max, target_min, target_max, updated_at
+                FROM device_settings
+                WHERE device_id = $1
+                "#,
+            )
+            .bind(&device_id)
+            .fetch_optional(&mut *tx)
+            .await?
+            .as_ref()
+            .map(device_settings)
+            .transpose()?;
+            Ok((settings, tx))
+        })
+        .await
+        .inspect_err(|e| tracing::error!(error = %e, "Failed to fetch device settings"))
+    }
+
+    /// Energy-averaged octave spectrum of a patient's readings on one UTC day
+    pub async fn octave_spectrum(
+        &self,
+        patient_id: &str,
+        date: NaiveDate,
+    ) -> Result<OctaveSpectrum, AppError> {
+        let start = date.and_hms_opt(0, 0, 0).unwrap_or_default().and_utc();
+        let end = start + chrono::Duration::days(1);
+        let patient_id = patient_id.to_string();
+
+        self.execute_with_timeout(QueryKind::Read, |mut tx| async move {
+            let row = sqlx::query(
+                r#"
+                SELECT
+                    COUNT(*) AS reading_count,
+                    10 * LOG(AVG(POWER(10, band_63hz / 10))) AS band_63hz,
+                    10 * LOG(AVG(POWER(10, band_125hz / 10))) AS band_125hz,
+                    10 * LOG(AVG(POWER(10, band_250hz / 10))) AS band_250hz,
+                    10 * LOG(AVG(POWER(10, band_500hz / 10))) AS band_500hz,
+                    10 * LOG(AVG(POWER(10, band_1khz / 10))) AS band_1khz,
+                    10 * LOG(AVG(POWER(10, band_2khz / 10))) AS band_2khz,
+                    10 * LOG(AVG(POWER(10, band_4khz / 10))) AS band_4khz,
+                    10 * LOG(AVG(POWER(10, band_8khz / 10))) AS band_8khz,
+                    10 * LOG(AVG(POWER(10, overall_db / 10))) AS overall_db
+                FROM octave_band_readings
+                WHERE patient_id = $1 AND timestamp >= $2 AND timestamp < $3
+                "#,
+            )
+            .bind(&patient_id)
+            .bind(start)
+            .bind(end)
+            .fetch_one(&mut *tx)
+            .await?;
+
+            let mut levels = [None; OCTAVE_BAND_COUNT];
+            for (level, column) in levels.iter_mut().zip(OCTAVE_BAND_COLUMNS) {
+                *level = row.try_get(column)?;
+            }
+            let spectrum = OctaveSpectrum::new(
+                patient_id,
+                date,
+                row.try_get("reading_count")?,
+                levels,
+                row.try_get("overall_db")?,
+            );
+            Ok((spectrum, tx))
+        })
+        .await
+        .inspect_err(|e| tracing::error!(error = %e, "Failed to compute octave spectrum"))
+    }
+
+    /// Insert a new training job; returns `false` if another job is already
+    /// in progress
+    pub async fn insert_training_job(&self, job: &TrainingJob) -> Result<bool, AppError> {
+        self.execute_with_timeout(QueryKind::Write, |mut tx| async move {
+            let result = sqlx::query(
+                r#"
+                INSERT INTO training_jobs (job_id, started_at, status, progress_percent, message)
+                VALUES ($1, $2, $3, $4, $5)
+                ON CONFLICT DO NOTHING
+                "#,
+            )
+            .bind(job.job_id)
+            .bind(job.started_at)
+            .bind(job.status.as_str())
+            .bind(job.progress_percent)
+            .bind(&job.message)
+            .execute(&mut *tx)
+            .await?;
+            Ok((result.rows_affected() == 1, tx))
+        })
+        .await
+        .inspect_err(|e| tracing::error!(error = %e, "Failed to insert training job"))
+    }
+
+    /// Store a job's latest status and progress
+    pub async fn update_training_job(&self, job: &TrainingJob) -> Result<(), AppError> {
+        self.execute_with_timeout(QueryKind::Write, |mut tx| async move {
+            sqlx::query(
+                r#"
+                UPDATE training_jobs
+                SET status = $2, progress_percent = $3, message = $4, updated_at = NOW()
+                WHERE job_id = $1
+                "#,
+            )
+            .bind(job.job_id)
+            .bind(job.status.as_str())
+            .bind(job.progress_percent)
+            .bind(&job.message)
+            .execute(&mut *tx)
+            .await?;
+            Ok(((), tx))
+        })
+        .await
+        .inspect_err(|e| tracing::error!(error = %e, "Failed to update training job"))
+    }
+
+    /// Get a training job by id
+    pub async fn get_training_job(&self, job_id: Uuid) -> Result<Option<TrainingJob>, AppError> {
+        self.execute_with_timeout(QueryKind::Read, |mut tx| async move {
+            let job = sqlx::query_as::<_, TrainingJob>(
+                r#"
+                SELECT job_id, started_at, status, progress_percent, message
+                FROM training_jobs
+                WHERE job_id = $1
+                "#,
+            )
+            .bind(job_id)
+            .fetch_optional(&mut *tx)
+            .await?;
+            Ok((job, tx))
+        })
+        .await
+        .inspect_err(|e| tracing::error!(error = %e, "Failed to fetch training job"))
+    }
+
+    /// Most recent training jobs, newest first
+    pub async fn list_training_jobs(&self, limit: i64) -> Result<Vec<TrainingJob>, AppError> {
+        self.execute_with_timeout(QueryKind::Read, |mut tx| async move {
+            let jobs = sqlx::query_as::<_, TrainingJob>(
+                r#"
+                SELECT job_id, started_at, status, progress_percent, message
+                FROM training_jobs
+                ORDER BY started_at DESC
+                LIMIT $1
+                "#,
+            )
+            .bind(limit)
+            .fetch_all(&mut *tx)
+            .await?;
+            Ok((jobs, tx))
+        })
+        .await
+        .inspect_err(|e| tracing::error!(error = %e, "Failed to list training jobs"))
+    }
+
+    /// Mark every in-progress job failed, returning how many were updated
+    pub async fn fail_in_progress_training_jobs(&self, message: &str) -> Result<u64, AppError> {
+        self.execute_with_timeout(QueryKind::Write, |mut tx| async move {
+            let result = sqlx::query(
+                r#"
+                UPDATE training_jobs
+                SET status = 'failed', message = $1, updated_at = NOW()
+                WHERE status = 'in_progress'
+                "#,
+            )
+            .bind(message)
+            .execute(&mut *tx)
+            .await?;
+            Ok((result.rows_affected(), tx))
+        })
+        .await
+        .inspect_err(|e| tracing::error!(error = %e, "Failed to fail interrupted training jobs"))
+    }
+
+    /// Record a newly started export job
+    pub async fn insert_export_job(&self, job: &ExportJob) -> Result<(), AppError> {
+        self.execute_with_timeout(QueryKind::Write, |mut tx| async move {
+            sqlx::query(
+                r#"
+                INSERT INTO export_jobs
+                    (job_id, owner, role, patients, since, request, transaction_time, status)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+                "#,
+            )
+            .bind(job.job_id)
+            .bind(&job.owner)
+            .bind(&job.role)
+            .bind(&job.patients)
+            .bind(job.since)
+            .bind(&job.request)
+            .bind(job.transaction_time)
+            .bind(job.status.as_str())
+            .execute(&mut *tx)
+            .await?;
+            Ok(((), tx))
+        })
+        .await
+        .inspect_err(|e| tracing::error!(error = %e, "Failed to insert export job"))
+    }
+
+    /// Store an export job's status and output
+    pub async fn update_export_job(&self, job: &ExportJob) -> Result<(), AppError> {
+        let file_counts: Vec<i64> = job.files.iter().map(|n| *n as i64).collect();
+        let file_counts = &file_counts;
+        self.execute_with_timeout(QueryKind::Write, |mut tx| async move {
+            sqlx::query(
+                r#"
+                UPDATE export_jobs
+                SET status = $2, exported = $3, file_counts = $4, error = $5, updated_at = NOW()
+                WHERE job_id = $1
+                "#,
+            )
+            .bind(job.job_id)
+            .bind(job.status.as_str())
+            .bind(job.exported as i64)
+            .bind(file_counts)
+            .bind(&job.error)
+            .execute(&mut *tx)
+            .await?;
+            Ok(((), tx))
+        })
+        .await
+        .inspect_err(|e| tracing::error!(error = %e, "Failed to update export job"))
+    }
+
+    /// Mark every in-progress export job failed, returning how many were
+    /// updated
+    pub async fn fail_in_progress_export_jobs(&self, message: &str) -> Result<u64, AppError> {
+        self.execute_with_timeout(QueryKind::Write, |mut tx| async move {
+            let result = sqlx::query(
+                r#"
+                UPDATE export_jobs
+                SET status = 'failed', error = $1, updated_at = NOW()
+                WHERE status = 'in_progress'
+                "#,
+            )
+            .bind(message)
+            .execute(&mut *tx)
+            .await?;
+            Ok((result.rows_affected(), tx))
+        })
+        .await
+        .inspect_err(|e| tracing::error!(error = %e, "Failed to fail interrupted export jobs"))
+    }
+
+    /// Audit and reading aggregates of the month from `start` to `end`, all
+    /// from one snapshot
+    pub async fn compliance_evidence(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> Result<ComplianceEvidence, AppError> {
+        self.execute_with_timeout(QueryKind::Report, |mut tx| async move {
+            sqlx::query("SET TRANSACTION ISOLATION LEVEL REPEATABLE READ, READ ONLY")
+                .execute(&mut *tx)
+                .await?;
+            let row = sqlx::query(
+                r#"
+                WITH audit AS (
+                    SELECT * FROM audit_logs WHERE timestamp >= $1 AND timestamp < $2
+                )
+                SELECT
+                    (SELECT COUNT(*) FROM sensor_readings
+                     WHERE timestamp >= $1 AND timestamp < $2) AS total_observations,
+                    (SELECT COUNT(DISTINCT patient_id) FROM audit
+                     WHERE action = 'READ') AS unique_patients_accessed,
+                    (SELECT COUNT(DISTINCT user_id) FROM audit
+                     WHERE action <> 'ACCESS_DENIED'
+                       AND user_role IS DISTINCT FROM 'device') AS unique_users,
+                    (SELECT COUNT(*) FROM audit
+                     WHERE action = 'ACCESS_DENIED') AS failed_auth_count,
+                    (SELECT COUNT(*) FROM training_jobs
+                     WHERE started_at >= $1 AND started_at < $2) AS ml_trainings,
+                    (SELECT COUNT(*) FROM audit
+                     WHERE resource_type = 'BulkExport') AS exports_performed,
+                    (SELECT COUNT(*) FROM audit
+                     WHERE action = 'CREATE'
+                       AND resource_type IN ('Anomaly', 'DeviceAlert')) AS alerts_fired
+                "#,
+            )
+            .bind(start)
+            .bind(end)
+            .fetch_one(&mut *tx)
+            .await?;
+
+            let denied = sqlx::query_as::<_, DeniedAccesses>(
+                r#"
+                SELECT user_id, (timestamp AT TIME ZONE 'UTC')::DATE AS day, COUNT(*) AS count
+                FROM audit_logs
+                WHERE timestamp >= $1 AND timestamp < $2
+                  AND action = 'ACCESS_DENIED' AND user_id IS NOT NULL
+                GROUP BY 1, 2
+                "#,
+            )
+            .bind(start)
+            .bind(end)
+            .fetch_all(&mut *tx)
+            .await?;
+
+            let devices = sqlx::query_as::<_, DeviceReadings>(
+                r#"
+                SELECT r.device_id, COUNT(*) AS readings,
+                       EXISTS (SELECT 1 FROM devices d WHERE d.device_id = r.device_id)
+                       OR EXISTS (SELECT 1 FROM iot_devices i WHERE i.device_id = r.device_id)
+                       OR EXISTS (SELECT 1 FROM device_settings s WHERE s.device_id = r.device_id)
+                       AS registered
+                FROM sensor_readings r
+                WHERE r.timestamp >= $1 AND r.timestamp < $2
+                GROUP BY r.device_id
+                "#,
+            )
+            .bind(start)
+            .bind(end)
+            .fetch_all(&mut *tx)
+            .await?;
+
+            // At most one row per minute, however busy the month was
+            let audit_minutes = sqlx::query_scalar::<_, DateTime<Utc>>(
+                r#"
+                SELECT DISTINCT date_trunc('minute', timestamp)
+                FROM audit_logs
+                WHERE timestamp >= $1 AND timestamp < $2
+                ORDER BY 1
+                "#,
+            )
+            .bind(start)
+            .bind(end)
+            .fetch_all(&mut *tx)
+            .await?;
+
+            let evidence = ComplianceEvidence {
+                total_observations: row.get("total_observations"),
+                unique_patients_accessed: row.get("unique_patients_accessed"),
+                unique_users: row.get("unique_users"),
+                failed_auth_count: row.get("failed_auth_count"),
+                ml_trainings: row.get("ml_trainings"),
+                exports_performed: row.get("exports_performed"),
+                alerts_fired: row.get("alerts_fired"),
+                denied,
+                devices,
+                audit_minutes,
+            };
+            Ok((evidence, tx))
+        })
+        .await
+        .inspect_err(|e| tracing::error!(error = %e, "Failed to gather compliance evidence"))
+    }
+
+    /// The stored report of the month starting on `month`
+    pub async fn compliance_report(
+        &self,
+        month: NaiveDate,
+    ) -> Result<Option<ComplianceReport>, AppError> {
+        let report = self
+            .execute_with_timeout(QueryKind::Read, |mut tx| async move {
+                let report = sqlx::query_scalar::<_, String>(
+                    "SELECT report::TEXT FROM compliance_reports WHERE month = $1",
+                )
+                .bind(month)
+                .fetch_optional(&mut *tx)
+                .await?;
+                Ok((report, tx))
+            })
+            .await
+            .inspect_err(|e| tracing::error!(error = %e, "Failed to load compliance report"))?;
+
+        report
+            .map(|r| serde_json::from_str(&r))
+            .transpose()
+            .map_err(|e| {
+                tracing::error!(error = %e, %month, "Stored compliance report is unreadable");
+                AppError::Internal
+            })
+    }
+
+    /// Store the report of the month starting on `month`, unless one is
+    /// stored already
+    pub async fn insert_compliance_report(
+        &self,
+        month: NaiveDate,
+        report: &ComplianceReport,
+    ) -> Result<(), AppError> {
+        let payload = serde_json::to_string(report).map_err(|_| AppError::Internal)?;
+        self.execute_with_timeout(QueryKind::Write, |mut tx| async move {
+            sqlx::query(
+                r#"
+                INSERT INTO compliance_reports (month, report, generated_at)
+                VALUES ($1, $2::JSONB, $3)
+                ON CONFLICT (month) DO NOTHING
+                "#,
+            )
+            .bind(month)
+            .bind(&payload)
+            .bind(report.generated_at)
+            .execute(&mut *tx)
+            .await?;
+            Ok(((), tx))
+        })
+        .await
+        .inspect_err(|e| tracing::error!(error = %e, "Failed to store compliance report"))
+    }
+
+    /// Health check - verify database connection is alive
+    pub async fn health_check(&self) -> Result<(), AppError> {
+        with_deadline(QueryKind::Read, self.read_timeout_ms, async {
+            sqlx::query("SELECT 1").fetch_one(&self.pool).await
+        })
+        .await
+        .inspect_err(|e| tracing::error!(error = %e, "Database health check failed"))?;
+
+        Ok(())
+    }
+}
+
+/// Whether an error means the query (or pool acquisition) timed out
+fn is_timeout_error(e: &sqlx::Error) -> bool {
+    match e {
+        sqlx::Error::PoolTimedOut => true,
+        sqlx::Error::Database(db_err) => {
+            // 57014 = query_canceled, raised by statement_timeout
+            db_err.code().as_deref() == Some("57014")
+                || db_err.message().contains("statement timeout")
+        }
+        _ => false,
+    }
+}
+
+/// Whether an error means the database could not be reached or dropped the
+/// connection, so the same statement may well succeed a moment later
+fn is_connection_error(e: &sqlx::Error) -> bool {
+    match e {
+        sqlx::Error::Io(_)
+        | sqlx::Error::Tls(_)
+        | sqlx::Error::PoolClosed
+        | sqlx::Error::WorkerCrashed => true,
+        sqlx::Error::Database(db_err) => db_err.code().is_some_and(|code| {
+            // Class 08 = connection exception; 57P01-03 = server shutting
+            // down or starting up; 40001/40P01 = serialization failure and
+            // deadlock, which are retried by design
+            code.starts_with("08")
+                || matches!(&*code, "57P01" | "57P02" | "57P03" | "40001" | "40P01")
+        }),
+        _ => false,
+    }
+}
+
+/// Bound a database future by a client-side deadline (matching the
+/// server-side statement timeout) and map timeouts to `AppError::Timeout`
+/// and lost connections to `AppError::ServiceUnavailable`.
+pub async fn with_deadline<T, Fut>(
+    kind: QueryKind,
+    timeout_ms: u64,
+    fut: Fut,
+) -> Result<T, AppError>
+where
+    Fut: Future<Output = Result<T, sqlx::Error>>,
+{
+    match tokio::time::timeout(Duration::from_millis(timeout_ms), fut).await {
+        Ok(Ok(value)) => Ok(value),
+        Ok(Err(e)) if is_timeout_error(&e) => {
+            tracing::warn!(query_type = %kind, timeout_ms, error = %e, "Database query timed out");
+            Err(AppError::Timeout)
+        }
+        Ok(Err(e)) if is_connection_error(&e) => {
+            tracing::warn!(query_type = %kind, error = %e, "Database unavailable");
+            Err(AppError::ServiceUnavailable("database unavailable".into()))
+        }
+        Ok(Err(e)) => {
+            tracing::debug!(query_type = %kind, error = %e, "Database query failed");
+            Err(AppError::Internal)
+        }
+        Err(_) => {
+            tracing::warn!(query_type = %kind, timeout_ms, "Database query exceeded deadline");
+            Err(AppError::Timeout)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_slow_query_returns_timeout() {
+        let started = tokio::time::Instant::now();
+
+        // A "query" that would run for six seconds
+        let result: Result<(), AppError> = with_deadline(QueryKind::Read, 5000, async {
+            tokio::time::sleep(Duration::from_secs(6)).await;
+            Ok(())
+        })
+        .await;
+
+        assert!(matches!(result, Err(AppError::Timeout)));
+        assert!(started.elapsed() <= Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn test_error_mapping() {
+        let result: Result<(), AppError> = with_deadline(QueryKind::Write, 2000, async {
+            Err(sqlx::Error::PoolTimedOut)
+        })
+        .await;
+        assert!(matches!(result, Err(AppError::Timeout)));
+
+        let result: Result<(), AppError> = with_deadline(QueryKind::Write, 2000, async {
+            Err(sqlx::Error::RowNotFound)
+        })
+        .await;
+        assert!(matches!(result, Err(AppError::Internal)));
+
+        let result: Result<(), AppError> = with_deadline(QueryKind::Write, 2000, async {
+            Err(sqlx::Error::Io(
+                std::io::ErrorKind::ConnectionRefused.into(),
+            ))
+        })
+        .await;
+        assert!(matches!(result, Err(AppError::ServiceUnavailable(_))));
+
+        let result = with_deadline(QueryKind::Read, 2000, async { Ok(7) }).await;
+        assert!(matches!(result, Ok(7)));
     }
 }
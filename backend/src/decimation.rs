@@ -0,0 +1,146 @@
+/// Server-Side Decimation
+///
+/// A device stuck in a tight loop can send hundreds of readings a second,
+/// filling storage with near-duplicates. With an interval configured, only
+/// the first reading of each device and signal code in every interval is
+/// kept; the rest are dropped and counted against the device. Intervals are
+/// measured on the readings' own timestamps, so a burst replayed later is
+/// thinned the same way as it would have been live.
+use chrono::{DateTime, Duration, Utc};
+use std::collections::HashMap;
+
+use crate::domain::models::SensorReading;
+
+/// Decides which readings to keep, per (device, code)
+#[derive(Debug, Default)]
+pub struct Decimator {
+    /// Interval of devices without their own; `None` keeps everything
+    default_interval_ms: Option<u64>,
+    /// Per-device intervals; 0 turns decimation off for the device
+    overrides: HashMap<String, u64>,
+    /// Timestamp of the last reading kept per device and code
+    last_kept: HashMap<(String, &'static str), DateTime<Utc>>,
+}
+
+impl Decimator {
+    /// Keep at most one reading per `default_interval_ms` of every device
+    /// without an interval of its own; `None` or 0 keeps every reading
+    pub fn new(default_interval_ms: Option<u64>) -> Self {
+        Self {
+            default_interval_ms: default_interval_ms.filter(|ms| *ms > 0),
+            ..Default::default()
+        }
+    }
+
+    /// Default interval from `DECIMATION_INTERVAL_MS` (unset or 0: off)
+    pub fn from_env() -> Self {
+        Self::new(
+            std::env::var("DECIMATION_INTERVAL_MS")
+                .ok()
+                .and_then(|v| v.parse::<u64>().ok()),
+        )
+    }
+
+    /// Give `device_id` its own interval (0 for none), or with `None` let
+    /// it use the default again
+    pub fn set_interval(&mut self, device_id: &str, interval_ms: Option<u64>) {
+        match interval_ms {
+            Some(ms) => {
+                self.overrides.insert(device_id.to_string(), ms);
+            }
+            None => {
+                self.overrides.remove(device_id);
+            }
+        }
+    }
+
+    /// Interval applied to `device_id`'s readings, if any
+    pub fn interval_ms(&self, device_id: &str) -> Option<u64> {
+        match self.overrides.get(device_id) {
+            Some(0) => None,
+            Some(ms) => Some(*ms),
+            None => self.default_interval_ms,
+        }
+    }
+
+    /// Whether to keep `reading`: the first of its device and code, or one
+    /// at least an interval away from the last one kept
+    pub fn keep(&mut self, reading: &SensorReading) -> bool {
+        let Some(interval_ms) = self.interval_ms(&reading.device_id) else {
+            return true;
+        };
+        let interval = Duration::milliseconds(interval_ms.min(i64::MAX as u64) as i64);
+
+        let key = (reading.device_id.clone(), reading.code.as_str());
+        if let Some(last) = self.last_kept.get(&key) {
+            // Either direction: a late reading is as redundant as an early one
+            if (reading.ts - *last).abs() < interval {
+                return false;
+            }
+        }
+        self.last_kept.insert(key, reading.ts);
+        true
+    }
+
+    /// Forget `device_id`, its own interval included
+    pub fn forget(&mut self, device_id: &str) {
+        self.overrides.remove(device_id);
+        self.last_kept.retain(|(device, _), _| device != device_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::models::SignalCode;
+    use chrono::TimeZone;
+
+    fn reading(device_id: &str, ts: DateTime<Utc>) -> SensorReading {
+        SensorReading {
+            patient_id: "p1".into(),
+            device_id: device_id.into(),
+            code: SignalCode::Sound,
+            value: 50.0,
+            unit: "dB".into(),
+            ts,
+            ts_source: Default::default(),
+            metadata: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_keeps_one_reading_per_interval_per_device() {
+        let mut decimator = Decimator::new(Some(1000));
+        let t0 = Utc.with_ymd_and_hms(2026, 3, 1, 12, 0, 0).unwrap();
+        let at = |ms| t0 + Duration::milliseconds(ms);
+
+        let kept: Vec<i64> = (0..25)
+            .map(|i| i * 100)
+            .filter(|ms| decimator.keep(&reading("d1", at(*ms))))
+            .collect();
+        assert_eq!(kept, [0, 1000, 2000]);
+
+        // Other devices are thinned separately
+        assert!(decimator.keep(&reading("d2", at(2100))));
+    }
+
+    #[test]
+    fn test_device_intervals_override_the_default() {
+        let mut decimator = Decimator::new(None);
+        let t0 = Utc.with_ymd_and_hms(2026, 3, 1, 12, 0, 0).unwrap();
+        assert!(decimator.keep(&reading("d1", t0)));
+        assert!(decimator.keep(&reading("d1", t0)));
+
+        decimator.set_interval("d1", Some(500));
+        assert_eq!(decimator.interval_ms("d1"), Some(500));
+        assert!(decimator.keep(&reading("d1", t0)));
+        assert!(!decimator.keep(&reading("d1", t0 + Duration::milliseconds(499))));
+
+        let mut decimator = Decimator::new(Some(500));
+        decimator.set_interval("d1", Some(0));
+        assert_eq!(decimator.interval_ms("d1"), None);
+        assert_eq!(decimator.interval_ms("d2"), Some(500));
+        decimator.set_interval("d1", None);
+        assert_eq!(decimator.interval_ms("d1"), Some(500));
+    }
+}
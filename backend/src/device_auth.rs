@@ -0,0 +1,312 @@
+/// Device Challenge-Response Onboarding
+///
+/// Each device is provisioned once with its own random secret. To get a
+/// token it asks for a challenge, a 32-byte random nonce valid for 60
+/// seconds, and answers with the nonce and `HMAC-SHA256(device_secret, nonce)`
+/// over the raw nonce bytes, both hex-encoded. A challenge can be answered
+/// once: it is removed when the answer arrives, right or wrong, so a captured
+/// answer cannot be replayed.
+///
+/// Challenges are kept by nonce, so a device can have several open and
+/// nobody else asking in its name can replace the one it is answering.
+/// Devices without a secret get a challenge too, one that is never stored
+/// and so never redeemed, so asking does not reveal which devices exist.
+///
+/// Only the SHA-256 of a secret is stored. Secrets are longer than the
+/// 64-byte HMAC block, and HMAC first hashes such keys with SHA-256, so the
+/// stored hash is the key HMAC actually uses. That keeps the secret itself
+/// out of the database, though anyone who reads the hash can still answer
+/// challenges, so treat it as a credential.
+use chrono::{DateTime, Duration, Utc};
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use tokio::sync::RwLock;
+use utoipa::ToSchema;
+
+use crate::db::Database;
+use crate::errors::AppError;
+
+/// How long a challenge can be answered
+pub const CHALLENGE_TTL: Duration = Duration::seconds(60);
+
+/// Random bytes in a challenge nonce
+const NONCE_BYTES: usize = 32;
+
+/// Random bytes in a device secret. Hex-encoded, it is 96 characters, longer
+/// than the 64-byte HMAC block, which is what makes the stored hash usable
+/// as the HMAC key.
+const SECRET_BYTES: usize = 48;
+
+/// A nonce a device must sign to get a token
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Challenge {
+    pub device_id: String,
+    /// 32 random bytes, hex-encoded
+    pub nonce: String,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl Challenge {
+    fn new(device_id: &str, ttl: Duration) -> Self {
+        let mut nonce = [0u8; NONCE_BYTES];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let created_at = Utc::now();
+        Self {
+            device_id: device_id.to_string(),
+            nonce: hex::encode(nonce),
+            created_at,
+            expires_at: created_at + ttl,
+        }
+    }
+}
+
+/// A fresh device secret: 48 random bytes, hex-encoded
+pub fn generate_device_secret() -> String {
+    let mut secret = [0u8; SECRET_BYTES];
+    rand::thread_rng().fill_bytes(&mut secret);
+    hex::encode(secret)
+}
+
+/// Hex SHA-256 of a device secret, as stored in `devices.device_secret_hash`
+pub fn secret_hash(secret: &str) -> String {
+    hex::encode(Sha256::digest(secret.as_bytes()))
+}
+
+/// Whether `response` (hex) is the HMAC of `nonce` (hex) under the secret
+/// whose hash is `secret_hash`. Compares in constant time.
+pub fn verify_response(secret_hash: &str, nonce: &str, response: &str) -> bool {
+    let (Ok(key), Ok(nonce), Ok(response)) = (
+        hex::decode(secret_hash),
+        hex::decode(nonce),
+        hex::decode(response),
+    ) else {
+        return false;
+    };
+    let mut mac = Hmac::<Sha256>::new_from_slice(&key).expect("HMAC takes keys of any length");
+    mac.update(&nonce);
+    mac.verify_slice(&response).is_ok()
+}
+
+/// Device secrets and open challenges, in the database or (without one) in
+/// memory
+#[derive(Debug)]
+pub struct DeviceAuthStore {
+    db: Option<Database>,
+    /// Secret hash per device, without a database
+    secrets: RwLock<HashMap<String, String>>,
+    /// Open challenges by nonce, without a database
+    challenges: RwLock<HashMap<String, Challenge>>,
+    ttl: Duration,
+}
+
+impl DeviceAuthStore {
+    pub fn new(db: Option<Database>) -> Self {
+        Self {
+            db,
+            secrets: RwLock::new(HashMap::new()),
+            challenges: RwLock::new(HashMap::new()),
+            ttl: CHALLENGE_TTL,
+        }
+    }
+
+    /// Let challenges be answered for `ttl` instead of 60 seconds
+    pub fn with_challenge_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Give `device_id` a new secret, replacing any previous one, and return
+    /// it. This is the only time the secret is available.
+    pub async fn provision(&self, device_id: &str) -> Result<String, AppError> {
+        let secret = generate_device_secret();
+        let hash = secret_hash(&secret);
+        if let Some(db) = &self.db {
+            db.upsert_device_secret(device_id, &hash).await?;
+        } else {
+            self.secrets
+                .write()
+                .await
+                .insert(device_id.to_string(), hash);
+            self.challenges
+                .write()
+                .await
+                .retain(|_, challenge| challenge.device_id != device_id);
+        }
+        Ok(secret)
+    }
+
+    /// Whether `device_id` has been given a secret
+    pub async fn is_provisioned(&self, device_id: &str) -> Result<bool, AppError> {
+        match &self.db {
+            Some(db) => db.device_provisioned(device_id).await,
+            None => Ok(self.secrets.read().await.contains_key(device_id)),
+        }
+    }
+
+    /// Open a challenge for `device_id`, alongside any open ones. A device
+    /// without a secret gets one as well, but it is not stored.
+    pub async fn challenge(&self, device_id: &str) -> Result<Challenge, AppError> {
+        let challenge = Challenge::new(device_id, self.ttl);
+        let known = if let Some(db) = &self.db {
+            db.insert_device_challenge(&challenge).await?
+        } else if self.secrets.read().await.contains_key(device_id) {
+            let mut challenges = self.challenges.write().await;
+            let now = Utc::now();
+            challenges.retain(|_, open| open.expires_at > now);
+            challenges.insert(challenge.nonce.clone(), challenge.clone());
+            true
+        } else {
+            false
+        };
+
+        if !known {
+            tracing::debug!("Challenge requested for unprovisioned device {}", device_id);
+        }
+        Ok(challenge)
+    }
+
+    /// Check a device's answer to its open challenge with `nonce`. The
+    /// challenge is used up either way; a missing, expired or wrong answer is
+    /// `Unauthorized`.
+    pub async fn redeem(
+        &self,
+        device_id: &str,
+        nonce: &str,
+        response: &str,
+    ) -> Result<(), AppError> {
+        let taken = if let Some(db) = &self.db {
+            db.take_device_challenge(device_id, nonce).await?
+        } else {
+            let mut challenges = self.challenges.write().await;
+            let challenge = match challenges.get(nonce) {
+                Some(open) if open.device_id == device_id => challenges.remove(nonce),
+                _ => None,
+            };
+            drop(challenges);
+            let hash = self.secrets.read().await.get(device_id).cloned();
+            challenge.zip(hash)
+        };
+
+        let Some((challenge, hash)) = taken else {
+            tracing::warn!("Device {} answered without an open challenge", device_id);
+            return Err(AppError::Unauthorized);
+        };
+        if Utc::now() >= challenge.expires_at {
+            tracing::warn!("Device {} answered an expired challenge", device_id);
+            return Err(AppError::Unauthorized);
+        }
+        if !verify_response(&hash, &challenge.nonce, response) {
+            tracing::warn!("Device {} answered its challenge wrongly", device_id);
+            return Err(AppError::Unauthorized);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// What a device computes, keyed with its secret itself
+    fn answer(secret: &str, nonce: &str) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+        mac.update(&hex::decode(nonce).unwrap());
+        hex::encode(mac.finalize().into_bytes())
+    }
+
+    #[test]
+    fn test_device_answer_verifies_against_stored_hash() {
+        let secret = generate_device_secret();
+        assert_eq!(secret.len(), 96);
+        let nonce = Challenge::new("d1", CHALLENGE_TTL).nonce;
+        assert_eq!(nonce.len(), 64);
+
+        let hash = secret_hash(&secret);
+        assert!(verify_response(&hash, &nonce, &answer(&secret, &nonce)));
+
+        let other = Challenge::new("d1", CHALLENGE_TTL).nonce;
+        assert!(!verify_response(&hash, &other, &answer(&secret, &nonce)));
+        let other_secret = generate_device_secret();
+        assert!(!verify_response(
+            &hash,
+            &nonce,
+            &answer(&other_secret, &nonce)
+        ));
+        assert!(!verify_response(&hash, &nonce, "not hex"));
+    }
+
+    #[tokio::test]
+    async fn test_challenges_are_single_use() {
+        let store = DeviceAuthStore::new(None);
+        let secret = store.provision("d1").await.unwrap();
+        let challenge = store.challenge("d1").await.unwrap();
+        let response = answer(&secret, &challenge.nonce);
+        assert!(store
+            .redeem("d1", &challenge.nonce, &response)
+            .await
+            .is_ok());
+        assert!(matches!(
+            store.redeem("d1", &challenge.nonce, &response).await,
+            Err(AppError::Unauthorized)
+        ));
+
+        // A wrong answer also uses the challenge up
+        let challenge = store.challenge("d1").await.unwrap();
+        assert!(store.redeem("d1", &challenge.nonce, "00").await.is_err());
+        let response = answer(&secret, &challenge.nonce);
+        assert!(store
+            .redeem("d1", &challenge.nonce, &response)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_new_challenge_leaves_open_ones_alone() {
+        let store = DeviceAuthStore::new(None);
+        let secret = store.provision("d1").await.unwrap();
+        let first = store.challenge("d1").await.unwrap();
+        let second = store.challenge("d1").await.unwrap();
+
+        let response = answer(&secret, &first.nonce);
+        assert!(store.redeem("d1", &first.nonce, &response).await.is_ok());
+        let response = answer(&secret, &second.nonce);
+        assert!(store.redeem("d1", &second.nonce, &response).await.is_ok());
+
+        // Another device cannot take a challenge that is not its own
+        store.provision("d2").await.unwrap();
+        let challenge = store.challenge("d1").await.unwrap();
+        assert!(store.redeem("d2", &challenge.nonce, "00").await.is_err());
+        let response = answer(&secret, &challenge.nonce);
+        assert!(store
+            .redeem("d1", &challenge.nonce, &response)
+            .await
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_unprovisioned_device_gets_a_challenge_it_cannot_redeem() {
+        let store = DeviceAuthStore::new(None);
+        let challenge = store.challenge("ghost").await.unwrap();
+        assert_eq!(challenge.nonce.len(), 64);
+        assert!(matches!(
+            store.redeem("ghost", &challenge.nonce, "00").await,
+            Err(AppError::Unauthorized)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_expired_challenge_is_refused() {
+        let store = DeviceAuthStore::new(None).with_challenge_ttl(Duration::zero());
+        let secret = store.provision("d1").await.unwrap();
+        let challenge = store.challenge("d1").await.unwrap();
+        let response = answer(&secret, &challenge.nonce);
+        assert!(matches!(
+            store.redeem("d1", &challenge.nonce, &response).await,
+            Err(AppError::Unauthorized)
+        ));
+    }
+}
@@ -0,0 +1,332 @@
+/// Per-Device Ingest Statistics
+///
+/// Rolling counters that help tell a silent sensor from a misbehaving one:
+/// how many readings each device had accepted, quarantined or refused, the
+/// last refusal, and how many readings arrived in the last 1, 5 and 60
+/// minutes. Counters live in memory for the lifetime of the process only, so
+/// they are reported together with the time the process started. At most
+/// `MAX_DEVICES` devices are counted at once; past that the device heard from
+/// longest ago is forgotten to make room.
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::HashMap;
+use utoipa::ToSchema;
+
+/// Minute buckets kept per device; the longest reported window
+const WINDOW_MINUTES: usize = 60;
+
+/// Devices counted at once; beyond this the least recently seen is forgotten
+const MAX_DEVICES: usize = 10_000;
+
+/// Counters of one device
+#[derive(Debug, Clone)]
+struct DeviceCounters {
+    accepted: u64,
+    quarantined: u64,
+    rejected: u64,
+    duplicates: u64,
+    decimated: u64,
+    last_error: Option<LastError>,
+    last_seen_at: Option<DateTime<Utc>>,
+    /// Device timestamp of the last accepted reading, to spot retransmissions
+    last_reading_ts: Option<DateTime<Utc>>,
+    /// Accepted readings per minute, indexed by minute modulo the window
+    buckets: [u32; WINDOW_MINUTES],
+    /// Minute (since the epoch) each bucket currently counts
+    bucket_minutes: [i64; WINDOW_MINUTES],
+}
+
+impl Default for DeviceCounters {
+    fn default() -> Self {
+        Self {
+            accepted: 0,
+            quarantined: 0,
+            rejected: 0,
+            duplicates: 0,
+            decimated: 0,
+            last_error: None,
+            last_seen_at: None,
+            last_reading_ts: None,
+            buckets: [0; WINDOW_MINUTES],
+            bucket_minutes: [i64::MIN; WINDOW_MINUTES],
+        }
+    }
+}
+
+impl DeviceCounters {
+    /// Accepted readings in the `minutes` up to and including `now`'s minute
+    fn readings_in_last(&self, minutes: usize, now: DateTime<Utc>) -> u32 {
+        let current = minute_of(now);
+        (0..minutes.min(WINDOW_MINUTES) as i64)
+            .map(|ago| current - ago)
+            .filter_map(|minute| {
+                let idx = bucket_index(minute);
+                (self.bucket_minutes[idx] == minute).then_some(self.buckets[idx])
+            })
+            .sum()
+    }
+}
+
+/// The most recent refusal of a device's reading
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct LastError {
+    pub message: String,
+    pub at: DateTime<Utc>,
+}
+
+/// Ingest statistics of one device, as reported by the API
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct DeviceStats {
+    pub device_id: String,
+    /// When counting started; counters cover this process only
+    pub process_started_at: DateTime<Utc>,
+    pub accepted: u64,
+    /// Readings held in quarantine for lack of patient consent; they are
+    /// not counted as accepted
+    pub quarantined: u64,
+    /// Readings refused for failing validation or lacking patient consent
+    pub rejected: u64,
+    /// Accepted readings with the same device timestamp as the reading
+    /// before, which usually means the device retransmitted
+    pub duplicates: u64,
+    /// Accepted readings dropped by server-side decimation, not stored
+    pub decimated: u64,
+    /// Decimation interval applied to the device; `null` keeps every reading
+    pub decimation_interval_ms: Option<u64>,
+    pub last_error: Option<LastError>,
+    pub last_seen_at: Option<DateTime<Utc>>,
+    pub readings_last_1m: u32,
+    pub readings_last_5m: u32,
+    pub readings_last_60m: u32,
+    /// Totals over all stored readings; `null` without a database
+    pub database: Option<DeviceTotals>,
+}
+
+/// What the database holds for a device
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct DeviceTotals {
+    pub total_readings: i64,
+    pub first_reading_at: Option<DateTime<Utc>>,
+    pub last_reading_at: Option<DateTime<Utc>>,
+}
+
+/// Rolling ingest counters of every device seen since startup
+#[derive(Debug)]
+pub struct DeviceStatsTracker {
+    started_at: DateTime<Utc>,
+    devices: HashMap<String, DeviceCounters>,
+    max_devices: usize,
+}
+
+impl Default for DeviceStatsTracker {
+    fn default() -> Self {
+        Self {
+            started_at: Utc::now(),
+            devices: HashMap::new(),
+            max_devices: MAX_DEVICES,
+        }
+    }
+}
+
+impl DeviceStatsTracker {
+    /// Count an accepted reading taken at `reading_ts`
+    pub fn record_accepted(&mut self, device_id: &str, reading_ts: DateTime<Utc>) {
+        self.record_accepted_at(device_id, reading_ts, Utc::now());
+    }
+
+    /// Count a reading quarantined for lack of consent
+    pub fn record_quarantined(&mut self, device_id: &str) {
+        let counters = self.counters(device_id);
+        counters.quarantined += 1;
+        counters.last_seen_at = Some(Utc::now());
+    }
+
+    /// Count a refused reading
+    pub fn record_rejected(&mut self, device_id: &str, message: &str) {
+        self.record_rejected_at(device_id, message, Utc::now());
+    }
+
+    /// Count a reading dropped by decimation; it still counts as accepted
+    pub fn record_decimated(&mut self, device_id: &str) {
+        self.counters(device_id).decimated += 1;
+    }
+
+    /// Counters of `device_id`, making room for it first if it is new and
+    /// the tracker is full
+    fn counters(&mut self, device_id: &str) -> &mut DeviceCounters {
+        if self.devices.len() >= self.max_devices && !self.devices.contains_key(device_id) {
+            let victim = self
+                .devices
+                .iter()
+                .min_by_key(|(_, counters)| counters.last_seen_at)
+                .map(|(id, _)| id.clone());
+            if let Some(id) = victim {
+                self.devices.remove(&id);
+            }
+        }
+        self.devices.entry(device_id.to_string()).or_default()
+    }
+
+    fn record_accepted_at(
+        &mut self,
+        device_id: &str,
+        reading_ts: DateTime<Utc>,
+        now: DateTime<Utc>,
+    ) {
+        let counters = self.counters(device_id);
+        counters.accepted += 1;
+        if counters.last_reading_ts == Some(reading_ts) {
+            counters.duplicates += 1;
+        }
+        counters.last_reading_ts = Some(reading_ts);
+        counters.last_seen_at = Some(now);
+
+        let minute = minute_of(now);
+        let idx = bucket_index(minute);
+        if counters.bucket_minutes[idx] != minute {
+            counters.bucket_minutes[idx] = minute;
+            counters.buckets[idx] = 0;
+        }
+        counters.buckets[idx] += 1;
+    }
+
+    fn record_rejected_at(&mut self, device_id: &str, message: &str, now: DateTime<Utc>) {
+        let counters = self.counters(device_id);
+        counters.rejected += 1;
+        counters.last_error = Some(LastError {
+            message: message.to_string(),
+            at: now,
+        });
+        counters.last_seen_at = Some(now);
+    }
+
+    /// Statistics of `device_id`, or `None` if it sent nothing since startup
+    pub fn stats(&self, device_id: &str) -> Option<DeviceStats> {
+        self.stats_at(device_id, Utc::now())
+    }
+
+    /// When each device sending since startup was last heard from
+    pub fn last_seen(&self) -> HashMap<String, DateTime<Utc>> {
+        self.devices
+            .iter()
+            .filter_map(|(id, counters)| Some((id.clone(), counters.last_seen_at?)))
+            .collect()
+    }
+
+    /// Stop tracking `device_id`, as if it had sent nothing since startup
+    pub fn forget(&mut self, device_id: &str) {
+        self.devices.remove(device_id);
+    }
+
+    /// Statistics of a device that sent nothing since startup
+    pub fn empty_stats(&self, device_id: &str) -> DeviceStats {
+        self.build(device_id, &DeviceCounters::default(), Utc::now())
+    }
+
+    fn stats_at(&self, device_id: &str, now: DateTime<Utc>) -> Option<DeviceStats> {
+        let counters = self.devices.get(device_id)?;
+        Some(self.build(device_id, counters, now))
+    }
+
+    fn build(&self, device_id: &str, counters: &DeviceCounters, now: DateTime<Utc>) -> DeviceStats {
+        DeviceStats {
+            device_id: device_id.to_string(),
+            process_started_at: self.started_at,
+            accepted: counters.accepted,
+            quarantined: counters.quarantined,
+            rejected: counters.rejected,
+            duplicates: counters.duplicates,
+            decimated: counters.decimated,
+            decimation_interval_ms: None,
+            last_error: counters.last_error.clone(),
+            last_seen_at: counters.last_seen_at,
+            readings_last_1m: counters.readings_in_last(1, now),
+            readings_last_5m: counters.readings_in_last(5, now),
+            readings_last_60m: counters.readings_in_last(60, now),
+            database: None,
+        }
+    }
+}
+
+fn minute_of(t: DateTime<Utc>) -> i64 {
+    t.timestamp().div_euclid(60)
+}
+
+fn bucket_index(minute: i64) -> usize {
+    minute.rem_euclid(WINDOW_MINUTES as i64) as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, TimeZone};
+
+    #[test]
+    fn test_rolling_windows() {
+        let mut tracker = DeviceStatsTracker::default();
+        let t0 = Utc.with_ymd_and_hms(2026, 3, 1, 12, 0, 30).unwrap();
+
+        // One reading 59 minutes ago, two 3 minutes ago, three now
+        for (ago, count) in [(59, 1), (3, 2), (0, 3)] {
+            for i in 0..count {
+                let at = t0 - Duration::minutes(ago);
+                tracker.record_accepted_at("d1", at + Duration::seconds(i), at);
+            }
+        }
+
+        let stats = tracker.stats_at("d1", t0).unwrap();
+        assert_eq!(stats.accepted, 6);
+        assert_eq!(stats.readings_last_1m, 3);
+        assert_eq!(stats.readings_last_5m, 5);
+        assert_eq!(stats.readings_last_60m, 6);
+
+        // An hour later everything has rolled out of the windows
+        let later = t0 + Duration::minutes(60);
+        let stats = tracker.stats_at("d1", later).unwrap();
+        assert_eq!(stats.readings_last_60m, 0);
+        assert_eq!(stats.accepted, 6);
+
+        // A bucket reused for a new minute starts from zero
+        tracker.record_accepted_at("d1", later, later);
+        assert_eq!(tracker.stats_at("d1", later).unwrap().readings_last_60m, 1);
+    }
+
+    #[test]
+    fn test_duplicates_and_errors() {
+        let mut tracker = DeviceStatsTracker::default();
+        let t0 = Utc.with_ymd_and_hms(2026, 3, 1, 12, 0, 0).unwrap();
+
+        tracker.record_accepted_at("d1", t0, t0);
+        tracker.record_accepted_at("d1", t0, t0);
+        tracker.record_accepted_at("d1", t0 + Duration::seconds(1), t0);
+        tracker.record_rejected_at("d1", "value must be finite", t0);
+
+        let stats = tracker.stats_at("d1", t0).unwrap();
+        assert_eq!(stats.accepted, 3);
+        assert_eq!(stats.duplicates, 1);
+        assert_eq!(stats.rejected, 1);
+        assert_eq!(stats.last_error.unwrap().message, "value must be finite");
+
+        assert!(tracker.stats_at("d2", t0).is_none());
+        assert_eq!(tracker.empty_stats("d2").accepted, 0);
+    }
+
+    #[test]
+    fn test_full_tracker_forgets_least_recently_seen() {
+        let mut tracker = DeviceStatsTracker {
+            max_devices: 2,
+            ..Default::default()
+        };
+        let t0 = Utc.with_ymd_and_hms(2026, 3, 1, 12, 0, 0).unwrap();
+
+        tracker.record_accepted_at("d1", t0, t0);
+        tracker.record_accepted_at("d2", t0, t0 + Duration::seconds(1));
+        // Hearing from d1 again makes d2 the least recently seen
+        tracker.record_accepted_at("d1", t0, t0 + Duration::seconds(2));
+        tracker.record_rejected_at("d3", "value must be finite", t0 + Duration::seconds(3));
+
+        assert_eq!(tracker.stats_at("d1", t0).unwrap().accepted, 2);
+        assert!(tracker.stats_at("d2", t0).is_none());
+        assert_eq!(tracker.stats_at("d3", t0).unwrap().rejected, 1);
+    }
+}
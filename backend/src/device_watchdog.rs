@@ -0,0 +1,581 @@
+/// Device Offline Watchdog
+///
+/// A silent sensor next to a patient looks exactly like a quiet ward, so a
+/// background task checks every `DEVICE_WATCHDOG_INTERVAL_SECS` (default 30)
+/// when each device was last heard from. A device silent for longer than its
+/// grace period (`DEVICE_OFFLINE_GRACE_SECS`, default 300, or the device's
+/// own via `PUT /api/devices/{id}`) raises a `DeviceOffline` alert on
+/// `/ws/alerts`, and a `DeviceRecovered` one once it sends again. Devices
+/// marked `decommissioned` never raise either.
+///
+/// Only devices heard from since startup are watched: the watchdog knows a
+/// device went quiet, not that one never came up.
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{Mutex, RwLock};
+use utoipa::ToSchema;
+
+use crate::db::Database;
+use crate::domain::store::AppState;
+use crate::errors::AppError;
+use crate::normalization::DeviceProfile;
+use crate::ws::WsHub;
+
+const DEFAULT_GRACE_SECS: u64 = 300;
+const DEFAULT_INTERVAL_SECS: u64 = 30;
+
+/// Source of the current time, so tests can simulate silence without
+/// sleeping
+pub trait Clock: Send + Sync + std::fmt::Debug {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+/// The system clock
+#[derive(Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// A clock that only moves when told to
+#[derive(Debug)]
+pub struct ManualClock(std::sync::Mutex<DateTime<Utc>>);
+
+impl ManualClock {
+    pub fn new(start: DateTime<Utc>) -> Self {
+        Self(std::sync::Mutex::new(start))
+    }
+
+    pub fn advance(&self, by: chrono::Duration) {
+        *self.0.lock().unwrap_or_else(|e| e.into_inner()) += by;
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> DateTime<Utc> {
+        *self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Whether a device is expected to report
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum DeviceStatus {
+    #[default]
+    Active,
+    /// Taken out of service; its silence raises no alerts
+    Decommissioned,
+}
+
+impl DeviceStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeviceStatus::Active => "active",
+            DeviceStatus::Decommissioned => "decommissioned",
+        }
+    }
+}
+
+impl TryFrom<String> for DeviceStatus {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        serde_json::from_value(Value::String(value))
+            .map_err(|e| format!("unknown device status: {}", e))
+    }
+}
+
+/// Monitoring settings of one device
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct DeviceSettings {
+    pub device_id: String,
+    pub status: DeviceStatus,
+    /// Silence tolerated before the device counts as offline; `null` uses
+    /// the deployment default
+    pub offline_grace_secs: Option<u64>,
+    /// Keep at most one reading per this many milliseconds (0: all of
+    /// them); `null` uses the deployment default
+    pub decimation_interval_ms: Option<u64>,
+    /// Output range of the device, mapped linearly onto `target_min` to
+    /// `target_max` as readings are stored; all four are set or none
+    pub scale_min: Option<f64>,
+    pub scale_max: Option<f64>,
+    pub target_min: Option<f64>,
+    pub target_max: Option<f64>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl DeviceSettings {
+    /// How the device's readings are normalized, if at all
+    pub fn profile(&self) -> Option<DeviceProfile> {
+        device_profile(
+            &self.device_id,
+            [
+                self.scale_min,
+                self.scale_max,
+                self.target_min,
+                self.target_max,
+            ],
+        )
+    }
+}
+
+/// The profile of `device_id` if all of `[scale_min, scale_max, target_min,
+/// target_max]` are set
+fn device_profile(device_id: &str, bounds: [Option<f64>; 4]) -> Option<DeviceProfile> {
+    let [Some(scale_min), Some(scale_max), Some(target_min), Some(target_max)] = bounds else {
+        return None;
+    };
+    Some(DeviceProfile {
+        device_id: device_id.to_string(),
+        scale_min,
+        scale_max,
+        target_min,
+        target_max,
+    })
+}
+
+/// New settings of a device, replacing its previous ones
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct DeviceSettingsUpdate {
+    #[serde(default)]
+    pub status: DeviceStatus,
+    /// Seconds of silence before an offline alert (default the deployment's
+    /// `DEVICE_OFFLINE_GRACE_SECS`)
+    #[serde(default)]
+    pub offline_grace_secs: Option<u64>,
+    /// Keep at most one reading per this many milliseconds, 0 to keep all
+    /// (default the deployment's `DECIMATION_INTERVAL_MS`)
+    #[serde(default)]
+    pub decimation_interval_ms: Option<u64>,
+    /// Lowest and highest value the device's firmware reports, e.g. 0 and
+    /// 1023 for a 10-bit ADC; with `target_min` and `target_max`, readings
+    /// are scaled onto that range (default: stored as sent)
+    #[serde(default)]
+    pub scale_min: Option<f64>,
+    #[serde(default)]
+    pub scale_max: Option<f64>,
+    #[serde(default)]
+    pub target_min: Option<f64>,
+    #[serde(default)]
+    pub target_max: Option<f64>,
+}
+
+impl DeviceSettingsUpdate {
+    pub fn validate(&self) -> Result<(), String> {
+        match self.offline_grace_secs {
+            // Also the limit of the BIGINT column
+            Some(secs) if secs == 0 || secs > i64::MAX as u64 => {
+                Err("offline_grace_secs must be a positive number of seconds".into())
+            }
+            _ if self
+                .decimation_interval_ms
+                .is_some_and(|ms| ms > i64::MAX as u64) =>
+            {
+                Err("decimation_interval_ms is too large".into())
+            }
+            _ => self.validate_profile(),
+        }
+    }
+
+    fn validate_profile(&self) -> Result<(), String> {
+        let bounds = [
+            self.scale_min,
+            self.scale_max,
+            self.target_min,
+            self.target_max,
+        ];
+        match device_profile("", bounds) {
+            Some(profile) => profile.validate(),
+            None if bounds.iter().all(Option::is_none) => Ok(()),
+            None => Err("scale_min, scale_max, target_min and target_max go together".into()),
+        }
+    }
+}
+
+/// Device settings, in the database or (without one) in memory
+#[derive(Debug)]
+pub struct DeviceSettingsStore {
+    db: Option<Database>,
+    /// Settings per device, without a database
+    settings: RwLock<HashMap<String, DeviceSettings>>,
+}
+
+impl DeviceSettingsStore {
+    pub fn new(db: Option<Database>) -> Self {
+        Self {
+            db,
+            settings: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Replace the settings of `device_id`
+    pub async fn put(
+        &self,
+        device_id: &str,
+        update: DeviceSettingsUpdate,
+    ) -> Result<DeviceSettings, AppError> {
+        let settings = DeviceSettings {
+            device_id: device_id.to_string(),
+            status: update.status,
+            offline_grace_secs: update.offline_grace_secs,
+            decimation_interval_ms: update.decimation_interval_ms,
+            scale_min: update.scale_min,
+            scale_max: update.scale_max,
+            target_min: update.target_min,
+            target_max: update.target_max,
+            updated_at: Utc::now(),
+        };
+        if let Some(db) = &self.db {
+            db.upsert_device_settings(&settings).await?;
+        } else {
+            self.settings
+                .write()
+                .await
+                .insert(settings.device_id.clone(), settings.clone());
+        }
+        Ok(settings)
+    }
+
+    /// Settings of every device that has any
+    pub async fn all(&self) -> Result<HashMap<String, DeviceSettings>, AppError> {
+        match &self.db {
+            Some(db) => Ok(db
+                .list_device_settings()
+                .await?
+                .into_iter()
+                .map(|s| (s.device_id.clone(), s))
+                .collect()),
+            None => Ok(self.settings.read().await.clone()),
+        }
+    }
+
+    /// Settings of `device_id`, if it has any
+    pub async fn get(&self, device_id: &str) -> Result<Option<DeviceSettings>, AppError> {
+        match &self.db {
+            Some(db) => db.get_device_settings(device_id).await,
+            None => Ok(self.settings.read().await.get(device_id).cloned()),
+        }
+    }
+
+    /// Drop the settings of devices kept in memory; the database path
+    /// removes its rows as part of `Database::erase_patient`
+    pub async fn forget(&self, device_ids: &[String]) {
+        let mut settings = self.settings.write().await;
+        for device_id in device_ids {
+            settings.remove(device_id);
+        }
+    }
+}
+
+/// Raised on `/ws/alerts` when a device goes silent or comes back
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+#[serde(tag = "type")]
+pub enum DeviceAlert {
+    DeviceOffline {
+        device_id: String,
+        last_seen_at: DateTime<Utc>,
+        /// Grace period the silence exceeded
+        grace_secs: u64,
+        at: DateTime<Utc>,
+    },
+    DeviceRecovered {
+        device_id: String,
+        /// When the device was reported offline
+        offline_since: DateTime<Utc>,
+        last_seen_at: DateTime<Utc>,
+        at: DateTime<Utc>,
+    },
+}
+
+impl DeviceAlert {
+    pub fn device_id(&self) -> &str {
+        match self {
+            DeviceAlert::DeviceOffline { device_id, .. }
+            | DeviceAlert::DeviceRecovered { device_id, .. } => device_id,
+        }
+    }
+}
+
+/// Tracks which devices are offline between scans
+#[derive(Debug)]
+pub struct DeviceWatchdog {
+    clock: Arc<dyn Clock>,
+    default_grace: Duration,
+    /// Devices reported offline, and when
+    offline: HashMap<String, DateTime<Utc>>,
+}
+
+impl DeviceWatchdog {
+    pub fn new(clock: Arc<dyn Clock>, default_grace: Duration) -> Self {
+        Self {
+            clock,
+            default_grace,
+            offline: HashMap::new(),
+        }
+    }
+
+    /// Watchdog on the system clock with the `DEVICE_OFFLINE_GRACE_SECS`
+    /// grace period
+    pub fn from_env() -> Self {
+        Self::new(Arc::new(SystemClock), Self::grace_from_env())
+    }
+
+    /// Grace period of devices without their own
+    pub fn default_grace() -> Duration {
+        Duration::from_secs(DEFAULT_GRACE_SECS)
+    }
+
+    /// Grace period from `DEVICE_OFFLINE_GRACE_SECS`
+    pub fn grace_from_env() -> Duration {
+        std::env::var("DEVICE_OFFLINE_GRACE_SECS")
+            .ok()
+            .and_then(|v| v.parse::<u64>().ok())
+            .filter(|v| *v > 0)
+            .map_or(Self::default_grace(), Duration::from_secs)
+    }
+
+    /// Scan interval from `DEVICE_WATCHDOG_INTERVAL_SECS`
+    pub fn interval_from_env() -> Duration {
+        let secs = std::env::var("DEVICE_WATCHDOG_INTERVAL_SECS")
+            .ok()
+            .and_then(|v| v.parse::<u64>().ok())
+            .filter(|v| *v > 0)
+            .unwrap_or(DEFAULT_INTERVAL_SECS);
+        Duration::from_secs(secs)
+    }
+
+    /// Alerts for devices that went silent or came back since the previous
+    /// scan, given when each device was last seen
+    pub fn scan(
+        &mut self,
+        last_seen: &HashMap<String, DateTime<Utc>>,
+        settings: &HashMap<String, DeviceSettings>,
+    ) -> Vec<DeviceAlert> {
+        let now = self.clock.now();
+        let mut alerts = Vec::new();
+        for (device_id, &last_seen_at) in last_seen {
+            let device = settings.get(device_id);
+            if device.is_some_and(|d| d.status == DeviceStatus::Decommissioned) {
+                self.offline.remove(device_id);
+                continue;
+            }
+            let grace_secs = device
+                .and_then(|d| d.offline_grace_secs)
+                .unwrap_or(self.default_grace.as_secs());
+            let silent = now.signed_duration_since(last_seen_at).num_seconds();
+            let is_silent = silent > i64::try_from(grace_secs).unwrap_or(i64::MAX);
+
+            match (is_silent, self.offline.get(device_id).copied()) {
+                (true, None) => {
+                    self.offline.insert(device_id.clone(), now);
+                    alerts.push(DeviceAlert::DeviceOffline {
+                        device_id: device_id.clone(),
+                        last_seen_at,
+                        grace_secs,
+                        at: now,
+                    });
+                }
+                (false, Some(offline_since)) => {
+                    self.offline.remove(device_id);
+                    alerts.push(DeviceAlert::DeviceRecovered {
+                        device_id: device_id.clone(),
+                        offline_since,
+                        last_seen_at,
+                        at: now,
+                    });
+                }
+                _ => {}
+            }
+        }
+        alerts
+    }
+
+    /// Devices currently reported offline
+    pub fn offline_count(&self) -> usize {
+        self.offline.len()
+    }
+
+    /// Scan the devices of `state` every `interval`, publishing alerts to
+    /// `hub`
+    pub fn spawn(mut self, state: Arc<Mutex<AppState>>, hub: WsHub, interval: Duration) {
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                let (last_seen, settings) = {
+                    let state = state.lock().await;
+                    (state.device_last_seen(), state.device_settings())
+                };
+                let settings = match settings.all().await {
+                    Ok(settings) => settings,
+                    Err(e) => {
+                        tracing::warn!(error = ?e, "Failed to load device settings; skipping scan");
+                        continue;
+                    }
+                };
+                for alert in self.scan(&last_seen, &settings) {
+                    match &alert {
+                        DeviceAlert::DeviceOffline {
+                            device_id,
+                            last_seen_at,
+                            ..
+                        } => tracing::warn!(%device_id, %last_seen_at, "Device went offline"),
+                        DeviceAlert::DeviceRecovered { device_id, .. } => {
+                            tracing::info!(%device_id, "Device is reporting again")
+                        }
+                    }
+                    hub.publish_alert(alert);
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn watchdog(clock: &Arc<ManualClock>) -> DeviceWatchdog {
+        DeviceWatchdog::new(clock.clone(), Duration::from_secs(300))
+    }
+
+    fn settings(device_id: &str, status: DeviceStatus, grace: Option<u64>) -> DeviceSettings {
+        DeviceSettings {
+            device_id: device_id.into(),
+            status,
+            offline_grace_secs: grace,
+            decimation_interval_ms: None,
+            scale_min: None,
+            scale_max: None,
+            target_min: None,
+            target_max: None,
+            updated_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn test_silence_past_the_grace_period_goes_offline_then_recovers() {
+        let t0 = Utc.with_ymd_and_hms(2026, 3, 1, 12, 0, 0).unwrap();
+        let clock = Arc::new(ManualClock::new(t0));
+        let mut dog = watchdog(&clock);
+        let mut last_seen = HashMap::from([("d1".to_string(), t0)]);
+        let none = HashMap::new();
+
+        clock.advance(chrono::Duration::seconds(300));
+        assert!(dog.scan(&last_seen, &none).is_empty());
+
+        clock.advance(chrono::Duration::seconds(30));
+        let alerts = dog.scan(&last_seen, &none);
+        assert_eq!(
+            alerts,
+            [DeviceAlert::DeviceOffline {
+                device_id: "d1".into(),
+                last_seen_at: t0,
+                grace_secs: 300,
+                at: clock.now(),
+            }]
+        );
+        // Reported once, not on every scan
+        clock.advance(chrono::Duration::seconds(30));
+        assert!(dog.scan(&last_seen, &none).is_empty());
+        assert_eq!(dog.offline_count(), 1);
+
+        let offline_since = t0 + chrono::Duration::seconds(330);
+        last_seen.insert("d1".into(), clock.now());
+        clock.advance(chrono::Duration::seconds(30));
+        let alerts = dog.scan(&last_seen, &none);
+        assert_eq!(
+            alerts,
+            [DeviceAlert::DeviceRecovered {
+                device_id: "d1".into(),
+                offline_since,
+                last_seen_at: t0 + chrono::Duration::seconds(360),
+                at: clock.now(),
+            }]
+        );
+        assert_eq!(dog.offline_count(), 0);
+    }
+
+    #[test]
+    fn test_per_device_grace_and_decommissioned_devices() {
+        let t0 = Utc.with_ymd_and_hms(2026, 3, 1, 12, 0, 0).unwrap();
+        let clock = Arc::new(ManualClock::new(t0));
+        let mut dog = watchdog(&clock);
+        let last_seen = HashMap::from([
+            ("slow".to_string(), t0),
+            ("retired".to_string(), t0),
+            ("normal".to_string(), t0),
+        ]);
+        let overrides = HashMap::from([
+            (
+                "slow".to_string(),
+                settings("slow", DeviceStatus::Active, Some(3600)),
+            ),
+            (
+                "retired".to_string(),
+                settings("retired", DeviceStatus::Decommissioned, None),
+            ),
+        ]);
+
+        clock.advance(chrono::Duration::minutes(10));
+        let alerts = dog.scan(&last_seen, &overrides);
+        assert_eq!(alerts.len(), 1);
+        assert!(matches!(
+            &alerts[0],
+            DeviceAlert::DeviceOffline { device_id, .. } if device_id == "normal"
+        ));
+
+        clock.advance(chrono::Duration::minutes(60));
+        let alerts = dog.scan(&last_seen, &overrides);
+        assert!(matches!(
+            alerts.as_slice(),
+            [DeviceAlert::DeviceOffline { device_id, grace_secs: 3600, .. }] if device_id == "slow"
+        ));
+    }
+
+    #[test]
+    fn test_device_status_round_trips() {
+        for status in [DeviceStatus::Active, DeviceStatus::Decommissioned] {
+            assert_eq!(
+                DeviceStatus::try_from(status.as_str().to_string()),
+                Ok(status)
+            );
+        }
+        assert!(DeviceStatus::try_from("retired".to_string()).is_err());
+
+        let zero = DeviceSettingsUpdate {
+            status: DeviceStatus::Active,
+            offline_grace_secs: Some(0),
+            decimation_interval_ms: None,
+            scale_min: None,
+            scale_max: None,
+            target_min: None,
+            target_max: None,
+        };
+        assert!(zero.validate().is_err());
+
+        let half_a_profile = DeviceSettingsUpdate {
+            offline_grace_secs: None,
+            scale_min: Some(0.0),
+            scale_max: Some(1023.0),
+            ..zero
+        };
+        assert!(half_a_profile.validate().is_err());
+        let profile = DeviceSettingsUpdate {
+            target_min: Some(30.0),
+            target_max: Some(130.0),
+            ..half_a_profile
+        };
+        assert!(profile.validate().is_ok());
+    }
+}
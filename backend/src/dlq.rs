@@ -0,0 +1,912 @@
+/// Dead-Letter Queue Module
+///
+/// Inserts that fail because the database is briefly unreachable are first
+/// retried a few times with exponential backoff (`InsertRetry`). Readings
+/// that still fail are appended to a newline-delimited JSON file and retried
+/// in order by a background task, so a temporary database outage no longer
+/// loses data.
+///
+/// The file doubles as a write-ahead buffer: while it holds readings, new
+/// ones are appended behind them instead of being inserted, so the database
+/// receives them in the order they arrived. When it is full the oldest tenth
+/// is dropped to make room, so the file is rewritten once per that many
+/// readings rather than for every one.
+///
+/// A retry pass stops at the first transient failure, since the database is
+/// still away. An entry that fails any other way would fail on every pass
+/// and hold up the rest behind it, so it is parked instead: moved to a
+/// second file next to the queue (`<DLQ_PATH>.parked`) with the error, for
+/// an operator to look at.
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::future::Future;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+use crate::db::Database;
+use crate::domain::models::{SensorReading, StoredReading};
+use crate::errors::AppError;
+use crate::metrics::{self, METRICS};
+
+const DEFAULT_DLQ_PATH: &str = "/tmp/soundsense-dlq.jsonl";
+const DEFAULT_MAX_ENTRIES: usize = 100_000;
+const DEFAULT_RETRY_INTERVAL_SECS: u64 = 30;
+const DEFAULT_INSERT_ATTEMPTS: u32 = 3;
+const DEFAULT_INSERT_BACKOFF_MS: u64 = 50;
+
+/// A full queue drops this fraction of its entries (at least one) at once
+const TRIM_DIVISOR: usize = 10;
+
+/// How often a failed insert is tried again before the reading is
+/// dead-lettered. Only transient failures (timeouts, lost connections) are
+/// retried; the backoff doubles after every attempt.
+///
+/// The retries run while the caller holds the application state, so keep
+/// the total backoff well below a second.
+#[derive(Debug, Clone, Copy)]
+pub struct InsertRetry {
+    /// Tries in total, the first one included
+    attempts: u32,
+    backoff: Duration,
+}
+
+impl Default for InsertRetry {
+    fn default() -> Self {
+        Self::new(
+            DEFAULT_INSERT_ATTEMPTS,
+            Duration::from_millis(DEFAULT_INSERT_BACKOFF_MS),
+        )
+    }
+}
+
+impl InsertRetry {
+    /// Try up to `attempts` times (at least once), waiting `backoff` before
+    /// the second try and twice as long before each one after it
+    pub fn new(attempts: u32, backoff: Duration) -> Self {
+        Self {
+            attempts: attempts.max(1),
+            backoff,
+        }
+    }
+
+    /// Policy from `DB_INSERT_ATTEMPTS` (default 3) and
+    /// `DB_INSERT_BACKOFF_MS` (default 50)
+    pub fn from_env() -> Self {
+        let attempts = std::env::var("DB_INSERT_ATTEMPTS")
+            .ok()
+            .and_then(|v| v.parse::<u32>().ok())
+            .unwrap_or(DEFAULT_INSERT_ATTEMPTS);
+        let backoff_ms = std::env::var("DB_INSERT_BACKOFF_MS")
+            .ok()
+            .and_then(|v| v.parse::<u64>().ok())
+            .unwrap_or(DEFAULT_INSERT_BACKOFF_MS);
+        Self::new(attempts, Duration::from_millis(backoff_ms))
+    }
+
+    /// Whether `e` may go away by trying again
+    pub fn is_transient(e: &AppError) -> bool {
+        matches!(e, AppError::Timeout | AppError::ServiceUnavailable(_))
+    }
+
+    /// Run `insert` until it succeeds, fails permanently or runs out of
+    /// attempts; the last error is returned
+    pub async fn run<T, F, Fut>(&self, mut insert: F) -> Result<T, AppError>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, AppError>>,
+    {
+        let mut backoff = self.backoff;
+        let mut attempt = 1;
+        loop {
+            match insert().await {
+                Err(e) if attempt < self.attempts && Self::is_transient(&e) => {
+                    tracing::debug!(error = ?e, attempt, "Insert failed, retrying");
+                    metrics::inc(&METRICS.db_insert_retries);
+                    tokio::time::sleep(backoff).await;
+                    backoff = backoff.saturating_mul(2);
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+/// A reading waiting to be retried
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DlqEntry {
+    pub enqueued_at: DateTime<Utc>,
+    /// Record id the reading's Observation was published under (entries
+    /// written before ids were kept get a new one when the queue is opened)
+    #[serde(default = "Uuid::new_v4")]
+    pub id: Uuid,
+    pub reading: SensorReading,
+    /// Value as sent by the device, if `reading.value` was normalized
+    #[serde(default)]
+    pub raw_value: Option<f64>,
+}
+
+/// Newline-delimited JSON file that is only appended to, or rewritten whole
+#[derive(Debug, Clone)]
+pub struct AppendOnlyFile {
+    path: PathBuf,
+}
+
+impl AppendOnlyFile {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Append one JSON line, flushed to disk before returning
+    pub fn append<T: Serialize>(&self, value: &T) -> std::io::Result<()> {
+        let mut line = serde_json::to_vec(value)?;
+        line.push(b'\n');
+
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        file.write_all(&line)?;
+        file.sync_data()
+    }
+
+    /// Read every line; unparseable lines are skipped with a warning
+    pub fn read_all<T: for<'de> Deserialize<'de>>(&self) -> std::io::Result<Vec<T>> {
+        let file = match std::fs::File::open(&self.path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+
+        let mut values = Vec::new();
+        for (idx, line) in BufReader::new(file).lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            match serde_json::from_str(&line) {
+                Ok(value) => values.push(value),
+                Err(e) => tracing::warn!(line = idx + 1, error = %e, "Skipping corrupt DLQ line"),
+            }
+        }
+        Ok(values)
+    }
+
+    /// Atomically replace the contents (write to a temp file, then rename)
+    pub fn rewrite<T: Serialize>(&self, values: &[T]) -> std::io::Result<()> {
+        let tmp = self.path.with_extension("tmp");
+        {
+            let mut file = std::fs::File::create(&tmp)?;
+            for value in values {
+                let mut line = serde_json::to_vec(value)?;
+                line.push(b'\n');
+                file.write_all(&line)?;
+            }
+            file.sync_data()?;
+        }
+        std::fs::rename(tmp, &self.path)
+    }
+}
+
+/// Run blocking file I/O on the blocking thread pool
+async fn blocking<T: Send + 'static>(
+    io: impl FnOnce() -> std::io::Result<T> + Send + 'static,
+) -> std::io::Result<T> {
+    tokio::task::spawn_blocking(io)
+        .await
+        .map_err(std::io::Error::other)?
+}
+
+/// An entry the database refused for a reason other than being away
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ParkedEntry {
+    pub parked_at: DateTime<Utc>,
+    /// Why its insert failed
+    pub error: String,
+    pub entry: DlqEntry,
+}
+
+/// Snapshot returned by `GET /api/admin/dlq/stats`
+#[derive(Debug, Clone, Serialize)]
+pub struct DlqStats {
+    pub pending_count: usize,
+    /// Entries set aside because their insert failed for good
+    pub parked_count: usize,
+    pub oldest_entry_age_secs: Option<i64>,
+    pub last_retry_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug)]
+struct DlqInner {
+    file: AppendOnlyFile,
+    parked: AppendOnlyFile,
+    pending: usize,
+    parked_count: usize,
+    oldest: Option<DateTime<Utc>>,
+    last_retry_at: Option<DateTime<Utc>>,
+    /// Bumped by every purge, so a retry pass knows its snapshot is stale
+    purges: u64,
+}
+
+/// File-backed queue of readings that failed to persist
+#[derive(Debug)]
+pub struct DeadLetterQueue {
+    inner: Mutex<DlqInner>,
+    /// Held while a retry pass inserts one entry, so a purge never races a
+    /// reading being inserted, yet waits for one insert at most
+    retrying: Mutex<()>,
+    path: PathBuf,
+    max_entries: usize,
+}
+
+impl DeadLetterQueue {
+    /// Open (or create) the queue at `path`, counting entries already on disk
+    pub fn open(path: impl Into<PathBuf>, max_entries: usize) -> std::io::Result<Self> {
+        let path = path.into();
+        let file = AppendOnlyFile::new(path.clone());
+        let existing: Vec<DlqEntry> = file.read_all()?;
+        let mut parked_path = path.clone().into_os_string();
+        parked_path.push(".parked");
+        let parked = AppendOnlyFile::new(parked_path);
+        let parked_count = parked.read_all::<ParkedEntry>()?.len();
+
+        if !existing.is_empty() {
+            tracing::info!(
+                pending = existing.len(),
+                path = %file.path().display(),
+                "Dead-letter queue has entries from a previous run"
+            );
+            // Written back so entries from before ids were kept keep the id
+            // they were given now, which a retry pass removes them by
+            file.rewrite(&existing)?;
+        }
+        if parked_count > 0 {
+            tracing::warn!(
+                parked = parked_count,
+                path = %parked.path().display(),
+                "Dead-letter queue has parked entries"
+            );
+        }
+
+        Ok(Self {
+            inner: Mutex::new(DlqInner {
+                file,
+                parked,
+                pending: existing.len(),
+                parked_count,
+                oldest: existing.first().map(|e| e.enqueued_at),
+                last_retry_at: None,
+                purges: 0,
+            }),
+            retrying: Mutex::new(()),
+            path,
+            max_entries,
+        })
+    }
+
+    /// File the queue is kept in
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// `DLQ_PATH`, or the default file
+    pub fn path_from_env() -> PathBuf {
+        std::env::var("DLQ_PATH")
+            .unwrap_or_else(|_| DEFAULT_DLQ_PATH.to_string())
+            .into()
+    }
+
+    /// Open using `DLQ_PATH` and `DLQ_MAX_ENTRIES`
+    pub fn from_env() -> std::io::Result<Self> {
+        let path = Self::path_from_env();
+        let max_entries = std::env::var("DLQ_MAX_ENTRIES")
+            .ok()
+            .and_then(|v| v.parse::<usize>().ok())
+            .filter(|v| *v > 0)
+            .unwrap_or(DEFAULT_MAX_ENTRIES);
+        Self::open(path, max_entries)
+    }
+
+    /// Retry interval from `DLQ_RETRY_INTERVAL_SECS`
+    pub fn retry_interval_from_env() -> Duration {
+        let secs = std::env::var("DLQ_RETRY_INTERVAL_SECS")
+            .ok()
+            .and_then(|v| v.parse::<u64>().ok())
+            .filter(|v| *v > 0)
+            .unwrap_or(DEFAULT_RETRY_INTERVAL_SECS);
+        Duration::from_secs(secs)
+    }
+
+    /// Append a reading that failed to persist
+    pub async fn enqueue(&self, reading: &SensorReading) -> Result<(), AppError> {
+        self.enqueue_as(Uuid::new_v4(), reading, None).await
+    }
+
+    /// Append a reading that failed to persist, keeping its record id and
+    /// the value its device sent
+    pub async fn enqueue_as(
+        &self,
+        id: Uuid,
+        reading: &SensorReading,
+        raw_value: Option<f64>,
+    ) -> Result<(), AppError> {
+        let mut inner = self.inner.lock().await;
+
+        if inner.pending >= self.max_entries {
+            let file = inner.file.clone();
+            let max_entries = self.max_entries;
+            let (dropped, remaining, oldest) = blocking(move || {
+                let entries: Vec<DlqEntry> = file.read_all()?;
+                let dropped = (entries.len() + 1)
+                    .saturating_sub(max_entries)
+                    .max(max_entries / TRIM_DIVISOR)
+                    .min(entries.len());
+                let remaining = &entries[dropped..];
+                file.rewrite(remaining)?;
+                Ok((
+                    dropped,
+                    remaining.len(),
+                    remaining.first().map(|e| e.enqueued_at),
+                ))
+            })
+            .await
+            .map_err(|e| {
+                tracing::error!(error = %e, "Failed to rewrite dead-letter queue");
+                AppError::Internal
+            })?;
+            tracing::warn!(
+                max_entries = self.max_entries,
+                dropped,
+                "Dead-letter queue full, dropping oldest readings"
+            );
+            metrics::add(&METRICS.dlq_dropped, dropped as u64);
+            inner.pending = remaining;
+            inner.oldest = oldest;
+        }
+
+        let entry = DlqEntry {
+            enqueued_at: Utc::now(),
+            id,
+            reading: reading.clone(),
+            raw_value,
+        };
+        let enqueued_at = entry.enqueued_at;
+        let file = inner.file.clone();
+        blocking(move || file.append(&entry)).await.map_err(|e| {
+            tracing::error!(error = %e, "Failed to write to dead-letter queue");
+            AppError::Internal
+        })?;
+
+        inner.pending += 1;
+        inner.oldest.get_or_insert(enqueued_at);
+        Ok(())
+    }
+
+    /// Number of readings waiting to be persisted
+    pub async fn pending(&self) -> usize {
+        self.inner.lock().await.pending
+    }
+
+    /// All pending entries, oldest first
+    pub async fn entries(&self) -> Result<Vec<DlqEntry>, AppError> {
+        let inner = self.inner.lock().await;
+        inner.file.read_all().map_err(|e| {
+            tracing::error!(error = %e, "Failed to read dead-letter queue");
+            AppError::Internal
+        })
+    }
+
+    /// Remove the first `count` entries (those a retry pass has persisted)
+    pub async fn remove_first(&self, count: usize) -> Result<(), AppError> {
+        if count == 0 {
+            return Ok(());
+        }
+
+        let mut inner = self.inner.lock().await;
+        // Re-read under the lock: entries appended since the snapshot are kept
+        let entries: Vec<DlqEntry> = inner.file.read_all().map_err(|_| AppError::Internal)?;
+        let remaining = &entries[count.min(entries.len())..];
+
+        inner.file.rewrite(remaining).map_err(|e| {
+            tracing::error!(error = %e, "Failed to rewrite dead-letter queue");
+            AppError::Internal
+        })?;
+        inner.pending = remaining.len();
+        inner.oldest = remaining.first().map(|e| e.enqueued_at);
+        Ok(())
+    }
+
+    /// Remove the entries with the given ids, wherever they are now
+    async fn remove_ids(&self, ids: &HashSet<Uuid>) -> Result<(), AppError> {
+        if ids.is_empty() {
+            return Ok(());
+        }
+
+        let mut inner = self.inner.lock().await;
+        let entries: Vec<DlqEntry> = inner.file.read_all().map_err(|_| AppError::Internal)?;
+        let remaining: Vec<_> = entries
+            .into_iter()
+            .filter(|e| !ids.contains(&e.id))
+            .collect();
+
+        inner.file.rewrite(&remaining).map_err(|e| {
+            tracing::error!(error = %e, "Failed to rewrite dead-letter queue");
+            AppError::Internal
+        })?;
+        inner.pending = remaining.len();
+        inner.oldest = remaining.first().map(|e| e.enqueued_at);
+        Ok(())
+    }
+
+    /// Set aside an entry whose insert failed with `error`
+    async fn park(&self, entry: DlqEntry, error: &AppError) -> Result<(), AppError> {
+        tracing::warn!(id = %entry.id, error = ?error, "Parking dead-lettered reading");
+        let parked = ParkedEntry {
+            parked_at: Utc::now(),
+            error: error.to_string(),
+            entry,
+        };
+        let mut inner = self.inner.lock().await;
+        inner.parked.append(&parked).map_err(|e| {
+            tracing::error!(error = %e, "Failed to write parked dead-letter entry");
+            AppError::Internal
+        })?;
+        inner.parked_count += 1;
+        metrics::inc(&METRICS.dlq_parked);
+        Ok(())
+    }
+
+    /// Entries set aside because their insert failed for good, oldest first
+    pub async fn parked(&self) -> Result<Vec<ParkedEntry>, AppError> {
+        let inner = self.inner.lock().await;
+        inner.parked.read_all().map_err(|e| {
+            tracing::error!(error = %e, "Failed to read parked dead-letter entries");
+            AppError::Internal
+        })
+    }
+
+    /// Drop every pending and parked reading of `patient_id`, waiting for an
+    /// insert in progress to finish first; returns how many were dropped
+    pub async fn purge_patient(&self, patient_id: &str) -> Result<usize, AppError> {
+        let _retrying = self.retrying.lock().await;
+        let mut inner = self.inner.lock().await;
+        inner.purges += 1;
+
+        let entries: Vec<DlqEntry> = inner.file.read_all().map_err(|_| AppError::Internal)?;
+        let (purged, remaining): (Vec<_>, Vec<_>) = entries
+            .into_iter()
+            .partition(|e| e.reading.patient_id == patient_id);
+        if !purged.is_empty() {
+            inner.file.rewrite(&remaining).map_err(|e| {
+                tracing::error!(error = %e, "Failed to rewrite dead-letter queue");
+                AppError::Internal
+            })?;
+            inner.pending = remaining.len();
+            inner.oldest = remaining.first().map(|e| e.enqueued_at);
+        }
+
+        let parked: Vec<ParkedEntry> = inner.parked.read_all().map_err(|_| AppError::Internal)?;
+        let (purged_parked, kept): (Vec<_>, Vec<_>) = parked
+            .into_iter()
+            .partition(|p| p.entry.reading.patient_id == patient_id);
+        if !purged_parked.is_empty() {
+            inner.parked.rewrite(&kept).map_err(|e| {
+                tracing::error!(error = %e, "Failed to rewrite parked dead-letter entries");
+                AppError::Internal
+            })?;
+            inner.parked_count = kept.len();
+        }
+        Ok(purged.len() + purged_parked.len())
+    }
+
+    /// Retry pending entries in order using `insert`, stopping at the first
+    /// transient failure so ordering is preserved; entries failing any other
+    /// way are parked. Returns how many were persisted.
+    pub async fn retry_with<F, Fut>(&self, mut insert: F) -> Result<usize, AppError>
+    where
+        F: FnMut(SensorReading) -> Fut,
+        Fut: Future<Output = Result<(), AppError>>,
+    {
+        self.retry_entries_with(|entry| insert(entry.reading)).await
+    }
+
+    /// Like `retry_with`, but `insert` gets the whole entry
+    pub async fn retry_entries_with<F, Fut>(&self, mut insert: F) -> Result<usize, AppError>
+    where
+        F: FnMut(DlqEntry) -> Fut,
+        Fut: Future<Output = Result<(), AppError>>,
+    {
+        let entries = self.entries().await?;
+        let purges = {
+            let mut inner = self.inner.lock().await;
+            inner.last_retry_at = Some(Utc::now());
+            inner.purges
+        };
+
+        let mut persisted = 0;
+        let mut done = HashSet::new();
+        for entry in entries {
+            let _retrying = self.retrying.lock().await;
+            if self.inner.lock().await.purges != purges {
+                // The snapshot may hold purged readings; the next pass
+                // starts from what is left
+                break;
+            }
+            let id = entry.id;
+            match insert(entry.clone()).await {
+                Ok(()) => persisted += 1,
+                Err(e) if InsertRetry::is_transient(&e) => {
+                    tracing::debug!(error = ?e, "DLQ retry failed, will try again later");
+                    break;
+                }
+                Err(e) => self.park(entry, &e).await?,
+            }
+            done.insert(id);
+        }
+
+        self.remove_ids(&done).await?;
+        Ok(persisted)
+    }
+
+    /// Retry pending entries against the database. Entries stored already
+    /// (say, before the queue could be rewritten) are skipped by the insert.
+    pub async fn retry(&self, db: &Database) -> Result<usize, AppError> {
+        self.retry_entries_with(|entry| async move {
+            let stored = StoredReading {
+                id: entry.id,
+                raw_value: entry.raw_value,
+                ..StoredReading::new(entry.reading)
+            };
+            db.insert_reading(&stored).await.map(|_| ())
+        })
+        .await
+    }
+
+    pub async fn stats(&self) -> DlqStats {
+        let inner = self.inner.lock().await;
+        DlqStats {
+            pending_count: inner.pending,
+            parked_count: inner.parked_count,
+            oldest_entry_age_secs: inner.oldest.map(|at| (Utc::now() - at).num_seconds()),
+            last_retry_at: inner.last_retry_at,
+        }
+    }
+
+    /// Periodically retry pending entries
+    pub fn spawn_retry(self: &Arc<Self>, db: Database, interval: Duration) {
+        let dlq = self.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                if dlq.pending().await == 0 {
+                    continue;
+                }
+                match dlq.retry(&db).await {
+                    Ok(0) => {}
+                    Ok(persisted) => {
+                        tracing::info!(persisted, "Replayed readings from dead-letter queue")
+                    }
+                    Err(e) => tracing::warn!(error = ?e, "Dead-letter queue retry failed"),
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::models::{SignalCode, TimestampSource};
+
+    fn temp_path() -> PathBuf {
+        std::env::temp_dir().join(format!("soundsense-dlq-{}.jsonl", uuid::Uuid::new_v4()))
+    }
+
+    fn reading(value: f64) -> SensorReading {
+        SensorReading {
+            patient_id: "p1".into(),
+            device_id: "d1".into(),
+            code: SignalCode::Sound,
+            value,
+            unit: "raw".into(),
+            ts: Utc::now(),
+            ts_source: TimestampSource::Device,
+            metadata: Default::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_enqueue_persists_across_reopen() {
+        let path = temp_path();
+        let dlq = DeadLetterQueue::open(&path, 10).unwrap();
+
+        dlq.enqueue(&reading(1.0)).await.unwrap();
+        dlq.enqueue(&reading(2.0)).await.unwrap();
+        assert_eq!(dlq.stats().await.pending_count, 2);
+        assert!(dlq.stats().await.oldest_entry_age_secs.is_some());
+
+        let reopened = DeadLetterQueue::open(&path, 10).unwrap();
+        let entries = reopened.entries().await.unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].reading.value, 1.0);
+        assert_eq!(reopened.stats().await.pending_count, 2);
+
+        std::fs::remove_file(path).ok();
+    }
+
+    #[tokio::test]
+    async fn test_dequeue_and_size_limit() {
+        let path = temp_path();
+        let dlq = DeadLetterQueue::open(&path, 2).unwrap();
+
+        dlq.enqueue(&reading(1.0)).await.unwrap();
+        dlq.enqueue(&reading(2.0)).await.unwrap();
+        // Full: the oldest reading makes room
+        dlq.enqueue(&reading(3.0)).await.unwrap();
+        let values: Vec<f64> = dlq
+            .entries()
+            .await
+            .unwrap()
+            .iter()
+            .map(|e| e.reading.value)
+            .collect();
+        assert_eq!(values, vec![2.0, 3.0]);
+        assert_eq!(dlq.pending().await, 2);
+
+        dlq.remove_first(1).await.unwrap();
+        let entries = dlq.entries().await.unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].reading.value, 3.0);
+        assert_eq!(dlq.stats().await.pending_count, 1);
+
+        std::fs::remove_file(path).ok();
+    }
+
+    #[tokio::test]
+    async fn test_full_queue_drops_its_oldest_tenth_at_once() {
+        let path = temp_path();
+        let dlq = DeadLetterQueue::open(&path, 20).unwrap();
+        for value in 0..20 {
+            dlq.enqueue(&reading(value as f64)).await.unwrap();
+        }
+
+        // Room for the next two readings is made in one rewrite
+        dlq.enqueue(&reading(20.0)).await.unwrap();
+        assert_eq!(dlq.pending().await, 19);
+        dlq.enqueue(&reading(21.0)).await.unwrap();
+        let values: Vec<f64> = dlq
+            .entries()
+            .await
+            .unwrap()
+            .iter()
+            .map(|e| e.reading.value)
+            .collect();
+        assert_eq!(values, (2..22).map(f64::from).collect::<Vec<_>>());
+
+        std::fs::remove_file(path).ok();
+    }
+
+    #[tokio::test]
+    async fn test_purge_drops_only_the_patients_entries() {
+        let path = temp_path();
+        let dlq = DeadLetterQueue::open(&path, 10).unwrap();
+        dlq.enqueue(&reading(1.0)).await.unwrap();
+        dlq.enqueue(&SensorReading {
+            patient_id: "p2".into(),
+            ..reading(2.0)
+        })
+        .await
+        .unwrap();
+        dlq.enqueue(&reading(3.0)).await.unwrap();
+
+        assert_eq!(dlq.purge_patient("p1").await.unwrap(), 2);
+        assert_eq!(dlq.purge_patient("p1").await.unwrap(), 0);
+        let entries = dlq.entries().await.unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].reading.patient_id, "p2");
+        assert_eq!(dlq.stats().await.pending_count, 1);
+
+        // Nothing is left for a retry to put back
+        let replayed = std::sync::Mutex::new(Vec::new());
+        dlq.retry_with(|r| {
+            replayed.lock().unwrap().push(r.patient_id);
+            async { Ok(()) }
+        })
+        .await
+        .unwrap();
+        assert_eq!(*replayed.lock().unwrap(), vec!["p2".to_string()]);
+
+        std::fs::remove_file(path).ok();
+    }
+
+    #[tokio::test]
+    async fn test_retry_removes_persisted_entries_in_order() {
+        let path = temp_path();
+        let dlq = DeadLetterQueue::open(&path, 10).unwrap();
+        for value in [1.0, 2.0, 3.0] {
+            dlq.enqueue(&reading(value)).await.unwrap();
+        }
+
+        // The database "recovers" for two inserts, then fails again
+        let persisted = std::sync::Mutex::new(Vec::new());
+        let count = dlq
+            .retry_with(|r| {
+                let mut persisted = persisted.lock().unwrap();
+                let ok = persisted.len() < 2;
+                if ok {
+                    persisted.push(r.value);
+                }
+                async move {
+                    if ok {
+                        Ok(())
+                    } else {
+                        Err(AppError::ServiceUnavailable("database unavailable".into()))
+                    }
+                }
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(count, 2);
+        assert_eq!(*persisted.lock().unwrap(), vec![1.0, 2.0]);
+        let stats = dlq.stats().await;
+        assert_eq!(stats.pending_count, 1);
+        assert!(stats.last_retry_at.is_some());
+        assert_eq!(dlq.entries().await.unwrap()[0].reading.value, 3.0);
+
+        // Full recovery drains the queue
+        assert_eq!(dlq.retry_with(|_| async { Ok(()) }).await.unwrap(), 1);
+        assert_eq!(dlq.stats().await.pending_count, 0);
+        assert!(dlq.stats().await.oldest_entry_age_secs.is_none());
+
+        std::fs::remove_file(path).ok();
+    }
+
+    #[tokio::test]
+    async fn test_poison_entries_are_parked_and_the_rest_retried() {
+        let path = temp_path();
+        let dlq = DeadLetterQueue::open(&path, 10).unwrap();
+        for value in [1.0, 2.0, 3.0] {
+            dlq.enqueue(&reading(value)).await.unwrap();
+        }
+
+        // The second entry is refused for good; the third still goes in
+        let persisted = std::sync::Mutex::new(Vec::new());
+        let count = dlq
+            .retry_with(|r| {
+                let poison = r.value == 2.0;
+                if !poison {
+                    persisted.lock().unwrap().push(r.value);
+                }
+                async move {
+                    if poison {
+                        Err(AppError::Internal)
+                    } else {
+                        Ok(())
+                    }
+                }
+            })
+            .await
+            .unwrap();
+        assert_eq!(count, 2);
+        assert_eq!(*persisted.lock().unwrap(), vec![1.0, 3.0]);
+        let stats = dlq.stats().await;
+        assert_eq!(stats.pending_count, 0);
+        assert_eq!(stats.parked_count, 1);
+        let parked = dlq.parked().await.unwrap();
+        assert_eq!(parked[0].entry.reading.value, 2.0);
+
+        // Parked entries survive a restart, and go with their patient
+        let reopened = DeadLetterQueue::open(&path, 10).unwrap();
+        assert_eq!(reopened.stats().await.parked_count, 1);
+        assert_eq!(reopened.purge_patient("p1").await.unwrap(), 1);
+        assert_eq!(reopened.stats().await.parked_count, 0);
+        assert!(reopened.parked().await.unwrap().is_empty());
+
+        std::fs::remove_file(&path).ok();
+        std::fs::remove_file(reopened.inner.lock().await.parked.path()).ok();
+    }
+
+    #[tokio::test]
+    async fn test_reopening_fixes_ids_of_entries_without_one() {
+        let path = temp_path();
+        let legacy = serde_json::json!({
+            "enqueued_at": Utc::now(),
+            "reading": reading(1.0),
+        });
+        std::fs::write(&path, format!("{}\n", legacy)).unwrap();
+
+        let dlq = DeadLetterQueue::open(&path, 10).unwrap();
+        let id = dlq.entries().await.unwrap()[0].id;
+        assert_eq!(dlq.entries().await.unwrap()[0].id, id);
+        assert_eq!(dlq.retry_with(|_| async { Ok(()) }).await.unwrap(), 1);
+        assert_eq!(dlq.pending().await, 0);
+
+        std::fs::remove_file(path).ok();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_transient_failures_are_retried_with_backoff() {
+        let retry = InsertRetry::new(3, Duration::from_millis(50));
+        let started = tokio::time::Instant::now();
+        let calls = std::sync::atomic::AtomicU32::new(0);
+
+        // Unreachable twice, then back
+        let result = retry
+            .run(|| {
+                let n = calls.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                async move {
+                    if n < 2 {
+                        Err(AppError::ServiceUnavailable("database unavailable".into()))
+                    } else {
+                        Ok(n)
+                    }
+                }
+            })
+            .await;
+        assert!(matches!(result, Ok(2)));
+        // 50 ms, then 100 ms
+        assert_eq!(started.elapsed(), Duration::from_millis(150));
+
+        // Permanent errors are not retried
+        calls.store(0, std::sync::atomic::Ordering::Relaxed);
+        let result: Result<(), AppError> = retry
+            .run(|| {
+                calls.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                async { Err(AppError::Internal) }
+            })
+            .await;
+        assert!(matches!(result, Err(AppError::Internal)));
+        assert_eq!(calls.load(std::sync::atomic::Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_persistent_failure_is_dead_lettered_then_replayed() {
+        let path = temp_path();
+        let dlq = DeadLetterQueue::open(&path, 10).unwrap();
+        let retry = InsertRetry::new(3, Duration::from_millis(10));
+        let failing = reading(42.0);
+
+        // The outage outlasts every attempt, so the reading is parked
+        let attempts = std::sync::atomic::AtomicU32::new(0);
+        let result: Result<(), AppError> = retry
+            .run(|| {
+                attempts.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                async { Err(AppError::Timeout) }
+            })
+            .await;
+        assert!(matches!(result, Err(AppError::Timeout)));
+        assert_eq!(attempts.load(std::sync::atomic::Ordering::Relaxed), 3);
+        let id = Uuid::new_v4();
+        dlq.enqueue_as(id, &failing, None).await.unwrap();
+        assert_eq!(dlq.stats().await.pending_count, 1);
+
+        // Once the database is back, the replay stores it under its id
+        let replayed = std::sync::Mutex::new(Vec::new());
+        let count = dlq
+            .retry_entries_with(|entry| {
+                replayed
+                    .lock()
+                    .unwrap()
+                    .push((entry.id, entry.reading.value));
+                async { Ok(()) }
+            })
+            .await
+            .unwrap();
+        assert_eq!(count, 1);
+        assert_eq!(*replayed.lock().unwrap(), vec![(id, 42.0)]);
+        assert_eq!(dlq.stats().await.pending_count, 0);
+
+        std::fs::remove_file(path).ok();
+    }
+}
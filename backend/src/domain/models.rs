@@ -1,7 +1,15 @@
-use chrono::{DateTime, Utc};
-use serde::{Deserialize, Serialize};
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use utoipa::ToSchema;
+use uuid::Uuid;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+use crate::fhir::{ObservationCategory, ObservationStatus};
+use crate::units::UnitConversion;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, ToSchema)]
 pub enum SignalCode {
     // Canonical serialized value
     #[serde(rename = "sound")]
@@ -13,17 +21,366 @@ pub enum SignalCode {
     Sound,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+impl SignalCode {
+    /// Every signal the backend accepts
+    pub const ALL: [SignalCode; 1] = [SignalCode::Sound];
+
+    /// Other spellings accepted on input, as in the serde attributes above
+    pub const ALIASES: [(&'static str, SignalCode); 4] = [
+        ("SoundLevel", SignalCode::Sound),
+        ("sound_level", SignalCode::Sound),
+        ("SOUND_LEVEL", SignalCode::Sound),
+        ("Sound", SignalCode::Sound),
+    ];
+
+    /// Code as stored and published
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SignalCode::Sound => "sound",
+        }
+    }
+
+    /// Human-readable name, as shown in FHIR codings
+    pub fn display(&self) -> &'static str {
+        match self {
+            SignalCode::Sound => "Sound Level",
+        }
+    }
+}
+
+impl std::str::FromStr for SignalCode {
+    type Err = String;
+
+    /// The canonical code or one of its aliases, as accepted in JSON
+    fn from_str(s: &str) -> Result<Self, String> {
+        Self::ALL
+            .into_iter()
+            .find(|code| code.as_str() == s)
+            .or_else(|| {
+                Self::ALIASES
+                    .into_iter()
+                    .find(|(alias, _)| *alias == s)
+                    .map(|(_, code)| code)
+            })
+            .ok_or_else(|| format!("unknown signal code '{}'", s))
+    }
+}
+
+/// Where a reading's timestamp came from
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum TimestampSource {
+    /// Supplied by the device in the ingest payload
+    #[default]
+    Device,
+    /// Assigned by the backend because the device sent none
+    Server,
+}
+
+impl TimestampSource {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TimestampSource::Device => "device",
+            TimestampSource::Server => "server",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct SensorReading {
     pub patient_id: String,
     pub device_id: String,
     pub code: SignalCode,
     pub value: f64,
     pub unit: String,
+    /// RFC 3339; integer epoch seconds or milliseconds are accepted too
+    #[serde(deserialize_with = "deserialize_timestamp")]
     pub ts: DateTime<Utc>,
+    #[serde(default)]
+    pub ts_source: TimestampSource,
+    /// Free-form device annotations; `fhir_category` overrides the
+    /// Observation category and `location` names where the device is
+    #[serde(default, skip_serializing_if = "serde_json::Map::is_empty")]
+    #[schema(value_type = Object)]
+    pub metadata: serde_json::Map<String, Value>,
+}
+
+/// Metadata key holding an observation-category code for the reading
+pub const FHIR_CATEGORY_METADATA_KEY: &str = "fhir_category";
+
+/// Metadata key holding where the reading was taken, e.g. a ward
+pub const LOCATION_METADATA_KEY: &str = "location";
+
+/// Longest location name stored with a reading
+pub const MAX_LOCATION_LEN: usize = 255;
+
+/// Epoch timestamps of at least this magnitude are milliseconds. As seconds
+/// it would be the year 5138; as milliseconds it is March 1973.
+const EPOCH_MILLIS_THRESHOLD: i64 = 100_000_000_000;
+
+/// Parse a reading timestamp: an RFC 3339 string, or integer seconds or
+/// milliseconds since the Unix epoch, told apart by magnitude
+pub fn parse_timestamp(value: &Value) -> Result<DateTime<Utc>, String> {
+    let parsed = match value {
+        Value::String(s) => DateTime::parse_from_rfc3339(s)
+            .map(|ts| ts.with_timezone(&Utc))
+            .ok(),
+        Value::Number(n) => n.as_i64().and_then(|n| {
+            if n.unsigned_abs() >= EPOCH_MILLIS_THRESHOLD as u64 {
+                DateTime::from_timestamp_millis(n)
+            } else {
+                DateTime::from_timestamp(n, 0)
+            }
+        }),
+        _ => None,
+    };
+    parsed.ok_or_else(|| {
+        format!(
+            "invalid timestamp {}: expected an RFC 3339 string or integer epoch seconds or milliseconds",
+            value
+        )
+    })
+}
+
+fn deserialize_timestamp<'de, D: Deserializer<'de>>(d: D) -> Result<DateTime<Utc>, D::Error> {
+    parse_timestamp(&Value::deserialize(d)?).map_err(serde::de::Error::custom)
+}
+
+/// Ingest payload as sent by devices.
+///
+/// Cheap sensors often have no clock, so `ts` is optional here; readings
+/// without one are stamped with the server time on arrival. Firmware that
+/// counts epoch seconds or milliseconds may send those instead of RFC 3339.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(try_from = "RawIngestReading")]
+pub struct IngestReading {
+    pub patient_id: String,
+    pub device_id: String,
+    pub code: SignalCode,
+    pub value: f64,
+    pub unit: String,
+    /// RFC 3339, or integer epoch seconds or milliseconds; server time if absent
+    #[serde(default)]
+    pub ts: Option<DateTime<Utc>>,
+    #[serde(default)]
+    #[schema(value_type = Object)]
+    pub metadata: serde_json::Map<String, Value>,
+}
+
+/// Untyped form of `IngestReading`, so deserialization errors can name the
+/// offending field(s) instead of only a line and column
+#[derive(Deserialize)]
+struct RawIngestReading {
+    patient_id: Option<Value>,
+    device_id: Option<Value>,
+    code: Option<Value>,
+    value: Option<Value>,
+    unit: Option<Value>,
+    ts: Option<Value>,
+    metadata: Option<Value>,
+}
+
+impl TryFrom<RawIngestReading> for IngestReading {
+    type Error = String;
+
+    fn try_from(raw: RawIngestReading) -> Result<Self, Self::Error> {
+        let mut missing = Vec::new();
+        let mut invalid = Vec::new();
+
+        let patient_id = field(raw.patient_id, "patient_id", &mut missing, &mut invalid);
+        let device_id = field(raw.device_id, "device_id", &mut missing, &mut invalid);
+        let code = field(raw.code, "code", &mut missing, &mut invalid);
+        let value = field(raw.value, "value", &mut missing, &mut invalid);
+        let unit: Option<String> = field(raw.unit, "unit", &mut missing, &mut invalid);
+        let ts = match raw.ts {
+            None | Some(Value::Null) => None,
+            Some(ts) => parse_timestamp(&ts)
+                .map_err(|e| invalid.push(format!("field `ts`: {}", e)))
+                .ok(),
+        };
+        let metadata = match raw.metadata {
+            None | Some(Value::Null) => Some(serde_json::Map::new()),
+            Some(metadata) => field(Some(metadata), "metadata", &mut missing, &mut invalid),
+        };
+
+        if !missing.is_empty() {
+            invalid.insert(
+                0,
+                format!("missing required field(s) {}", missing.join(", ")),
+            );
+        }
+        if !invalid.is_empty() {
+            return Err(invalid.join("; "));
+        }
+
+        // Every required field is present when nothing was reported
+        match (patient_id, device_id, code, value, unit, metadata) {
+            (
+                Some(patient_id),
+                Some(device_id),
+                Some(code),
+                Some(value),
+                Some(unit),
+                Some(metadata),
+            ) => Ok(Self {
+                patient_id,
+                device_id,
+                code,
+                value,
+                unit: normalize_unit(&unit),
+                ts,
+                metadata,
+            }),
+            _ => Err("incomplete reading".into()),
+        }
+    }
+}
+
+/// Decode one field, recording a missing or mistyped value by name
+fn field<T: DeserializeOwned>(
+    value: Option<Value>,
+    name: &str,
+    missing: &mut Vec<String>,
+    invalid: &mut Vec<String>,
+) -> Option<T> {
+    let Some(value) = value else {
+        missing.push(format!("`{}`", name));
+        return None;
+    };
+
+    serde_json::from_value(value)
+        .map_err(|e| invalid.push(format!("field `{}`: {}", name, e)))
+        .ok()
+}
+
+impl IngestReading {
+    /// Convert into a stored reading, assigning `Utc::now()` if `ts` is missing
+    pub fn into_reading(self) -> SensorReading {
+        let (ts, ts_source) = match self.ts {
+            Some(ts) => (ts, TimestampSource::Device),
+            None => (Utc::now(), TimestampSource::Server),
+        };
+
+        SensorReading {
+            patient_id: self.patient_id,
+            device_id: self.device_id,
+            code: self.code,
+            value: self.value,
+            unit: self.unit,
+            ts,
+            ts_source,
+            metadata: self.metadata,
+        }
+    }
+}
+
+/// Most samples accepted in one packed reading
+pub const MAX_PACKED_SAMPLES: usize = 1000;
+
+/// Longest sampling interval of a packed reading (one minute)
+pub const MAX_PACKED_INTERVAL_MS: u32 = 60_000;
+
+/// Several samples of one signal taken at a fixed rate, as sent by
+/// high-rate firmware in one request. Sample `i` was taken at
+/// `start_ts + i * interval_ms`.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct PackedReading {
+    pub patient_id: String,
+    pub device_id: String,
+    pub code: SignalCode,
+    pub unit: String,
+    /// Time of the first sample: RFC 3339, or integer epoch seconds or milliseconds
+    #[serde(deserialize_with = "deserialize_timestamp")]
+    pub start_ts: DateTime<Utc>,
+    /// Time between consecutive samples
+    pub interval_ms: u32,
+    pub values: Vec<f64>,
+    /// Copied into every sample's metadata
+    #[serde(default)]
+    #[schema(value_type = Object)]
+    pub metadata: serde_json::Map<String, Value>,
+}
+
+impl PackedReading {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.values.is_empty() {
+            return Err("values must not be empty".into());
+        }
+        if self.values.len() > MAX_PACKED_SAMPLES {
+            return Err(format!(
+                "at most {} values per packed reading",
+                MAX_PACKED_SAMPLES
+            ));
+        }
+        if !(1..=MAX_PACKED_INTERVAL_MS).contains(&self.interval_ms) {
+            return Err(format!(
+                "interval_ms must be between 1 and {}",
+                MAX_PACKED_INTERVAL_MS
+            ));
+        }
+        if let Some(i) = self.values.iter().position(|v| !v.is_finite()) {
+            return Err(format!("values[{}] must be finite", i));
+        }
+        if self.sample_ts(self.values.len() - 1).is_none() {
+            return Err("samples must not run past the latest supported timestamp".into());
+        }
+        Ok(())
+    }
+
+    /// Timestamp of sample `index`; `None` past the latest representable time
+    pub fn sample_ts(&self, index: usize) -> Option<DateTime<Utc>> {
+        let offset_ms = i64::try_from(index)
+            .ok()?
+            .checked_mul(i64::from(self.interval_ms))?;
+        self.start_ts
+            .checked_add_signed(chrono::Duration::try_milliseconds(offset_ms)?)
+    }
+
+    /// One reading per sample, oldest first. Only for packed readings that
+    /// passed `validate`, whose every sample has a timestamp.
+    pub fn into_readings(self) -> Vec<SensorReading> {
+        (0..self.values.len())
+            .map(|i| SensorReading {
+                patient_id: self.patient_id.clone(),
+                device_id: self.device_id.clone(),
+                code: self.code,
+                value: self.values[i],
+                unit: normalize_unit(&self.unit),
+                ts: self.sample_ts(i).unwrap_or(DateTime::<Utc>::MAX_UTC),
+                ts_source: TimestampSource::Device,
+                metadata: self.metadata.clone(),
+            })
+            .collect()
+    }
 }
 
 impl SensorReading {
+    /// Observation category: the `fhir_category` metadata code if given,
+    /// otherwise the default for the signal
+    pub fn category(&self) -> Result<ObservationCategory, String> {
+        match self.metadata.get(FHIR_CATEGORY_METADATA_KEY) {
+            None | Some(Value::Null) => Ok(ObservationCategory::for_signal(&self.code)),
+            Some(Value::String(code)) => ObservationCategory::try_from(code.clone()),
+            Some(_) => Err("metadata.fhir_category must be a string".into()),
+        }
+    }
+
+    /// Where the reading was taken: the trimmed `location` metadata string,
+    /// `None` if absent or blank
+    pub fn location(&self) -> Result<Option<&str>, String> {
+        match self.metadata.get(LOCATION_METADATA_KEY) {
+            None | Some(Value::Null) => Ok(None),
+            Some(Value::String(location)) if location.trim().len() > MAX_LOCATION_LEN => {
+                Err(format!(
+                    "metadata.location must be at most {} characters",
+                    MAX_LOCATION_LEN
+                ))
+            }
+            Some(Value::String(location)) => Ok(Some(location.trim()).filter(|l| !l.is_empty())),
+            Some(_) => Err("metadata.location must be a string".into()),
+        }
+    }
+
     pub fn validate(&self) -> Result<(), String> {
         if self.patient_id.trim().is_empty() {
             return Err("patient_id required".into());
@@ -34,6 +391,769 @@ impl SensorReading {
         if !self.value.is_finite() {
             return Err("value must be finite".into());
         }
+        self.category()?;
+        self.location()?;
         Ok(())
     }
 }
+
+/// A value measured together with a reading's main one, such as the raw ADC
+/// count behind a calibrated level
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ReadingExtra {
+    pub code: SignalCode,
+    pub value: f64,
+    pub unit: String,
+}
+
+/// A reading with the values measured alongside it, published together as
+/// one Observation with a component per extra
+#[derive(Debug, Clone)]
+pub struct ExtendedReading {
+    pub reading: SensorReading,
+    pub extras: Vec<ReadingExtra>,
+}
+
+impl From<SensorReading> for ExtendedReading {
+    fn from(reading: SensorReading) -> Self {
+        Self {
+            reading,
+            extras: Vec::new(),
+        }
+    }
+}
+
+impl SensorReading {
+    /// This reading together with values measured alongside it
+    pub fn with_extras(self, extras: Vec<ReadingExtra>) -> ExtendedReading {
+        ExtendedReading {
+            reading: self,
+            extras,
+        }
+    }
+}
+
+/// A reading as kept by the backend: the measurement plus the record id its
+/// Observation is published under, a review status clinicians can amend,
+/// its Observation category, when it was deleted and what the device sent
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredReading {
+    pub id: Uuid,
+    pub reading: SensorReading,
+    pub status: ObservationStatus,
+    pub note: Option<String>,
+    pub category: ObservationCategory,
+    /// Deleted readings are kept for the audit trail but left out of queries
+    pub deleted_at: Option<DateTime<Utc>>,
+    /// Value as sent by the device, once `reading.value` has been normalized
+    /// by the device's profile (see `normalization`)
+    #[serde(default)]
+    pub raw_value: Option<f64>,
+}
+
+impl StoredReading {
+    /// A newly ingested reading: fresh id, status final, no note. An invalid
+    /// category override falls back to the signal's default.
+    pub fn new(reading: SensorReading) -> Self {
+        let category = reading
+            .category()
+            .unwrap_or_else(|_| ObservationCategory::for_signal(&reading.code));
+        Self {
+            id: Uuid::new_v4(),
+            reading,
+            status: ObservationStatus::Final,
+            note: None,
+            category,
+            deleted_at: None,
+            raw_value: None,
+        }
+    }
+
+    /// Whether the reading counts towards statistics and model data
+    pub fn is_usable(&self) -> bool {
+        self.status != ObservationStatus::EnteredInError && self.deleted_at.is_none()
+    }
+}
+
+/// Where an ingested reading ended up
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Storage {
+    /// Inserted into the database
+    Database,
+    /// Written to the local write-ahead buffer, to be inserted once the
+    /// database is reachable again
+    Buffered,
+    /// Kept in memory only: there is no database, or the insert failed
+    /// with nowhere to buffer it
+    Memory,
+    /// Handed to the ingest queue, not stored yet
+    Queued,
+    /// Held back for lack of consent
+    Quarantine,
+    /// Dropped by server-side decimation: another reading of the device
+    /// was kept for the same interval
+    Decimated,
+}
+
+/// What the server did with an ingested reading, returned with it so a
+/// device can tell a stored reading from one only buffered
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct IngestReceipt {
+    /// Record id the Observation is published under
+    pub id: Uuid,
+    /// Whether the reading is in durable storage
+    pub persisted: bool,
+    pub storage: Storage,
+    /// When the server received the reading
+    pub received_at: DateTime<Utc>,
+}
+
+/// Which stored readings an observation query returns
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ReadingFilter<'a> {
+    pub code: Option<SignalCode>,
+    /// FHIR token: a category code, or `system|code`
+    pub category: Option<&'a str>,
+    /// Only readings of these patients; `None` for all
+    pub patients: Option<&'a [String]>,
+    /// Also return deleted readings
+    pub include_deleted: bool,
+    /// Conversion of the values returned; a strict one only returns the
+    /// readings it can convert
+    pub units: Option<&'a UnitConversion>,
+}
+
+/// Owned counterpart of `ReadingFilter`, for queries outliving the request
+#[derive(Debug, Clone, Default)]
+pub struct ReadingQuery {
+    pub code: Option<SignalCode>,
+    pub category: Option<String>,
+    pub patients: Option<Vec<String>>,
+    pub include_deleted: bool,
+}
+
+impl ReadingQuery {
+    pub fn filter(&self) -> ReadingFilter<'_> {
+        ReadingFilter {
+            code: self.code,
+            category: self.category.as_deref(),
+            patients: self.patients.as_deref(),
+            include_deleted: self.include_deleted,
+            units: None,
+        }
+    }
+}
+
+impl ReadingFilter<'_> {
+    /// The category token split into its optional system and its code
+    fn category_token(&self) -> Option<(Option<&str>, &str)> {
+        self.category.map(|token| match token.split_once('|') {
+            Some((system, code)) => (Some(system).filter(|s| !s.is_empty()), code),
+            None => (None, token),
+        })
+    }
+
+    /// JSONB value a matching `sensor_readings.category` contains
+    pub fn category_json(&self) -> Option<String> {
+        let (system, code) = self.category_token()?;
+        let mut coding = serde_json::json!({ "code": code });
+        if let Some(system) = system {
+            coding["system"] = system.into();
+        }
+        Some(serde_json::json!([{ "coding": [coding] }]).to_string())
+    }
+
+    /// Whether an in-memory reading passes the filter
+    pub fn matches(&self, r: &StoredReading) -> bool {
+        (self.include_deleted || r.deleted_at.is_none())
+            && self.code.is_none_or(|code| r.reading.code == code)
+            && self.category_token().is_none_or(|(system, code)| {
+                system.is_none_or(|s| s == ObservationCategory::SYSTEM)
+                    && r.category.as_str() == code
+            })
+            && self
+                .patients
+                .is_none_or(|p| p.contains(&r.reading.patient_id))
+            && self.units.is_none_or(|units| units.keeps(&r.reading))
+    }
+}
+
+impl From<SensorReading> for StoredReading {
+    fn from(reading: SensorReading) -> Self {
+        Self::new(reading)
+    }
+}
+
+/// Time-weighted average level above which a patient counts as over-exposed
+pub const NOISE_EXPOSURE_LIMIT_DB: f64 = 85.0;
+
+/// Units whose values are sound levels in decibels; readings in other units
+/// (e.g. raw ADC counts) can't be assessed against `NOISE_EXPOSURE_LIMIT_DB`
+pub const DECIBEL_UNITS: [&str; 3] = ["db", "dba", "db(a)"];
+
+/// Whether a unit denotes decibels
+pub fn is_decibel_unit(unit: &str) -> bool {
+    DECIBEL_UNITS.contains(&unit.to_ascii_lowercase().as_str())
+}
+
+/// Spellings of a unit (lowercase) that older devices and tools send, and
+/// the unit they are stored as
+pub const UNIT_ALIASES: [(&str, &str); 6] = [
+    ("au", "raw"),
+    ("a.u.", "raw"),
+    ("counts", "raw"),
+    ("db", "dB"),
+    ("dba", "dBA"),
+    ("db(a)", "dBA"),
+];
+
+/// The canonical spelling of `unit`; units without an alias are kept as sent
+pub fn normalize_unit(unit: &str) -> String {
+    let unit = unit.trim();
+    let lower = unit.to_ascii_lowercase();
+    UNIT_ALIASES
+        .iter()
+        .find(|(alias, _)| *alias == lower)
+        .map_or(unit, |(_, canonical)| canonical)
+        .to_string()
+}
+
+/// What erasing a patient's data removed
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PatientErasure {
+    /// `sensor_readings` rows deleted
+    pub readings: u64,
+    /// Devices unregistered because only this patient's readings used them
+    pub devices: Vec<String>,
+}
+
+/// Patients with readings of one signal in a reporting period
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PopulationCounts {
+    /// Patients with at least one usable reading
+    pub patients: i64,
+    /// Patients with at least one usable reading in decibels
+    pub measured: i64,
+    /// Patients whose time-weighted average level exceeds `NOISE_EXPOSURE_LIMIT_DB`
+    pub over_limit: i64,
+}
+
+impl PopulationCounts {
+    /// Count patients per signal code. The time-weighted average is the
+    /// energy average (3 dB exchange rate) of a patient's decibel readings,
+    /// each reading weighted equally. Entered-in-error readings are skipped.
+    pub fn from_readings<'a>(
+        readings: impl IntoIterator<Item = &'a StoredReading>,
+    ) -> HashMap<&'static str, PopulationCounts> {
+        let mut levels: HashMap<(&'static str, &str), Vec<f64>> = HashMap::new();
+        for r in readings.into_iter().filter(|r| r.is_usable()) {
+            let patient_levels = levels
+                .entry((r.reading.code.as_str(), r.reading.patient_id.as_str()))
+                .or_default();
+            if is_decibel_unit(&r.reading.unit) {
+                patient_levels.push(r.reading.value);
+            }
+        }
+
+        let mut counts = HashMap::<_, PopulationCounts>::new();
+        for ((code, _), patient_levels) in levels {
+            let entry = counts.entry(code).or_default();
+            entry.patients += 1;
+            if let Some(twa) = energy_average(patient_levels.into_iter()) {
+                entry.measured += 1;
+                if twa > NOISE_EXPOSURE_LIMIT_DB {
+                    entry.over_limit += 1;
+                }
+            }
+        }
+        counts
+    }
+}
+
+/// Number of bands reported by octave-band microphone modules
+pub const OCTAVE_BAND_COUNT: usize = 8;
+
+/// Nominal centre frequencies (Hz) of `OctaveBandReading::bands`, i.e. the
+/// standard eight octave bands from 63 Hz to 8 kHz
+pub const OCTAVE_BAND_CENTERS_HZ: [f64; OCTAVE_BAND_COUNT] =
+    [63.0, 125.0, 250.0, 500.0, 1000.0, 2000.0, 4000.0, 8000.0];
+
+/// Accepted octave band and overall levels in dB: past the loudest sound air
+/// can carry (about 194 dB), and far from where energy averages overflow
+pub const OCTAVE_LEVEL_RANGE_DB: std::ops::RangeInclusive<f64> = -50.0..=200.0;
+
+/// Per-octave sound levels from a professional microphone module
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct OctaveBandReading {
+    pub patient_id: String,
+    pub device_id: String,
+    pub ts: DateTime<Utc>,
+    /// Band levels in dB, lowest band first (see `OCTAVE_BAND_CENTERS_HZ`)
+    pub bands: [f64; OCTAVE_BAND_COUNT],
+    pub overall_db: f64,
+    /// A-weighted level of the bands in dB(A), set by the backend when
+    /// `ENABLE_A_WEIGHTING=true`
+    #[serde(default, skip_deserializing)]
+    #[schema(read_only)]
+    pub calibrated_value: Option<f64>,
+}
+
+impl OctaveBandReading {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.patient_id.trim().is_empty() {
+            return Err("patient_id required".into());
+        }
+        if self.device_id.trim().is_empty() {
+            return Err("device_id required".into());
+        }
+        let (min, max) = (OCTAVE_LEVEL_RANGE_DB.start(), OCTAVE_LEVEL_RANGE_DB.end());
+        if !OCTAVE_LEVEL_RANGE_DB.contains(&self.overall_db) {
+            return Err(format!("overall_db must be between {} and {}", min, max));
+        }
+        if let Some(i) = self
+            .bands
+            .iter()
+            .position(|b| !OCTAVE_LEVEL_RANGE_DB.contains(b))
+        {
+            return Err(format!(
+                "band {} ({} Hz) must be between {} and {} dB",
+                i, OCTAVE_BAND_CENTERS_HZ[i], min, max
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Average level of one octave band over a period
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct OctaveBandLevel {
+    pub center_hz: f64,
+    /// `None` when there were no readings
+    pub level_db: Option<f64>,
+}
+
+/// Day-averaged octave spectrum for a patient. Levels are energy averages
+/// (the mean of the sound power, converted back to dB), as is usual for
+/// averaging decibel values.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct OctaveSpectrum {
+    pub patient_id: String,
+    pub date: NaiveDate,
+    pub reading_count: i64,
+    pub bands: Vec<OctaveBandLevel>,
+    pub overall_db: Option<f64>,
+}
+
+impl OctaveSpectrum {
+    pub fn new(
+        patient_id: String,
+        date: NaiveDate,
+        reading_count: i64,
+        levels: [Option<f64>; OCTAVE_BAND_COUNT],
+        overall_db: Option<f64>,
+    ) -> Self {
+        Self {
+            patient_id,
+            date,
+            reading_count,
+            bands: OCTAVE_BAND_CENTERS_HZ
+                .iter()
+                .zip(levels)
+                .map(|(&center_hz, level_db)| OctaveBandLevel {
+                    center_hz,
+                    level_db,
+                })
+                .collect(),
+            overall_db,
+        }
+    }
+
+    /// Average the given readings (already filtered to one patient and day)
+    pub fn from_readings<'a>(
+        patient_id: String,
+        date: NaiveDate,
+        readings: impl IntoIterator<Item = &'a OctaveBandReading> + Clone,
+    ) -> Self {
+        let levels = std::array::from_fn(|i| {
+            energy_average(readings.clone().into_iter().map(|r| r.bands[i]))
+        });
+        let overall_db = energy_average(readings.clone().into_iter().map(|r| r.overall_db));
+        let reading_count = readings.into_iter().count() as i64;
+
+        Self::new(patient_id, date, reading_count, levels, overall_db)
+    }
+}
+
+/// Energy average of decibel levels: 10 * log10(mean(10^(L/10)))
+pub fn energy_average(levels: impl Iterator<Item = f64>) -> Option<f64> {
+    let (sum, n) = levels.fold((0.0, 0u32), |(sum, n), db| {
+        (sum + 10f64.powf(db / 10.0), n + 1)
+    });
+    (n > 0).then(|| 10.0 * (sum / n as f64).log10())
+}
+
+/// Whether a patient agreed to have their readings stored
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ConsentStatus {
+    /// Registered, consent not yet recorded
+    #[default]
+    Pending,
+    Granted,
+    Revoked,
+}
+
+impl ConsentStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ConsentStatus::Pending => "pending",
+            ConsentStatus::Granted => "granted",
+            ConsentStatus::Revoked => "revoked",
+        }
+    }
+}
+
+impl TryFrom<String> for ConsentStatus {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        serde_json::from_value(Value::String(value))
+            .map_err(|e| format!("unknown consent status: {}", e))
+    }
+}
+
+/// A patient known to the system and their consent
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Patient {
+    pub patient_id: String,
+    /// Human-readable label (e.g. ward and bed), never a name
+    pub label: Option<String>,
+    pub consent_status: ConsentStatus,
+    /// When consent was granted; unset while pending or after revocation
+    pub consented_at: Option<DateTime<Utc>>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl Patient {
+    /// `patient_id` with its consent changed to `status`, keeping the
+    /// original grant time if consent was already granted
+    pub fn with_consent(
+        previous: Option<Patient>,
+        patient_id: &str,
+        status: ConsentStatus,
+        label: Option<String>,
+    ) -> Self {
+        let now = Utc::now();
+        let consented_at = match (status, &previous) {
+            (ConsentStatus::Granted, Some(p)) if p.consent_status == ConsentStatus::Granted => {
+                p.consented_at
+            }
+            (ConsentStatus::Granted, _) => Some(now),
+            _ => None,
+        };
+        Self {
+            patient_id: patient_id.to_string(),
+            label: label.or(previous.and_then(|p| p.label)),
+            consent_status: status,
+            consented_at,
+            updated_at: now,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_signal_codes_round_trip() {
+        let from_json = |s: &str| serde_json::from_value::<SignalCode>(Value::from(s));
+        for code in SignalCode::ALL {
+            assert_eq!(code.as_str().parse::<SignalCode>(), Ok(code));
+            assert_eq!(from_json(code.as_str()).unwrap(), code);
+            assert_eq!(serde_json::to_value(code).unwrap(), code.as_str());
+        }
+        // Every alias means the same in a query string as in JSON
+        for (alias, code) in SignalCode::ALIASES {
+            assert_eq!(alias.parse::<SignalCode>(), Ok(code));
+            assert_eq!(from_json(alias).unwrap(), code);
+        }
+        for unknown in ["", "SOUND", "heart_rate"] {
+            assert!(unknown.parse::<SignalCode>().is_err());
+            assert!(from_json(unknown).is_err());
+        }
+    }
+
+    #[test]
+    fn test_ingest_without_ts_gets_server_time() {
+        let payload: IngestReading = serde_json::from_str(
+            r#"{"patient_id":"p1","device_id":"d1","code":"sound","value":1.0,"unit":"raw"}"#,
+        )
+        .unwrap();
+
+        let before = Utc::now();
+        let reading = payload.into_reading();
+
+        assert!(reading.ts >= before && reading.ts <= Utc::now());
+        assert_eq!(reading.ts_source, TimestampSource::Server);
+    }
+
+    #[test]
+    fn test_octave_spectrum_uses_energy_average() {
+        let reading = |bands: [f64; OCTAVE_BAND_COUNT], overall_db| OctaveBandReading {
+            calibrated_value: None,
+            patient_id: "p1".into(),
+            device_id: "d1".into(),
+            ts: Utc::now(),
+            bands,
+            overall_db,
+        };
+        let readings = [
+            reading([60.0; OCTAVE_BAND_COUNT], 70.0),
+            reading([50.0; OCTAVE_BAND_COUNT], 70.0),
+        ];
+
+        let date = Utc::now().date_naive();
+        let spectrum = OctaveSpectrum::from_readings("p1".into(), date, &readings);
+
+        assert_eq!(spectrum.reading_count, 2);
+        assert_eq!(spectrum.bands.len(), OCTAVE_BAND_COUNT);
+        assert_eq!(spectrum.bands[0].center_hz, 63.0);
+        assert_eq!(spectrum.bands[7].center_hz, 8000.0);
+        // 60 dB and 50 dB average to 57.4 dB, not 55 dB
+        let level = spectrum.bands[3].level_db.unwrap();
+        assert!((level - 57.40).abs() < 0.01, "{level}");
+        assert!((spectrum.overall_db.unwrap() - 70.0).abs() < 1e-9);
+
+        // Levels that would overflow the energy sum are refused up front
+        assert!(readings[0].validate().is_ok());
+        let mut loud = reading([60.0; OCTAVE_BAND_COUNT], 70.0);
+        loud.bands[2] = 1e6;
+        assert!(loud.validate().unwrap_err().contains("band 2"));
+        assert!(reading([60.0; OCTAVE_BAND_COUNT], f64::NAN)
+            .validate()
+            .is_err());
+
+        let empty = OctaveSpectrum::from_readings("p1".into(), date, &[]);
+        assert_eq!(empty.reading_count, 0);
+        assert!(empty.bands.iter().all(|b| b.level_db.is_none()));
+        assert!(empty.overall_db.is_none());
+    }
+
+    #[test]
+    fn test_ingest_errors_name_the_field() {
+        let err = serde_json::from_str::<IngestReading>(r#"{"value":"abc"}"#)
+            .unwrap_err()
+            .to_string();
+
+        assert!(err.contains("`patient_id`"), "{err}");
+        assert!(err.contains("`unit`"), "{err}");
+        assert!(
+            err.contains("field `value`: invalid type: string \"abc\", expected f64"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn test_ingest_with_ts_preserves_it() {
+        let payload: IngestReading = serde_json::from_str(
+            r#"{"patient_id":"p1","device_id":"d1","code":"sound","value":1.0,"unit":"raw","ts":"2026-01-01T12:00:00Z"}"#,
+        )
+        .unwrap();
+
+        let reading = payload.into_reading();
+
+        assert_eq!(reading.ts.to_rfc3339(), "2026-01-01T12:00:00+00:00");
+        assert_eq!(reading.ts_source, TimestampSource::Device);
+    }
+
+    #[test]
+    fn test_unit_aliases_are_normalized() {
+        assert_eq!(normalize_unit("au"), "raw");
+        assert_eq!(normalize_unit("AU"), "raw");
+        assert_eq!(normalize_unit(" db "), "dB");
+        assert_eq!(normalize_unit("dB(A)"), "dBA");
+        assert_eq!(normalize_unit("Pa"), "Pa");
+
+        let payload: IngestReading = serde_json::from_str(
+            r#"{"patient_id":"p1","device_id":"d1","code":"sound","value":-12.5,"unit":"au"}"#,
+        )
+        .unwrap();
+        assert_eq!(payload.unit, "raw");
+        assert_eq!(payload.value, -12.5);
+
+        let mut p = packed("2026-03-01T12:00:00Z", 100, vec![60.0]);
+        p.unit = "dba".into();
+        assert_eq!(p.into_readings()[0].unit, "dBA");
+    }
+
+    #[test]
+    fn test_timestamp_shapes() {
+        let expected = "2024-05-02T12:35:00Z".parse::<DateTime<Utc>>().unwrap();
+        let parse = |v: Value| parse_timestamp(&v);
+
+        assert_eq!(parse("2024-05-02T12:35:00Z".into()), Ok(expected));
+        assert_eq!(parse("2024-05-02T14:35:00+02:00".into()), Ok(expected));
+        assert_eq!(parse(1714653300.into()), Ok(expected));
+        assert_eq!(
+            parse(1714653300123i64.into()),
+            Ok(expected + chrono::Duration::milliseconds(123))
+        );
+        // Small values are seconds, even early in the epoch
+        assert_eq!(parse(60.into()).unwrap().timestamp(), 60);
+
+        for garbage in [
+            Value::from("yesterday"),
+            Value::from("2024-05-02 12:35"),
+            Value::from("1714653300"),
+            Value::from(""),
+            Value::from(1714653300.5),
+            Value::Bool(true),
+            serde_json::json!({}),
+        ] {
+            assert!(parse(garbage.clone()).is_err(), "{garbage}");
+        }
+    }
+
+    #[test]
+    fn test_ingest_accepts_epoch_timestamps() {
+        let ingest = |ts: Value| {
+            serde_json::from_value::<IngestReading>(serde_json::json!({
+                "patient_id": "p1", "device_id": "d1", "code": "sound",
+                "value": 1.0, "unit": "raw", "ts": ts,
+            }))
+        };
+
+        let reading = ingest(1714653300123i64.into()).unwrap().into_reading();
+        assert_eq!(reading.ts.timestamp_millis(), 1714653300123);
+        assert_eq!(reading.ts_source, TimestampSource::Device);
+
+        let reading = ingest(1714653300.into()).unwrap().into_reading();
+        assert_eq!(reading.ts.timestamp(), 1714653300);
+
+        let reading = ingest(Value::Null).unwrap().into_reading();
+        assert_eq!(reading.ts_source, TimestampSource::Server);
+
+        let err = ingest("soon".into()).unwrap_err().to_string();
+        assert!(err.contains("field `ts`: invalid timestamp"), "{err}");
+    }
+
+    #[test]
+    fn test_sensor_reading_ts_round_trips_as_rfc3339() {
+        let reading: SensorReading = serde_json::from_value(serde_json::json!({
+            "patient_id": "p1", "device_id": "d1", "code": "sound",
+            "value": 1.0, "unit": "raw", "ts": 1714653300123i64,
+        }))
+        .unwrap();
+
+        let json = serde_json::to_value(&reading).unwrap();
+        assert_eq!(json["ts"], "2024-05-02T12:35:00.123Z");
+        let back: SensorReading = serde_json::from_value(json).unwrap();
+        assert_eq!(back.ts, reading.ts);
+    }
+
+    fn packed(start_ts: &str, interval_ms: u32, values: Vec<f64>) -> PackedReading {
+        serde_json::from_value(serde_json::json!({
+            "patient_id": "p1", "device_id": "d1", "code": "sound", "unit": "dB",
+            "start_ts": start_ts, "interval_ms": interval_ms, "values": values,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_packed_reading_timestamps() {
+        let readings = packed("2026-03-01T12:00:00Z", 100, vec![60.0; 10]).into_readings();
+        assert_eq!(readings.len(), 10);
+        assert_eq!(readings[0].ts.to_rfc3339(), "2026-03-01T12:00:00+00:00");
+        assert_eq!(readings[9].ts.to_rfc3339(), "2026-03-01T12:00:00.900+00:00");
+        assert!(readings
+            .iter()
+            .all(|r| r.ts_source == TimestampSource::Device && r.device_id == "d1"));
+
+        // A packet straddling midnight (and the end of a month) keeps counting
+        let readings = packed("2026-02-28T23:59:59.950Z", 100, vec![1.0, 2.0, 3.0]).into_readings();
+        let ts: Vec<String> = readings.iter().map(|r| r.ts.to_rfc3339()).collect();
+        assert_eq!(
+            ts,
+            [
+                "2026-02-28T23:59:59.950+00:00",
+                "2026-03-01T00:00:00.050+00:00",
+                "2026-03-01T00:00:00.150+00:00",
+            ]
+        );
+        assert_eq!(readings[2].value, 3.0);
+
+        // The largest packet at the longest interval spans 999 minutes
+        let p = packed(
+            "2026-03-01T00:00:00Z",
+            MAX_PACKED_INTERVAL_MS,
+            vec![0.0; MAX_PACKED_SAMPLES],
+        );
+        assert!(p.validate().is_ok());
+        assert_eq!(
+            p.sample_ts(MAX_PACKED_SAMPLES - 1).unwrap().to_rfc3339(),
+            "2026-03-01T16:39:00+00:00"
+        );
+
+        // Samples past the end of time are refused rather than overflowing
+        let mut p = packed("2026-03-01T00:00:00Z", 1000, vec![0.0; 2]);
+        p.start_ts = DateTime::<Utc>::MAX_UTC;
+        assert!(p.sample_ts(0).is_some());
+        assert!(p.sample_ts(1).is_none());
+        assert!(p.sample_ts(usize::MAX).is_none());
+        assert!(p
+            .validate()
+            .unwrap_err()
+            .contains("latest supported timestamp"));
+    }
+
+    #[test]
+    fn test_packed_reading_validation() {
+        assert!(packed("2026-03-01T12:00:00Z", 100, vec![])
+            .validate()
+            .is_err());
+        assert!(packed(
+            "2026-03-01T12:00:00Z",
+            100,
+            vec![0.0; MAX_PACKED_SAMPLES + 1]
+        )
+        .validate()
+        .is_err());
+        assert!(packed("2026-03-01T12:00:00Z", 0, vec![1.0])
+            .validate()
+            .is_err());
+        assert!(packed(
+            "2026-03-01T12:00:00Z",
+            MAX_PACKED_INTERVAL_MS + 1,
+            vec![1.0]
+        )
+        .validate()
+        .is_err());
+
+        let mut p = packed("2026-03-01T12:00:00Z", 100, vec![1.0, 2.0]);
+        p.values[1] = f64::NAN;
+        assert_eq!(p.validate().unwrap_err(), "values[1] must be finite");
+    }
+
+    #[test]
+    fn test_location_metadata() {
+        let reading = |location: Value| {
+            let payload: IngestReading = serde_json::from_value(serde_json::json!({
+                "patient_id": "p1", "device_id": "d1", "code": "sound",
+                "value": 60.0, "unit": "dB", "metadata": { "location": location },
+            }))
+            .unwrap();
+            payload.into_reading()
+        };
+
+        let r = reading(" Ward 3B ".into());
+        assert_eq!(r.location().unwrap(), Some("Ward 3B"));
+        assert_eq!(reading("  ".into()).location().unwrap(), None);
+        assert_eq!(reading(Value::Null).location().unwrap(), None);
+        assert!(reading(3.into()).validate().is_err());
+        assert!(reading("x".repeat(MAX_LOCATION_LEN + 1).into())
+            .validate()
+            .is_err());
+    }
+}
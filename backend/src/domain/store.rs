@@ -1,94 +1,1484 @@
+use crate::alert_suppression::AlertSuppressions;
 use crate::audit::{AuditAction, AuditLogEntry};
 use crate::auth::Claims;
+use crate::bundle_stream::ObservationPages;
+use crate::calibration::{
+    window_values, CalibrationHistory, CalibrationVerification, DEFAULT_TOLERANCE_DB,
+};
+use crate::consent::{Admission, ConsentMode};
+use crate::dashboard::{day_start, hour_of, trend_start, DashboardReadings};
 use crate::db::Database;
-use crate::domain::models::SensorReading;
+use crate::decimation::Decimator;
+use crate::device_auth::DeviceAuthStore;
+use crate::device_stats::{DeviceStats, DeviceStatsTracker};
+use crate::device_watchdog::{DeviceSettingsStore, DeviceStatus};
+use crate::dlq::{DeadLetterQueue, InsertRetry};
+use crate::domain::models::{
+    energy_average, is_decibel_unit, ConsentStatus, OctaveBandReading, OctaveSpectrum, Patient,
+    PatientErasure, PopulationCounts, ReadingFilter, ReadingQuery, SensorReading, Storage,
+    StoredReading,
+};
 use crate::errors::AppError;
-use crate::fhir::{FhirBundle, FhirObservation};
-use std::collections::VecDeque;
+use crate::fhir::{FhirBundle, FhirObservation, ObservationStatus};
+use crate::hearing::HearingProtector;
+use crate::hourly_pattern::HourStats;
+use crate::iot_auth::IotAuthStore;
+use crate::live_stats::LiveStats;
+use crate::log_sampling::LogSampler;
+use crate::ml_client::{Analysis, AnalysisWindow, ScoreSample};
+use crate::noise_map::LocationNoise;
+use crate::normalization::{DeviceProfile, Normalizer};
+use crate::reports::{
+    default_recommendations, DailyExposure, PatientSummaryReport, PeakReading, WEEK_DAYS,
+};
+use crate::rollups::DailyRollup;
+use crate::spectrogram::{frames_in_range, SpectrogramFrame};
+use crate::text_search;
+use crate::training::TrainingJobStore;
+use crate::units::{RawCalibration, TargetUnit, UnitConversion};
+use crate::users::UserStore;
+use crate::validation::ValidationPipeline;
+use actix_web::ResponseError;
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::sync::Arc;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+/// Stored readings are logged at `LOG_SAMPLE_RATE`
+static STORE_LOG: LogSampler = LogSampler::new();
+
+/// Rows `AppState::erase_patient` deletes per database transaction
+const ERASE_CHUNK_ROWS: i64 = 5000;
+
+/// Where `AppState::push` kept a reading
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PushOutcome {
+    pub id: Uuid,
+    /// `Database`, `Buffered` or `Memory`
+    pub storage: Storage,
+}
+
+impl PushOutcome {
+    pub fn persisted(&self) -> bool {
+        self.storage == Storage::Database
+    }
+}
 
 #[derive(Debug)]
 pub struct AppState {
-    readings: VecDeque<SensorReading>,
+    readings: VecDeque<StoredReading>,
+    octave_readings: VecDeque<OctaveBandReading>,
+    spectrogram_frames: VecDeque<SpectrogramFrame>,
     max: usize,
     db: Option<Database>,
+    users: Arc<UserStore>,
+    training_jobs: Arc<TrainingJobStore>,
+    device_auth: Arc<DeviceAuthStore>,
+    iot_auth: Arc<IotAuthStore>,
+    alert_suppressions: Arc<AlertSuppressions>,
+    device_settings: Arc<DeviceSettingsStore>,
+    dlq: Option<Arc<DeadLetterQueue>>,
+    /// Retries of inserts failing transiently, before dead-lettering
+    insert_retry: InsertRetry,
+    consent_mode: ConsentMode,
+    /// Patient registry when running without a database
+    patients: HashMap<String, Patient>,
+    /// Readings received without consent when running without a database
+    quarantined: VecDeque<QuarantinedReading>,
+    /// Hearing protectors when running without a database
+    hearing_protectors: Vec<HearingProtector>,
+    /// Ingest counters per device since startup
+    device_stats: DeviceStatsTracker,
+    /// Largest deviation from the reference tone that passes calibration
+    calibration_tolerance_db: f64,
+    /// Calibration checks per device, newest first, without a database
+    calibrations: HashMap<String, Vec<CalibrationVerification>>,
+    /// Deployment-specific checks run on ingested readings
+    validation: ValidationPipeline,
+    /// Store octave-band readings with their A-weighted level
+    a_weighting: bool,
+    /// Thins out bursts of readings before they are stored
+    decimator: Decimator,
+    /// Maps each device's output scale onto the common one
+    normalizer: Normalizer,
+    /// Rolling statistics of recent readings, for gauges
+    live_stats: Arc<LiveStats>,
+}
+
+/// What the in-memory reading buffer holds, for debugging
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct MemoryStoreStats {
+    pub len: usize,
+    /// Readings kept before the oldest is dropped
+    pub capacity: usize,
+    /// Earliest and latest reading timestamp; `null` while empty
+    pub oldest_ts: Option<DateTime<Utc>>,
+    pub newest_ts: Option<DateTime<Utc>>,
+    /// Readings per signal code
+    pub per_code: BTreeMap<String, usize>,
+}
+
+/// A reading held back because its patient has no consent on record
+#[derive(Debug, Clone)]
+pub struct QuarantinedReading {
+    pub patient_id: String,
+    pub kind: &'static str,
+    pub payload: serde_json::Value,
+    pub reason: &'static str,
+    pub received_at: DateTime<Utc>,
 }
 
 impl AppState {
     pub fn new_demo() -> Self {
         Self {
             readings: VecDeque::new(),
+            octave_readings: VecDeque::new(),
+            spectrogram_frames: VecDeque::new(),
             max: 500,
             db: None,
+            users: Arc::new(UserStore::new(None)),
+            training_jobs: Arc::new(TrainingJobStore::new(None)),
+            device_auth: Arc::new(DeviceAuthStore::new(None)),
+            iot_auth: Arc::new(IotAuthStore::new(None)),
+            alert_suppressions: Arc::new(AlertSuppressions::new(None)),
+            device_settings: Arc::new(DeviceSettingsStore::new(None)),
+            dlq: None,
+            insert_retry: InsertRetry::default(),
+            consent_mode: ConsentMode::Off,
+            patients: HashMap::new(),
+            quarantined: VecDeque::new(),
+            hearing_protectors: Vec::new(),
+            device_stats: DeviceStatsTracker::default(),
+            calibration_tolerance_db: DEFAULT_TOLERANCE_DB,
+            calibrations: HashMap::new(),
+            validation: ValidationPipeline::default(),
+            a_weighting: false,
+            decimator: Decimator::default(),
+            normalizer: Normalizer::default(),
+            live_stats: Arc::new(LiveStats::default()),
         }
     }
 
     pub fn with_database(db: Database) -> Self {
         Self {
             readings: VecDeque::new(),
+            octave_readings: VecDeque::new(),
+            spectrogram_frames: VecDeque::new(),
             max: 500,
+            users: Arc::new(UserStore::new(Some(db.clone()))),
+            training_jobs: Arc::new(TrainingJobStore::new(Some(db.clone()))),
+            device_auth: Arc::new(DeviceAuthStore::new(Some(db.clone()))),
+            iot_auth: Arc::new(IotAuthStore::new(Some(db.clone()))),
+            alert_suppressions: Arc::new(AlertSuppressions::new(Some(db.clone()))),
+            device_settings: Arc::new(DeviceSettingsStore::new(Some(db.clone()))),
             db: Some(db),
+            dlq: None,
+            insert_retry: InsertRetry::default(),
+            consent_mode: ConsentMode::Off,
+            patients: HashMap::new(),
+            quarantined: VecDeque::new(),
+            hearing_protectors: Vec::new(),
+            device_stats: DeviceStatsTracker::default(),
+            calibration_tolerance_db: DEFAULT_TOLERANCE_DB,
+            calibrations: HashMap::new(),
+            validation: ValidationPipeline::default(),
+            a_weighting: false,
+            decimator: Decimator::default(),
+            normalizer: Normalizer::default(),
+            live_stats: Arc::new(LiveStats::default()),
+        }
+    }
+
+    /// Keep up to `max` readings in memory instead of 500
+    pub fn with_max_readings(mut self, max: usize) -> Self {
+        self.max = max;
+        self
+    }
+
+    /// Run `validation` on ingested readings
+    pub fn with_validation(mut self, validation: ValidationPipeline) -> Self {
+        self.validation = validation;
+        self
+    }
+
+    /// Park readings that fail to persist in a dead-letter queue for retry
+    pub fn with_dlq(mut self, dlq: Arc<DeadLetterQueue>) -> Self {
+        self.dlq = Some(dlq);
+        self
+    }
+
+    /// Retry transiently failing inserts per `insert_retry`
+    pub fn with_insert_retry(mut self, insert_retry: InsertRetry) -> Self {
+        self.insert_retry = insert_retry;
+        self
+    }
+
+    /// Replace the training job store (e.g. to change its poll interval)
+    pub fn with_training_jobs(mut self, training_jobs: Arc<TrainingJobStore>) -> Self {
+        self.training_jobs = training_jobs;
+        self
+    }
+
+    /// Replace the user account store (e.g. to change its bcrypt cost)
+    pub fn with_users(mut self, users: Arc<UserStore>) -> Self {
+        self.users = users;
+        self
+    }
+
+    /// Replace the device credential store (e.g. to change how long
+    /// challenges stay open)
+    pub fn with_device_auth(mut self, device_auth: Arc<DeviceAuthStore>) -> Self {
+        self.device_auth = device_auth;
+        self
+    }
+
+    /// Replace the IoT factory key store (e.g. to change how long nonces
+    /// stay valid)
+    pub fn with_iot_auth(mut self, iot_auth: Arc<IotAuthStore>) -> Self {
+        self.iot_auth = iot_auth;
+        self
+    }
+
+    /// Thin out bursts of readings with `decimator`
+    pub fn with_decimator(mut self, decimator: Decimator) -> Self {
+        self.decimator = decimator;
+        self
+    }
+
+    /// Normalize readings by `normalizer`'s device profiles
+    pub fn with_normalizer(mut self, normalizer: Normalizer) -> Self {
+        self.normalizer = normalizer;
+        self
+    }
+
+    /// The device profiles readings are normalized by; ingest applies them
+    /// before validating a reading, and `push` to any it did not
+    pub fn normalizer(&self) -> &Normalizer {
+        &self.normalizer
+    }
+
+    /// Require patient consent before readings are stored
+    pub fn with_consent_mode(mut self, consent_mode: ConsentMode) -> Self {
+        self.consent_mode = consent_mode;
+        self
+    }
+
+    /// Pass calibration checks deviating at most `tolerance_db` from the reference
+    pub fn with_calibration_tolerance(mut self, tolerance_db: f64) -> Self {
+        self.calibration_tolerance_db = tolerance_db;
+        self
+    }
+
+    /// Compute the A-weighted level of octave-band readings on ingest
+    pub fn with_a_weighting(mut self, enabled: bool) -> Self {
+        self.a_weighting = enabled;
+        self
+    }
+
+    pub fn a_weighting(&self) -> bool {
+        self.a_weighting
+    }
+
+    /// Decide, for each of `patient_ids`, whether their readings may be
+    /// stored; in strict mode a patient without consent fails the whole call
+    pub async fn admit(&self, patient_ids: &[&str]) -> Result<Vec<Admission>, AppError> {
+        self.admit_each(patient_ids).await?.into_iter().collect()
+    }
+
+    /// Like `admit`, but decides on each of `patient_ids` separately, so a
+    /// patient without consent in strict mode fails only their own readings
+    pub async fn admit_each(
+        &self,
+        patient_ids: &[&str],
+    ) -> Result<Vec<Result<Admission, AppError>>, AppError> {
+        if self.consent_mode == ConsentMode::Off {
+            return Ok(patient_ids.iter().map(|_| Ok(Admission::Store)).collect());
+        }
+
+        let statuses = match &self.db {
+            Some(db) => db.consent_statuses(patient_ids).await?,
+            None => self
+                .patients
+                .values()
+                .map(|p| (p.patient_id.clone(), p.consent_status))
+                .collect(),
+        };
+        Ok(patient_ids
+            .iter()
+            .map(|id| self.consent_mode.admit(id, statuses.get(*id).copied()))
+            .collect())
+    }
+
+    /// Hold back a reading of a patient without consent. It is kept apart
+    /// from stored readings and never returned by any query.
+    pub async fn quarantine(
+        &mut self,
+        patient_id: &str,
+        kind: &'static str,
+        payload: &impl serde::Serialize,
+        reason: &'static str,
+    ) -> Result<(), AppError> {
+        let payload = serde_json::to_value(payload).map_err(|_| AppError::Internal)?;
+        tracing::info!(
+            patient_id,
+            kind,
+            reason,
+            "Quarantined reading without consent"
+        );
+
+        if let Some(db) = &self.db {
+            db.insert_quarantined_reading(patient_id, kind, &payload, reason)
+                .await?;
+            return Ok(());
+        }
+
+        if self.quarantined.len() >= self.max {
+            self.quarantined.pop_front();
+        }
+        self.quarantined.push_back(QuarantinedReading {
+            patient_id: patient_id.to_string(),
+            kind,
+            payload,
+            reason,
+            received_at: Utc::now(),
+        });
+        Ok(())
+    }
+
+    /// Readings held back in memory (without a database), oldest first
+    pub fn quarantined(&self) -> impl Iterator<Item = &QuarantinedReading> {
+        self.quarantined.iter()
+    }
+
+    pub async fn get_patient(&self, patient_id: &str) -> Result<Option<Patient>, AppError> {
+        if let Some(db) = &self.db {
+            return db.get_patient(patient_id).await;
+        }
+        Ok(self.patients.get(patient_id).cloned())
+    }
+
+    /// The registered patients among `patient_ids`, in the order given
+    pub async fn get_patients(&self, patient_ids: &[String]) -> Result<Vec<Patient>, AppError> {
+        let mut found = match &self.db {
+            Some(db) => db.get_patients(patient_ids).await?,
+            None => patient_ids
+                .iter()
+                .filter_map(|id| Some((id.clone(), self.patients.get(id)?.clone())))
+                .collect(),
+        };
+        Ok(patient_ids
+            .iter()
+            .filter_map(|id| found.remove(id))
+            .collect())
+    }
+
+    /// Status of a registered device, one given settings or provisioned
+    /// with a secret or factory key; active unless its settings say
+    /// otherwise. `None` for devices that were never registered.
+    pub async fn registered_device(
+        &self,
+        device_id: &str,
+    ) -> Result<Option<DeviceStatus>, AppError> {
+        if let Some(settings) = self.device_settings.get(device_id).await? {
+            return Ok(Some(settings.status));
         }
+        let provisioned = self.device_auth.is_provisioned(device_id).await?
+            || self.iot_auth.is_provisioned(device_id).await?;
+        Ok(provisioned.then_some(DeviceStatus::Active))
+    }
+
+    /// Record a patient's consent (registering the patient if needed)
+    pub async fn set_consent(
+        &mut self,
+        patient_id: &str,
+        status: ConsentStatus,
+        label: Option<String>,
+        claims: &Claims,
+    ) -> Result<Patient, AppError> {
+        let previous = self.get_patient(patient_id).await?;
+        let before = previous.as_ref().map(|p| p.consent_status.as_str());
+        let patient = Patient::with_consent(previous.clone(), patient_id, status, label);
+
+        if let Some(db) = &self.db {
+            db.upsert_patient(&patient).await?;
+
+            let audit_entry = AuditLogEntry::new(AuditAction::Update, "Patient".to_string())
+                .with_user(claims.sub.clone(), claims.role.clone())
+                .with_resource_id(patient_id.to_string())
+                .with_patient_id(patient_id.to_string())
+                .with_status_code(200)
+                .with_metadata(serde_json::json!({
+                    "before": { "consent_status": before },
+                    "after": { "consent_status": status.as_str() },
+                }));
+            if let Err(e) = audit_entry.log(db.pool()).await {
+                tracing::warn!(error = ?e, "Failed to log audit event");
+            }
+        } else {
+            self.patients
+                .insert(patient_id.to_string(), patient.clone());
+        }
+        self.validation.register_patient(patient_id);
+
+        tracing::info!(
+            patient_id,
+            before = ?before,
+            after = status.as_str(),
+            "Consent updated by {}",
+            claims.sub
+        );
+        Ok(patient)
     }
 
     /// Push a sensor reading to both database (if available) and in-memory storage
     /// Logs audit trail if user claims provided
     pub async fn push(
         &mut self,
-        r: SensorReading,
+        r: impl Into<StoredReading>,
         claims: Option<&Claims>,
-    ) -> Result<(), AppError> {
-        // Store in database if available
+    ) -> Result<PushOutcome, AppError> {
+        let mut r = r.into();
+        self.normalizer.apply(&mut r);
+        let mut storage = Storage::Memory;
+
+        // Store in database if available; while earlier readings wait in
+        // the write-ahead buffer, queue behind them to keep the order
         if let Some(db) = &self.db {
-            match db.insert_reading(&r).await {
-                Ok(id) => {
-                    tracing::debug!(id = %id, "Stored reading in database");
+            let inserted = if self.has_backlog().await {
+                None
+            } else {
+                Some(self.insert_retry.run(|| db.insert_reading(&r)).await)
+            };
+            match inserted {
+                None => storage = self.buffer(std::slice::from_ref(&r)).await,
+                Some(Ok(id)) => {
+                    storage = Storage::Database;
+                    if STORE_LOG.sample() {
+                        tracing::debug!(id = %id, "Stored reading in database");
+                    }
 
-                    // Log audit event for HIPAA compliance
+                    // Log audit event for HIPAA compliance; readings from
+                    // public ingest are logged without a user
+                    let mut audit_entry =
+                        AuditLogEntry::new(AuditAction::Create, "SensorReading".to_string())
+                            .with_resource_id(id.to_string())
+                            .with_patient_id(r.reading.patient_id.clone())
+                            .with_status_code(200);
                     if let Some(user_claims) = claims {
-                        let audit_entry =
-                            AuditLogEntry::new(AuditAction::Create, "SensorReading".to_string())
-                                .with_user(user_claims.sub.clone(), user_claims.role.clone())
-                                .with_resource_id(id.to_string())
-                                .with_patient_id(r.patient_id.clone())
-                                .with_status_code(200);
-
-                        if let Err(e) = audit_entry.log(db.pool()).await {
-                            tracing::warn!(error = ?e, "Failed to log audit event");
-                            // Don't fail the request if audit logging fails
-                        }
+                        audit_entry = audit_entry
+                            .with_user(user_claims.sub.clone(), user_claims.role.clone());
+                    }
+
+                    if let Err(e) = audit_entry.log(db.pool()).await {
+                        tracing::warn!(error = ?e, "Failed to log audit event");
+                        // Don't fail the request if audit logging fails
                     }
                 }
-                Err(e) => {
+                Some(Err(e)) => {
                     tracing::error!(error = ?e, "Failed to store reading in database, continuing with in-memory only");
-                    // Continue execution - fallback to in-memory
+                    // Keep the reading for a later retry instead of dropping it
+                    storage = self.buffer(std::slice::from_ref(&r)).await;
                 }
             }
         }
 
         // Always store in memory for WebSocket streaming
+        self.live_stats.record(&r.reading, Utc::now());
         if self.readings.len() >= self.max {
             self.readings.pop_front();
         }
+        let id = r.id;
         self.readings.push_back(r);
 
+        Ok(PushOutcome { id, storage })
+    }
+
+    /// Whether earlier readings are still waiting in the write-ahead buffer
+    async fn has_backlog(&self) -> bool {
+        match &self.dlq {
+            Some(dlq) => dlq.pending().await > 0,
+            None => false,
+        }
+    }
+
+    /// Append `records` to the write-ahead buffer, if there is one, for the
+    /// retry task to insert; `Memory` if any of them could not be written
+    async fn buffer(&self, records: &[StoredReading]) -> Storage {
+        let Some(dlq) = &self.dlq else {
+            return Storage::Memory;
+        };
+        let mut storage = Storage::Buffered;
+        for r in records {
+            if let Err(e) = dlq.enqueue_as(r.id, &r.reading, r.raw_value).await {
+                tracing::error!(error = ?e, "Failed to dead-letter reading");
+                storage = Storage::Memory;
+            }
+        }
+        storage
+    }
+
+    /// Push several sensor readings, inserting them into the database (if
+    /// available) in one statement. Creation is audited once per patient
+    /// with the number of readings rather than once per reading.
+    pub async fn push_many(
+        &mut self,
+        mut records: Vec<StoredReading>,
+        claims: Option<&Claims>,
+    ) -> Result<(), AppError> {
+        for r in &mut records {
+            self.normalizer.apply(r);
+        }
+        if let Some(db) = &self.db {
+            let inserted = if self.has_backlog().await {
+                None
+            } else {
+                Some(self.insert_retry.run(|| db.insert_readings(&records)).await)
+            };
+            match inserted {
+                None => {
+                    self.buffer(&records).await;
+                }
+                Some(Ok(())) => {
+                    if let Some(user_claims) = claims {
+                        let mut per_patient: HashMap<&str, usize> = HashMap::new();
+                        for r in &records {
+                            *per_patient
+                                .entry(r.reading.patient_id.as_str())
+                                .or_default() += 1;
+                        }
+                        for (patient_id, count) in per_patient {
+                            let audit_entry = AuditLogEntry::new(
+                                AuditAction::Create,
+                                "SensorReading".to_string(),
+                            )
+                            .with_user(user_claims.sub.clone(), user_claims.role.clone())
+                            .with_patient_id(patient_id.to_string())
+                            .with_status_code(200)
+                            .with_metadata(serde_json::json!({ "count": count }));
+
+                            if let Err(e) = audit_entry.log(db.pool()).await {
+                                tracing::warn!(error = ?e, "Failed to log audit event");
+                            }
+                        }
+                    }
+                }
+                Some(Err(e)) => {
+                    tracing::error!(error = ?e, "Failed to store readings in database, continuing with in-memory only");
+                    self.buffer(&records).await;
+                }
+            }
+        }
+
+        let now = Utc::now();
+        for r in records {
+            self.live_stats.record(&r.reading, now);
+            if self.readings.len() >= self.max {
+                self.readings.pop_front();
+            }
+            self.readings.push_back(r);
+        }
+
         Ok(())
     }
 
-    /// Get recent observations, preferring database if available, fallback to in-memory
+    /// Change a reading's status (and optionally its note), recording the
+    /// change in the audit log. 404 for an unknown id, 403 (audited) if the
+    /// token may not read the patient, 409 if the transition isn't allowed.
+    pub async fn amend_reading(
+        &mut self,
+        id: Uuid,
+        status: ObservationStatus,
+        note: Option<String>,
+        claims: &Claims,
+    ) -> Result<StoredReading, AppError> {
+        let current = match &self.db {
+            Some(db) => db.get_reading(id).await?,
+            None => self.readings.iter().find(|r| r.id == id).cloned(),
+        }
+        .filter(|r| r.deleted_at.is_none())
+        .ok_or_else(|| AppError::NotFound(format!("Observation {}", id)))?;
+
+        if !claims.may_read_patient(&current.reading.patient_id) {
+            tracing::warn!(
+                "User {} attempted to amend a reading of unpermitted patient {}",
+                claims.sub,
+                current.reading.patient_id
+            );
+            self.users()
+                .audit(
+                    AuditLogEntry::new(AuditAction::AccessDenied, "Observation".to_string())
+                        .with_user(claims.sub.clone(), claims.role.clone())
+                        .with_resource_id(id.to_string())
+                        .with_patient_id(current.reading.patient_id.clone())
+                        .with_status_code(403)
+                        .with_error("patient not permitted".to_string()),
+                )
+                .await;
+            return Err(AppError::Forbidden(format!(
+                "no access to patient {}",
+                current.reading.patient_id
+            )));
+        }
+
+        if !current.status.can_amend_to(status) {
+            return Err(AppError::Conflict(format!(
+                "cannot change status from {} to {}",
+                current.status.as_str(),
+                status.as_str()
+            )));
+        }
+
+        let amended = match &self.db {
+            Some(db) => db
+                .amend_reading(id, current.status, status, note.as_deref())
+                .await?
+                .ok_or_else(|| {
+                    AppError::Conflict(format!("Observation {} was changed concurrently", id))
+                })?,
+            None => StoredReading {
+                status,
+                note: note.clone().or_else(|| current.note.clone()),
+                ..current.clone()
+            },
+        };
+
+        // Keep the in-memory copy in step
+        if let Some(cached) = self.readings.iter_mut().find(|r| r.id == id) {
+            *cached = amended.clone();
+        }
+
+        if let Some(db) = &self.db {
+            let audit_entry = AuditLogEntry::new(AuditAction::Update, "Observation".to_string())
+                .with_user(claims.sub.clone(), claims.role.clone())
+                .with_resource_id(id.to_string())
+                .with_patient_id(amended.reading.patient_id.clone())
+                .with_status_code(200)
+                .with_metadata(serde_json::json!({
+                    "before": { "status": current.status },
+                    "after": { "status": amended.status },
+                    "note": note,
+                }));
+
+            if let Err(e) = audit_entry.log(db.pool()).await {
+                tracing::warn!(error = ?e, "Failed to log audit event");
+            }
+        }
+
+        Ok(amended)
+    }
+
+    /// Mark a reading deleted instead of erasing it, recording the deletion
+    /// in the audit log. 404 for an unknown or already deleted id.
+    pub async fn delete_reading(
+        &mut self,
+        id: Uuid,
+        claims: &Claims,
+    ) -> Result<StoredReading, AppError> {
+        let deleted = match &self.db {
+            Some(db) => db.soft_delete_reading(id).await?,
+            None => self
+                .readings
+                .iter()
+                .find(|r| r.id == id && r.deleted_at.is_none())
+                .map(|r| StoredReading {
+                    deleted_at: Some(Utc::now()),
+                    ..r.clone()
+                }),
+        }
+        .ok_or_else(|| AppError::NotFound(format!("Observation {}", id)))?;
+
+        // Keep the in-memory copy in step
+        if let Some(cached) = self.readings.iter_mut().find(|r| r.id == id) {
+            *cached = deleted.clone();
+        }
+
+        self.users.audit(deletion_audit(&deleted, claims)).await;
+
+        Ok(deleted)
+    }
+
+    /// Erase every reading of a patient who withdrew consent: database rows
+    /// (in chunks), readings waiting in the dead-letter queue, the in-memory
+    /// buffer, and the registry entries of devices no one else used. One
+    /// audit entry records the count, or the error if the erasure failed;
+    /// earlier audit rows are kept. 404 for a patient with neither a record
+    /// nor readings.
+    pub async fn erase_patient(
+        &mut self,
+        patient_id: &str,
+        claims: &Claims,
+    ) -> Result<PatientErasure, AppError> {
+        let entry = AuditLogEntry::new(AuditAction::Delete, "Patient".to_string())
+            .with_user(claims.sub.clone(), claims.role.clone())
+            .with_resource_id(patient_id.to_string())
+            .with_patient_id(patient_id.to_string());
+
+        let erased = self.erase_patient_data(patient_id).await;
+        let entry = match &erased {
+            Ok(erasure) => entry
+                .with_status_code(200)
+                .with_metadata(serde_json::json!({
+                    "erased_readings": erasure.readings,
+                    "unregistered_devices": erasure.devices,
+                })),
+            Err(e) => entry
+                .with_status_code(e.status_code().as_u16().into())
+                .with_error(e.to_string()),
+        };
+        self.users.audit(entry).await;
+        erased
+    }
+
+    async fn erase_patient_data(&mut self, patient_id: &str) -> Result<PatientErasure, AppError> {
+        let registered = self.get_patient(patient_id).await?.is_some();
+        let devices: BTreeSet<String> = self
+            .readings
+            .iter()
+            .map(|r| &r.reading)
+            .filter(|r| r.patient_id == patient_id)
+            .map(|r| r.device_id.clone())
+            .chain(
+                self.octave_readings
+                    .iter()
+                    .filter(|r| r.patient_id == patient_id)
+                    .map(|r| r.device_id.clone()),
+            )
+            .chain(
+                self.spectrogram_frames
+                    .iter()
+                    .filter(|f| f.patient_id == patient_id)
+                    .map(|f| f.device_id.clone()),
+            )
+            .collect();
+        let buffered = self
+            .readings
+            .iter()
+            .filter(|r| r.reading.patient_id == patient_id)
+            .count() as u64;
+
+        // Dead-lettered readings go first, so the retry task can't insert
+        // them behind the database deletes
+        let dead_lettered = match &self.dlq {
+            Some(dlq) => dlq.purge_patient(patient_id).await? as u64,
+            None => 0,
+        };
+        let erased = match &self.db {
+            Some(db) => Some(db.erase_patient(patient_id, ERASE_CHUNK_ROWS).await?),
+            None => None,
+        };
+
+        self.readings.retain(|r| r.reading.patient_id != patient_id);
+        self.octave_readings.retain(|r| r.patient_id != patient_id);
+        self.spectrogram_frames
+            .retain(|f| f.patient_id != patient_id);
+        self.quarantined.retain(|q| q.patient_id != patient_id);
+
+        let mut erasure = erased.unwrap_or_else(|| PatientErasure {
+            readings: buffered,
+            devices: devices
+                .iter()
+                .filter(|d| !self.device_in_use(d))
+                .cloned()
+                .collect(),
+        });
+        if self.db.is_some() {
+            erasure.readings += dead_lettered;
+        }
+        if !registered && erasure.readings == 0 && devices.is_empty() {
+            return Err(AppError::NotFound(format!("patient {}", patient_id)));
+        }
+
+        for device_id in &erasure.devices {
+            self.device_stats.forget(device_id);
+            self.decimator.forget(device_id);
+            self.normalizer.forget(device_id);
+        }
+        self.device_settings.forget(&erasure.devices).await;
+
+        Ok(erasure)
+    }
+
+    /// Whether any reading, octave spectrum or spectrogram frame in memory
+    /// came from `device_id`
+    fn device_in_use(&self, device_id: &str) -> bool {
+        self.readings
+            .iter()
+            .any(|r| r.reading.device_id == device_id)
+            || self
+                .octave_readings
+                .iter()
+                .any(|r| r.device_id == device_id)
+            || self
+                .spectrogram_frames
+                .iter()
+                .any(|f| f.device_id == device_id)
+    }
+
+    /// Basic statistics of the readings taken from `start` up to `end`
+    /// (excluded); `None` if there were none
+    pub async fn reading_stats(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> Result<Option<Analysis>, AppError> {
+        if let Some(db) = &self.db {
+            return db.reading_stats(start, end).await;
+        }
+
+        Ok(Analysis::summarize(
+            self.readings
+                .iter()
+                .filter(|r| r.is_usable() && r.reading.ts >= start && r.reading.ts < end)
+                .map(|r| (r.reading.ts, r.reading.value)),
+        ))
+    }
+
+    /// Up to `limit` of a patient's usable readings between `start` and
+    /// `end`, oldest first
+    pub async fn patient_readings(
+        &self,
+        patient_id: &str,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        limit: usize,
+    ) -> Result<Vec<SensorReading>, AppError> {
+        if let Some(db) = &self.db {
+            return db.patient_readings(patient_id, start, end, limit).await;
+        }
+
+        let mut readings: Vec<_> = self
+            .readings
+            .iter()
+            .filter(|r| r.is_usable() && r.reading.patient_id == patient_id)
+            .filter(|r| r.reading.ts >= start && r.reading.ts <= end)
+            .map(|r| r.reading.clone())
+            .collect();
+        readings.sort_by_key(|r| r.ts);
+        readings.truncate(limit);
+        Ok(readings)
+    }
+
+    /// A patient's usable readings in `window`, oldest first
+    pub async fn patient_samples(
+        &self,
+        patient_id: &str,
+        window: &AnalysisWindow,
+    ) -> Result<Vec<ScoreSample>, AppError> {
+        if let Some(db) = &self.db {
+            return db.patient_samples(patient_id, window).await;
+        }
+
+        let (after, before, limit) = window.bounds(Utc::now());
+        let mut samples: Vec<_> = self
+            .readings
+            .iter()
+            .filter(|r| r.is_usable() && r.reading.patient_id == patient_id)
+            .filter(|r| after.is_none_or(|t| r.reading.ts >= t))
+            .filter(|r| before.is_none_or(|t| r.reading.ts < t))
+            .map(|r| ScoreSample {
+                value: r.reading.value,
+                timestamp: r.reading.ts,
+            })
+            .collect();
+        samples.sort_by_key(|s| s.timestamp);
+        Ok(samples.split_off(samples.len().saturating_sub(limit)))
+    }
+
+    /// A patient's usable readings in `unit` (ignoring case), or converted
+    /// to it if it is a `TargetUnit` and they can be, from `start` to `end`
+    /// by hour of the day at `offset`, hours without readings left out
+    pub async fn hourly_stats(
+        &self,
+        patient_id: &str,
+        unit: &str,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        offset: chrono::FixedOffset,
+    ) -> Result<Vec<HourStats>, AppError> {
+        if let Some(db) = &self.db {
+            return db.hourly_stats(patient_id, unit, start, end, offset).await;
+        }
+
+        let units = match unit.parse::<TargetUnit>() {
+            Ok(target) => Some(self.unit_conversion(target, true).await?),
+            Err(_) => None,
+        };
+        Ok(HourStats::from_values(
+            self.readings
+                .iter()
+                .filter(|r| r.is_usable() && r.reading.patient_id == patient_id)
+                .filter(|r| r.reading.ts >= start && r.reading.ts <= end)
+                .filter_map(|r| {
+                    let value = match &units {
+                        Some(units) => units.value(&r.reading)?,
+                        None => Some(r.reading.value)
+                            .filter(|_| r.reading.unit.eq_ignore_ascii_case(unit))?,
+                    };
+                    Some((r.reading.ts, value))
+                }),
+            offset,
+        ))
+    }
+
+    /// Exposure populations per signal code for readings between `start` and `end`
+    pub async fn population_counts(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> Result<HashMap<&'static str, PopulationCounts>, AppError> {
+        if let Some(db) = &self.db {
+            return db.population_counts(start, end).await;
+        }
+
+        Ok(PopulationCounts::from_readings(
+            self.readings
+                .iter()
+                .filter(|r| r.reading.ts >= start && r.reading.ts <= end),
+        ))
+    }
+
+    /// Store an octave-band reading. Unlike `push`, a database failure is
+    /// returned to the caller: spectra aren't dead-lettered.
+    pub async fn push_octave_bands(
+        &mut self,
+        r: OctaveBandReading,
+        claims: Option<&Claims>,
+    ) -> Result<(), AppError> {
+        if let Some(db) = &self.db {
+            let id = db.insert_octave_band_reading(&r).await?;
+            tracing::debug!(id = %id, "Stored octave band reading in database");
+
+            if let Some(user_claims) = claims {
+                let audit_entry =
+                    AuditLogEntry::new(AuditAction::Create, "OctaveBandReading".to_string())
+                        .with_user(user_claims.sub.clone(), user_claims.role.clone())
+                        .with_resource_id(id.to_string())
+                        .with_patient_id(r.patient_id.clone())
+                        .with_status_code(200);
+
+                if let Err(e) = audit_entry.log(db.pool()).await {
+                    tracing::warn!(error = ?e, "Failed to log audit event");
+                }
+            }
+        }
+
+        if self.octave_readings.len() >= self.max {
+            self.octave_readings.pop_front();
+        }
+        self.octave_readings.push_back(r);
+
+        Ok(())
+    }
+
+    pub async fn push_spectrogram_frame(
+        &mut self,
+        frame: SpectrogramFrame,
+        claims: Option<&Claims>,
+    ) -> Result<(), AppError> {
+        if let Some(db) = &self.db {
+            let id = db.insert_spectrogram_frame(&frame).await?;
+            tracing::debug!(id = %id, "Stored spectrogram frame in database");
+
+            if let Some(user_claims) = claims {
+                let audit_entry =
+                    AuditLogEntry::new(AuditAction::Create, "SpectrogramFrame".to_string())
+                        .with_user(user_claims.sub.clone(), user_claims.role.clone())
+                        .with_resource_id(id.to_string())
+                        .with_patient_id(frame.patient_id.clone())
+                        .with_status_code(201);
+
+                if let Err(e) = audit_entry.log(db.pool()).await {
+                    tracing::warn!(error = ?e, "Failed to log audit event");
+                }
+            }
+            return Ok(());
+        }
+
+        if self.spectrogram_frames.len() >= self.max {
+            self.spectrogram_frames.pop_front();
+        }
+        self.spectrogram_frames.push_back(frame);
+
+        Ok(())
+    }
+
+    /// A patient's spectrogram frames from `start` to `end` (inclusive),
+    /// oldest first, at most `max_frames`
+    pub async fn spectrogram(
+        &self,
+        patient_id: &str,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        max_frames: usize,
+    ) -> Result<Vec<SpectrogramFrame>, AppError> {
+        if let Some(db) = &self.db {
+            return db
+                .spectrogram_frames(patient_id, start, end, max_frames)
+                .await;
+        }
+        Ok(frames_in_range(
+            &self.spectrogram_frames,
+            patient_id,
+            start,
+            end,
+            max_frames,
+        ))
+    }
+
+    /// Day-averaged octave spectrum for a patient (UTC day)
+    pub async fn octave_spectrum(
+        &self,
+        patient_id: &str,
+        date: NaiveDate,
+    ) -> Result<OctaveSpectrum, AppError> {
+        if let Some(db) = &self.db {
+            return db.octave_spectrum(patient_id, date).await;
+        }
+
+        let readings = self
+            .octave_readings
+            .iter()
+            .filter(|r| r.patient_id == patient_id && r.ts.date_naive() == date);
+        Ok(OctaveSpectrum::from_readings(
+            patient_id.to_string(),
+            date,
+            readings,
+        ))
+    }
+
+    /// Time-weighted average of a patient's decibel readings on one UTC day
+    /// (see `PopulationCounts::from_readings`); `None` without any
+    pub async fn daily_twa(
+        &self,
+        patient_id: &str,
+        date: NaiveDate,
+    ) -> Result<Option<f64>, AppError> {
+        let start = date.and_hms_opt(0, 0, 0).unwrap_or_default().and_utc();
+        let end = start + chrono::Duration::days(1);
+        if let Some(db) = &self.db {
+            return db.patient_twa(patient_id, start, end).await;
+        }
+
+        Ok(energy_average(
+            self.readings
+                .iter()
+                .filter(|r| {
+                    r.is_usable()
+                        && r.reading.patient_id == patient_id
+                        && r.reading.ts >= start
+                        && r.reading.ts < end
+                        && is_decibel_unit(&r.reading.unit)
+                })
+                .map(|r| r.reading.value),
+        ))
+    }
+
+    /// A patient's exposure over the week from `week_start`, compared to the
+    /// week before; `None` without decibel readings that week. A week or its
+    /// predecessor past the representable dates is a `BadRequest`.
+    pub async fn patient_summary(
+        &self,
+        patient_id: &str,
+        week_start: NaiveDate,
+    ) -> Result<Option<PatientSummaryReport>, AppError> {
+        let start = week_start
+            .and_hms_opt(0, 0, 0)
+            .unwrap_or_default()
+            .and_utc();
+        let week = chrono::Duration::days(WEEK_DAYS);
+        let (Some(end), Some(previous_start)) = (
+            start.checked_add_signed(week),
+            start.checked_sub_signed(week),
+        ) else {
+            return Err(AppError::BadRequest(format!(
+                "week_start {} is out of range",
+                week_start
+            )));
+        };
+
+        let (days, peak, recommendations) = match &self.db {
+            Some(db) => (
+                db.patient_daily_twas(patient_id, previous_start, end)
+                    .await?,
+                db.patient_peak(patient_id, start, end).await?,
+                db.list_recommendations().await?,
+            ),
+            None => {
+                let readings: Vec<_> = self
+                    .readings
+                    .iter()
+                    .filter(|r| {
+                        r.is_usable()
+                            && r.reading.patient_id == patient_id
+                            && r.reading.ts >= previous_start
+                            && r.reading.ts < end
+                            && is_decibel_unit(&r.reading.unit)
+                    })
+                    .map(|r| &r.reading)
+                    .collect();
+
+                let mut by_day: std::collections::BTreeMap<NaiveDate, Vec<f64>> =
+                    Default::default();
+                for r in &readings {
+                    by_day.entry(r.ts.date_naive()).or_default().push(r.value);
+                }
+                let days = by_day
+                    .into_iter()
+                    .filter_map(|(date, values)| {
+                        Some(DailyExposure {
+                            date,
+                            twa_db: energy_average(values.into_iter())?,
+                        })
+                    })
+                    .collect();
+                let peak = readings.iter().filter(|r| r.ts >= start).fold(
+                    None::<PeakReading>,
+                    |peak, r| match peak {
+                        Some(p)
+                            if p.value_db > r.value || (p.value_db == r.value && p.at <= r.ts) =>
+                        {
+                            Some(p)
+                        }
+                        _ => Some(PeakReading {
+                            value_db: r.value,
+                            at: r.ts,
+                        }),
+                    },
+                );
+                (days, peak, default_recommendations())
+            }
+        };
+
+        let (previous_week, this_week): (Vec<_>, Vec<_>) =
+            days.into_iter().partition(|d| d.date < week_start);
+        Ok(PatientSummaryReport::build(
+            patient_id,
+            week_start,
+            &this_week,
+            &previous_week,
+            peak,
+            &recommendations,
+        ))
+    }
+
+    /// A patient's latest reading (that `units` keeps, if given), hourly
+    /// levels since `trend_start(now)`, today's TWA and the devices that
+    /// reported for them over that time
+    pub async fn dashboard_readings(
+        &self,
+        patient_id: &str,
+        now: DateTime<Utc>,
+        units: Option<&UnitConversion>,
+    ) -> Result<DashboardReadings, AppError> {
+        let since = trend_start(now);
+        if let Some(db) = &self.db {
+            return db
+                .dashboard_readings(patient_id, since, day_start(now), units)
+                .await;
+        }
+
+        let readings: Vec<_> = self
+            .readings
+            .iter()
+            .filter(|r| r.is_usable() && r.reading.patient_id == patient_id)
+            .map(|r| &r.reading)
+            .collect();
+        let decibels = || {
+            readings
+                .iter()
+                .filter(|r| r.ts >= since && is_decibel_unit(&r.unit))
+        };
+
+        let mut by_hour: std::collections::BTreeMap<DateTime<Utc>, Vec<f64>> = Default::default();
+        for r in decibels() {
+            by_hour.entry(hour_of(r.ts)).or_default().push(r.value);
+        }
+        let mut device_last_seen: std::collections::BTreeMap<String, DateTime<Utc>> =
+            Default::default();
+        for r in readings.iter().filter(|r| r.ts >= since) {
+            let seen = device_last_seen.entry(r.device_id.clone()).or_insert(r.ts);
+            *seen = (*seen).max(r.ts);
+        }
+
+        Ok(DashboardReadings {
+            last_reading: readings
+                .iter()
+                .filter(|r| units.is_none_or(|units| units.keeps(r)))
+                .max_by_key(|r| r.ts)
+                .map(|r| (*r).clone()),
+            hourly_db: by_hour
+                .into_iter()
+                .filter_map(|(hour, values)| Some((hour, energy_average(values.into_iter())?)))
+                .collect(),
+            today_twa_db: energy_average(
+                decibels()
+                    .filter(|r| r.ts >= day_start(now))
+                    .map(|r| r.value),
+            ),
+            device_last_seen: device_last_seen.into_iter().collect(),
+        })
+    }
+
+    /// Noise per location from `start` to `end`, for locations with at
+    /// least `min_count` readings
+    pub async fn noise_map(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        min_count: usize,
+    ) -> Result<Vec<LocationNoise>, AppError> {
+        if let Some(db) = &self.db {
+            return db.noise_map(start, end, min_count).await;
+        }
+
+        Ok(LocationNoise::from_readings(
+            &self.readings,
+            start,
+            end,
+            min_count,
+        ))
+    }
+
+    /// A patient's daily rollups from `from` to `to` (inclusive). Without a
+    /// database they are computed from the in-memory readings (UTC days),
+    /// leaving out today like the nightly job does.
+    pub async fn daily_rollups(
+        &self,
+        patient_id: &str,
+        from: NaiveDate,
+        to: NaiveDate,
+    ) -> Result<Vec<DailyRollup>, AppError> {
+        if let Some(db) = &self.db {
+            return db.daily_rollups(patient_id, from, to).await;
+        }
+
+        let yesterday = Utc::now().date_naive() - chrono::Duration::days(1);
+        Ok(DailyRollup::from_readings(
+            patient_id,
+            &self.readings,
+            from,
+            to.min(yesterday),
+        ))
+    }
+
+    /// All hearing protectors, weakest first
+    pub async fn hearing_protectors(&self) -> Result<Vec<HearingProtector>, AppError> {
+        if let Some(db) = &self.db {
+            return db.list_hearing_protectors().await;
+        }
+        let mut protectors = self.hearing_protectors.clone();
+        protectors.sort_by(|a, b| a.nrr_db.cmp(&b.nrr_db).then_with(|| a.name.cmp(&b.name)));
+        Ok(protectors)
+    }
+
+    pub async fn add_hearing_protector(
+        &mut self,
+        protector: HearingProtector,
+    ) -> Result<(), AppError> {
+        if let Some(db) = &self.db {
+            return db.insert_hearing_protector(&protector).await;
+        }
+        self.hearing_protectors.push(protector);
+        Ok(())
+    }
+
+    /// Replace the protector with `protector.id`; `false` if there is none
+    pub async fn update_hearing_protector(
+        &mut self,
+        protector: HearingProtector,
+    ) -> Result<bool, AppError> {
+        if let Some(db) = &self.db {
+            return db.update_hearing_protector(&protector).await;
+        }
+        match self
+            .hearing_protectors
+            .iter_mut()
+            .find(|p| p.id == protector.id)
+        {
+            Some(existing) => {
+                *existing = protector;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Delete a protector; `false` if there is none with `id`
+    pub async fn delete_hearing_protector(&mut self, id: Uuid) -> Result<bool, AppError> {
+        if let Some(db) = &self.db {
+            return db.delete_hearing_protector(id).await;
+        }
+        let before = self.hearing_protectors.len();
+        self.hearing_protectors.retain(|p| p.id != id);
+        Ok(self.hearing_protectors.len() < before)
+    }
+
+    /// Check `device_id`'s readings taken from `start` to `end` against a
+    /// reference tone of `reference_db`, recording the outcome. 409 if the
+    /// device sent no decibel or raw readings in that window.
+    pub async fn verify_calibration(
+        &mut self,
+        device_id: &str,
+        reference_db: f64,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> Result<CalibrationVerification, AppError> {
+        let values = match &self.db {
+            Some(db) => db.device_window_values(device_id, start, end).await?,
+            None => window_values(device_id, &self.readings, start, end),
+        };
+        if values.is_empty() {
+            return Err(AppError::Conflict(format!(
+                "no decibel or raw readings from device {} during the verification window",
+                device_id
+            )));
+        }
+
+        let verification = CalibrationVerification::evaluate(
+            device_id,
+            end,
+            reference_db,
+            &values,
+            self.calibration_tolerance_db,
+        );
+        tracing::info!(
+            device_id,
+            deviation_db = ?verification.deviation_db,
+            measured_avg_raw = ?verification.measured_avg_raw,
+            passed = verification.passed,
+            "Calibration verified"
+        );
+
+        if let Some(db) = &self.db {
+            db.insert_calibration_verification(&verification).await?;
+        } else {
+            self.calibrations
+                .entry(device_id.to_string())
+                .or_default()
+                .insert(0, verification.clone());
+        }
+        Ok(verification)
+    }
+
+    /// A device's calibration status and past verifications
+    pub async fn calibration_history(
+        &self,
+        device_id: &str,
+    ) -> Result<CalibrationHistory, AppError> {
+        if let Some(db) = &self.db {
+            return Ok(CalibrationHistory {
+                device_id: device_id.to_string(),
+                calibration_status: db.device_calibration_status(device_id).await?,
+                verifications: db.calibration_verifications(device_id).await?,
+            });
+        }
+        let verifications = self
+            .calibrations
+            .get(device_id)
+            .cloned()
+            .unwrap_or_default();
+        Ok(CalibrationHistory::new(device_id, verifications))
+    }
+
+    /// Count a reading of `device_id` that passed validation
+    pub fn record_accepted(&mut self, device_id: &str, reading_ts: DateTime<Utc>) {
+        self.device_stats.record_accepted(device_id, reading_ts);
+    }
+
+    /// Count a reading of `device_id` quarantined for lack of consent
+    pub fn record_quarantined(&mut self, device_id: &str) {
+        self.device_stats.record_quarantined(device_id);
+    }
+
+    /// Count a refused reading of `device_id`
+    pub fn record_rejected(&mut self, device_id: &str, message: &str) {
+        self.device_stats.record_rejected(device_id, message);
+    }
+
+    /// Whether decimation drops `reading`; dropped readings are counted
+    /// against their device
+    pub fn decimate(&mut self, reading: &SensorReading) -> bool {
+        if self.decimator.keep(reading) {
+            return false;
+        }
+        self.device_stats.record_decimated(&reading.device_id);
+        true
+    }
+
+    /// Decimate `device_id`'s readings every `interval_ms` (0 for never), or
+    /// with `None` at the deployment default
+    pub fn set_decimation_interval(&mut self, device_id: &str, interval_ms: Option<u64>) {
+        self.decimator.set_interval(device_id, interval_ms);
+    }
+
+    /// Normalize `device_id`'s readings by `profile`, or with `None` store
+    /// them as sent
+    pub fn set_device_profile(&mut self, device_id: &str, profile: Option<DeviceProfile>) {
+        self.normalizer.set_profile(device_id, profile);
+    }
+
+    /// When each device sending since startup was last heard from
+    pub fn device_last_seen(&self) -> HashMap<String, DateTime<Utc>> {
+        self.device_stats.last_seen()
+    }
+
+    /// Ingest statistics of a device, with database totals when available;
+    /// `None` if the device is unknown to both
+    pub async fn device_stats(&self, device_id: &str) -> Result<Option<DeviceStats>, AppError> {
+        let decimation_interval_ms = self.decimator.interval_ms(device_id);
+        let stats = self.device_stats.stats(device_id).map(|stats| DeviceStats {
+            decimation_interval_ms,
+            ..stats
+        });
+        let Some(db) = &self.db else {
+            return Ok(stats);
+        };
+
+        let totals = db.device_totals(device_id).await?;
+        if stats.is_none() && totals.total_readings == 0 {
+            return Ok(None);
+        }
+        let mut stats = stats.unwrap_or_else(|| DeviceStats {
+            decimation_interval_ms,
+            ..self.device_stats.empty_stats(device_id)
+        });
+        stats.database = Some(totals);
+        Ok(Some(stats))
+    }
+
+    /// Get recent observations matching `filter`, preferring database if
+    /// available, fallback to in-memory. The `offset` newest matches are skipped.
     pub async fn recent_observations(
         &self,
         limit: usize,
-        code_filter: Option<&str>,
+        offset: usize,
+        filter: &ReadingFilter<'_>,
     ) -> Result<Vec<FhirObservation>, AppError> {
+        Ok(self
+            .recent_readings(limit, offset, filter)
+            .await?
+            .into_iter()
+            .map(FhirObservation::from_stored)
+            .collect())
+    }
+
+    /// The readings of `recent_observations`
+    async fn recent_readings(
+        &self,
+        limit: usize,
+        offset: usize,
+        filter: &ReadingFilter<'_>,
+    ) -> Result<Vec<StoredReading>, AppError> {
+        if filter.patients.is_some_and(|p| p.is_empty()) {
+            return Ok(Vec::new());
+        }
+
         // Try database first
         if let Some(db) = &self.db {
-            match db.get_recent_readings(limit, code_filter).await {
-                Ok(readings) => {
-                    return Ok(readings
-                        .into_iter()
-                        .map(FhirObservation::from_reading)
-                        .collect());
-                }
+            match db.get_recent_readings(limit, offset, filter).await {
+                Ok(readings) => return Ok(readings),
                 Err(e) => {
                     tracing::warn!(error = ?e, "Failed to query database, falling back to in-memory");
                     // Fall through to in-memory fallback
@@ -97,26 +1487,170 @@ impl AppState {
         }
 
         // Fallback to in-memory
-        let n = limit.min(self.readings.len());
-        let observations: Vec<_> = self
+        Ok(self
             .readings
             .iter()
             .rev()
-            .take(n)
+            .filter(|r| filter.matches(r))
+            .skip(offset)
+            .take(limit)
+            .cloned()
+            .collect())
+    }
+
+    /// Conversion of observations to `target` by the devices' current
+    /// calibration records
+    pub async fn unit_conversion(
+        &self,
+        target: TargetUnit,
+        strict: bool,
+    ) -> Result<UnitConversion, AppError> {
+        let calibrations = match &self.db {
+            Some(db) => db.raw_calibrations().await?,
+            None => RawCalibration::latest(&self.calibrations),
+        };
+        Ok(UnitConversion {
+            target,
+            strict,
+            calibrations,
+        })
+    }
+
+    /// How many readings match `query`, and the readings themselves newest
+    /// first, a page at a time, for streaming rather than holding them all
+    pub async fn observation_pages(
+        &self,
+        query: ReadingQuery,
+        page_size: usize,
+    ) -> Result<(usize, ObservationPages), AppError> {
+        if let Some(db) = &self.db {
+            let (total, cursor) = db.open_reading_cursor(query, page_size).await?;
+            return Ok((total, ObservationPages::Database(Box::new(cursor))));
+        }
+
+        let filter = query.filter();
+        let readings: Vec<_> = self
+            .readings
+            .iter()
+            .rev()
+            .filter(|r| filter.matches(r))
             .cloned()
-            .map(FhirObservation::from_reading)
             .collect();
+        Ok((
+            readings.len(),
+            ObservationPages::Memory {
+                readings: readings.into_iter(),
+                page_size: page_size.max(1),
+            },
+        ))
+    }
+
+    /// Readings matching `filter` in (effective time, id) order after
+    /// `after`, at most `limit`. Unlike `recent_observations` there is no
+    /// fallback: a bulk export must not quietly switch to the in-memory
+    /// readings halfway through.
+    pub async fn readings_after(
+        &self,
+        after: Option<(DateTime<Utc>, Uuid)>,
+        limit: usize,
+        filter: &ReadingFilter<'_>,
+    ) -> Result<Vec<StoredReading>, AppError> {
+        if filter.patients.is_some_and(|p| p.is_empty()) {
+            return Ok(Vec::new());
+        }
+        if let Some(db) = &self.db {
+            return db.readings_after(after, limit, filter).await;
+        }
 
-        Ok(observations)
+        let mut readings: Vec<_> = self
+            .readings
+            .iter()
+            .filter(|r| filter.matches(r))
+            .filter(|r| after.is_none_or(|after| (r.reading.ts, r.id) > after))
+            .collect();
+        readings.sort_by_key(|r| (r.reading.ts, r.id));
+        Ok(readings.into_iter().take(limit).cloned().collect())
     }
 
+    /// Number of observations `recent_observations` pages through
+    pub async fn count_observations(&self, filter: &ReadingFilter<'_>) -> Result<usize, AppError> {
+        if filter.patients.is_some_and(|p| p.is_empty()) {
+            return Ok(0);
+        }
+
+        if let Some(db) = &self.db {
+            match db.count_readings(filter).await {
+                Ok(count) => return Ok(count),
+                Err(e) => {
+                    tracing::warn!(error = ?e, "Failed to query database, falling back to in-memory");
+                }
+            }
+        }
+
+        Ok(self.readings.iter().filter(|r| filter.matches(r)).count())
+    }
+
+    /// Observations matching `filter` whose note or metadata contain the
+    /// words of `terms`, best first, at most `limit`
+    pub async fn search_observations(
+        &self,
+        terms: &str,
+        limit: usize,
+        filter: &ReadingFilter<'_>,
+    ) -> Result<FhirBundle, AppError> {
+        if filter.patients.is_some_and(|p| p.is_empty()) {
+            return Ok(FhirBundle::from_matches(Vec::new()));
+        }
+
+        let matches = match &self.db {
+            Some(db) => db.search_readings(terms, limit, filter).await?,
+            None => text_search::search_readings(self.readings.iter(), terms, limit, filter),
+        };
+        Ok(FhirBundle::from_matches(
+            matches
+                .into_iter()
+                .filter_map(|(r, score)| Some((observation(r, filter.units)?, score)))
+                .collect(),
+        ))
+    }
+
+    /// Distinct tags starting with `prefix` on readings matching `filter`
+    pub async fn suggest_tags(
+        &self,
+        prefix: &str,
+        limit: usize,
+        filter: &ReadingFilter<'_>,
+    ) -> Result<Vec<String>, AppError> {
+        if filter.patients.is_some_and(|p| p.is_empty()) {
+            return Ok(Vec::new());
+        }
+
+        match &self.db {
+            Some(db) => db.suggest_tags(prefix, limit, filter).await,
+            None => Ok(text_search::suggest_tags(
+                &self.readings,
+                prefix,
+                limit,
+                filter,
+            )),
+        }
+    }
+
+    /// Bundle of `recent_observations`, converted by the filter's units if
+    /// given
     pub async fn bundle(
         &self,
         limit: usize,
-        code_filter: Option<&str>,
+        offset: usize,
+        filter: &ReadingFilter<'_>,
     ) -> Result<FhirBundle, AppError> {
-        let observations = self.recent_observations(limit, code_filter).await?;
-        Ok(FhirBundle::from_obs(observations))
+        let readings = self.recent_readings(limit, offset, filter).await?;
+        Ok(FhirBundle::from_obs(
+            readings
+                .into_iter()
+                .filter_map(|r| observation(r, filter.units))
+                .collect(),
+        ))
     }
 
     pub async fn health_check(&self) -> Result<bool, AppError> {
@@ -132,8 +1666,286 @@ impl AppState {
         self.db.is_some()
     }
 
-    pub async fn bundle_by_code(&self, limit: usize, code: &str) -> Result<FhirBundle, AppError> {
-        let observations = self.recent_observations(limit, Some(code)).await?;
-        Ok(FhirBundle::from_obs(observations))
+    /// Copy of the in-memory readings, oldest first
+    pub fn snapshot(&self) -> Vec<StoredReading> {
+        self.readings.iter().cloned().collect()
+    }
+
+    /// Size, time span and codes of the in-memory readings
+    pub fn memory_stats(&self) -> MemoryStoreStats {
+        let mut per_code = BTreeMap::new();
+        for stored in &self.readings {
+            *per_code
+                .entry(stored.reading.code.as_str().to_string())
+                .or_insert(0) += 1;
+        }
+        MemoryStoreStats {
+            len: self.readings.len(),
+            capacity: self.max,
+            oldest_ts: self.readings.iter().map(|r| r.reading.ts).min(),
+            newest_ts: self.readings.iter().map(|r| r.reading.ts).max(),
+            per_code,
+        }
+    }
+
+    /// Replace the in-memory readings with a snapshot's, keeping the newest
+    /// that fit
+    pub fn restore_from_snapshot(&mut self, readings: Vec<StoredReading>) {
+        let skip = readings.len().saturating_sub(self.max);
+        self.readings = readings.into_iter().skip(skip).collect();
+    }
+
+    /// User account store (shares the database, if configured)
+    pub fn users(&self) -> Arc<UserStore> {
+        self.users.clone()
+    }
+
+    /// Deployment-specific checks for ingested readings
+    pub fn validation(&self) -> &ValidationPipeline {
+        &self.validation
+    }
+
+    /// Device secrets and challenges (shares the database, if configured)
+    pub fn device_auth(&self) -> Arc<DeviceAuthStore> {
+        self.device_auth.clone()
+    }
+
+    pub fn iot_auth(&self) -> Arc<IotAuthStore> {
+        self.iot_auth.clone()
+    }
+
+    /// Alert suppression windows (shares the database, if configured)
+    pub fn alert_suppressions(&self) -> Arc<AlertSuppressions> {
+        self.alert_suppressions.clone()
+    }
+
+    /// Device status and offline grace periods (shares the database, if
+    /// configured)
+    pub fn device_settings(&self) -> Arc<DeviceSettingsStore> {
+        self.device_settings.clone()
+    }
+
+    /// Rolling statistics of recent readings, shared with WebSocket
+    /// sessions
+    pub fn live_stats(&self) -> Arc<LiveStats> {
+        self.live_stats.clone()
+    }
+
+    /// ML training job store (shares the database, if configured)
+    pub fn training_jobs(&self) -> Arc<TrainingJobStore> {
+        self.training_jobs.clone()
+    }
+
+    /// Dead-letter queue for failed writes, if configured
+    pub fn dlq(&self) -> Option<Arc<DeadLetterQueue>> {
+        self.dlq.clone()
+    }
+
+    /// Handle to the database, if configured
+    pub fn database(&self) -> Option<Database> {
+        self.db.clone()
+    }
+}
+
+/// Observation of `stored`, converted by `units` if given; `None` if it
+/// can't be and the conversion is strict
+fn observation(stored: StoredReading, units: Option<&UnitConversion>) -> Option<FhirObservation> {
+    match units {
+        Some(units) => units.observation(stored),
+        None => Some(FhirObservation::from_stored(stored)),
+    }
+}
+
+/// Audit entry of a reading's deletion, keeping what was deleted
+fn deletion_audit(deleted: &StoredReading, claims: &Claims) -> AuditLogEntry {
+    AuditLogEntry::new(AuditAction::Delete, "Observation".to_string())
+        .with_user(claims.sub.clone(), claims.role.clone())
+        .with_resource_id(deleted.id.to_string())
+        .with_patient_id(deleted.reading.patient_id.clone())
+        .with_status_code(204)
+        .with_metadata(serde_json::json!({
+            "deleted_at": deleted.deleted_at,
+            "status": deleted.status,
+            "device_id": deleted.reading.device_id,
+            "value": deleted.reading.value,
+            "unit": deleted.reading.unit,
+            "effective": deleted.reading.ts,
+        }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::models::{SensorReading, SignalCode, TimestampSource};
+
+    fn reading(patient_id: &str) -> SensorReading {
+        SensorReading {
+            patient_id: patient_id.into(),
+            device_id: "d1".into(),
+            code: SignalCode::Sound,
+            value: 70.0,
+            unit: "dB".into(),
+            ts: Utc::now(),
+            ts_source: TimestampSource::Device,
+            metadata: Default::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_push_without_database_is_not_persisted() {
+        let mut state = AppState::new_demo();
+        let record = StoredReading::new(reading("p1"));
+        let outcome = state.push(record.clone(), None).await.unwrap();
+        assert_eq!(outcome.id, record.id);
+        assert_eq!(outcome.storage, Storage::Memory);
+        assert!(!outcome.persisted());
+    }
+
+    #[tokio::test]
+    async fn test_outage_buffers_readings_and_flushes_them_in_order() {
+        let path =
+            std::env::temp_dir().join(format!("soundsense-wal-{}.jsonl", uuid::Uuid::new_v4()));
+        let dlq = Arc::new(DeadLetterQueue::open(&path, 10).unwrap());
+        // Nothing listens here, so every insert fails as during an outage
+        let pool = sqlx::postgres::PgPoolOptions::new()
+            .acquire_timeout(std::time::Duration::from_millis(200))
+            .connect_lazy("postgres://soundsense@127.0.0.1:1/soundsense")
+            .unwrap();
+        let mut state = AppState::with_database(Database::new(pool))
+            .with_dlq(dlq.clone())
+            .with_insert_retry(InsertRetry::new(1, std::time::Duration::ZERO));
+
+        let mut ids = Vec::new();
+        for value in [1.0, 2.0, 3.0] {
+            let outcome = state
+                .push(
+                    StoredReading::new(SensorReading {
+                        value,
+                        ..reading("p1")
+                    }),
+                    None,
+                )
+                .await
+                .unwrap();
+            assert_eq!(outcome.storage, Storage::Buffered);
+            assert!(!outcome.persisted());
+            ids.push(outcome.id);
+        }
+        assert_eq!(dlq.pending().await, 3);
+        // Still served from memory meanwhile
+        assert_eq!(state.snapshot().len(), 3);
+
+        // Recovery drains the buffer oldest first, under the original ids
+        let flushed = std::sync::Mutex::new(Vec::new());
+        let count = dlq
+            .retry_entries_with(|entry| {
+                flushed
+                    .lock()
+                    .unwrap()
+                    .push((entry.id, entry.reading.value));
+                async { Ok(()) }
+            })
+            .await
+            .unwrap();
+        assert_eq!(count, 3);
+        assert_eq!(
+            *flushed.lock().unwrap(),
+            vec![(ids[0], 1.0), (ids[1], 2.0), (ids[2], 3.0)]
+        );
+        assert_eq!(dlq.pending().await, 0);
+
+        std::fs::remove_file(path).ok();
+    }
+
+    #[tokio::test]
+    async fn test_deleted_readings_are_kept_but_hidden() {
+        let mut state = AppState::new_demo();
+        let kept = StoredReading::new(reading("p1"));
+        let deleted = StoredReading::new(reading("p1"));
+        state.push(kept.clone(), None).await.unwrap();
+        state.push(deleted.clone(), None).await.unwrap();
+
+        let admin = Claims::new("admin".into(), "admin".into(), None, 1);
+        let removed = state.delete_reading(deleted.id, &admin).await.unwrap();
+        assert!(removed.deleted_at.is_some());
+        assert!(matches!(
+            state.delete_reading(deleted.id, &admin).await,
+            Err(AppError::NotFound(_))
+        ));
+        assert!(matches!(
+            state
+                .amend_reading(deleted.id, ObservationStatus::Amended, None, &admin)
+                .await,
+            Err(AppError::NotFound(_))
+        ));
+
+        let filter = ReadingFilter::default();
+        assert_eq!(state.count_observations(&filter).await.unwrap(), 1);
+        let (start, end) = (Utc::now() - chrono::Duration::hours(1), Utc::now());
+        let readings = state.patient_readings("p1", start, end, 10).await.unwrap();
+        assert_eq!(readings.len(), 1);
+
+        let filter = ReadingFilter {
+            include_deleted: true,
+            ..filter
+        };
+        let all = state.recent_observations(10, 0, &filter).await.unwrap();
+        assert_eq!(all.len(), 2);
+        let shown = all.iter().find(|o| o.id == deleted.id.to_string()).unwrap();
+        assert_eq!(shown.status, ObservationStatus::EnteredInError);
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_round_trip() {
+        let mut state = AppState::new_demo();
+        for value in [55.0, 70.5, 91.25] {
+            let mut r = reading("p1");
+            r.value = value;
+            state.push(StoredReading::new(r), None).await.unwrap();
+        }
+        let snapshot = state.snapshot();
+
+        let mut restored = AppState::new_demo();
+        restored.restore_from_snapshot(snapshot.clone());
+        let filter = ReadingFilter::default();
+        assert_eq!(restored.count_observations(&filter).await.unwrap(), 3);
+        let again = restored.snapshot();
+        assert_eq!(
+            again
+                .iter()
+                .map(|r| (r.id, r.reading.value))
+                .collect::<Vec<_>>(),
+            snapshot
+                .iter()
+                .map(|r| (r.id, r.reading.value))
+                .collect::<Vec<_>>()
+        );
+
+        // Only the newest readings that fit are kept
+        restored.max = 2;
+        restored.restore_from_snapshot(snapshot.clone());
+        let kept: Vec<f64> = restored
+            .snapshot()
+            .iter()
+            .map(|r| r.reading.value)
+            .collect();
+        assert_eq!(kept, vec![70.5, 91.25]);
+    }
+
+    #[test]
+    fn test_deletion_is_audited() {
+        let mut deleted = StoredReading::new(reading("p1"));
+        deleted.deleted_at = Some(Utc::now());
+        let admin = Claims::new("admin".into(), "admin".into(), None, 1);
+
+        let entry = deletion_audit(&deleted, &admin);
+        assert!(matches!(entry.action, AuditAction::Delete));
+        assert_eq!(entry.resource_type, "Observation");
+        assert_eq!(entry.resource_id, Some(deleted.id.to_string()));
+        assert_eq!(entry.patient_id.as_deref(), Some("p1"));
+        assert_eq!(entry.user_id.as_deref(), Some("admin"));
+        let metadata = entry.metadata.unwrap();
+        assert_eq!(metadata["value"], 70.0);
+        assert!(metadata["deleted_at"].is_string());
     }
 }
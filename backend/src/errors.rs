@@ -1,6 +1,13 @@
-use actix_web::{http::StatusCode, HttpResponse, ResponseError};
+use actix_web::{
+    http::{header, StatusCode},
+    HttpResponse, ResponseError,
+};
 use serde::Serialize;
 use thiserror::Error;
+use utoipa::ToSchema;
+
+use crate::auth::bearer_challenge;
+use crate::timeout::REQUEST_ID_HEADER;
 
 #[derive(Debug, Error)]
 pub enum AppError {
@@ -10,13 +17,72 @@ pub enum AppError {
     #[error("bad request: {0}")]
     BadRequest(String),
 
+    #[error("forbidden: {0}")]
+    Forbidden(String),
+
+    #[error("not found: {0}")]
+    NotFound(String),
+
+    #[error("conflict: {0}")]
+    Conflict(String),
+
+    #[error("payload too large: {0}")]
+    PayloadTooLarge(String),
+
+    #[error("unsupported media type: {0}")]
+    UnsupportedMediaType(String),
+
+    #[error("not acceptable: {0}")]
+    NotAcceptable(String),
+
+    #[error("too many requests: retry after {0} seconds")]
+    TooManyRequests(u64),
+
     #[error("internal error")]
     Internal,
+
+    #[error("service unavailable: database query timed out")]
+    Timeout,
+
+    #[error("service unavailable: {0}")]
+    ServiceUnavailable(String),
+
+    /// An upstream service (the ML service) refused or failed our request
+    #[error("bad gateway: {0}")]
+    BadGateway(String),
+
+    /// The handler ran past the request deadline; carries the request id
+    #[error("gateway timeout: request {0} took too long")]
+    RequestTimeout(String),
 }
 
-#[derive(Serialize)]
-struct ErrBody {
+impl AppError {
+    /// Stable machine-readable error code, sent alongside the message
+    pub fn code(&self) -> &'static str {
+        match self {
+            AppError::Unauthorized => "unauthorized",
+            AppError::BadRequest(_) => "bad_request",
+            AppError::Forbidden(_) => "forbidden",
+            AppError::NotFound(_) => "not_found",
+            AppError::Conflict(_) => "conflict",
+            AppError::PayloadTooLarge(_) => "payload_too_large",
+            AppError::UnsupportedMediaType(_) => "unsupported_media_type",
+            AppError::NotAcceptable(_) => "not_acceptable",
+            AppError::TooManyRequests(_) => "too_many_requests",
+            AppError::Internal => "internal",
+            AppError::Timeout => "timeout",
+            AppError::ServiceUnavailable(_) => "service_unavailable",
+            AppError::BadGateway(_) => "bad_gateway",
+            AppError::RequestTimeout(_) => "request_timeout",
+        }
+    }
+}
+
+/// JSON body of every error response
+#[derive(Serialize, ToSchema)]
+pub(crate) struct ErrBody {
     error: String,
+    code: &'static str,
 }
 
 impl ResponseError for AppError {
@@ -24,13 +90,38 @@ impl ResponseError for AppError {
         match self {
             AppError::Unauthorized => StatusCode::UNAUTHORIZED,
             AppError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            AppError::Forbidden(_) => StatusCode::FORBIDDEN,
+            AppError::NotFound(_) => StatusCode::NOT_FOUND,
+            AppError::Conflict(_) => StatusCode::CONFLICT,
+            AppError::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            AppError::UnsupportedMediaType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            AppError::NotAcceptable(_) => StatusCode::NOT_ACCEPTABLE,
+            AppError::TooManyRequests(_) => StatusCode::TOO_MANY_REQUESTS,
             AppError::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+            AppError::Timeout => StatusCode::SERVICE_UNAVAILABLE,
+            AppError::ServiceUnavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            AppError::BadGateway(_) => StatusCode::BAD_GATEWAY,
+            AppError::RequestTimeout(_) => StatusCode::GATEWAY_TIMEOUT,
         }
     }
 
     fn error_response(&self) -> HttpResponse {
-        HttpResponse::build(self.status_code()).json(ErrBody {
+        let mut resp = HttpResponse::build(self.status_code());
+        match self {
+            AppError::TooManyRequests(retry_after_secs) => {
+                resp.insert_header((header::RETRY_AFTER, retry_after_secs.to_string()));
+            }
+            AppError::Unauthorized => {
+                resp.insert_header((header::WWW_AUTHENTICATE, bearer_challenge(None)));
+            }
+            AppError::RequestTimeout(request_id) => {
+                resp.insert_header((REQUEST_ID_HEADER, request_id.as_str()));
+            }
+            _ => {}
+        }
+        resp.json(ErrBody {
             error: self.to_string(),
+            code: self.code(),
         })
     }
 }
@@ -1,73 +1,581 @@
 use chrono::{DateTime, Utc};
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::RwLock;
+use utoipa::ToSchema;
 use uuid::Uuid;
 
-use crate::domain::models::{SensorReading, SignalCode};
+use crate::device_watchdog::DeviceStatus;
+use crate::domain::models::{
+    ExtendedReading, OctaveBandReading, Patient, PopulationCounts, SignalCode, StoredReading,
+    TimestampSource, OCTAVE_BAND_COUNT,
+};
+use crate::units::UCUM_SYSTEM;
 
-#[derive(Debug, Serialize, Clone)]
+/// FHIR release the resources here follow (R4)
+pub const FHIR_VERSION: &str = "4.0.1";
+
+/// Code system of the signal codes
+pub const LOINC_SYSTEM: &str = "http://loinc.org";
+
+/// Subject references are this prefix followed by the patient id
+pub const DEFAULT_SUBJECT_BASE: &str = "Patient/";
+
+/// The deployment's subject prefix; `None` until set from `FHIR_SUBJECT_BASE`
+static SUBJECT_BASE: RwLock<Option<String>> = RwLock::new(None);
+
+/// A configured subject prefix, ending in `/`; the default when blank
+pub fn normalize_subject_base(base: Option<&str>) -> String {
+    match base.map(str::trim).filter(|b| !b.is_empty()) {
+        Some(base) if base.ends_with('/') => base.to_string(),
+        Some(base) => format!("{}/", base),
+        None => DEFAULT_SUBJECT_BASE.to_string(),
+    }
+}
+
+/// Set the subject prefix from `FHIR_SUBJECT_BASE`, e.g.
+/// `https://fhir.example.org/Patient/`; returns the prefix in use
+pub fn init_subject_base_from_env() -> String {
+    let base = normalize_subject_base(std::env::var("FHIR_SUBJECT_BASE").ok().as_deref());
+    *SUBJECT_BASE.write().unwrap_or_else(|e| e.into_inner()) = Some(base.clone());
+    base
+}
+
+/// Reference to `patient_id` under the deployment's subject prefix
+pub fn subject_reference(patient_id: &str) -> String {
+    let base = SUBJECT_BASE.read().unwrap_or_else(|e| e.into_inner());
+    format!(
+        "{}{}",
+        base.as_deref().unwrap_or(DEFAULT_SUBJECT_BASE),
+        patient_id
+    )
+}
+
+/// The patient id in a subject reference made by `subject_reference`
+pub fn subject_patient_id(reference: &str) -> Option<&str> {
+    let base = SUBJECT_BASE.read().unwrap_or_else(|e| e.into_inner());
+    let base = base.as_deref().unwrap_or(DEFAULT_SUBJECT_BASE);
+    reference.strip_prefix(base).filter(|id| !id.is_empty())
+}
+
+/// Device references are this prefix followed by the device id
+pub const DEVICE_REFERENCE_BASE: &str = "Device/";
+
+/// Reference to the device `device_id`
+pub fn device_reference(device_id: &str) -> String {
+    format!("{}{}", DEVICE_REFERENCE_BASE, device_id)
+}
+
+/// The device id in a reference made by `device_reference`
+pub fn device_reference_id(reference: &str) -> Option<&str> {
+    reference
+        .strip_prefix(DEVICE_REFERENCE_BASE)
+        .filter(|id| !id.is_empty())
+}
+
+/// Codings published for a signal after its own, e.g. SNOMED CT ones for
+/// systems keyed on those; set from `FHIR_EXTRA_CODINGS`
+static EXTRA_CODINGS: RwLock<Vec<(SignalCode, FhirCoding)>> = RwLock::new(Vec::new());
+
+/// Extra codings of a `;`-separated list of `signal=system|code|display`
+/// entries; the display defaults to the signal's own
+pub fn parse_extra_codings(list: &str) -> Result<Vec<(SignalCode, FhirCoding)>, String> {
+    let mut codings = Vec::new();
+    for entry in list.split(';').map(str::trim).filter(|e| !e.is_empty()) {
+        let (signal, coding) = entry
+            .split_once('=')
+            .ok_or_else(|| format!("'{}' is not signal=system|code|display", entry))?;
+        let signal = signal.trim().parse::<SignalCode>()?;
+        let mut parts = coding.splitn(3, '|').map(str::trim);
+        let system = parts.next().unwrap_or_default();
+        let code = parts.next().unwrap_or_default();
+        let display = parts
+            .next()
+            .filter(|d| !d.is_empty())
+            .unwrap_or(signal.display());
+        if !system.starts_with("http://") && !system.starts_with("https://") {
+            return Err(format!("coding system must be a URI: '{}'", system));
+        }
+        if code.is_empty() {
+            return Err(format!("'{}' has no code", entry));
+        }
+        codings.push((
+            signal,
+            FhirCoding {
+                system: system.into(),
+                code: code.into(),
+                display: display.into(),
+            },
+        ));
+    }
+    Ok(codings)
+}
+
+/// Replace the extra codings published for each signal
+pub fn set_extra_codings(codings: Vec<(SignalCode, FhirCoding)>) {
+    *EXTRA_CODINGS.write().unwrap_or_else(|e| e.into_inner()) = codings;
+}
+
+/// Set the extra codings from `FHIR_EXTRA_CODINGS`; returns how many there
+/// are. A malformed entry is an error rather than skipped, so a typo can't
+/// silently drop a coding an integration relies on.
+pub fn init_extra_codings_from_env() -> Result<usize, String> {
+    let list = std::env::var("FHIR_EXTRA_CODINGS").unwrap_or_default();
+    let codings = parse_extra_codings(&list).map_err(|e| format!("FHIR_EXTRA_CODINGS: {}", e))?;
+    let count = codings.len();
+    set_extra_codings(codings);
+    Ok(count)
+}
+
+/// Whether `reference` is a literal reference: `Type/id`, optionally behind
+/// an absolute `http(s)://` base. The id is everything after the type, so it
+/// may contain slashes itself.
+fn is_valid_reference(reference: &str) -> bool {
+    let is_resource_type = |segment: &str| {
+        segment
+            .chars()
+            .next()
+            .is_some_and(|c| c.is_ascii_uppercase())
+            && segment.chars().all(|c| c.is_ascii_alphanumeric())
+    };
+
+    match reference
+        .strip_prefix("https://")
+        .or_else(|| reference.strip_prefix("http://"))
+    {
+        // The host comes first and is not part of the reference. The base
+        // path before the type is the server's own, so the type is any
+        // segment that looks like one and is followed by an id.
+        Some(url) => match url.split_once('/') {
+            Some((host, path)) if !host.is_empty() => path.match_indices('/').any(|(i, _)| {
+                let segment = path[..i].rsplit('/').next().unwrap_or_default();
+                is_resource_type(segment) && i + 1 < path.len()
+            }),
+            _ => false,
+        },
+        // Other schemes aren't resolvable references
+        None if reference.contains("://") => false,
+        None => reference
+            .split_once('/')
+            .is_some_and(|(resource_type, id)| is_resource_type(resource_type) && !id.is_empty()),
+    }
+}
+
+#[derive(Debug, Serialize, Clone, ToSchema)]
 pub struct FhirCoding {
-    pub system: &'static str,
-    pub code: &'static str,
-    pub display: &'static str,
+    pub system: String,
+    pub code: String,
+    pub display: String,
 }
 
-#[derive(Debug, Serialize, Clone)]
+#[derive(Debug, Serialize, Clone, ToSchema)]
 pub struct FhirCode {
     pub coding: Vec<FhirCoding>,
     pub text: &'static str,
 }
 
-#[derive(Debug, Serialize, Clone)]
+#[derive(Debug, Serialize, Clone, ToSchema)]
 pub struct FhirQuantity {
     pub value: f64,
     pub unit: String,
+    /// UCUM, for quantities converted to a requested unit
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub system: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<String>,
+}
+
+impl FhirQuantity {
+    /// `value` in `unit` as stored, without a coded unit
+    pub fn new(value: f64, unit: impl Into<String>) -> Self {
+        Self {
+            value,
+            unit: unit.into(),
+            system: None,
+            code: None,
+        }
+    }
+
+    /// `value` in `unit`, coded as the UCUM unit `code`
+    pub fn ucum(value: f64, unit: impl Into<String>, code: &str) -> Self {
+        Self {
+            system: Some(UCUM_SYSTEM.into()),
+            code: Some(code.into()),
+            ..Self::new(value, unit)
+        }
+    }
 }
 
-#[derive(Debug, Serialize, Clone)]
+#[derive(Debug, Serialize, Clone, ToSchema)]
 pub struct FhirReference {
     pub reference: String,
 }
 
-#[derive(Debug, Serialize, Clone)]
+#[derive(Debug, Serialize, Clone, ToSchema)]
+pub struct FhirMeta {
+    pub tag: Vec<FhirCoding>,
+}
+
+/// FHIR R4 Observation status (http://hl7.org/fhir/observation-status)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum ObservationStatus {
+    Registered,
+    Preliminary,
+    Final,
+    Amended,
+    Corrected,
+    Cancelled,
+    EnteredInError,
+    Unknown,
+}
+
+impl ObservationStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ObservationStatus::Registered => "registered",
+            ObservationStatus::Preliminary => "preliminary",
+            ObservationStatus::Final => "final",
+            ObservationStatus::Amended => "amended",
+            ObservationStatus::Corrected => "corrected",
+            ObservationStatus::Cancelled => "cancelled",
+            ObservationStatus::EnteredInError => "entered-in-error",
+            ObservationStatus::Unknown => "unknown",
+        }
+    }
+
+    /// Whether a stored reading in this status may be amended to `next`.
+    /// Final and amended readings can be amended (again), cancelled or marked
+    /// entered-in-error; cancelled and entered-in-error are terminal.
+    pub fn can_amend_to(&self, next: ObservationStatus) -> bool {
+        matches!(self, ObservationStatus::Final | ObservationStatus::Amended)
+            && matches!(
+                next,
+                ObservationStatus::Amended
+                    | ObservationStatus::Cancelled
+                    | ObservationStatus::EnteredInError
+            )
+    }
+}
+
+impl TryFrom<String> for ObservationStatus {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        serde_json::from_value(serde_json::Value::String(value))
+            .map_err(|e| format!("unknown observation status: {}", e))
+    }
+}
+
+/// FHIR R4 Observation category
+/// (http://terminology.hl7.org/CodeSystem/observation-category)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum ObservationCategory {
+    SocialHistory,
+    VitalSigns,
+    Imaging,
+    Laboratory,
+    Procedure,
+    Survey,
+    Exam,
+    Therapy,
+    Activity,
+}
+
+impl ObservationCategory {
+    pub const SYSTEM: &'static str = "http://terminology.hl7.org/CodeSystem/observation-category";
+
+    /// Category of readings of `code` unless the device says otherwise.
+    /// Sound monitoring measures the patient's environment during activity.
+    pub fn for_signal(code: &SignalCode) -> Self {
+        match code {
+            SignalCode::Sound => ObservationCategory::Activity,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ObservationCategory::SocialHistory => "social-history",
+            ObservationCategory::VitalSigns => "vital-signs",
+            ObservationCategory::Imaging => "imaging",
+            ObservationCategory::Laboratory => "laboratory",
+            ObservationCategory::Procedure => "procedure",
+            ObservationCategory::Survey => "survey",
+            ObservationCategory::Exam => "exam",
+            ObservationCategory::Therapy => "therapy",
+            ObservationCategory::Activity => "activity",
+        }
+    }
+
+    pub fn display(&self) -> &'static str {
+        match self {
+            ObservationCategory::SocialHistory => "Social History",
+            ObservationCategory::VitalSigns => "Vital Signs",
+            ObservationCategory::Imaging => "Imaging",
+            ObservationCategory::Laboratory => "Laboratory",
+            ObservationCategory::Procedure => "Procedure",
+            ObservationCategory::Survey => "Survey",
+            ObservationCategory::Exam => "Exam",
+            ObservationCategory::Therapy => "Therapy",
+            ObservationCategory::Activity => "Activity",
+        }
+    }
+
+    /// The category as an Observation `category` entry
+    pub fn to_code(self) -> FhirCode {
+        FhirCode {
+            coding: vec![FhirCoding {
+                system: Self::SYSTEM.into(),
+                code: self.as_str().into(),
+                display: self.display().into(),
+            }],
+            text: self.display(),
+        }
+    }
+}
+
+impl TryFrom<String> for ObservationCategory {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        serde_json::from_value(serde_json::Value::String(value))
+            .map_err(|e| format!("unknown observation category: {}", e))
+    }
+}
+
+#[derive(Debug, Serialize, Clone, ToSchema)]
+pub struct FhirAnnotation {
+    pub text: String,
+}
+
+/// Code for the A-weighted level of an octave-band reading
+const A_WEIGHTED_CODE: (&str, &str) = ("sound-level-a-weighted", "Sound level, A-weighted");
+
+/// Code of an octave-band panel; its bands are components
+const OCTAVE_BANDS_CODE: &str = "sound-octave-bands";
+
+/// Codes for the octave bands, in `OctaveBandReading::bands` order
+const OCTAVE_BAND_CODES: [(&str, &str); OCTAVE_BAND_COUNT] = [
+    ("sound-octave-63hz", "Sound level, 63 Hz octave band"),
+    ("sound-octave-125hz", "Sound level, 125 Hz octave band"),
+    ("sound-octave-250hz", "Sound level, 250 Hz octave band"),
+    ("sound-octave-500hz", "Sound level, 500 Hz octave band"),
+    ("sound-octave-1khz", "Sound level, 1 kHz octave band"),
+    ("sound-octave-2khz", "Sound level, 2 kHz octave band"),
+    ("sound-octave-4khz", "Sound level, 4 kHz octave band"),
+    ("sound-octave-8khz", "Sound level, 8 kHz octave band"),
+];
+
+/// Observation (or component) code of a signal: its LOINC coding, then any
+/// extra ones configured for it
+fn signal_code(code: &SignalCode) -> FhirCode {
+    signal_code_with(
+        code,
+        &EXTRA_CODINGS.read().unwrap_or_else(|e| e.into_inner()),
+    )
+}
+
+/// `signal_code` with the given extra codings instead of the configured ones
+fn signal_code_with(code: &SignalCode, extra: &[(SignalCode, FhirCoding)]) -> FhirCode {
+    let mut coding = vec![FhirCoding {
+        system: LOINC_SYSTEM.into(),
+        code: code.as_str().into(),
+        display: code.display().into(),
+    }];
+    coding.extend(
+        extra
+            .iter()
+            .filter(|(signal, _)| signal == code)
+            .map(|(_, c)| c.clone()),
+    );
+    FhirCode {
+        coding,
+        text: code.display(),
+    }
+}
+
+/// Each coding of `code` needs a URI system and a code, and may appear only
+/// once
+fn validate_codings(code: &FhirCode) -> Result<(), String> {
+    for (idx, coding) in code.coding.iter().enumerate() {
+        if coding.system.is_empty() {
+            return Err("Coding system is required".into());
+        }
+        if !coding.system.starts_with("http://") && !coding.system.starts_with("https://") {
+            return Err(format!(
+                "Coding system must be a valid URI: {}",
+                coding.system
+            ));
+        }
+        if coding.code.is_empty() {
+            return Err("Coding code is required".into());
+        }
+        if code.coding[..idx]
+            .iter()
+            .any(|c| c.system == coding.system && c.code == coding.code)
+        {
+            return Err(format!(
+                "Coding {}|{} appears more than once",
+                coding.system, coding.code
+            ));
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug, Serialize, Clone, ToSchema)]
+pub struct FhirComponent {
+    pub code: FhirCode,
+    #[serde(rename = "valueQuantity")]
+    pub value_quantity: FhirQuantity,
+}
+
+#[derive(Debug, Serialize, Clone, ToSchema)]
 pub struct FhirObservation {
     #[serde(rename = "resourceType")]
     pub resource_type: &'static str,
     pub id: String,
-    pub status: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub meta: Option<FhirMeta>,
+    pub status: ObservationStatus,
+    pub category: Vec<FhirCode>,
     pub code: FhirCode,
     pub subject: FhirReference,
+    /// The device that took the reading
+    pub device: FhirReference,
     #[serde(rename = "effectiveDateTime")]
     pub effective_date_time: DateTime<Utc>,
     #[serde(rename = "valueQuantity")]
     pub value_quantity: FhirQuantity,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub component: Vec<FhirComponent>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub note: Vec<FhirAnnotation>,
+    /// `none` when the value could not be converted to the unit the query
+    /// asked for and is given as stored
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub conversion: Option<&'static str>,
 }
 
 impl FhirObservation {
-    pub fn from_reading(r: SensorReading) -> Self {
-        let (code, display) = match r.code {
-            SignalCode::Sound => ("sound", "Sound Level"),
+    /// Observation for a new reading, with a fresh id and status final. The
+    /// reading's extras, if any, become components.
+    pub fn from_reading(r: impl Into<ExtendedReading>) -> Self {
+        let ExtendedReading { reading, extras } = r.into();
+        let mut obs = Self::from_stored(StoredReading::new(reading));
+        obs.component = extras
+            .into_iter()
+            .map(|extra| FhirComponent {
+                code: signal_code(&extra.code),
+                value_quantity: FhirQuantity::new(extra.value, extra.unit),
+            })
+            .collect();
+        obs
+    }
+
+    /// Observation for a stored reading, keeping its id, status and note
+    pub fn from_stored(stored: StoredReading) -> Self {
+        let StoredReading {
+            id,
+            reading: r,
+            status,
+            note,
+            category,
+            deleted_at,
+            raw_value: _,
+        } = stored;
+        // Deleted readings are only ever shown to admins, flagged as such
+        let status = match deleted_at {
+            Some(_) => ObservationStatus::EnteredInError,
+            None => status,
         };
+        // Flag readings whose effectiveDateTime was assigned on ingest
+        let meta = match r.ts_source {
+            TimestampSource::Device => None,
+            TimestampSource::Server => Some(FhirMeta {
+                tag: vec![FhirCoding {
+                    system: "urn:soundsense:timestamp-source".into(),
+                    code: "server-assigned".into(),
+                    display: "Timestamp assigned by server".into(),
+                }],
+            }),
+        };
+
+        Self {
+            resource_type: "Observation",
+            id: id.to_string(),
+            meta,
+            status,
+            category: vec![category.to_code()],
+            code: signal_code(&r.code),
+            subject: FhirReference {
+                reference: subject_reference(&r.patient_id),
+            },
+            device: FhirReference {
+                reference: device_reference(&r.device_id),
+            },
+            effective_date_time: r.ts,
+            value_quantity: FhirQuantity::new(r.value, r.unit),
+            component: Vec::new(),
+            note: note
+                .into_iter()
+                .map(|text| FhirAnnotation { text })
+                .collect(),
+            conversion: None,
+        }
+    }
+
+    /// Octave-band reading: the overall level is the main value, each band a
+    /// component
+    pub fn from_octave_band(r: &OctaveBandReading) -> Self {
+        let display = "Sound Level (octave bands)";
+        let a_weighted = r
+            .calibrated_value
+            .map(|value| (A_WEIGHTED_CODE, value, "dB(A)"));
+        let component = OCTAVE_BAND_CODES
+            .iter()
+            .zip(r.bands)
+            .map(|(&code, value)| (code, value, "dB"))
+            .chain(a_weighted)
+            .map(|((code, display), value, unit)| FhirComponent {
+                code: FhirCode {
+                    coding: vec![FhirCoding {
+                        system: "http://loinc.org".into(),
+                        code: code.into(),
+                        display: display.into(),
+                    }],
+                    text: display,
+                },
+                value_quantity: FhirQuantity::new(value, unit),
+            })
+            .collect();
 
         Self {
             resource_type: "Observation",
             id: Uuid::new_v4().to_string(),
-            status: "final",
+            meta: None,
+            status: ObservationStatus::Final,
+            category: vec![ObservationCategory::for_signal(&SignalCode::Sound).to_code()],
             code: FhirCode {
                 coding: vec![FhirCoding {
-                    system: "http://loinc.org",
-                    code,
-                    display,
+                    system: LOINC_SYSTEM.into(),
+                    code: OCTAVE_BANDS_CODE.into(),
+                    display: display.into(),
                 }],
                 text: display,
             },
             subject: FhirReference {
-                reference: format!("Patient/{}", r.patient_id),
+                reference: subject_reference(&r.patient_id),
             },
-            effective_date_time: r.ts,
-            value_quantity: FhirQuantity {
-                value: r.value,
-                unit: r.unit,
+            device: FhirReference {
+                reference: device_reference(&r.device_id),
             },
+            effective_date_time: r.ts,
+            value_quantity: FhirQuantity::new(r.overall_db, "dB"),
+            component,
+            note: Vec::new(),
+            conversion: None,
         }
     }
 
@@ -84,43 +592,38 @@ impl FhirObservation {
         }
         Uuid::parse_str(&self.id).map_err(|_| "ID must be a valid UUID")?;
 
-        // Status must be one of: registered, preliminary, final, amended, corrected, cancelled, entered-in-error, unknown
-        let valid_statuses = [
-            "registered",
-            "preliminary",
-            "final",
-            "amended",
-            "corrected",
-            "cancelled",
-            "entered-in-error",
-            "unknown",
-        ];
-        if !valid_statuses.contains(&self.status) {
-            return Err(format!(
-                "Invalid status '{}'. Must be one of: {}",
-                self.status,
-                valid_statuses.join(", ")
-            ));
-        }
-
         // Code must have at least one coding
         if self.code.coding.is_empty() {
             return Err("Observation must have at least one coding".into());
         }
 
-        // Validate coding system (must be a valid URI)
-        for coding in &self.code.coding {
-            if coding.system.is_empty() {
-                return Err("Coding system is required".into());
-            }
-            if !coding.system.starts_with("http://") && !coding.system.starts_with("https://") {
+        // Validate every coding (system must be a valid URI)
+        validate_codings(&self.code)?;
+
+        // A signal's code must come from the signal code ValueSet; octave-band
+        // panels have a code of their own
+        if self.code.coding[0].code != OCTAVE_BANDS_CODE {
+            let value_set = FhirValueSet::signal_codes();
+            if !self
+                .code
+                .coding
+                .iter()
+                .any(|c| value_set.contains(Some(&c.system), &c.code))
+            {
                 return Err(format!(
-                    "Coding system must be a valid URI: {}",
-                    coding.system
+                    "Observation code must be in ValueSet {}",
+                    value_set.url
                 ));
             }
-            if coding.code.is_empty() {
-                return Err("Coding code is required".into());
+        }
+
+        // Each category needs a coding with a code
+        for (idx, category) in self.category.iter().enumerate() {
+            if category.coding.is_empty() || category.coding.iter().any(|c| c.code.is_empty()) {
+                return Err(format!(
+                    "Category {} must have at least one non-empty coding",
+                    idx
+                ));
             }
         }
 
@@ -128,8 +631,15 @@ impl FhirObservation {
         if self.subject.reference.is_empty() {
             return Err("Subject reference is required".into());
         }
-        if !self.subject.reference.contains('/') {
-            return Err("Subject reference must follow format: ResourceType/id".into());
+        if !is_valid_reference(&self.subject.reference) {
+            return Err(
+                "Subject reference must follow format: ResourceType/id, optionally behind an absolute URL"
+                    .into(),
+            );
+        }
+
+        if device_reference_id(&self.device.reference).is_none() {
+            return Err("Device reference must follow format: Device/id".into());
         }
 
         // Value must be finite
@@ -142,20 +652,113 @@ impl FhirObservation {
             return Err("Value unit is required".into());
         }
 
+        // Components need a code and a finite value too
+        for (idx, component) in self.component.iter().enumerate() {
+            if component.code.coding.is_empty() {
+                return Err(format!("Component {} must have at least one coding", idx));
+            }
+            validate_codings(&component.code).map_err(|e| format!("Component {}: {}", idx, e))?;
+            if !component.value_quantity.value.is_finite() {
+                return Err(format!("Component {} value must be a finite number", idx));
+            }
+        }
+
         Ok(())
     }
 }
 
-#[derive(Debug, Serialize, Clone)]
+/// FHIR R4 Patient, as included alongside the observations that refer to it.
+/// Only the id is published: the registry keeps no demographics, and its
+/// display label is not a name.
+#[derive(Debug, Serialize, Clone, ToSchema)]
+pub struct FhirPatient {
+    #[serde(rename = "resourceType")]
+    pub resource_type: &'static str,
+    pub id: String,
+}
+
+impl FhirPatient {
+    pub fn from_patient(patient: &Patient) -> Self {
+        Self {
+            resource_type: "Patient",
+            id: patient.patient_id.clone(),
+        }
+    }
+}
+
+/// FHIR R4 Device, as read directly or included alongside the observations
+/// that took it. The registry knows a device only by its id and whether it
+/// is in service.
+#[derive(Debug, Serialize, Clone, ToSchema)]
+pub struct FhirDevice {
+    #[serde(rename = "resourceType")]
+    pub resource_type: &'static str,
+    pub id: String,
+    /// `active`, or `inactive` once decommissioned
+    pub status: &'static str,
+}
+
+impl FhirDevice {
+    pub fn new(device_id: &str, status: DeviceStatus) -> Self {
+        Self {
+            resource_type: "Device",
+            id: device_id.to_string(),
+            status: match status {
+                DeviceStatus::Active => "active",
+                DeviceStatus::Decommissioned => "inactive",
+            },
+        }
+    }
+}
+
+/// A resource in a bundle entry
+#[derive(Debug, Serialize, Clone, ToSchema)]
+#[serde(untagged)]
+pub enum FhirBundleResource {
+    Observation(Box<FhirObservation>),
+    Patient(FhirPatient),
+    Device(FhirDevice),
+}
+
+/// Why an entry is in a search result
+#[derive(Debug, Serialize, Clone, ToSchema)]
+pub struct FhirBundleEntrySearch {
+    /// `match` for text search hits, `include` for resources added by
+    /// `_include`
+    pub mode: String,
+    /// Relevance of a text search hit; higher is better
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub score: Option<f64>,
+}
+
+#[derive(Debug, Serialize, Clone, ToSchema)]
 pub struct FhirBundleEntry {
-    pub resource: FhirObservation,
+    pub resource: FhirBundleResource,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub search: Option<FhirBundleEntrySearch>,
+}
+
+impl FhirBundleEntry {
+    /// A matched observation
+    pub fn observation(obs: FhirObservation) -> Self {
+        Self {
+            resource: FhirBundleResource::Observation(Box::new(obs)),
+            search: None,
+        }
+    }
+
+    /// Whether the entry was added by `_include` rather than matched
+    pub fn is_include(&self) -> bool {
+        self.search.as_ref().is_some_and(|s| s.mode == "include")
+    }
 }
 
-#[derive(Debug, Serialize, Clone)]
+#[derive(Debug, Serialize, Clone, ToSchema)]
 pub struct FhirBundle {
     #[serde(rename = "resourceType")]
     pub resource_type: &'static str,
     pub r#type: &'static str,
+    /// Matched observations; included resources are not counted
     pub total: usize,
     pub entry: Vec<FhirBundleEntry>,
 }
@@ -167,13 +770,76 @@ impl FhirBundle {
             resource_type: "Bundle",
             r#type: "collection",
             total,
-            entry: obs
+            entry: obs.into_iter().map(FhirBundleEntry::observation).collect(),
+        }
+    }
+
+    /// Search result of scored observations, best first
+    pub fn from_matches(matches: Vec<(FhirObservation, f64)>) -> Self {
+        Self {
+            resource_type: "Bundle",
+            r#type: "searchset",
+            total: matches.len(),
+            entry: matches
                 .into_iter()
-                .map(|o| FhirBundleEntry { resource: o })
+                .map(|(o, score)| FhirBundleEntry {
+                    resource: FhirBundleResource::Observation(Box::new(o)),
+                    search: Some(FhirBundleEntrySearch {
+                        mode: "match".into(),
+                        score: Some(score),
+                    }),
+                })
                 .collect(),
         }
     }
 
+    /// Ids of the patients the observations refer to, each once, in order
+    pub fn subject_patient_ids(&self) -> Vec<String> {
+        self.referenced_ids(|obs| subject_patient_id(&obs.subject.reference))
+    }
+
+    /// Ids of the devices the observations refer to, each once, in order
+    pub fn device_ids(&self) -> Vec<String> {
+        self.referenced_ids(|obs| device_reference_id(&obs.device.reference))
+    }
+
+    fn referenced_ids<'a>(
+        &'a self,
+        id: impl Fn(&'a FhirObservation) -> Option<&'a str>,
+    ) -> Vec<String> {
+        let mut ids: Vec<String> = Vec::new();
+        for entry in &self.entry {
+            if let FhirBundleResource::Observation(obs) = &entry.resource {
+                if let Some(id) = id(obs) {
+                    if !ids.iter().any(|known| known == id) {
+                        ids.push(id.to_string());
+                    }
+                }
+            }
+        }
+        ids
+    }
+
+    /// Append `patients` as `include` entries; `total` is unchanged
+    pub fn include_patients(&mut self, patients: Vec<FhirPatient>) {
+        self.include(patients.into_iter().map(FhirBundleResource::Patient));
+    }
+
+    /// Append `devices` as `include` entries; `total` is unchanged
+    pub fn include_devices(&mut self, devices: Vec<FhirDevice>) {
+        self.include(devices.into_iter().map(FhirBundleResource::Device));
+    }
+
+    fn include(&mut self, resources: impl Iterator<Item = FhirBundleResource>) {
+        self.entry.extend(resources.map(|resource| FhirBundleEntry {
+            resource,
+            search: Some(FhirBundleEntrySearch {
+                mode: "include".into(),
+                score: None,
+            }),
+        }));
+    }
+
     /// Validate FHIR Bundle against FHIR R4 schema
     pub fn validate(&self) -> Result<(), String> {
         // Resource type must be "Bundle"
@@ -201,21 +867,290 @@ impl FhirBundle {
             ));
         }
 
-        // Total must match entry count
-        if self.total != self.entry.len() {
+        // Total must match the count of matched (not included) entries
+        let matched = self.entry.iter().filter(|e| !e.is_include()).count();
+        if self.total != matched {
             return Err(format!(
                 "Bundle total ({}) does not match entry count ({})",
-                self.total,
-                self.entry.len()
+                self.total, matched
             ));
         }
 
         // Validate all observations in the bundle
         for (idx, entry) in self.entry.iter().enumerate() {
-            entry
-                .resource
-                .validate()
-                .map_err(|e| format!("Observation at index {} is invalid: {}", idx, e))?;
+            match &entry.resource {
+                FhirBundleResource::Observation(obs) => obs
+                    .validate()
+                    .map_err(|e| format!("Observation at index {} is invalid: {}", idx, e))?,
+                FhirBundleResource::Patient(patient) if patient.id.trim().is_empty() => {
+                    return Err(format!("Patient at index {} must have an id", idx));
+                }
+                FhirBundleResource::Patient(_) => {}
+                FhirBundleResource::Device(device) if device.id.trim().is_empty() => {
+                    return Err(format!("Device at index {} must have an id", idx));
+                }
+                FhirBundleResource::Device(_) => {}
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Id of the ValueSet of signal codes
+pub const SIGNAL_CODES_VALUE_SET_ID: &str = "soundsense-signal-codes";
+
+/// Canonical URL of the ValueSet of signal codes
+pub const SIGNAL_CODES_VALUE_SET_URL: &str = "urn:soundsense:valueset:soundsense-signal-codes";
+
+/// Whether `uri` is absolute: a scheme (a letter, then letters, digits,
+/// `+`, `-` or `.`), a colon and something after it
+fn is_absolute_uri(uri: &str) -> bool {
+    let Some((scheme, rest)) = uri.split_once(':') else {
+        return false;
+    };
+    scheme
+        .chars()
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic())
+        && scheme
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'))
+        && !rest.is_empty()
+}
+
+#[derive(Debug, Serialize, Clone, ToSchema)]
+pub struct FhirValueSetConcept {
+    pub code: String,
+    pub display: String,
+}
+
+/// Codes of one code system included in a ValueSet
+#[derive(Debug, Serialize, Clone, ToSchema)]
+pub struct FhirValueSetInclude {
+    pub system: String,
+    pub concept: Vec<FhirValueSetConcept>,
+}
+
+#[derive(Debug, Serialize, Clone, ToSchema)]
+pub struct FhirValueSetCompose {
+    pub include: Vec<FhirValueSetInclude>,
+}
+
+/// FHIR R4 ValueSet, for terminology servers and validators to check codes
+/// against
+#[derive(Debug, Serialize, Clone, ToSchema)]
+pub struct FhirValueSet {
+    #[serde(rename = "resourceType")]
+    pub resource_type: &'static str,
+    pub id: String,
+    pub url: String,
+    pub version: String,
+    pub name: String,
+    pub title: String,
+    pub status: &'static str,
+    pub compose: FhirValueSetCompose,
+}
+
+impl FhirValueSet {
+    /// The LOINC code of every signal the backend accepts. Aliases accepted
+    /// on ingest are spellings, not codes, and are not listed.
+    pub fn signal_codes() -> Self {
+        Self {
+            resource_type: "ValueSet",
+            id: SIGNAL_CODES_VALUE_SET_ID.into(),
+            url: SIGNAL_CODES_VALUE_SET_URL.into(),
+            version: crate::build_info::VERSION.into(),
+            name: "SoundSenseSignalCodes".into(),
+            title: "SoundSense signal codes".into(),
+            status: "active",
+            compose: FhirValueSetCompose {
+                include: vec![FhirValueSetInclude {
+                    system: LOINC_SYSTEM.into(),
+                    concept: SignalCode::ALL
+                        .iter()
+                        .map(|signal| FhirValueSetConcept {
+                            code: signal.as_str().into(),
+                            display: signal.display().into(),
+                        })
+                        .collect(),
+                }],
+            },
+        }
+    }
+
+    /// Whether `code` is in the ValueSet, in `system` if given
+    pub fn contains(&self, system: Option<&str>, code: &str) -> bool {
+        self.compose
+            .include
+            .iter()
+            .filter(|include| system.is_none_or(|s| s == include.system))
+            .any(|include| include.concept.iter().any(|c| c.code == code))
+    }
+
+    /// Validate against the FHIR R4 ValueSet constraints we rely on
+    pub fn validate(&self) -> Result<(), String> {
+        if self.resource_type != "ValueSet" {
+            return Err("resourceType must be 'ValueSet'".into());
+        }
+        if !is_absolute_uri(&self.url) {
+            return Err(format!("url must be an absolute URI: '{}'", self.url));
+        }
+        if self.compose.include.is_empty() {
+            return Err("compose must include at least one code system".into());
+        }
+        for include in &self.compose.include {
+            if !is_absolute_uri(&include.system) {
+                return Err(format!(
+                    "include system must be an absolute URI: '{}'",
+                    include.system
+                ));
+            }
+            if include.concept.is_empty() || include.concept.iter().any(|c| c.code.is_empty()) {
+                return Err(format!(
+                    "include of {} must list non-empty codes",
+                    include.system
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Canonical URL of the noise exposure measure reported by `FhirMeasureReport`
+pub const NOISE_EXPOSURE_MEASURE: &str = "urn:soundsense:measure:noise-exposure-85db-twa";
+
+const MEASURE_POPULATION_SYSTEM: &str = "http://terminology.hl7.org/CodeSystem/measure-population";
+
+#[derive(Debug, Serialize, Clone, ToSchema)]
+pub struct FhirPeriod {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Clone, ToSchema)]
+pub struct Population {
+    pub code: FhirCode,
+    pub count: i64,
+}
+
+#[derive(Debug, Serialize, Clone, ToSchema)]
+pub struct MeasureGroup {
+    pub code: FhirCode,
+    pub population: Vec<Population>,
+    /// Proportion of the denominator in the numerator; absent when the
+    /// denominator is empty
+    #[serde(rename = "measureScore", skip_serializing_if = "Option::is_none")]
+    pub measure_score: Option<FhirQuantity>,
+}
+
+/// Population-level summary of noise exposure over a reporting period
+#[derive(Debug, Serialize, Clone, ToSchema)]
+pub struct FhirMeasureReport {
+    #[serde(rename = "resourceType")]
+    pub resource_type: &'static str,
+    pub id: String,
+    pub status: &'static str,
+    pub r#type: &'static str,
+    pub measure: &'static str,
+    pub period: FhirPeriod,
+    pub group: Vec<MeasureGroup>,
+}
+
+impl FhirMeasureReport {
+    /// Summary report with one group per signal code: every patient with
+    /// readings forms the initial population, those with decibel readings
+    /// the denominator and those whose time-weighted average exceeds
+    /// `models::NOISE_EXPOSURE_LIMIT_DB` the numerator
+    pub fn noise_exposure(
+        period: FhirPeriod,
+        counts: &HashMap<&'static str, PopulationCounts>,
+    ) -> Self {
+        let population = |code: &'static str, count: i64| Population {
+            code: FhirCode {
+                coding: vec![FhirCoding {
+                    system: MEASURE_POPULATION_SYSTEM.into(),
+                    code: code.into(),
+                    display: code.into(),
+                }],
+                text: code,
+            },
+            count,
+        };
+
+        let group = SignalCode::ALL
+            .iter()
+            .map(|signal| {
+                let c = counts.get(signal.as_str()).copied().unwrap_or_default();
+                MeasureGroup {
+                    code: signal_code(signal),
+                    population: vec![
+                        population("initial-population", c.patients),
+                        population("numerator", c.over_limit),
+                        population("denominator", c.measured),
+                    ],
+                    measure_score: (c.measured > 0)
+                        .then(|| FhirQuantity::new(c.over_limit as f64 / c.measured as f64, "1")),
+                }
+            })
+            .collect();
+
+        Self {
+            resource_type: "MeasureReport",
+            id: Uuid::new_v4().to_string(),
+            status: "complete",
+            r#type: "summary",
+            measure: NOISE_EXPOSURE_MEASURE,
+            period,
+            group,
+        }
+    }
+
+    /// Validate against the FHIR R4 MeasureReport constraints we rely on
+    pub fn validate(&self) -> Result<(), String> {
+        if self.resource_type != "MeasureReport" {
+            return Err("resourceType must be 'MeasureReport'".into());
+        }
+        Uuid::parse_str(&self.id).map_err(|_| "ID must be a valid UUID")?;
+
+        // Reports are generated in one go, never left pending
+        if self.status != "complete" {
+            return Err(format!("status must be 'complete', got '{}'", self.status));
+        }
+        if self.period.end <= self.period.start {
+            return Err("period.end must be after period.start".into());
+        }
+
+        for group in &self.group {
+            let count = |code: &str| {
+                group
+                    .population
+                    .iter()
+                    .find(|p| p.code.coding.iter().any(|c| c.code == code))
+                    .map(|p| p.count)
+            };
+            if group.population.iter().any(|p| p.count < 0) {
+                return Err("population counts must not be negative".into());
+            }
+            if let (Some(numerator), Some(denominator)) = (count("numerator"), count("denominator"))
+            {
+                if numerator > denominator {
+                    return Err(format!(
+                        "numerator ({}) exceeds denominator ({})",
+                        numerator, denominator
+                    ));
+                }
+            }
+            if let (Some(denominator), Some(initial)) =
+                (count("denominator"), count("initial-population"))
+            {
+                if denominator > initial {
+                    return Err(format!(
+                        "denominator ({}) exceeds initial population ({})",
+                        denominator, initial
+                    ));
+                }
+            }
         }
 
         Ok(())
@@ -225,6 +1160,7 @@ impl FhirBundle {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::domain::models::{ReadingExtra, SensorReading};
     use chrono::Utc;
 
     #[test]
@@ -232,79 +1168,510 @@ mod tests {
         let obs = FhirObservation {
             resource_type: "Observation",
             id: Uuid::new_v4().to_string(),
-            status: "final",
+            meta: None,
+            status: ObservationStatus::Final,
+            category: vec![ObservationCategory::Activity.to_code()],
             code: FhirCode {
                 coding: vec![FhirCoding {
-                    system: "http://loinc.org",
-                    code: "sound",
-                    display: "Sound Level",
+                    system: "http://loinc.org".into(),
+                    code: "sound".into(),
+                    display: "Sound Level".into(),
                 }],
                 text: "Sound Level",
             },
             subject: FhirReference {
                 reference: "Patient/p1".into(),
             },
-            effective_date_time: Utc::now(),
-            value_quantity: FhirQuantity {
-                value: 200.0,
-                unit: "raw".into(),
+            device: FhirReference {
+                reference: "Device/d1".into(),
             },
+            effective_date_time: Utc::now(),
+            value_quantity: FhirQuantity::new(200.0, "raw"),
+            component: Vec::new(),
+            note: Vec::new(),
+            conversion: None,
+        };
+
+        assert!(obs.validate().is_ok());
+    }
+
+    #[test]
+    fn test_category_defaults_to_activity_and_can_be_overridden() {
+        let mut reading = SensorReading {
+            patient_id: "p1".into(),
+            device_id: "d1".into(),
+            code: SignalCode::Sound,
+            value: 200.0,
+            unit: "raw".into(),
+            ts: Utc::now(),
+            ts_source: TimestampSource::Device,
+            metadata: Default::default(),
         };
 
+        let obs = FhirObservation::from_reading(reading.clone());
+        assert_eq!(obs.category.len(), 1);
+        assert_eq!(
+            obs.category[0].coding[0].system,
+            ObservationCategory::SYSTEM
+        );
+        assert_eq!(obs.category[0].coding[0].code, "activity");
+        assert_eq!(obs.category[0].coding[0].display, "Activity");
+
+        reading
+            .metadata
+            .insert("fhir_category".into(), "survey".into());
+        let obs = FhirObservation::from_reading(reading);
+        assert_eq!(obs.category[0].coding[0].code, "survey");
         assert!(obs.validate().is_ok());
     }
 
+    #[test]
+    fn test_category_needs_a_coding() {
+        let mut obs = FhirObservation::from_reading(SensorReading {
+            patient_id: "p1".into(),
+            device_id: "d1".into(),
+            code: SignalCode::Sound,
+            value: 200.0,
+            unit: "raw".into(),
+            ts: Utc::now(),
+            ts_source: TimestampSource::Device,
+            metadata: Default::default(),
+        });
+        obs.category[0].coding.clear();
+        assert!(obs.validate().is_err());
+    }
+
     #[test]
     fn test_invalid_status() {
+        // The status is an enum now, so codes outside the value set don't parse
+        assert!(ObservationStatus::try_from("invalid_status".to_string()).is_err());
+        assert_eq!(
+            ObservationStatus::try_from("entered-in-error".to_string()),
+            Ok(ObservationStatus::EnteredInError)
+        );
+        assert_eq!(
+            serde_json::to_value(ObservationStatus::EnteredInError).unwrap(),
+            "entered-in-error"
+        );
+    }
+
+    #[test]
+    fn test_amendment_transitions() {
+        use ObservationStatus::*;
+
+        assert!(Final.can_amend_to(Amended));
+        assert!(Final.can_amend_to(Cancelled));
+        assert!(Final.can_amend_to(EnteredInError));
+        assert!(Amended.can_amend_to(EnteredInError));
+        assert!(!Final.can_amend_to(Final));
+        assert!(!Amended.can_amend_to(Final));
+        assert!(!Cancelled.can_amend_to(Amended));
+        assert!(!EnteredInError.can_amend_to(Amended));
+    }
+
+    #[test]
+    fn test_invalid_value() {
         let obs = FhirObservation {
             resource_type: "Observation",
             id: Uuid::new_v4().to_string(),
-            status: "invalid_status",
+            meta: None,
+            status: ObservationStatus::Final,
+            category: vec![ObservationCategory::Activity.to_code()],
             code: FhirCode {
                 coding: vec![FhirCoding {
-                    system: "http://loinc.org",
-                    code: "sound",
-                    display: "Sound Level",
+                    system: "http://loinc.org".into(),
+                    code: "sound".into(),
+                    display: "Sound Level".into(),
                 }],
                 text: "Sound Level",
             },
             subject: FhirReference {
                 reference: "Patient/p1".into(),
             },
+            device: FhirReference {
+                reference: "Device/d1".into(),
+            },
             effective_date_time: Utc::now(),
-            value_quantity: FhirQuantity {
+            value_quantity: FhirQuantity::new(f64::NAN, "raw"),
+            component: Vec::new(),
+            note: Vec::new(),
+            conversion: None,
+        };
+
+        assert!(obs.validate().is_err());
+    }
+
+    #[test]
+    fn test_subject_reference_defaults_to_relative() {
+        let obs = FhirObservation::from_reading(SensorReading {
+            patient_id: "p1".into(),
+            device_id: "d1".into(),
+            code: SignalCode::Sound,
+            value: 200.0,
+            unit: "raw".into(),
+            ts: Utc::now(),
+            ts_source: TimestampSource::Device,
+            metadata: Default::default(),
+        });
+        assert_eq!(obs.subject.reference, "Patient/p1");
+        assert_eq!(subject_patient_id(&obs.subject.reference), Some("p1"));
+        assert_eq!(normalize_subject_base(None), "Patient/");
+        assert_eq!(normalize_subject_base(Some(" ")), "Patient/");
+    }
+
+    #[test]
+    fn test_absolute_subject_reference_passes_validation() {
+        let base = normalize_subject_base(Some("https://fhir.example.org/Patient"));
+        assert_eq!(base, "https://fhir.example.org/Patient/");
+
+        let mut obs = FhirObservation::from_reading(SensorReading {
+            patient_id: "p1".into(),
+            device_id: "d1".into(),
+            code: SignalCode::Sound,
+            value: 200.0,
+            unit: "raw".into(),
+            ts: Utc::now(),
+            ts_source: TimestampSource::Device,
+            metadata: Default::default(),
+        });
+        obs.subject.reference = format!("{}p1", base);
+        assert!(obs.validate().is_ok());
+
+        for reference in [
+            "Group/g1",
+            "Patient/ward-3/bed-2",
+            "http://fhir.example.org/fhir/R4/Patient/p1",
+            "https://fhir.example.org/Patient/ward-3/bed-2",
+        ] {
+            obs.subject.reference = reference.into();
+            assert!(obs.validate().is_ok(), "{}", reference);
+        }
+        for reference in [
+            "p1",
+            "Patient/",
+            "patient/p1",
+            "ward/Patient/p1",
+            "https://fhir.example.org/fhir/Patient/",
+            "https://fhir.example.org",
+            "https:///Patient/p1",
+            "ftp://fhir.example.org/Patient/p1",
+        ] {
+            obs.subject.reference = reference.into();
+            assert!(obs.validate().is_err(), "{}", reference);
+        }
+    }
+
+    #[test]
+    fn test_octave_bands_map_to_components() {
+        let reading = OctaveBandReading {
+            patient_id: "p1".into(),
+            device_id: "mic-1".into(),
+            ts: Utc::now(),
+            bands: [41.0, 42.5, 44.0, 47.5, 50.0, 48.0, 43.5, 38.0],
+            overall_db: 55.2,
+            calibrated_value: None,
+        };
+
+        let obs = FhirObservation::from_octave_band(&reading);
+
+        assert!(obs.validate().is_ok());
+        assert_eq!(obs.code.coding[0].code, "sound-octave-bands");
+        assert_eq!(obs.value_quantity.value, 55.2);
+        assert_eq!(obs.value_quantity.unit, "dB");
+        assert_eq!(obs.component.len(), OCTAVE_BAND_COUNT);
+
+        let codes: Vec<_> = obs
+            .component
+            .iter()
+            .map(|c| c.code.coding[0].code.as_str())
+            .collect();
+        assert_eq!(
+            codes,
+            [
+                "sound-octave-63hz",
+                "sound-octave-125hz",
+                "sound-octave-250hz",
+                "sound-octave-500hz",
+                "sound-octave-1khz",
+                "sound-octave-2khz",
+                "sound-octave-4khz",
+                "sound-octave-8khz",
+            ]
+        );
+        for (component, band) in obs.component.iter().zip(reading.bands) {
+            assert_eq!(component.value_quantity.value, band);
+            assert_eq!(component.value_quantity.unit, "dB");
+        }
+
+        let json = serde_json::to_value(&obs).unwrap();
+        assert_eq!(json["component"][4]["valueQuantity"]["value"], 50.0);
+
+        // The A-weighted level, when computed, follows the bands
+        let weighted = OctaveBandReading {
+            calibrated_value: Some(52.3),
+            ..reading
+        };
+        let obs = FhirObservation::from_octave_band(&weighted);
+        assert!(obs.validate().is_ok());
+        let last = obs.component.last().unwrap();
+        assert_eq!(obs.component.len(), OCTAVE_BAND_COUNT + 1);
+        assert_eq!(last.code.coding[0].code, "sound-level-a-weighted");
+        assert_eq!(last.value_quantity.value, 52.3);
+        assert_eq!(last.value_quantity.unit, "dB(A)");
+    }
+
+    #[test]
+    fn test_single_reading_has_no_component() {
+        let obs = FhirObservation::from_reading(SensorReading {
+            patient_id: "p1".into(),
+            device_id: "d1".into(),
+            code: SignalCode::Sound,
+            value: 200.0,
+            unit: "raw".into(),
+            ts: Utc::now(),
+            ts_source: TimestampSource::Device,
+            metadata: Default::default(),
+        });
+
+        let json = serde_json::to_value(&obs).unwrap();
+        assert!(json.get("component").is_none());
+    }
+
+    #[test]
+    fn test_configured_codings_follow_loinc() {
+        let codings = parse_extra_codings(
+            "sound=http://snomed.info/sct|1234567|Sound level (observable entity); \
+             sound=https://codes.example.org/local|SND",
+        )
+        .unwrap();
+        assert_eq!(codings[1].1.display, "Sound Level");
+        assert!(parse_extra_codings("sound=urn:local|SND").is_err());
+        assert!(parse_extra_codings("sound=http://snomed.info/sct|").is_err());
+        assert!(parse_extra_codings("heart_rate=http://snomed.info/sct|1").is_err());
+
+        let mut obs = FhirObservation::from_reading(SensorReading {
+            patient_id: "p1".into(),
+            device_id: "d1".into(),
+            code: SignalCode::Sound,
+            value: 70.0,
+            unit: "dB".into(),
+            ts: Utc::now(),
+            ts_source: TimestampSource::Device,
+            metadata: Default::default(),
+        });
+        obs.code = signal_code_with(&SignalCode::Sound, &codings);
+
+        let systems: Vec<_> = obs.code.coding.iter().map(|c| c.system.as_str()).collect();
+        assert_eq!(
+            systems,
+            [
+                "http://loinc.org",
+                "http://snomed.info/sct",
+                "https://codes.example.org/local"
+            ]
+        );
+        assert_eq!(obs.code.coding[1].code, "1234567");
+        assert!(obs.validate().is_ok());
+
+        // Every coding is checked, not just the first
+        obs.code.coding.push(obs.code.coding[1].clone());
+        assert!(obs.validate().unwrap_err().contains("more than once"));
+        obs.code.coding.pop();
+        obs.code.coding[2].code.clear();
+        assert!(obs.validate().is_err());
+    }
+
+    #[test]
+    fn test_extras_become_components() {
+        let reading = SensorReading {
+            patient_id: "p1".into(),
+            device_id: "d1".into(),
+            code: SignalCode::Sound,
+            value: 62.5,
+            unit: "dB".into(),
+            ts: Utc::now(),
+            ts_source: TimestampSource::Device,
+            metadata: Default::default(),
+        };
+        let raw = ReadingExtra {
+            code: SignalCode::Sound,
+            value: 412.0,
+            unit: "raw".into(),
+        };
+
+        let paired = FhirObservation::from_reading(reading.clone().with_extras(vec![raw.clone()]));
+        assert!(paired.validate().is_ok());
+        let json = serde_json::to_value(&paired).unwrap();
+        assert_eq!(json["valueQuantity"]["value"], 62.5);
+        assert_eq!(json["component"][0]["code"]["coding"][0]["code"], "sound");
+        assert_eq!(json["component"][0]["valueQuantity"]["value"], 412.0);
+        assert_eq!(json["component"][0]["valueQuantity"]["unit"], "raw");
+
+        // A bundle mixing both shapes omits `component` where there is none
+        let bundle =
+            FhirBundle::from_obs(vec![paired, FhirObservation::from_reading(reading.clone())]);
+        assert!(bundle.validate().is_ok());
+        let json = serde_json::to_value(&bundle).unwrap();
+        assert!(json["entry"][0]["resource"].get("component").is_some());
+        assert!(json["entry"][1]["resource"].get("component").is_none());
+
+        let broken = ReadingExtra {
+            value: f64::NAN,
+            ..raw
+        };
+        let obs = FhirObservation::from_reading(reading.with_extras(vec![broken]));
+        assert_eq!(
+            obs.validate().unwrap_err(),
+            "Component 0 value must be a finite number"
+        );
+    }
+
+    #[test]
+    fn test_included_patients_are_not_counted() {
+        let obs = |patient_id: &str| {
+            FhirObservation::from_reading(SensorReading {
+                patient_id: patient_id.into(),
+                device_id: "d1".into(),
+                code: SignalCode::Sound,
                 value: 200.0,
                 unit: "raw".into(),
-            },
+                ts: Utc::now(),
+                ts_source: TimestampSource::Device,
+                metadata: Default::default(),
+            })
         };
+        let mut bundle = FhirBundle::from_obs(vec![obs("p2"), obs("p1"), obs("p2")]);
+        assert_eq!(bundle.subject_patient_ids(), ["p2", "p1"]);
+        assert_eq!(bundle.device_ids(), ["d1"]);
 
-        assert!(obs.validate().is_err());
+        bundle.include_patients(vec![FhirPatient {
+            resource_type: "Patient",
+            id: "p1".into(),
+        }]);
+        bundle.include_devices(vec![FhirDevice::new("d1", DeviceStatus::Decommissioned)]);
+        assert_eq!(bundle.total, 3);
+        assert_eq!(bundle.entry.len(), 5);
+        assert!(bundle.validate().is_ok());
+        // Included resources carry no references of their own
+        assert_eq!(bundle.device_ids(), ["d1"]);
+
+        let json = serde_json::to_value(&bundle).unwrap();
+        assert!(json["entry"][0].get("search").is_none());
+        assert_eq!(
+            json["entry"][0]["resource"]["device"]["reference"],
+            "Device/d1"
+        );
+        assert_eq!(json["entry"][3]["search"]["mode"], "include");
+        assert_eq!(json["entry"][3]["resource"]["resourceType"], "Patient");
+        assert_eq!(json["entry"][3]["resource"]["id"], "p1");
+        assert_eq!(json["entry"][4]["search"]["mode"], "include");
+        assert_eq!(json["entry"][4]["resource"]["resourceType"], "Device");
+        assert_eq!(json["entry"][4]["resource"]["id"], "d1");
+        assert_eq!(json["entry"][4]["resource"]["status"], "inactive");
     }
 
     #[test]
-    fn test_invalid_value() {
-        let obs = FhirObservation {
-            resource_type: "Observation",
-            id: Uuid::new_v4().to_string(),
-            status: "final",
-            code: FhirCode {
-                coding: vec![FhirCoding {
-                    system: "http://loinc.org",
-                    code: "sound",
-                    display: "Sound Level",
-                }],
-                text: "Sound Level",
-            },
-            subject: FhirReference {
-                reference: "Patient/p1".into(),
+    fn test_measure_report_populations() {
+        let start = Utc::now() - chrono::Duration::days(7);
+        let period = FhirPeriod {
+            start,
+            end: Utc::now(),
+        };
+        let counts = HashMap::from([(
+            "sound",
+            PopulationCounts {
+                patients: 5,
+                measured: 4,
+                over_limit: 1,
             },
-            effective_date_time: Utc::now(),
-            value_quantity: FhirQuantity {
-                value: f64::NAN,
-                unit: "raw".into(),
+        )]);
+
+        let report = FhirMeasureReport::noise_exposure(period, &counts);
+
+        assert!(report.validate().is_ok());
+        assert_eq!(report.group.len(), SignalCode::ALL.len());
+        let group = &report.group[0];
+        let counts: Vec<_> = group
+            .population
+            .iter()
+            .map(|p| (p.code.coding[0].code.as_str(), p.count))
+            .collect();
+        assert_eq!(
+            counts,
+            [
+                ("initial-population", 5),
+                ("numerator", 1),
+                ("denominator", 4)
+            ]
+        );
+        assert_eq!(group.measure_score.as_ref().unwrap().value, 0.25);
+
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["resourceType"], "MeasureReport");
+        assert_eq!(json["type"], "summary");
+        assert_eq!(json["group"][0]["measureScore"]["value"], 0.25);
+    }
+
+    #[test]
+    fn test_measure_report_rejects_inverted_period() {
+        let now = Utc::now();
+        let mut report = FhirMeasureReport::noise_exposure(
+            FhirPeriod {
+                start: now,
+                end: now - chrono::Duration::hours(1),
             },
-        };
+            &HashMap::new(),
+        );
+        assert!(report.validate().is_err());
 
-        assert!(obs.validate().is_err());
+        report.period.end = now + chrono::Duration::hours(1);
+        assert!(report.validate().is_ok());
+        // No patients: no score rather than a division by zero
+        assert!(report.group[0].measure_score.is_none());
+
+        report.status = "pending";
+        assert!(report.validate().is_err());
+    }
+
+    #[test]
+    fn test_signal_code_value_set() {
+        let mut value_set = FhirValueSet::signal_codes();
+        assert!(value_set.validate().is_ok());
+        assert!(value_set.contains(Some(LOINC_SYSTEM), "sound"));
+        assert!(value_set.contains(None, "sound"));
+        assert!(!value_set.contains(Some(LOINC_SYSTEM), "noise"));
+        assert!(!value_set.contains(Some("http://snomed.info/sct"), "sound"));
+        // Ingest aliases are not codes
+        assert!(!value_set.contains(None, "SoundLevel"));
+
+        let json = serde_json::to_value(&value_set).unwrap();
+        assert_eq!(json["resourceType"], "ValueSet");
+        assert_eq!(json["compose"]["include"][0]["system"], LOINC_SYSTEM);
+        assert_eq!(json["compose"]["include"][0]["concept"][0]["code"], "sound");
+        assert_eq!(
+            json["compose"]["include"][0]["concept"][0]["display"],
+            "Sound Level"
+        );
+
+        value_set.url = "soundsense-signal-codes".into();
+        assert!(value_set.validate().is_err());
+        value_set.url = SIGNAL_CODES_VALUE_SET_URL.into();
+        value_set.compose.include.clear();
+        assert!(value_set.validate().is_err());
+
+        // Observations are coded from it
+        let mut obs = FhirObservation::from_reading(SensorReading {
+            patient_id: "p1".into(),
+            device_id: "d1".into(),
+            code: SignalCode::Sound,
+            value: 70.0,
+            unit: "dB".into(),
+            ts: Utc::now(),
+            ts_source: TimestampSource::Device,
+            metadata: Default::default(),
+        });
+        assert!(obs.validate().is_ok());
+        obs.code.coding[0].code = "noise".into();
+        assert!(obs.validate().unwrap_err().contains("ValueSet"));
     }
 }
@@ -0,0 +1,209 @@
+/// Reading Gap Detection
+///
+/// Wearables transmit at a steady rate (typically 1 Hz), so a long silence
+/// between two readings means the device was taken off or out of range.
+/// `GapDetector` finds those silences and can fill them with linearly
+/// interpolated readings for display; synthetic readings are never stored.
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::domain::models::{SensorReading, TimestampSource};
+
+/// Default silence, in seconds, that counts as a gap
+pub const DEFAULT_MAX_GAP_SECS: u64 = 5;
+
+/// Longest silence, in seconds, that can be asked for: no gap outlasts the
+/// longest analysis range
+pub const MAX_GAP_SECS: u64 = crate::ml_client::MAX_ANALYSIS_RANGE_DAYS as u64 * 24 * 3600;
+
+/// Most synthetic readings inserted into one gap; longer gaps get a coarser step
+const MAX_FILL_PER_GAP: i64 = 3600;
+
+/// A silence between two consecutive readings
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Gap {
+    /// Timestamp of the last reading before the gap
+    pub start: DateTime<Utc>,
+    /// Timestamp of the first reading after the gap
+    pub end: DateTime<Utc>,
+    pub duration_secs: f64,
+    /// Interpolated readings, when filling was requested
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub synthetic_readings: Vec<SyntheticReading>,
+}
+
+/// A reading made up to fill a gap
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct SyntheticReading {
+    #[serde(flatten)]
+    pub reading: SensorReading,
+    pub metadata: SyntheticMetadata,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct SyntheticMetadata {
+    /// Always `true`
+    pub synthetic: bool,
+}
+
+pub struct GapDetector;
+
+impl GapDetector {
+    /// Gaps longer than `max_gap_secs` between consecutive readings of one
+    /// stream. `readings` must be sorted by timestamp.
+    pub fn find_gaps(readings: &[SensorReading], max_gap_secs: u64) -> Vec<Gap> {
+        let max_gap = gap_threshold(max_gap_secs);
+        readings
+            .windows(2)
+            .filter(|pair| pair[1].ts - pair[0].ts > max_gap)
+            .map(|pair| Gap {
+                start: pair[0].ts,
+                end: pair[1].ts,
+                duration_secs: (pair[1].ts - pair[0].ts).num_milliseconds() as f64 / 1000.0,
+                synthetic_readings: Vec::new(),
+            })
+            .collect()
+    }
+
+    /// Like `find_gaps`, with each gap filled by linear interpolation between
+    /// the readings on either side, spaced at the stream's usual interval
+    pub fn find_and_fill(readings: &[SensorReading], max_gap_secs: u64) -> Vec<Gap> {
+        let step = typical_interval(readings, max_gap_secs);
+        let max_gap = gap_threshold(max_gap_secs);
+
+        readings
+            .windows(2)
+            .filter(|pair| pair[1].ts - pair[0].ts > max_gap)
+            .map(|pair| {
+                let (before, after) = (&pair[0], &pair[1]);
+                let span = after.ts - before.ts;
+                let step = step.max(span / MAX_FILL_PER_GAP as i32);
+                let span_ms = span.num_milliseconds() as f64;
+
+                let mut synthetic_readings = Vec::new();
+                let mut ts = before.ts + step;
+                while ts < after.ts {
+                    let fraction = (ts - before.ts).num_milliseconds() as f64 / span_ms;
+                    synthetic_readings.push(SyntheticReading {
+                        reading: SensorReading {
+                            value: before.value + (after.value - before.value) * fraction,
+                            ts,
+                            ts_source: TimestampSource::Server,
+                            ..before.clone()
+                        },
+                        metadata: SyntheticMetadata { synthetic: true },
+                    });
+                    ts += step;
+                }
+
+                Gap {
+                    start: before.ts,
+                    end: after.ts,
+                    duration_secs: span_ms / 1000.0,
+                    synthetic_readings,
+                }
+            })
+            .collect()
+    }
+}
+
+/// `max_gap_secs` as a duration, capped at `MAX_GAP_SECS`
+fn gap_threshold(max_gap_secs: u64) -> Duration {
+    Duration::seconds(max_gap_secs.min(MAX_GAP_SECS) as i64)
+}
+
+/// Median spacing of readings outside gaps, at least one second
+fn typical_interval(readings: &[SensorReading], max_gap_secs: u64) -> Duration {
+    let max_gap = gap_threshold(max_gap_secs);
+    let mut intervals: Vec<Duration> = readings
+        .windows(2)
+        .map(|pair| pair[1].ts - pair[0].ts)
+        .filter(|d| *d <= max_gap)
+        .collect();
+    intervals.sort();
+
+    intervals
+        .get(intervals.len() / 2)
+        .copied()
+        .unwrap_or_else(|| Duration::seconds(1))
+        .max(Duration::seconds(1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::models::SignalCode;
+    use chrono::TimeZone;
+
+    /// Ten readings at 1 Hz with 30 seconds missing after the fifth
+    fn readings_with_gap() -> Vec<SensorReading> {
+        let t0 = Utc.with_ymd_and_hms(2026, 3, 1, 12, 0, 0).unwrap();
+        (0..10)
+            .map(|i| {
+                let offset = if i < 5 { i } else { i + 30 };
+                SensorReading {
+                    patient_id: "p1".into(),
+                    device_id: "wearable-1".into(),
+                    code: SignalCode::Sound,
+                    value: 100.0 + i as f64 * 10.0,
+                    unit: "raw".into(),
+                    ts: t0 + Duration::seconds(offset),
+                    ts_source: TimestampSource::Device,
+                    metadata: Default::default(),
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_single_gap_is_found() {
+        let readings = readings_with_gap();
+        let gaps = GapDetector::find_gaps(&readings, DEFAULT_MAX_GAP_SECS);
+
+        assert_eq!(gaps.len(), 1);
+        assert_eq!(gaps[0].start, readings[4].ts);
+        assert_eq!(gaps[0].end, readings[5].ts);
+        assert_eq!(gaps[0].duration_secs, 31.0);
+        assert!(gaps[0].synthetic_readings.is_empty());
+
+        // A 31 second silence is no gap with a tolerance of a minute
+        assert!(GapDetector::find_gaps(&readings, 60).is_empty());
+        assert!(GapDetector::find_gaps(&readings[..1], 5).is_empty());
+        // Thresholds beyond `MAX_GAP_SECS` are capped rather than overflowing
+        assert!(GapDetector::find_gaps(&readings, u64::MAX).is_empty());
+        assert_eq!(GapDetector::find_and_fill(&readings, u64::MAX).len(), 0);
+    }
+
+    #[test]
+    fn test_gap_is_filled_linearly() {
+        let readings = readings_with_gap();
+        let gaps = GapDetector::find_and_fill(&readings, DEFAULT_MAX_GAP_SECS);
+
+        assert_eq!(gaps.len(), 1);
+        let filled = &gaps[0].synthetic_readings;
+        // One per second strictly between the readings at 4 s and 35 s
+        assert_eq!(filled.len(), 30);
+        assert_eq!(filled[0].reading.ts, readings[4].ts + Duration::seconds(1));
+        assert_eq!(filled[29].reading.ts, readings[5].ts - Duration::seconds(1));
+
+        // 140 at 4 s to 150 at 35 s
+        let halfway = &filled[14].reading;
+        assert!((halfway.value - (140.0 + 10.0 * 15.0 / 31.0)).abs() < 1e-9);
+        assert_eq!(halfway.device_id, "wearable-1");
+
+        let json = serde_json::to_value(&filled[0]).unwrap();
+        assert_eq!(json["metadata"]["synthetic"], true);
+        assert_eq!(json["patient_id"], "p1");
+    }
+
+    #[test]
+    fn test_long_gap_fill_is_capped() {
+        let mut readings = readings_with_gap();
+        readings[9].ts = readings[8].ts + Duration::hours(10);
+
+        let gaps = GapDetector::find_and_fill(&readings, DEFAULT_MAX_GAP_SECS);
+        assert_eq!(gaps.len(), 2);
+        assert!(gaps[1].synthetic_readings.len() as i64 <= MAX_FILL_PER_GAP);
+    }
+}
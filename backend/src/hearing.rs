@@ -0,0 +1,200 @@
+/// Hearing Protection Recommendations
+///
+/// The noise dose follows NIOSH: an 85 dB(A) limit over 8 hours with a 3 dB
+/// exchange rate, so every 3 dB above the limit doubles the dose. The daily
+/// TWA is the energy average of a patient's decibel readings that day.
+///
+/// A protector's labelled NRR overstates real-world attenuation; OSHA's
+/// derating for A-weighted measurements is `(NRR - 7) / 2`. The recommended
+/// protector is the one with the lowest NRR that still brings the TWA below
+/// the limit.
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::domain::models::NOISE_EXPOSURE_LIMIT_DB;
+
+/// Highest NRR accepted for a protector; no single device is rated above this
+pub const MAX_NRR_DB: i32 = 50;
+
+/// A hearing protector that can be recommended
+#[derive(Debug, Clone, Serialize, ToSchema, sqlx::FromRow)]
+pub struct HearingProtector {
+    pub id: Uuid,
+    pub name: String,
+    /// Noise Reduction Rating on the product label
+    pub nrr_db: i32,
+    /// e.g. `earplug`, `earmuff`
+    pub category: String,
+}
+
+/// A protector as created or replaced by an admin
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct HearingProtectorRequest {
+    pub name: String,
+    pub nrr_db: i32,
+    pub category: String,
+}
+
+impl HearingProtectorRequest {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.name.trim().is_empty() {
+            return Err("name required".into());
+        }
+        if self.category.trim().is_empty() {
+            return Err("category required".into());
+        }
+        if !(0..=MAX_NRR_DB).contains(&self.nrr_db) {
+            return Err(format!("nrr_db must be between 0 and {}", MAX_NRR_DB));
+        }
+        Ok(())
+    }
+
+    pub fn into_protector(self, id: Uuid) -> HearingProtector {
+        HearingProtector {
+            id,
+            name: self.name.trim().to_string(),
+            nrr_db: self.nrr_db,
+            category: self.category.trim().to_string(),
+        }
+    }
+}
+
+/// A protector chosen for a patient's exposure
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Recommendation {
+    pub name: String,
+    pub nrr_db: i32,
+    pub category: String,
+    /// Derated attenuation in dB, `(nrr_db - 7) / 2`
+    pub effective_attenuation: f64,
+    /// TWA with the protector worn
+    pub projected_twa_db: f64,
+}
+
+/// A patient's noise dose for a day and the protector to wear
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ProtectionAdvice {
+    /// NIOSH daily dose; 100 is the permissible limit
+    pub dose_percent: f64,
+    pub twa_db: f64,
+    /// `null` when the TWA is already below the limit
+    pub recommendation: Option<Recommendation>,
+}
+
+/// Derated attenuation of a protector with the given NRR
+pub fn effective_attenuation(nrr_db: i32) -> f64 {
+    (nrr_db as f64 - 7.0) / 2.0
+}
+
+/// NIOSH noise dose, in percent, of an 8-hour TWA
+pub fn niosh_dose_percent(twa_db: f64) -> f64 {
+    100.0 * 10f64.powf((twa_db - NOISE_EXPOSURE_LIMIT_DB) / 10.0)
+}
+
+impl ProtectionAdvice {
+    /// Advice for `twa_db`. When no protector is strong enough, the strongest
+    /// one is recommended and `projected_twa_db` shows the remaining excess.
+    pub fn for_exposure(twa_db: f64, protectors: &[HearingProtector]) -> Self {
+        let recommendation = if twa_db < NOISE_EXPOSURE_LIMIT_DB {
+            None
+        } else {
+            let projected = |p: &HearingProtector| twa_db - effective_attenuation(p.nrr_db);
+            protectors
+                .iter()
+                .filter(|p| projected(p) < NOISE_EXPOSURE_LIMIT_DB)
+                .min_by_key(|p| p.nrr_db)
+                .or_else(|| protectors.iter().max_by_key(|p| p.nrr_db))
+                .map(|p| Recommendation {
+                    name: p.name.clone(),
+                    nrr_db: p.nrr_db,
+                    category: p.category.clone(),
+                    effective_attenuation: effective_attenuation(p.nrr_db),
+                    projected_twa_db: projected(p),
+                })
+        };
+
+        Self {
+            dose_percent: niosh_dose_percent(twa_db),
+            twa_db,
+            recommendation,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn protector(name: &str, nrr_db: i32) -> HearingProtector {
+        HearingProtector {
+            id: Uuid::new_v4(),
+            name: name.into(),
+            nrr_db,
+            category: "earplug".into(),
+        }
+    }
+
+    #[test]
+    fn test_dose_doubles_every_3_db() {
+        assert!((niosh_dose_percent(85.0) - 100.0).abs() < 1e-9);
+        assert!((niosh_dose_percent(88.0) - 199.5).abs() < 0.1);
+        assert!((niosh_dose_percent(82.0) - 50.1).abs() < 0.1);
+    }
+
+    #[test]
+    fn test_weakest_sufficient_protector_is_chosen() {
+        let protectors = vec![
+            protector("Foam plug", 33),
+            protector("Flange plug", 22),
+            protector("Earmuff", 29),
+        ];
+
+        // 95 dB needs more than 10 dB: NRR 22 gives 7.5, 29 gives 11
+        let advice = ProtectionAdvice::for_exposure(95.0, &protectors);
+        let rec = advice.recommendation.unwrap();
+        assert_eq!(rec.name, "Earmuff");
+        assert_eq!(rec.effective_attenuation, 11.0);
+        assert_eq!(rec.projected_twa_db, 84.0);
+
+        // 91 dB: NRR 22 brings it to 83.5
+        let rec = ProtectionAdvice::for_exposure(91.0, &protectors)
+            .recommendation
+            .unwrap();
+        assert_eq!(rec.nrr_db, 22);
+
+        assert!(ProtectionAdvice::for_exposure(84.9, &protectors)
+            .recommendation
+            .is_none());
+    }
+
+    #[test]
+    fn test_strongest_protector_when_none_suffices() {
+        let protectors = vec![protector("Flange plug", 22), protector("Foam plug", 33)];
+        let rec = ProtectionAdvice::for_exposure(105.0, &protectors)
+            .recommendation
+            .unwrap();
+        assert_eq!(rec.nrr_db, 33);
+        assert_eq!(rec.projected_twa_db, 92.0);
+
+        assert!(ProtectionAdvice::for_exposure(95.0, &[])
+            .recommendation
+            .is_none());
+    }
+
+    #[test]
+    fn test_protector_request_validation() {
+        let mut req = HearingProtectorRequest {
+            name: "Foam plug".into(),
+            nrr_db: 33,
+            category: "earplug".into(),
+        };
+        assert!(req.validate().is_ok());
+        req.nrr_db = 60;
+        assert!(req.validate().is_err());
+        req.nrr_db = 33;
+        req.name = " ".into();
+        assert!(req.validate().is_err());
+    }
+}
@@ -0,0 +1,227 @@
+/// Hourly Pattern
+///
+/// When in the day a patient's surroundings are loudest and quietest, by
+/// local hour of the day. The ML service's analysis reports the same in UTC
+/// and only when it is reachable; here the hours follow a fixed UTC offset
+/// (e.g. `+03:00` for a ward in East Africa) and come straight from the
+/// readings. Each hour carries its sample count, and hours with fewer than
+/// `min_count` readings are never picked as loudest or quietest.
+///
+/// Only readings in one unit (dB unless asked otherwise) are looked at:
+/// raw ADC counts or dB(A) levels averaged with dB ones would mean nothing.
+/// For dB, readings that can be converted to it (see `units`) are looked at
+/// converted.
+use chrono::{DateTime, FixedOffset, Timelike, Utc};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+/// Days looked at by default
+pub const DEFAULT_DAYS: i64 = 7;
+
+/// Unit looked at by default
+pub const DEFAULT_UNIT: &str = "dB";
+
+/// Longest window, in days
+pub const MAX_DAYS: i64 = 90;
+
+/// Offset of a `±HH:MM` string, or `Z` for UTC. A leading space is read as
+/// `+`, since an unencoded `+` in a query string arrives as one.
+pub fn parse_utc_offset(tz: &str) -> Result<FixedOffset, String> {
+    let invalid = || format!("tz must be an offset like +03:00 or -05:30, got '{}'", tz);
+    if tz == "Z" {
+        return FixedOffset::east_opt(0).ok_or_else(invalid);
+    }
+    let (sign, rest) = match tz.as_bytes().first() {
+        Some(b'+' | b' ') => (1, &tz[1..]),
+        Some(b'-') => (-1, &tz[1..]),
+        _ => return Err(invalid()),
+    };
+    let (hours, minutes) = rest.split_once(':').ok_or_else(invalid)?;
+    if hours.len() != 2 || minutes.len() != 2 {
+        return Err(invalid());
+    }
+    let hours: i32 = hours.parse().map_err(|_| invalid())?;
+    let minutes: i32 = minutes.parse().map_err(|_| invalid())?;
+    if hours > 14 || minutes > 59 {
+        return Err(invalid());
+    }
+    FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60)).ok_or_else(invalid)
+}
+
+/// The offset as `±HH:MM`
+pub fn format_utc_offset(offset: FixedOffset) -> String {
+    let secs = offset.local_minus_utc();
+    let sign = if secs < 0 { '-' } else { '+' };
+    let secs = secs.abs();
+    format!("{}{:02}:{:02}", sign, secs / 3600, secs % 3600 / 60)
+}
+
+/// Readings of one local hour of the day, over every day of the window
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, ToSchema)]
+pub struct HourStats {
+    /// Local hour of the day, 0 to 23
+    pub hour: u32,
+    /// Arithmetic mean of the values; `null` without readings
+    pub mean: Option<f64>,
+    pub max: Option<f64>,
+    pub count: i64,
+}
+
+impl HourStats {
+    fn empty(hour: u32) -> Self {
+        Self {
+            hour,
+            mean: None,
+            max: None,
+            count: 0,
+        }
+    }
+
+    /// Stats of the hours the `(ts, value)` pairs of `values` fall in at
+    /// `offset`, hours without values left out. Mirrors the database query.
+    pub fn from_values(
+        values: impl IntoIterator<Item = (DateTime<Utc>, f64)>,
+        offset: FixedOffset,
+    ) -> Vec<Self> {
+        let mut hours: [(f64, f64, i64); 24] = [(0.0, f64::NEG_INFINITY, 0); 24];
+        for (ts, value) in values {
+            let (sum, max, count) = &mut hours[ts.with_timezone(&offset).hour() as usize];
+            *sum += value;
+            *max = max.max(value);
+            *count += 1;
+        }
+        hours
+            .iter()
+            .enumerate()
+            .filter(|(_, (_, _, count))| *count > 0)
+            .map(|(hour, &(sum, max, count))| Self {
+                hour: hour as u32,
+                mean: Some(sum / count as f64),
+                max: Some(max),
+                count,
+            })
+            .collect()
+    }
+}
+
+/// A patient's readings by local hour of the day
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct HourlyPattern {
+    pub patient_id: String,
+    /// Unit of the readings looked at; readings in others are left out,
+    /// unless they were converted to dB
+    pub unit: String,
+    /// Offset the hours are local to, as `±HH:MM`
+    pub tz: String,
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    /// Every hour of the day, 0 first
+    pub hours: Vec<HourStats>,
+    /// Hour with the highest mean among those with at least `min_count`
+    /// readings; `null` if there is none
+    pub loudest_hour: Option<u32>,
+    /// Likewise the lowest mean
+    pub quietest_hour: Option<u32>,
+}
+
+impl HourlyPattern {
+    /// The pattern of the hours with readings in `stats`; ties go to the
+    /// earlier hour
+    pub fn build(
+        patient_id: &str,
+        unit: &str,
+        offset: FixedOffset,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        stats: Vec<HourStats>,
+        min_count: i64,
+    ) -> Self {
+        let mut hours: Vec<_> = (0..24).map(HourStats::empty).collect();
+        for s in stats.into_iter().filter(|s| s.hour < 24) {
+            hours[s.hour as usize] = s;
+        }
+        let trusted = || {
+            hours
+                .iter()
+                .filter(|h| h.count >= min_count.max(1))
+                .filter_map(|h| Some((h.hour, h.mean?)))
+        };
+        let loudest_hour = trusted()
+            .reduce(|best, h| if h.1 > best.1 { h } else { best })
+            .map(|(hour, _)| hour);
+        let quietest_hour = trusted()
+            .reduce(|best, h| if h.1 < best.1 { h } else { best })
+            .map(|(hour, _)| hour);
+
+        Self {
+            patient_id: patient_id.to_string(),
+            unit: unit.to_string(),
+            tz: format_utc_offset(offset),
+            from,
+            to,
+            hours,
+            loudest_hour,
+            quietest_hour,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_offsets() {
+        assert_eq!(
+            parse_utc_offset("+03:00").unwrap().local_minus_utc(),
+            3 * 3600
+        );
+        assert_eq!(
+            parse_utc_offset("-05:30").unwrap().local_minus_utc(),
+            -(5 * 3600 + 30 * 60)
+        );
+        assert_eq!(parse_utc_offset(" 03:00"), parse_utc_offset("+03:00"));
+        assert_eq!(parse_utc_offset("Z").unwrap().local_minus_utc(), 0);
+        for bad in ["03:00", "+3:00", "+03", "+15:00", "+03:60", "UTC", ""] {
+            assert!(parse_utc_offset(bad).is_err(), "{}", bad);
+        }
+        assert_eq!(
+            format_utc_offset(parse_utc_offset("-05:30").unwrap()),
+            "-05:30"
+        );
+    }
+
+    #[test]
+    fn test_hours_are_local_and_sparse_hours_are_not_picked() {
+        let offset = parse_utc_offset("+03:00").unwrap();
+        let day = |h, m| Utc.with_ymd_and_hms(2026, 3, 2, h, m, 0).unwrap();
+        let values = [
+            // 14:00 local, three readings
+            (day(11, 0), 80.0),
+            (day(11, 20), 90.0),
+            (day(11, 40), 70.0),
+            // 03:00 local, twice
+            (day(0, 10), 40.0),
+            (day(0, 50), 44.0),
+            // 22:00 local, once but loudest
+            (day(19, 0), 100.0),
+        ];
+        let stats = HourStats::from_values(values, offset);
+        assert_eq!(stats.len(), 3);
+
+        let pattern =
+            HourlyPattern::build("p1", "dB", offset, day(0, 0), day(23, 0), stats.clone(), 2);
+        assert_eq!(pattern.hours.len(), 24);
+        assert_eq!(pattern.tz, "+03:00");
+        let h14 = pattern.hours[14];
+        assert_eq!((h14.mean, h14.max, h14.count), (Some(80.0), Some(90.0), 3));
+        assert_eq!(pattern.hours[22].count, 1);
+        assert_eq!(pattern.hours[5].mean, None);
+        assert_eq!(pattern.loudest_hour, Some(14));
+        assert_eq!(pattern.quietest_hour, Some(3));
+
+        let pattern = HourlyPattern::build("p1", "dB", offset, day(0, 0), day(23, 0), stats, 1);
+        assert_eq!(pattern.loudest_hour, Some(22));
+    }
+}
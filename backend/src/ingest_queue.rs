@@ -0,0 +1,324 @@
+/// Ingest Queue Module
+///
+/// Decouples ingest requests from persistence. Authenticated (clinical)
+/// readings go on a high-priority channel, public simulator traffic on a
+/// low-priority one; the worker always drains the high-priority channel first,
+/// so bulk simulator traffic can never delay clinical data.
+use std::sync::Arc;
+use tokio::sync::{mpsc, Mutex};
+
+use crate::auth::Claims;
+use crate::domain::models::StoredReading;
+use crate::domain::store::AppState;
+use crate::errors::AppError;
+use crate::fhir::FhirObservation;
+use crate::metrics::{self, Exposition, METRICS};
+use crate::ws::WsHub;
+
+/// Default capacity of each priority channel
+const DEFAULT_QUEUE_CAPACITY: usize = 10_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Priority {
+    /// Authenticated `/api/ingest` traffic
+    High,
+    /// Public `/ingest` traffic (simulator, mock data)
+    Low,
+}
+
+/// A validated reading waiting to be persisted and broadcast
+#[derive(Debug, Clone)]
+pub struct QueuedReading {
+    pub record: StoredReading,
+    pub obs: FhirObservation,
+    pub claims: Option<Claims>,
+    /// Publish to WebSocket clients once stored; off for the samples of a
+    /// packed reading that the live stream skips
+    pub broadcast: bool,
+}
+
+/// Producer side of the ingest queue, shared by all HTTP workers
+#[derive(Debug, Clone)]
+pub struct IngestQueue {
+    high_priority_tx: mpsc::Sender<QueuedReading>,
+    low_priority_tx: mpsc::Sender<QueuedReading>,
+    respond_ok: bool,
+}
+
+impl IngestQueue {
+    /// Create the queue and its worker; each channel holds `capacity` readings
+    pub fn new(capacity: usize) -> (Self, IngestWorker) {
+        let (high_priority_tx, high_rx) = mpsc::channel(capacity);
+        let (low_priority_tx, low_rx) = mpsc::channel(capacity);
+
+        let queue = Self {
+            high_priority_tx,
+            low_priority_tx,
+            respond_ok: false,
+        };
+        (queue, IngestWorker { high_rx, low_rx })
+    }
+
+    /// Create from `INGEST_QUEUE_CAPACITY` and `INGEST_QUEUE_RESPOND_200`
+    pub fn from_env() -> (Self, IngestWorker) {
+        let capacity = std::env::var("INGEST_QUEUE_CAPACITY")
+            .ok()
+            .and_then(|v| v.parse::<usize>().ok())
+            .filter(|v| *v > 0)
+            .unwrap_or(DEFAULT_QUEUE_CAPACITY);
+
+        let (mut queue, worker) = Self::new(capacity);
+        queue.respond_ok = std::env::var("INGEST_QUEUE_RESPOND_200")
+            .map(|v| v.eq_ignore_ascii_case("true"))
+            .unwrap_or(false);
+        (queue, worker)
+    }
+
+    /// Whether handlers should answer 200 instead of 202 (for older clients)
+    pub fn respond_ok(&self) -> bool {
+        self.respond_ok
+    }
+
+    /// Enqueue without waiting; a full queue is reported as 503
+    pub fn enqueue(&self, priority: Priority, item: QueuedReading) -> Result<(), AppError> {
+        let (tx, counter) = match priority {
+            Priority::High => (&self.high_priority_tx, &METRICS.ingest_enqueued_high),
+            Priority::Low => (&self.low_priority_tx, &METRICS.ingest_enqueued_low),
+        };
+
+        tx.try_send(item).map_err(|e| {
+            metrics::inc(&METRICS.ingest_queue_rejected);
+            tracing::warn!(?priority, "Ingest queue rejected reading: {}", e);
+            AppError::ServiceUnavailable("ingest queue is full".into())
+        })?;
+
+        metrics::inc(counter);
+        Ok(())
+    }
+
+    /// Enqueue a batch without waiting, all or nothing: if the channel lacks
+    /// room for every reading none is enqueued and the batch gets 503, so a
+    /// retry cannot duplicate the readings that fit
+    pub fn enqueue_all(
+        &self,
+        priority: Priority,
+        items: Vec<QueuedReading>,
+    ) -> Result<(), AppError> {
+        let (tx, counter) = match priority {
+            Priority::High => (&self.high_priority_tx, &METRICS.ingest_enqueued_high),
+            Priority::Low => (&self.low_priority_tx, &METRICS.ingest_enqueued_low),
+        };
+
+        let permits = tx.try_reserve_many(items.len()).map_err(|e| {
+            metrics::add(&METRICS.ingest_queue_rejected, items.len() as u64);
+            tracing::warn!(
+                ?priority,
+                readings = items.len(),
+                "Ingest queue rejected batch: {}",
+                e
+            );
+            AppError::ServiceUnavailable("ingest queue is full".into())
+        })?;
+
+        let count = items.len() as u64;
+        for (permit, item) in permits.zip(items) {
+            permit.send(item);
+        }
+        metrics::add(counter, count);
+        Ok(())
+    }
+
+    /// Readings currently waiting on a channel
+    pub fn depth(&self, priority: Priority) -> usize {
+        let tx = match priority {
+            Priority::High => &self.high_priority_tx,
+            Priority::Low => &self.low_priority_tx,
+        };
+        tx.max_capacity() - tx.capacity()
+    }
+
+    /// Render queue depth gauges
+    pub fn render_metrics(&self, exp: &mut Exposition) {
+        exp.family(
+            "soundsense_ingest_queue_depth",
+            "gauge",
+            "Readings waiting on the ingest queue",
+            &[
+                ("priority=\"high\"", self.depth(Priority::High) as u64),
+                ("priority=\"low\"", self.depth(Priority::Low) as u64),
+            ],
+        );
+    }
+}
+
+/// Consumer side: persists, audits and broadcasts queued readings
+#[derive(Debug)]
+pub struct IngestWorker {
+    high_rx: mpsc::Receiver<QueuedReading>,
+    low_rx: mpsc::Receiver<QueuedReading>,
+}
+
+impl IngestWorker {
+    /// Next reading to process, always preferring the high-priority channel.
+    /// Returns `None` once both channels are closed and empty.
+    pub async fn next(&mut self) -> Option<(Priority, QueuedReading)> {
+        if let Ok(item) = self.high_rx.try_recv() {
+            return Some((Priority::High, item));
+        }
+        if let Ok(item) = self.low_rx.try_recv() {
+            return Some((Priority::Low, item));
+        }
+
+        // Both empty: wait for whichever arrives first
+        tokio::select! {
+            biased;
+            Some(item) = self.high_rx.recv() => Some((Priority::High, item)),
+            Some(item) = self.low_rx.recv() => Some((Priority::Low, item)),
+            else => None,
+        }
+    }
+
+    /// Process readings until the queue is dropped
+    pub async fn run(mut self, state: Arc<Mutex<AppState>>, hub: WsHub) {
+        while let Some((priority, item)) = self.next().await {
+            {
+                let mut st = state.lock().await;
+                if let Err(e) = st.push(item.record, item.claims.as_ref()).await {
+                    tracing::error!(error = ?e, "Failed to store queued reading");
+                }
+            }
+
+            if item.broadcast {
+                hub.publish(&item.obs);
+            }
+
+            metrics::inc(match priority {
+                Priority::High => &METRICS.ingest_processed_high,
+                Priority::Low => &METRICS.ingest_processed_low,
+            });
+        }
+
+        tracing::info!("Ingest queue closed, worker exiting");
+    }
+
+    /// Run the worker on the tokio runtime
+    pub fn spawn(self, state: Arc<Mutex<AppState>>, hub: WsHub) {
+        tokio::spawn(self.run(state, hub));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::models::{SensorReading, SignalCode, TimestampSource};
+    use chrono::Utc;
+
+    fn queued(patient_id: &str) -> QueuedReading {
+        let record = StoredReading::new(SensorReading {
+            patient_id: patient_id.into(),
+            device_id: "d1".into(),
+            code: SignalCode::Sound,
+            value: 1.0,
+            unit: "raw".into(),
+            ts: Utc::now(),
+            ts_source: TimestampSource::Device,
+            metadata: Default::default(),
+        });
+        QueuedReading {
+            obs: FhirObservation::from_stored(record.clone()),
+            record,
+            claims: None,
+            broadcast: true,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_high_priority_processed_before_low_backlog() {
+        let (queue, mut worker) = IngestQueue::new(1000);
+
+        for _ in 0..100 {
+            queue.enqueue(Priority::Low, queued("simulated")).unwrap();
+        }
+        queue.enqueue(Priority::High, queued("clinical")).unwrap();
+        assert_eq!(queue.depth(Priority::Low), 100);
+        assert_eq!(queue.depth(Priority::High), 1);
+
+        let (priority, first) = worker.next().await.unwrap();
+        assert_eq!(priority, Priority::High);
+        assert_eq!(first.record.reading.patient_id, "clinical");
+
+        // A high-priority reading arriving mid-backlog jumps ahead again
+        worker.next().await.unwrap();
+        queue.enqueue(Priority::High, queued("clinical-2")).unwrap();
+        let (priority, item) = worker.next().await.unwrap();
+        assert_eq!(priority, Priority::High);
+        assert_eq!(item.record.reading.patient_id, "clinical-2");
+    }
+
+    #[tokio::test]
+    async fn test_worker_persists_and_broadcasts() {
+        let (queue, worker) = IngestQueue::new(1000);
+        let state = Arc::new(Mutex::new(AppState::new_demo()));
+        let hub = WsHub::new(None);
+        let mut hub_rx = hub.tx.subscribe();
+
+        for _ in 0..100 {
+            queue.enqueue(Priority::Low, queued("simulated")).unwrap();
+        }
+        queue.enqueue(Priority::High, queued("clinical")).unwrap();
+
+        // Dropping the producer lets the worker exit once drained
+        drop(queue);
+        worker.run(state.clone(), hub).await;
+
+        let first_broadcast = hub_rx.recv().await.unwrap();
+        assert_eq!(first_broadcast.subject.reference, "Patient/clinical");
+
+        let observations = state
+            .lock()
+            .await
+            .recent_observations(200, 0, &Default::default())
+            .await
+            .unwrap();
+        assert_eq!(observations.len(), 101);
+        // Newest first, so the first stored reading is last
+        assert_eq!(
+            observations.last().unwrap().subject.reference,
+            "Patient/clinical"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_full_queue_is_rejected() {
+        let (queue, _worker) = IngestQueue::new(1);
+
+        queue.enqueue(Priority::Low, queued("a")).unwrap();
+        assert!(matches!(
+            queue.enqueue(Priority::Low, queued("b")),
+            Err(AppError::ServiceUnavailable(_))
+        ));
+        // The other channel has its own capacity
+        queue.enqueue(Priority::High, queued("c")).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_batch_is_enqueued_whole_or_not_at_all() {
+        let (queue, mut worker) = IngestQueue::new(3);
+
+        queue.enqueue(Priority::High, queued("a")).unwrap();
+        assert!(matches!(
+            queue.enqueue_all(Priority::High, vec![queued("b"), queued("c"), queued("d")]),
+            Err(AppError::ServiceUnavailable(_))
+        ));
+        assert_eq!(queue.depth(Priority::High), 1);
+
+        queue
+            .enqueue_all(Priority::High, vec![queued("b"), queued("c")])
+            .unwrap();
+        assert_eq!(queue.depth(Priority::High), 3);
+        for patient_id in ["a", "b", "c"] {
+            let (_, item) = worker.next().await.unwrap();
+            assert_eq!(item.record.reading.patient_id, patient_id);
+        }
+    }
+}
@@ -0,0 +1,419 @@
+/// IoT Device Handshake
+///
+/// For devices without secure storage for a long-lived token: each one is
+/// provisioned with a factory key and trades a signed, timestamped answer to
+/// a 30-second nonce for a device token valid for an hour. The device sends
+/// `HMAC-SHA256(factory_key, nonce + device_id + timestamp)`, hex-encoded,
+/// with `timestamp` its clock in Unix seconds, which must be within 5
+/// seconds of the server's.
+///
+/// Nonces are not stored when handed out: each names its device and expiry
+/// and carries a tag from `IOT_NONCE_KEY`, so only nonces the deployment
+/// issued are accepted, by whichever replica the answer reaches. A nonce is recorded as used once it has been redeemed, and
+/// forgotten when it expires, so an answer cannot be replayed.
+///
+/// As with per-device secrets, only the SHA-256 of a factory key is stored;
+/// factory keys are longer than the HMAC block, so the hash is the key HMAC
+/// actually uses.
+use chrono::{DateTime, Duration, Utc};
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use utoipa::ToSchema;
+
+use crate::db::Database;
+use crate::device_auth::{generate_device_secret, secret_hash};
+use crate::errors::AppError;
+
+/// How long a nonce can be answered
+pub const NONCE_TTL: Duration = Duration::seconds(30);
+
+/// Largest difference between a device's timestamp and server time
+pub const CLOCK_SKEW: Duration = Duration::seconds(5);
+
+/// Lifetime of the device tokens issued
+pub const TOKEN_HOURS: i64 = 1;
+
+/// Random bytes in a nonce
+const NONCE_BYTES: usize = 16;
+
+/// Shortest `IOT_NONCE_KEY` accepted
+const MIN_NONCE_KEY_CHARS: usize = 32;
+
+/// Read `IOT_NONCE_KEY`, the secret nonces are tagged with, shared by every
+/// replica so a nonce can be answered on any of them and across restarts.
+/// Unset means a random key per process; shorter than 32 characters is an
+/// error.
+pub fn nonce_key_from_env() -> Result<Option<[u8; 32]>, String> {
+    let Some(secret) = std::env::var("IOT_NONCE_KEY")
+        .ok()
+        .filter(|s| !s.trim().is_empty())
+    else {
+        return Ok(None);
+    };
+    if secret.chars().count() < MIN_NONCE_KEY_CHARS {
+        return Err(format!(
+            "IOT_NONCE_KEY must be at least {} characters",
+            MIN_NONCE_KEY_CHARS
+        ));
+    }
+    Ok(Some(nonce_key(&secret)))
+}
+
+/// The 32-byte nonce key derived from `secret`
+pub fn nonce_key(secret: &str) -> [u8; 32] {
+    <Sha256 as sha2::Digest>::digest(secret.as_bytes()).into()
+}
+
+/// A nonce a device must sign to get a token
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct IotChallenge {
+    pub device_id: String,
+    /// Sign it as given, without decoding
+    pub nonce: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// A device's signed answer to a nonce
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+pub struct IotTokenRequest {
+    pub device_id: String,
+    /// The device's clock, Unix seconds
+    pub timestamp: i64,
+    pub nonce: String,
+    /// Hex `HMAC-SHA256(factory_key, nonce + device_id + timestamp)`
+    pub hmac: String,
+}
+
+impl IotTokenRequest {
+    /// The bytes the device signs
+    pub fn message(&self) -> String {
+        format!("{}{}{}", self.nonce, self.device_id, self.timestamp)
+    }
+}
+
+/// Whether `hmac` (hex) signs `message` under the factory key whose hash is
+/// `key_hash`. Compares in constant time.
+pub fn verify_hmac(key_hash: &str, message: &str, hmac: &str) -> bool {
+    let (Ok(key), Ok(hmac)) = (hex::decode(key_hash), hex::decode(hmac)) else {
+        return false;
+    };
+    let mut mac = Hmac::<Sha256>::new_from_slice(&key).expect("HMAC takes keys of any length");
+    mac.update(message.as_bytes());
+    mac.verify_slice(&hmac).is_ok()
+}
+
+/// Factory key hashes and used nonces, in the database or (without one) in
+/// memory
+#[derive(Debug)]
+pub struct IotAuthStore {
+    db: Option<Database>,
+    /// Tags the nonces handed out; random per process unless configured
+    nonce_key: [u8; 32],
+    nonce_ttl: Duration,
+    /// Factory key hash per device, without a database
+    devices: RwLock<HashMap<String, String>>,
+    /// Redeemed nonces and when they expire, without a database
+    used_nonces: RwLock<HashMap<String, DateTime<Utc>>>,
+}
+
+impl IotAuthStore {
+    pub fn new(db: Option<Database>) -> Self {
+        let mut nonce_key = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut nonce_key);
+        Self {
+            db,
+            nonce_key,
+            nonce_ttl: NONCE_TTL,
+            devices: RwLock::new(HashMap::new()),
+            used_nonces: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Tag nonces with `key` instead of a random one, so other stores with
+    /// the same key accept them
+    pub fn with_nonce_key(mut self, key: [u8; 32]) -> Self {
+        self.nonce_key = key;
+        self
+    }
+
+    /// Let nonces be answered for `ttl` instead of 30 seconds
+    pub fn with_nonce_ttl(mut self, ttl: Duration) -> Self {
+        self.nonce_ttl = ttl;
+        self
+    }
+
+    /// Register `device_id` with a new factory key and return it, the only
+    /// time it is available; 409 if the device is registered already
+    pub async fn provision(&self, device_id: &str) -> Result<String, AppError> {
+        let factory_key = generate_device_secret();
+        let hash = secret_hash(&factory_key);
+        let created = match &self.db {
+            Some(db) => db.insert_iot_device(device_id, &hash).await?,
+            None => {
+                let mut devices = self.devices.write().await;
+                if devices.contains_key(device_id) {
+                    false
+                } else {
+                    devices.insert(device_id.to_string(), hash);
+                    true
+                }
+            }
+        };
+        if !created {
+            return Err(AppError::Conflict(format!(
+                "IoT device {} is already provisioned",
+                device_id
+            )));
+        }
+        Ok(factory_key)
+    }
+
+    /// Whether `device_id` has been registered with a factory key
+    pub async fn is_provisioned(&self, device_id: &str) -> Result<bool, AppError> {
+        match &self.db {
+            Some(db) => Ok(db.iot_factory_key_hash(device_id).await?.is_some()),
+            None => Ok(self.devices.read().await.contains_key(device_id)),
+        }
+    }
+
+    /// A fresh nonce for a provisioned device
+    pub async fn challenge(&self, device_id: &str) -> Result<IotChallenge, AppError> {
+        if self.factory_key_hash(device_id).await?.is_none() {
+            return Err(AppError::NotFound(format!(
+                "IoT device {} is not provisioned",
+                device_id
+            )));
+        }
+
+        let mut random = [0u8; NONCE_BYTES];
+        rand::thread_rng().fill_bytes(&mut random);
+        let expires_at = Utc::now() + self.nonce_ttl;
+        let body = format!("{}.{}", expires_at.timestamp_millis(), hex::encode(random));
+        Ok(IotChallenge {
+            device_id: device_id.to_string(),
+            nonce: format!(
+                "{}.{}",
+                body,
+                hex::encode(self.nonce_mac(device_id, &body).finalize().into_bytes())
+            ),
+            expires_at,
+        })
+    }
+
+    /// Check a device's answer and use its nonce up; anything wrong with it
+    /// is `Unauthorized`
+    pub async fn redeem(&self, request: &IotTokenRequest) -> Result<(), AppError> {
+        let device_id = &request.device_id;
+        let now = Utc::now();
+
+        let Some(expires_at) = self.nonce_expiry(device_id, &request.nonce) else {
+            tracing::warn!("IoT device {} sent a nonce not issued to it", device_id);
+            return Err(AppError::Unauthorized);
+        };
+        if now >= expires_at {
+            tracing::warn!("IoT device {} answered an expired nonce", device_id);
+            return Err(AppError::Unauthorized);
+        }
+        let skew = DateTime::from_timestamp(request.timestamp, 0).map(|ts| ts - now);
+        if skew.is_none_or(|skew| skew.abs() > CLOCK_SKEW) {
+            tracing::warn!(
+                "IoT device {} sent timestamp {}, too far from server time",
+                device_id,
+                request.timestamp
+            );
+            return Err(AppError::Unauthorized);
+        }
+
+        let Some(key_hash) = self.factory_key_hash(device_id).await? else {
+            tracing::warn!("Unprovisioned IoT device {} asked for a token", device_id);
+            return Err(AppError::Unauthorized);
+        };
+        if !verify_hmac(&key_hash, &request.message(), &request.hmac) {
+            tracing::warn!("IoT device {} sent a wrong HMAC", device_id);
+            return Err(AppError::Unauthorized);
+        }
+
+        if !self
+            .use_nonce(device_id, &request.nonce, expires_at)
+            .await?
+        {
+            tracing::warn!("IoT device {} replayed a used nonce", device_id);
+            return Err(AppError::Unauthorized);
+        }
+        Ok(())
+    }
+
+    /// Forget used nonces that have expired, returning how many
+    pub async fn cleanup_expired(&self) -> Result<u64, AppError> {
+        let now = Utc::now();
+        if let Some(db) = &self.db {
+            return db.delete_expired_nonces(now).await;
+        }
+        let mut used = self.used_nonces.write().await;
+        let before = used.len();
+        used.retain(|_, expires_at| *expires_at > now);
+        Ok((before - used.len()) as u64)
+    }
+
+    /// Forget expired nonces every `interval`
+    pub fn spawn_cleanup(self: &Arc<Self>, interval: std::time::Duration) {
+        let store = self.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                match store.cleanup_expired().await {
+                    Ok(removed) if removed > 0 => {
+                        tracing::debug!(removed, "Removed expired IoT nonces")
+                    }
+                    Ok(_) => {}
+                    Err(e) => tracing::warn!(error = ?e, "IoT nonce cleanup failed"),
+                }
+            }
+        });
+    }
+
+    async fn factory_key_hash(&self, device_id: &str) -> Result<Option<String>, AppError> {
+        match &self.db {
+            Some(db) => db.iot_factory_key_hash(device_id).await,
+            None => Ok(self.devices.read().await.get(device_id).cloned()),
+        }
+    }
+
+    /// Record `nonce` as used; false if it was already
+    async fn use_nonce(
+        &self,
+        device_id: &str,
+        nonce: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<bool, AppError> {
+        if let Some(db) = &self.db {
+            return db.insert_used_nonce(nonce, device_id, expires_at).await;
+        }
+        let mut used = self.used_nonces.write().await;
+        if used.contains_key(nonce) {
+            return Ok(false);
+        }
+        used.insert(nonce.to_string(), expires_at);
+        Ok(true)
+    }
+
+    /// MAC binding a nonce `body` to `device_id`
+    fn nonce_mac(&self, device_id: &str, body: &str) -> Hmac<Sha256> {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.nonce_key).expect("HMAC takes keys of any length");
+        mac.update(device_id.as_bytes());
+        mac.update(b"\0");
+        mac.update(body.as_bytes());
+        mac
+    }
+
+    /// When `nonce` expires, if it was issued to `device_id` under our key
+    fn nonce_expiry(&self, device_id: &str, nonce: &str) -> Option<DateTime<Utc>> {
+        let (body, tag) = nonce.rsplit_once('.')?;
+        self.nonce_mac(device_id, body)
+            .verify_slice(&hex::decode(tag).ok()?)
+            .ok()?;
+
+        let (expires_ms, _) = body.split_once('.')?;
+        DateTime::from_timestamp_millis(expires_ms.parse().ok()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// What a device computes, keyed with its factory key itself
+    fn sign(factory_key: &str, challenge: &IotChallenge, timestamp: i64) -> IotTokenRequest {
+        let mut request = IotTokenRequest {
+            device_id: challenge.device_id.clone(),
+            timestamp,
+            nonce: challenge.nonce.clone(),
+            hmac: String::new(),
+        };
+        let mut mac = Hmac::<Sha256>::new_from_slice(factory_key.as_bytes()).unwrap();
+        mac.update(request.message().as_bytes());
+        request.hmac = hex::encode(mac.finalize().into_bytes());
+        request
+    }
+
+    #[tokio::test]
+    async fn test_nonces_are_bound_to_their_device() {
+        let store = IotAuthStore::new(None);
+        let key = store.provision("d1").await.unwrap();
+        store.provision("d2").await.unwrap();
+        assert!(matches!(
+            store.provision("d1").await,
+            Err(AppError::Conflict(_))
+        ));
+
+        let challenge = store.challenge("d2").await.unwrap();
+        let stolen = IotChallenge {
+            device_id: "d1".into(),
+            ..challenge
+        };
+        let request = sign(&key, &stolen, Utc::now().timestamp());
+        assert!(store.redeem(&request).await.is_err());
+
+        let forged = IotChallenge {
+            nonce: format!("{}.00", Utc::now().timestamp_millis() + 10_000),
+            ..stolen
+        };
+        let request = sign(&key, &forged, Utc::now().timestamp());
+        assert!(store.redeem(&request).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_stores_sharing_a_key_accept_each_others_nonces() {
+        let issuer = IotAuthStore::new(None).with_nonce_key(nonce_key("k1"));
+        let key = issuer.provision("d1").await.unwrap();
+        let challenge = issuer.challenge("d1").await.unwrap();
+        let request = sign(&key, &challenge, Utc::now().timestamp());
+
+        // Another process: same devices, its own key or the shared one
+        let stranger = IotAuthStore::new(None);
+        let replica = IotAuthStore::new(None).with_nonce_key(nonce_key("k1"));
+        for store in [&stranger, &replica] {
+            let hash = issuer.factory_key_hash("d1").await.unwrap().unwrap();
+            store.devices.write().await.insert("d1".into(), hash);
+        }
+        assert!(stranger.redeem(&request).await.is_err());
+        assert!(replica.redeem(&request).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_timestamps_must_be_close_to_server_time() {
+        let store = IotAuthStore::new(None);
+        let key = store.provision("d1").await.unwrap();
+        let now = Utc::now().timestamp();
+
+        for timestamp in [now - 60, now + 60] {
+            let challenge = store.challenge("d1").await.unwrap();
+            let request = sign(&key, &challenge, timestamp);
+            assert!(store.redeem(&request).await.is_err());
+        }
+        let challenge = store.challenge("d1").await.unwrap();
+        assert!(store.redeem(&sign(&key, &challenge, now)).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_expired_used_nonces_are_forgotten() {
+        let store = IotAuthStore::new(None).with_nonce_ttl(Duration::milliseconds(50));
+        let key = store.provision("d1").await.unwrap();
+        let challenge = store.challenge("d1").await.unwrap();
+        store
+            .redeem(&sign(&key, &challenge, Utc::now().timestamp()))
+            .await
+            .unwrap();
+
+        assert_eq!(store.cleanup_expired().await.unwrap(), 0);
+        tokio::time::sleep(std::time::Duration::from_millis(60)).await;
+        assert_eq!(store.cleanup_expired().await.unwrap(), 1);
+    }
+}
@@ -1,11 +1,55 @@
+pub mod acoustics;
+pub mod alert_suppression;
+pub mod anomaly;
 pub mod audit;
 pub mod auth;
+pub mod build_info;
+pub mod bulk_export;
+pub mod bundle_stream;
+pub mod calibration;
+pub mod compliance;
+pub mod consent;
+pub mod content_type;
+pub mod cors;
+pub mod dashboard;
 pub mod db;
+pub mod decimation;
+pub mod device_auth;
+pub mod device_stats;
+pub mod device_watchdog;
+pub mod dlq;
 pub mod domain;
 pub mod errors;
 pub mod fhir;
+pub mod gaps;
+pub mod hearing;
+pub mod hourly_pattern;
+pub mod ingest_queue;
+pub mod iot_auth;
+pub mod live_stats;
+pub mod load_shed;
+pub mod log_sampling;
+pub mod metrics;
+pub mod ml_cache;
 pub mod ml_client;
+pub mod noise_map;
+pub mod normalization;
+pub mod oidc;
+pub mod reports;
+pub mod rollups;
 pub mod routes;
+pub mod security;
+pub mod self_check;
 pub mod serial_ingest;
+pub mod sessions;
+pub mod snapshot;
+pub mod spectrogram;
+pub mod stream_ingest;
 pub mod telemetry;
+pub mod text_search;
+pub mod timeout;
+pub mod training;
+pub mod units;
+pub mod users;
+pub mod validation;
 pub mod ws;
@@ -0,0 +1,420 @@
+/// Live Statistics Module
+///
+/// Rolling mean and maximum of each patient's signals over the last minute
+/// and the last five, for gauges. Every stream keeps one bucket per second
+/// of the longest window in a ring, so a reading costs one bucket update
+/// however many arrive; a summary adds up at most that many buckets.
+/// Readings are bucketed by when they arrive rather than by their own
+/// timestamp, so a device clock running off doesn't move the gauge, and
+/// readings taken longer ago than the longest window (backfills) are left
+/// out. The WebSocket `stats` frames and `GET /api/patients/{id}/gauge`
+/// both read from here, so they agree. Streams gone quiet are swept out as
+/// readings arrive, and the number of streams is capped, so the map stays
+/// bounded whether or not anyone reads it.
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use utoipa::ToSchema;
+
+use crate::domain::models::{SensorReading, SignalCode};
+
+/// Seconds kept per stream: the longest window
+const BUCKETS: usize = 300;
+
+/// Streams kept at most; a new one beyond it replaces the quietest
+pub const MAX_STREAMS: usize = 10_000;
+
+/// Seconds between sweeps of idle streams while recording
+const SWEEP_SECS: i64 = 60;
+
+/// A window statistics are taken over, ending now
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+pub enum StatsWindow {
+    #[serde(rename = "1m")]
+    OneMinute,
+    #[serde(rename = "5m")]
+    FiveMinutes,
+}
+
+impl StatsWindow {
+    pub const ALL: [StatsWindow; 2] = [StatsWindow::OneMinute, StatsWindow::FiveMinutes];
+
+    pub fn secs(self) -> i64 {
+        match self {
+            StatsWindow::OneMinute => 60,
+            StatsWindow::FiveMinutes => BUCKETS as i64,
+        }
+    }
+}
+
+/// Readings of one second
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    second: i64,
+    count: u64,
+    sum: f64,
+    max: f64,
+}
+
+impl Bucket {
+    const EMPTY: Bucket = Bucket {
+        second: i64::MIN,
+        count: 0,
+        sum: 0.0,
+        max: f64::NEG_INFINITY,
+    };
+}
+
+/// Count, mean and maximum of a window; no mean or maximum while empty
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, ToSchema)]
+pub struct WindowStats {
+    pub count: u64,
+    pub mean: Option<f64>,
+    pub max: Option<f64>,
+}
+
+/// Per-second buckets of one stream over the longest window
+#[derive(Debug, Clone)]
+pub struct RollingStats {
+    buckets: Vec<Bucket>,
+    /// Latest second a reading was counted in
+    newest: i64,
+}
+
+impl Default for RollingStats {
+    fn default() -> Self {
+        Self {
+            buckets: vec![Bucket::EMPTY; BUCKETS],
+            newest: i64::MIN,
+        }
+    }
+}
+
+impl RollingStats {
+    /// Count `value` in the bucket of `second` (Unix time), reusing the
+    /// bucket if it still holds a second that has left every window. A
+    /// second already out of the longest window is ignored.
+    pub fn push(&mut self, second: i64, value: f64) {
+        if second <= self.newest.saturating_sub(BUCKETS as i64) {
+            return;
+        }
+        let bucket = &mut self.buckets[second.rem_euclid(BUCKETS as i64) as usize];
+        if bucket.second != second {
+            *bucket = Bucket {
+                second,
+                ..Bucket::EMPTY
+            };
+        }
+        bucket.count += 1;
+        bucket.sum += value;
+        bucket.max = bucket.max.max(value);
+        self.newest = self.newest.max(second);
+    }
+
+    /// Statistics of the `window` seconds up to and including `now`
+    pub fn summary(&self, window: StatsWindow, now: i64) -> WindowStats {
+        let from = now - window.secs();
+        let (count, sum, max) = self
+            .buckets
+            .iter()
+            .filter(|b| b.count > 0 && b.second > from && b.second <= now)
+            .fold((0, 0.0, f64::NEG_INFINITY), |(count, sum, max), b| {
+                (count + b.count, sum + b.sum, f64::max(max, b.max))
+            });
+        WindowStats {
+            count,
+            mean: (count > 0).then(|| sum / count as f64),
+            max: (count > 0).then_some(max),
+        }
+    }
+
+    /// Whether nothing was counted within the longest window before `now`
+    pub fn is_idle(&self, now: i64) -> bool {
+        self.newest <= now - BUCKETS as i64
+    }
+}
+
+/// Statistics of one patient's signal over one window: a WebSocket `stats`
+/// frame, and an entry of the gauge endpoint
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct StatsFrame {
+    pub patient: String,
+    pub code: SignalCode,
+    pub window: StatsWindow,
+    #[serde(flatten)]
+    pub stats: WindowStats,
+}
+
+/// Streams by patient and signal, and when idle ones were last dropped
+#[derive(Debug)]
+struct Streams {
+    by_key: HashMap<(String, SignalCode), RollingStats>,
+    swept_at: i64,
+}
+
+impl Streams {
+    /// Forget the streams with nothing in the longest window before `now`
+    fn sweep(&mut self, now: i64) {
+        self.by_key.retain(|_, stats| !stats.is_idle(now));
+        self.swept_at = now;
+    }
+}
+
+/// Rolling statistics of every patient and signal heard from recently
+#[derive(Debug)]
+pub struct LiveStats {
+    max_streams: usize,
+    streams: Mutex<Streams>,
+}
+
+impl Default for LiveStats {
+    fn default() -> Self {
+        Self::new(MAX_STREAMS)
+    }
+}
+
+impl LiveStats {
+    /// Keep at most `max_streams` streams
+    pub fn new(max_streams: usize) -> Self {
+        Self {
+            max_streams: max_streams.max(1),
+            streams: Mutex::new(Streams {
+                by_key: HashMap::new(),
+                swept_at: i64::MIN,
+            }),
+        }
+    }
+
+    /// Count `reading`, arriving at `now`, unless it was taken before the
+    /// longest window. Idle streams are swept out every minute; a new
+    /// stream when the map is full takes the place of the one heard from
+    /// least recently.
+    pub fn record(&self, reading: &SensorReading, now: DateTime<Utc>) {
+        let now = now.timestamp();
+        if reading.ts.timestamp() <= now - BUCKETS as i64 {
+            return;
+        }
+        let mut streams = self.streams.lock().unwrap_or_else(|e| e.into_inner());
+        if now.saturating_sub(streams.swept_at) >= SWEEP_SECS {
+            streams.sweep(now);
+        }
+
+        let key = (reading.patient_id.clone(), reading.code);
+        if !streams.by_key.contains_key(&key) && streams.by_key.len() >= self.max_streams {
+            streams.sweep(now);
+            if streams.by_key.len() >= self.max_streams {
+                let quietest = streams
+                    .by_key
+                    .iter()
+                    .min_by_key(|(_, stats)| stats.newest)
+                    .map(|(key, _)| key.clone());
+                if let Some(quietest) = quietest {
+                    streams.by_key.remove(&quietest);
+                }
+            }
+        }
+        streams
+            .by_key
+            .entry(key)
+            .or_default()
+            .push(now, reading.value);
+    }
+
+    /// A frame per window of each stream with readings in the longest
+    /// window before `now`, limited to `patient_id` and `code` if given,
+    /// by patient and code. Streams gone quiet are forgotten.
+    pub fn frames(
+        &self,
+        now: DateTime<Utc>,
+        patient_id: Option<&str>,
+        code: Option<SignalCode>,
+    ) -> Vec<StatsFrame> {
+        let now = now.timestamp();
+        let mut streams = self.streams.lock().unwrap_or_else(|e| e.into_inner());
+        streams.sweep(now);
+        let streams = &streams.by_key;
+
+        let mut keys: Vec<_> = streams
+            .keys()
+            .filter(|(patient, signal)| {
+                patient_id.is_none_or(|id| id == patient) && code.is_none_or(|c| c == *signal)
+            })
+            .cloned()
+            .collect();
+        keys.sort_by(|a, b| (&a.0, a.1.as_str()).cmp(&(&b.0, b.1.as_str())));
+
+        let mut frames = Vec::new();
+        for key in keys {
+            let stats = &streams[&key];
+            for window in StatsWindow::ALL {
+                frames.push(StatsFrame {
+                    patient: key.0.clone(),
+                    code: key.1,
+                    window,
+                    stats: stats.summary(window, now),
+                });
+            }
+        }
+        frames
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn reading(patient_id: &str, value: f64, ts: DateTime<Utc>) -> SensorReading {
+        SensorReading {
+            patient_id: patient_id.into(),
+            device_id: "d1".into(),
+            code: SignalCode::Sound,
+            value,
+            unit: "dB".into(),
+            ts,
+            ts_source: Default::default(),
+            metadata: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_windows_roll_over_second_by_second() {
+        let mut stats = RollingStats::default();
+        // One reading a second for ten minutes, valued by its second
+        for second in 0..600 {
+            stats.push(second, second as f64);
+        }
+        let now = 599;
+        let minute = stats.summary(StatsWindow::OneMinute, now);
+        assert_eq!(minute.count, 60);
+        assert_eq!(minute.mean, Some((540..600).sum::<i64>() as f64 / 60.0));
+        assert_eq!(minute.max, Some(599.0));
+        let five = stats.summary(StatsWindow::FiveMinutes, now);
+        assert_eq!(five.count, 300);
+        assert_eq!(five.mean, Some((300..600).sum::<i64>() as f64 / 300.0));
+
+        // A minute later the last minute's buckets have left the short window
+        let later = stats.summary(StatsWindow::OneMinute, now + 60);
+        assert_eq!(later.count, 0);
+        assert_eq!(stats.summary(StatsWindow::FiveMinutes, now + 60).count, 240);
+
+        // Reusing a bucket drops what it held, five minutes earlier
+        stats.push(600, 1.0);
+        stats.push(600, 3.0);
+        let minute = stats.summary(StatsWindow::OneMinute, 600);
+        assert_eq!(minute.count, 61);
+        assert_eq!(stats.summary(StatsWindow::FiveMinutes, 600).count, 301);
+        assert_eq!(stats.summary(StatsWindow::OneMinute, 659).mean, Some(2.0));
+
+        // Too late for any window
+        stats.push(300, 1000.0);
+        assert_eq!(
+            stats.summary(StatsWindow::FiveMinutes, 600).max,
+            Some(599.0)
+        );
+    }
+
+    #[test]
+    fn test_empty_windows_have_no_mean_or_max() {
+        let empty = WindowStats {
+            count: 0,
+            mean: None,
+            max: None,
+        };
+        let mut stats = RollingStats::default();
+        assert_eq!(stats.summary(StatsWindow::OneMinute, 1_000), empty);
+        assert!(stats.is_idle(1_000));
+
+        stats.push(1_000, 70.0);
+        // Not yet in the window, then in both, then in neither
+        assert_eq!(stats.summary(StatsWindow::OneMinute, 999), empty);
+        assert_eq!(stats.summary(StatsWindow::OneMinute, 1_030).count, 1);
+        assert_eq!(stats.summary(StatsWindow::OneMinute, 1_060), empty);
+        assert_eq!(stats.summary(StatsWindow::FiveMinutes, 1_299).count, 1);
+        assert_eq!(stats.summary(StatsWindow::FiveMinutes, 1_300), empty);
+        assert!(stats.is_idle(1_300));
+    }
+
+    #[test]
+    fn test_frames_of_recent_streams() {
+        let live = LiveStats::default();
+        let now = Utc.with_ymd_and_hms(2026, 3, 1, 12, 0, 0).unwrap();
+        live.record(&reading("p2", 80.0, now), now);
+        live.record(&reading("p1", 60.0, now), now);
+        live.record(
+            &reading("p1", 70.0, now),
+            now + chrono::Duration::seconds(90),
+        );
+        // A backfilled reading counts nowhere
+        live.record(
+            &reading("p3", 90.0, now - chrono::Duration::minutes(10)),
+            now,
+        );
+
+        let later = now + chrono::Duration::seconds(100);
+        let frames = live.frames(later, None, None);
+        let summary: Vec<_> = frames
+            .iter()
+            .map(|f| (f.patient.as_str(), f.window, f.stats.count, f.stats.max))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("p1", StatsWindow::OneMinute, 1, Some(70.0)),
+                ("p1", StatsWindow::FiveMinutes, 2, Some(70.0)),
+                ("p2", StatsWindow::OneMinute, 0, None),
+                ("p2", StatsWindow::FiveMinutes, 1, Some(80.0)),
+            ]
+        );
+        assert_eq!(
+            live.frames(later, Some("p2"), Some(SignalCode::Sound))
+                .len(),
+            2
+        );
+
+        let json = serde_json::to_value(&frames[1]).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "patient": "p1", "code": "sound", "window": "5m",
+                "count": 2, "mean": 65.0, "max": 70.0,
+            })
+        );
+
+        // Quiet for five minutes: forgotten
+        assert!(live
+            .frames(now + chrono::Duration::minutes(10), None, None)
+            .is_empty());
+        assert!(live.streams.lock().unwrap().by_key.is_empty());
+    }
+
+    #[test]
+    fn test_streams_are_swept_and_capped_without_readers() {
+        let live = LiveStats::new(2);
+        let now = Utc.with_ymd_and_hms(2026, 3, 1, 12, 0, 0).unwrap();
+        let at = |secs: i64| now + chrono::Duration::seconds(secs);
+        let patients = |live: &LiveStats| {
+            let mut ids: Vec<_> = live
+                .streams
+                .lock()
+                .unwrap()
+                .by_key
+                .keys()
+                .map(|(patient, _)| patient.clone())
+                .collect();
+            ids.sort();
+            ids
+        };
+
+        live.record(&reading("p1", 60.0, at(0)), at(0));
+        live.record(&reading("p2", 70.0, at(10)), at(10));
+        // Full: the stream heard from least recently makes way
+        live.record(&reading("p3", 80.0, at(20)), at(20));
+        assert_eq!(patients(&live), ["p2", "p3"]);
+
+        // Once quiet for five minutes, streams leave on the next reading
+        live.record(&reading("p3", 80.0, at(400)), at(400));
+        assert_eq!(patients(&live), ["p3"]);
+    }
+}
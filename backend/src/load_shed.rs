@@ -0,0 +1,119 @@
+/// Ingest Load Shedding Module
+///
+/// Caps the number of ingest requests in flight at once. When every permit is
+/// taken, new requests are turned away with 429 and `Retry-After` instead of
+/// piling up behind the store lock; well-behaved devices back off and retry.
+use actix_web::body::{EitherBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use actix_web::{web, Error};
+use std::sync::Arc;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::errors::AppError;
+use crate::metrics::{self, Exposition, METRICS};
+
+/// Default number of concurrent ingest requests
+const DEFAULT_MAX_CONCURRENT_INGESTS: usize = 256;
+
+/// Default `Retry-After` sent with 429 responses (seconds)
+const DEFAULT_RETRY_AFTER_SECS: u64 = 1;
+
+/// Shared in-flight counter for the ingest routes
+#[derive(Debug, Clone)]
+pub struct IngestLimiter {
+    permits: Arc<Semaphore>,
+    max_in_flight: usize,
+    retry_after_secs: u64,
+}
+
+impl IngestLimiter {
+    pub fn new(max_in_flight: usize, retry_after_secs: u64) -> Self {
+        Self {
+            permits: Arc::new(Semaphore::new(max_in_flight)),
+            max_in_flight,
+            retry_after_secs,
+        }
+    }
+
+    /// Create from `MAX_CONCURRENT_INGESTS` and `INGEST_RETRY_AFTER_SECS`
+    pub fn from_env() -> Self {
+        let max_in_flight = std::env::var("MAX_CONCURRENT_INGESTS")
+            .ok()
+            .and_then(|v| v.parse::<usize>().ok())
+            .filter(|v| *v > 0)
+            .unwrap_or(DEFAULT_MAX_CONCURRENT_INGESTS);
+        let retry_after_secs = std::env::var("INGEST_RETRY_AFTER_SECS")
+            .ok()
+            .and_then(|v| v.parse::<u64>().ok())
+            .filter(|v| *v > 0)
+            .unwrap_or(DEFAULT_RETRY_AFTER_SECS);
+
+        Self::new(max_in_flight, retry_after_secs)
+    }
+
+    /// Take a permit without waiting; saturation is reported as 429
+    pub fn try_acquire(&self) -> Result<OwnedSemaphorePermit, AppError> {
+        self.permits.clone().try_acquire_owned().map_err(|_| {
+            metrics::inc(&METRICS.ingest_shed);
+            AppError::TooManyRequests(self.retry_after_secs)
+        })
+    }
+
+    /// Ingest requests currently holding a permit
+    pub fn in_flight(&self) -> usize {
+        self.max_in_flight - self.permits.available_permits()
+    }
+
+    /// Render the in-flight gauge
+    pub fn render_metrics(&self, exp: &mut Exposition) {
+        exp.family(
+            "soundsense_ingest_in_flight",
+            "gauge",
+            "Ingest requests currently being handled",
+            &[("", self.in_flight() as u64)],
+        );
+    }
+}
+
+/// Middleware for the ingest resources. The permit is held until the response
+/// is produced, so body buffering and parsing count towards the limit. Without
+/// a registered `IngestLimiter` requests pass straight through.
+pub async fn shed_ingest_load<B: MessageBody + 'static>(
+    req: ServiceRequest,
+    next: Next<B>,
+) -> Result<ServiceResponse<EitherBody<B>>, Error> {
+    let permit = match req.app_data::<web::Data<IngestLimiter>>() {
+        Some(limiter) => match limiter.try_acquire() {
+            Ok(permit) => Some(permit),
+            Err(e) => return Ok(req.error_response(e).map_into_right_body()),
+        },
+        None => None,
+    };
+
+    let resp = next.call(req).await?;
+    drop(permit);
+    Ok(resp.map_into_left_body())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_permits_are_released_on_drop() {
+        let limiter = IngestLimiter::new(2, 5);
+
+        let first = limiter.try_acquire().unwrap();
+        let _second = limiter.try_acquire().unwrap();
+        assert_eq!(limiter.in_flight(), 2);
+        assert!(matches!(
+            limiter.try_acquire(),
+            Err(AppError::TooManyRequests(5))
+        ));
+
+        drop(first);
+        assert_eq!(limiter.in_flight(), 1);
+        assert!(limiter.try_acquire().is_ok());
+    }
+}
@@ -0,0 +1,181 @@
+/// Log Sampling
+///
+/// Debug events on the ingest hot path (storing a reading, sending it to
+/// WebSocket clients) fire once per reading, which at a thousand readings a
+/// second drowns a log aggregator. Those call sites ask a `LogSampler`
+/// first, which lets through only every Nth event, N being
+/// `LOG_SAMPLE_RATE` (default 1, everything). Warnings and errors are never
+/// sampled.
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::Instant;
+use utoipa::ToSchema;
+
+/// Rate of samplers that follow the deployment's `LOG_SAMPLE_RATE`
+static SAMPLE_RATE: AtomicUsize = AtomicUsize::new(1);
+
+/// Events let through and skipped, over every sampler
+static SAMPLED: AtomicU64 = AtomicU64::new(0);
+static DROPPED: AtomicU64 = AtomicU64::new(0);
+
+/// Counters at the previous `stats` call
+static LAST_STATS: Mutex<Option<Counts>> = Mutex::new(None);
+
+/// Set the sample rate from `LOG_SAMPLE_RATE` and start the first stats
+/// window
+pub fn init_from_env() -> usize {
+    let rate = std::env::var("LOG_SAMPLE_RATE")
+        .ok()
+        .and_then(|v| v.parse::<usize>().ok())
+        .filter(|v| *v > 0)
+        .unwrap_or(1);
+    set_sample_rate(rate);
+    *LAST_STATS.lock().unwrap_or_else(|e| e.into_inner()) = Some(Counts::now());
+    rate
+}
+
+/// Let through one in `rate` events of samplers following the deployment
+/// rate; 0 counts as 1
+pub fn set_sample_rate(rate: usize) {
+    SAMPLE_RATE.store(rate.max(1), Ordering::Relaxed);
+}
+
+pub fn sample_rate() -> usize {
+    SAMPLE_RATE.load(Ordering::Relaxed)
+}
+
+/// Decides which events of one call site are logged
+#[derive(Debug)]
+pub struct LogSampler {
+    /// 0 follows the deployment rate
+    rate: usize,
+    calls: AtomicUsize,
+}
+
+impl LogSampler {
+    /// A sampler at the deployment's `LOG_SAMPLE_RATE`
+    pub const fn new() -> Self {
+        Self::with_rate(0)
+    }
+
+    /// A sampler letting through one in `rate` events
+    pub const fn with_rate(rate: usize) -> Self {
+        Self {
+            rate,
+            calls: AtomicUsize::new(0),
+        }
+    }
+
+    /// Whether to log this event. The first event is always logged, then
+    /// every Nth.
+    pub fn sample(&self) -> bool {
+        let rate = match self.rate {
+            0 => sample_rate(),
+            rate => rate,
+        };
+        let keep = self
+            .calls
+            .fetch_add(1, Ordering::Relaxed)
+            .is_multiple_of(rate);
+        let counter = if keep { &SAMPLED } else { &DROPPED };
+        counter.fetch_add(1, Ordering::Relaxed);
+        keep
+    }
+}
+
+impl Default for LogSampler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Counts {
+    at: Instant,
+    sampled: u64,
+    dropped: u64,
+}
+
+impl Counts {
+    fn now() -> Self {
+        Self {
+            at: Instant::now(),
+            sampled: SAMPLED.load(Ordering::Relaxed),
+            dropped: DROPPED.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Sampled events per second since the previous call
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct LogStats {
+    pub sample_rate: usize,
+    /// Events logged
+    pub sampled_events_per_sec: f64,
+    /// Events skipped by sampling
+    pub dropped_events_per_sec: f64,
+    /// Seconds since the previous call (or since startup)
+    pub window_secs: f64,
+}
+
+/// Rates over the time since the previous call
+pub fn stats() -> LogStats {
+    let now = Counts::now();
+    let mut last = LAST_STATS.lock().unwrap_or_else(|e| e.into_inner());
+    let prev = last.replace(now).unwrap_or(now);
+    rates(prev, now)
+}
+
+fn rates(prev: Counts, now: Counts) -> LogStats {
+    let window_secs = now.at.duration_since(prev.at).as_secs_f64();
+    let per_sec = |from: u64, to: u64| {
+        if window_secs > 0.0 {
+            to.saturating_sub(from) as f64 / window_secs
+        } else {
+            0.0
+        }
+    };
+    LogStats {
+        sample_rate: sample_rate(),
+        sampled_events_per_sec: per_sec(prev.sampled, now.sampled),
+        dropped_events_per_sec: per_sec(prev.dropped, now.dropped),
+        window_secs,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_one_in_rate_events_is_logged() {
+        let sampler = LogSampler::with_rate(10);
+        let kept: Vec<usize> = (0..100).filter(|_| sampler.sample()).collect();
+        assert_eq!(kept, [0, 10, 20, 30, 40, 50, 60, 70, 80, 90]);
+
+        let everything = LogSampler::with_rate(1);
+        assert!((0..20).all(|_| everything.sample()));
+    }
+
+    #[test]
+    fn test_rates_over_the_window() {
+        let prev = Counts {
+            at: Instant::now(),
+            sampled: 10,
+            dropped: 90,
+        };
+        let now = Counts {
+            at: prev.at + Duration::from_secs(2),
+            sampled: 30,
+            dropped: 270,
+        };
+        let stats = rates(prev, now);
+        assert_eq!(stats.window_secs, 2.0);
+        assert_eq!(stats.sampled_events_per_sec, 10.0);
+        assert_eq!(stats.dropped_events_per_sec, 90.0);
+
+        assert_eq!(rates(now, now).sampled_events_per_sec, 0.0);
+    }
+}
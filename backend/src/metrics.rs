@@ -0,0 +1,182 @@
+/// Metrics Module
+///
+/// Process-wide counters exposed in Prometheus text format on `/metrics`.
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Process-wide counters
+#[derive(Debug)]
+pub struct Metrics {
+    pub ingest_enqueued_high: AtomicU64,
+    pub ingest_enqueued_low: AtomicU64,
+    pub ingest_processed_high: AtomicU64,
+    pub ingest_processed_low: AtomicU64,
+    pub ingest_queue_rejected: AtomicU64,
+    pub ingest_body_too_large: AtomicU64,
+    pub ingest_shed: AtomicU64,
+    pub db_rows_unknown_code: AtomicU64,
+    pub db_rows_unknown_status: AtomicU64,
+    pub db_insert_retries: AtomicU64,
+    pub dlq_dropped: AtomicU64,
+    pub dlq_parked: AtomicU64,
+}
+
+impl Metrics {
+    const fn new() -> Self {
+        Self {
+            ingest_enqueued_high: AtomicU64::new(0),
+            ingest_enqueued_low: AtomicU64::new(0),
+            ingest_processed_high: AtomicU64::new(0),
+            ingest_processed_low: AtomicU64::new(0),
+            ingest_queue_rejected: AtomicU64::new(0),
+            ingest_body_too_large: AtomicU64::new(0),
+            ingest_shed: AtomicU64::new(0),
+            db_rows_unknown_code: AtomicU64::new(0),
+            db_rows_unknown_status: AtomicU64::new(0),
+            db_insert_retries: AtomicU64::new(0),
+            dlq_dropped: AtomicU64::new(0),
+            dlq_parked: AtomicU64::new(0),
+        }
+    }
+}
+
+pub static METRICS: Metrics = Metrics::new();
+
+/// Increment a counter by one
+pub fn inc(counter: &AtomicU64) {
+    counter.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Increment a counter by `n`
+pub fn add(counter: &AtomicU64, n: u64) {
+    counter.fetch_add(n, Ordering::Relaxed);
+}
+
+/// Read a counter
+pub fn get(counter: &AtomicU64) -> u64 {
+    counter.load(Ordering::Relaxed)
+}
+
+/// Builder for the Prometheus text exposition format
+#[derive(Debug, Default)]
+pub struct Exposition {
+    out: String,
+}
+
+impl Exposition {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Write a metric family with one or more `(labels, value)` samples
+    pub fn family(&mut self, name: &str, kind: &str, help: &str, samples: &[(&str, u64)]) {
+        let _ = writeln!(self.out, "# HELP {} {}", name, help);
+        let _ = writeln!(self.out, "# TYPE {} {}", name, kind);
+        for (labels, value) in samples {
+            if labels.is_empty() {
+                let _ = writeln!(self.out, "{} {}", name, value);
+            } else {
+                let _ = writeln!(self.out, "{}{{{}}} {}", name, labels, value);
+            }
+        }
+    }
+
+    pub fn finish(self) -> String {
+        self.out
+    }
+}
+
+/// Render the process-wide counters
+pub fn render_counters(exp: &mut Exposition) {
+    exp.family(
+        "soundsense_ingest_enqueued_total",
+        "counter",
+        "Readings accepted onto the ingest queue",
+        &[
+            ("priority=\"high\"", get(&METRICS.ingest_enqueued_high)),
+            ("priority=\"low\"", get(&METRICS.ingest_enqueued_low)),
+        ],
+    );
+    exp.family(
+        "soundsense_ingest_processed_total",
+        "counter",
+        "Readings processed by the ingest worker",
+        &[
+            ("priority=\"high\"", get(&METRICS.ingest_processed_high)),
+            ("priority=\"low\"", get(&METRICS.ingest_processed_low)),
+        ],
+    );
+    exp.family(
+        "soundsense_ingest_queue_rejected_total",
+        "counter",
+        "Readings rejected because the ingest queue was full",
+        &[("", get(&METRICS.ingest_queue_rejected))],
+    );
+    exp.family(
+        "soundsense_ingest_rejected_total",
+        "counter",
+        "Ingest requests rejected before reaching the handler",
+        &[
+            (
+                "reason=\"body_too_large\"",
+                get(&METRICS.ingest_body_too_large),
+            ),
+            ("reason=\"overloaded\"", get(&METRICS.ingest_shed)),
+        ],
+    );
+    exp.family(
+        "soundsense_db_rows_skipped_total",
+        "counter",
+        "Stored readings left out of results because they could not be decoded",
+        &[
+            (
+                "reason=\"unknown_code\"",
+                get(&METRICS.db_rows_unknown_code),
+            ),
+            (
+                "reason=\"unknown_status\"",
+                get(&METRICS.db_rows_unknown_status),
+            ),
+        ],
+    );
+    exp.family(
+        "soundsense_db_insert_retries_total",
+        "counter",
+        "Reading inserts tried again after a transient database failure",
+        &[("", get(&METRICS.db_insert_retries))],
+    );
+    exp.family(
+        "soundsense_dlq_dropped_total",
+        "counter",
+        "Buffered readings dropped, oldest first, because the write-ahead buffer was full",
+        &[("", get(&METRICS.dlq_dropped))],
+    );
+    exp.family(
+        "soundsense_dlq_parked_total",
+        "counter",
+        "Buffered readings set aside because their insert failed other than transiently",
+        &[("", get(&METRICS.dlq_parked))],
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exposition_format() {
+        let mut exp = Exposition::new();
+        exp.family(
+            "demo_total",
+            "counter",
+            "A demo counter",
+            &[("kind=\"a\"", 3), ("", 1)],
+        );
+        let text = exp.finish();
+
+        assert!(text.contains("# HELP demo_total A demo counter\n"));
+        assert!(text.contains("# TYPE demo_total counter\n"));
+        assert!(text.contains("demo_total{kind=\"a\"} 3\n"));
+        assert!(text.contains("demo_total 1\n"));
+    }
+}
@@ -0,0 +1,142 @@
+/// ML Result Cache
+///
+/// Keeps the last successful ML prediction and analysis per query so the
+/// dashboard still has something to show while the ML service is down.
+/// Cached results are only served up to `ML_CACHE_MAX_AGE_SECS` old.
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// Default for how old a cached result may be and still be served
+const DEFAULT_MAX_AGE_SECS: i64 = 900;
+
+/// Query signatures remembered at once; beyond this the oldest is forgotten
+const MAX_ENTRIES: usize = 256;
+
+/// A cached result served in place of a failed live call
+#[derive(Debug, Clone)]
+pub struct StaleResult {
+    /// The result, with `stale: true` and `stale_as_of` added
+    pub body: Value,
+    /// When the result was computed
+    pub as_of: DateTime<Utc>,
+}
+
+#[derive(Debug)]
+pub struct MlResultCache {
+    entries: Mutex<HashMap<String, (DateTime<Utc>, Value)>>,
+    max_age: Duration,
+}
+
+impl MlResultCache {
+    pub fn new(max_age: Duration) -> Self {
+        Self {
+            entries: Mutex::new(HashMap::new()),
+            max_age,
+        }
+    }
+
+    /// Create from `ML_CACHE_MAX_AGE_SECS` (default 900); `0` disables the cache
+    pub fn from_env() -> Option<Self> {
+        let max_age_secs = std::env::var("ML_CACHE_MAX_AGE_SECS")
+            .ok()
+            .and_then(|v| v.parse::<i64>().ok())
+            .filter(|v| *v >= 0)
+            .unwrap_or(DEFAULT_MAX_AGE_SECS);
+
+        (max_age_secs > 0).then(|| Self::new(Duration::seconds(max_age_secs)))
+    }
+
+    /// Remember a successful result for `key`
+    pub fn store(&self, key: &str, result: &impl Serialize) {
+        self.store_at(key, result, Utc::now());
+    }
+
+    fn store_at(&self, key: &str, result: &impl Serialize, at: DateTime<Utc>) {
+        let Ok(value) = serde_json::to_value(result) else {
+            return;
+        };
+        let Ok(mut entries) = self.entries.lock() else {
+            return;
+        };
+        if entries.len() >= MAX_ENTRIES && !entries.contains_key(key) {
+            if let Some(oldest) = entries
+                .iter()
+                .min_by_key(|(_, (stored_at, _))| *stored_at)
+                .map(|(k, _)| k.clone())
+            {
+                entries.remove(&oldest);
+            }
+        }
+        entries.insert(key.to_string(), (at, value));
+    }
+
+    /// The last result for `key`, unless it is older than the maximum age
+    pub fn stale(&self, key: &str) -> Option<StaleResult> {
+        let entries = self.entries.lock().ok()?;
+        let (as_of, value) = entries.get(key)?;
+        if Utc::now() - *as_of > self.max_age {
+            return None;
+        }
+
+        let mut body = value.clone();
+        if let Value::Object(fields) = &mut body {
+            fields.insert("stale".into(), Value::Bool(true));
+            fields.insert("stale_as_of".into(), serde_json::json!(as_of));
+        }
+        Some(StaleResult {
+            body,
+            as_of: *as_of,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stale_result_is_flagged() {
+        let cache = MlResultCache::new(Duration::minutes(15));
+        assert!(cache.stale("predict?limit=100").is_none());
+
+        cache.store("predict?limit=100", &serde_json::json!({ "success": true }));
+        let stale = cache.stale("predict?limit=100").unwrap();
+        assert_eq!(stale.body["success"], true);
+        assert_eq!(stale.body["stale"], true);
+        assert!(stale.body["stale_as_of"].is_string());
+
+        // Results are per query
+        assert!(cache.stale("predict?limit=50").is_none());
+    }
+
+    #[test]
+    fn test_expired_result_is_not_served() {
+        let cache = MlResultCache::new(Duration::minutes(15));
+        let key = "predict?limit=100";
+        cache.store_at(
+            key,
+            &serde_json::json!({ "success": true }),
+            Utc::now() - Duration::minutes(16),
+        );
+        assert!(cache.stale(key).is_none());
+    }
+
+    #[test]
+    fn test_oldest_entry_is_evicted() {
+        let cache = MlResultCache::new(Duration::minutes(15));
+        let now = Utc::now();
+        for i in 0..MAX_ENTRIES {
+            let at = now - Duration::seconds((MAX_ENTRIES - i) as i64);
+            cache.store_at(&format!("k{i}"), &i, at);
+        }
+        cache.store("new", &0);
+
+        let entries = cache.entries.lock().unwrap();
+        assert_eq!(entries.len(), MAX_ENTRIES);
+        assert!(!entries.contains_key("k0"));
+        assert!(entries.contains_key("new"));
+    }
+}
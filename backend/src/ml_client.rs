@@ -1,13 +1,32 @@
 /// ML Service Client
 ///
 /// Communicates with Python ML service for predictions and analysis.
+use chrono::{DateTime, SecondsFormat, Timelike, Utc};
+use reqwest::header::AUTHORIZATION;
+use reqwest::{Response, StatusCode};
 use serde::{Deserialize, Serialize};
+use std::sync::Arc;
 use std::time::Duration;
+use thiserror::Error;
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+use crate::auth::{Claims, JwtManager, SCOPE_ML_SERVICE};
+use crate::errors::AppError;
+
+/// Longest span a date-range analysis may cover
+pub const MAX_ANALYSIS_RANGE_DAYS: i64 = 31;
+
+/// Most readings sent in one analysis request (the ML service's limit)
+pub const MAX_ANALYSIS_READINGS: usize = 10_000;
 
 #[derive(Debug, Clone)]
 pub struct MlClient {
     base_url: String,
     client: reqwest::Client,
+    auth: MlAuth,
+    /// The current service JWT, shared by clones of the client
+    service_token: Arc<Mutex<Option<ServiceToken>>>,
 }
 
 #[derive(Debug, Serialize)]
@@ -53,6 +72,87 @@ pub struct PredictionSummary {
 pub struct AnalysisResponse {
     pub success: bool,
     pub analysis: Analysis,
+    /// Who computed the analysis; not sent by the ML service itself
+    #[serde(default)]
+    pub source: AnalysisSource,
+    /// Patient the readings were limited to; older ML services ignore the
+    /// filter and leave this out
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub patient_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AnalysisSource {
+    /// The ML service
+    #[default]
+    Ml,
+    /// Basic statistics computed by the backend while the ML service was unavailable
+    Local,
+}
+
+/// Readings selected for pattern analysis
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnalysisWindow {
+    /// The latest `limit` readings, optionally only from the last `hours_back` hours
+    Recent {
+        limit: usize,
+        hours_back: Option<u32>,
+    },
+    /// Every reading from `start` up to, but not including, `end`
+    Range {
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    },
+}
+
+impl AnalysisWindow {
+    /// A date range, rejected if inverted or longer than `MAX_ANALYSIS_RANGE_DAYS`
+    pub fn range(start: DateTime<Utc>, end: DateTime<Utc>) -> Result<Self, String> {
+        if end <= start {
+            return Err("end must be after start".into());
+        }
+        if end - start > chrono::Duration::days(MAX_ANALYSIS_RANGE_DAYS) {
+            return Err(format!(
+                "range must not span more than {} days",
+                MAX_ANALYSIS_RANGE_DAYS
+            ));
+        }
+        Ok(Self::Range { start, end })
+    }
+
+    /// Lower (included) and upper (excluded) timestamp bounds and maximum
+    /// number of readings, for selecting the window's readings locally
+    pub fn bounds(
+        &self,
+        now: DateTime<Utc>,
+    ) -> (Option<DateTime<Utc>>, Option<DateTime<Utc>>, usize) {
+        match *self {
+            Self::Recent { limit, hours_back } => (
+                hours_back.map(|h| now - chrono::Duration::hours(h.into())),
+                None,
+                limit.min(MAX_ANALYSIS_READINGS),
+            ),
+            Self::Range { start, end } => (Some(start), Some(end), MAX_ANALYSIS_READINGS),
+        }
+    }
+
+    /// Query parameters for the ML service's `/analysis`
+    fn query_params(&self) -> Vec<(&'static str, String)> {
+        match self {
+            Self::Recent { limit, hours_back } => {
+                let mut params = vec![("limit", limit.to_string())];
+                if let Some(hours) = hours_back {
+                    params.push(("hours_back", hours.to_string()));
+                }
+                params
+            }
+            Self::Range { start, end } => vec![
+                ("start", start.to_rfc3339_opts(SecondsFormat::Secs, true)),
+                ("end", end.to_rfc3339_opts(SecondsFormat::Secs, true)),
+            ],
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -72,6 +172,56 @@ pub struct Analysis {
     pub quietest_hour: i32,
 }
 
+impl Analysis {
+    /// Basic statistics over `(timestamp, value)` pairs, for when the ML
+    /// service can't be asked. Anomaly figures are left at zero. `None` if
+    /// there are no readings.
+    pub fn summarize(readings: impl IntoIterator<Item = (DateTime<Utc>, f64)>) -> Option<Self> {
+        let mut values = Vec::new();
+        let mut hourly = [(0.0, 0usize); 24];
+        for (ts, value) in readings {
+            values.push(value);
+            let (sum, n) = &mut hourly[ts.hour() as usize];
+            *sum += value;
+            *n += 1;
+        }
+        if values.is_empty() {
+            return None;
+        }
+
+        let n = values.len() as f64;
+        let avg_level = values.iter().sum::<f64>() / n;
+        // Sample standard deviation, as pandas computes it
+        let std_level = if values.len() > 1 {
+            (values.iter().map(|v| (v - avg_level).powi(2)).sum::<f64>() / (n - 1.0)).sqrt()
+        } else {
+            0.0
+        };
+        let hourly_avg = hourly
+            .iter()
+            .enumerate()
+            .filter(|(_, (_, count))| *count > 0)
+            .map(|(hour, (sum, count))| (hour as i32, sum / *count as f64));
+
+        Some(Self {
+            total_readings: values.len(),
+            avg_level,
+            std_level,
+            min_level: values.iter().copied().fold(f64::INFINITY, f64::min),
+            max_level: values.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+            anomaly_count: 0,
+            anomaly_percentage: 0.0,
+            peak_hour: hourly_avg
+                .clone()
+                .max_by(|a, b| a.1.total_cmp(&b.1))
+                .map_or(0, |(hour, _)| hour),
+            quietest_hour: hourly_avg
+                .min_by(|a, b| a.1.total_cmp(&b.1))
+                .map_or(0, |(hour, _)| hour),
+        })
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct HealthResponse {
     pub status: String,
@@ -80,6 +230,110 @@ pub struct HealthResponse {
     pub anomaly_detector_loaded: bool,
 }
 
+/// Progress of a training run as reported by the ML service
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TrainingStatusResponse {
+    pub status: String,
+    #[serde(default)]
+    pub progress_percent: f32,
+    #[serde(default)]
+    pub message: String,
+}
+
+/// One reading sent for anomaly scoring
+#[derive(Debug, Clone, Serialize)]
+pub struct ScoreSample {
+    pub value: f64,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// Verdict for the last of the scored readings
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AnomalyScore {
+    pub is_anomaly: bool,
+    pub anomaly_score: f64,
+    pub category: String,
+}
+
+/// Credentials sent with every request to the ML service
+#[derive(Clone, Default)]
+pub enum MlAuth {
+    /// No `Authorization` header
+    #[default]
+    None,
+    /// A fixed bearer token
+    Static(String),
+    /// Short-lived JWTs with `role: "backend"`, minted by our own
+    /// `JwtManager` and renewed shortly before they expire
+    ServiceJwt {
+        jwt: Arc<JwtManager>,
+        ttl: chrono::Duration,
+    },
+}
+
+impl std::fmt::Debug for MlAuth {
+    // Keeps the static token out of logs
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MlAuth::None => f.write_str("None"),
+            MlAuth::Static(_) => f.write_str("Static(..)"),
+            MlAuth::ServiceJwt { ttl, .. } => {
+                f.debug_struct("ServiceJwt").field("ttl", ttl).finish()
+            }
+        }
+    }
+}
+
+/// Subject of the service JWTs sent to the ML service
+pub const SERVICE_TOKEN_SUBJECT: &str = "soundsense-backend";
+
+/// Lifetime of service JWTs unless `ML_SERVICE_TOKEN_TTL_SECS` says otherwise
+const DEFAULT_SERVICE_TOKEN_TTL_SECS: i64 = 300;
+
+/// A minted service JWT and when it expires
+#[derive(Debug, Clone)]
+struct ServiceToken {
+    token: String,
+    expires_at: DateTime<Utc>,
+}
+
+impl ServiceToken {
+    /// Whether the token is within its last fifth of `ttl` at `now`, so a
+    /// request sent with it could arrive after it expired
+    fn needs_refresh(&self, now: DateTime<Utc>, ttl: chrono::Duration) -> bool {
+        now + ttl / 5 >= self.expires_at
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum MlError {
+    #[error("ML service request failed: {0}")]
+    Request(String),
+
+    /// The service refused our credentials (401 or 403)
+    #[error("ML service rejected our credentials with status {0}")]
+    Unauthorized(StatusCode),
+
+    #[error("ML service returned status: {0}")]
+    Status(StatusCode),
+
+    #[error("Failed to parse ML response: {0}")]
+    Parse(String),
+}
+
+impl From<MlError> for AppError {
+    /// Credential problems are the ML service refusing us, 502; other
+    /// failures stay internal errors
+    fn from(e: MlError) -> Self {
+        match e {
+            MlError::Unauthorized(_) => {
+                AppError::BadGateway("ML service rejected the backend's credentials".into())
+            }
+            _ => AppError::Internal,
+        }
+    }
+}
+
 impl MlClient {
     pub fn new(base_url: String) -> Self {
         let client = reqwest::Client::builder()
@@ -87,7 +341,125 @@ impl MlClient {
             .build()
             .unwrap();
 
-        Self { base_url, client }
+        Self {
+            base_url,
+            client,
+            auth: MlAuth::None,
+            service_token: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Client for `base_url` authenticating with `ML_SERVICE_TOKEN`, or with
+    /// service JWTs from `jwt` when `ML_SERVICE_AUTH=jwt` (lifetime
+    /// `ML_SERVICE_TOKEN_TTL_SECS`, default 300)
+    pub fn from_env(base_url: String, jwt: &Arc<JwtManager>) -> Result<Self, String> {
+        let token = std::env::var("ML_SERVICE_TOKEN")
+            .ok()
+            .filter(|t| !t.trim().is_empty());
+        let mode = std::env::var("ML_SERVICE_AUTH").unwrap_or_default();
+
+        let auth = match (mode.to_ascii_lowercase().as_str(), token) {
+            ("" | "token", Some(token)) => MlAuth::Static(token),
+            ("", None) | ("none", None) => MlAuth::None,
+            ("token", None) => {
+                return Err("ML_SERVICE_AUTH=token requires ML_SERVICE_TOKEN".into());
+            }
+            ("jwt", None) => {
+                let ttl_secs = match std::env::var("ML_SERVICE_TOKEN_TTL_SECS") {
+                    Err(_) => DEFAULT_SERVICE_TOKEN_TTL_SECS,
+                    Ok(secs) => secs
+                        .parse::<i64>()
+                        .ok()
+                        .filter(|secs| *secs > 0)
+                        .ok_or_else(|| {
+                            format!(
+                                "ML_SERVICE_TOKEN_TTL_SECS must be a positive number of seconds, got '{}'",
+                                secs
+                            )
+                        })?,
+                };
+                MlAuth::ServiceJwt {
+                    jwt: jwt.clone(),
+                    ttl: chrono::Duration::seconds(ttl_secs),
+                }
+            }
+            ("jwt" | "none", Some(_)) => {
+                return Err(format!(
+                    "ML_SERVICE_TOKEN cannot be combined with ML_SERVICE_AUTH={}",
+                    mode
+                ));
+            }
+            _ => {
+                return Err(format!(
+                    "ML_SERVICE_AUTH must be none, token or jwt, got '{}'",
+                    mode
+                ));
+            }
+        };
+
+        Ok(Self::new(base_url).with_auth(auth))
+    }
+
+    /// Send `auth` with every request
+    pub fn with_auth(mut self, auth: MlAuth) -> Self {
+        self.auth = auth;
+        self
+    }
+
+    /// The `Authorization` header value for the next request, if any
+    async fn authorization(&self) -> Result<Option<String>, MlError> {
+        let (jwt, ttl) = match &self.auth {
+            MlAuth::None => return Ok(None),
+            MlAuth::Static(token) => return Ok(Some(format!("Bearer {}", token))),
+            MlAuth::ServiceJwt { jwt, ttl } => (jwt, *ttl),
+        };
+
+        let mut cached = self.service_token.lock().await;
+        let now = Utc::now();
+        if let Some(current) = cached.as_ref().filter(|t| !t.needs_refresh(now, ttl)) {
+            return Ok(Some(format!("Bearer {}", current.token)));
+        }
+
+        let mut claims = Claims::new(SERVICE_TOKEN_SUBJECT.into(), "backend".into(), None, 0)
+            .with_scope(SCOPE_ML_SERVICE);
+        let expires_at = now + ttl;
+        claims.iat = now.timestamp();
+        claims.exp = expires_at.timestamp();
+        let token = jwt
+            .generate_token(claims)
+            .map_err(|e| MlError::Request(format!("could not mint a service token: {}", e)))?;
+        tracing::debug!(%expires_at, "Minted a new ML service token");
+
+        let header = format!("Bearer {}", token);
+        *cached = Some(ServiceToken { token, expires_at });
+        Ok(Some(header))
+    }
+
+    /// Send `request` with our credentials; non-success statuses are errors
+    async fn send(&self, request: reqwest::RequestBuilder) -> Result<Response, MlError> {
+        let request = match self.authorization().await? {
+            Some(value) => request.header(AUTHORIZATION, value),
+            None => request,
+        };
+
+        let response = request
+            .send()
+            .await
+            .map_err(|e| MlError::Request(e.to_string()))?;
+
+        let status = response.status();
+        if matches!(status, StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN) {
+            tracing::error!(
+                %status,
+                url = %response.url(),
+                "ML service refused our credentials; check ML_SERVICE_TOKEN / ML_SERVICE_AUTH"
+            );
+            return Err(MlError::Unauthorized(status));
+        }
+        if !status.is_success() {
+            return Err(MlError::Status(status));
+        }
+        Ok(response)
     }
 
     /// Get ML predictions for recent readings
@@ -95,82 +467,100 @@ impl MlClient {
         &self,
         limit: usize,
         hours_back: Option<u32>,
-    ) -> Result<PredictionResponse, String> {
+    ) -> Result<PredictionResponse, MlError> {
         let url = format!("{}/predict", self.base_url);
 
         let request_body = PredictionRequest { limit, hours_back };
 
         let response = self
-            .client
-            .post(&url)
-            .json(&request_body)
-            .send()
-            .await
-            .map_err(|e| format!("ML service request failed: {}", e))?;
-
-        if !response.status().is_success() {
-            return Err(format!("ML service returned status: {}", response.status()));
-        }
+            .send(self.client.post(&url).json(&request_body))
+            .await?;
 
         response
             .json::<PredictionResponse>()
             .await
-            .map_err(|e| format!("Failed to parse ML response: {}", e))
+            .map_err(|e| MlError::Parse(e.to_string()))
     }
 
-    /// Get pattern analysis
+    /// Get pattern analysis of the readings in `window`, only `patient_id`'s if given
     pub async fn get_analysis(
         &self,
-        limit: usize,
-        hours_back: Option<u32>,
-    ) -> Result<AnalysisResponse, String> {
-        let mut url = format!("{}/analysis?limit={}", self.base_url, limit);
+        window: &AnalysisWindow,
+        patient_id: Option<&str>,
+    ) -> Result<AnalysisResponse, MlError> {
+        let url = format!("{}/analysis", self.base_url);
 
-        if let Some(hours) = hours_back {
-            url.push_str(&format!("&hours_back={}", hours));
+        let mut params = window.query_params();
+        if let Some(patient_id) = patient_id {
+            params.push(("patient_id", patient_id.to_string()));
         }
 
-        let response = self
-            .client
-            .get(&url)
-            .send()
+        let response = self.send(self.client.get(&url).query(&params)).await?;
+
+        response
+            .json::<AnalysisResponse>()
             .await
-            .map_err(|e| format!("ML service request failed: {}", e))?;
+            .map_err(|e| MlError::Parse(e.to_string()))
+    }
 
-        if !response.status().is_success() {
-            return Err(format!("ML service returned status: {}", response.status()));
-        }
+    /// Get pattern analysis of readings selected by the caller
+    pub async fn analyze_readings(
+        &self,
+        patient_id: &str,
+        readings: &[ScoreSample],
+    ) -> Result<AnalysisResponse, MlError> {
+        let url = format!("{}/analysis", self.base_url);
+
+        let response = self
+            .send(
+                self.client
+                    .post(&url)
+                    .json(&serde_json::json!({ "patient_id": patient_id, "readings": readings })),
+            )
+            .await?;
 
         response
             .json::<AnalysisResponse>()
             .await
-            .map_err(|e| format!("Failed to parse ML response: {}", e))
+            .map_err(|e| MlError::Parse(e.to_string()))
     }
 
-    /// Trigger model training
-    pub async fn train_models(&self, min_samples: usize) -> Result<String, String> {
+    /// Score the last of `readings` for anomalies; earlier readings give the
+    /// model its rolling context
+    pub async fn score_readings(&self, readings: &[ScoreSample]) -> Result<AnomalyScore, MlError> {
+        let url = format!("{}/score", self.base_url);
+
+        let response = self
+            .send(
+                self.client
+                    .post(&url)
+                    .json(&serde_json::json!({ "readings": readings })),
+            )
+            .await?;
+
+        response
+            .json::<AnomalyScore>()
+            .await
+            .map_err(|e| MlError::Parse(e.to_string()))
+    }
+
+    /// Trigger model training; progress is reported under `job_id`
+    pub async fn train_models(&self, job_id: Uuid, min_samples: usize) -> Result<String, MlError> {
         let url = format!("{}/train", self.base_url);
 
         let request_body = serde_json::json!({
+            "job_id": job_id,
             "min_samples": min_samples
         });
 
         let response = self
-            .client
-            .post(&url)
-            .json(&request_body)
-            .send()
-            .await
-            .map_err(|e| format!("ML service request failed: {}", e))?;
-
-        if !response.status().is_success() {
-            return Err(format!("ML service returned status: {}", response.status()));
-        }
+            .send(self.client.post(&url).json(&request_body))
+            .await?;
 
         let body: serde_json::Value = response
             .json()
             .await
-            .map_err(|e| format!("Failed to parse ML response: {}", e))?;
+            .map_err(|e| MlError::Parse(e.to_string()))?;
 
         Ok(body["message"]
             .as_str()
@@ -178,24 +568,70 @@ impl MlClient {
             .to_string())
     }
 
-    /// Check ML service health
-    pub async fn health_check(&self) -> Result<HealthResponse, String> {
-        let url = format!("{}/health", self.base_url);
+    /// Get the progress of a training run
+    pub async fn training_status(&self, job_id: Uuid) -> Result<TrainingStatusResponse, MlError> {
+        let url = format!("{}/training/status/{}", self.base_url, job_id);
 
-        let response = self
-            .client
-            .get(&url)
-            .send()
+        let response = self.send(self.client.get(&url)).await?;
+
+        response
+            .json::<TrainingStatusResponse>()
             .await
-            .map_err(|e| format!("ML service request failed: {}", e))?;
+            .map_err(|e| MlError::Parse(e.to_string()))
+    }
 
-        if !response.status().is_success() {
-            return Err(format!("ML service returned status: {}", response.status()));
-        }
+    /// Check ML service health
+    pub async fn health_check(&self) -> Result<HealthResponse, MlError> {
+        let url = format!("{}/health", self.base_url);
+
+        let response = self.send(self.client.get(&url)).await?;
 
         response
             .json::<HealthResponse>()
             .await
-            .map_err(|e| format!("Failed to parse ML response: {}", e))
+            .map_err(|e| MlError::Parse(e.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_analysis_range_is_validated() {
+        let start = Utc.with_ymd_and_hms(2026, 2, 1, 0, 0, 0).unwrap();
+        let week = start + chrono::Duration::days(7);
+
+        let window = AnalysisWindow::range(start, week).unwrap();
+        assert_eq!(
+            window.query_params(),
+            [
+                ("start", "2026-02-01T00:00:00Z".to_string()),
+                ("end", "2026-02-08T00:00:00Z".to_string()),
+            ]
+        );
+
+        assert!(AnalysisWindow::range(week, start).is_err());
+        assert!(AnalysisWindow::range(start, start).is_err());
+        let too_long = start + chrono::Duration::days(MAX_ANALYSIS_RANGE_DAYS + 1);
+        assert!(AnalysisWindow::range(start, too_long).is_err());
+    }
+
+    #[test]
+    fn test_summarize_computes_basic_stats() {
+        let at = |hour| Utc.with_ymd_and_hms(2026, 2, 1, hour, 0, 0).unwrap();
+        let analysis =
+            Analysis::summarize([(at(3), 100.0), (at(3), 200.0), (at(14), 600.0)]).unwrap();
+
+        assert_eq!(analysis.total_readings, 3);
+        assert_eq!(analysis.avg_level, 300.0);
+        assert!((analysis.std_level - 264.575).abs() < 0.001);
+        assert_eq!(analysis.min_level, 100.0);
+        assert_eq!(analysis.max_level, 600.0);
+        assert_eq!(analysis.peak_hour, 14);
+        assert_eq!(analysis.quietest_hour, 3);
+
+        assert!(Analysis::summarize([]).is_none());
     }
 }
@@ -0,0 +1,122 @@
+/// Noise Map
+///
+/// Sound levels per location across all devices and patients, for
+/// facilities management. A reading's location is the `location` string in
+/// its metadata; readings without one are left out, as are readings in units
+/// other than decibels and readings entered in error or deleted.
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashSet};
+use utoipa::ToSchema;
+
+use crate::domain::models::{is_decibel_unit, StoredReading};
+
+/// Sound levels measured at one location
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct LocationNoise {
+    pub location: String,
+    /// Arithmetic mean of the readings
+    pub avg_db: f64,
+    pub max_db: f64,
+    pub count: i64,
+    /// Distinct patients with readings there
+    pub patient_count: i64,
+}
+
+impl LocationNoise {
+    /// Noise per location of `readings` taken from `start` to `end`
+    /// (inclusive), by location name. Locations with fewer than `min_count`
+    /// readings are skipped. Mirrors the database query.
+    pub fn from_readings<'a>(
+        readings: impl IntoIterator<Item = &'a StoredReading>,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        min_count: usize,
+    ) -> Vec<LocationNoise> {
+        let mut locations: BTreeMap<&str, Vec<&StoredReading>> = BTreeMap::new();
+        for r in readings.into_iter().filter(|r| {
+            r.is_usable()
+                && is_decibel_unit(&r.reading.unit)
+                && r.reading.ts >= start
+                && r.reading.ts <= end
+        }) {
+            if let Ok(Some(location)) = r.reading.location() {
+                locations.entry(location).or_default().push(r);
+            }
+        }
+
+        locations
+            .into_iter()
+            .filter(|(_, readings)| readings.len() >= min_count)
+            .map(|(location, readings)| {
+                let values = readings.iter().map(|r| r.reading.value);
+                let patients: HashSet<&str> = readings
+                    .iter()
+                    .map(|r| r.reading.patient_id.as_str())
+                    .collect();
+                LocationNoise {
+                    location: location.to_string(),
+                    avg_db: values.clone().sum::<f64>() / readings.len() as f64,
+                    max_db: values.fold(f64::NEG_INFINITY, f64::max),
+                    count: readings.len() as i64,
+                    patient_count: patients.len() as i64,
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::models::{SensorReading, SignalCode, TimestampSource};
+    use chrono::{Duration, TimeZone};
+
+    fn stored(patient_id: &str, location: Option<&str>, value: f64, unit: &str) -> StoredReading {
+        let mut metadata = serde_json::Map::new();
+        if let Some(location) = location {
+            metadata.insert("location".into(), location.into());
+        }
+        StoredReading::new(SensorReading {
+            patient_id: patient_id.into(),
+            device_id: "d1".into(),
+            code: SignalCode::Sound,
+            value,
+            unit: unit.into(),
+            ts: Utc.with_ymd_and_hms(2026, 3, 1, 12, 0, 0).unwrap(),
+            ts_source: TimestampSource::Device,
+            metadata,
+        })
+    }
+
+    #[test]
+    fn test_noise_per_location() {
+        let readings = vec![
+            stored("p1", Some("Ward 3B"), 70.0, "dB"),
+            stored("p2", Some("Ward 3B"), 80.0, "dBA"),
+            stored("p1", Some("Ward 3B"), 75.0, "dB"),
+            stored("p3", Some("ICU"), 55.0, "dB"),
+            // No location, or not in decibels
+            stored("p1", None, 99.0, "dB"),
+            stored("p1", Some("ICU"), 900.0, "raw"),
+        ];
+        let t = readings[0].reading.ts;
+
+        let map = LocationNoise::from_readings(&readings, t, t, 1);
+        assert_eq!(map.len(), 2);
+        assert_eq!(map[0].location, "ICU");
+        assert_eq!(map[0].count, 1);
+        assert_eq!(map[1].location, "Ward 3B");
+        assert_eq!(map[1].avg_db, 75.0);
+        assert_eq!(map[1].max_db, 80.0);
+        assert_eq!(map[1].count, 3);
+        assert_eq!(map[1].patient_count, 2);
+
+        let map = LocationNoise::from_readings(&readings, t, t, 2);
+        assert_eq!(map.len(), 1);
+        assert_eq!(map[0].location, "Ward 3B");
+
+        let later = t + Duration::seconds(1);
+        assert!(LocationNoise::from_readings(&readings, later, later, 1).is_empty());
+    }
+}
@@ -0,0 +1,181 @@
+/// Device Output Normalization
+///
+/// Firmware versions report on different scales: 0–255, 0–1023, or a
+/// voltage of 0.0–5.0 V. A device profile maps its scale linearly onto a
+/// common target range in dB, so readings of different devices can be
+/// compared. Readings are normalized as they arrive, before validation
+/// rules, consent checks and the Observation see them; the value the device
+/// sent is kept alongside. Devices without a profile are stored as sent.
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use utoipa::ToSchema;
+
+use crate::domain::models::StoredReading;
+
+/// How one device's output scale maps onto the target range
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct DeviceProfile {
+    pub device_id: String,
+    /// Device output corresponding to `target_min`
+    pub scale_min: f64,
+    /// Device output corresponding to `target_max`
+    pub scale_max: f64,
+    pub target_min: f64,
+    pub target_max: f64,
+}
+
+impl DeviceProfile {
+    pub fn validate(&self) -> Result<(), String> {
+        let bounds = [
+            self.scale_min,
+            self.scale_max,
+            self.target_min,
+            self.target_max,
+        ];
+        if bounds.iter().any(|b| !b.is_finite()) {
+            return Err("scale and target bounds must be finite numbers".into());
+        }
+        if self.scale_min == self.scale_max {
+            return Err("scale_min and scale_max must differ".into());
+        }
+        if self.target_min >= self.target_max {
+            return Err("target_max must be greater than target_min".into());
+        }
+        Ok(())
+    }
+}
+
+/// Unit of normalized readings: profiles map onto a decibel range
+pub const NORMALIZED_UNIT: &str = "dB";
+
+/// `raw` mapped linearly from `profile`'s scale onto its target range,
+/// clamped to that range
+pub fn normalize(raw: f64, profile: &DeviceProfile) -> f64 {
+    let fraction = (raw - profile.scale_min) / (profile.scale_max - profile.scale_min);
+    let value = profile.target_min + fraction * (profile.target_max - profile.target_min);
+    value.clamp(profile.target_min, profile.target_max)
+}
+
+/// Device profiles, applied to readings as they arrive
+#[derive(Debug, Clone, Default)]
+pub struct Normalizer {
+    profiles: HashMap<String, DeviceProfile>,
+}
+
+impl Normalizer {
+    /// Give a device a profile, or with `None` store its readings as sent
+    pub fn set_profile(&mut self, device_id: &str, profile: Option<DeviceProfile>) {
+        match profile {
+            Some(profile) => {
+                self.profiles.insert(device_id.to_string(), profile);
+            }
+            None => {
+                self.profiles.remove(device_id);
+            }
+        }
+    }
+
+    pub fn profile(&self, device_id: &str) -> Option<&DeviceProfile> {
+        self.profiles.get(device_id)
+    }
+
+    /// Normalize `stored`'s value by its device's profile into dB, keeping
+    /// what the device sent in `raw_value`. Readings normalized before are
+    /// left alone.
+    pub fn apply(&self, stored: &mut StoredReading) {
+        if stored.raw_value.is_some() {
+            return;
+        }
+        let raw = stored.reading.value;
+        if let Some(profile) = self.profile(&stored.reading.device_id) {
+            stored.reading.value = normalize(raw, profile);
+            stored.reading.unit = NORMALIZED_UNIT.to_string();
+        }
+        stored.raw_value = Some(raw);
+    }
+
+    /// Forget `device_id`'s profile
+    pub fn forget(&mut self, device_id: &str) {
+        self.profiles.remove(device_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::models::{SensorReading, SignalCode};
+    use chrono::Utc;
+
+    fn profile(scale_min: f64, scale_max: f64) -> DeviceProfile {
+        DeviceProfile {
+            device_id: "d1".into(),
+            scale_min,
+            scale_max,
+            target_min: 30.0,
+            target_max: 130.0,
+        }
+    }
+
+    fn stored(device_id: &str, value: f64) -> StoredReading {
+        StoredReading::new(SensorReading {
+            patient_id: "p1".into(),
+            device_id: device_id.into(),
+            code: SignalCode::Sound,
+            value,
+            unit: "raw".into(),
+            ts: Utc::now(),
+            ts_source: Default::default(),
+            metadata: Default::default(),
+        })
+    }
+
+    #[test]
+    fn test_normalize_maps_scale_bounds_onto_target_bounds() {
+        let adc = profile(0.0, 1023.0);
+        assert_eq!(normalize(0.0, &adc), 30.0);
+        assert_eq!(normalize(1023.0, &adc), 130.0);
+        assert_eq!(normalize(511.5, &adc), 80.0);
+
+        let volts = profile(0.0, 5.0);
+        assert_eq!(normalize(2.5, &volts), 80.0);
+
+        // Outside the scale: clamped to the target range
+        assert_eq!(normalize(-10.0, &adc), 30.0);
+        assert_eq!(normalize(2000.0, &adc), 130.0);
+
+        // An inverted scale maps its minimum onto the top of the range
+        let inverted = profile(255.0, 0.0);
+        assert_eq!(normalize(255.0, &inverted), 30.0);
+        assert_eq!(normalize(0.0, &inverted), 130.0);
+
+        assert!(profile(1.0, 1.0).validate().is_err());
+        assert!(profile(0.0, 255.0).validate().is_ok());
+    }
+
+    #[test]
+    fn test_devices_without_a_profile_pass_through() {
+        let mut normalizer = Normalizer::default();
+        normalizer.set_profile("d1", Some(profile(0.0, 255.0)));
+
+        let mut passthrough = stored("d2", 200.0);
+        normalizer.apply(&mut passthrough);
+        assert_eq!(passthrough.reading.value, 200.0);
+        assert_eq!(passthrough.reading.unit, "raw");
+        assert_eq!(passthrough.raw_value, Some(200.0));
+
+        let mut scaled = stored("d1", 255.0);
+        normalizer.apply(&mut scaled);
+        assert_eq!(scaled.reading.value, 130.0);
+        assert_eq!(scaled.reading.unit, NORMALIZED_UNIT);
+        assert_eq!(scaled.raw_value, Some(255.0));
+
+        // Applying twice does not scale again
+        normalizer.apply(&mut scaled);
+        assert_eq!(scaled.reading.value, 130.0);
+
+        normalizer.set_profile("d1", None);
+        let mut unscaled = stored("d1", 255.0);
+        normalizer.apply(&mut unscaled);
+        assert_eq!(unscaled.reading.value, 255.0);
+    }
+}
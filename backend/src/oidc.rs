@@ -0,0 +1,449 @@
+/// OIDC Token Validation Module
+///
+/// Accepts RS256 access tokens issued by an external identity provider
+/// (e.g. Keycloak). The provider's JWKS is fetched and cached, keys are
+/// selected by `kid`, and a configurable claim is mapped onto our internal
+/// role model. Locally issued HS256 tokens are unaffected. `user` and
+/// `viewer` subjects only read the patients assigned to their `sub`, looked
+/// up on every request since the provider's tokens carry no such list.
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::{decode, decode_header, Algorithm, DecodingKey, Validation};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+use crate::auth::Claims;
+use crate::users::UserStore;
+
+/// Minimum time between on-demand JWKS refreshes triggered by unknown `kid`s
+const MIN_REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+
+/// OIDC provider configuration
+#[derive(Debug, Clone)]
+pub struct OidcConfig {
+    pub issuer: String,
+    /// Explicit JWKS URL; discovered from the issuer when not set
+    pub jwks_url: Option<String>,
+    pub audience: Option<String>,
+    /// Dot-separated path to the roles claim, e.g. `realm_access.roles`
+    pub role_claim: String,
+    /// Ordered (external role, internal role) pairs; the first match wins
+    pub role_mapping: Vec<(String, String)>,
+    pub refresh_interval: Duration,
+}
+
+impl OidcConfig {
+    /// Load configuration from the environment. Returns `None` unless
+    /// `OIDC_ISSUER_URL` is set.
+    pub fn from_env() -> Option<Self> {
+        let issuer = std::env::var("OIDC_ISSUER_URL").ok()?;
+        let issuer = issuer.trim().trim_end_matches('/').to_string();
+        if issuer.is_empty() {
+            return None;
+        }
+
+        Some(Self {
+            issuer,
+            jwks_url: std::env::var("OIDC_JWKS_URL").ok(),
+            audience: std::env::var("OIDC_AUDIENCE").ok(),
+            role_claim: std::env::var("OIDC_ROLE_CLAIM")
+                .unwrap_or_else(|_| "realm_access.roles".to_string()),
+            role_mapping: parse_role_mapping(&std::env::var("ROLE_MAPPING").unwrap_or_default()),
+            refresh_interval: Duration::from_secs(
+                std::env::var("OIDC_JWKS_REFRESH_SECS")
+                    .ok()
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(300),
+            ),
+        })
+    }
+}
+
+/// Parse `ROLE_MAPPING`, e.g. `soundsense-admin:admin,clinician:user`
+pub fn parse_role_mapping(raw: &str) -> Vec<(String, String)> {
+    raw.split(',')
+        .filter_map(|pair| {
+            let (external, internal) = pair.split_once(':')?;
+            let (external, internal) = (external.trim(), internal.trim());
+            if external.is_empty() || internal.is_empty() {
+                return None;
+            }
+            Some((external.to_string(), internal.to_string()))
+        })
+        .collect()
+}
+
+/// Read the `iss` claim without verifying the signature, to route the token
+/// to the right validator. Never trust anything else from this decode.
+pub fn unverified_issuer(token: &str) -> Option<String> {
+    let mut validation = Validation::default();
+    validation.insecure_disable_signature_validation();
+    validation.validate_exp = false;
+    validation.required_spec_claims.clear();
+
+    decode::<Value>(token, &DecodingKey::from_secret(&[]), &validation)
+        .ok()
+        .and_then(|data| data.claims.get("iss")?.as_str().map(str::to_string))
+}
+
+/// Validates tokens from the configured OIDC issuer against its cached JWKS
+pub struct OidcValidator {
+    config: OidcConfig,
+    keys: RwLock<HashMap<String, DecodingKey>>,
+    /// Time of the last JWKS fetch attempt, successful or not
+    last_refresh: RwLock<Option<Instant>>,
+    client: reqwest::Client,
+    /// Where the patients of `user` and `viewer` subjects are kept; without
+    /// it they may read none
+    users: Option<Arc<UserStore>>,
+}
+
+impl OidcValidator {
+    pub fn new(config: OidcConfig) -> Result<Self, String> {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(5))
+            .build()
+            .map_err(|e| format!("Failed to build the OIDC HTTP client: {}", e))?;
+
+        Ok(Self {
+            config,
+            keys: RwLock::new(HashMap::new()),
+            last_refresh: RwLock::new(None),
+            client,
+            users: None,
+        })
+    }
+
+    /// Limit `user` and `viewer` subjects to the patients assigned to their
+    /// `sub` in `users`
+    pub fn with_users(mut self, users: Arc<UserStore>) -> Self {
+        self.users = Some(users);
+        self
+    }
+
+    pub fn issuer(&self) -> &str {
+        &self.config.issuer
+    }
+
+    /// Whether a token claims to come from this issuer
+    pub fn handles(&self, token: &str) -> bool {
+        unverified_issuer(token)
+            .map(|iss| iss.trim_end_matches('/') == self.config.issuer)
+            .unwrap_or(false)
+    }
+
+    /// Replace the cached keys with a JWKS document. Keys without a `kid`
+    /// or that fail to parse are skipped. Returns the number of keys loaded.
+    pub fn set_jwks(&self, jwks: &JwkSet) -> usize {
+        let keys: HashMap<String, DecodingKey> = jwks
+            .keys
+            .iter()
+            .filter_map(|jwk| {
+                let kid = jwk.common.key_id.clone()?;
+                match DecodingKey::from_jwk(jwk) {
+                    Ok(key) => Some((kid, key)),
+                    Err(e) => {
+                        tracing::warn!(kid = %kid, error = %e, "Skipping unusable JWKS key");
+                        None
+                    }
+                }
+            })
+            .collect();
+
+        let count = keys.len();
+        if let Ok(mut cached) = self.keys.write() {
+            *cached = keys;
+        }
+        count
+    }
+
+    /// Resolve the JWKS URL, using OIDC discovery when not configured
+    async fn jwks_url(&self) -> Result<String, String> {
+        if let Some(url) = &self.config.jwks_url {
+            return Ok(url.clone());
+        }
+
+        let discovery = format!("{}/.well-known/openid-configuration", self.config.issuer);
+        let doc: Value = self
+            .client
+            .get(&discovery)
+            .send()
+            .await
+            .map_err(|e| format!("OIDC discovery request failed: {}", e))?
+            .json()
+            .await
+            .map_err(|e| format!("Failed to parse OIDC discovery document: {}", e))?;
+
+        doc["jwks_uri"]
+            .as_str()
+            .map(str::to_string)
+            .ok_or_else(|| "OIDC discovery document has no jwks_uri".to_string())
+    }
+
+    /// Fetch the provider's JWKS. On failure the previous cache is kept.
+    pub async fn refresh(&self) -> Result<usize, String> {
+        if let Ok(mut last) = self.last_refresh.write() {
+            *last = Some(Instant::now());
+        }
+
+        let url = self.jwks_url().await?;
+
+        let jwks: JwkSet = self
+            .client
+            .get(&url)
+            .send()
+            .await
+            .map_err(|e| format!("JWKS request failed: {}", e))?
+            .json()
+            .await
+            .map_err(|e| format!("Failed to parse JWKS: {}", e))?;
+
+        let count = self.set_jwks(&jwks);
+        tracing::debug!(count, "Refreshed OIDC JWKS");
+        Ok(count)
+    }
+
+    /// Refresh the JWKS periodically in the background
+    pub fn spawn_refresh(self: &Arc<Self>) {
+        let validator = Arc::clone(self);
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(validator.config.refresh_interval);
+            // The first tick completes immediately; keys were fetched at startup
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                if let Err(e) = validator.refresh().await {
+                    tracing::warn!(error = %e, "OIDC JWKS refresh failed, serving cached keys");
+                }
+            }
+        });
+    }
+
+    fn cached_key(&self, kid: &str) -> Option<DecodingKey> {
+        self.keys.read().ok()?.get(kid).cloned()
+    }
+
+    fn may_refresh_now(&self) -> bool {
+        self.last_refresh
+            .read()
+            .map(|last| last.is_none_or(|t| t.elapsed() >= MIN_REFRESH_INTERVAL))
+            .unwrap_or(false)
+    }
+
+    /// Validate an RS256 token from the provider and map it onto our claims
+    pub async fn validate(&self, token: &str) -> Result<Claims, String> {
+        let header = decode_header(token).map_err(|e| format!("Invalid token header: {}", e))?;
+        if header.alg != Algorithm::RS256 {
+            return Err(format!("Unsupported OIDC algorithm: {:?}", header.alg));
+        }
+        let kid = header.kid.ok_or("OIDC token has no kid")?;
+
+        let key = match self.cached_key(&kid) {
+            Some(key) => key,
+            None => {
+                // Unknown kid: the provider may have rotated keys
+                if self.may_refresh_now() {
+                    if let Err(e) = self.refresh().await {
+                        tracing::warn!(error = %e, "On-demand JWKS refresh failed");
+                    }
+                }
+                self.cached_key(&kid)
+                    .ok_or_else(|| format!("Unknown signing key: {}", kid))?
+            }
+        };
+
+        let mut validation = Validation::new(Algorithm::RS256);
+        validation.set_issuer(&[&self.config.issuer]);
+        match &self.config.audience {
+            Some(aud) => validation.set_audience(&[aud]),
+            None => validation.validate_aud = false,
+        }
+
+        let data = decode::<Value>(token, &key, &validation)
+            .map_err(|e| format!("Invalid OIDC token: {}", e))?;
+
+        let claims = self.map_claims(&data.claims)?;
+        if !matches!(claims.role.as_str(), "user" | "viewer") {
+            return Ok(claims);
+        }
+        let patients = match &self.users {
+            Some(users) => users
+                .permitted_patients(&claims.sub)
+                .await
+                .map_err(|e| format!("Cannot look up the patients of {}: {}", claims.sub, e))?,
+            None => Vec::new(),
+        };
+        Ok(claims.with_permitted_patients(patients))
+    }
+
+    /// Map provider claims onto internal `Claims`
+    fn map_claims(&self, claims: &Value) -> Result<Claims, String> {
+        let sub = claims["sub"]
+            .as_str()
+            .ok_or("OIDC token has no sub claim")?
+            .to_string();
+        let role = self
+            .map_role(claims)
+            .ok_or_else(|| format!("No mapped role for OIDC subject {}", sub))?;
+
+        Ok(Claims {
+            sub,
+            exp: claims["exp"].as_i64().unwrap_or_default(),
+            iat: claims["iat"].as_i64().unwrap_or_default(),
+            role,
+            device_id: None,
+            jti: claims["jti"].as_str().map(str::to_string),
+            scope: None,
+            permitted_patients: None,
+        })
+    }
+
+    /// Find the first configured mapping whose external role the token carries
+    pub fn map_role(&self, claims: &Value) -> Option<String> {
+        let mut value = claims;
+        for segment in self.config.role_claim.split('.') {
+            value = value.get(segment)?;
+        }
+
+        let external: Vec<&str> = match value {
+            Value::Array(items) => items.iter().filter_map(Value::as_str).collect(),
+            Value::String(s) => vec![s.as_str()],
+            _ => return None,
+        };
+
+        self.config
+            .role_mapping
+            .iter()
+            .find(|(ext, _)| external.contains(&ext.as_str()))
+            .map(|(_, internal)| internal.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jsonwebtoken::{encode, EncodingKey, Header};
+
+    const ISSUER: &str = "https://idp.example.org/realms/hospital";
+    const PRIVATE_KEY: &str = include_str!("../tests/fixtures/test_rsa_private.pem");
+    const JWKS: &str = include_str!("../tests/fixtures/test_jwks.json");
+
+    fn validator() -> OidcValidator {
+        let validator = OidcValidator::new(OidcConfig {
+            issuer: ISSUER.to_string(),
+            // Unroutable, so on-demand refreshes fail fast
+            jwks_url: Some("http://127.0.0.1:9/certs".to_string()),
+            audience: None,
+            role_claim: "realm_access.roles".to_string(),
+            role_mapping: parse_role_mapping("soundsense-admin:admin,clinician:user"),
+            refresh_interval: Duration::from_secs(300),
+        })
+        .unwrap();
+        let jwks: JwkSet = serde_json::from_str(JWKS).unwrap();
+        assert_eq!(validator.set_jwks(&jwks), 1);
+        validator
+    }
+
+    fn idp_token(kid: &str, iss: &str, roles: &[&str]) -> String {
+        let now = chrono::Utc::now().timestamp();
+        let claims = serde_json::json!({
+            "iss": iss,
+            "sub": "alice",
+            "iat": now,
+            "exp": now + 300,
+            "realm_access": { "roles": roles }
+        });
+        let mut header = Header::new(Algorithm::RS256);
+        header.kid = Some(kid.to_string());
+        let key = EncodingKey::from_rsa_pem(PRIVATE_KEY.as_bytes()).unwrap();
+        encode(&header, &claims, &key).unwrap()
+    }
+
+    #[test]
+    fn test_parse_role_mapping() {
+        let mapping = parse_role_mapping("soundsense-admin:admin, clinician:user,bogus,:x");
+        assert_eq!(
+            mapping,
+            vec![
+                ("soundsense-admin".to_string(), "admin".to_string()),
+                ("clinician".to_string(), "user".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unverified_issuer() {
+        let token = idp_token("test-idp-key", ISSUER, &["clinician"]);
+        assert_eq!(unverified_issuer(&token).as_deref(), Some(ISSUER));
+        assert!(unverified_issuer("not-a-token").is_none());
+    }
+
+    #[actix_web::test]
+    async fn test_valid_idp_token_maps_role() {
+        let validator = validator();
+
+        let token = idp_token("test-idp-key", ISSUER, &["offline_access", "clinician"]);
+        assert!(validator.handles(&token));
+
+        let claims = validator.validate(&token).await.unwrap();
+        assert_eq!(claims.sub, "alice");
+        assert_eq!(claims.role, "user");
+
+        // Mapping order decides priority
+        let token = idp_token("test-idp-key", ISSUER, &["clinician", "soundsense-admin"]);
+        let claims = validator.validate(&token).await.unwrap();
+        assert_eq!(claims.role, "admin");
+    }
+
+    #[actix_web::test]
+    async fn test_user_role_limited_to_subject_patients() {
+        let token = idp_token("test-idp-key", ISSUER, &["clinician"]);
+
+        // Nowhere to look the patients up: none
+        let claims = validator().validate(&token).await.unwrap();
+        assert_eq!(claims.patient_filter(), Some(&[][..]));
+
+        let users = Arc::new(UserStore::new(None));
+        let validator = validator().with_users(users.clone());
+        let claims = validator.validate(&token).await.unwrap();
+        assert_eq!(claims.patient_filter(), Some(&[][..]));
+
+        users.permit_patient("alice", "p1").await.unwrap();
+        users.permit_patient("bob", "p2").await.unwrap();
+        let claims = validator.validate(&token).await.unwrap();
+        assert_eq!(claims.patient_filter(), Some(&["p1".to_string()][..]));
+
+        // Admins are not limited
+        let token = idp_token("test-idp-key", ISSUER, &["soundsense-admin"]);
+        let claims = validator.validate(&token).await.unwrap();
+        assert_eq!(claims.permitted_patients, None);
+    }
+
+    #[actix_web::test]
+    async fn test_unmapped_role_rejected() {
+        let validator = validator();
+        let token = idp_token("test-idp-key", ISSUER, &["offline_access"]);
+        assert!(validator.validate(&token).await.is_err());
+    }
+
+    #[actix_web::test]
+    async fn test_unknown_kid_and_wrong_issuer_rejected() {
+        let validator = validator();
+
+        // Refresh fails (unreachable JWKS) but the cached keys still serve
+        let token = idp_token("rotated-key", ISSUER, &["clinician"]);
+        assert!(validator.validate(&token).await.is_err());
+
+        let token = idp_token(
+            "test-idp-key",
+            "https://other-idp.example.org",
+            &["clinician"],
+        );
+        assert!(!validator.handles(&token));
+        assert!(validator.validate(&token).await.is_err());
+
+        let token = idp_token("test-idp-key", ISSUER, &["clinician"]);
+        assert!(validator.validate(&token).await.is_ok());
+    }
+}
@@ -0,0 +1,359 @@
+/// Patient Summary Reports
+///
+/// A weekly summary of a patient's noise exposure, worded for the patient
+/// rather than for clinicians, so it can be emailed or printed as is. Each
+/// day's TWA is the energy average of that day's decibel readings (see
+/// `hearing`); the week's average is the energy average of the days that had
+/// any. The advice comes from the `recommendations` table, chosen by the
+/// NIOSH dose of that average.
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use pdf_writer::{Content, Name, Pdf, Rect, Ref, Str};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::domain::models::{energy_average, NOISE_EXPOSURE_LIMIT_DB};
+use crate::hearing::niosh_dose_percent;
+
+/// Days in a report
+pub const WEEK_DAYS: i64 = 7;
+
+/// Change in the weekly average (dB) still reported as stable
+pub const STABLE_BAND_DB: f64 = 1.0;
+
+/// Advice for weekly average doses from `min_dose_percent` up to (not
+/// including) `max_dose_percent`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Recommendation {
+    pub min_dose_percent: f64,
+    /// `None` for no upper bound
+    pub max_dose_percent: Option<f64>,
+    pub text: String,
+}
+
+/// The advice seeded by the migration, used without a database
+pub fn default_recommendations() -> Vec<Recommendation> {
+    [
+        (0.0, Some(50.0), "Your noise exposure this week was well within safe limits. Keep it up."),
+        (50.0, Some(100.0), "Your noise exposure stayed within the daily limit, but came close to it. Take breaks from loud places where you can."),
+        (100.0, Some(200.0), "Your noise exposure was above the recommended daily limit. Wear hearing protection in loud places and spend less time there."),
+        (200.0, None, "Your noise exposure was well above the safe limit. Wear hearing protection whenever you are somewhere loud, and talk to your audiologist."),
+    ]
+    .into_iter()
+    .map(|(min, max, text)| Recommendation {
+        min_dose_percent: min,
+        max_dose_percent: max,
+        text: text.to_string(),
+    })
+    .collect()
+}
+
+/// Advice whose range holds `dose_percent`
+pub fn recommendation_for(dose_percent: f64, recommendations: &[Recommendation]) -> Option<&str> {
+    recommendations
+        .iter()
+        .find(|r| {
+            dose_percent >= r.min_dose_percent
+                && r.max_dose_percent.is_none_or(|max| dose_percent < max)
+        })
+        .map(|r| r.text.as_str())
+}
+
+/// TWA of one day with decibel readings
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DailyExposure {
+    pub date: NaiveDate,
+    pub twa_db: f64,
+}
+
+/// The loudest decibel reading of a period
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PeakReading {
+    pub value_db: f64,
+    pub at: DateTime<Utc>,
+}
+
+/// How the week compares to the week before
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+pub enum Trend {
+    /// Average at least `STABLE_BAND_DB` lower
+    Improving,
+    /// Within `STABLE_BAND_DB`, or no readings the week before
+    Stable,
+    /// Average at least `STABLE_BAND_DB` higher
+    Worsening,
+}
+
+impl Trend {
+    pub fn between(previous_avg_db: Option<f64>, current_avg_db: f64) -> Self {
+        match previous_avg_db.map(|previous| current_avg_db - previous) {
+            Some(change) if change <= -STABLE_BAND_DB => Trend::Improving,
+            Some(change) if change >= STABLE_BAND_DB => Trend::Worsening,
+            _ => Trend::Stable,
+        }
+    }
+
+    fn describe(&self) -> &'static str {
+        match self {
+            Trend::Improving => "Quieter than the week before",
+            Trend::Stable => "About the same as the week before",
+            Trend::Worsening => "Louder than the week before",
+        }
+    }
+}
+
+/// A patient's week of noise exposure, in plain English
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct PatientSummaryReport {
+    pub patient_id: String,
+    pub week_start: NaiveDate,
+    /// Last day of the week (inclusive)
+    pub week_end: NaiveDate,
+    /// Energy average of the daily TWAs of days with readings
+    pub avg_daily_twa_db: f64,
+    /// Days whose TWA was above the 85 dB limit
+    pub days_exceeding_limit: usize,
+    pub peak_reading_db: f64,
+    pub peak_reading_time: DateTime<Utc>,
+    pub recommendation_text: String,
+    pub trend: Trend,
+}
+
+impl PatientSummaryReport {
+    /// Summarize `this_week`, comparing it to `previous_week`. `None` if the
+    /// week has no decibel readings.
+    pub fn build(
+        patient_id: &str,
+        week_start: NaiveDate,
+        this_week: &[DailyExposure],
+        previous_week: &[DailyExposure],
+        peak: Option<PeakReading>,
+        recommendations: &[Recommendation],
+    ) -> Option<Self> {
+        let avg_daily_twa_db = energy_average(this_week.iter().map(|d| d.twa_db))?;
+        let peak = peak?;
+        let previous_avg = energy_average(previous_week.iter().map(|d| d.twa_db));
+        let recommendation_text =
+            recommendation_for(niosh_dose_percent(avg_daily_twa_db), recommendations)
+                .unwrap_or("Talk to your audiologist about your noise exposure.")
+                .to_string();
+
+        Some(Self {
+            patient_id: patient_id.to_string(),
+            week_start,
+            week_end: week_start + Duration::days(WEEK_DAYS - 1),
+            avg_daily_twa_db,
+            days_exceeding_limit: this_week
+                .iter()
+                .filter(|d| d.twa_db > NOISE_EXPOSURE_LIMIT_DB)
+                .count(),
+            peak_reading_db: peak.value_db,
+            peak_reading_time: peak.at,
+            recommendation_text,
+            trend: Trend::between(previous_avg, avg_daily_twa_db),
+        })
+    }
+
+    /// The report as a one-page A4 PDF in Helvetica
+    pub fn to_pdf(&self) -> Vec<u8> {
+        let catalog_id = Ref::new(1);
+        let page_tree_id = Ref::new(2);
+        let page_id = Ref::new(3);
+        let font_id = Ref::new(4);
+        let bold_id = Ref::new(5);
+        let content_id = Ref::new(6);
+        let (font, bold) = (Name(b"F1"), Name(b"F2"));
+
+        let mut pdf = Pdf::new();
+        pdf.catalog(catalog_id).pages(page_tree_id);
+        pdf.pages(page_tree_id).kids([page_id]).count(1);
+        let mut page = pdf.page(page_id);
+        page.media_box(Rect::new(0.0, 0.0, 595.0, 842.0))
+            .parent(page_tree_id)
+            .contents(content_id);
+        page.resources()
+            .fonts()
+            .pair(font, font_id)
+            .pair(bold, bold_id);
+        drop(page);
+        pdf.type1_font(font_id).base_font(Name(b"Helvetica"));
+        pdf.type1_font(bold_id).base_font(Name(b"Helvetica-Bold"));
+
+        let mut lines: Vec<(Name, f32, String)> = vec![
+            (bold, 18.0, "Your weekly noise exposure summary".into()),
+            (
+                font,
+                11.0,
+                format!(
+                    "Patient {}, {} to {}",
+                    self.patient_id, self.week_start, self.week_end
+                ),
+            ),
+            (font, 11.0, String::new()),
+            (
+                font,
+                11.0,
+                format!(
+                    "Average daily noise level: {:.1} dB (the safe daily limit is {:.0} dB)",
+                    self.avg_daily_twa_db, NOISE_EXPOSURE_LIMIT_DB
+                ),
+            ),
+            (
+                font,
+                11.0,
+                format!(
+                    "Days above the limit: {} of {}",
+                    self.days_exceeding_limit, WEEK_DAYS
+                ),
+            ),
+            (
+                font,
+                11.0,
+                format!(
+                    "Loudest moment: {:.1} dB on {}",
+                    self.peak_reading_db,
+                    self.peak_reading_time.format("%A %-d %B at %H:%M UTC")
+                ),
+            ),
+            (font, 11.0, format!("Trend: {}", self.trend.describe())),
+            (font, 11.0, String::new()),
+            (bold, 12.0, "What this means for you".into()),
+        ];
+        lines.extend(
+            wrap(&self.recommendation_text, 90)
+                .into_iter()
+                .map(|line| (font, 11.0, line)),
+        );
+
+        let mut content = Content::new();
+        content.begin_text();
+        content.next_line(56.0, 770.0);
+        for (i, (name, size, text)) in lines.iter().enumerate() {
+            if i > 0 {
+                content.next_line(0.0, -(size + 8.0));
+            }
+            content.set_font(*name, *size);
+            content.show(Str(pdf_text(text).as_bytes()));
+        }
+        content.end_text();
+        pdf.stream(content_id, &content.finish());
+        pdf.finish()
+    }
+}
+
+/// `text` in the characters the standard encoding of the base fonts shares
+/// with ASCII; anything else becomes `?`
+fn pdf_text(text: &str) -> String {
+    text.chars()
+        .map(|c| {
+            if c.is_ascii_graphic() || c == ' ' {
+                c
+            } else {
+                '?'
+            }
+        })
+        .collect()
+}
+
+/// Break `text` into lines of at most `width` characters at spaces
+fn wrap(text: &str, width: usize) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    for word in text.split_whitespace() {
+        match lines.last_mut() {
+            Some(line) if line.len() + 1 + word.len() <= width => {
+                line.push(' ');
+                line.push_str(word);
+            }
+            _ => lines.push(word.to_string()),
+        }
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn week(start: NaiveDate, twas: &[f64]) -> Vec<DailyExposure> {
+        twas.iter()
+            .enumerate()
+            .map(|(i, &twa_db)| DailyExposure {
+                date: start + Duration::days(i as i64),
+                twa_db,
+            })
+            .collect()
+    }
+
+    fn summary(previous: &[f64], current: &[f64]) -> PatientSummaryReport {
+        let week_start = NaiveDate::from_ymd_opt(2024, 1, 15).unwrap();
+        let peak = PeakReading {
+            value_db: 97.5,
+            at: Utc.with_ymd_and_hms(2024, 1, 17, 14, 30, 0).unwrap(),
+        };
+        PatientSummaryReport::build(
+            "p1",
+            week_start,
+            &week(week_start, current),
+            &week(week_start - Duration::days(WEEK_DAYS), previous),
+            Some(peak),
+            &default_recommendations(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_quieter_week_is_improving() {
+        let report = summary(
+            &[88.0, 89.0, 87.0, 90.0, 88.0],
+            &[82.0, 83.0, 81.0, 86.0, 80.0],
+        );
+        assert_eq!(report.trend, Trend::Improving);
+        assert_eq!(report.days_exceeding_limit, 1);
+        assert_eq!(
+            report.week_end,
+            NaiveDate::from_ymd_opt(2024, 1, 21).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_similar_week_is_stable() {
+        let report = summary(
+            &[80.0, 81.0, 80.0, 79.0, 80.0, 81.0, 80.0],
+            &[80.5, 80.0, 81.0, 80.0, 79.5, 80.0, 80.5],
+        );
+        assert_eq!(report.trend, Trend::Stable);
+        assert_eq!(report.days_exceeding_limit, 0);
+        assert!(report.recommendation_text.contains("well within"));
+
+        // Nothing to compare against
+        assert_eq!(summary(&[], &[80.0]).trend, Trend::Stable);
+    }
+
+    #[test]
+    fn test_louder_week_is_worsening() {
+        let report = summary(&[78.0, 79.0, 80.0], &[86.0, 88.0, 91.0, 87.0]);
+        assert_eq!(report.trend, Trend::Worsening);
+        assert_eq!(report.days_exceeding_limit, 4);
+        // 10 log of the mean power of 86, 88, 91 and 87 dB
+        assert!((report.avg_daily_twa_db - 88.43).abs() < 0.01);
+        assert!(report.recommendation_text.contains("well above"));
+    }
+
+    #[test]
+    fn test_recommendation_ranges_and_pdf() {
+        let recommendations = default_recommendations();
+        assert!(recommendation_for(49.9, &recommendations)
+            .unwrap()
+            .contains("well within"));
+        assert!(recommendation_for(100.0, &recommendations)
+            .unwrap()
+            .contains("above the recommended"));
+        assert!(recommendation_for(1e6, &recommendations).is_some());
+        assert!(recommendation_for(-1.0, &recommendations).is_none());
+
+        let pdf = summary(&[80.0], &[90.0]).to_pdf();
+        assert!(pdf.starts_with(b"%PDF-"));
+        let text = String::from_utf8_lossy(&pdf);
+        assert!(text.contains("Days above the limit: 1 of 7"));
+    }
+}
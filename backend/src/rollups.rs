@@ -0,0 +1,236 @@
+/// Daily Rollups
+///
+/// Per-patient, per-signal aggregates of each complete day, so month-long
+/// queries don't have to scan raw readings. A background job fills the
+/// `daily_rollups` table shortly after local midnight (`ROLLUP_TIMEZONE`,
+/// any IANA name Postgres knows; default UTC). Each run recomputes every day
+/// of the last `ROLLUP_BACKFILL_DAYS` that has no rollup yet or received
+/// readings after it was computed, so startup backfills and late readings
+/// are picked up on the next run. Amending or deleting a reading drops the
+/// rollups it may have counted towards, to be recomputed on the next run.
+/// Without a database, rollups are computed from the in-memory readings on
+/// request, with UTC days.
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::db::Database;
+use crate::domain::models::{is_decibel_unit, StoredReading, NOISE_EXPOSURE_LIMIT_DB};
+use crate::errors::AppError;
+
+/// Default number of past days checked for missing or outdated rollups
+const DEFAULT_BACKFILL_DAYS: i64 = 90;
+
+/// How long after local midnight the nightly run starts, so devices that
+/// upload in bursts have delivered the previous day
+const RUN_DELAY: Duration = Duration::minutes(5);
+
+/// Aggregates of one patient's readings of one signal on one day
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct DailyRollup {
+    pub patient_id: String,
+    pub code: String,
+    pub date: NaiveDate,
+    pub count: i64,
+    pub mean: f64,
+    pub max: f64,
+    /// 95th percentile, linearly interpolated
+    pub p95: f64,
+    /// Clock minutes with at least one decibel reading above 85 dB
+    pub minutes_above_threshold: i64,
+    pub computed_at: DateTime<Utc>,
+}
+
+impl DailyRollup {
+    /// Rollups of `patient_id`'s usable readings per UTC day from `from` to
+    /// `to` (inclusive), skipping days without readings. Mirrors the query
+    /// that fills `daily_rollups`.
+    pub fn from_readings<'a>(
+        patient_id: &str,
+        readings: impl IntoIterator<Item = &'a StoredReading>,
+        from: NaiveDate,
+        to: NaiveDate,
+    ) -> Vec<DailyRollup> {
+        let mut days: std::collections::BTreeMap<(NaiveDate, &str), Vec<&StoredReading>> =
+            Default::default();
+        for r in readings.into_iter().filter(|r| {
+            let date = r.reading.ts.date_naive();
+            r.is_usable() && r.reading.patient_id == patient_id && date >= from && date <= to
+        }) {
+            days.entry((r.reading.ts.date_naive(), r.reading.code.as_str()))
+                .or_default()
+                .push(r);
+        }
+
+        let computed_at = Utc::now();
+        days.into_iter()
+            .map(|((date, code), day)| {
+                let mut values: Vec<f64> = day.iter().map(|r| r.reading.value).collect();
+                values.sort_by(f64::total_cmp);
+
+                let mut minutes: Vec<i64> = day
+                    .iter()
+                    .filter(|r| {
+                        is_decibel_unit(&r.reading.unit)
+                            && r.reading.value > NOISE_EXPOSURE_LIMIT_DB
+                    })
+                    .map(|r| r.reading.ts.timestamp().div_euclid(60))
+                    .collect();
+                minutes.sort_unstable();
+                minutes.dedup();
+
+                DailyRollup {
+                    patient_id: patient_id.to_string(),
+                    code: code.to_string(),
+                    date,
+                    count: values.len() as i64,
+                    mean: values.iter().sum::<f64>() / values.len() as f64,
+                    max: values.last().copied().unwrap_or_default(),
+                    p95: percentile_cont(&values, 0.95),
+                    minutes_above_threshold: minutes.len() as i64,
+                    computed_at,
+                }
+            })
+            .collect()
+    }
+}
+
+/// Percentile of sorted, non-empty `values`, interpolated like Postgres'
+/// `percentile_cont`
+fn percentile_cont(values: &[f64], fraction: f64) -> f64 {
+    let rank = fraction * (values.len() - 1) as f64;
+    let (lower, upper) = (rank.floor() as usize, rank.ceil() as usize);
+    values[lower] + (values[upper] - values[lower]) * (rank - lower as f64)
+}
+
+/// Nightly job filling `daily_rollups`
+pub struct RollupJob {
+    db: Database,
+    timezone: String,
+    backfill_days: i64,
+}
+
+impl RollupJob {
+    /// Configure from `ROLLUP_TIMEZONE` (default `UTC`) and
+    /// `ROLLUP_BACKFILL_DAYS` (default 90). An unknown time zone falls back
+    /// to UTC with a warning.
+    pub async fn from_env(db: Database) -> Self {
+        let backfill_days = std::env::var("ROLLUP_BACKFILL_DAYS")
+            .ok()
+            .and_then(|v| v.parse::<i64>().ok())
+            .filter(|v| *v > 0)
+            .unwrap_or(DEFAULT_BACKFILL_DAYS);
+
+        let mut timezone = std::env::var("ROLLUP_TIMEZONE").unwrap_or_else(|_| "UTC".into());
+        if db.next_local_midnight(&timezone).await.is_err() {
+            tracing::warn!("Unknown ROLLUP_TIMEZONE '{}', using UTC", timezone);
+            timezone = "UTC".into();
+        }
+
+        Self {
+            db,
+            timezone,
+            backfill_days,
+        }
+    }
+
+    /// Recompute every day needing it; returns how many days were rolled up
+    pub async fn run_once(&self) -> Result<usize, AppError> {
+        let since = Utc::now() - Duration::days(self.backfill_days);
+        let days = self.db.stale_rollup_days(&self.timezone, since).await?;
+        for date in &days {
+            let rows = self.db.upsert_daily_rollups(&self.timezone, *date).await?;
+            tracing::debug!(%date, rows, "Rolled up readings");
+        }
+        Ok(days.len())
+    }
+
+    /// Run now (backfilling missed days), then shortly after every local midnight
+    pub fn spawn(self) {
+        tokio::spawn(async move {
+            loop {
+                match self.run_once().await {
+                    Ok(0) => {}
+                    Ok(days) => {
+                        tracing::info!(days, timezone = %self.timezone, "Daily rollups updated")
+                    }
+                    Err(e) => tracing::warn!(error = ?e, "Daily rollup run failed"),
+                }
+
+                let next_run = match self.db.next_local_midnight(&self.timezone).await {
+                    Ok(midnight) => midnight + RUN_DELAY,
+                    // Database unavailable; try again in an hour
+                    Err(_) => Utc::now() + Duration::hours(1),
+                };
+                let wait = (next_run - Utc::now()).to_std().unwrap_or_default();
+                tokio::time::sleep(wait).await;
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::models::{SensorReading, SignalCode, TimestampSource};
+    use crate::fhir::ObservationStatus;
+    use chrono::TimeZone;
+
+    fn stored(patient_id: &str, value: f64, unit: &str, ts: DateTime<Utc>) -> StoredReading {
+        StoredReading::new(SensorReading {
+            patient_id: patient_id.into(),
+            device_id: "d1".into(),
+            code: SignalCode::Sound,
+            value,
+            unit: unit.into(),
+            ts,
+            ts_source: TimestampSource::Device,
+            metadata: Default::default(),
+        })
+    }
+
+    #[test]
+    fn test_percentile_matches_postgres() {
+        // percentile_cont(0.95) over 1..=10 is 9.55
+        let values: Vec<f64> = (1..=10).map(f64::from).collect();
+        assert!((percentile_cont(&values, 0.95) - 9.55).abs() < 1e-9);
+        assert_eq!(percentile_cont(&[42.0], 0.95), 42.0);
+    }
+
+    #[test]
+    fn test_rollups_per_day() {
+        let t0 = Utc.with_ymd_and_hms(2026, 3, 1, 22, 0, 0).unwrap();
+        let mut readings = vec![
+            stored("p1", 80.0, "dB", t0),
+            stored("p1", 90.0, "dB", t0 + Duration::seconds(10)),
+            stored("p1", 95.0, "dB", t0 + Duration::seconds(70)),
+            // Next day, and another patient
+            stored("p1", 70.0, "dB", t0 + Duration::hours(3)),
+            stored("p2", 99.0, "dB", t0),
+        ];
+        let mut wrong = stored("p1", 120.0, "dB", t0);
+        wrong.status = ObservationStatus::EnteredInError;
+        readings.push(wrong);
+
+        let day1 = NaiveDate::from_ymd_opt(2026, 3, 1).unwrap();
+        let day2 = NaiveDate::from_ymd_opt(2026, 3, 2).unwrap();
+        let rollups = DailyRollup::from_readings("p1", &readings, day1, day2);
+
+        assert_eq!(rollups.len(), 2);
+        let first = &rollups[0];
+        assert_eq!(first.date, day1);
+        assert_eq!(first.code, "sound");
+        assert_eq!(first.count, 3);
+        assert!((first.mean - 265.0 / 3.0).abs() < 1e-9);
+        assert_eq!(first.max, 95.0);
+        assert!((first.p95 - 94.5).abs() < 1e-9);
+        // 22:00 (90 dB) and 22:01 (95 dB); 80 dB is under the threshold
+        assert_eq!(first.minutes_above_threshold, 2);
+
+        assert_eq!(rollups[1].date, day2);
+        assert_eq!(rollups[1].count, 1);
+        assert_eq!(rollups[1].minutes_above_threshold, 0);
+
+        assert!(DailyRollup::from_readings("p1", &readings, day2, day2).len() == 1);
+    }
+}
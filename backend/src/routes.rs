@@ -1,55 +1,839 @@
-use actix_web::{web, HttpRequest, HttpResponse};
+use actix_web::http::header;
+use actix_web::middleware::from_fn;
+use actix_web::{error::JsonPayloadError, guard, web, HttpRequest, HttpResponse, ResponseError};
+use actix_web_httpauth::extractors::bearer;
 use actix_web_httpauth::middleware::HttpAuthentication;
-use std::sync::Arc;
-use tokio::sync::{broadcast, Mutex};
+use futures_util::StreamExt;
+use std::sync::{Arc, RwLock};
+use tokio::sync::Mutex;
 
-use crate::auth::{get_claims_from_request, jwt_validator, Claims, JwtManager};
-use crate::domain::models::SensorReading;
+use crate::acoustics::{self, AWeightRequest, AWeightedLevel};
+use crate::alert_suppression::{AlertSuppressions, SuppressionRequest, SuppressionWindow};
+use crate::anomaly::AnomalyScorer;
+use crate::audit::{AuditAction, AuditLogEntry, AuditLogger, AuditPage};
+use crate::auth::{
+    get_claims_from_request, jwt_validator, optional_claims, Claims, JwtExpiryConfig, JwtManager,
+    Permission, AUTH_REALM, SCOPE_PASSWORD_CHANGE,
+};
+use crate::build_info;
+use crate::bulk_export::{BulkExports, ExportJob, ExportManifest, ExportStatus};
+use crate::bundle_stream::{self, bundle_chunks};
+use crate::calibration::{CalibrationHistory, CalibrationVerification, VerifyRequest};
+use crate::compliance::{month_bounds, ComplianceReport, ComplianceReportGenerator};
+use crate::consent::Admission;
+use crate::content_type::require_json;
+use crate::cors::{CorsAllowlist, CorsOrigin};
+use crate::dashboard::{day_start, Dashboards, PatientDashboard};
+use crate::device_auth::Challenge;
+use crate::device_stats::DeviceStats;
+use crate::device_watchdog::{DeviceSettings, DeviceSettingsUpdate};
+use crate::domain::models::{
+    normalize_unit, ConsentStatus, IngestReading, IngestReceipt, OctaveBandReading, OctaveSpectrum,
+    PackedReading, Patient, ReadingFilter, ReadingQuery, SensorReading, SignalCode, Storage,
+    StoredReading,
+};
 use crate::domain::store::AppState;
-use crate::errors::AppError;
-use crate::fhir::FhirObservation;
-use crate::ml_client::MlClient;
-use crate::ws::{ws_live, WsHub};
+use crate::errors::{AppError, ErrBody};
+use crate::fhir::{
+    FhirBundle, FhirDevice, FhirMeasureReport, FhirObservation, FhirPatient, FhirPeriod,
+    FhirValueSet, ObservationStatus, FHIR_VERSION,
+};
+use crate::gaps::{Gap, GapDetector, DEFAULT_MAX_GAP_SECS, MAX_GAP_SECS};
+use crate::hearing::{HearingProtectorRequest, ProtectionAdvice};
+use crate::hourly_pattern::{self, HourlyPattern};
+use crate::ingest_queue::{IngestQueue, Priority, QueuedReading};
+use crate::iot_auth::{self, IotChallenge, IotTokenRequest};
+use crate::live_stats::StatsFrame;
+use crate::load_shed::{shed_ingest_load, IngestLimiter};
+use crate::log_sampling;
+use crate::metrics::{self, Exposition, METRICS};
+use crate::ml_cache::MlResultCache;
+use crate::ml_client::{
+    Analysis, AnalysisResponse, AnalysisSource, AnalysisWindow, MlClient, MlError,
+    MAX_ANALYSIS_RANGE_DAYS,
+};
+use crate::noise_map::LocationNoise;
+use crate::normalization::Normalizer;
+use crate::reports::PatientSummaryReport;
+use crate::rollups::DailyRollup;
+use crate::security::DEFAULT_DEVICE_TOKEN_SECRET;
+use crate::self_check::{self, CheckReport, ReadinessCache};
+use crate::serial_ingest::SerialStatus;
+use crate::sessions::SessionStore;
+use crate::snapshot::SnapshotManager;
+use crate::spectrogram::{SpectrogramFrame, DEFAULT_MAX_FRAMES, MAX_FRAMES_LIMIT};
+use crate::stream_ingest::{Line, LineSplitter, StreamIngestConfig, StreamIngestSummary};
+use crate::text_search;
+use crate::timeout::{enforce_request_timeout, RequestTimeout};
+use crate::units::TargetUnit;
+use crate::users::{generate_temporary_password, validate_password_strength, User, UserStore};
+use crate::validation::ValidationPipeline;
+use crate::ws::{ws_alerts, ws_anomalies, ws_live, WsHub};
+use utoipa::openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::{IntoParams, Modify, OpenApi, ToSchema};
+use utoipa_swagger_ui::{Config as SwaggerConfig, SwaggerUi};
 
-pub fn configure(cfg: &mut web::ServiceConfig) {
-    let (tx, _rx) = broadcast::channel::<FhirObservation>(256);
+/// Default body limit for single-reading ingest (bytes)
+const DEFAULT_INGEST_MAX_BYTES: usize = 8 * 1024;
+
+/// Default body limit for all other JSON endpoints (actix's own default)
+const DEFAULT_JSON_MAX_BYTES: usize = 2 * 1024 * 1024;
+
+/// Default body limit for batch ingest (bytes)
+const DEFAULT_INGEST_BATCH_MAX_BYTES: usize = 1024 * 1024;
+
+/// Default share of packed samples sent to WebSocket clients: one in ten,
+/// i.e. one per second from 10 Hz firmware
+const DEFAULT_PACKED_BROADCAST_EVERY: usize = 10;
 
-    // Initialize ML client if ML_SERVICE_URL is set
-    let ml_client = std::env::var("ML_SERVICE_URL")
+/// Only every Nth sample of a packed reading is published to WebSocket clients
+struct PackedBroadcastEvery(usize);
+
+fn env_limit(name: &str, default: usize) -> usize {
+    std::env::var(name)
         .ok()
-        .map(|url| Arc::new(MlClient::new(url)));
+        .and_then(|v| v.parse::<usize>().ok())
+        .filter(|v| *v > 0)
+        .unwrap_or(default)
+}
+
+/// JSON extractor config with an explicit body limit. Oversized bodies get a
+/// 413 naming the limit; malformed or mistyped JSON a 400 explaining why, both
+/// in the standard error body.
+fn json_config(limit: usize) -> web::JsonConfig {
+    web::JsonConfig::default()
+        .limit(limit)
+        .error_handler(|err, _req| json_error(err).into())
+}
+
+/// Like `json_config`, but oversized bodies are counted as ingest rejections
+fn ingest_json_config(limit: usize) -> web::JsonConfig {
+    web::JsonConfig::default()
+        .limit(limit)
+        .error_handler(|err, _req| {
+            let app_err = json_error(err);
+            if matches!(app_err, AppError::PayloadTooLarge(_)) {
+                metrics::inc(&METRICS.ingest_body_too_large);
+            }
+            app_err.into()
+        })
+}
+
+fn json_error(err: JsonPayloadError) -> AppError {
+    match err {
+        JsonPayloadError::OverflowKnownLength { length, limit } => AppError::PayloadTooLarge(
+            format!("body is {} bytes, limit is {} bytes", length, limit),
+        ),
+        JsonPayloadError::Overflow { limit } => {
+            AppError::PayloadTooLarge(format!("body exceeds limit of {} bytes", limit))
+        }
+        JsonPayloadError::ContentType => {
+            AppError::BadRequest("Content-Type must be application/json".into())
+        }
+        JsonPayloadError::Deserialize(e) if e.is_data() => {
+            AppError::BadRequest(format!("invalid request body: {}", e))
+        }
+        JsonPayloadError::Deserialize(e) => AppError::BadRequest(format!("malformed JSON: {}", e)),
+        other => AppError::BadRequest(other.to_string()),
+    }
+}
+
+/// Handles shared by every worker: one live/anomaly broadcast hub, one ML
+/// client and one token manager per process. Build them once and pass the
+/// same value to `configure_with` in each worker's app factory; handles
+/// built per app would leave WebSocket clients on one worker blind to
+/// readings ingested on another.
+#[derive(Clone)]
+pub struct RouteDeps {
+    pub hub: WsHub,
+    /// Set when `ML_SERVICE_URL` is
+    pub ml_client: Option<Arc<MlClient>>,
+    pub jwt_manager: Arc<JwtManager>,
+    /// Lifetime of issued tokens per role
+    pub token_expiry: JwtExpiryConfig,
+    /// Whether unauthenticated `POST /ingest` is served
+    pub public_ingest: PublicIngest,
+    /// Whether the admin-only `/api/debug/*` endpoints are served
+    pub debug_endpoints: bool,
+    /// Patient dashboard cache and rate limit
+    pub dashboards: Arc<Dashboards>,
+    /// Bulk `$export` jobs and their files
+    pub exports: Arc<BulkExports>,
+    /// Deadlines of `/api` requests
+    pub request_timeout: Arc<RequestTimeout>,
+    /// The last `/readyz` report
+    pub readiness: Arc<ReadinessCache>,
+}
+
+/// `PUBLIC_INGEST` setting
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PublicIngest {
+    /// `/ingest` is not registered and answers 404; only the authenticated
+    /// `/api/ingest` takes readings
+    Disabled,
+    /// `/ingest` takes readings without a token, for the simulator and the
+    /// serial bridge
+    Demo,
+}
+
+impl PublicIngest {
+    /// Read `PUBLIC_INGEST` (`disabled` or `demo`, default `demo`). The older
+    /// `ALLOW_PUBLIC_INGEST=false` still disables it when `PUBLIC_INGEST` is
+    /// unset.
+    pub fn from_env() -> Result<Self, String> {
+        match std::env::var("PUBLIC_INGEST") {
+            Ok(value) => Self::parse(&value)
+                .ok_or_else(|| format!("PUBLIC_INGEST must be disabled or demo, got '{}'", value)),
+            Err(_) => Ok(match std::env::var("ALLOW_PUBLIC_INGEST") {
+                Ok(v) if v.eq_ignore_ascii_case("false") => Self::Disabled,
+                _ => Self::Demo,
+            }),
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "disabled" => Some(Self::Disabled),
+            "demo" => Some(Self::Demo),
+            _ => None,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        *self == Self::Demo
+    }
+}
+
+impl RouteDeps {
+    /// Defaults without reading the environment: the built-in anomaly
+    /// detector, no ML service, public ingest in demo mode, no debug
+    /// endpoints, default token lifetimes and request deadline, and tokens
+    /// checked by `jwt_manager`
+    pub fn new(jwt_manager: JwtManager) -> Self {
+        Self {
+            hub: WsHub::new(Some(AnomalyScorer::builtin())),
+            ml_client: None,
+            jwt_manager: Arc::new(jwt_manager),
+            token_expiry: JwtExpiryConfig::default(),
+            public_ingest: PublicIngest::Demo,
+            debug_endpoints: false,
+            dashboards: Arc::new(Dashboards::default()),
+            exports: Arc::new(BulkExports::default()),
+            request_timeout: Arc::new(RequestTimeout::default()),
+            readiness: Arc::new(ReadinessCache::default()),
+        }
+    }
+
+    /// Build from `ML_SERVICE_URL` and its credentials, `PUBLIC_INGEST`,
+    /// `DEBUG_ENDPOINTS`, the anomaly detector, WebSocket, JWT and request
+    /// timeout settings
+    pub fn from_env() -> Result<Self, String> {
+        let jwt_manager = Arc::new(JwtManager::from_env()?);
+        let ml_client = match std::env::var("ML_SERVICE_URL") {
+            Ok(url) => Some(Arc::new(MlClient::from_env(url, &jwt_manager)?)),
+            Err(_) => None,
+        };
+        Ok(Self {
+            hub: WsHub::from_env(AnomalyScorer::from_env(ml_client.clone())),
+            ml_client,
+            jwt_manager,
+            token_expiry: JwtExpiryConfig::from_env()?,
+            public_ingest: PublicIngest::from_env()?,
+            debug_endpoints: std::env::var("DEBUG_ENDPOINTS")
+                .is_ok_and(|v| v.eq_ignore_ascii_case("true")),
+            dashboards: Arc::new(Dashboards::from_env()),
+            exports: Arc::new(BulkExports::from_env()),
+            request_timeout: Arc::new(RequestTimeout::from_env()),
+            readiness: Arc::new(ReadinessCache::default()),
+        })
+    }
+}
+
+/// Register routes with handles built from the environment for this app
+/// alone. Fine for tests and single-worker setups; servers with several
+/// workers use `configure_with`. Panics if the JWT configuration or
+/// `PUBLIC_INGEST` is unusable.
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    let deps = RouteDeps::from_env().expect("invalid route configuration");
+    configure_with(cfg, &deps);
+}
+
+/// Register routes and attach the shared handles in `deps`
+pub fn configure_with(cfg: &mut web::ServiceConfig, deps: &RouteDeps) {
+    // Single readings are tiny; keep their limit tight to limit abuse
+    let ingest_limit = env_limit("INGEST_MAX_BODY_BYTES", DEFAULT_INGEST_MAX_BYTES);
+    let batch_limit = env_limit(
+        "INGEST_BATCH_MAX_BODY_BYTES",
+        DEFAULT_INGEST_BATCH_MAX_BYTES,
+    );
+    let packed_broadcast_every =
+        env_limit("PACKED_BROADCAST_EVERY", DEFAULT_PACKED_BROADCAST_EVERY);
 
-    if let Some(ref client) = ml_client {
+    if let Some(client) = &deps.ml_client {
         cfg.app_data(web::Data::new(client.clone()));
     }
+    cfg.app_data(web::Data::new(deps.jwt_manager.clone()));
+    cfg.app_data(web::Data::new(deps.token_expiry));
+    cfg.app_data(web::Data::from(deps.dashboards.clone()));
+    cfg.app_data(web::Data::from(deps.exports.clone()));
+    cfg.app_data(web::Data::from(deps.request_timeout.clone()));
+    cfg.app_data(web::Data::from(deps.readiness.clone()));
+
+    // Interactive API docs at /api/docs/, off unless API_DOCS_ENABLED=true.
+    // Like /api/openapi.json, registered ahead of the authenticated /api scope.
+    let docs_enabled = std::env::var("API_DOCS_ENABLED")
+        .map(|v| v.eq_ignore_ascii_case("true"))
+        .unwrap_or(false);
+    if docs_enabled {
+        cfg.service(
+            SwaggerUi::new("/api/docs/{_:.*}").config(SwaggerConfig::from("/api/openapi.json")),
+        );
+    }
+
+    // Public ingest for simulator/mock data. With PUBLIC_INGEST=disabled it
+    // is not registered at all, so /ingest answers 404.
+    if deps.public_ingest.is_enabled() {
+        cfg.service(
+            web::resource("/ingest")
+                .app_data(ingest_json_config(ingest_limit))
+                .wrap(from_fn(shed_ingest_load))
+                .wrap(from_fn(require_json))
+                .route(web::post().to(ingest_public)),
+        );
+    }
+
+    // Admin-only introspection, off unless DEBUG_ENDPOINTS=true. Registered
+    // ahead of the /api scope, which would otherwise answer 404.
+    if deps.debug_endpoints {
+        cfg.service(
+            web::resource("/api/debug/store")
+                .wrap(HttpAuthentication::bearer(jwt_validator))
+                .route(web::get().to(debug_store)),
+        );
+    }
 
     // JWT authentication middleware
     let auth_middleware = HttpAuthentication::bearer(jwt_validator);
 
-    cfg.app_data(web::Data::new(WsHub { tx }))
+    cfg.app_data(web::Data::new(deps.hub.clone()))
+        .app_data(json_config(DEFAULT_JSON_MAX_BYTES))
+        // Challenge sent when a protected endpoint is called without a token
+        .app_data(bearer::Config::default().realm(AUTH_REALM))
         // Public endpoints (no auth required)
         .route("/healthz", web::get().to(healthz))
-        .route("/auth/login", web::post().to(login))
-        .route("/auth/token", web::post().to(generate_device_token))
-        .route("/ws/live", web::get().to(ws_live)) // WebSocket endpoint (public for browser compatibility)
-        .route("/ingest", web::post().to(ingest_public)) // Public ingest for simulator/mock data
+        .route("/readyz", web::get().to(readyz))
+        .route("/version", web::get().to(version))
+        .route("/metrics", web::get().to(metrics_endpoint))
+        // Public routes taking JSON bodies
+        .service(
+            web::resource("/auth/login")
+                .wrap(from_fn(require_json))
+                .route(web::post().to(login)),
+        )
+        .service(
+            web::resource("/auth/token")
+                .wrap(from_fn(require_json))
+                .route(web::post().to(generate_device_token)),
+        )
+        .service(
+            web::resource("/auth/device/challenge")
+                .wrap(from_fn(require_json))
+                .route(web::post().to(request_device_challenge)),
+        )
+        .service(
+            web::resource("/auth/device/token")
+                .wrap(from_fn(require_json))
+                .route(web::post().to(answer_device_challenge)),
+        )
+        .service(
+            web::resource("/auth/iot/challenge")
+                .wrap(from_fn(require_json))
+                .route(web::post().to(request_iot_challenge)),
+        )
+        .service(
+            web::resource("/auth/iot/token")
+                .wrap(from_fn(require_json))
+                .route(web::post().to(answer_iot_challenge)),
+        )
+        .service(
+            web::resource("/auth/whoami")
+                .wrap(HttpAuthentication::bearer(jwt_validator))
+                .route(web::get().to(whoami)),
+        )
+        // WebSocket endpoint; takes its token as ?access_token= from browsers
+        .route("/ws/live", web::get().to(ws_live))
+        .route("/ws/anomalies", web::get().to(ws_anomalies))
+        .route("/ws/alerts", web::get().to(ws_alerts))
+        // Terminology is public, like the codes it lists
+        .route(
+            "/fhir/ValueSet/soundsense-signal-codes",
+            web::get().to(get_signal_code_value_set),
+        )
+        .route(
+            "/fhir/ValueSet/soundsense-signal-codes/$validate-code",
+            web::get().to(validate_signal_code),
+        )
+        .route("/api/openapi.json", web::get().to(openapi_json))
+        // Capability probes; other methods fall through to the /api scope
+        .service(allowed_methods(
+            "/api/fhir/Observation",
+            "GET, HEAD, OPTIONS",
+        ))
+        .service(allowed_methods(
+            "/api/fhir/Observation/{id}",
+            "PUT, DELETE, OPTIONS",
+        ))
         // Protected endpoints (JWT required)
         .service(
             web::scope("/api")
+                // Inside the token check, so unauthenticated clients learn
+                // nothing about the media types
+                .wrap(from_fn(require_json))
                 .wrap(auth_middleware)
-                .route("/ingest", web::post().to(ingest))
-                .route("/fhir/Observation", web::get().to(get_observations))
+                // Outermost, so token checks count towards the deadline too
+                .wrap(from_fn(enforce_request_timeout))
+                .service(
+                    web::resource("/ingest")
+                        .app_data(ingest_json_config(ingest_limit))
+                        .wrap(from_fn(shed_ingest_load))
+                        .route(web::post().to(ingest)),
+                )
+                .service(
+                    web::resource("/ingest/batch")
+                        .app_data(ingest_json_config(batch_limit))
+                        .wrap(from_fn(shed_ingest_load))
+                        .route(web::post().to(ingest_batch)),
+                )
+                .service(
+                    web::resource("/ingest/packed")
+                        .app_data(ingest_json_config(batch_limit))
+                        .app_data(web::Data::new(PackedBroadcastEvery(packed_broadcast_every)))
+                        .wrap(from_fn(shed_ingest_load))
+                        .route(web::post().to(ingest_packed)),
+                )
+                .service(
+                    // Read line by line, so no body limit; lines are capped
+                    // by STREAM_INGEST_MAX_LINE_BYTES and STREAM_INGEST_MAX_LINES
+                    web::resource("/ingest/stream")
+                        .wrap(from_fn(shed_ingest_load))
+                        .route(web::post().to(ingest_stream)),
+                )
+                .service(
+                    web::resource("/ingest/octave-bands")
+                        .app_data(ingest_json_config(ingest_limit))
+                        .wrap(from_fn(shed_ingest_load))
+                        .route(web::post().to(ingest_octave_bands)),
+                )
+                .service(
+                    // FFT frames carry thousands of bins, so they get the batch limit
+                    web::resource("/ingest/spectrogram")
+                        .app_data(ingest_json_config(batch_limit))
+                        .wrap(from_fn(shed_ingest_load))
+                        .route(web::post().to(ingest_spectrogram)),
+                )
+                .route("/auth/password", web::post().to(change_password))
+                .route("/auth/sessions", web::get().to(list_sessions))
+                .route("/auth/sessions/{jti}", web::delete().to(revoke_session))
+                .service(
+                    web::resource("/fhir/Observation")
+                        .route(web::get().to(get_observations))
+                        // Same response; the server drops the body and keeps
+                        // Content-Length
+                        .route(web::head().to(get_observations)),
+                )
+                // Ahead of /fhir/Observation/{id}
+                .route(
+                    "/fhir/Observation/$suggest",
+                    web::get().to(suggest_observation_tags),
+                )
+                .route(
+                    "/fhir/Observation/stream",
+                    web::get().to(stream_observations),
+                )
+                .route(
+                    "/fhir/Observation/$export",
+                    web::get().to(start_observation_export),
+                )
+                .route("/fhir/Observation/{id}", web::put().to(amend_observation))
+                .route(
+                    "/fhir/Observation/{id}",
+                    web::delete().to(delete_observation),
+                )
+                .route("/fhir/MeasureReport", web::get().to(get_measure_report))
+                .route("/fhir/Patient/{id}", web::get().to(get_fhir_patient))
+                .route("/fhir/Device/{id}", web::get().to(get_fhir_device))
+                .route("/fhir/$export", web::post().to(start_bulk_export))
+                .service(
+                    web::resource("/fhir/$export/{job_id}")
+                        .route(web::get().to(bulk_export_status))
+                        .route(web::delete().to(cancel_bulk_export)),
+                )
+                .route(
+                    "/fhir/$export/{job_id}/{file}",
+                    web::get().to(bulk_export_file),
+                )
+                .route(
+                    "/analysis/octave-spectrum",
+                    web::get().to(get_octave_spectrum),
+                )
+                .route("/analysis/gaps", web::get().to(get_reading_gaps))
+                .route("/analysis/spectrogram", web::get().to(get_spectrogram))
+                .route("/analysis/noise-map", web::get().to(get_noise_map))
+                .route(
+                    "/observations/hourly-pattern",
+                    web::get().to(get_hourly_pattern),
+                )
+                .route(
+                    "/analysis/recommendation",
+                    web::get().to(get_protection_recommendation),
+                )
+                .route("/analysis/a-weight", web::post().to(a_weight_bands))
                 // ML endpoints
                 .route("/ml/predict", web::get().to(ml_predict))
                 .route("/ml/analysis", web::get().to(ml_analysis))
                 .route("/ml/train", web::post().to(ml_train))
-                .route("/ml/health", web::get().to(ml_health)),
+                .route("/ml/train", web::get().to(list_training_jobs))
+                .route("/ml/train/{job_id}", web::get().to(get_training_job))
+                .route("/ml/health", web::get().to(ml_health))
+                // Admin endpoints
+                .route("/admin/cors", web::get().to(list_cors_origins))
+                .route("/admin/cors", web::post().to(add_cors_origin))
+                .route("/admin/dlq/stats", web::get().to(dlq_stats))
+                .route("/admin/log-stats", web::get().to(log_stats))
+                .route(
+                    "/admin/compliance-report/{year}/{month}",
+                    web::get().to(get_compliance_report),
+                )
+                .route("/audit/patient/{id}", web::get().to(get_patient_audit_log))
+                .route("/serial/status", web::get().to(serial_status))
+                .route("/admin/snapshot", web::post().to(take_snapshot))
+                .route("/admin/snapshot/meta", web::get().to(snapshot_meta))
+                .route("/admin/iot-devices", web::post().to(provision_iot_device))
+                .route(
+                    "/admin/hearing-protectors",
+                    web::get().to(list_hearing_protectors),
+                )
+                .route(
+                    "/admin/hearing-protectors",
+                    web::post().to(add_hearing_protector),
+                )
+                .route(
+                    "/admin/hearing-protectors/{id}",
+                    web::put().to(update_hearing_protector),
+                )
+                .route(
+                    "/admin/hearing-protectors/{id}",
+                    web::delete().to(delete_hearing_protector),
+                )
+                // Alert suppression windows; `active` goes before `{id}`
+                .route(
+                    "/alert-suppressions",
+                    web::get().to(list_alert_suppressions),
+                )
+                .route(
+                    "/alert-suppressions",
+                    web::post().to(create_alert_suppression),
+                )
+                .route(
+                    "/alert-suppressions/active",
+                    web::get().to(active_alert_suppressions),
+                )
+                .route(
+                    "/alert-suppressions/{id}",
+                    web::get().to(get_alert_suppression),
+                )
+                .route(
+                    "/alert-suppressions/{id}",
+                    web::put().to(update_alert_suppression),
+                )
+                .route(
+                    "/alert-suppressions/{id}",
+                    web::delete().to(delete_alert_suppression),
+                )
+                // Patient registry
+                .route("/patients/{id}", web::get().to(get_patient))
+                .route("/patients/{id}/consent", web::put().to(set_patient_consent))
+                .route(
+                    "/patients/{id}/observations",
+                    web::delete().to(erase_patient_observations),
+                )
+                .route("/patients/{id}/rollups", web::get().to(get_daily_rollups))
+                .route("/patients/{id}/gauge", web::get().to(get_patient_gauge))
+                // Patient reports; the PDF route goes first, as
+                // `{patient_id}` alone would also match `p1.pdf`
+                .route(
+                    "/reports/patient-summary/{patient_id}.pdf",
+                    web::get().to(get_patient_summary_pdf),
+                )
+                .route(
+                    "/reports/patient-summary/{patient_id}",
+                    web::get().to(get_patient_summary),
+                )
+                .route(
+                    "/dashboard/patient/{patient_id}",
+                    web::get().to(get_patient_dashboard),
+                )
+                // Device monitoring
+                .route("/devices/{id}", web::put().to(set_device_settings))
+                .route("/devices/{id}/stats", web::get().to(get_device_stats))
+                .route(
+                    "/devices/{id}/calibration-history",
+                    web::get().to(get_calibration_history),
+                )
+                .route(
+                    "/calibrate/device/{id}/verify",
+                    web::post().to(verify_device_calibration),
+                )
+                .route(
+                    "/devices/{id}/secret",
+                    web::post().to(provision_device_secret),
+                )
+                // User administration
+                .route("/users", web::get().to(list_users))
+                .route("/users", web::post().to(create_user))
+                .route(
+                    "/users/{id}/reset-password",
+                    web::post().to(reset_user_password),
+                )
+                .route(
+                    "/admin/users/{id}/patients",
+                    web::post().to(add_user_patient),
+                )
+                .route(
+                    "/admin/oidc-subjects/{sub}/patients",
+                    web::post().to(add_subject_patient),
+                ),
+        );
+}
+
+/// OPTIONS responder for `path` listing `methods` in the `Allow` header.
+/// Public, like the OpenAPI document that describes the same thing.
+fn allowed_methods(path: &str, methods: &'static str) -> actix_web::Resource {
+    web::resource(path)
+        .guard(guard::Options())
+        .to(move || async move {
+            HttpResponse::NoContent()
+                .insert_header((header::ALLOW, methods))
+                .finish()
+        })
+}
+
+/// OpenAPI description of the public API, derived from the handler
+/// annotations and the request/response types they use
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "SoundSense API"),
+    paths(
+        healthz,
+        readyz,
+        version,
+        serial_status,
+        login,
+        generate_device_token,
+        request_device_challenge,
+        answer_device_challenge,
+        request_iot_challenge,
+        answer_iot_challenge,
+        whoami,
+        ingest_public,
+        ingest,
+        ingest_batch,
+        ingest_packed,
+        ingest_stream,
+        ingest_octave_bands,
+        ingest_spectrogram,
+        get_observations,
+        get_fhir_patient,
+        get_fhir_device,
+        stream_observations,
+        suggest_observation_tags,
+        amend_observation,
+        delete_observation,
+        get_measure_report,
+        get_signal_code_value_set,
+        validate_signal_code,
+        start_bulk_export,
+        start_observation_export,
+        bulk_export_status,
+        cancel_bulk_export,
+        bulk_export_file,
+        get_octave_spectrum,
+        get_spectrogram,
+        get_reading_gaps,
+        get_noise_map,
+        get_hourly_pattern,
+        get_protection_recommendation,
+        a_weight_bands,
+        get_patient,
+        set_patient_consent,
+        erase_patient_observations,
+        get_daily_rollups,
+        get_patient_gauge,
+        get_patient_summary,
+        get_patient_summary_pdf,
+        get_patient_dashboard,
+        get_device_stats,
+        verify_device_calibration,
+        provision_device_secret,
+        provision_iot_device,
+        set_device_settings,
+        get_calibration_history,
+        get_compliance_report,
+        get_patient_audit_log,
+        list_alert_suppressions,
+        active_alert_suppressions,
+        create_alert_suppression,
+        get_alert_suppression,
+        update_alert_suppression,
+        delete_alert_suppression
+    ),
+    components(schemas(SensorReading, ErrBody)),
+    modifiers(&BearerAuth),
+    tags(
+        (name = "system", description = "Health checks and build information"),
+        (name = "auth", description = "Token issuance"),
+        (name = "ingest", description = "Sensor reading ingest"),
+        (name = "fhir", description = "FHIR Observation queries"),
+        (name = "analysis", description = "Aggregated sound analysis"),
+        (name = "patients", description = "Patient registry and consent"),
+        (name = "reports", description = "Patient-facing exposure summaries"),
+        (name = "devices", description = "Device ingest monitoring and calibration"),
+        (name = "alerts", description = "Alert suppression windows"),
+    )
+)]
+pub struct ApiDoc;
+
+/// Registers the `bearer_auth` security scheme used by protected paths
+struct BearerAuth;
+
+impl Modify for BearerAuth {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "bearer_auth",
+            SecurityScheme::Http(
+                HttpBuilder::new()
+                    .scheme(HttpAuthScheme::Bearer)
+                    .bearer_format("JWT")
+                    .build(),
+            ),
         );
+    }
+}
+
+async fn openapi_json() -> HttpResponse {
+    HttpResponse::Ok().json(ApiDoc::openapi())
+}
+
+/// Extract claims and require the admin role
+fn require_admin(req: &HttpRequest) -> Result<Claims, AppError> {
+    require_permission(req, Permission::Admin)
+}
+
+/// Extract claims and require `permission`. Read-only tokens are refused
+/// with 403; other tokens lacking admin rights keep getting 401 from admin
+/// endpoints, as they always have.
+fn require_permission(req: &HttpRequest, permission: Permission) -> Result<Claims, AppError> {
+    let claims = get_claims_from_request(req).ok_or(AppError::Unauthorized)?;
+
+    if !claims.can(permission) {
+        tracing::warn!(
+            "User {} with role {} attempted to access {} without {:?}",
+            claims.sub,
+            claims.role,
+            req.path(),
+            permission
+        );
+        if permission == Permission::Admin && !claims.is_read_only() {
+            return Err(AppError::Unauthorized);
+        }
+        return Err(AppError::Forbidden(format!(
+            "{} tokens cannot {}",
+            claims.role,
+            permission.describe()
+        )));
+    }
+
+    Ok(claims)
+}
+
+/// Require access to `patient_id`'s readings, recording the attempt in the
+/// audit log. Devices only report readings; accounts only see the patients
+/// their claims permit (`Claims::may_read_patient`).
+async fn authorize_patient_access(
+    req: &HttpRequest,
+    state: &web::Data<Arc<Mutex<AppState>>>,
+    claims: &Claims,
+    patient_id: &str,
+    resource_type: &str,
+) -> Result<(), AppError> {
+    let users = state.lock().await.users();
+    let audit = AuditLogEntry::new(AuditAction::Read, resource_type.to_string())
+        .with_user(claims.sub.clone(), claims.role.clone())
+        .with_patient_id(patient_id.to_string())
+        .with_request_context(None, None, Some(req.path().to_string()));
+
+    if !claims.can(Permission::ReadData) {
+        tracing::warn!(
+            "Device {} attempted to read data of patient {}",
+            claims.sub,
+            patient_id
+        );
+        users
+            .audit(
+                AuditLogEntry {
+                    action: AuditAction::AccessDenied,
+                    ..audit
+                }
+                .with_status_code(403)
+                .with_error("devices cannot read patient data".to_string()),
+            )
+            .await;
+        return Err(AppError::Forbidden(
+            "devices cannot read patient data".into(),
+        ));
+    }
+
+    if !claims.may_read_patient(patient_id) {
+        tracing::warn!(
+            "User {} attempted to read data of unpermitted patient {}",
+            claims.sub,
+            patient_id
+        );
+        users
+            .audit(
+                AuditLogEntry {
+                    action: AuditAction::AccessDenied,
+                    ..audit
+                }
+                .with_status_code(403)
+                .with_error("patient not permitted".to_string()),
+            )
+            .await;
+        return Err(AppError::Forbidden(format!(
+            "no access to patient {}",
+            patient_id
+        )));
+    }
+
+    users.audit(audit.with_status_code(200)).await;
+    Ok(())
 }
 
+#[utoipa::path(
+    get,
+    path = "/healthz",
+    tag = "system",
+    responses(
+        (status = 200, description = "Service, database, ML service and serial link status", body = serde_json::Value),
+        (status = 503, description = "Database unavailable", body = ErrBody),
+    )
+)]
 async fn healthz(
     state: web::Data<Arc<Mutex<AppState>>>,
     ml_client: Option<web::Data<Arc<MlClient>>>,
+    hub: Option<web::Data<WsHub>>,
+    serial: Option<web::Data<RwLock<SerialStatus>>>,
 ) -> Result<HttpResponse, AppError> {
     // Check database connection if configured
     let st = state.lock().await;
@@ -61,6 +845,32 @@ async fn healthz(
         "authentication": "JWT enabled"
     });
 
+    // Readings waiting to be inserted after a database outage
+    if let Some(dlq) = st.dlq() {
+        response["write_ahead_buffer"] = serde_json::json!({
+            "pending": dlq.pending().await
+        });
+    }
+
+    if let Some(hub) = hub {
+        response["websocket"] = serde_json::json!({
+            "live_subscribers": hub.subscriber_count(),
+            "anomaly_subscribers": hub.anomaly_subscriber_count(),
+            "alert_subscribers": hub.alert_subscriber_count(),
+            "dropped_messages": hub.dropped_messages()
+        });
+    }
+
+    // Only when a serial port is configured; details at /api/serial/status
+    if let Some(serial) = serial {
+        let serial = serial.read().unwrap_or_else(|e| e.into_inner());
+        response["serial"] = serde_json::json!({
+            "port": serial.port,
+            "connected": serial.connected,
+            "last_reading_at": serial.last_reading_at
+        });
+    }
+
     // Check ML service if configured
     if let Some(client) = ml_client {
         match client.health_check().await {
@@ -77,7 +887,7 @@ async fn healthz(
                 response["ml_service"] = serde_json::json!({
                     "status": "error",
                     "connected": false,
-                    "error": e
+                    "error": e.to_string()
                 });
             }
         }
@@ -91,53 +901,224 @@ async fn healthz(
     Ok(HttpResponse::Ok().json(response))
 }
 
+/// The checks of `--check` against the running service: the database, the
+/// ML service, the signing keys and the directories files are written to.
+/// The serial port is held by the serial reader; see `/api/serial/status`.
+/// The report is reused for a few seconds, and each check's `detail` is
+/// only given to callers with a valid token.
+#[utoipa::path(
+    get,
+    path = "/readyz",
+    tag = "system",
+    responses(
+        (status = 200, description = "Every configured dependency passed its check", body = CheckReport),
+        (status = 503, description = "A dependency failed its check", body = CheckReport),
+    )
+)]
+async fn readyz(
+    req: HttpRequest,
+    cache: web::Data<ReadinessCache>,
+    state: web::Data<Arc<Mutex<AppState>>>,
+    ml_client: Option<web::Data<Arc<MlClient>>>,
+    jwt_manager: web::Data<Arc<JwtManager>>,
+    exports: web::Data<BulkExports>,
+    snapshots: Option<web::Data<SnapshotManager>>,
+) -> HttpResponse {
+    let report = cache
+        .get_or_check(async {
+            let (db, dlq) = {
+                let st = state.lock().await;
+                (st.database(), st.dlq())
+            };
+
+            let mut checks = vec![
+                self_check::check_database(db.as_ref()).await,
+                self_check::check_ml_service(
+                    ml_client.as_ref().map(|client| client.get_ref().as_ref()),
+                )
+                .await,
+                self_check::check_jwt(&jwt_manager),
+            ];
+            checks.extend(self_check::check_directories(
+                exports.dir(),
+                dlq.as_ref().map(|dlq| dlq.path()),
+                snapshots.as_ref().map(|s| s.path()),
+            ));
+            CheckReport::new(checks)
+        })
+        .await;
+    let report = match optional_claims(&req).await {
+        Some(_) => report,
+        None => report.redacted(),
+    };
+    if report.ok {
+        HttpResponse::Ok().json(report)
+    } else {
+        HttpResponse::ServiceUnavailable().json(report)
+    }
+}
+
+/// The running build
+#[derive(serde::Serialize, ToSchema)]
+struct VersionInfo {
+    version: &'static str,
+    /// `unknown` if built outside a git checkout
+    git_commit: &'static str,
+    built_at: Option<chrono::DateTime<chrono::Utc>>,
+    fhir_version: &'static str,
+    database_configured: bool,
+    ml_configured: bool,
+}
+
+/// Which build is running. Unlike `/healthz` it contacts neither the
+/// database nor the ML service.
+#[utoipa::path(
+    get,
+    path = "/version",
+    tag = "system",
+    responses(
+        (status = 200, description = "Build and configuration", body = VersionInfo),
+    )
+)]
+async fn version(
+    state: web::Data<Arc<Mutex<AppState>>>,
+    ml_client: Option<web::Data<Arc<MlClient>>>,
+) -> HttpResponse {
+    let database_configured = state.lock().await.has_database();
+    HttpResponse::Ok().json(VersionInfo {
+        version: build_info::VERSION,
+        git_commit: build_info::GIT_COMMIT,
+        built_at: build_info::built_at(),
+        fhir_version: FHIR_VERSION,
+        database_configured,
+        ml_configured: ml_client.is_some(),
+    })
+}
+
+/// Prometheus scrape endpoint
+async fn metrics_endpoint(
+    queue: Option<web::Data<IngestQueue>>,
+    limiter: Option<web::Data<IngestLimiter>>,
+    hub: Option<web::Data<WsHub>>,
+    request_timeout: Option<web::Data<RequestTimeout>>,
+) -> HttpResponse {
+    let mut exp = Exposition::new();
+    metrics::render_counters(&mut exp);
+    if let Some(queue) = queue {
+        queue.render_metrics(&mut exp);
+    }
+    if let Some(limiter) = limiter {
+        limiter.render_metrics(&mut exp);
+    }
+    if let Some(hub) = hub {
+        hub.render_metrics(&mut exp);
+    }
+    if let Some(request_timeout) = request_timeout {
+        request_timeout.render_metrics(&mut exp);
+    }
+
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(exp.finish())
+}
+
 // Authentication endpoints
 
-#[derive(serde::Deserialize)]
+#[derive(serde::Deserialize, ToSchema)]
 struct LoginRequest {
     username: String,
     password: String,
 }
 
-#[derive(serde::Serialize)]
+#[derive(serde::Serialize, ToSchema)]
 struct LoginResponse {
     token: String,
     expires_in: i64,
     role: String,
+    must_change_password: bool,
 }
 
-async fn login(body: web::Json<LoginRequest>) -> Result<HttpResponse, AppError> {
-    // In production, validate against database with hashed passwords
-    // For now, using environment variable for demo
-    let valid_username = std::env::var("AUTH_USERNAME").unwrap_or_else(|_| "admin".to_string());
-    let valid_password = std::env::var("AUTH_PASSWORD").unwrap_or_else(|_| "admin123".to_string());
+#[utoipa::path(
+    post,
+    path = "/auth/login",
+    tag = "auth",
+    request_body = LoginRequest,
+    responses(
+        (status = 200, description = "Issued access token", body = LoginResponse),
+        (status = 401, description = "Invalid credentials", body = ErrBody),
+    )
+)]
+async fn login(
+    state: web::Data<Arc<Mutex<AppState>>>,
+    jwt_manager: web::Data<Arc<JwtManager>>,
+    token_expiry: web::Data<JwtExpiryConfig>,
+    sessions: Option<web::Data<Arc<SessionStore>>>,
+    body: web::Json<LoginRequest>,
+) -> Result<HttpResponse, AppError> {
+    let users = state.lock().await.users();
 
-    if body.username != valid_username || body.password != valid_password {
-        tracing::warn!("Failed login attempt for user: {}", body.username);
-        return Err(AppError::Unauthorized);
-    }
+    let user = match users.authenticate(&body.username, &body.password).await? {
+        Some(user) => user,
+        None => {
+            tracing::warn!("Failed login attempt for user: {}", body.username);
+            // Counted in the monthly compliance reports
+            users
+                .audit(
+                    AuditLogEntry {
+                        user_id: Some(body.username.chars().take(255).collect()),
+                        ..AuditLogEntry::new(AuditAction::AccessDenied, "Session".to_string())
+                    }
+                    .with_request_context(None, None, Some("/auth/login".to_string()))
+                    .with_status_code(401)
+                    .with_error("invalid credentials".to_string()),
+                )
+                .await;
+            return Err(AppError::Unauthorized);
+        }
+    };
 
-    // Generate JWT token
-    let jwt_secret = std::env::var("JWT_SECRET")
-        .unwrap_or_else(|_| "default_secret_change_in_production".to_string());
+    // Upgrade hashes made with older parameters while we have the plaintext
+    if users.needs_rehash(&user) {
+        if let Err(e) = users
+            .set_password(user.id, &body.password, user.must_change_password)
+            .await
+        {
+            tracing::warn!(error = ?e, "Failed to rehash password for {}", user.username);
+        }
+    }
 
-    let jwt_manager = JwtManager::new(jwt_secret);
-    let expires_in_hours = 24;
+    // Users and viewers only see the patients assigned to them; the list is
+    // fixed for the lifetime of the token
+    let permitted_patients = if matches!(user.role.as_str(), "user" | "viewer") {
+        Some(users.permitted_patients(&user.id.to_string()).await?)
+    } else {
+        None
+    };
 
-    let claims = Claims::new(
-        body.username.clone(),
-        "admin".to_string(),
-        None,
-        expires_in_hours,
-    );
+    // After an admin reset the user only gets a short-lived token that can
+    // change the password, nothing else
+    let (claims, expires_in_hours) = if user.must_change_password {
+        let claims = Claims::new(user.username.clone(), user.role.clone(), None, 1)
+            .with_scope(SCOPE_PASSWORD_CHANGE);
+        (claims, 1)
+    } else {
+        let hours = token_expiry.for_role(&user.role);
+        let mut claims = Claims::new(user.username.clone(), user.role.clone(), None, hours);
+        if let Some(patients) = permitted_patients {
+            claims = claims.with_permitted_patients(patients);
+        }
+        (claims, hours)
+    };
 
-    match jwt_manager.generate_token(claims) {
+    match jwt_manager.generate_token(claims.clone()) {
         Ok(token) => {
-            tracing::info!("User {} logged in successfully", body.username);
+            record_session(sessions.as_ref().map(|s| s.get_ref()), &claims).await;
+            tracing::info!("User {} logged in successfully", user.username);
             Ok(HttpResponse::Ok().json(LoginResponse {
                 token,
                 expires_in: expires_in_hours * 3600, // in seconds
-                role: "admin".to_string(),
+                role: user.role,
+                must_change_password: user.must_change_password,
             }))
         }
         Err(e) => {
@@ -147,206 +1128,3728 @@ async fn login(body: web::Json<LoginRequest>) -> Result<HttpResponse, AppError>
     }
 }
 
+/// Add a freshly issued token to the session inventory, if one is configured.
+/// A failure here must not block the login itself.
+async fn record_session(sessions: Option<&Arc<SessionStore>>, claims: &Claims) {
+    if let Some(sessions) = sessions {
+        if let Err(e) = sessions.record(claims).await {
+            tracing::warn!(error = ?e, "Failed to record issued token for {}", claims.sub);
+        }
+    }
+}
+
 #[derive(serde::Deserialize)]
-struct DeviceTokenRequest {
-    device_id: String,
-    secret: String, // Admin secret to generate device tokens
+struct SessionQuery {
+    user: Option<String>,
 }
 
-async fn generate_device_token(
-    body: web::Json<DeviceTokenRequest>,
+/// List active tokens, optionally for one user
+async fn list_sessions(
+    req: HttpRequest,
+    sessions: Option<web::Data<Arc<SessionStore>>>,
+    q: web::Query<SessionQuery>,
 ) -> Result<HttpResponse, AppError> {
-    // Verify admin secret
-    let admin_secret =
-        std::env::var("DEVICE_TOKEN_SECRET").unwrap_or_else(|_| "change_this_secret".to_string());
+    require_admin(&req)?;
+    let sessions = sessions
+        .ok_or_else(|| AppError::ServiceUnavailable("session inventory not configured".into()))?;
 
-    if body.secret != admin_secret {
-        tracing::warn!(
-            "Invalid device token generation attempt for device: {}",
-            body.device_id
-        );
-        return Err(AppError::Unauthorized);
-    }
+    Ok(HttpResponse::Ok().json(sessions.list(q.user.as_deref()).await?))
+}
 
-    // Generate JWT token for device
-    let jwt_secret = std::env::var("JWT_SECRET")
-        .unwrap_or_else(|_| "default_secret_change_in_production".to_string());
+/// Revoke a token; it is rejected from the next request on
+async fn revoke_session(
+    req: HttpRequest,
+    state: web::Data<Arc<Mutex<AppState>>>,
+    sessions: Option<web::Data<Arc<SessionStore>>>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, AppError> {
+    let claims = require_admin(&req)?;
+    let sessions = sessions
+        .ok_or_else(|| AppError::ServiceUnavailable("session inventory not configured".into()))?;
+    let jti = path.into_inner();
 
-    let jwt_manager = JwtManager::new(jwt_secret);
-    let expires_in_hours = 8760; // 1 year for devices
+    if !sessions.revoke(&jti).await? {
+        return Err(AppError::NotFound(format!("session {}", jti)));
+    }
 
-    let claims = Claims::new(
-        format!("device_{}", body.device_id),
-        "device".to_string(),
-        Some(body.device_id.clone()),
-        expires_in_hours,
-    );
+    state
+        .lock()
+        .await
+        .users()
+        .audit(
+            AuditLogEntry::new(AuditAction::Delete, "Session".to_string())
+                .with_user(claims.sub.clone(), claims.role)
+                .with_resource_id(jti.clone())
+                .with_request_context(None, None, Some(req.path().to_string()))
+                .with_status_code(204),
+        )
+        .await;
 
-    match jwt_manager.generate_token(claims) {
-        Ok(token) => {
-            tracing::info!("Generated token for device: {}", body.device_id);
-            Ok(HttpResponse::Ok().json(LoginResponse {
-                token,
-                expires_in: expires_in_hours * 3600,
-                role: "device".to_string(),
-            }))
-        }
-        Err(e) => {
-            tracing::error!("Failed to generate device token: {}", e);
-            Err(AppError::Internal)
-        }
-    }
+    tracing::info!("Admin {} revoked session {}", claims.sub, jti);
+    Ok(HttpResponse::NoContent().finish())
 }
 
-// Protected endpoints
+#[derive(serde::Deserialize)]
+struct ChangePasswordRequest {
+    current_password: String,
+    new_password: String,
+}
 
-// Public ingest endpoint (no auth required - for simulator and mock data)
-async fn ingest_public(
+/// Change the caller's own password (also completes a forced rotation)
+async fn change_password(
+    req: HttpRequest,
     state: web::Data<Arc<Mutex<AppState>>>,
-    hub: web::Data<WsHub>,
-    payload: web::Json<SensorReading>,
+    body: web::Json<ChangePasswordRequest>,
 ) -> Result<HttpResponse, AppError> {
-    tracing::debug!("Public ingest request (no auth)");
+    let claims = get_claims_from_request(&req).ok_or(AppError::Unauthorized)?;
+    let users = state.lock().await.users();
 
-    // Validate
-    let reading = payload.into_inner();
-    reading.validate().map_err(AppError::BadRequest)?;
+    let user = users
+        .find_by_username(&claims.sub)
+        .await?
+        .ok_or(AppError::Unauthorized)?;
 
-    // Convert to FHIR Observation
-    let obs = FhirObservation::from_reading(reading.clone());
+    let audit = AuditLogEntry::new(AuditAction::Update, "UserPassword".to_string())
+        .with_user(claims.sub.clone(), claims.role.clone())
+        .with_resource_id(user.id.to_string())
+        .with_request_context(None, None, Some(req.path().to_string()));
 
-    // Validate FHIR schema compliance
-    obs.validate().map_err(AppError::BadRequest)?;
+    if !users.verify_password(&user, &body.current_password).await {
+        tracing::warn!(
+            "Password change with wrong current password for {}",
+            user.username
+        );
+        users
+            .audit(
+                AuditLogEntry {
+                    action: AuditAction::AccessDenied,
+                    ..audit
+                }
+                .with_status_code(401)
+                .with_error("current password did not match".to_string()),
+            )
+            .await;
+        return Err(AppError::Unauthorized);
+    }
 
-    // Store reading (now with database support)
-    {
-        let mut st = state.lock().await;
-        st.push(reading, None).await?;
+    validate_password_strength(&body.new_password, &user.username).map_err(AppError::BadRequest)?;
+    if body.new_password == body.current_password {
+        return Err(AppError::BadRequest(
+            "new password must differ from the current one".into(),
+        ));
     }
 
-    // Push to WebSocket subscribers
-    let _ = hub.tx.send(obs.clone());
+    users
+        .set_password(user.id, &body.new_password, false)
+        .await?;
+    users
+        .audit(
+            audit
+                .with_status_code(200)
+                .with_metadata(serde_json::json!({ "event": "password_changed" })),
+        )
+        .await;
 
-    Ok(HttpResponse::Ok().json(obs))
+    tracing::info!("User {} changed their password", user.username);
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "status": "password_changed" })))
 }
 
-// Protected ingest endpoint (JWT required)
-async fn ingest(
+async fn list_users(
     req: HttpRequest,
     state: web::Data<Arc<Mutex<AppState>>>,
-    hub: web::Data<WsHub>,
-    payload: web::Json<SensorReading>,
 ) -> Result<HttpResponse, AppError> {
-    // Get authenticated user from JWT claims
-    let claims = get_claims_from_request(&req).ok_or_else(|| AppError::Unauthorized)?;
-
-    tracing::debug!(
-        "Ingest request from user: {}, role: {}",
-        claims.sub,
-        claims.role
-    );
-
-    // Validate
-    let reading = payload.into_inner();
-    reading.validate().map_err(AppError::BadRequest)?;
+    require_admin(&req)?;
+    let users = state.lock().await.users();
+    Ok(HttpResponse::Ok().json(users.list().await?))
+}
 
-    // Convert to FHIR Observation
-    let obs = FhirObservation::from_reading(reading.clone());
+#[derive(serde::Deserialize)]
+struct CreateUserRequest {
+    username: String,
+    password: String,
+    role: String,
+}
 
-    // Validate FHIR schema compliance
-    obs.validate().map_err(AppError::BadRequest)?;
+/// Create an account; the initial password must be rotated at first login
+async fn create_user(
+    req: HttpRequest,
+    state: web::Data<Arc<Mutex<AppState>>>,
+    body: web::Json<CreateUserRequest>,
+) -> Result<HttpResponse, AppError> {
+    let claims = require_admin(&req)?;
 
-    // Store reading (now with database support and audit logging)
-    {
-        let mut st = state.lock().await;
-        st.push(reading.clone(), Some(&claims)).await?;
+    let username = body.username.trim();
+    if username.is_empty() {
+        return Err(AppError::BadRequest("username required".into()));
     }
+    if !matches!(body.role.as_str(), "admin" | "user" | "viewer") {
+        return Err(AppError::BadRequest(
+            "role must be 'admin', 'user' or 'viewer'".into(),
+        ));
+    }
+    validate_password_strength(&body.password, username).map_err(AppError::BadRequest)?;
 
-    // Push to WebSocket subscribers
-    let _ = hub.tx.send(obs.clone());
+    let users = state.lock().await.users();
+    let user: User = users
+        .create(username, &body.password, &body.role, true)
+        .await?;
 
-    Ok(HttpResponse::Ok().json(obs))
+    users
+        .audit(
+            AuditLogEntry::new(AuditAction::Create, "User".to_string())
+                .with_user(claims.sub, claims.role)
+                .with_resource_id(user.id.to_string())
+                .with_status_code(201)
+                .with_metadata(serde_json::json!({ "username": user.username, "role": user.role })),
+        )
+        .await;
+
+    Ok(HttpResponse::Created().json(user))
 }
 
-#[derive(serde::Deserialize)]
-struct ObsQuery {
-    code: Option<String>,
-    limit: Option<usize>,
+#[derive(serde::Deserialize, Default)]
+struct ResetPasswordRequest {
+    temporary_password: Option<String>,
 }
 
-async fn get_observations(
+/// Admin reset: set a temporary password and force rotation at next login
+async fn reset_user_password(
     req: HttpRequest,
     state: web::Data<Arc<Mutex<AppState>>>,
-    q: web::Query<ObsQuery>,
+    path: web::Path<String>,
+    body: Option<web::Json<ResetPasswordRequest>>,
 ) -> Result<HttpResponse, AppError> {
-    // Verify authentication
-    let _claims = get_claims_from_request(&req).ok_or_else(|| AppError::Unauthorized)?;
+    let claims = require_admin(&req)?;
 
-    let limit = q.limit.unwrap_or(100).min(500);
+    let id = uuid::Uuid::parse_str(&path.into_inner())
+        .map_err(|_| AppError::BadRequest("invalid user id".into()))?;
 
-    let st = state.lock().await;
+    let users = state.lock().await.users();
+    let user = users
+        .get(id)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("user {}", id)))?;
 
-    let bundle = if let Some(code) = &q.code {
-        st.bundle_by_code(limit, code).await?
-    } else {
-        st.bundle(limit, None).await?
+    let temporary_password = match body.and_then(|b| b.into_inner().temporary_password) {
+        Some(password) => {
+            validate_password_strength(&password, &user.username).map_err(AppError::BadRequest)?;
+            password
+        }
+        None => generate_temporary_password(),
     };
 
-    Ok(HttpResponse::Ok().json(bundle))
-}
+    users
+        .set_password(user.id, &temporary_password, true)
+        .await?;
+    users
+        .audit(
+            AuditLogEntry::new(AuditAction::Update, "UserPassword".to_string())
+                .with_user(claims.sub.clone(), claims.role)
+                .with_resource_id(user.id.to_string())
+                .with_request_context(None, None, Some(req.path().to_string()))
+                .with_status_code(200)
+                .with_metadata(serde_json::json!({ "event": "password_reset" })),
+        )
+        .await;
 
-// ML Endpoints
+    tracing::info!(
+        "Admin {} reset the password of {}",
+        claims.sub,
+        user.username
+    );
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "id": user.id,
+        "username": user.username,
+        "temporary_password": temporary_password,
+        "must_change_password": true
+    })))
+}
 
 #[derive(serde::Deserialize)]
-struct MlQuery {
-    limit: Option<usize>,
-    hours_back: Option<u32>,
+#[serde(deny_unknown_fields)]
+struct UserPatientRequest {
+    patient_id: String,
 }
 
-async fn ml_predict(
+/// Admin: allow a user to read a patient's data, from their next login on
+async fn add_user_patient(
     req: HttpRequest,
-    ml_client: Option<web::Data<Arc<MlClient>>>,
-    query: web::Query<MlQuery>,
+    state: web::Data<Arc<Mutex<AppState>>>,
+    path: web::Path<String>,
+    body: web::Json<UserPatientRequest>,
 ) -> Result<HttpResponse, AppError> {
-    // Verify authentication
-    let _claims = get_claims_from_request(&req).ok_or_else(|| AppError::Unauthorized)?;
+    let claims = require_admin(&req)?;
 
-    let client =
-        ml_client.ok_or_else(|| AppError::BadRequest("ML service not configured".to_string()))?;
+    let id = uuid::Uuid::parse_str(&path.into_inner())
+        .map_err(|_| AppError::BadRequest("invalid user id".into()))?;
 
-    let limit = query.limit.unwrap_or(100).min(1000);
-    let hours_back = query.hours_back;
+    let users = state.lock().await.users();
+    let user = users
+        .get(id)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("user {}", id)))?;
 
-    match client.get_predictions(limit, hours_back).await {
-        Ok(predictions) => Ok(HttpResponse::Ok().json(predictions)),
-        Err(e) => {
-            tracing::error!("ML prediction failed: {}", e);
-            Err(AppError::Internal)
-        }
-    }
+    let account = user.id.to_string();
+    let (added, patient_ids) =
+        permit_account_patient(&req, &users, claims, &account, body.into_inner()).await?;
+    let body = serde_json::json!({
+        "user_id": user.id,
+        "patient_ids": patient_ids,
+    });
+    Ok(if added {
+        HttpResponse::Created().json(body)
+    } else {
+        HttpResponse::Ok().json(body)
+    })
 }
 
-async fn ml_analysis(
+/// Admin: allow an identity provider's subject (its `sub` claim) to read a
+/// patient's data. Read on every request, as such tokens carry no list.
+async fn add_subject_patient(
     req: HttpRequest,
-    ml_client: Option<web::Data<Arc<MlClient>>>,
-    query: web::Query<MlQuery>,
+    state: web::Data<Arc<Mutex<AppState>>>,
+    path: web::Path<String>,
+    body: web::Json<UserPatientRequest>,
 ) -> Result<HttpResponse, AppError> {
-    // Verify authentication
-    let _claims = get_claims_from_request(&req).ok_or_else(|| AppError::Unauthorized)?;
+    let claims = require_admin(&req)?;
+    let subject = path.into_inner();
 
-    let client =
+    let users = state.lock().await.users();
+    let (added, patient_ids) =
+        permit_account_patient(&req, &users, claims, &subject, body.into_inner()).await?;
+    let body = serde_json::json!({
+        "subject": subject,
+        "patient_ids": patient_ids,
+    });
+    Ok(if added {
+        HttpResponse::Created().json(body)
+    } else {
+        HttpResponse::Ok().json(body)
+    })
+}
+
+/// Add `request`'s patient to `account`'s list, audited when it is new;
+/// whether it was, and the account's patients afterwards
+async fn permit_account_patient(
+    req: &HttpRequest,
+    users: &UserStore,
+    claims: Claims,
+    account: &str,
+    request: UserPatientRequest,
+) -> Result<(bool, Vec<String>), AppError> {
+    let patient_id = request.patient_id.trim().to_string();
+    if patient_id.is_empty() {
+        return Err(AppError::BadRequest("patient_id required".into()));
+    }
+
+    let added = users.permit_patient(account, &patient_id).await?;
+    if added {
+        users
+            .audit(
+                AuditLogEntry::new(AuditAction::Create, "UserPatient".to_string())
+                    .with_user(claims.sub, claims.role)
+                    .with_resource_id(account.to_string())
+                    .with_patient_id(patient_id)
+                    .with_request_context(None, None, Some(req.path().to_string()))
+                    .with_status_code(201),
+            )
+            .await;
+    }
+    Ok((added, users.permitted_patients(account).await?))
+}
+
+#[derive(serde::Deserialize, ToSchema)]
+struct DeviceTokenRequest {
+    device_id: String,
+    secret: String, // Admin secret to generate device tokens
+}
+
+/// Legacy: issue a device token for the shared `DEVICE_TOKEN_SECRET`.
+/// Prefer the per-device challenge-response flow under `/auth/device/`.
+#[utoipa::path(
+    post,
+    path = "/auth/token",
+    tag = "auth",
+    request_body = DeviceTokenRequest,
+    responses(
+        (status = 200, description = "Issued device token", body = LoginResponse),
+        (status = 401, description = "Invalid admin secret", body = ErrBody),
+    )
+)]
+async fn generate_device_token(
+    jwt_manager: web::Data<Arc<JwtManager>>,
+    token_expiry: web::Data<JwtExpiryConfig>,
+    sessions: Option<web::Data<Arc<SessionStore>>>,
+    body: web::Json<DeviceTokenRequest>,
+) -> Result<HttpResponse, AppError> {
+    // Verify admin secret
+    let admin_secret = std::env::var("DEVICE_TOKEN_SECRET")
+        .unwrap_or_else(|_| DEFAULT_DEVICE_TOKEN_SECRET.to_string());
+
+    if body.secret != admin_secret {
+        tracing::warn!(
+            "Invalid device token generation attempt for device: {}",
+            body.device_id
+        );
+        return Err(AppError::Unauthorized);
+    }
+
+    issue_device_token(
+        &jwt_manager,
+        sessions.as_ref().map(|s| s.get_ref()),
+        &body.device_id,
+        token_expiry.for_role("device"),
+    )
+    .await
+}
+
+/// Sign a token for `device_id` valid for `expires_in_hours` and record its
+/// session
+async fn issue_device_token(
+    jwt_manager: &JwtManager,
+    sessions: Option<&Arc<SessionStore>>,
+    device_id: &str,
+    expires_in_hours: i64,
+) -> Result<HttpResponse, AppError> {
+    let claims = Claims::new(
+        format!("device_{}", device_id),
+        "device".to_string(),
+        Some(device_id.to_string()),
+        expires_in_hours,
+    );
+
+    match jwt_manager.generate_token(claims.clone()) {
+        Ok(token) => {
+            record_session(sessions, &claims).await;
+            tracing::info!("Generated token for device: {}", device_id);
+            Ok(HttpResponse::Ok().json(LoginResponse {
+                token,
+                expires_in: expires_in_hours * 3600,
+                role: "device".to_string(),
+                must_change_password: false,
+            }))
+        }
+        Err(e) => {
+            tracing::error!("Failed to generate device token: {}", e);
+            Err(AppError::Internal)
+        }
+    }
+}
+
+#[derive(serde::Deserialize, ToSchema)]
+struct DeviceChallengeRequest {
+    device_id: String,
+}
+
+/// Start device authentication: returns a nonce the device must sign with
+/// its secret within 60 seconds. Open challenges stay open alongside it. A
+/// device without a secret gets the same answer, with a nonce that no
+/// answer can redeem.
+#[utoipa::path(
+    post,
+    path = "/auth/device/challenge",
+    tag = "auth",
+    request_body = DeviceChallengeRequest,
+    responses(
+        (status = 200, description = "Open challenge", body = Challenge),
+    )
+)]
+async fn request_device_challenge(
+    state: web::Data<Arc<Mutex<AppState>>>,
+    body: web::Json<DeviceChallengeRequest>,
+) -> Result<HttpResponse, AppError> {
+    let device_auth = state.lock().await.device_auth();
+    let challenge = device_auth.challenge(&body.device_id).await?;
+    Ok(HttpResponse::Ok().json(challenge))
+}
+
+#[derive(serde::Deserialize, ToSchema)]
+struct DeviceChallengeAnswer {
+    device_id: String,
+    /// The nonce of the challenge being answered
+    nonce: String,
+    /// Hex `HMAC-SHA256(device_secret, nonce)`, over the decoded nonce bytes
+    response: String,
+}
+
+/// Finish device authentication: a correct answer to the open challenge
+/// gets a device token. Each challenge can be answered once.
+#[utoipa::path(
+    post,
+    path = "/auth/device/token",
+    tag = "auth",
+    request_body = DeviceChallengeAnswer,
+    responses(
+        (status = 200, description = "Issued device token", body = LoginResponse),
+        (status = 401, description = "No open challenge, expired or wrong answer", body = ErrBody),
+    )
+)]
+async fn answer_device_challenge(
+    state: web::Data<Arc<Mutex<AppState>>>,
+    jwt_manager: web::Data<Arc<JwtManager>>,
+    token_expiry: web::Data<JwtExpiryConfig>,
+    sessions: Option<web::Data<Arc<SessionStore>>>,
+    body: web::Json<DeviceChallengeAnswer>,
+) -> Result<HttpResponse, AppError> {
+    let device_auth = state.lock().await.device_auth();
+    device_auth
+        .redeem(&body.device_id, &body.nonce, &body.response)
+        .await?;
+    issue_device_token(
+        &jwt_manager,
+        sessions.as_ref().map(|s| s.get_ref()),
+        &body.device_id,
+        token_expiry.for_role("device"),
+    )
+    .await
+}
+
+/// Start the IoT handshake: returns a nonce the device must sign with its
+/// factory key within 30 seconds
+#[utoipa::path(
+    post,
+    path = "/auth/iot/challenge",
+    tag = "auth",
+    request_body = DeviceChallengeRequest,
+    responses(
+        (status = 200, description = "Nonce to sign", body = IotChallenge),
+        (status = 404, description = "Device is not provisioned", body = ErrBody),
+    )
+)]
+async fn request_iot_challenge(
+    state: web::Data<Arc<Mutex<AppState>>>,
+    body: web::Json<DeviceChallengeRequest>,
+) -> Result<HttpResponse, AppError> {
+    let iot_auth = state.lock().await.iot_auth();
+    let challenge = iot_auth.challenge(&body.device_id).await?;
+    Ok(HttpResponse::Ok().json(challenge))
+}
+
+/// Finish the IoT handshake: a correct, timely HMAC over an unused nonce
+/// gets a device token valid for an hour
+#[utoipa::path(
+    post,
+    path = "/auth/iot/token",
+    tag = "auth",
+    request_body = IotTokenRequest,
+    responses(
+        (status = 200, description = "Issued device token", body = LoginResponse),
+        (status = 401, description = "Unknown, expired or used nonce, clock too far off or wrong HMAC", body = ErrBody),
+    )
+)]
+async fn answer_iot_challenge(
+    state: web::Data<Arc<Mutex<AppState>>>,
+    jwt_manager: web::Data<Arc<JwtManager>>,
+    sessions: Option<web::Data<Arc<SessionStore>>>,
+    body: web::Json<IotTokenRequest>,
+) -> Result<HttpResponse, AppError> {
+    let iot_auth = state.lock().await.iot_auth();
+    iot_auth.redeem(&body).await?;
+    issue_device_token(
+        &jwt_manager,
+        sessions.as_ref().map(|s| s.get_ref()),
+        &body.device_id,
+        iot_auth::TOKEN_HOURS,
+    )
+    .await
+}
+
+#[utoipa::path(
+    get,
+    path = "/auth/whoami",
+    tag = "auth",
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 200, description = "Claims of the presented token", body = Claims),
+        (status = 401, description = "Missing or invalid token", body = ErrBody),
+    )
+)]
+async fn whoami(req: HttpRequest) -> Result<HttpResponse, AppError> {
+    // Claims only: the token itself is never echoed back
+    let claims = get_claims_from_request(&req).ok_or(AppError::Unauthorized)?;
+    Ok(HttpResponse::Ok().json(claims))
+}
+
+// Protected endpoints
+
+// Public ingest endpoint (no auth required - for simulator and mock data);
+// unregistered with PUBLIC_INGEST=disabled
+#[utoipa::path(
+    post,
+    path = "/ingest",
+    tag = "ingest",
+    request_body = IngestReading,
+    responses(
+        (status = 200, description = "Reading stored, with any validation warnings", body = IngestResponse),
+        (status = 202, description = "Reading queued, or quarantined for lack of consent", body = IngestResponse),
+        (status = 400, description = "Invalid reading, or refused by a validation rule", body = ErrBody),
+        (status = 403, description = "No consent on record for the patient", body = ErrBody),
+        (status = 413, description = "Body too large", body = ErrBody),
+        (status = 429, description = "Too many concurrent ingests", body = ErrBody),
+    )
+)]
+async fn ingest_public(
+    state: web::Data<Arc<Mutex<AppState>>>,
+    hub: web::Data<WsHub>,
+    queue: Option<web::Data<IngestQueue>>,
+    payload: web::Json<IngestReading>,
+) -> Result<HttpResponse, AppError> {
+    tracing::debug!("Public ingest request (no auth)");
+
+    ingest_reading(
+        &state,
+        &hub,
+        queue.as_ref().map(|q| q.get_ref()),
+        payload.into_inner(),
+        None,
+    )
+    .await
+}
+
+// Protected ingest endpoint (JWT required)
+#[utoipa::path(
+    post,
+    path = "/api/ingest",
+    tag = "ingest",
+    request_body = IngestReading,
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 200, description = "Reading stored, with any validation warnings", body = IngestResponse),
+        (status = 202, description = "Reading queued, or quarantined for lack of consent", body = IngestResponse),
+        (status = 400, description = "Invalid reading, or refused by a validation rule", body = ErrBody),
+        (status = 401, description = "Missing or invalid token", body = ErrBody),
+        (status = 403, description = "No consent on record for the patient", body = ErrBody),
+        (status = 413, description = "Body too large", body = ErrBody),
+        (status = 429, description = "Too many concurrent ingests", body = ErrBody),
+    )
+)]
+async fn ingest(
+    req: HttpRequest,
+    state: web::Data<Arc<Mutex<AppState>>>,
+    hub: web::Data<WsHub>,
+    queue: Option<web::Data<IngestQueue>>,
+    payload: web::Json<IngestReading>,
+) -> Result<HttpResponse, AppError> {
+    // Get authenticated user from JWT claims
+    let claims = require_permission(&req, Permission::WriteReadings)?;
+
+    tracing::debug!(
+        "Ingest request from user: {}, role: {}",
+        claims.sub,
+        claims.role
+    );
+
+    ingest_reading(
+        &state,
+        &hub,
+        queue.as_ref().map(|q| q.get_ref()),
+        payload.into_inner(),
+        Some(claims),
+    )
+    .await
+}
+
+/// Single-reading ingest behind both `/ingest` (`claims` is `None`) and
+/// `/api/ingest`: validate the reading, then store or enqueue it and count
+/// the outcome against its device. Public readings are queued behind
+/// authenticated ones, so simulator traffic never delays clinical readings.
+async fn ingest_reading(
+    state: &Mutex<AppState>,
+    hub: &WsHub,
+    queue: Option<&IngestQueue>,
+    payload: IngestReading,
+    claims: Option<Claims>,
+) -> Result<HttpResponse, AppError> {
+    let received_at = chrono::Utc::now();
+    let reading = payload.into_reading();
+    let mut devices = vec![(reading.device_id.clone(), reading.ts)];
+
+    let result = async {
+        // Validate, then scale by the device's profile so the rules, the
+        // consent check and the Observation all see the stored value
+        reading.validate().map_err(AppError::BadRequest)?;
+        let mut record = StoredReading::new(reading);
+        state.lock().await.normalizer().apply(&mut record);
+        let warnings = run_validation_rules(state, &record.reading).await?;
+
+        // Convert to FHIR Observation
+        let record_id = record.id;
+        let obs = FhirObservation::from_stored(record.clone());
+
+        // Validate FHIR schema compliance
+        obs.validate().map_err(AppError::BadRequest)?;
+
+        dispatch_reading(
+            state,
+            hub,
+            queue,
+            &mut devices,
+            record,
+            IngestResponse {
+                obs,
+                warnings,
+                receipt: IngestReceipt {
+                    id: record_id,
+                    persisted: false,
+                    storage: Storage::Memory,
+                    received_at,
+                },
+            },
+            claims,
+        )
+        .await
+    }
+    .await;
+
+    count_ingest_outcome(state, &devices, &result).await;
+    result
+}
+
+/// Count the outcome of an ingest against the devices whose readings
+/// (device id and reading time) it carried. Success counts as accepted; an
+/// invalid reading or missing consent as a refusal of every reading in the
+/// request. Server-side failures are not the devices' doing and not counted.
+/// Quarantined readings were counted when quarantined and taken out of
+/// `devices`.
+async fn count_ingest_outcome<T>(
+    state: &Mutex<AppState>,
+    devices: &[(String, chrono::DateTime<chrono::Utc>)],
+    result: &Result<T, AppError>,
+) {
+    let mut st = state.lock().await;
+    match result {
+        Ok(_) => {
+            for (device_id, ts) in devices {
+                st.record_accepted(device_id, *ts);
+            }
+        }
+        Err(AppError::BadRequest(message) | AppError::Forbidden(message)) => {
+            for (device_id, _) in devices {
+                st.record_rejected(device_id, message);
+            }
+        }
+        Err(_) => {}
+    }
+}
+
+/// Store and broadcast a validated reading. With an ingest queue configured
+/// the reading is only enqueued and the response is 202 Accepted; a reading
+/// dropped by decimation is neither stored nor broadcast. Readings of
+/// authenticated callers are queued ahead of public ones.
+async fn dispatch_reading(
+    state: &Mutex<AppState>,
+    hub: &WsHub,
+    queue: Option<&IngestQueue>,
+    devices: &mut Vec<(String, chrono::DateTime<chrono::Utc>)>,
+    record: StoredReading,
+    mut response: IngestResponse,
+    claims: Option<Claims>,
+) -> Result<HttpResponse, AppError> {
+    if state.lock().await.decimate(&record.reading) {
+        response.receipt.storage = Storage::Decimated;
+        return Ok(HttpResponse::Ok().json(response));
+    }
+
+    if !admit_or_quarantine(
+        state,
+        devices,
+        &record.reading.patient_id,
+        "reading",
+        &record.reading,
+    )
+    .await?
+    {
+        response.receipt.storage = Storage::Quarantine;
+        response.receipt.persisted = state.lock().await.has_database();
+        return Ok(HttpResponse::Accepted().json(response));
+    }
+
+    if let Some(queue) = queue {
+        let priority = match claims {
+            Some(_) => Priority::High,
+            None => Priority::Low,
+        };
+        queue.enqueue(
+            priority,
+            QueuedReading {
+                record,
+                obs: response.obs.clone(),
+                claims,
+                broadcast: true,
+            },
+        )?;
+
+        response.receipt.storage = Storage::Queued;
+        let mut resp = if queue.respond_ok() {
+            HttpResponse::Ok()
+        } else {
+            HttpResponse::Accepted()
+        };
+        return Ok(resp.json(response));
+    }
+
+    // Store reading (with database support and audit logging)
+    let outcome = {
+        let mut st = state.lock().await;
+        st.push(record, claims.as_ref()).await?
+    };
+    response.receipt.storage = outcome.storage;
+    response.receipt.persisted = outcome.persisted();
+
+    // Push to WebSocket subscribers
+    hub.publish(&response.obs);
+
+    Ok(HttpResponse::Ok().json(response))
+}
+
+/// The deployment's validation rules for one reading: 400 if an error-level
+/// rule fails, otherwise the findings of warning-level ones
+async fn run_validation_rules(
+    state: &Mutex<AppState>,
+    reading: &SensorReading,
+) -> Result<Vec<String>, AppError> {
+    let result = state.lock().await.validation().run(reading);
+    if !result.warnings.is_empty() {
+        tracing::debug!(
+            device_id = %reading.device_id,
+            "Reading stored with warnings: {}",
+            result.warnings.join("; ")
+        );
+    }
+    result.into_warnings()
+}
+
+/// Ingest response: the Observation, the receipt saying where it was kept,
+/// plus the findings of warning-level validation rules when there are any
+#[derive(serde::Serialize, ToSchema)]
+struct IngestResponse {
+    #[serde(flatten)]
+    obs: FhirObservation,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    warnings: Vec<String>,
+    receipt: IngestReceipt,
+}
+
+/// Consent check for a single reading: `Ok(true)` to store it, `Ok(false)`
+/// once it has been quarantined and counted as such against the reading's
+/// `devices`, 403 in strict mode
+async fn admit_or_quarantine(
+    state: &Mutex<AppState>,
+    devices: &mut Vec<(String, chrono::DateTime<chrono::Utc>)>,
+    patient_id: &str,
+    kind: &'static str,
+    payload: &impl serde::Serialize,
+) -> Result<bool, AppError> {
+    let mut st = state.lock().await;
+    match st.admit(&[patient_id]).await?.first() {
+        Some(Admission::Quarantine(reason)) => {
+            st.quarantine(patient_id, kind, payload, reason).await?;
+            for (device_id, _) in devices.drain(..) {
+                st.record_quarantined(&device_id);
+            }
+            Ok(false)
+        }
+        _ => Ok(true),
+    }
+}
+
+// Protected batch ingest endpoint (JWT required)
+#[utoipa::path(
+    post,
+    path = "/api/ingest/batch",
+    tag = "ingest",
+    request_body = Vec<IngestReading>,
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 200, description = "Readings stored", body = FhirBundle),
+        (status = 202, description = "Readings queued", body = FhirBundle),
+        (status = 400, description = "Invalid reading in batch", body = ErrBody),
+        (status = 401, description = "Missing or invalid token", body = ErrBody),
+        (status = 403, description = "A patient in the batch has no consent on record", body = ErrBody),
+        (status = 413, description = "Body too large", body = ErrBody),
+        (status = 429, description = "Too many concurrent ingests", body = ErrBody),
+    )
+)]
+async fn ingest_batch(
+    req: HttpRequest,
+    state: web::Data<Arc<Mutex<AppState>>>,
+    hub: web::Data<WsHub>,
+    queue: Option<web::Data<IngestQueue>>,
+    payload: web::Json<Vec<IngestReading>>,
+) -> Result<HttpResponse, AppError> {
+    let claims = require_permission(&req, Permission::WriteReadings)?;
+
+    let readings: Vec<_> = payload
+        .into_inner()
+        .into_iter()
+        .map(|r| StoredReading::new(r.into_reading()))
+        .collect();
+
+    tracing::debug!(
+        "Batch ingest of {} readings from user: {}, role: {}",
+        readings.len(),
+        claims.sub,
+        claims.role
+    );
+
+    if readings.is_empty() {
+        return Err(AppError::BadRequest("batch must not be empty".into()));
+    }
+
+    let mut devices: Vec<_> = readings
+        .iter()
+        .map(|r| (r.reading.device_id.clone(), r.reading.ts))
+        .collect();
+    let result = store_batch(&state, &hub, queue, claims, readings, 1, true)
+        .await
+        .map(|(mut resp, observations, quarantined)| {
+            uncount_quarantined(&mut devices, &quarantined);
+            resp.json(FhirBundle::from_obs(observations))
+        });
+    count_ingest_outcome(&state, &devices, &result).await;
+    result
+}
+
+/// Outcome of a packed ingest
+#[derive(serde::Serialize, ToSchema)]
+struct PackedIngestResponse {
+    /// Samples stored or queued; samples of a patient without consent are
+    /// quarantined instead
+    stored: usize,
+    first_ts: chrono::DateTime<chrono::Utc>,
+    last_ts: chrono::DateTime<chrono::Utc>,
+}
+
+/// Ingest several samples taken at a fixed rate in one request. Each sample
+/// is stored as its own reading at `start_ts + i * interval_ms`; only every
+/// `PACKED_BROADCAST_EVERY`th sample (default 10th) is sent to WebSocket
+/// clients.
+#[utoipa::path(
+    post,
+    path = "/api/ingest/packed",
+    tag = "ingest",
+    request_body = PackedReading,
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 200, description = "Samples stored", body = PackedIngestResponse),
+        (status = 202, description = "Samples queued", body = PackedIngestResponse),
+        (status = 400, description = "Invalid packed reading", body = ErrBody),
+        (status = 401, description = "Missing or invalid token", body = ErrBody),
+        (status = 403, description = "No consent on record for the patient", body = ErrBody),
+        (status = 413, description = "Body too large", body = ErrBody),
+        (status = 429, description = "Too many concurrent ingests", body = ErrBody),
+    )
+)]
+async fn ingest_packed(
+    req: HttpRequest,
+    state: web::Data<Arc<Mutex<AppState>>>,
+    hub: web::Data<WsHub>,
+    queue: Option<web::Data<IngestQueue>>,
+    broadcast_every: web::Data<PackedBroadcastEvery>,
+    payload: web::Json<PackedReading>,
+) -> Result<HttpResponse, AppError> {
+    let claims = require_permission(&req, Permission::WriteReadings)?;
+
+    let packed = payload.into_inner();
+    // Validation refuses samples past the latest representable time
+    let last_ts = packed
+        .sample_ts(packed.values.len().saturating_sub(1))
+        .unwrap_or(packed.start_ts);
+    let mut devices = vec![(packed.device_id.clone(), last_ts)];
+
+    let result = async {
+        packed.validate().map_err(AppError::BadRequest)?;
+        let first_ts = packed.start_ts;
+
+        tracing::debug!(
+            "Packed ingest of {} samples from user: {}, role: {}",
+            packed.values.len(),
+            claims.sub,
+            claims.role
+        );
+
+        let readings = packed
+            .into_readings()
+            .into_iter()
+            .map(StoredReading::new)
+            .collect();
+        // Packed samples come at the rate the device declared; they are
+        // not decimated
+        let (mut resp, observations, quarantined) = store_batch(
+            &state,
+            &hub,
+            queue,
+            claims,
+            readings,
+            broadcast_every.0,
+            false,
+        )
+        .await?;
+        uncount_quarantined(&mut devices, &quarantined);
+        Ok(resp.json(PackedIngestResponse {
+            stored: observations.len(),
+            first_ts,
+            last_ts,
+        }))
+    }
+    .await;
+
+    count_ingest_outcome(&state, &devices, &result).await;
+    result
+}
+
+/// Take the devices of quarantined readings out of the `devices` counted by
+/// `count_ingest_outcome`, one reading each
+fn uncount_quarantined(
+    devices: &mut Vec<(String, chrono::DateTime<chrono::Utc>)>,
+    quarantined: &[String],
+) {
+    for device_id in quarantined {
+        if let Some(idx) = devices.iter().position(|(d, _)| d == device_id) {
+            devices.swap_remove(idx);
+        }
+    }
+}
+
+/// Validate, admit and store or enqueue a non-empty batch, publishing every
+/// `broadcast_every`th reading (counting from the first) to WebSocket
+/// clients. Returns the response status to use, the Observations of the
+/// readings stored or queued, and the devices of the readings quarantined
+/// (counted as such already); quarantined readings, and with `decimate`
+/// readings dropped by decimation, are left out of the Observations.
+async fn store_batch(
+    state: &Mutex<AppState>,
+    hub: &WsHub,
+    queue: Option<web::Data<IngestQueue>>,
+    claims: Claims,
+    mut readings: Vec<StoredReading>,
+    broadcast_every: usize,
+    decimate: bool,
+) -> Result<
+    (
+        actix_web::HttpResponseBuilder,
+        Vec<FhirObservation>,
+        Vec<String>,
+    ),
+    AppError,
+> {
+    // Validate the whole batch, normalized, before storing anything. Like
+    // a stream, a batch is held to error-level rules only.
+    let (validation, normalizer) = {
+        let st = state.lock().await;
+        (st.validation().clone(), st.normalizer().clone())
+    };
+    let mut observations = Vec::with_capacity(readings.len());
+    for (idx, record) in readings.iter_mut().enumerate() {
+        record
+            .reading
+            .validate()
+            .map_err(|e| AppError::BadRequest(format!("reading {}: {}", idx, e)))?;
+        normalizer.apply(record);
+        let errors = validation.run(&record.reading).errors;
+        if !errors.is_empty() {
+            return Err(AppError::BadRequest(format!(
+                "reading {}: {}",
+                idx,
+                errors.join("; ")
+            )));
+        }
+
+        let obs = FhirObservation::from_stored(record.clone());
+        obs.validate()
+            .map_err(|e| AppError::BadRequest(format!("reading {}: {}", idx, e)))?;
+        observations.push(obs);
+    }
+
+    let (readings, observations): (Vec<_>, Vec<_>) = if decimate {
+        let mut st = state.lock().await;
+        readings
+            .into_iter()
+            .zip(observations)
+            .filter(|(record, _)| !st.decimate(&record.reading))
+            .unzip()
+    } else {
+        (readings, observations)
+    };
+
+    // Strict mode rejects the whole batch; in quarantine mode only the
+    // readings of patients without consent are held back
+    let mut quarantined = Vec::new();
+    let (readings, observations) = {
+        let mut st = state.lock().await;
+        let patient_ids: Vec<&str> = readings
+            .iter()
+            .map(|r| r.reading.patient_id.as_str())
+            .collect();
+        let admissions = st.admit(&patient_ids).await?;
+
+        let mut admitted = (Vec::new(), Vec::new());
+        for (idx, ((record, obs), admission)) in readings
+            .into_iter()
+            .zip(observations)
+            .zip(admissions)
+            .enumerate()
+        {
+            match admission {
+                Admission::Store => {
+                    admitted.0.push((record, idx % broadcast_every.max(1) == 0));
+                    admitted.1.push(obs);
+                }
+                Admission::Quarantine(reason) => {
+                    st.quarantine(
+                        &record.reading.patient_id,
+                        "reading",
+                        &record.reading,
+                        reason,
+                    )
+                    .await?;
+                    st.record_quarantined(&record.reading.device_id);
+                    quarantined.push(record.reading.device_id);
+                }
+            }
+        }
+        admitted
+    };
+
+    if let Some(queue) = queue {
+        queue.enqueue_all(
+            Priority::High,
+            readings
+                .into_iter()
+                .zip(&observations)
+                .map(|((record, broadcast), obs)| QueuedReading {
+                    record,
+                    obs: obs.clone(),
+                    claims: Some(claims.clone()),
+                    broadcast,
+                })
+                .collect(),
+        )?;
+
+        let resp = if queue.respond_ok() {
+            HttpResponse::Ok()
+        } else {
+            HttpResponse::Accepted()
+        };
+        return Ok((resp, observations, quarantined));
+    }
+
+    let mut published = Vec::new();
+    {
+        let mut st = state.lock().await;
+        for ((reading, broadcast), obs) in readings.into_iter().zip(&observations) {
+            st.push(reading, Some(&claims)).await?;
+            if broadcast {
+                published.push(obs);
+            }
+        }
+    }
+
+    for obs in published {
+        hub.publish(obs);
+    }
+
+    Ok((HttpResponse::Ok(), observations, quarantined))
+}
+
+/// Backfill readings from an `application/x-ndjson` body, one reading per
+/// line. The body is read as it arrives; invalid lines are skipped and
+/// counted, valid ones inserted every `STREAM_INGEST_BATCH_ROWS` readings.
+/// Only every `STREAM_BROADCAST_EVERY`th accepted reading (default 1000th)
+/// is sent to WebSocket clients. Readings are stored directly, bypassing
+/// the ingest queue.
+#[utoipa::path(
+    post,
+    path = "/api/ingest/stream",
+    tag = "ingest",
+    request_body(
+        content = String,
+        content_type = "application/x-ndjson",
+        description = "One IngestReading per line"
+    ),
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 200, description = "Stream processed", body = StreamIngestSummary),
+        (status = 400, description = "Not NDJSON, or the body could not be read", body = ErrBody),
+        (status = 401, description = "Missing or invalid token", body = ErrBody),
+        (status = 413, description = "More than STREAM_INGEST_MAX_LINES lines; earlier lines were processed", body = ErrBody),
+        (status = 429, description = "Too many concurrent ingests", body = ErrBody),
+    )
+)]
+async fn ingest_stream(
+    req: HttpRequest,
+    state: web::Data<Arc<Mutex<AppState>>>,
+    hub: web::Data<WsHub>,
+    config: Option<web::Data<StreamIngestConfig>>,
+    mut payload: web::Payload,
+) -> Result<HttpResponse, AppError> {
+    let claims = require_permission(&req, Permission::WriteReadings)?;
+
+    let ndjson = req
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.split(';').next())
+        .is_some_and(|v| v.trim().eq_ignore_ascii_case(NDJSON));
+    if !ndjson {
+        return Err(AppError::BadRequest(format!(
+            "expected Content-Type {}",
+            NDJSON
+        )));
+    }
+
+    let config = config.map(|c| **c).unwrap_or_default();
+    let (validation, normalizer) = {
+        let st = state.lock().await;
+        (st.validation().clone(), st.normalizer().clone())
+    };
+    let mut ingest = StreamIngest {
+        state: &state,
+        hub: &hub,
+        claims: &claims,
+        config,
+        validation,
+        normalizer,
+        pending: Vec::new(),
+        summary: StreamIngestSummary::default(),
+        lines: 0,
+    };
+
+    let mut splitter = LineSplitter::new(config.max_line_bytes);
+    while let Some(chunk) = payload.next().await {
+        let chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(e) => return Err(ingest.abort(format!("failed to read stream: {}", e)).await),
+        };
+        for line in splitter.push(&chunk) {
+            ingest.line(line).await?;
+        }
+    }
+    if let Some(line) = splitter.finish() {
+        ingest.line(line).await?;
+    }
+    ingest.flush().await?;
+
+    tracing::info!(
+        lines = ingest.lines,
+        accepted = ingest.summary.accepted,
+        quarantined = ingest.summary.quarantined,
+        rejected = ingest.summary.rejected,
+        "Stream ingest from user: {}, role: {}",
+        claims.sub,
+        claims.role
+    );
+    Ok(HttpResponse::Ok().json(ingest.summary))
+}
+
+const NDJSON: &str = "application/x-ndjson";
+
+/// A stream ingest in progress: valid readings waiting for the next batch
+/// insert, with the line they came from
+struct StreamIngest<'a> {
+    state: &'a Mutex<AppState>,
+    hub: &'a WsHub,
+    claims: &'a Claims,
+    config: StreamIngestConfig,
+    validation: ValidationPipeline,
+    normalizer: Normalizer,
+    pending: Vec<(usize, StoredReading)>,
+    summary: StreamIngestSummary,
+    /// Lines seen so far, blank ones included
+    lines: usize,
+}
+
+impl StreamIngest<'_> {
+    /// Check the next line and queue its reading, inserting the batch once full
+    async fn line(&mut self, line: Line) -> Result<(), AppError> {
+        self.lines += 1;
+        if self.lines > self.config.max_lines {
+            let message = format!("stream exceeds {} lines", self.config.max_lines);
+            return Err(self.abort(message).await);
+        }
+
+        let bytes = match line {
+            Line::Complete(bytes) => bytes,
+            Line::TooLong => {
+                let message = format!("line exceeds {} bytes", self.config.max_line_bytes);
+                self.summary.reject(self.lines, message);
+                return Ok(());
+            }
+        };
+        if bytes.trim_ascii().is_empty() {
+            return Ok(());
+        }
+
+        let reading = match serde_json::from_slice::<IngestReading>(&bytes) {
+            Ok(reading) => reading.into_reading(),
+            Err(e) => {
+                self.summary.reject(self.lines, e.to_string());
+                return Ok(());
+            }
+        };
+        let mut record = StoredReading::new(reading);
+        let checked = record
+            .reading
+            .validate()
+            .and_then(|_| {
+                self.normalizer.apply(&mut record);
+                FhirObservation::from_stored(record.clone()).validate()
+            })
+            .and_then(|_| {
+                let errors = self.validation.run(&record.reading).errors;
+                if errors.is_empty() {
+                    Ok(())
+                } else {
+                    Err(errors.join("; "))
+                }
+            });
+        if let Err(e) = checked {
+            self.state
+                .lock()
+                .await
+                .record_rejected(&record.reading.device_id, &e);
+            self.summary.reject(self.lines, e);
+            return Ok(());
+        }
+
+        self.pending.push((self.lines, record));
+        if self.pending.len() >= self.config.batch_rows {
+            self.flush().await?;
+        }
+        Ok(())
+    }
+
+    /// Insert the pending readings, skipping those of patients without
+    /// consent in strict mode
+    async fn flush(&mut self) -> Result<(), AppError> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let pending = std::mem::take(&mut self.pending);
+
+        let listened = self.hub.wants_observations();
+        let mut published = Vec::new();
+        {
+            let mut st = self.state.lock().await;
+            let patient_ids: Vec<&str> = pending
+                .iter()
+                .map(|(_, r)| r.reading.patient_id.as_str())
+                .collect();
+            let admissions = st.admit_each(&patient_ids).await?;
+
+            let mut stored = Vec::with_capacity(pending.len());
+            for ((line, record), admission) in pending.into_iter().zip(admissions) {
+                match admission {
+                    Ok(Admission::Store) => {
+                        st.record_accepted(&record.reading.device_id, record.reading.ts);
+                        // Nothing to build for a hub nobody listens to
+                        if listened
+                            && self
+                                .summary
+                                .accepted
+                                .is_multiple_of(self.config.broadcast_every.max(1))
+                        {
+                            published.push(FhirObservation::from_stored(record.clone()));
+                        }
+                        self.summary.accepted += 1;
+                        stored.push(record);
+                    }
+                    Ok(Admission::Quarantine(reason)) => {
+                        st.quarantine(
+                            &record.reading.patient_id,
+                            "reading",
+                            &record.reading,
+                            reason,
+                        )
+                        .await?;
+                        st.record_quarantined(&record.reading.device_id);
+                        self.summary.quarantined += 1;
+                    }
+                    Err(e) => {
+                        let message = e.to_string();
+                        st.record_rejected(&record.reading.device_id, &message);
+                        self.summary.reject(line, message);
+                    }
+                }
+            }
+            st.push_many(stored, Some(self.claims)).await?;
+        }
+
+        for obs in published {
+            self.hub.publish(&obs);
+        }
+        Ok(())
+    }
+
+    /// Insert what is pending and give up on the rest of the stream with
+    /// `message`, which also tells how far it got
+    async fn abort(&mut self, message: String) -> AppError {
+        if let Err(e) = self.flush().await {
+            return e;
+        }
+        let message = format!(
+            "{}; the first {} lines were processed ({} accepted, {} quarantined, {} rejected)",
+            message,
+            self.lines.min(self.config.max_lines),
+            self.summary.accepted,
+            self.summary.quarantined,
+            self.summary.rejected
+        );
+        if self.lines > self.config.max_lines {
+            AppError::PayloadTooLarge(message)
+        } else {
+            AppError::BadRequest(message)
+        }
+    }
+}
+
+/// Partial update of a stored Observation; only the status may change
+#[derive(serde::Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+struct AmendObservationRequest {
+    /// `amended`, `cancelled` or `entered-in-error`
+    status: ObservationStatus,
+    /// Why the reading was amended
+    note: Option<String>,
+}
+
+/// Mark a reading amended, cancelled or entered-in-error instead of deleting
+/// it. Entered-in-error readings are left out of statistics and model data.
+#[utoipa::path(
+    put,
+    path = "/api/fhir/Observation/{id}",
+    tag = "fhir",
+    params(("id" = String, Path, description = "Observation id")),
+    request_body = AmendObservationRequest,
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 200, description = "Amended Observation", body = FhirObservation),
+        (status = 400, description = "Invalid id or body", body = ErrBody),
+        (status = 401, description = "Missing or invalid token", body = ErrBody),
+        (status = 403, description = "Device tokens cannot amend, or the patient is not permitted", body = ErrBody),
+        (status = 404, description = "No such Observation", body = ErrBody),
+        (status = 409, description = "Status transition not allowed", body = ErrBody),
+    )
+)]
+async fn amend_observation(
+    req: HttpRequest,
+    state: web::Data<Arc<Mutex<AppState>>>,
+    path: web::Path<String>,
+    body: web::Json<AmendObservationRequest>,
+) -> Result<HttpResponse, AppError> {
+    // Amendments are a clinical judgement; sensors may only report readings
+    let claims = require_permission(&req, Permission::AmendReadings)?;
+    let id = uuid::Uuid::parse_str(&path.into_inner())
+        .map_err(|_| AppError::BadRequest("invalid Observation id".into()))?;
+    let body = body.into_inner();
+
+    let note = body.note.filter(|n| !n.trim().is_empty());
+    let amended = state
+        .lock()
+        .await
+        .amend_reading(id, body.status, note, &claims)
+        .await?;
+
+    tracing::info!(
+        "User {} set Observation {} to {}",
+        claims.sub,
+        id,
+        amended.status.as_str()
+    );
+
+    Ok(HttpResponse::Ok().json(FhirObservation::from_stored(amended)))
+}
+
+/// Delete a reading (admin only). It is only marked deleted: it drops out of
+/// every query, stays listed for admins with `include_deleted=true`, and the
+/// deletion is audited.
+#[utoipa::path(
+    delete,
+    path = "/api/fhir/Observation/{id}",
+    tag = "fhir",
+    params(("id" = String, Path, description = "Observation id")),
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 204, description = "Observation deleted"),
+        (status = 400, description = "Invalid id", body = ErrBody),
+        (status = 401, description = "Missing token or not an admin", body = ErrBody),
+        (status = 404, description = "No such Observation, or deleted already", body = ErrBody),
+    )
+)]
+async fn delete_observation(
+    req: HttpRequest,
+    state: web::Data<Arc<Mutex<AppState>>>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, AppError> {
+    let claims = require_admin(&req)?;
+    let id = uuid::Uuid::parse_str(&path.into_inner())
+        .map_err(|_| AppError::BadRequest("invalid Observation id".into()))?;
+
+    state.lock().await.delete_reading(id, &claims).await?;
+    tracing::info!("User {} deleted Observation {}", claims.sub, id);
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Consent update for a patient, registering them if unknown
+#[derive(serde::Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+struct ConsentRequest {
+    /// `pending`, `granted` or `revoked`
+    status: ConsentStatus,
+    /// Display label; the current one is kept when omitted
+    label: Option<String>,
+}
+
+/// A registered patient and their consent status (admin only)
+#[utoipa::path(
+    get,
+    path = "/api/patients/{id}",
+    tag = "patients",
+    params(("id" = String, Path, description = "Patient id")),
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 200, description = "Patient", body = Patient),
+        (status = 401, description = "Missing token or not an admin", body = ErrBody),
+        (status = 404, description = "No such patient", body = ErrBody),
+    )
+)]
+async fn get_patient(
+    req: HttpRequest,
+    state: web::Data<Arc<Mutex<AppState>>>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, AppError> {
+    require_admin(&req)?;
+    let patient_id = path.into_inner();
+
+    let patient = state
+        .lock()
+        .await
+        .get_patient(&patient_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("patient {}", patient_id)))?;
+
+    Ok(HttpResponse::Ok().json(patient))
+}
+
+/// Record a patient's consent; readings are only stored once it is granted
+/// (admin only)
+#[utoipa::path(
+    put,
+    path = "/api/patients/{id}/consent",
+    tag = "patients",
+    params(("id" = String, Path, description = "Patient id")),
+    request_body = ConsentRequest,
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 200, description = "Updated patient", body = Patient),
+        (status = 400, description = "Invalid body", body = ErrBody),
+        (status = 401, description = "Missing token or not an admin", body = ErrBody),
+    )
+)]
+async fn set_patient_consent(
+    req: HttpRequest,
+    state: web::Data<Arc<Mutex<AppState>>>,
+    path: web::Path<String>,
+    body: web::Json<ConsentRequest>,
+) -> Result<HttpResponse, AppError> {
+    let claims = require_admin(&req)?;
+    let patient_id = path.into_inner();
+    if patient_id.trim().is_empty() || patient_id.len() > 255 {
+        return Err(AppError::BadRequest("invalid patient id".into()));
+    }
+    let body = body.into_inner();
+    let label = body.label.filter(|l| !l.trim().is_empty());
+
+    let patient = state
+        .lock()
+        .await
+        .set_consent(&patient_id, body.status, label, &claims)
+        .await?;
+
+    Ok(HttpResponse::Ok().json(patient))
+}
+
+/// Longest range of daily rollups returned at once
+const MAX_ROLLUP_RANGE_DAYS: i64 = 366;
+
+#[derive(serde::Deserialize, IntoParams)]
+struct RollupQuery {
+    /// First day, `YYYY-MM-DD`
+    from: chrono::NaiveDate,
+    /// Last day (inclusive), `YYYY-MM-DD`
+    to: chrono::NaiveDate,
+}
+
+/// A patient's per-day aggregates, read from the rollup table. Today and
+/// days not rolled up yet are absent.
+#[utoipa::path(
+    get,
+    path = "/api/patients/{id}/rollups",
+    tag = "patients",
+    params(("id" = String, Path, description = "Patient id"), RollupQuery),
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 200, description = "Rollups, oldest day first", body = [DailyRollup]),
+        (status = 400, description = "Invalid range", body = ErrBody),
+        (status = 401, description = "Missing or invalid token", body = ErrBody),
+        (status = 403, description = "Device tokens cannot read patient data", body = ErrBody),
+    )
+)]
+async fn get_daily_rollups(
+    req: HttpRequest,
+    state: web::Data<Arc<Mutex<AppState>>>,
+    path: web::Path<String>,
+    q: web::Query<RollupQuery>,
+) -> Result<HttpResponse, AppError> {
+    let claims = get_claims_from_request(&req).ok_or(AppError::Unauthorized)?;
+    let patient_id = path.into_inner();
+
+    if q.to < q.from {
+        return Err(AppError::BadRequest("to must not be before from".into()));
+    }
+    if (q.to - q.from).num_days() >= MAX_ROLLUP_RANGE_DAYS {
+        return Err(AppError::BadRequest(format!(
+            "range must not span more than {} days",
+            MAX_ROLLUP_RANGE_DAYS
+        )));
+    }
+
+    authorize_patient_access(&req, &state, &claims, &patient_id, "DailyRollup").await?;
+
+    let rollups = state
+        .lock()
+        .await
+        .daily_rollups(&patient_id, q.from, q.to)
+        .await?;
+
+    Ok(HttpResponse::Ok().json(rollups))
+}
+
+/// Rolling mean and maximum of each of the patient's signals over the last
+/// minute and the last five: the figures `/ws/live` sends in its `stats`
+/// frames. Empty while the patient has no readings in the last five
+/// minutes.
+#[utoipa::path(
+    get,
+    path = "/api/patients/{id}/gauge",
+    tag = "patients",
+    params(("id" = String, Path, description = "Patient id")),
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 200, description = "Statistics per signal and window", body = [StatsFrame]),
+        (status = 401, description = "Missing or invalid token", body = ErrBody),
+        (status = 403, description = "No access to the patient", body = ErrBody),
+    )
+)]
+async fn get_patient_gauge(
+    req: HttpRequest,
+    state: web::Data<Arc<Mutex<AppState>>>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, AppError> {
+    let claims = get_claims_from_request(&req).ok_or(AppError::Unauthorized)?;
+    let patient_id = path.into_inner();
+    authorize_patient_access(&req, &state, &claims, &patient_id, "PatientGauge").await?;
+
+    let stats = state.lock().await.live_stats();
+    Ok(HttpResponse::Ok().json(stats.frames(chrono::Utc::now(), Some(&patient_id), None)))
+}
+
+#[derive(serde::Deserialize, IntoParams)]
+struct PatientSummaryQuery {
+    /// First day of the week, `YYYY-MM-DD` (UTC)
+    week_start: chrono::NaiveDate,
+}
+
+/// Check access to `patient_id` and summarize their week
+async fn patient_summary(
+    req: &HttpRequest,
+    state: &web::Data<Arc<Mutex<AppState>>>,
+    patient_id: &str,
+    week_start: chrono::NaiveDate,
+) -> Result<PatientSummaryReport, AppError> {
+    let claims = get_claims_from_request(req).ok_or(AppError::Unauthorized)?;
+    authorize_patient_access(req, state, &claims, patient_id, "PatientSummaryReport").await?;
+
+    state
+        .lock()
+        .await
+        .patient_summary(patient_id, week_start)
+        .await?
+        .ok_or_else(|| {
+            AppError::NotFound(format!(
+                "decibel readings for patient {} in the week from {}",
+                patient_id, week_start
+            ))
+        })
+}
+
+/// A patient's week of noise exposure in plain English: average daily TWA,
+/// days above 85 dB, the loudest reading, advice for the week's dose and
+/// the trend against the week before
+#[utoipa::path(
+    get,
+    path = "/api/reports/patient-summary/{patient_id}",
+    tag = "reports",
+    params(("patient_id" = String, Path, description = "Patient id"), PatientSummaryQuery),
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 200, description = "Weekly summary", body = PatientSummaryReport),
+        (status = 400, description = "Invalid week_start", body = ErrBody),
+        (status = 401, description = "Missing or invalid token", body = ErrBody),
+        (status = 403, description = "No access to the patient", body = ErrBody),
+        (status = 404, description = "No decibel readings that week", body = ErrBody),
+    )
+)]
+async fn get_patient_summary(
+    req: HttpRequest,
+    state: web::Data<Arc<Mutex<AppState>>>,
+    path: web::Path<String>,
+    q: web::Query<PatientSummaryQuery>,
+) -> Result<HttpResponse, AppError> {
+    let report = patient_summary(&req, &state, &path.into_inner(), q.week_start).await?;
+    Ok(HttpResponse::Ok().json(report))
+}
+
+/// The unit a query asks values to be converted to, if any; 400 for one
+/// they can't be
+fn target_unit(unit: Option<&str>) -> Result<Option<TargetUnit>, AppError> {
+    unit.map(str::parse::<TargetUnit>)
+        .transpose()
+        .map_err(AppError::BadRequest)
+}
+
+#[derive(serde::Deserialize, IntoParams)]
+struct DashboardQuery {
+    /// `dB` to convert the last reading to decibels, a raw one by its
+    /// device's latest calibration; one that can't be is given as stored,
+    /// with `conversion: "none"`
+    unit: Option<String>,
+    /// With `unit`, give the latest reading that can be converted instead
+    strict: Option<bool>,
+}
+
+/// Everything a clinical dashboard shows for one patient: the latest
+/// reading, this hour's level, today's noise dose, alerts, devices and
+/// hourly levels over the last day. Cached for `DASHBOARD_CACHE_SECS` and
+/// rate limited per account.
+#[utoipa::path(
+    get,
+    path = "/api/dashboard/patient/{patient_id}",
+    tag = "patients",
+    params(("patient_id" = String, Path, description = "Patient id"), DashboardQuery),
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 200, description = "Current state of the patient", body = PatientDashboard),
+        (status = 400, description = "Unsupported unit", body = ErrBody),
+        (status = 401, description = "Missing or invalid token", body = ErrBody),
+        (status = 403, description = "No access to the patient", body = ErrBody),
+        (status = 429, description = "Too many dashboard requests", body = ErrBody),
+    )
+)]
+async fn get_patient_dashboard(
+    req: HttpRequest,
+    state: web::Data<Arc<Mutex<AppState>>>,
+    hub: web::Data<WsHub>,
+    dashboards: web::Data<Dashboards>,
+    path: web::Path<String>,
+    q: web::Query<DashboardQuery>,
+) -> Result<HttpResponse, AppError> {
+    let patient_id = path.into_inner();
+    let claims = get_claims_from_request(&req).ok_or(AppError::Unauthorized)?;
+    dashboards.check_rate(&claims.sub)?;
+    let unit = target_unit(q.unit.as_deref())?.map(|target| (target, q.strict.unwrap_or(false)));
+    authorize_patient_access(&req, &state, &claims, &patient_id, "PatientDashboard").await?;
+
+    if let Some(dashboard) = dashboards.cached(&patient_id, unit) {
+        return Ok(HttpResponse::Ok().json(dashboard));
+    }
+
+    let now = chrono::Utc::now();
+    let (readings, settings, units) = {
+        let state = state.lock().await;
+        let units = match unit {
+            Some((target, strict)) => Some(state.unit_conversion(target, strict).await?),
+            None => None,
+        };
+        (
+            state
+                .dashboard_readings(&patient_id, now, units.as_ref())
+                .await?,
+            state.device_settings(),
+            units,
+        )
+    };
+    let dashboard = PatientDashboard::build(
+        &patient_id,
+        now,
+        readings,
+        hub.recent_anomalies(&patient_id, day_start(now)),
+        &settings.all().await?,
+        dashboards.offline_grace,
+        units.as_ref(),
+    );
+    dashboards.store(&dashboard, unit);
+    Ok(HttpResponse::Ok().json(dashboard))
+}
+
+/// The weekly summary as a one-page PDF, ready to email to the patient
+#[utoipa::path(
+    get,
+    path = "/api/reports/patient-summary/{patient_id}.pdf",
+    tag = "reports",
+    params(("patient_id" = String, Path, description = "Patient id"), PatientSummaryQuery),
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 200, description = "Weekly summary", content_type = "application/pdf", body = Vec<u8>),
+        (status = 400, description = "Invalid week_start", body = ErrBody),
+        (status = 401, description = "Missing or invalid token", body = ErrBody),
+        (status = 403, description = "No access to the patient", body = ErrBody),
+        (status = 404, description = "No decibel readings that week", body = ErrBody),
+    )
+)]
+async fn get_patient_summary_pdf(
+    req: HttpRequest,
+    state: web::Data<Arc<Mutex<AppState>>>,
+    path: web::Path<String>,
+    q: web::Query<PatientSummaryQuery>,
+) -> Result<HttpResponse, AppError> {
+    let report = patient_summary(&req, &state, &path.into_inner(), q.week_start).await?;
+    Ok(HttpResponse::Ok()
+        .content_type("application/pdf")
+        .insert_header((
+            header::CONTENT_DISPOSITION,
+            format!(
+                "inline; filename=\"noise-summary-{}.pdf\"",
+                report.week_start
+            ),
+        ))
+        .body(report.to_pdf()))
+}
+
+/// Ingest counters of a device since the process started, with the totals
+/// the database holds for it. 404 for a device that sent nothing since
+/// startup and has no stored readings.
+#[utoipa::path(
+    get,
+    path = "/api/devices/{id}/stats",
+    tag = "devices",
+    params(("id" = String, Path, description = "Device id")),
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 200, description = "Device statistics", body = DeviceStats),
+        (status = 401, description = "Missing or invalid token", body = ErrBody),
+        (status = 403, description = "Device tokens cannot read statistics", body = ErrBody),
+        (status = 404, description = "Unknown device", body = ErrBody),
+    )
+)]
+async fn get_device_stats(
+    req: HttpRequest,
+    state: web::Data<Arc<Mutex<AppState>>>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, AppError> {
+    let claims = get_claims_from_request(&req).ok_or(AppError::Unauthorized)?;
+    if !claims.can(Permission::ReadData) {
+        return Err(AppError::Forbidden(
+            "device tokens cannot read device statistics".into(),
+        ));
+    }
+
+    let device_id = path.into_inner();
+    let stats = state
+        .lock()
+        .await
+        .device_stats(&device_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("device {}", device_id)))?;
+
+    Ok(HttpResponse::Ok().json(stats))
+}
+
+/// Check a device against a reference tone (admin only). Start the tone,
+/// then call this: the request stays open for `duration_secs` while the
+/// device's readings are collected, and returns how far their average is
+/// from `reference_db`. The device's calibration status becomes `verified`
+/// or `failed` accordingly.
+#[utoipa::path(
+    post,
+    path = "/api/calibrate/device/{id}/verify",
+    tag = "devices",
+    params(("id" = String, Path, description = "Device id")),
+    request_body = VerifyRequest,
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 200, description = "Verification result", body = CalibrationVerification),
+        (status = 400, description = "Invalid reference level or duration", body = ErrBody),
+        (status = 401, description = "Missing token or not an admin", body = ErrBody),
+        (status = 409, description = "No decibel readings from the device during the window", body = ErrBody),
+    )
+)]
+async fn verify_device_calibration(
+    req: HttpRequest,
+    state: web::Data<Arc<Mutex<AppState>>>,
+    path: web::Path<String>,
+    body: web::Json<VerifyRequest>,
+) -> Result<HttpResponse, AppError> {
+    let claims = require_admin(&req)?;
+    body.validate().map_err(AppError::BadRequest)?;
+
+    let device_id = path.into_inner();
+    let start = chrono::Utc::now();
+    // Readings keep arriving meanwhile, so the state is not held while waiting
+    tokio::time::sleep(std::time::Duration::from_secs(body.duration_secs.into())).await;
+    let end = chrono::Utc::now();
+
+    let verification = state
+        .lock()
+        .await
+        .verify_calibration(&device_id, body.reference_db, start, end)
+        .await?;
+
+    tracing::info!(device_id = %device_id, user = %claims.sub, passed = verification.passed, "Verified device calibration");
+    Ok(HttpResponse::Ok().json(verification))
+}
+
+/// A newly provisioned device secret
+#[derive(serde::Serialize, ToSchema)]
+struct DeviceSecretResponse {
+    device_id: String,
+    /// Shown only in this response; load it onto the device
+    device_secret: String,
+}
+
+/// Give a device its own secret for challenge-response authentication (admin
+/// only). Calling it again rotates the secret and cancels open challenges.
+#[utoipa::path(
+    post,
+    path = "/api/devices/{id}/secret",
+    tag = "devices",
+    params(("id" = String, Path, description = "Device id")),
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 201, description = "Provisioned secret", body = DeviceSecretResponse),
+        (status = 401, description = "Missing token or not an admin", body = ErrBody),
+    )
+)]
+async fn provision_device_secret(
+    req: HttpRequest,
+    state: web::Data<Arc<Mutex<AppState>>>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, AppError> {
+    let claims = require_admin(&req)?;
+    let device_id = path.into_inner();
+    let (device_auth, users) = {
+        let state = state.lock().await;
+        (state.device_auth(), state.users())
+    };
+
+    let device_secret = device_auth.provision(&device_id).await?;
+    users
+        .audit(
+            AuditLogEntry::new(AuditAction::Update, "DeviceSecret".to_string())
+                .with_user(claims.sub, claims.role)
+                .with_resource_id(device_id.clone())
+                .with_request_context(None, None, Some(req.path().to_string()))
+                .with_status_code(201),
+        )
+        .await;
+
+    tracing::info!(device_id = %device_id, "Provisioned device secret");
+    Ok(HttpResponse::Created().json(DeviceSecretResponse {
+        device_id,
+        device_secret,
+    }))
+}
+
+/// A newly provisioned IoT device
+#[derive(serde::Serialize, ToSchema)]
+struct IotDeviceResponse {
+    device_id: String,
+    /// Shown only in this response; burn it into the device
+    factory_key: String,
+}
+
+/// Register an IoT device and generate its factory key (admin only); 409 if
+/// the device is registered already
+#[utoipa::path(
+    post,
+    path = "/api/admin/iot-devices",
+    tag = "devices",
+    request_body = DeviceChallengeRequest,
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 201, description = "Provisioned device", body = IotDeviceResponse),
+        (status = 400, description = "Invalid device id", body = ErrBody),
+        (status = 401, description = "Missing token or not an admin", body = ErrBody),
+        (status = 409, description = "Device already provisioned", body = ErrBody),
+    )
+)]
+async fn provision_iot_device(
+    req: HttpRequest,
+    state: web::Data<Arc<Mutex<AppState>>>,
+    body: web::Json<DeviceChallengeRequest>,
+) -> Result<HttpResponse, AppError> {
+    let claims = require_admin(&req)?;
+    let device_id = body.into_inner().device_id;
+    if device_id.trim().is_empty() || device_id.len() > 255 {
+        return Err(AppError::BadRequest("invalid device id".into()));
+    }
+    let (iot_auth, users) = {
+        let state = state.lock().await;
+        (state.iot_auth(), state.users())
+    };
+
+    let factory_key = iot_auth.provision(&device_id).await?;
+    users
+        .audit(
+            AuditLogEntry::new(AuditAction::Create, "IotDevice".to_string())
+                .with_user(claims.sub, claims.role)
+                .with_resource_id(device_id.clone())
+                .with_request_context(None, None, Some(req.path().to_string()))
+                .with_status_code(201),
+        )
+        .await;
+
+    tracing::info!(device_id = %device_id, "Provisioned IoT device");
+    Ok(HttpResponse::Created().json(IotDeviceResponse {
+        device_id,
+        factory_key,
+    }))
+}
+
+/// Set a device's status and offline grace period (admin only). Devices
+/// that report infrequently get a longer grace period; decommissioned ones
+/// never raise offline alerts.
+#[utoipa::path(
+    put,
+    path = "/api/devices/{id}",
+    tag = "devices",
+    params(("id" = String, Path, description = "Device id")),
+    request_body = DeviceSettingsUpdate,
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 200, description = "Updated settings", body = DeviceSettings),
+        (status = 400, description = "Invalid body", body = ErrBody),
+        (status = 401, description = "Missing token or not an admin", body = ErrBody),
+    )
+)]
+async fn set_device_settings(
+    req: HttpRequest,
+    state: web::Data<Arc<Mutex<AppState>>>,
+    path: web::Path<String>,
+    body: web::Json<DeviceSettingsUpdate>,
+) -> Result<HttpResponse, AppError> {
+    let claims = require_admin(&req)?;
+    let device_id = path.into_inner();
+    if device_id.trim().is_empty() || device_id.len() > 255 {
+        return Err(AppError::BadRequest("invalid device id".into()));
+    }
+    let body = body.into_inner();
+    body.validate().map_err(AppError::BadRequest)?;
+
+    let (device_settings, users) = {
+        let state = state.lock().await;
+        (state.device_settings(), state.users())
+    };
+    let settings = device_settings.put(&device_id, body).await?;
+    {
+        let mut state = state.lock().await;
+        state.set_decimation_interval(&device_id, settings.decimation_interval_ms);
+        state.set_device_profile(&device_id, settings.profile());
+    }
+    users
+        .audit(
+            AuditLogEntry::new(AuditAction::Update, "DeviceSettings".to_string())
+                .with_user(claims.sub, claims.role)
+                .with_resource_id(device_id.clone())
+                .with_request_context(None, None, Some(req.path().to_string()))
+                .with_status_code(200),
+        )
+        .await;
+
+    tracing::info!(
+        device_id = %device_id,
+        status = settings.status.as_str(),
+        offline_grace_secs = ?settings.offline_grace_secs,
+        decimation_interval_ms = ?settings.decimation_interval_ms,
+        normalized = settings.profile().is_some(),
+        "Updated device settings"
+    );
+    Ok(HttpResponse::Ok().json(settings))
+}
+
+/// A device's calibration status and past verifications, newest first
+#[utoipa::path(
+    get,
+    path = "/api/devices/{id}/calibration-history",
+    tag = "devices",
+    params(("id" = String, Path, description = "Device id")),
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 200, description = "Calibration history", body = CalibrationHistory),
+        (status = 401, description = "Missing or invalid token", body = ErrBody),
+        (status = 403, description = "Device tokens cannot read calibration history", body = ErrBody),
+    )
+)]
+async fn get_calibration_history(
+    req: HttpRequest,
+    state: web::Data<Arc<Mutex<AppState>>>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, AppError> {
+    let claims = get_claims_from_request(&req).ok_or(AppError::Unauthorized)?;
+    if !claims.can(Permission::ReadData) {
+        return Err(AppError::Forbidden(
+            "device tokens cannot read calibration history".into(),
+        ));
+    }
+
+    let history = state
+        .lock()
+        .await
+        .calibration_history(&path.into_inner())
+        .await?;
+    Ok(HttpResponse::Ok().json(history))
+}
+
+#[derive(serde::Deserialize, IntoParams)]
+struct BulkExportQuery {
+    /// Only Observations taken at or after this RFC 3339 time
+    #[serde(rename = "_since")]
+    #[param(rename = "_since")]
+    since: Option<chrono::DateTime<chrono::Utc>>,
+    /// Resource types to export; only `Observation` is supported
+    #[serde(rename = "_type")]
+    #[param(rename = "_type")]
+    resource_type: Option<String>,
+}
+
+/// Start exporting every Observation the caller may read as gzip-compressed
+/// NDJSON. The
+/// export runs in the background; poll the `Content-Location` URL.
+#[utoipa::path(
+    post,
+    path = "/api/fhir/$export",
+    tag = "fhir",
+    params(BulkExportQuery),
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 202, description = "Export started; status URL in `Content-Location`"),
+        (status = 400, description = "Unsupported `_type`", body = ErrBody),
+        (status = 401, description = "Missing or invalid token", body = ErrBody),
+        (status = 403, description = "Device tokens cannot read patient data", body = ErrBody),
+        (status = 409, description = "The caller has an export in progress", body = ErrBody),
+    )
+)]
+async fn start_bulk_export(
+    req: HttpRequest,
+    state: web::Data<Arc<Mutex<AppState>>>,
+    exports: web::Data<BulkExports>,
+    q: web::Query<BulkExportQuery>,
+) -> Result<HttpResponse, AppError> {
+    let claims = require_permission(&req, Permission::ReadData)?;
+    kick_off_export(&req, &state, exports, &claims, &q).await
+}
+
+/// Start exporting every Observation as gzip-compressed NDJSON (admin only).
+/// The export runs in the background; poll the `Content-Location` URL.
+#[utoipa::path(
+    get,
+    path = "/api/fhir/Observation/$export",
+    tag = "fhir",
+    params(BulkExportQuery),
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 202, description = "Export started; status URL in `Content-Location`"),
+        (status = 400, description = "Unsupported `_type`", body = ErrBody),
+        (status = 401, description = "Missing or invalid token, or not an admin", body = ErrBody),
+        (status = 409, description = "The caller has an export in progress", body = ErrBody),
+    )
+)]
+async fn start_observation_export(
+    req: HttpRequest,
+    state: web::Data<Arc<Mutex<AppState>>>,
+    exports: web::Data<BulkExports>,
+    q: web::Query<BulkExportQuery>,
+) -> Result<HttpResponse, AppError> {
+    let claims = require_admin(&req)?;
+    kick_off_export(&req, &state, exports, &claims, &q).await
+}
+
+/// Start an export of the Observations `claims` may read, answering 202
+/// with its status URL
+async fn kick_off_export(
+    req: &HttpRequest,
+    state: &web::Data<Arc<Mutex<AppState>>>,
+    exports: web::Data<BulkExports>,
+    claims: &Claims,
+    q: &BulkExportQuery,
+) -> Result<HttpResponse, AppError> {
+    if let Some(types) = q.resource_type.as_deref() {
+        if types.split(',').any(|t| t.trim() != "Observation") {
+            return Err(AppError::BadRequest(
+                "only Observation can be exported".into(),
+            ));
+        }
+    }
+
+    let job = exports
+        .into_inner()
+        .start(
+            state.get_ref().clone(),
+            &claims.sub,
+            &claims.role,
+            claims.patient_filter().map(<[String]>::to_vec),
+            q.since,
+            req.full_url().to_string(),
+        )
+        .await?;
+    tracing::info!(job_id = %job.job_id, "User {} started a bulk export", claims.sub);
+
+    let users = state.lock().await.users();
+    users
+        .audit(
+            AuditLogEntry::new(AuditAction::Create, "BulkExport".to_string())
+                .with_user(claims.sub.clone(), claims.role.clone())
+                .with_resource_id(job.job_id.to_string())
+                .with_request_context(None, None, Some(req.path().to_string()))
+                .with_status_code(202)
+                .with_metadata(serde_json::json!({
+                    "since": q.since,
+                    "patients": claims.patient_filter(),
+                })),
+        )
+        .await;
+
+    let status_url = req
+        .full_url()
+        .join(&format!("/api/fhir/$export/{}", job.job_id))
+        .map_err(|_| AppError::Internal)?;
+    Ok(HttpResponse::Accepted()
+        .insert_header((header::CONTENT_LOCATION, status_url.to_string()))
+        .finish())
+}
+
+/// The caller's (or, for admins, any) export job; 404 for others
+async fn visible_export(
+    req: &HttpRequest,
+    exports: &BulkExports,
+    job_id: &str,
+) -> Result<ExportJob, AppError> {
+    let claims = require_permission(req, Permission::ReadData)?;
+    let not_found = || AppError::NotFound(format!("export {}", job_id));
+    let job_id = uuid::Uuid::parse_str(job_id).map_err(|_| not_found())?;
+    exports
+        .get(job_id)
+        .await
+        .filter(|job| job.visible_to(&claims.sub, &claims.role))
+        .ok_or_else(not_found)
+}
+
+/// Status of an export: 202 while it runs, then the manifest listing its
+/// output files
+#[utoipa::path(
+    get,
+    path = "/api/fhir/$export/{job_id}",
+    tag = "fhir",
+    params(("job_id" = String, Path, description = "Export job id")),
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 200, description = "Finished; output files listed", body = ExportManifest),
+        (status = 202, description = "Still running; progress in `X-Progress`"),
+        (status = 401, description = "Missing or invalid token", body = ErrBody),
+        (status = 404, description = "No such export", body = ErrBody),
+        (status = 500, description = "The export failed", body = ErrBody),
+    )
+)]
+async fn bulk_export_status(
+    req: HttpRequest,
+    exports: web::Data<BulkExports>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, AppError> {
+    let job = visible_export(&req, &exports, &path.into_inner()).await?;
+
+    match job.status {
+        ExportStatus::InProgress => Ok(HttpResponse::Accepted()
+            .insert_header((
+                "X-Progress",
+                format!("{} observations exported", job.exported),
+            ))
+            .insert_header((header::RETRY_AFTER, "2"))
+            .finish()),
+        ExportStatus::Completed => {
+            let status_url = req
+                .full_url()
+                .join(&format!("/api/fhir/$export/{}", job.job_id))
+                .map_err(|_| AppError::Internal)?;
+            Ok(HttpResponse::Ok().json(ExportManifest::new(&job, status_url.as_str())))
+        }
+        ExportStatus::Failed | ExportStatus::Cancelled => Ok(HttpResponse::InternalServerError()
+            .json(serde_json::json!({
+                "error": job.error.unwrap_or_else(|| "export failed".into()),
+                "code": "export_failed",
+            }))),
+    }
+}
+
+/// Cancel an export, or delete the files of a finished one. Its status URL
+/// answers 404 afterwards.
+#[utoipa::path(
+    delete,
+    path = "/api/fhir/$export/{job_id}",
+    tag = "fhir",
+    params(("job_id" = String, Path, description = "Export job id")),
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 202, description = "Cancelled, or its files deleted"),
+        (status = 401, description = "Missing or invalid token", body = ErrBody),
+        (status = 404, description = "No such export", body = ErrBody),
+    )
+)]
+async fn cancel_bulk_export(
+    req: HttpRequest,
+    exports: web::Data<BulkExports>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, AppError> {
+    let job = visible_export(&req, &exports, &path.into_inner()).await?;
+    if !exports.cancel(job.job_id).await {
+        return Err(AppError::NotFound(format!("export {}", job.job_id)));
+    }
+    tracing::info!(job_id = %job.job_id, "Bulk export deleted");
+    Ok(HttpResponse::Accepted().finish())
+}
+
+/// Chunk size used to stream export files
+const EXPORT_CHUNK_BYTES: usize = 64 * 1024;
+
+/// One gzip-compressed NDJSON file of a finished export, streamed from disk
+/// as stored
+#[utoipa::path(
+    get,
+    path = "/api/fhir/$export/{job_id}/{file}",
+    tag = "fhir",
+    params(
+        ("job_id" = String, Path, description = "Export job id"),
+        ("file" = String, Path, description = "File name from the manifest"),
+    ),
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 200, description = "One Observation per line, with `Content-Encoding: gzip`", content_type = "application/fhir+ndjson", body = String),
+        (status = 401, description = "Missing or invalid token", body = ErrBody),
+        (status = 404, description = "No such export or file, or not finished", body = ErrBody),
+    )
+)]
+async fn bulk_export_file(
+    req: HttpRequest,
+    exports: web::Data<BulkExports>,
+    path: web::Path<(String, String)>,
+) -> Result<HttpResponse, AppError> {
+    let (job_id, name) = path.into_inner();
+    let job = visible_export(&req, &exports, &job_id).await?;
+    if job.status != ExportStatus::Completed || !job.has_file(&name) {
+        return Err(AppError::NotFound(format!(
+            "output {} of export {}",
+            name, job.job_id
+        )));
+    }
+
+    let path = exports.output_path(job.job_id, &name);
+    let file = tokio::task::spawn_blocking(move || std::fs::File::open(path))
+        .await
+        .map_err(|_| AppError::Internal)?
+        .map_err(|e| {
+            tracing::error!(job_id = %job.job_id, error = %e, "Bulk export file missing");
+            AppError::NotFound(format!("output of export {}", job.job_id))
+        })?;
+
+    // Read a chunk at a time off the runtime's threads
+    let chunks = futures_util::stream::try_unfold(file, |mut file| async move {
+        tokio::task::spawn_blocking(move || {
+            use std::io::Read;
+            let mut chunk = vec![0; EXPORT_CHUNK_BYTES];
+            let read = file.read(&mut chunk)?;
+            chunk.truncate(read);
+            Ok::<_, std::io::Error>((read > 0).then(|| (web::Bytes::from(chunk), file)))
+        })
+        .await
+        .map_err(std::io::Error::other)?
+    });
+
+    // Already compressed; the compression middleware leaves encoded
+    // responses alone
+    Ok(HttpResponse::Ok()
+        .content_type("application/fhir+ndjson")
+        .insert_header((header::CONTENT_ENCODING, "gzip"))
+        .streaming(chunks))
+}
+
+/// Every signal code, for terminology servers and validators
+#[utoipa::path(
+    get,
+    path = "/fhir/ValueSet/soundsense-signal-codes",
+    tag = "fhir",
+    responses(
+        (status = 200, description = "ValueSet of the LOINC codes of every signal", body = FhirValueSet),
+    )
+)]
+async fn get_signal_code_value_set() -> HttpResponse {
+    HttpResponse::Ok().json(FhirValueSet::signal_codes())
+}
+
+#[derive(serde::Deserialize, IntoParams)]
+struct ValidateCodeQuery {
+    /// Code to look up, e.g. `sound`
+    code: String,
+    /// Its code system, e.g. `http://loinc.org`; any if not given
+    system: Option<String>,
+}
+
+/// Outcome of `$validate-code`
+#[derive(serde::Serialize, ToSchema)]
+struct ValidateCodeResult {
+    result: bool,
+}
+
+/// Whether a code is a signal code
+#[utoipa::path(
+    get,
+    path = "/fhir/ValueSet/soundsense-signal-codes/$validate-code",
+    tag = "fhir",
+    params(ValidateCodeQuery),
+    responses(
+        (status = 200, description = "Whether the code is in the ValueSet", body = ValidateCodeResult),
+        (status = 400, description = "No code given", body = ErrBody),
+    )
+)]
+async fn validate_signal_code(q: web::Query<ValidateCodeQuery>) -> HttpResponse {
+    let result = FhirValueSet::signal_codes().contains(q.system.as_deref(), &q.code);
+    HttpResponse::Ok().json(ValidateCodeResult { result })
+}
+
+#[derive(serde::Deserialize, IntoParams)]
+struct MeasureReportQuery {
+    /// RFC 3339 timestamp or `YYYY-MM-DD` (start of that day)
+    period_start: String,
+    /// RFC 3339 timestamp or `YYYY-MM-DD` (end of that day)
+    period_end: String,
+}
+
+/// Population-level noise exposure for a reporting period (admin only)
+#[utoipa::path(
+    get,
+    path = "/api/fhir/MeasureReport",
+    tag = "fhir",
+    params(MeasureReportQuery),
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 200, description = "Summary MeasureReport, one group per signal", body = FhirMeasureReport),
+        (status = 400, description = "Invalid period", body = ErrBody),
+        (status = 401, description = "Missing token or not an admin", body = ErrBody),
+    )
+)]
+async fn get_measure_report(
+    req: HttpRequest,
+    state: web::Data<Arc<Mutex<AppState>>>,
+    q: web::Query<MeasureReportQuery>,
+) -> Result<HttpResponse, AppError> {
+    let claims = require_admin(&req)?;
+
+    let period = FhirPeriod {
+        start: parse_range_bound("period_start", &q.period_start, chrono::NaiveTime::MIN)?,
+        end: parse_range_bound(
+            "period_end",
+            &q.period_end,
+            chrono::NaiveTime::from_hms_opt(23, 59, 59).unwrap_or_default(),
+        )?,
+    };
+    if period.end <= period.start {
+        return Err(AppError::BadRequest(
+            "period_end must be after period_start".into(),
+        ));
+    }
+
+    let counts = state
+        .lock()
+        .await
+        .population_counts(period.start, period.end)
+        .await?;
+    let report = FhirMeasureReport::noise_exposure(period, &counts);
+    report.validate().map_err(AppError::BadRequest)?;
+
+    tracing::info!("MeasureReport generated for admin {}", claims.sub);
+
+    Ok(HttpResponse::Ok().json(report))
+}
+
+/// Confirmation required to erase a patient's data
+#[derive(serde::Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+struct EraseRequest {
+    /// The patient id again, guarding against erasing the wrong patient
+    confirm: String,
+}
+
+/// Outcome of erasing a patient's data
+#[derive(serde::Serialize, ToSchema)]
+struct EraseResponse {
+    /// Readings deleted
+    deleted: u64,
+}
+
+/// Erase every reading of a patient who withdrew consent, together with
+/// device registrations only they used and bulk exports that included them
+/// (admin only). Audit entries are kept, and every attempt is audited.
+#[utoipa::path(
+    delete,
+    path = "/api/patients/{id}/observations",
+    tag = "patients",
+    params(("id" = String, Path, description = "Patient id")),
+    request_body = EraseRequest,
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 200, description = "Readings deleted", body = EraseResponse),
+        (status = 400, description = "Missing or mismatched confirmation", body = ErrBody),
+        (status = 401, description = "Missing token or not an admin", body = ErrBody),
+        (status = 404, description = "No such patient", body = ErrBody),
+    )
+)]
+async fn erase_patient_observations(
+    req: HttpRequest,
+    state: web::Data<Arc<Mutex<AppState>>>,
+    hub: web::Data<WsHub>,
+    dashboards: web::Data<Dashboards>,
+    exports: web::Data<BulkExports>,
+    path: web::Path<String>,
+    body: web::Json<EraseRequest>,
+) -> Result<HttpResponse, AppError> {
+    let patient_id = path.into_inner();
+
+    // Refused attempts are audited too; the erasure audits itself
+    let claims = match require_admin(&req).and_then(|claims| {
+        if body.confirm != patient_id {
+            return Err(AppError::BadRequest(
+                "confirm must repeat the patient id".into(),
+            ));
+        }
+        Ok(claims)
+    }) {
+        Ok(claims) => claims,
+        Err(e) => {
+            let action = match e {
+                AppError::BadRequest(_) => AuditAction::Delete,
+                _ => AuditAction::AccessDenied,
+            };
+            let mut entry = AuditLogEntry::new(action, "Patient".to_string())
+                .with_resource_id(patient_id.clone())
+                .with_patient_id(patient_id.clone())
+                .with_request_context(None, None, Some(req.path().to_string()))
+                .with_status_code(e.status_code().as_u16().into())
+                .with_error(e.to_string());
+            if let Some(claims) = get_claims_from_request(&req) {
+                entry = entry.with_user(claims.sub, claims.role);
+            }
+            state.lock().await.users().audit(entry).await;
+            return Err(e);
+        }
+    };
+
+    let erasure = state
+        .lock()
+        .await
+        .erase_patient(&patient_id, &claims)
+        .await?;
+    hub.forget_patient(&patient_id);
+    dashboards.invalidate(&patient_id);
+    let exports = exports.forget_patient(&patient_id).await;
+
+    tracing::info!(
+        patient_id,
+        deleted = erasure.readings,
+        devices = erasure.devices.len(),
+        exports,
+        "User {} erased patient data",
+        claims.sub
+    );
+    Ok(HttpResponse::Ok().json(EraseResponse {
+        deleted: erasure.readings,
+    }))
+}
+
+// Octave-band ingest from professional microphone modules (JWT required)
+#[utoipa::path(
+    post,
+    path = "/api/ingest/octave-bands",
+    tag = "ingest",
+    request_body = OctaveBandReading,
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 200, description = "Reading stored; one component per band", body = FhirObservation),
+        (status = 202, description = "Reading quarantined for lack of consent", body = FhirObservation),
+        (status = 400, description = "Invalid reading", body = ErrBody),
+        (status = 401, description = "Missing or invalid token", body = ErrBody),
+        (status = 403, description = "No consent on record for the patient", body = ErrBody),
+        (status = 413, description = "Body too large", body = ErrBody),
+        (status = 429, description = "Too many concurrent ingests", body = ErrBody),
+    )
+)]
+async fn ingest_octave_bands(
+    req: HttpRequest,
+    state: web::Data<Arc<Mutex<AppState>>>,
+    hub: web::Data<WsHub>,
+    payload: web::Json<OctaveBandReading>,
+) -> Result<HttpResponse, AppError> {
+    let claims = require_permission(&req, Permission::WriteReadings)?;
+
+    let mut reading = payload.into_inner();
+    let mut devices = vec![(reading.device_id.clone(), reading.ts)];
+
+    let result = async {
+        reading.validate().map_err(AppError::BadRequest)?;
+        if state.lock().await.a_weighting() {
+            reading.calibrated_value = Some(acoustics::octave_a_weighted(&reading.bands));
+        }
+
+        let obs = FhirObservation::from_octave_band(&reading);
+        obs.validate().map_err(AppError::BadRequest)?;
+
+        if !admit_or_quarantine(
+            &state,
+            &mut devices,
+            &reading.patient_id,
+            "octave-bands",
+            &reading,
+        )
+        .await?
+        {
+            return Ok(HttpResponse::Accepted().json(obs));
+        }
+
+        {
+            let mut st = state.lock().await;
+            st.push_octave_bands(reading, Some(&claims)).await?;
+        }
+
+        hub.publish(&obs);
+
+        Ok(HttpResponse::Ok().json(obs))
+    }
+    .await;
+
+    count_ingest_outcome(&state, &devices, &result).await;
+    result
+}
+
+// Spectrogram frames from sound level meters (JWT required)
+#[utoipa::path(
+    post,
+    path = "/api/ingest/spectrogram",
+    tag = "ingest",
+    request_body = SpectrogramFrame,
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 201, description = "Frame stored", body = SpectrogramFrame),
+        (status = 202, description = "Frame quarantined for lack of consent", body = SpectrogramFrame),
+        (status = 400, description = "Invalid frame", body = ErrBody),
+        (status = 401, description = "Missing or invalid token", body = ErrBody),
+        (status = 403, description = "No consent on record for the patient", body = ErrBody),
+        (status = 413, description = "Body too large", body = ErrBody),
+        (status = 429, description = "Too many concurrent ingests", body = ErrBody),
+    )
+)]
+async fn ingest_spectrogram(
+    req: HttpRequest,
+    state: web::Data<Arc<Mutex<AppState>>>,
+    payload: web::Json<SpectrogramFrame>,
+) -> Result<HttpResponse, AppError> {
+    let claims = require_permission(&req, Permission::WriteReadings)?;
+
+    let frame = payload.into_inner();
+    let mut devices = vec![(frame.device_id.clone(), frame.ts)];
+
+    let result = async {
+        frame.validate().map_err(AppError::BadRequest)?;
+
+        if !admit_or_quarantine(
+            &state,
+            &mut devices,
+            &frame.patient_id,
+            "spectrogram",
+            &frame,
+        )
+        .await?
+        {
+            return Ok(HttpResponse::Accepted().json(&frame));
+        }
+
+        let response = HttpResponse::Created().json(&frame);
+        state
+            .lock()
+            .await
+            .push_spectrogram_frame(frame, Some(&claims))
+            .await?;
+        Ok(response)
+    }
+    .await;
+
+    count_ingest_outcome(&state, &devices, &result).await;
+    result
+}
+
+#[derive(serde::Deserialize, IntoParams)]
+struct SpectrogramQuery {
+    patient_id: String,
+    /// RFC 3339 timestamp or `YYYY-MM-DD` (start of that day)
+    ts_from: String,
+    /// RFC 3339 timestamp or `YYYY-MM-DD` (end of that day)
+    ts_to: String,
+    /// Most frames to return, oldest first (default 100, at most 1000)
+    max_frames: Option<usize>,
+}
+
+/// A patient's spectrogram frames in a time range, oldest first
+#[utoipa::path(
+    get,
+    path = "/api/analysis/spectrogram",
+    tag = "analysis",
+    params(SpectrogramQuery),
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 200, description = "Frames in time order", body = [SpectrogramFrame]),
+        (status = 400, description = "Invalid query", body = ErrBody),
+        (status = 401, description = "Missing or invalid token", body = ErrBody),
+        (status = 403, description = "Device tokens cannot read patient data", body = ErrBody),
+    )
+)]
+async fn get_spectrogram(
+    req: HttpRequest,
+    state: web::Data<Arc<Mutex<AppState>>>,
+    q: web::Query<SpectrogramQuery>,
+) -> Result<HttpResponse, AppError> {
+    let claims = get_claims_from_request(&req).ok_or(AppError::Unauthorized)?;
+
+    if q.patient_id.trim().is_empty() {
+        return Err(AppError::BadRequest("patient_id required".into()));
+    }
+    let start = parse_range_bound("ts_from", &q.ts_from, chrono::NaiveTime::MIN)?;
+    let end = parse_range_bound(
+        "ts_to",
+        &q.ts_to,
+        chrono::NaiveTime::from_hms_opt(23, 59, 59).unwrap_or_default(),
+    )?;
+    if end < start {
+        return Err(AppError::BadRequest(
+            "ts_to must not be before ts_from".into(),
+        ));
+    }
+    let max_frames = q.max_frames.unwrap_or(DEFAULT_MAX_FRAMES);
+    if !(1..=MAX_FRAMES_LIMIT).contains(&max_frames) {
+        return Err(AppError::BadRequest(format!(
+            "max_frames must be between 1 and {}",
+            MAX_FRAMES_LIMIT
+        )));
+    }
+
+    authorize_patient_access(&req, &state, &claims, &q.patient_id, "SpectrogramFrame").await?;
+
+    let frames = state
+        .lock()
+        .await
+        .spectrogram(&q.patient_id, start, end, max_frames)
+        .await?;
+    Ok(HttpResponse::Ok().json(frames))
+}
+
+#[derive(serde::Deserialize, IntoParams)]
+struct SpectrumQuery {
+    patient_id: String,
+    /// UTC day to average over (default today)
+    date: Option<chrono::NaiveDate>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/analysis/octave-spectrum",
+    tag = "analysis",
+    params(SpectrumQuery),
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 200, description = "Energy-averaged level per octave band", body = OctaveSpectrum),
+        (status = 400, description = "Invalid query", body = ErrBody),
+        (status = 401, description = "Missing or invalid token", body = ErrBody),
+        (status = 403, description = "Device tokens cannot read patient data", body = ErrBody),
+    )
+)]
+async fn get_octave_spectrum(
+    req: HttpRequest,
+    state: web::Data<Arc<Mutex<AppState>>>,
+    q: web::Query<SpectrumQuery>,
+) -> Result<HttpResponse, AppError> {
+    let claims = get_claims_from_request(&req).ok_or(AppError::Unauthorized)?;
+
+    if q.patient_id.trim().is_empty() {
+        return Err(AppError::BadRequest("patient_id required".into()));
+    }
+    let date = q.date.unwrap_or_else(|| chrono::Utc::now().date_naive());
+
+    authorize_patient_access(&req, &state, &claims, &q.patient_id, "OctaveBandReading").await?;
+
+    let st = state.lock().await;
+    let spectrum = st.octave_spectrum(&q.patient_id, date).await?;
+
+    Ok(HttpResponse::Ok().json(spectrum))
+}
+
+#[derive(serde::Deserialize, IntoParams)]
+struct RecommendationQuery {
+    patient_id: String,
+    /// UTC day whose noise dose to assess (default today)
+    date: Option<chrono::NaiveDate>,
+}
+
+/// A-weight band levels and combine them into one dB(A) level
+#[utoipa::path(
+    post,
+    path = "/api/analysis/a-weight",
+    tag = "analysis",
+    request_body = AWeightRequest,
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 200, description = "Weighted bands and their combined level", body = AWeightedLevel),
+        (status = 400, description = "Invalid bands or frequencies", body = ErrBody),
+        (status = 401, description = "Missing or invalid token", body = ErrBody),
+    )
+)]
+async fn a_weight_bands(
+    req: HttpRequest,
+    body: web::Json<AWeightRequest>,
+) -> Result<HttpResponse, AppError> {
+    get_claims_from_request(&req).ok_or(AppError::Unauthorized)?;
+    body.validate().map_err(AppError::BadRequest)?;
+    Ok(HttpResponse::Ok().json(body.weigh()))
+}
+
+/// A patient's daily noise dose and the weakest hearing protector that
+/// brings their TWA below 85 dB
+#[utoipa::path(
+    get,
+    path = "/api/analysis/recommendation",
+    tag = "analysis",
+    params(RecommendationQuery),
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 200, description = "Dose and recommended protector (null below 85 dB)", body = ProtectionAdvice),
+        (status = 400, description = "Invalid query", body = ErrBody),
+        (status = 401, description = "Missing or invalid token", body = ErrBody),
+        (status = 403, description = "Device tokens cannot read patient data", body = ErrBody),
+        (status = 404, description = "No decibel readings that day", body = ErrBody),
+    )
+)]
+async fn get_protection_recommendation(
+    req: HttpRequest,
+    state: web::Data<Arc<Mutex<AppState>>>,
+    q: web::Query<RecommendationQuery>,
+) -> Result<HttpResponse, AppError> {
+    let claims = get_claims_from_request(&req).ok_or(AppError::Unauthorized)?;
+
+    if q.patient_id.trim().is_empty() {
+        return Err(AppError::BadRequest("patient_id required".into()));
+    }
+    let date = q.date.unwrap_or_else(|| chrono::Utc::now().date_naive());
+
+    authorize_patient_access(&req, &state, &claims, &q.patient_id, "SensorReading").await?;
+
+    let st = state.lock().await;
+    let twa_db = st.daily_twa(&q.patient_id, date).await?.ok_or_else(|| {
+        AppError::NotFound(format!(
+            "decibel readings for patient {} on {}",
+            q.patient_id, date
+        ))
+    })?;
+    let protectors = st.hearing_protectors().await?;
+
+    Ok(HttpResponse::Ok().json(ProtectionAdvice::for_exposure(twa_db, &protectors)))
+}
+
+#[derive(serde::Deserialize, IntoParams)]
+struct NoiseMapQuery {
+    /// RFC 3339 timestamp or `YYYY-MM-DD` (start of that day)
+    date_from: String,
+    /// RFC 3339 timestamp or `YYYY-MM-DD` (end of that day)
+    date_to: String,
+    /// Leave out locations with fewer readings than this (default 1)
+    min_count: Option<i64>,
+}
+
+/// Average and peak sound level per device location across all patients,
+/// from the decibel readings that carry a `location` (admin only)
+#[utoipa::path(
+    get,
+    path = "/api/analysis/noise-map",
+    tag = "analysis",
+    params(NoiseMapQuery),
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 200, description = "Locations by name", body = [LocationNoise]),
+        (status = 400, description = "Invalid query", body = ErrBody),
+        (status = 401, description = "Missing token or not an admin", body = ErrBody),
+    )
+)]
+async fn get_noise_map(
+    req: HttpRequest,
+    state: web::Data<Arc<Mutex<AppState>>>,
+    q: web::Query<NoiseMapQuery>,
+) -> Result<HttpResponse, AppError> {
+    require_admin(&req)?;
+
+    let start = parse_range_bound("date_from", &q.date_from, chrono::NaiveTime::MIN)?;
+    let end = parse_range_bound(
+        "date_to",
+        &q.date_to,
+        chrono::NaiveTime::from_hms_opt(23, 59, 59).unwrap_or_default(),
+    )?;
+    if end <= start {
+        return Err(AppError::BadRequest(
+            "date_to must be after date_from".into(),
+        ));
+    }
+    if end - start > chrono::Duration::days(MAX_ANALYSIS_RANGE_DAYS) {
+        return Err(AppError::BadRequest(format!(
+            "range must not span more than {} days",
+            MAX_ANALYSIS_RANGE_DAYS
+        )));
+    }
+    let min_count = usize::try_from(q.min_count.unwrap_or(1))
+        .map_err(|_| AppError::BadRequest("min_count must not be negative".into()))?;
+
+    let map = state.lock().await.noise_map(start, end, min_count).await?;
+
+    Ok(HttpResponse::Ok().json(map))
+}
+
+#[derive(serde::Deserialize, IntoParams)]
+struct HourlyPatternQuery {
+    patient: String,
+    /// Days before now to look at (default 7, at most 90)
+    days: Option<i64>,
+    /// UTC offset the hours are local to, `±HH:MM` (default `+00:00`)
+    tz: Option<String>,
+    /// Fewest readings an hour needs to be picked as loudest or quietest
+    /// (default 1)
+    min_count: Option<i64>,
+    /// Unit of the readings to look at (default `dB`); readings in other
+    /// units are left out. With `dB`, raw and pascal readings that can be
+    /// converted are looked at in dB.
+    unit: Option<String>,
+}
+
+/// Mean and peak of a patient's readings per local hour of the day over
+/// the last `days`, with the loudest and quietest hours. Works without the
+/// ML service.
+#[utoipa::path(
+    get,
+    path = "/api/observations/hourly-pattern",
+    tag = "analysis",
+    params(HourlyPatternQuery),
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 200, description = "Every hour of the day", body = HourlyPattern),
+        (status = 400, description = "Invalid query", body = ErrBody),
+        (status = 401, description = "Missing or invalid token", body = ErrBody),
+        (status = 403, description = "No access to the patient", body = ErrBody),
+    )
+)]
+async fn get_hourly_pattern(
+    req: HttpRequest,
+    state: web::Data<Arc<Mutex<AppState>>>,
+    q: web::Query<HourlyPatternQuery>,
+) -> Result<HttpResponse, AppError> {
+    let claims = get_claims_from_request(&req).ok_or(AppError::Unauthorized)?;
+
+    if q.patient.trim().is_empty() {
+        return Err(AppError::BadRequest("patient required".into()));
+    }
+    let days = q.days.unwrap_or(hourly_pattern::DEFAULT_DAYS);
+    if !(1..=hourly_pattern::MAX_DAYS).contains(&days) {
+        return Err(AppError::BadRequest(format!(
+            "days must be between 1 and {}",
+            hourly_pattern::MAX_DAYS
+        )));
+    }
+    let offset = hourly_pattern::parse_utc_offset(q.tz.as_deref().unwrap_or("+00:00"))
+        .map_err(AppError::BadRequest)?;
+    let unit = normalize_unit(q.unit.as_deref().unwrap_or(hourly_pattern::DEFAULT_UNIT));
+    if unit.is_empty() {
+        return Err(AppError::BadRequest("unit must not be empty".into()));
+    }
+
+    authorize_patient_access(&req, &state, &claims, &q.patient, "SensorReading").await?;
+
+    let to = chrono::Utc::now();
+    let from = to - chrono::Duration::days(days);
+    let stats = state
+        .lock()
+        .await
+        .hourly_stats(&q.patient, &unit, from, to, offset)
+        .await?;
+
+    Ok(HttpResponse::Ok().json(HourlyPattern::build(
+        &q.patient,
+        &unit,
+        offset,
+        from,
+        to,
+        stats,
+        q.min_count.unwrap_or(1),
+    )))
+}
+
+/// Most readings examined by one gap query (a day at 1 Hz fits)
+const MAX_GAP_READINGS: usize = 100_000;
+
+#[derive(serde::Deserialize, IntoParams)]
+struct GapQuery {
+    patient_id: String,
+    /// RFC 3339 timestamp or `YYYY-MM-DD` (start of that day)
+    date_from: String,
+    /// RFC 3339 timestamp or `YYYY-MM-DD` (end of that day)
+    date_to: String,
+    /// Silence between readings, in seconds, that counts as a gap (default 5,
+    /// at most 31 days)
+    max_gap_secs: Option<u64>,
+    /// `linear` to fill each gap with interpolated readings (never stored)
+    fill: Option<String>,
+}
+
+/// Gaps in a patient's readings, e.g. while a wearable was taken off
+#[utoipa::path(
+    get,
+    path = "/api/analysis/gaps",
+    tag = "analysis",
+    params(GapQuery),
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 200, description = "Gaps, oldest first", body = [Gap]),
+        (status = 400, description = "Invalid query or too many readings", body = ErrBody),
+        (status = 401, description = "Missing or invalid token", body = ErrBody),
+        (status = 403, description = "Device tokens cannot read patient data", body = ErrBody),
+    )
+)]
+async fn get_reading_gaps(
+    req: HttpRequest,
+    state: web::Data<Arc<Mutex<AppState>>>,
+    q: web::Query<GapQuery>,
+) -> Result<HttpResponse, AppError> {
+    let claims = get_claims_from_request(&req).ok_or(AppError::Unauthorized)?;
+
+    if q.patient_id.trim().is_empty() {
+        return Err(AppError::BadRequest("patient_id required".into()));
+    }
+    let start = parse_range_bound("date_from", &q.date_from, chrono::NaiveTime::MIN)?;
+    let end = parse_range_bound(
+        "date_to",
+        &q.date_to,
+        chrono::NaiveTime::from_hms_opt(23, 59, 59).unwrap_or_default(),
+    )?;
+    if end <= start {
+        return Err(AppError::BadRequest(
+            "date_to must be after date_from".into(),
+        ));
+    }
+    if end - start > chrono::Duration::days(MAX_ANALYSIS_RANGE_DAYS) {
+        return Err(AppError::BadRequest(format!(
+            "range must not span more than {} days",
+            MAX_ANALYSIS_RANGE_DAYS
+        )));
+    }
+    let max_gap_secs = q.max_gap_secs.unwrap_or(DEFAULT_MAX_GAP_SECS);
+    if !(1..=MAX_GAP_SECS).contains(&max_gap_secs) {
+        return Err(AppError::BadRequest(format!(
+            "max_gap_secs must be between 1 and {}",
+            MAX_GAP_SECS
+        )));
+    }
+    let fill = match q.fill.as_deref() {
+        None => false,
+        Some("linear") => true,
+        Some(other) => {
+            return Err(AppError::BadRequest(format!(
+                "unsupported fill '{}'; only 'linear' is available",
+                other
+            )))
+        }
+    };
+
+    authorize_patient_access(&req, &state, &claims, &q.patient_id, "SensorReading").await?;
+
+    let readings = state
+        .lock()
+        .await
+        .patient_readings(&q.patient_id, start, end, MAX_GAP_READINGS + 1)
+        .await?;
+    if readings.len() > MAX_GAP_READINGS {
+        return Err(AppError::BadRequest(format!(
+            "more than {} readings in range; narrow date_from/date_to",
+            MAX_GAP_READINGS
+        )));
+    }
+
+    let gaps = if fill {
+        GapDetector::find_and_fill(&readings, max_gap_secs)
+    } else {
+        GapDetector::find_gaps(&readings, max_gap_secs)
+    };
+
+    Ok(HttpResponse::Ok().json(gaps))
+}
+
+/// A registered patient as a FHIR Patient, the target of Observation
+/// subject references
+#[utoipa::path(
+    get,
+    path = "/api/fhir/Patient/{id}",
+    tag = "fhir",
+    params(("id" = String, Path, description = "Patient id")),
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 200, description = "Patient", body = FhirPatient),
+        (status = 401, description = "Missing or invalid token", body = ErrBody),
+        (status = 403, description = "Device token, or a patient the user may not read", body = ErrBody),
+        (status = 404, description = "No such patient", body = ErrBody),
+    )
+)]
+async fn get_fhir_patient(
+    req: HttpRequest,
+    state: web::Data<Arc<Mutex<AppState>>>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, AppError> {
+    let claims = require_permission(&req, Permission::ReadData)?;
+    let patient_id = path.into_inner();
+    if !claims.may_read_patient(&patient_id) {
+        return Err(AppError::Forbidden(format!(
+            "no access to patient {}",
+            patient_id
+        )));
+    }
+
+    let patient = state
+        .lock()
+        .await
+        .get_patient(&patient_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("Patient {}", patient_id)))?;
+
+    Ok(HttpResponse::Ok().json(FhirPatient::from_patient(&patient)))
+}
+
+/// A registered device as a FHIR Device, the target of Observation device
+/// references. Devices are registered by provisioning them or giving them
+/// settings.
+#[utoipa::path(
+    get,
+    path = "/api/fhir/Device/{id}",
+    tag = "fhir",
+    params(("id" = String, Path, description = "Device id")),
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 200, description = "Device", body = FhirDevice),
+        (status = 401, description = "Missing or invalid token", body = ErrBody),
+        (status = 403, description = "Device tokens cannot read devices", body = ErrBody),
+        (status = 404, description = "No such device", body = ErrBody),
+    )
+)]
+async fn get_fhir_device(
+    req: HttpRequest,
+    state: web::Data<Arc<Mutex<AppState>>>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, AppError> {
+    require_permission(&req, Permission::ReadData)?;
+    let device_id = path.into_inner();
+
+    let status = state
+        .lock()
+        .await
+        .registered_device(&device_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("Device {}", device_id)))?;
+
+    Ok(HttpResponse::Ok().json(FhirDevice::new(&device_id, status)))
+}
+
+#[derive(serde::Deserialize, IntoParams)]
+struct ObsQuery {
+    /// Only observations with this signal code, or an alias of it accepted
+    /// on ingest (e.g. `SoundLevel`)
+    code: Option<String>,
+    /// Only observations in this category: a code such as `activity`, or
+    /// `system|code`
+    category: Option<String>,
+    /// Maximum number of observations (default 100, at most 500)
+    limit: Option<usize>,
+    /// Number of newest observations to skip (default 0)
+    offset: Option<usize>,
+    /// Also list deleted observations, as `entered-in-error` (admin only)
+    include_deleted: Option<bool>,
+    /// Only observations whose note or metadata contain all these words,
+    /// most relevant first with a `search.score`; at most 200, no `offset`
+    #[serde(rename = "_text")]
+    #[param(rename = "_text")]
+    text: Option<String>,
+    /// `dB` to convert values to decibels, raw ones by their device's
+    /// latest calibration; values that can't be are given as stored, with
+    /// `conversion: "none"`
+    unit: Option<String>,
+    /// With `unit`, leave out observations that can't be converted instead
+    strict: Option<bool>,
+}
+
+/// `_include` of the observations' Patient resources
+const INCLUDE_SUBJECT: &str = "Observation:subject";
+/// `_include` of the observations' Device resources
+const INCLUDE_DEVICE: &str = "Observation:device";
+
+/// What the `_include` parameters, which may repeat, ask for: the subjects
+/// and the devices
+fn observation_includes(req: &HttpRequest) -> Result<(bool, bool), AppError> {
+    let params = web::Query::<Vec<(String, String)>>::from_query(req.query_string())
+        .map_err(|e| AppError::BadRequest(e.to_string()))?;
+    let (mut subject, mut device) = (false, false);
+    for (_, include) in params.iter().filter(|(name, _)| name == "_include") {
+        match include.as_str() {
+            INCLUDE_SUBJECT => subject = true,
+            INCLUDE_DEVICE => device = true,
+            other => {
+                return Err(AppError::BadRequest(format!(
+                    "unsupported _include {}; only {} and {} are supported",
+                    other, INCLUDE_SUBJECT, INCLUDE_DEVICE
+                )))
+            }
+        }
+    }
+    Ok((subject, device))
+}
+
+#[utoipa::path(
+    method(get, head),
+    path = "/api/fhir/Observation",
+    tag = "fhir",
+    params(
+        ObsQuery,
+        ("_include" = Option<String>, Query, description = "`Observation:subject` and/or `Observation:device` to append the Patient and Device resources the observations refer to, those that are registered; may be repeated"),
+    ),
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 200, description = "FHIR searchset bundle, newest first (HEAD: headers only)", body = FhirBundle,
+            headers(
+                ("ETag" = String, description = "Changes whenever the bundle does"),
+                ("X-Total-Count" = usize, description = "Observations matching the query across all pages"),
+                ("Link" = String, description = "RFC 5988 `next` and `prev` page links, when there are such pages"),
+            )),
+        (status = 400, description = "Unsupported _include or unit, or _text without a word or with offset", body = ErrBody),
+        (status = 401, description = "Missing or invalid token", body = ErrBody),
+        (status = 403, description = "Device token, or include_deleted without an admin token", body = ErrBody),
+    )
+)]
+async fn get_observations(
+    req: HttpRequest,
+    state: web::Data<Arc<Mutex<AppState>>>,
+    q: web::Query<ObsQuery>,
+) -> Result<HttpResponse, AppError> {
+    let claims = require_permission(&req, Permission::ReadData)?;
+    let patients = claims.patient_filter();
+    let include_deleted = q.include_deleted.unwrap_or(false);
+    if include_deleted && claims.role != "admin" {
+        return Err(AppError::Forbidden(
+            "only admins may list deleted observations".into(),
+        ));
+    }
+    let (include_subject, include_device) = observation_includes(&req)?;
+
+    // Aliases are accepted as on ingest
+    let code = q
+        .code
+        .as_deref()
+        .map(str::parse::<SignalCode>)
+        .transpose()
+        .map_err(AppError::BadRequest)?;
+
+    // Text search results are ranked rather than paged
+    let terms = match q.text.as_deref() {
+        Some(_) if q.offset.is_some() => {
+            return Err(AppError::BadRequest(
+                "offset cannot be combined with _text".into(),
+            ))
+        }
+        Some(text) => Some(text_search::search_terms(text)?),
+        None => None,
+    };
+
+    let limit = match terms {
+        Some(_) => q
+            .limit
+            .unwrap_or(text_search::MAX_TEXT_SEARCH_RESULTS)
+            .min(text_search::MAX_TEXT_SEARCH_RESULTS),
+        None => q.limit.unwrap_or(100).min(500),
+    };
+    // Negative offsets already fail to deserialize; the database takes an i64
+    let offset = q.offset.unwrap_or(0);
+    if i64::try_from(offset).is_err() {
+        return Err(AppError::BadRequest(format!(
+            "offset must be at most {}",
+            i64::MAX
+        )));
+    }
+    let target = target_unit(q.unit.as_deref())?;
+
+    let st = state.lock().await;
+    let units = match target {
+        Some(target) => Some(
+            st.unit_conversion(target, q.strict.unwrap_or(false))
+                .await?,
+        ),
+        None => None,
+    };
+
+    let filter = ReadingFilter {
+        code,
+        category: q.category.as_deref(),
+        patients,
+        include_deleted,
+        units: units.as_ref(),
+    };
+    let (mut bundle, total) = match &terms {
+        Some(terms) => {
+            let bundle = st.search_observations(terms, limit, &filter).await?;
+            let total = bundle.total;
+            (bundle, total)
+        }
+        None => (
+            st.bundle(limit, offset, &filter).await?,
+            st.count_observations(&filter).await?,
+        ),
+    };
+    if include_subject {
+        // Subjects without a registry entry are left out
+        let patients = st
+            .get_patients(&bundle.subject_patient_ids())
+            .await?
+            .iter()
+            .map(FhirPatient::from_patient)
+            .collect();
+        bundle.include_patients(patients);
+    }
+    if include_device {
+        // As are devices that were never registered
+        let mut devices = Vec::new();
+        for device_id in bundle.device_ids() {
+            if let Some(status) = st.registered_device(&device_id).await? {
+                devices.push(FhirDevice::new(&device_id, status));
+            }
+        }
+        bundle.include_devices(devices);
+    }
+    drop(st);
+
+    let body = serde_json::to_vec(&bundle).map_err(|_| AppError::Internal)?;
+    let mut response = HttpResponse::Ok();
+    response
+        .content_type(header::ContentType::json())
+        .insert_header((header::ETAG, body_etag(&body)))
+        .insert_header(("X-Total-Count", total.to_string()));
+    if let Some(links) = page_links(&req, limit, offset, total) {
+        response.insert_header((header::LINK, links));
+    }
+    Ok(response.body(body))
+}
+
+#[derive(serde::Deserialize, IntoParams)]
+struct StreamQuery {
+    /// Signal code, e.g. `sound`
+    code: Option<String>,
+    /// Category token: a code, or `system|code`
+    category: Option<String>,
+}
+
+/// Every matching Observation, newest first, in one Bundle streamed as it
+/// is read so that large results never sit in memory. The count in
+/// `X-Total-Count` and `total` comes from the same snapshot as the entries.
+#[utoipa::path(
+    get,
+    path = "/api/fhir/Observation/stream",
+    tag = "fhir",
+    params(StreamQuery),
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 200, description = "Collection Bundle, sent in chunks", body = FhirBundle),
+        (status = 400, description = "Unknown code", body = ErrBody),
+        (status = 401, description = "Missing or invalid token", body = ErrBody),
+        (status = 403, description = "Device tokens cannot read patient data", body = ErrBody),
+    )
+)]
+async fn stream_observations(
+    req: HttpRequest,
+    state: web::Data<Arc<Mutex<AppState>>>,
+    q: web::Query<StreamQuery>,
+) -> Result<HttpResponse, AppError> {
+    let claims = require_permission(&req, Permission::ReadData)?;
+    let q = q.into_inner();
+    let query = ReadingQuery {
+        code: q
+            .code
+            .as_deref()
+            .map(str::parse::<SignalCode>)
+            .transpose()
+            .map_err(AppError::BadRequest)?,
+        category: q.category,
+        patients: claims.patient_filter().map(<[String]>::to_vec),
+        include_deleted: false,
+    };
+
+    let (total, pages) = state
+        .lock()
+        .await
+        .observation_pages(query, bundle_stream::DEFAULT_PAGE_SIZE)
+        .await?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(header::ContentType::json())
+        .insert_header(("X-Total-Count", total.to_string()))
+        .streaming(bundle_chunks(total, pages)))
+}
+
+#[derive(serde::Deserialize, IntoParams)]
+struct SuggestQuery {
+    /// Start of the tag, ignoring case; empty lists the first tags
+    prefix: String,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/fhir/Observation/$suggest",
+    tag = "fhir",
+    params(SuggestQuery),
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 200, description = "Up to 10 distinct tags (strings in the `tags` metadata array) starting with the prefix, in order", body = Vec<String>),
+        (status = 400, description = "Prefix too long", body = ErrBody),
+        (status = 401, description = "Missing or invalid token", body = ErrBody),
+        (status = 403, description = "Device tokens cannot read patient data", body = ErrBody),
+    )
+)]
+async fn suggest_observation_tags(
+    req: HttpRequest,
+    state: web::Data<Arc<Mutex<AppState>>>,
+    q: web::Query<SuggestQuery>,
+) -> Result<HttpResponse, AppError> {
+    let claims = require_permission(&req, Permission::ReadData)?;
+    if q.prefix.chars().count() > text_search::MAX_SEARCH_TEXT_CHARS {
+        return Err(AppError::BadRequest(format!(
+            "prefix must be at most {} characters",
+            text_search::MAX_SEARCH_TEXT_CHARS
+        )));
+    }
+
+    let filter = ReadingFilter {
+        code: None,
+        category: None,
+        patients: claims.patient_filter(),
+        include_deleted: false,
+        units: None,
+    };
+    let tags = state
+        .lock()
+        .await
+        .suggest_tags(&q.prefix, text_search::MAX_TAG_SUGGESTIONS, &filter)
+        .await?;
+
+    Ok(HttpResponse::Ok().json(tags))
+}
+
+/// RFC 5988 `Link` header value with the `next` and `prev` pages of an
+/// offset-paginated list, keeping the request's other query parameters.
+/// `None` when everything fits on this page.
+fn page_links(req: &HttpRequest, limit: usize, offset: usize, total: usize) -> Option<String> {
+    let page_url = |page_offset: usize| {
+        let mut url = req.full_url();
+        let params: Vec<(String, String)> = url
+            .query_pairs()
+            .filter(|(key, _)| key != "offset")
+            .map(|(key, value)| (key.into_owned(), value.into_owned()))
+            .collect();
+        url.query_pairs_mut()
+            .clear()
+            .extend_pairs(params)
+            .append_pair("offset", &page_offset.to_string());
+        url.to_string()
+    };
+
+    let mut links = Vec::new();
+    if limit > 0 && offset.saturating_add(limit) < total {
+        links.push(format!("<{}>; rel=\"next\"", page_url(offset + limit)));
+    }
+    if offset > 0 {
+        let prev = offset
+            .saturating_sub(limit)
+            .min(total.saturating_sub(limit));
+        links.push(format!("<{}>; rel=\"prev\"", page_url(prev)));
+    }
+    (!links.is_empty()).then(|| links.join(", "))
+}
+
+/// Strong entity tag for a response body: its SHA-256, which is stable
+/// across releases and restarts, unlike the standard library's hasher
+fn body_etag(body: &[u8]) -> String {
+    use sha2::{Digest, Sha256};
+
+    format!("\"{}\"", hex::encode(Sha256::digest(body)))
+}
+
+// ML Endpoints
+
+#[derive(serde::Deserialize)]
+struct MlQuery {
+    limit: Option<usize>,
+    hours_back: Option<u32>,
+}
+
+async fn ml_predict(
+    req: HttpRequest,
+    ml_client: Option<web::Data<Arc<MlClient>>>,
+    ml_cache: Option<web::Data<Arc<MlResultCache>>>,
+    query: web::Query<MlQuery>,
+) -> Result<HttpResponse, AppError> {
+    // Verify authentication
+    let _claims = get_claims_from_request(&req).ok_or(AppError::Unauthorized)?;
+
+    let client =
         ml_client.ok_or_else(|| AppError::BadRequest("ML service not configured".to_string()))?;
 
-    let limit = query.limit.unwrap_or(1000).min(10000);
+    let limit = query.limit.unwrap_or(100).min(1000);
     let hours_back = query.hours_back;
+    let cache_key = format!("predict limit={} hours_back={:?}", limit, hours_back);
 
-    match client.get_analysis(limit, hours_back).await {
-        Ok(analysis) => Ok(HttpResponse::Ok().json(analysis)),
+    match client.get_predictions(limit, hours_back).await {
+        Ok(predictions) => {
+            if let Some(cache) = &ml_cache {
+                cache.store(&cache_key, &predictions);
+            }
+            Ok(HttpResponse::Ok().json(predictions))
+        }
         Err(e) => {
-            tracing::error!("ML analysis failed: {}", e);
-            Err(AppError::Internal)
+            tracing::error!("ML prediction failed: {}", e);
+            stale_ml_result(ml_cache.as_ref(), &cache_key, e)
+        }
+    }
+}
+
+/// The cached result for `cache_key` after a failed ML call, flagged as
+/// stale. A refusal of our credentials is not papered over: it won't heal
+/// on its own, so it is reported as 502.
+fn stale_ml_result(
+    ml_cache: Option<&web::Data<Arc<MlResultCache>>>,
+    cache_key: &str,
+    error: MlError,
+) -> Result<HttpResponse, AppError> {
+    if matches!(error, MlError::Unauthorized(_)) {
+        return Err(error.into());
+    }
+    let stale = ml_cache
+        .and_then(|cache| cache.stale(cache_key))
+        .ok_or(error)?;
+
+    tracing::warn!("Serving ML result cached at {}", stale.as_of);
+    Ok(HttpResponse::Ok()
+        .insert_header(("Warning", "110 - \"Response is Stale\""))
+        .json(stale.body))
+}
+
+#[derive(serde::Deserialize)]
+struct AnalysisQuery {
+    limit: Option<usize>,
+    hours_back: Option<u32>,
+    /// Start of a date range, RFC 3339 or `YYYY-MM-DD` (start of that day)
+    start: Option<String>,
+    /// End of a date range, excluded: RFC 3339 or `YYYY-MM-DD` (through the
+    /// end of that day)
+    end: Option<String>,
+    /// Only analyze this patient's readings
+    patient_id: Option<String>,
+}
+
+impl AnalysisQuery {
+    fn window(&self) -> Result<AnalysisWindow, AppError> {
+        match (&self.start, &self.end) {
+            (None, None) => Ok(AnalysisWindow::Recent {
+                limit: self.limit.unwrap_or(1000).min(10000),
+                hours_back: self.hours_back,
+            }),
+            (Some(start), Some(end)) => {
+                if self.hours_back.is_some() {
+                    return Err(AppError::BadRequest(
+                        "hours_back cannot be combined with start/end".into(),
+                    ));
+                }
+                let start = parse_range_bound("start", start, chrono::NaiveTime::MIN)?;
+                let end = parse_range_end("end", end)?;
+                AnalysisWindow::range(start, end).map_err(AppError::BadRequest)
+            }
+            _ => Err(AppError::BadRequest(
+                "start and end must be given together".into(),
+            )),
+        }
+    }
+}
+
+/// Parse an RFC 3339 timestamp, or a bare date at `time_of_day` (UTC)
+fn parse_range_bound(
+    name: &str,
+    value: &str,
+    time_of_day: chrono::NaiveTime,
+) -> Result<chrono::DateTime<chrono::Utc>, AppError> {
+    if let Ok(ts) = chrono::DateTime::parse_from_rfc3339(value) {
+        return Ok(ts.with_timezone(&chrono::Utc));
+    }
+    chrono::NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .map(|date| date.and_time(time_of_day).and_utc())
+        .map_err(|_| {
+            AppError::BadRequest(format!(
+                "{} must be an RFC 3339 timestamp or a YYYY-MM-DD date",
+                name
+            ))
+        })
+}
+
+/// Parse the excluded end of a range: an RFC 3339 timestamp, or a bare date
+/// standing for the midnight after it (UTC), so no part of that day is lost
+fn parse_range_end(name: &str, value: &str) -> Result<chrono::DateTime<chrono::Utc>, AppError> {
+    if let Ok(ts) = chrono::DateTime::parse_from_rfc3339(value) {
+        return Ok(ts.with_timezone(&chrono::Utc));
+    }
+    let date = chrono::NaiveDate::parse_from_str(value, "%Y-%m-%d").map_err(|_| {
+        AppError::BadRequest(format!(
+            "{} must be an RFC 3339 timestamp or a YYYY-MM-DD date",
+            name
+        ))
+    })?;
+    date.succ_opt()
+        .map(|next| next.and_time(chrono::NaiveTime::MIN).and_utc())
+        .ok_or_else(|| AppError::BadRequest(format!("{} is out of range", name)))
+}
+
+async fn ml_analysis(
+    req: HttpRequest,
+    state: web::Data<Arc<Mutex<AppState>>>,
+    ml_client: Option<web::Data<Arc<MlClient>>>,
+    ml_cache: Option<web::Data<Arc<MlResultCache>>>,
+    query: web::Query<AnalysisQuery>,
+) -> Result<HttpResponse, AppError> {
+    // Verify authentication
+    let claims = get_claims_from_request(&req).ok_or(AppError::Unauthorized)?;
+
+    let window = query.window()?;
+
+    if let Some(patient_id) = query.patient_id.as_deref() {
+        if patient_id.trim().is_empty() {
+            return Err(AppError::BadRequest("patient_id must not be empty".into()));
+        }
+        authorize_patient_access(&req, &state, &claims, patient_id, "Analysis").await?;
+        let ml_client = ml_client.as_ref().map(|c| c.get_ref().as_ref());
+        return patient_analysis(&state, ml_client, &window, patient_id).await;
+    }
+
+    let AnalysisWindow::Range { start, end } = window else {
+        let client = ml_client
+            .ok_or_else(|| AppError::BadRequest("ML service not configured".to_string()))?;
+
+        let cache_key = format!("analysis {:?}", window);
+        return match client.get_analysis(&window, None).await {
+            Ok(analysis) => {
+                if let Some(cache) = &ml_cache {
+                    cache.store(&cache_key, &analysis);
+                }
+                Ok(HttpResponse::Ok().json(analysis))
+            }
+            Err(e) => {
+                tracing::error!("ML analysis failed: {}", e);
+                stale_ml_result(ml_cache.as_ref(), &cache_key, e)
+            }
+        };
+    };
+
+    // Date ranges degrade to basic statistics when the ML service can't help
+    if let Some(client) = &ml_client {
+        match client.get_analysis(&window, None).await {
+            Ok(analysis) => return Ok(HttpResponse::Ok().json(analysis)),
+            Err(e) => tracing::warn!("ML analysis failed, computing basic stats locally: {}", e),
+        }
+    }
+
+    let analysis = state
+        .lock()
+        .await
+        .reading_stats(start, end)
+        .await?
+        .ok_or_else(|| AppError::NotFound("no readings in range".into()))?;
+
+    Ok(HttpResponse::Ok().json(AnalysisResponse {
+        success: true,
+        analysis,
+        source: AnalysisSource::Local,
+        patient_id: None,
+    }))
+}
+
+/// Analysis of one patient's readings. ML services that ignore the
+/// `patient_id` filter are sent the patient's readings instead; without an
+/// answer from the ML service, basic statistics are computed locally.
+async fn patient_analysis(
+    state: &web::Data<Arc<Mutex<AppState>>>,
+    ml_client: Option<&MlClient>,
+    window: &AnalysisWindow,
+    patient_id: &str,
+) -> Result<HttpResponse, AppError> {
+    let mut ml_client = ml_client;
+    if let Some(client) = ml_client {
+        match client.get_analysis(window, Some(patient_id)).await {
+            Ok(analysis) if analysis.patient_id.as_deref() == Some(patient_id) => {
+                return Ok(HttpResponse::Ok().json(analysis));
+            }
+            Ok(_) => tracing::debug!("ML service ignored the patient filter, sending readings"),
+            Err(e) => {
+                tracing::warn!("ML analysis failed, computing basic stats locally: {}", e);
+                ml_client = None;
+            }
+        }
+    }
+
+    let samples = state
+        .lock()
+        .await
+        .patient_samples(patient_id, window)
+        .await?;
+    if samples.is_empty() {
+        return Err(AppError::NotFound("no readings for patient".into()));
+    }
+
+    if let Some(client) = ml_client {
+        match client.analyze_readings(patient_id, &samples).await {
+            Ok(analysis) => {
+                return Ok(HttpResponse::Ok().json(AnalysisResponse {
+                    patient_id: Some(patient_id.to_string()),
+                    ..analysis
+                }));
+            }
+            Err(e) => tracing::warn!("ML analysis failed, computing basic stats locally: {}", e),
         }
     }
+
+    let analysis = Analysis::summarize(samples.iter().map(|s| (s.timestamp, s.value)))
+        .ok_or_else(|| AppError::NotFound("no readings for patient".into()))?;
+
+    Ok(HttpResponse::Ok().json(AnalysisResponse {
+        success: true,
+        analysis,
+        source: AnalysisSource::Local,
+        patient_id: Some(patient_id.to_string()),
+    }))
 }
 
 #[derive(serde::Deserialize)]
@@ -354,34 +4857,63 @@ struct TrainRequest {
     min_samples: Option<usize>,
 }
 
+/// Start a training run and follow its progress in the background
 async fn ml_train(
     req: HttpRequest,
+    state: web::Data<Arc<Mutex<AppState>>>,
     ml_client: Option<web::Data<Arc<MlClient>>>,
     body: web::Json<TrainRequest>,
 ) -> Result<HttpResponse, AppError> {
-    // Verify authentication and require admin role
-    let claims = get_claims_from_request(&req).ok_or_else(|| AppError::Unauthorized)?;
-
-    if claims.role != "admin" {
-        tracing::warn!("Non-admin user {} attempted to train models", claims.sub);
-        return Err(AppError::Unauthorized);
-    }
+    let claims = require_admin(&req)?;
 
     let client =
         ml_client.ok_or_else(|| AppError::BadRequest("ML service not configured".to_string()))?;
 
     let min_samples = body.min_samples.unwrap_or(100);
 
-    match client.train_models(min_samples).await {
-        Ok(message) => Ok(HttpResponse::Ok().json(serde_json::json!({
-            "success": true,
-            "message": message
-        }))),
-        Err(e) => {
-            tracing::error!("ML training failed: {}", e);
-            Err(AppError::Internal)
-        }
+    let training_jobs = state.lock().await.training_jobs();
+    let mut job = training_jobs.start().await?;
+
+    if let Err(e) = client.train_models(job.job_id, min_samples).await {
+        tracing::error!("ML training failed: {}", e);
+        training_jobs.fail(job.job_id, &e.to_string()).await?;
+        return Err(e.into());
     }
+
+    tracing::info!(job_id = %job.job_id, "Training started by {}", claims.sub);
+    training_jobs.spawn_poller(client.get_ref().clone(), job.job_id);
+
+    job.message = "Training started".into();
+    Ok(HttpResponse::Accepted().json(job))
+}
+
+/// The 20 most recent training jobs
+async fn list_training_jobs(
+    req: HttpRequest,
+    state: web::Data<Arc<Mutex<AppState>>>,
+) -> Result<HttpResponse, AppError> {
+    require_admin(&req)?;
+
+    let training_jobs = state.lock().await.training_jobs();
+    Ok(HttpResponse::Ok().json(training_jobs.list().await?))
+}
+
+async fn get_training_job(
+    req: HttpRequest,
+    state: web::Data<Arc<Mutex<AppState>>>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, AppError> {
+    require_admin(&req)?;
+
+    let job_id = uuid::Uuid::parse_str(&path.into_inner())
+        .map_err(|_| AppError::BadRequest("invalid job id".into()))?;
+    let training_jobs = state.lock().await.training_jobs();
+    let job = training_jobs
+        .get(job_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("training job {}", job_id)))?;
+
+    Ok(HttpResponse::Ok().json(job))
 }
 
 async fn ml_health(
@@ -389,7 +4921,7 @@ async fn ml_health(
     ml_client: Option<web::Data<Arc<MlClient>>>,
 ) -> Result<HttpResponse, AppError> {
     // Verify authentication
-    let _claims = get_claims_from_request(&req).ok_or_else(|| AppError::Unauthorized)?;
+    let _claims = get_claims_from_request(&req).ok_or(AppError::Unauthorized)?;
 
     let client =
         ml_client.ok_or_else(|| AppError::BadRequest("ML service not configured".to_string()))?;
@@ -402,3 +4934,593 @@ async fn ml_health(
         }
     }
 }
+
+// Admin: CORS allowlist management
+
+async fn list_cors_origins(
+    req: HttpRequest,
+    allowlist: Option<web::Data<CorsAllowlist>>,
+) -> Result<HttpResponse, AppError> {
+    require_admin(&req)?;
+
+    let allowlist = allowlist
+        .ok_or_else(|| AppError::BadRequest("CORS allowlist not configured".to_string()))?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "dev_mode": allowlist.is_dev_mode(),
+        "origins": allowlist.origins()
+    })))
+}
+
+async fn add_cors_origin(
+    req: HttpRequest,
+    state: web::Data<Arc<Mutex<AppState>>>,
+    allowlist: Option<web::Data<CorsAllowlist>>,
+    body: web::Json<CorsOrigin>,
+) -> Result<HttpResponse, AppError> {
+    let claims = require_admin(&req)?;
+
+    let allowlist = allowlist
+        .ok_or_else(|| AppError::BadRequest("CORS allowlist not configured".to_string()))?;
+
+    let mut entry = body.into_inner();
+    entry.origin = entry.origin.trim().trim_end_matches('/').to_string();
+
+    if entry.origin.is_empty() {
+        return Err(AppError::BadRequest("origin required".into()));
+    }
+    // An origin is scheme://host[:port] with no path
+    let authority = entry
+        .origin
+        .split_once("://")
+        .map(|(_, rest)| rest)
+        .unwrap_or(&entry.origin);
+    if authority.is_empty() || authority.contains('/') {
+        return Err(AppError::BadRequest(
+            "origin must be scheme://host[:port] or a *.domain wildcard".into(),
+        ));
+    }
+
+    let db = state.lock().await.database();
+    if let Some(db) = db {
+        db.insert_cors_origin(&entry).await?;
+    }
+
+    tracing::info!(origin = %entry.origin, user = %claims.sub, "Added CORS origin");
+    allowlist.add(entry.clone());
+
+    Ok(HttpResponse::Created().json(entry))
+}
+
+async fn list_hearing_protectors(
+    req: HttpRequest,
+    state: web::Data<Arc<Mutex<AppState>>>,
+) -> Result<HttpResponse, AppError> {
+    require_admin(&req)?;
+
+    let protectors = state.lock().await.hearing_protectors().await?;
+    Ok(HttpResponse::Ok().json(protectors))
+}
+
+async fn add_hearing_protector(
+    req: HttpRequest,
+    state: web::Data<Arc<Mutex<AppState>>>,
+    body: web::Json<HearingProtectorRequest>,
+) -> Result<HttpResponse, AppError> {
+    let claims = require_admin(&req)?;
+
+    let body = body.into_inner();
+    body.validate().map_err(AppError::BadRequest)?;
+    let protector = body.into_protector(uuid::Uuid::new_v4());
+
+    state
+        .lock()
+        .await
+        .add_hearing_protector(protector.clone())
+        .await?;
+
+    tracing::info!(id = %protector.id, name = %protector.name, user = %claims.sub, "Added hearing protector");
+    Ok(HttpResponse::Created().json(protector))
+}
+
+async fn update_hearing_protector(
+    req: HttpRequest,
+    state: web::Data<Arc<Mutex<AppState>>>,
+    path: web::Path<String>,
+    body: web::Json<HearingProtectorRequest>,
+) -> Result<HttpResponse, AppError> {
+    let claims = require_admin(&req)?;
+    let id = uuid::Uuid::parse_str(&path.into_inner())
+        .map_err(|_| AppError::BadRequest("invalid hearing protector id".into()))?;
+
+    let body = body.into_inner();
+    body.validate().map_err(AppError::BadRequest)?;
+    let protector = body.into_protector(id);
+
+    if !state
+        .lock()
+        .await
+        .update_hearing_protector(protector.clone())
+        .await?
+    {
+        return Err(AppError::NotFound(format!("hearing protector {}", id)));
+    }
+
+    tracing::info!(id = %id, user = %claims.sub, "Updated hearing protector");
+    Ok(HttpResponse::Ok().json(protector))
+}
+
+async fn delete_hearing_protector(
+    req: HttpRequest,
+    state: web::Data<Arc<Mutex<AppState>>>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, AppError> {
+    let claims = require_admin(&req)?;
+    let id = uuid::Uuid::parse_str(&path.into_inner())
+        .map_err(|_| AppError::BadRequest("invalid hearing protector id".into()))?;
+
+    if !state.lock().await.delete_hearing_protector(id).await? {
+        return Err(AppError::NotFound(format!("hearing protector {}", id)));
+    }
+
+    tracing::info!(id = %id, user = %claims.sub, "Deleted hearing protector");
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Admins and users may manage suppression windows, users only those of
+/// their permitted patients; viewers and devices get 403
+fn require_suppression_access(req: &HttpRequest) -> Result<Claims, AppError> {
+    require_permission(req, Permission::AmendReadings)
+}
+
+fn parse_suppression_id(path: web::Path<String>) -> Result<uuid::Uuid, AppError> {
+    uuid::Uuid::parse_str(&path.into_inner())
+        .map_err(|_| AppError::BadRequest("invalid suppression window id".into()))
+}
+
+/// The window with `id` if the caller may see it; 404 otherwise, so users
+/// cannot probe for other patients' windows
+async fn permitted_suppression(
+    suppressions: &AlertSuppressions,
+    claims: &Claims,
+    id: uuid::Uuid,
+) -> Result<SuppressionWindow, AppError> {
+    suppressions
+        .get(id)
+        .await?
+        .filter(|w| claims.may_read_patient(&w.patient_id))
+        .ok_or_else(|| AppError::NotFound(format!("suppression window {}", id)))
+}
+
+/// Suppression windows of the patients the caller may read
+#[utoipa::path(
+    get,
+    path = "/api/alert-suppressions",
+    tag = "alerts",
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 200, description = "Windows by start time", body = Vec<SuppressionWindow>),
+        (status = 401, description = "Missing or invalid token", body = ErrBody),
+        (status = 403, description = "Viewer or device token", body = ErrBody),
+    )
+)]
+async fn list_alert_suppressions(
+    req: HttpRequest,
+    state: web::Data<Arc<Mutex<AppState>>>,
+) -> Result<HttpResponse, AppError> {
+    let claims = require_suppression_access(&req)?;
+
+    let suppressions = state.lock().await.alert_suppressions();
+    let mut windows = suppressions.list().await?;
+    windows.retain(|w| claims.may_read_patient(&w.patient_id));
+    Ok(HttpResponse::Ok().json(windows))
+}
+
+/// Windows covering now
+#[utoipa::path(
+    get,
+    path = "/api/alert-suppressions/active",
+    tag = "alerts",
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 200, description = "Windows covering now", body = Vec<SuppressionWindow>),
+        (status = 401, description = "Missing or invalid token", body = ErrBody),
+        (status = 403, description = "Viewer or device token", body = ErrBody),
+    )
+)]
+async fn active_alert_suppressions(
+    req: HttpRequest,
+    state: web::Data<Arc<Mutex<AppState>>>,
+) -> Result<HttpResponse, AppError> {
+    let claims = require_suppression_access(&req)?;
+
+    let suppressions = state.lock().await.alert_suppressions();
+    let mut windows = suppressions.active().await?;
+    windows.retain(|w| claims.may_read_patient(&w.patient_id));
+    Ok(HttpResponse::Ok().json(windows))
+}
+
+/// Silence a patient's anomaly alerts for a time window
+#[utoipa::path(
+    post,
+    path = "/api/alert-suppressions",
+    tag = "alerts",
+    request_body = SuppressionRequest,
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 201, description = "Window created", body = SuppressionWindow),
+        (status = 400, description = "Invalid window", body = ErrBody),
+        (status = 401, description = "Missing or invalid token", body = ErrBody),
+        (status = 403, description = "Viewer or device token, or no access to the patient", body = ErrBody),
+    )
+)]
+async fn create_alert_suppression(
+    req: HttpRequest,
+    state: web::Data<Arc<Mutex<AppState>>>,
+    body: web::Json<SuppressionRequest>,
+) -> Result<HttpResponse, AppError> {
+    let claims = require_suppression_access(&req)?;
+
+    let body = body.into_inner();
+    let now = chrono::Utc::now();
+    body.validate(now).map_err(AppError::BadRequest)?;
+    if !claims.may_read_patient(body.patient_id.trim()) {
+        return Err(AppError::Forbidden(format!(
+            "no access to patient {}",
+            body.patient_id.trim()
+        )));
+    }
+    let window = body.into_window(uuid::Uuid::new_v4(), claims.sub.clone(), now);
+
+    let (suppressions, users) = {
+        let state = state.lock().await;
+        (state.alert_suppressions(), state.users())
+    };
+    suppressions.create(&window).await?;
+    users
+        .audit(
+            AuditLogEntry::new(AuditAction::Create, "AlertSuppression".to_string())
+                .with_user(claims.sub.clone(), claims.role.clone())
+                .with_resource_id(window.id.to_string())
+                .with_patient_id(window.patient_id.clone())
+                .with_request_context(None, None, Some(req.path().to_string()))
+                .with_status_code(201),
+        )
+        .await;
+
+    tracing::info!(
+        id = %window.id,
+        patient_id = %window.patient_id,
+        end_at = %window.end_at,
+        user = %claims.sub,
+        "Added alert suppression window"
+    );
+    Ok(HttpResponse::Created().json(window))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/alert-suppressions/{id}",
+    tag = "alerts",
+    params(("id" = String, Path, description = "Window id")),
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 200, description = "The window", body = SuppressionWindow),
+        (status = 400, description = "Invalid id", body = ErrBody),
+        (status = 401, description = "Missing or invalid token", body = ErrBody),
+        (status = 403, description = "Viewer or device token", body = ErrBody),
+        (status = 404, description = "No such window for the caller's patients", body = ErrBody),
+    )
+)]
+async fn get_alert_suppression(
+    req: HttpRequest,
+    state: web::Data<Arc<Mutex<AppState>>>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, AppError> {
+    let claims = require_suppression_access(&req)?;
+    let id = parse_suppression_id(path)?;
+
+    let suppressions = state.lock().await.alert_suppressions();
+    let window = permitted_suppression(&suppressions, &claims, id).await?;
+    Ok(HttpResponse::Ok().json(window))
+}
+
+/// Replace a window; it keeps its id and creator
+#[utoipa::path(
+    put,
+    path = "/api/alert-suppressions/{id}",
+    tag = "alerts",
+    params(("id" = String, Path, description = "Window id")),
+    request_body = SuppressionRequest,
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 200, description = "Updated window", body = SuppressionWindow),
+        (status = 400, description = "Invalid id or window", body = ErrBody),
+        (status = 401, description = "Missing or invalid token", body = ErrBody),
+        (status = 403, description = "Viewer or device token, or no access to the patient", body = ErrBody),
+        (status = 404, description = "No such window for the caller's patients", body = ErrBody),
+    )
+)]
+async fn update_alert_suppression(
+    req: HttpRequest,
+    state: web::Data<Arc<Mutex<AppState>>>,
+    path: web::Path<String>,
+    body: web::Json<SuppressionRequest>,
+) -> Result<HttpResponse, AppError> {
+    let claims = require_suppression_access(&req)?;
+    let id = parse_suppression_id(path)?;
+
+    let body = body.into_inner();
+    let now = chrono::Utc::now();
+    body.validate(now).map_err(AppError::BadRequest)?;
+    if !claims.may_read_patient(body.patient_id.trim()) {
+        return Err(AppError::Forbidden(format!(
+            "no access to patient {}",
+            body.patient_id.trim()
+        )));
+    }
+
+    let (suppressions, users) = {
+        let state = state.lock().await;
+        (state.alert_suppressions(), state.users())
+    };
+    let existing = permitted_suppression(&suppressions, &claims, id).await?;
+    let window = body.into_window(id, existing.created_by, now);
+    if !suppressions.update(&window).await? {
+        return Err(AppError::NotFound(format!("suppression window {}", id)));
+    }
+    users
+        .audit(
+            AuditLogEntry::new(AuditAction::Update, "AlertSuppression".to_string())
+                .with_user(claims.sub.clone(), claims.role.clone())
+                .with_resource_id(id.to_string())
+                .with_patient_id(window.patient_id.clone())
+                .with_request_context(None, None, Some(req.path().to_string()))
+                .with_status_code(200)
+                .with_metadata(serde_json::json!({
+                    "previous_patient_id": existing.patient_id,
+                    "previous_end_at": existing.end_at,
+                })),
+        )
+        .await;
+
+    tracing::info!(id = %id, user = %claims.sub, "Updated alert suppression window");
+    Ok(HttpResponse::Ok().json(window))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/alert-suppressions/{id}",
+    tag = "alerts",
+    params(("id" = String, Path, description = "Window id")),
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 204, description = "Window deleted"),
+        (status = 400, description = "Invalid id", body = ErrBody),
+        (status = 401, description = "Missing or invalid token", body = ErrBody),
+        (status = 403, description = "Viewer or device token", body = ErrBody),
+        (status = 404, description = "No such window for the caller's patients", body = ErrBody),
+    )
+)]
+async fn delete_alert_suppression(
+    req: HttpRequest,
+    state: web::Data<Arc<Mutex<AppState>>>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, AppError> {
+    let claims = require_suppression_access(&req)?;
+    let id = parse_suppression_id(path)?;
+
+    let (suppressions, users) = {
+        let state = state.lock().await;
+        (state.alert_suppressions(), state.users())
+    };
+    let existing = permitted_suppression(&suppressions, &claims, id).await?;
+    if !suppressions.delete(id).await? {
+        return Err(AppError::NotFound(format!("suppression window {}", id)));
+    }
+    users
+        .audit(
+            AuditLogEntry::new(AuditAction::Delete, "AlertSuppression".to_string())
+                .with_user(claims.sub.clone(), claims.role.clone())
+                .with_resource_id(id.to_string())
+                .with_patient_id(existing.patient_id)
+                .with_request_context(None, None, Some(req.path().to_string()))
+                .with_status_code(204),
+        )
+        .await;
+
+    tracing::info!(id = %id, user = %claims.sub, "Deleted alert suppression window");
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Dead-letter queue status for operators
+async fn dlq_stats(
+    req: HttpRequest,
+    state: web::Data<Arc<Mutex<AppState>>>,
+) -> Result<HttpResponse, AppError> {
+    require_admin(&req)?;
+
+    let dlq =
+        state.lock().await.dlq().ok_or_else(|| {
+            AppError::ServiceUnavailable("dead-letter queue not configured".into())
+        })?;
+
+    Ok(HttpResponse::Ok().json(dlq.stats().await))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/admin/compliance-report/{year}/{month}",
+    tag = "system",
+    security(("bearer_auth" = [])),
+    params(
+        ("year" = i32, Path, description = "Year of the month"),
+        ("month" = u32, Path, description = "Month, 1-12"),
+    ),
+    responses(
+        (status = 200, description = "Audit activity and suspicious events of the UTC month", body = ComplianceReport),
+        (status = 400, description = "No such month, or it has not started", body = ErrBody),
+        (status = 401, description = "Missing, invalid or non-admin token", body = ErrBody),
+        (status = 503, description = "No database configured", body = ErrBody),
+    )
+)]
+async fn get_compliance_report(
+    req: HttpRequest,
+    state: web::Data<Arc<Mutex<AppState>>>,
+    path: web::Path<(i32, u32)>,
+) -> Result<HttpResponse, AppError> {
+    let claims = require_admin(&req)?;
+    let (year, month) = path.into_inner();
+    month_bounds(year, month).map_err(AppError::BadRequest)?;
+
+    let (db, users) = {
+        let state = state.lock().await;
+        (state.database(), state.users())
+    };
+    let db = db
+        .ok_or_else(|| AppError::ServiceUnavailable("compliance reports need a database".into()))?;
+    let report = ComplianceReportGenerator::new(db)
+        .generate(year, month)
+        .await?;
+
+    users
+        .audit(
+            AuditLogEntry::new(AuditAction::Read, "ComplianceReport".to_string())
+                .with_user(claims.sub.clone(), claims.role.clone())
+                .with_resource_id(report.month.clone())
+                .with_request_context(None, None, Some(req.path().to_string()))
+                .with_status_code(200),
+        )
+        .await;
+    Ok(HttpResponse::Ok().json(report))
+}
+
+#[derive(serde::Deserialize, IntoParams)]
+struct AuditQuery {
+    /// Maximum number of entries (default 50, at most 500)
+    limit: Option<i64>,
+    /// `next_cursor` of the previous page
+    after: Option<uuid::Uuid>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/audit/patient/{id}",
+    tag = "system",
+    security(("bearer_auth" = [])),
+    params(("id" = String, Path, description = "Patient ID"), AuditQuery),
+    responses(
+        (status = 200, description = "A page of the patient's audit log, newest first", body = AuditPage,
+            headers(("X-Total-Count" = i64, description = "Entries across all pages"))),
+        (status = 400, description = "`after` is not a cursor of this patient's log", body = ErrBody),
+        (status = 401, description = "Missing, invalid or non-admin token", body = ErrBody),
+        (status = 503, description = "No database configured", body = ErrBody),
+    )
+)]
+async fn get_patient_audit_log(
+    req: HttpRequest,
+    state: web::Data<Arc<Mutex<AppState>>>,
+    path: web::Path<String>,
+    query: web::Query<AuditQuery>,
+) -> Result<HttpResponse, AppError> {
+    let claims = require_admin(&req)?;
+    let patient_id = path.into_inner();
+    let limit = query.limit.unwrap_or(50).clamp(1, 500);
+
+    let (db, users) = {
+        let state = state.lock().await;
+        (state.database(), state.users())
+    };
+    let db = db.ok_or_else(|| AppError::ServiceUnavailable("audit logs need a database".into()))?;
+    let page = AuditLogger::new(db.pool().clone())
+        .get_patient_access_log(&patient_id, limit, query.after)
+        .await
+        .map_err(|e| {
+            tracing::error!(error = %e, "Failed to read audit log");
+            AppError::Internal
+        })?
+        .ok_or_else(|| AppError::BadRequest("unknown or expired cursor".into()))?;
+
+    // Not filed under the patient, whose log is being paged
+    users
+        .audit(
+            AuditLogEntry::new(AuditAction::Read, "AuditLog".to_string())
+                .with_user(claims.sub.clone(), claims.role.clone())
+                .with_resource_id(patient_id)
+                .with_request_context(None, None, Some(req.path().to_string()))
+                .with_status_code(200),
+        )
+        .await;
+    Ok(HttpResponse::Ok()
+        .insert_header(("X-Total-Count", page.total_count.to_string()))
+        .json(page))
+}
+
+/// Rates of sampled hot-path debug events since the previous call
+async fn log_stats(req: HttpRequest) -> Result<HttpResponse, AppError> {
+    require_admin(&req)?;
+    Ok(HttpResponse::Ok().json(log_sampling::stats()))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/serial/status",
+    tag = "system",
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 200, description = "State of the serial link and its line counters", body = SerialStatus),
+        (status = 401, description = "Missing, invalid or non-admin token", body = ErrBody),
+        (status = 503, description = "No serial port configured", body = ErrBody),
+    )
+)]
+async fn serial_status(
+    req: HttpRequest,
+    status: Option<web::Data<RwLock<SerialStatus>>>,
+) -> Result<HttpResponse, AppError> {
+    require_admin(&req)?;
+    let status = status.ok_or_else(|| {
+        AppError::ServiceUnavailable("no serial port configured; set SERIAL_PORT".into())
+    })?;
+
+    let status = status.read().unwrap_or_else(|e| e.into_inner()).clone();
+    Ok(HttpResponse::Ok().json(status))
+}
+
+/// Snapshot the in-memory readings now (`SNAPSHOT_PATH` must be set)
+async fn take_snapshot(
+    req: HttpRequest,
+    state: web::Data<Arc<Mutex<AppState>>>,
+    snapshots: Option<web::Data<SnapshotManager>>,
+) -> Result<HttpResponse, AppError> {
+    let claims = require_admin(&req)?;
+    let snapshots = snapshots.ok_or_else(snapshots_not_configured)?;
+
+    let meta = snapshots.take(state.get_ref()).await?;
+    tracing::info!(count = meta.reading_count, user = %claims.sub, "Wrote snapshot on request");
+    Ok(HttpResponse::Ok().json(meta))
+}
+
+/// When the last snapshot was written or loaded, and how many readings it holds
+async fn snapshot_meta(
+    req: HttpRequest,
+    snapshots: Option<web::Data<SnapshotManager>>,
+) -> Result<HttpResponse, AppError> {
+    require_admin(&req)?;
+    let snapshots = snapshots.ok_or_else(snapshots_not_configured)?;
+
+    Ok(HttpResponse::Ok().json(snapshots.meta().await))
+}
+
+/// Size, capacity, time span and per-code counts of the in-memory readings
+async fn debug_store(
+    req: HttpRequest,
+    state: web::Data<Arc<Mutex<AppState>>>,
+) -> Result<HttpResponse, AppError> {
+    require_admin(&req)?;
+    let stats = state.lock().await.memory_stats();
+    Ok(HttpResponse::Ok().json(stats))
+}
+
+fn snapshots_not_configured() -> AppError {
+    AppError::ServiceUnavailable("snapshots not configured; set SNAPSHOT_PATH".into())
+}
@@ -0,0 +1,272 @@
+/// Startup Security Configuration Check
+///
+/// The secrets the backend signs and checks tokens with, and the bootstrap
+/// admin password, all fall back to well-known values when unset. At startup
+/// each is checked against those defaults, the placeholders shipped in
+/// `.env.example` and a minimum strength. Findings are logged as warnings;
+/// with `STRICT_SECURITY=true` the backend refuses to start instead.
+use crate::auth::{parse_jwt_keys, JwtAlgorithm};
+use crate::users::validate_password_strength;
+use std::collections::BTreeMap;
+
+/// JWT signing secret used when `JWT_SECRET` is unset
+pub const DEFAULT_JWT_SECRET: &str = "default_secret_change_in_production";
+
+/// Device token secret used when `DEVICE_TOKEN_SECRET` is unset
+pub const DEFAULT_DEVICE_TOKEN_SECRET: &str = "change_this_secret";
+
+/// Bootstrap admin password used when `AUTH_PASSWORD` is unset
+pub const DEFAULT_AUTH_PASSWORD: &str = "admin123";
+
+/// Bootstrap admin username used when `AUTH_USERNAME` is unset
+pub const DEFAULT_AUTH_USERNAME: &str = "admin";
+
+/// Shortest accepted secret: 256 bits for HS256 when written as ASCII
+pub const MIN_SECRET_LEN: usize = 32;
+
+/// Known values that must never protect a deployment
+const PLACEHOLDER_SECRETS: [&str; 5] = [
+    DEFAULT_JWT_SECRET,
+    DEFAULT_DEVICE_TOKEN_SECRET,
+    DEFAULT_AUTH_PASSWORD,
+    // .env.example
+    "your_super_secret_jwt_key_change_this_in_production_min_32_chars",
+    "your_device_token_generation_secret_change_this",
+];
+
+/// A setting that leaves the deployment open to token forgery or takeover
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SecurityIssue {
+    /// Environment variable at fault
+    pub variable: &'static str,
+    pub problem: String,
+}
+
+impl std::fmt::Display for SecurityIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.variable, self.problem)
+    }
+}
+
+/// Whether user tokens are signed with RS256, which needs no `JWT_SECRET`
+fn uses_rs256(var: &impl Fn(&str) -> Option<String>) -> bool {
+    var("JWT_ALGORITHM").is_some_and(|a| a.eq_ignore_ascii_case("RS256"))
+}
+
+/// Whether a production deployment (`SOUNDSENSE_ENV=production`) signs user
+/// tokens with HS256, so every verifier holds the signing secret
+pub fn hs256_in_production(var: impl Fn(&str) -> Option<String>) -> bool {
+    var("SOUNDSENSE_ENV").is_some_and(|env| env.eq_ignore_ascii_case("production"))
+        && !uses_rs256(&var)
+}
+
+/// Why `secret` must not sign or check tokens, if it must not
+fn secret_problem(secret: &str) -> Option<String> {
+    if PLACEHOLDER_SECRETS.contains(&secret) {
+        Some("left at a published default".to_string())
+    } else if secret.chars().count() < MIN_SECRET_LEN {
+        Some(format!("shorter than {} characters", MIN_SECRET_LEN))
+    } else {
+        None
+    }
+}
+
+/// Problems with the security settings that `var` looks up (the process
+/// environment at startup), in `JWT_SECRET` (or each `JWT_KEYS` secret; no
+/// secret is needed with RS256), `DEVICE_TOKEN_SECRET`, `AUTH_PASSWORD` order
+pub fn check_security_config(var: impl Fn(&str) -> Option<String>) -> Vec<SecurityIssue> {
+    let mut issues = Vec::new();
+
+    if let (false, Some(spec)) = (uses_rs256(&var), var("JWT_KEYS")) {
+        // A malformed JWT_KEYS stops startup when the token manager is built
+        let keys: BTreeMap<_, _> = parse_jwt_keys(&spec)
+            .unwrap_or_default()
+            .into_iter()
+            .collect();
+        for (kid, key) in keys {
+            if let JwtAlgorithm::HS256(secret) = key {
+                issues.extend(secret_problem(&secret).map(|problem| SecurityIssue {
+                    variable: "JWT_KEYS",
+                    problem: format!("key '{}' {}", kid, problem),
+                }));
+            }
+        }
+    }
+
+    let secrets: &[&'static str] = if uses_rs256(&var) || var("JWT_KEYS").is_some() {
+        &["DEVICE_TOKEN_SECRET"]
+    } else {
+        &["JWT_SECRET", "DEVICE_TOKEN_SECRET"]
+    };
+    for &variable in secrets {
+        let problem = match var(variable) {
+            None => Some("not set, so the built-in default is used".to_string()),
+            Some(secret) => secret_problem(&secret),
+        };
+        issues.extend(problem.map(|problem| SecurityIssue { variable, problem }));
+    }
+
+    let username = var("AUTH_USERNAME").unwrap_or_else(|| DEFAULT_AUTH_USERNAME.to_string());
+    let problem = match var("AUTH_PASSWORD") {
+        None => Some("not set, so the built-in default is used".to_string()),
+        Some(password) if PLACEHOLDER_SECRETS.contains(&password.as_str()) => {
+            Some("left at a published default".to_string())
+        }
+        Some(password) => validate_password_strength(&password, &username).err(),
+    };
+    issues.extend(problem.map(|problem| SecurityIssue {
+        variable: "AUTH_PASSWORD",
+        problem,
+    }));
+
+    issues
+}
+
+/// Check the process environment, logging every problem. Fails if there are
+/// any and `STRICT_SECURITY=true`. HS256 in production is only warned about.
+pub fn validate_security_config() -> Result<(), String> {
+    if hs256_in_production(|name| std::env::var(name).ok()) {
+        tracing::warn!(
+            "JWT_ALGORITHM is HS256 in production; consider RS256 so services that only verify tokens need not hold the signing secret"
+        );
+    }
+
+    let issues = check_security_config(|name| std::env::var(name).ok());
+    if issues.is_empty() {
+        return Ok(());
+    }
+
+    for issue in &issues {
+        tracing::warn!("Insecure configuration: {}", issue);
+    }
+
+    let strict = std::env::var("STRICT_SECURITY")
+        .map(|v| v.eq_ignore_ascii_case("true"))
+        .unwrap_or(false);
+    if strict {
+        return Err(format!(
+            "refusing to start with STRICT_SECURITY=true: {}",
+            issues
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join("; ")
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn check(vars: &[(&str, &str)]) -> Vec<&'static str> {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        check_security_config(|name| vars.get(name).cloned())
+            .into_iter()
+            .map(|issue| issue.variable)
+            .collect()
+    }
+
+    const STRONG: [(&str, &str); 3] = [
+        ("JWT_SECRET", "0f9c2d8e7b6a5f4e3d2c1b0a9f8e7d6c5b4a"),
+        (
+            "DEVICE_TOKEN_SECRET",
+            "a1b2c3d4e5f6a7b8c9d0e1f2a3b4c5d6e7f8",
+        ),
+        ("AUTH_PASSWORD", "Correct-horse-42"),
+    ];
+
+    #[test]
+    fn test_strong_settings_pass() {
+        assert!(check(&STRONG).is_empty());
+    }
+
+    #[test]
+    fn test_unset_default_and_short_secrets_are_flagged() {
+        assert_eq!(
+            check(&[]),
+            ["JWT_SECRET", "DEVICE_TOKEN_SECRET", "AUTH_PASSWORD"]
+        );
+
+        let mut vars = STRONG.to_vec();
+        vars[0].1 = DEFAULT_JWT_SECRET;
+        assert_eq!(check(&vars), ["JWT_SECRET"]);
+
+        vars[0] = STRONG[0];
+        vars[1].1 = "your_device_token_generation_secret_change_this";
+        assert_eq!(check(&vars), ["DEVICE_TOKEN_SECRET"]);
+
+        vars[1].1 = "too-short-secret";
+        assert_eq!(check(&vars), ["DEVICE_TOKEN_SECRET"]);
+    }
+
+    #[test]
+    fn test_weak_admin_password_is_flagged() {
+        let mut vars = STRONG.to_vec();
+        vars[2].1 = DEFAULT_AUTH_PASSWORD;
+        assert_eq!(check(&vars), ["AUTH_PASSWORD"]);
+
+        vars[2].1 = "short1";
+        assert_eq!(check(&vars), ["AUTH_PASSWORD"]);
+
+        // The strength policy also rejects the username in the password
+        vars[2].1 = "Admin-password-2026";
+        assert_eq!(check(&vars), ["AUTH_PASSWORD"]);
+        vars.push(("AUTH_USERNAME", "root"));
+        assert!(check(&vars).is_empty());
+    }
+
+    #[test]
+    fn test_rs256_needs_no_jwt_secret() {
+        let mut vars: Vec<_> = STRONG[1..].to_vec();
+        assert_eq!(check(&vars), ["JWT_SECRET"]);
+        vars.push(("JWT_ALGORITHM", "RS256"));
+        assert!(check(&vars).is_empty());
+    }
+
+    #[test]
+    fn test_each_rotated_key_is_checked() {
+        let mut vars: Vec<_> = STRONG[1..].to_vec();
+        vars.push((
+            "JWT_KEYS",
+            "old:0f9c2d8e7b6a5f4e3d2c1b0a9f8e7d6c5b4a,new:short",
+        ));
+        let issues = {
+            let vars: HashMap<String, String> = vars
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect();
+            check_security_config(|name| vars.get(name).cloned())
+        };
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].variable, "JWT_KEYS");
+        assert!(issues[0].problem.starts_with("key 'new'"));
+    }
+
+    #[test]
+    fn test_hs256_in_production_is_detected() {
+        let in_env = |vars: &[(&str, &str)]| {
+            let vars: HashMap<String, String> = vars
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect();
+            hs256_in_production(|name| vars.get(name).cloned())
+        };
+        assert!(in_env(&[("SOUNDSENSE_ENV", "production")]));
+        assert!(in_env(&[
+            ("SOUNDSENSE_ENV", "production"),
+            ("JWT_ALGORITHM", "HS256")
+        ]));
+        assert!(!in_env(&[
+            ("SOUNDSENSE_ENV", "production"),
+            ("JWT_ALGORITHM", "rs256")
+        ]));
+        assert!(!in_env(&[("SOUNDSENSE_ENV", "development")]));
+        assert!(!in_env(&[]));
+    }
+}
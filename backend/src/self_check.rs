@@ -0,0 +1,475 @@
+/// Self-Test
+///
+/// `soundsense-backend --check` loads the configuration, tries everything it
+/// points at and exits 0 if all of it works, 1 otherwise, instead of
+/// serving: the database answers a query, the ML service its health
+/// endpoint, the JWT keys sign a token they then accept, the serial port
+/// opens and the directories files are written to take a file. `/readyz`
+/// runs the same checks against the running service, except the serial
+/// port, which the serial reader holds open, and reuses its report for a
+/// few seconds; callers without a token only see each check's name and
+/// status, not the paths and errors in its detail. Nothing is changed: no
+/// migrations are run, the serial port is closed right away and the probe
+/// files are removed again.
+use serde::Serialize;
+use std::fmt;
+use std::future::Future;
+use std::path::Path;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::auth::{Claims, JwtManager};
+use crate::bulk_export::BulkExports;
+use crate::db::Database;
+use crate::dlq::DeadLetterQueue;
+use crate::ml_client::MlClient;
+use crate::routes::RouteDeps;
+use crate::security::validate_security_config;
+use crate::serial_ingest::SerialConfig;
+use crate::snapshot::SnapshotManager;
+use crate::{fhir, validation};
+
+/// How long `--check` waits for a database connection
+const DATABASE_CONNECT_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// How long `/readyz` reuses a report before checking again
+pub const READINESS_CACHE_TTL: Duration = Duration::from_secs(5);
+
+/// Outcome of one check
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckStatus {
+    Ok,
+    Failed,
+    /// Not configured, so there was nothing to check
+    Skipped,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct CheckResult {
+    pub name: String,
+    pub status: CheckStatus,
+    /// Left out of `/readyz` for callers without a token
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub detail: String,
+}
+
+impl CheckResult {
+    pub fn ok(name: &str, detail: impl Into<String>) -> Self {
+        Self::new(name, CheckStatus::Ok, detail)
+    }
+
+    pub fn failed(name: &str, detail: impl Into<String>) -> Self {
+        Self::new(name, CheckStatus::Failed, detail)
+    }
+
+    pub fn skipped(name: &str, detail: impl Into<String>) -> Self {
+        Self::new(name, CheckStatus::Skipped, detail)
+    }
+
+    fn new(name: &str, status: CheckStatus, detail: impl Into<String>) -> Self {
+        Self {
+            name: name.to_string(),
+            status,
+            detail: detail.into(),
+        }
+    }
+}
+
+/// Results of a set of checks; `ok` unless one of them failed
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct CheckReport {
+    pub ok: bool,
+    pub checks: Vec<CheckResult>,
+}
+
+impl CheckReport {
+    pub fn new(checks: Vec<CheckResult>) -> Self {
+        Self {
+            ok: checks.iter().all(|c| c.status != CheckStatus::Failed),
+            checks,
+        }
+    }
+
+    pub fn failed(&self) -> usize {
+        self.checks
+            .iter()
+            .filter(|c| c.status == CheckStatus::Failed)
+            .count()
+    }
+
+    /// The same report with only the name and status of each check
+    pub fn redacted(&self) -> Self {
+        Self {
+            ok: self.ok,
+            checks: self
+                .checks
+                .iter()
+                .map(|c| CheckResult::new(&c.name, c.status, ""))
+                .collect(),
+        }
+    }
+}
+
+/// The last `/readyz` report, reused for `ttl` so that frequent probes
+/// don't touch the database, the ML service and the disk on every call
+#[derive(Debug)]
+pub struct ReadinessCache {
+    ttl: Duration,
+    last: Mutex<Option<(Instant, CheckReport)>>,
+}
+
+impl Default for ReadinessCache {
+    fn default() -> Self {
+        Self::new(READINESS_CACHE_TTL)
+    }
+}
+
+impl ReadinessCache {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            last: Mutex::new(None),
+        }
+    }
+
+    /// The cached report while it is fresh, otherwise the one `check`
+    /// produces. Concurrent callers wait for a single run of the checks.
+    pub async fn get_or_check(&self, check: impl Future<Output = CheckReport>) -> CheckReport {
+        let mut last = self.last.lock().await;
+        if let Some((at, report)) = last.as_ref() {
+            if at.elapsed() < self.ttl {
+                return report.clone();
+            }
+        }
+        let report = check.await;
+        *last = Some((Instant::now(), report.clone()));
+        report
+    }
+}
+
+/// One line per check, then a summary
+impl fmt::Display for CheckReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let width = self.checks.iter().map(|c| c.name.len()).max().unwrap_or(0);
+        for check in &self.checks {
+            let status = match check.status {
+                CheckStatus::Ok => " ok ",
+                CheckStatus::Failed => "FAIL",
+                CheckStatus::Skipped => "skip",
+            };
+            writeln!(
+                f,
+                "[{}] {:width$}  {}",
+                status,
+                check.name,
+                check.detail,
+                width = width
+            )?;
+        }
+        match self.failed() {
+            0 => write!(f, "All {} checks passed", self.checks.len()),
+            n => write!(f, "{} of {} checks failed", n, self.checks.len()),
+        }
+    }
+}
+
+/// The database answers `SELECT 1`; skipped without one
+pub async fn check_database(db: Option<&Database>) -> CheckResult {
+    const NAME: &str = "database";
+    let Some(db) = db else {
+        return CheckResult::skipped(NAME, "DATABASE_URL not set, readings kept in memory");
+    };
+    match db.health_check().await {
+        Ok(()) => CheckResult::ok(NAME, "query succeeded"),
+        Err(e) => CheckResult::failed(NAME, e.to_string()),
+    }
+}
+
+/// Connect to `database_url` and check the database as `check_database`
+/// does, without running migrations
+pub async fn check_database_url(database_url: Option<&str>) -> CheckResult {
+    let Some(url) = database_url else {
+        return check_database(None).await;
+    };
+    let pool = sqlx::postgres::PgPoolOptions::new()
+        .max_connections(1)
+        .acquire_timeout(DATABASE_CONNECT_TIMEOUT)
+        .connect(url)
+        .await;
+    match pool {
+        Ok(pool) => {
+            let result = check_database(Some(&Database::new(pool.clone()))).await;
+            pool.close().await;
+            result
+        }
+        Err(e) => CheckResult::failed("database", format!("cannot connect: {}", e)),
+    }
+}
+
+/// The ML service answers its health endpoint; skipped without one
+pub async fn check_ml_service(client: Option<&MlClient>) -> CheckResult {
+    const NAME: &str = "ml_service";
+    let Some(client) = client else {
+        return CheckResult::skipped(NAME, "ML_SERVICE_URL not set");
+    };
+    match client.health_check().await {
+        Ok(health) => CheckResult::ok(NAME, format!("status {}", health.status)),
+        Err(e) => CheckResult::failed(NAME, e.to_string()),
+    }
+}
+
+/// The token manager signs a token and accepts it back
+pub fn check_jwt(manager: &JwtManager) -> CheckResult {
+    const NAME: &str = "jwt";
+    let claims = Claims::new("self-check".into(), "viewer".into(), None, 1).with_scope(NAME);
+    let token = match manager.generate_token(claims) {
+        Ok(token) => token,
+        Err(e) => return CheckResult::failed(NAME, format!("cannot sign: {}", e)),
+    };
+    match manager.validate_token(&token) {
+        Ok(_) => CheckResult::ok(
+            NAME,
+            format!("{} token signed and verified", manager.algorithm().name()),
+        ),
+        Err(e) => CheckResult::failed(NAME, format!("cannot verify: {}", e)),
+    }
+}
+
+/// `port` opens with `config`; it is closed again straight away
+pub fn check_serial_port(port: Option<&str>, config: SerialConfig) -> CheckResult {
+    const NAME: &str = "serial_port";
+    let Some(port) = port else {
+        return CheckResult::skipped(NAME, "no serial port configured");
+    };
+    match serialport::new(port, config.baud)
+        .timeout(config.timeout)
+        .open()
+    {
+        Ok(_) => CheckResult::ok(NAME, format!("{} opened at {} baud", port, config.baud)),
+        Err(e) => CheckResult::failed(NAME, format!("cannot open {}: {}", port, e)),
+    }
+}
+
+/// A file can be created in `dir`. With `create` the directory is made on
+/// demand, so the closest existing ancestor is tried instead when it
+/// doesn't exist yet.
+pub fn check_writable_dir(name: &str, dir: &Path, create: bool) -> CheckResult {
+    let mut target = dir;
+    if create {
+        while !target.exists() {
+            match target.parent() {
+                Some(parent) if parent.as_os_str().is_empty() => target = Path::new("."),
+                Some(parent) => target = parent,
+                None => break,
+            }
+        }
+    }
+    if !target.is_dir() {
+        return CheckResult::failed(name, format!("{} is not a directory", dir.display()));
+    }
+
+    let probe = target.join(format!(".soundsense-check-{}", Uuid::new_v4()));
+    let written = std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&probe)
+        .and_then(|_| std::fs::remove_file(&probe));
+    match written {
+        Ok(()) if target == dir => CheckResult::ok(name, format!("{} is writable", dir.display())),
+        Ok(()) => CheckResult::ok(
+            name,
+            format!("{} can be created in {}", dir.display(), target.display()),
+        ),
+        Err(e) => CheckResult::failed(name, format!("cannot write to {}: {}", target.display(), e)),
+    }
+}
+
+/// The directory `file` is written in
+fn check_file_dir(name: &str, file: &Path) -> CheckResult {
+    match file.parent().filter(|p| !p.as_os_str().is_empty()) {
+        Some(dir) => check_writable_dir(name, dir, false),
+        None => check_writable_dir(name, Path::new("."), false),
+    }
+}
+
+/// Directories the service writes to: bulk exports, the dead-letter queue
+/// and snapshots, those that are configured
+pub fn check_directories(
+    export_dir: &Path,
+    dlq_path: Option<&Path>,
+    snapshot_path: Option<&Path>,
+) -> Vec<CheckResult> {
+    vec![
+        check_writable_dir("export_dir", export_dir, true),
+        match dlq_path {
+            Some(path) => check_file_dir("dlq_dir", path),
+            None => CheckResult::skipped("dlq_dir", "no dead-letter queue"),
+        },
+        match snapshot_path {
+            Some(path) => check_file_dir("snapshot_dir", path),
+            None => CheckResult::skipped("snapshot_dir", "SNAPSHOT_PATH not set"),
+        },
+    ]
+}
+
+/// Every check, against the configuration in the environment, with the
+/// serial port and line settings given on the command line or in the
+/// environment
+pub async fn check_environment(
+    serial_port: Option<&str>,
+    serial_config: Result<SerialConfig, String>,
+) -> CheckReport {
+    let database_url = std::env::var("DATABASE_URL").ok();
+
+    let mut errors = Vec::new();
+    if let Err(e) = validate_security_config() {
+        errors.push(e);
+    }
+    if let Err(e) = std::env::var("FHIR_EXTRA_CODINGS")
+        .map_or(Ok(Vec::new()), |list| fhir::parse_extra_codings(&list))
+    {
+        errors.push(format!("FHIR_EXTRA_CODINGS: {}", e));
+    }
+    if let Err(e) = validation::allowed_signal_codes_from_env() {
+        errors.push(e);
+    }
+    let serial_config = serial_config.map_err(|e| errors.push(e)).ok();
+    let deps = RouteDeps::from_env().map_err(|e| errors.push(e)).ok();
+
+    let mut checks = vec![if errors.is_empty() {
+        CheckResult::ok("configuration", "loaded")
+    } else {
+        CheckResult::failed("configuration", errors.join("; "))
+    }];
+    checks.push(check_database_url(database_url.as_deref()).await);
+    match &deps {
+        Some(deps) => {
+            checks.push(check_ml_service(deps.ml_client.as_deref()).await);
+            checks.push(check_jwt(&deps.jwt_manager));
+        }
+        None => {
+            checks.push(CheckResult::skipped("ml_service", "configuration invalid"));
+            checks.push(CheckResult::skipped("jwt", "configuration invalid"));
+        }
+    }
+    checks.push(match serial_config {
+        Some(config) => check_serial_port(serial_port, config),
+        None => CheckResult::skipped("serial_port", "configuration invalid"),
+    });
+
+    // The queue is only opened with a database; the snapshot only without
+    let dlq_path = database_url
+        .as_ref()
+        .map(|_| DeadLetterQueue::path_from_env());
+    let snapshots = SnapshotManager::from_env().filter(|_| database_url.is_none());
+    checks.extend(check_directories(
+        BulkExports::from_env().dir(),
+        dlq_path.as_deref(),
+        snapshots.as_ref().map(|s| s.path()),
+    ));
+
+    CheckReport::new(checks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::JwtAlgorithm;
+
+    #[test]
+    fn test_report_fails_only_on_failed_checks() {
+        let report = CheckReport::new(vec![
+            CheckResult::ok("database", "query succeeded"),
+            CheckResult::skipped("ml_service", "ML_SERVICE_URL not set"),
+        ]);
+        assert!(report.ok);
+        assert!(report.to_string().ends_with("All 2 checks passed"));
+
+        let report = CheckReport::new(vec![
+            CheckResult::ok("database", "query succeeded"),
+            CheckResult::failed("jwt", "cannot sign"),
+        ]);
+        assert!(!report.ok);
+        let text = report.to_string();
+        assert!(text.contains("[FAIL] jwt       cannot sign\n"), "{}", text);
+        assert!(text.ends_with("1 of 2 checks failed"));
+
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["ok"], false);
+        assert_eq!(json["checks"][1]["status"], "failed");
+    }
+
+    #[tokio::test]
+    async fn test_readiness_is_checked_again_once_stale() {
+        let report = |name: &'static str| async move {
+            CheckReport::new(vec![CheckResult::failed(
+                name,
+                "/var/lib/soundsense: denied",
+            )])
+        };
+        let cache = ReadinessCache::new(Duration::from_millis(50));
+        assert_eq!(
+            cache.get_or_check(report("first")).await.checks[0].name,
+            "first"
+        );
+        assert_eq!(
+            cache.get_or_check(report("second")).await.checks[0].name,
+            "first"
+        );
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        let fresh = cache.get_or_check(report("third")).await;
+        assert_eq!(fresh.checks[0].name, "third");
+
+        let json = serde_json::to_value(fresh.redacted()).unwrap();
+        assert_eq!(json["ok"], false);
+        assert_eq!(
+            json["checks"][0],
+            serde_json::json!({ "name": "third", "status": "failed" })
+        );
+    }
+
+    #[test]
+    fn test_jwt_round_trip() {
+        let manager = JwtManager::new(JwtAlgorithm::HS256("test_secret".to_string()));
+        assert_eq!(check_jwt(&manager).status, CheckStatus::Ok);
+    }
+
+    #[test]
+    fn test_directories_are_probed_without_leaving_files() {
+        let dir = std::env::temp_dir().join(format!("soundsense-check-test-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let check = check_writable_dir("export_dir", &dir, false);
+        assert_eq!(check.status, CheckStatus::Ok, "{:?}", check);
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+
+        // Created on demand: the parent is what has to be writable
+        let missing = dir.join("exports");
+        let check = check_writable_dir("export_dir", &missing, true);
+        assert_eq!(check.status, CheckStatus::Ok, "{:?}", check);
+        assert!(!missing.exists());
+        assert_eq!(
+            check_writable_dir("dlq_dir", &missing, false).status,
+            CheckStatus::Failed
+        );
+
+        let checks = check_directories(&dir, Some(&dir.join("dlq.jsonl")), None);
+        assert_eq!(checks[1].status, CheckStatus::Ok);
+        assert_eq!(checks[2].status, CheckStatus::Skipped);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_serial_port_that_does_not_exist_fails() {
+        let config = SerialConfig::default();
+        assert_eq!(check_serial_port(None, config).status, CheckStatus::Skipped);
+        assert_eq!(
+            check_serial_port(Some("/dev/soundsense-no-such-port"), config).status,
+            CheckStatus::Failed
+        );
+    }
+}
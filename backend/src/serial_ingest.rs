@@ -1,53 +1,545 @@
 use anyhow::{Context, Result};
-use chrono::Utc;
+use chrono::{DateTime, Utc};
 use regex::Regex;
+use serde::Serialize;
+use std::collections::VecDeque;
 use std::io::{BufRead, BufReader, Read, Write};
 use std::net::TcpStream;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, RwLock};
 use std::time::Duration;
+use utoipa::ToSchema;
 
-use crate::domain::models::{SensorReading, SignalCode};
+use crate::domain::models::{normalize_unit, SensorReading, SignalCode, TimestampSource};
 
-pub fn run_serial_to_ingest(
-    port_name: &str,
-    baud: u32,
-    ingest_url: &str, // e.g. "http://127.0.0.1:8080/ingest"
-    token: Option<&str>,
-) -> Result<()> {
-    let port = serialport::new(port_name, baud)
-        .timeout(Duration::from_millis(1000))
-        .open()
-        .with_context(|| format!("Failed to open serial port {}", port_name))?;
+/// One line of the serial protocol
+#[derive(Debug, Clone, PartialEq)]
+pub enum SerialLine {
+    /// `SOUND:<value>`, e.g. `SOUND:512` or `SOUND:-12.5`
+    Sound(f64),
+    /// `UNIT:<unit>`, e.g. `UNIT:dB`; applies to the readings that follow
+    Unit(String),
+}
 
-    let mut reader = BufReader::new(port);
+/// Parses serial lines, remembering the unit last declared by the device
+pub struct SerialParser {
+    sound: Regex,
+    unit_decl: Regex,
+    unit: String,
+}
 
-    // Accepts: SOUND:123
-    let re = Regex::new(r"^SOUND:(\d+)\s*$")?;
+impl Default for SerialParser {
+    fn default() -> Self {
+        Self {
+            sound: Regex::new(r"^SOUND:([+-]?\d+(?:\.\d+)?)\s*$").expect("valid regex"),
+            unit_decl: Regex::new(r"^UNIT:(\S+)\s*$").expect("valid regex"),
+            // Plain Arduino sketches send ADC counts and never declare a unit
+            unit: "raw".into(),
+        }
+    }
+}
 
-    loop {
-        let mut line = String::new();
-        let n = reader.read_line(&mut line)?;
-        if n == 0 {
-            continue;
+impl SerialParser {
+    /// Unit of the readings that follow
+    pub fn unit(&self) -> &str {
+        &self.unit
+    }
+
+    /// Recognise one line; anything else the device prints yields `None`
+    pub fn parse(&mut self, line: &str) -> Option<SerialLine> {
+        let line = line.trim();
+        if let Some(caps) = self.sound.captures(line) {
+            return caps[1].parse().ok().map(SerialLine::Sound);
+        }
+        if let Some(caps) = self.unit_decl.captures(line) {
+            self.unit = normalize_unit(&caps[1]);
+            return Some(SerialLine::Unit(self.unit.clone()));
         }
+        None
+    }
+}
+
+/// Baud rate of the bundled Arduino sketch
+pub const DEFAULT_BAUD: u32 = 9600;
+/// How long a read may wait for the device before the link is checked
+pub const DEFAULT_SERIAL_TIMEOUT_MS: u64 = 1000;
+/// Fastest baud rate accepted; common USB serial adapters top out below it
+const MAX_BAUD: u32 = 4_000_000;
+
+/// Line settings the serial port is opened with. A baud rate differing
+/// from the board's reads as garbage rather than failing, so it must match.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SerialConfig {
+    pub baud: u32,
+    pub timeout: Duration,
+}
+
+impl Default for SerialConfig {
+    fn default() -> Self {
+        Self {
+            baud: DEFAULT_BAUD,
+            timeout: Duration::from_millis(DEFAULT_SERIAL_TIMEOUT_MS),
+        }
+    }
+}
+
+impl SerialConfig {
+    /// Settings from `--baud`/`SERIAL_BAUD` and
+    /// `--serial-timeout-ms`/`SERIAL_TIMEOUT_MS` values, each defaulting
+    /// when not given
+    pub fn parse(baud: Option<&str>, timeout_ms: Option<&str>) -> Result<Self, String> {
+        let mut config = Self::default();
+        if let Some(value) = baud {
+            config.baud = value
+                .trim()
+                .parse::<u32>()
+                .ok()
+                .filter(|b| (1..=MAX_BAUD).contains(b))
+                .ok_or_else(|| {
+                    format!(
+                        "serial baud rate must be between 1 and {}, got '{}'",
+                        MAX_BAUD, value
+                    )
+                })?;
+        }
+        if let Some(value) = timeout_ms {
+            let ms = value
+                .trim()
+                .parse::<u64>()
+                .ok()
+                .filter(|ms| *ms > 0)
+                .ok_or_else(|| {
+                    format!(
+                        "serial timeout must be a positive number of milliseconds, got '{}'",
+                        value
+                    )
+                })?;
+            config.timeout = Duration::from_millis(ms);
+        }
+        Ok(config)
+    }
+}
+
+/// State of the serial link, shared between the reader thread and the web app
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct SerialStatus {
+    pub port: String,
+    pub connected: bool,
+    pub last_reading_at: Option<DateTime<Utc>>,
+    /// Lines recognised as readings or unit declarations
+    pub lines_parsed: u64,
+    /// Other lines the device printed
+    pub lines_rejected: u64,
+    /// Times the link was re-established after failing
+    pub reconnect_count: u64,
+    pub last_error: Option<String>,
+    /// Readings waiting to be forwarded again after the backend was down
+    #[schema(value_type = SerialIngestStatus)]
+    pub retry_queue: Arc<SerialIngestStatus>,
+    /// Whether the link was ever up, so the first connection isn't counted
+    /// as a reconnect
+    #[serde(skip)]
+    ever_connected: bool,
+}
+
+impl SerialStatus {
+    pub fn new(port: impl Into<String>) -> Self {
+        Self {
+            port: port.into(),
+            connected: false,
+            last_reading_at: None,
+            lines_parsed: 0,
+            lines_rejected: 0,
+            reconnect_count: 0,
+            last_error: None,
+            retry_queue: Arc::default(),
+            ever_connected: false,
+        }
+    }
+
+    fn connect(&mut self) {
+        if self.ever_connected {
+            self.reconnect_count += 1;
+        }
+        self.ever_connected = true;
+        self.connected = true;
+    }
+
+    fn fail(&mut self, error: impl ToString) {
+        self.connected = false;
+        self.last_error = Some(error.to_string());
+    }
+}
+
+pub type SharedSerialStatus = Arc<RwLock<SerialStatus>>;
+
+const DEFAULT_RETRY_QUEUE_SIZE: usize = 1000;
+const DEFAULT_RETRY_INTERVAL_MS: u64 = 5000;
+
+/// Counters of a `RetryQueue`, updated as it is used
+#[derive(Debug, Default, Serialize, ToSchema)]
+pub struct SerialIngestStatus {
+    /// Readings waiting in the queue
+    #[serde(serialize_with = "serialize_counter")]
+    #[schema(value_type = u64)]
+    pub queued_count: AtomicU64,
+    /// Queued readings the backend has since accepted
+    #[serde(serialize_with = "serialize_counter")]
+    #[schema(value_type = u64)]
+    pub retry_successes: AtomicU64,
+    /// Retries the backend failed or did not answer, kept for later
+    #[serde(serialize_with = "serialize_counter")]
+    #[schema(value_type = u64)]
+    pub retry_failures: AtomicU64,
+    /// Readings the backend refused with a 4xx, dropped since sending them
+    /// again would not change its answer
+    #[serde(serialize_with = "serialize_counter")]
+    #[schema(value_type = u64)]
+    pub refused: AtomicU64,
+}
 
-        let trimmed = line.trim();
-        if let Some(caps) = re.captures(trimmed) {
-            let v: f64 = caps[1].parse::<u32>().unwrap_or(0) as f64;
+/// The backend refused a reading with this 4xx status; sending it again
+/// would get the same answer
+#[derive(Debug)]
+pub struct Refused(pub u16);
 
-            let reading = SensorReading {
-                patient_id: "demo-patient-1".into(),
-                device_id: format!("arduino-{}", port_name),
-                code: SignalCode::Sound,
-                value: v,
-                unit: "raw".into(),
-                ts: Utc::now(),
+impl std::fmt::Display for Refused {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "backend refused the reading with {}", self.0)
+    }
+}
+
+impl std::error::Error for Refused {}
+
+/// Whether `e` is a refusal rather than a failure worth retrying
+pub fn is_refused(e: &anyhow::Error) -> bool {
+    e.downcast_ref::<Refused>().is_some()
+}
+
+fn serialize_counter<S: serde::Serializer>(counter: &AtomicU64, s: S) -> Result<S::Ok, S::Error> {
+    s.serialize_u64(counter.load(Ordering::Relaxed))
+}
+
+/// Bounded FIFO of readings the backend could not take. When full, the
+/// oldest entry makes room for the new one.
+#[derive(Debug)]
+pub struct RetryQueue<T> {
+    items: Mutex<VecDeque<T>>,
+    capacity: usize,
+    status: Arc<SerialIngestStatus>,
+}
+
+impl<T> RetryQueue<T> {
+    /// Hold up to `capacity` (at least one) entries, counting into `status`
+    pub fn new(capacity: usize, status: Arc<SerialIngestStatus>) -> Self {
+        Self {
+            items: Mutex::new(VecDeque::new()),
+            capacity: capacity.max(1),
+            status,
+        }
+    }
+
+    /// Size from `SERIAL_RETRY_QUEUE_SIZE` (default 1000)
+    pub fn from_env(status: Arc<SerialIngestStatus>) -> Self {
+        let capacity = std::env::var("SERIAL_RETRY_QUEUE_SIZE")
+            .ok()
+            .and_then(|v| v.parse::<usize>().ok())
+            .filter(|v| *v > 0)
+            .unwrap_or(DEFAULT_RETRY_QUEUE_SIZE);
+        Self::new(capacity, status)
+    }
+
+    /// How often to retry, from `SERIAL_RETRY_INTERVAL_MS` (default 5000)
+    pub fn interval_from_env() -> Duration {
+        let ms = std::env::var("SERIAL_RETRY_INTERVAL_MS")
+            .ok()
+            .and_then(|v| v.parse::<u64>().ok())
+            .filter(|v| *v > 0)
+            .unwrap_or(DEFAULT_RETRY_INTERVAL_MS);
+        Duration::from_millis(ms)
+    }
+
+    fn items(&self) -> MutexGuard<'_, VecDeque<T>> {
+        self.items.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn set_count(&self, items: &VecDeque<T>) {
+        self.status
+            .queued_count
+            .store(items.len() as u64, Ordering::Relaxed);
+    }
+
+    pub fn len(&self) -> usize {
+        self.items().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Queue `item`, returning the oldest entry if it had to be dropped
+    pub fn push(&self, item: T) -> Option<T> {
+        let mut items = self.items();
+        let evicted = if items.len() >= self.capacity {
+            items.pop_front()
+        } else {
+            None
+        };
+        items.push_back(item);
+        self.set_count(&items);
+        evicted
+    }
+
+    /// Try `send` on the oldest entry, dequeuing it if that succeeds or is
+    /// `Refused`. `None` if the queue is empty. The queue isn't locked while
+    /// `send` runs, so new readings can still be queued meanwhile.
+    pub fn retry_oldest(&self, send: impl FnOnce(&T) -> Result<()>) -> Option<Result<()>> {
+        let item = {
+            let mut items = self.items();
+            let item = items.pop_front()?;
+            self.set_count(&items);
+            item
+        };
+
+        let result = send(&item);
+        match &result {
+            Ok(()) => {
+                self.status.retry_successes.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(e) if is_refused(e) => {
+                tracing::warn!(error = %e, "Dropping queued serial reading");
+                self.status.refused.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(_) => {
+                self.status.retry_failures.fetch_add(1, Ordering::Relaxed);
+                // Back to the front, unless newer readings filled the queue:
+                // it is the oldest, so the first to go anyway
+                let mut items = self.items();
+                if items.len() < self.capacity {
+                    items.push_front(item);
+                    self.set_count(&items);
+                }
+            }
+        }
+        Some(result)
+    }
+
+    /// Retry oldest first until the queue is empty or a retry fails;
+    /// refused entries are dropped on the way
+    pub fn drain(&self, mut send: impl FnMut(&T) -> Result<()>) -> usize {
+        let mut sent = 0;
+        loop {
+            match self.retry_oldest(&mut send) {
+                Some(Ok(())) => sent += 1,
+                Some(Err(e)) if is_refused(&e) => {}
+                _ => return sent,
+            }
+        }
+    }
+}
+
+/// How long to wait before reopening a failed serial port
+const RECONNECT_DELAY: Duration = Duration::from_secs(2);
+
+/// Longest line kept. The protocol's lines are a few bytes; a longer one
+/// means the stream is garbage, e.g. at the wrong baud rate.
+pub const MAX_LINE_BYTES: usize = 256;
+
+/// Reads the serial protocol off a port, keeping its `SerialStatus` current
+pub struct SerialLink {
+    parser: SerialParser,
+    status: SharedSerialStatus,
+}
+
+impl SerialLink {
+    pub fn new(status: SharedSerialStatus) -> Self {
+        Self {
+            parser: SerialParser::default(),
+            status,
+        }
+    }
+
+    fn update(&self, f: impl FnOnce(&mut SerialStatus)) {
+        // A panic elsewhere must not stop the link from reporting
+        let mut status = self.status.write().unwrap_or_else(|e| e.into_inner());
+        f(&mut status);
+    }
+
+    /// Pass each reading on `port`, which has just been opened, to `send`
+    /// with the unit last declared, until the port fails; returns why.
+    /// Read timeouts only mean the device is quiet. Lines longer than
+    /// `MAX_LINE_BYTES` are dropped up to the next newline, and lines that
+    /// aren't UTF-8 are dropped, so garbage never stalls the link.
+    pub fn read<R: BufRead>(
+        &mut self,
+        mut port: R,
+        mut send: impl FnMut(f64, &str) -> Result<()>,
+    ) -> std::io::Error {
+        self.update(SerialStatus::connect);
+
+        // Kept across timeouts, which can split a line
+        let mut line = Vec::new();
+        // Whether the rest of an over-long line is being skipped
+        let mut skipping = false;
+        let error = loop {
+            let buf = match port.fill_buf() {
+                Ok([]) => {
+                    break std::io::Error::new(
+                        std::io::ErrorKind::UnexpectedEof,
+                        "serial port closed",
+                    )
+                }
+                Ok(buf) => buf,
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        std::io::ErrorKind::TimedOut | std::io::ErrorKind::Interrupted
+                    ) =>
+                {
+                    continue
+                }
+                Err(e) => break e,
+            };
+            let (chunk, complete) = match buf.iter().position(|b| *b == b'\n') {
+                Some(end) => (&buf[..=end], true),
+                None => (buf, false),
             };
+            let used = chunk.len();
+            if !skipping && line.len() + used > MAX_LINE_BYTES {
+                tracing::warn!(
+                    max_bytes = MAX_LINE_BYTES,
+                    "Serial line too long, skipping to the next newline"
+                );
+                self.update(|s| s.lines_rejected += 1);
+                line.clear();
+                skipping = true;
+            }
+            if !skipping {
+                line.extend_from_slice(chunk);
+            }
+            port.consume(used);
+
+            if !complete {
+                continue;
+            }
+            if std::mem::take(&mut skipping) {
+                continue;
+            }
+            match String::from_utf8(std::mem::take(&mut line)) {
+                Ok(line) => self.handle_line(&line, &mut send),
+                Err(e) => {
+                    tracing::debug!(
+                        line = %String::from_utf8_lossy(e.as_bytes()).trim(),
+                        "Dropping serial line that isn't UTF-8"
+                    );
+                    self.update(|s| s.lines_rejected += 1);
+                }
+            }
+        };
+
+        self.update(|s| s.fail(&error));
+        error
+    }
+
+    fn handle_line(&mut self, line: &str, send: &mut impl FnMut(f64, &str) -> Result<()>) {
+        if line.trim().is_empty() {
+            return;
+        }
+
+        match self.parser.parse(line) {
+            Some(SerialLine::Sound(value)) => {
+                self.update(|s| {
+                    s.lines_parsed += 1;
+                    s.last_reading_at = Some(Utc::now());
+                });
+                if let Err(e) = send(value, self.parser.unit()) {
+                    tracing::warn!(error = ?e, "Failed to forward serial reading");
+                    self.update(|s| s.last_error = Some(format!("{:#}", e)));
+                }
+            }
+            Some(SerialLine::Unit(unit)) => {
+                tracing::info!(%unit, "Serial device declared its unit");
+                self.update(|s| s.lines_parsed += 1);
+            }
+            None => {
+                tracing::debug!(line = line.trim(), "Ignoring unrecognised serial line");
+                self.update(|s| s.lines_rejected += 1);
+            }
+        }
+    }
+}
 
-            // Send to backend /ingest
-            if let Err(e) = http_post_json(ingest_url, &reading, token) {
-                eprintln!("serial->ingest POST failed: {e:?}");
+/// Forward readings from the serial port to `ingest_url`, reopening the
+/// port whenever it fails. Never returns.
+pub fn run_serial_to_ingest(
+    port_name: &str,
+    config: SerialConfig,
+    ingest_url: &str, // e.g. "http://127.0.0.1:8080/ingest"
+    token: Option<&str>,
+    status: SharedSerialStatus,
+) {
+    let mut link = SerialLink::new(status.clone());
+    let device_id = format!("arduino-{}", port_name);
+
+    // Readings the backend could not take are retried in the background
+    let counters = status
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .retry_queue
+        .clone();
+    let retry_queue = Arc::new(RetryQueue::from_env(counters.clone()));
+    {
+        let retry_queue = retry_queue.clone();
+        let ingest_url = ingest_url.to_string();
+        let token = token.map(str::to_string);
+        let interval = RetryQueue::<SensorReading>::interval_from_env();
+        std::thread::spawn(move || loop {
+            std::thread::sleep(interval);
+            let sent = retry_queue.drain(|r| http_post_json(&ingest_url, r, token.as_deref()));
+            if sent > 0 {
+                tracing::info!(sent, "Forwarded queued serial readings");
+            }
+        });
+    }
+
+    loop {
+        match serialport::new(port_name, config.baud)
+            .timeout(config.timeout)
+            .open()
+        {
+            Ok(port) => {
+                tracing::info!(port = port_name, %ingest_url, "Serial port open");
+                let error = link.read(BufReader::new(port), |value, unit| {
+                    let reading = SensorReading {
+                        patient_id: "demo-patient-1".into(),
+                        device_id: device_id.clone(),
+                        code: SignalCode::Sound,
+                        value,
+                        unit: unit.to_string(),
+                        ts: Utc::now(),
+                        ts_source: TimestampSource::Device,
+                        metadata: Default::default(),
+                    };
+                    http_post_json(ingest_url, &reading, token).inspect_err(|e| {
+                        if is_refused(e) {
+                            tracing::warn!(error = %e, "Dropping serial reading");
+                            counters.refused.fetch_add(1, Ordering::Relaxed);
+                        } else if retry_queue.push(reading).is_some() {
+                            tracing::warn!("Serial retry queue full, dropped the oldest reading");
+                        }
+                    })
+                });
+                tracing::warn!(port = port_name, error = %error, "Serial link lost");
+            }
+            Err(e) => {
+                tracing::warn!(port = port_name, error = %e, "Failed to open serial port");
+                status
+                    .write()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .fail(format!("failed to open {}: {}", port_name, e));
             }
         }
+        std::thread::sleep(RECONNECT_DELAY);
     }
 }
 
@@ -103,12 +595,300 @@ fn http_post_json(url: &str, reading: &SensorReading, token: Option<&str>) -> Re
         resp.push_str(&String::from_utf8_lossy(&buf[..n]));
     }
 
-    // Basic status check
-    if !resp.starts_with("HTTP/1.1 200") && !resp.starts_with("HTTP/1.1 201") {
-        // Print first line for debugging
-        let first_line = resp.lines().next().unwrap_or("<no response>");
-        anyhow::bail!("unexpected response: {}", first_line);
+    check_status(resp.lines().next().unwrap_or("<no response>"))
+}
+
+/// Judge the backend's answer by its status line. 2xx is success and a 4xx
+/// is `Refused`, except 408 and 429, which only ask the client to come back
+/// later; anything else (5xx, no answer) is a failure worth retrying.
+fn check_status(status_line: &str) -> Result<()> {
+    let status = status_line
+        .strip_prefix("HTTP/1.")
+        .and_then(|rest| rest.split_whitespace().nth(1))
+        .and_then(|code| code.parse::<u16>().ok());
+    match status {
+        Some(200..=299) => Ok(()),
+        Some(status @ 400..=499) if status != 408 && status != 429 => {
+            Err(anyhow::Error::new(Refused(status)).context(status_line.to_string()))
+        }
+        // Keep the first line for debugging
+        _ => anyhow::bail!("unexpected response: {}", status_line),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+    use std::io::ErrorKind;
+
+    /// Port replaying scripted reads; like the real one, it times out when
+    /// the device is quiet
+    struct MockPort(VecDeque<std::io::Result<&'static [u8]>>);
+
+    impl Read for MockPort {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            match self.0.pop_front() {
+                Some(Ok(bytes)) => {
+                    buf[..bytes.len()].copy_from_slice(bytes);
+                    Ok(bytes.len())
+                }
+                Some(Err(e)) => Err(e),
+                None => Ok(0),
+            }
+        }
+    }
+
+    fn port(reads: Vec<std::io::Result<&'static [u8]>>) -> BufReader<MockPort> {
+        BufReader::new(MockPort(reads.into()))
+    }
+
+    #[test]
+    fn test_link_status_follows_the_port() {
+        let status = Arc::new(RwLock::new(SerialStatus::new("/dev/ttyACM0")));
+        let mut link = SerialLink::new(status.clone());
+        let mut sent = Vec::new();
+
+        let error = link.read(
+            port(vec![
+                Ok(b"Sensor ready\r\n"),
+                Ok(b"SOUND:51"),
+                Err(ErrorKind::TimedOut.into()),
+                Ok(b"2\r\nUNIT:dB\r\n\r\n"),
+                Ok(b"SOUND:64.5\r\n"),
+                Err(ErrorKind::BrokenPipe.into()),
+            ]),
+            |value, unit| {
+                sent.push((value, unit.to_string()));
+                Ok(())
+            },
+        );
+        assert_eq!(error.kind(), ErrorKind::BrokenPipe);
+        assert_eq!(sent, vec![(512.0, "raw".into()), (64.5, "dB".into())]);
+        {
+            let status = status.read().unwrap();
+            assert!(!status.connected);
+            assert_eq!(status.lines_parsed, 3);
+            assert_eq!(status.lines_rejected, 1);
+            assert_eq!(status.reconnect_count, 0);
+            assert!(status.last_reading_at.is_some());
+            assert!(status.last_error.is_some());
+        }
+
+        // The port comes back; a reading that can't be forwarded is counted
+        // as parsed and its error kept
+        let mut seen_error = None;
+        let error = link.read(
+            port(vec![Ok(b"SOUND:70\n"), Ok(b"SOUND:71\n")]),
+            |value, _| {
+                if value == 70.0 {
+                    anyhow::bail!("unexpected response: HTTP/1.1 401 Unauthorized");
+                }
+                seen_error = status.read().unwrap().last_error.clone();
+                Ok(())
+            },
+        );
+        assert_eq!(error.kind(), ErrorKind::UnexpectedEof);
+        assert_eq!(
+            seen_error.as_deref(),
+            Some("unexpected response: HTTP/1.1 401 Unauthorized")
+        );
+        let status = status.read().unwrap();
+        assert_eq!(status.reconnect_count, 1);
+        assert_eq!(status.lines_parsed, 5);
+        assert_eq!(status.last_error.as_deref(), Some("serial port closed"));
+    }
+
+    #[test]
+    fn test_garbage_lines_are_dropped_and_reading_resumes() {
+        let status = Arc::new(RwLock::new(SerialStatus::new("/dev/ttyACM0")));
+        let mut link = SerialLink::new(status.clone());
+        let mut sent = Vec::new();
+
+        let error = link.read(
+            port(vec![
+                Ok(b"SOUND:10\n"),
+                // Over-long, arriving in pieces and across a timeout
+                Ok(&[b'7'; MAX_LINE_BYTES]),
+                Err(ErrorKind::TimedOut.into()),
+                Ok(&[0x55; 1000]),
+                Ok(b"SOUND:99\nSOUND:20\n"),
+                // Not UTF-8
+                Ok(b"SOUND:\xff\xfe30\n"),
+                Ok(b"\xc3SOUND:31\nSOUND:30\n"),
+                // Exactly at the limit, newline included
+                Ok(&[b' '; MAX_LINE_BYTES - 10]),
+                Ok(b"SOUND:40\n"),
+            ]),
+            |value, _| {
+                sent.push(value);
+                Ok(())
+            },
+        );
+
+        assert_eq!(error.kind(), ErrorKind::UnexpectedEof);
+        assert_eq!(sent, vec![10.0, 20.0, 30.0, 40.0]);
+        let status = status.read().unwrap();
+        assert_eq!(status.lines_parsed, 4);
+        assert_eq!(status.lines_rejected, 3);
+    }
+
+    #[test]
+    fn test_full_retry_queue_evicts_the_oldest() {
+        let status = Arc::new(SerialIngestStatus::default());
+        let queue = RetryQueue::new(2, status.clone());
+
+        assert_eq!(queue.push(1), None);
+        assert_eq!(queue.push(2), None);
+        assert_eq!(queue.push(3), Some(1));
+        assert_eq!(queue.len(), 2);
+        assert_eq!(status.queued_count.load(Ordering::Relaxed), 2);
+
+        let mut sent = Vec::new();
+        queue.drain(|n| {
+            sent.push(*n);
+            Ok(())
+        });
+        assert_eq!(sent, vec![2, 3]);
+    }
+
+    #[test]
+    fn test_only_refusals_are_dropped() {
+        assert!(check_status("HTTP/1.1 200 OK").is_ok());
+        assert!(check_status("HTTP/1.1 202 Accepted").is_ok());
+        for line in ["HTTP/1.1 400 Bad Request", "HTTP/1.1 403 Forbidden"] {
+            assert!(is_refused(&check_status(line).unwrap_err()), "{}", line);
+        }
+        for line in [
+            "HTTP/1.1 500 Internal Server Error",
+            "HTTP/1.1 503 Service Unavailable",
+            "HTTP/1.1 429 Too Many Requests",
+            "HTTP/1.1 408 Request Timeout",
+            "<no response>",
+        ] {
+            let e = check_status(line).unwrap_err();
+            assert!(!is_refused(&e), "{}", line);
+        }
+
+        // A refused entry leaves the queue; the one behind it is still sent
+        let status = Arc::new(SerialIngestStatus::default());
+        let queue = RetryQueue::new(10, status.clone());
+        queue.push(1);
+        queue.push(2);
+        let mut sent = Vec::new();
+        let count = queue.drain(|n| {
+            if *n == 1 {
+                return check_status("HTTP/1.1 422 Unprocessable Entity");
+            }
+            sent.push(*n);
+            Ok(())
+        });
+        assert_eq!(count, 1);
+        assert_eq!(sent, vec![2]);
+        assert!(queue.is_empty());
+        assert_eq!(status.refused.load(Ordering::Relaxed), 1);
+        assert_eq!(status.retry_failures.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn test_retry_dequeues_only_on_success() {
+        let status = Arc::new(SerialIngestStatus::default());
+        let queue = RetryQueue::new(10, status.clone());
+        queue.push("a");
+        queue.push("b");
+
+        // Backend still down: the oldest stays at the front
+        let result = queue.retry_oldest(|_| anyhow::bail!("TCP connect failed"));
+        assert!(matches!(result, Some(Err(_))));
+        assert_eq!(queue.len(), 2);
+        assert_eq!(status.retry_failures.load(Ordering::Relaxed), 1);
+
+        // Back up: sent oldest first and dequeued
+        let mut sent = Vec::new();
+        let result = queue.retry_oldest(|r| {
+            sent.push(*r);
+            Ok(())
+        });
+        assert!(matches!(result, Some(Ok(()))));
+        assert_eq!(sent, vec!["a"]);
+        assert_eq!(queue.len(), 1);
+        assert_eq!(status.retry_successes.load(Ordering::Relaxed), 1);
+        assert_eq!(status.queued_count.load(Ordering::Relaxed), 1);
+
+        assert_eq!(queue.drain(|_| Ok(())), 1);
+        assert!(queue.is_empty());
+        assert!(queue.retry_oldest(|_| Ok(())).is_none());
     }
 
-    Ok(())
+    #[test]
+    fn test_serial_config_defaults_and_validation() {
+        assert_eq!(
+            SerialConfig::parse(None, None),
+            Ok(SerialConfig {
+                baud: 9600,
+                timeout: Duration::from_millis(1000),
+            })
+        );
+        assert_eq!(
+            SerialConfig::parse(Some("115200"), Some(" 250 ")),
+            Ok(SerialConfig {
+                baud: 115200,
+                timeout: Duration::from_millis(250),
+            })
+        );
+        // Either may be given without the other
+        assert_eq!(
+            SerialConfig::parse(Some("57600"), None).unwrap().baud,
+            57600
+        );
+        assert_eq!(
+            SerialConfig::parse(None, Some("50")).unwrap(),
+            SerialConfig {
+                baud: 9600,
+                timeout: Duration::from_millis(50),
+            }
+        );
+
+        for baud in ["0", "-9600", "fast", "", "4000001"] {
+            assert!(SerialConfig::parse(Some(baud), None).is_err(), "{}", baud);
+        }
+        for ms in ["0", "-1", "1s"] {
+            assert!(SerialConfig::parse(None, Some(ms)).is_err(), "{}", ms);
+        }
+    }
+
+    #[test]
+    fn test_sound_values() {
+        let mut parser = SerialParser::default();
+        assert_eq!(
+            parser.parse("SOUND:512\r\n"),
+            Some(SerialLine::Sound(512.0))
+        );
+        assert_eq!(parser.parse("SOUND:-12.5"), Some(SerialLine::Sound(-12.5)));
+        assert_eq!(parser.parse("SOUND:+3.25 "), Some(SerialLine::Sound(3.25)));
+
+        assert_eq!(parser.parse("SOUND:"), None);
+        assert_eq!(parser.parse("SOUND:1."), None);
+        assert_eq!(parser.parse("SOUND:.5"), None);
+        assert_eq!(parser.parse("SOUND:1e3"), None);
+        assert_eq!(parser.parse("Sensor ready"), None);
+    }
+
+    #[test]
+    fn test_unit_declaration_applies_to_later_readings() {
+        let mut parser = SerialParser::default();
+        assert_eq!(parser.unit(), "raw");
+
+        assert_eq!(parser.parse("UNIT:dB"), Some(SerialLine::Unit("dB".into())));
+        assert_eq!(parser.unit(), "dB");
+        assert_eq!(parser.parse("SOUND:-3.5"), Some(SerialLine::Sound(-3.5)));
+        assert_eq!(parser.unit(), "dB");
+
+        // Declared units are normalized like ingested ones
+        parser.parse("UNIT:au");
+        assert_eq!(parser.unit(), "raw");
+
+        assert_eq!(parser.parse("UNIT:"), None);
+        assert_eq!(parser.unit(), "raw");
+    }
 }
@@ -0,0 +1,325 @@
+/// Session Inventory Module
+///
+/// Tracks locally issued tokens so administrators can see a user's active
+/// sessions and revoke them (e.g. when offboarding staff). Revoked token ids are
+/// held in memory so the validator can reject them without a database round
+/// trip; `last_seen` is updated lazily and never on the request path.
+use chrono::{DateTime, TimeZone, Utc};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+use crate::auth::Claims;
+use crate::db::Database;
+use crate::errors::AppError;
+
+/// Entries kept when running without a database
+const MAX_IN_MEMORY_TOKENS: usize = 10_000;
+
+/// Minimum interval between `last_seen` updates for one token
+const LAST_SEEN_INTERVAL: Duration = Duration::from_secs(60);
+
+/// A token recorded at issue time
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct IssuedToken {
+    pub jti: String,
+    pub subject: String,
+    pub role: String,
+    pub device_id: Option<String>,
+    pub issued_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    pub last_seen_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+impl IssuedToken {
+    /// Build an inventory entry from freshly issued claims
+    pub fn from_claims(claims: &Claims) -> Option<Self> {
+        Some(Self {
+            jti: claims.jti.clone()?,
+            subject: claims.sub.clone(),
+            role: claims.role.clone(),
+            device_id: claims.device_id.clone(),
+            issued_at: Utc.timestamp_opt(claims.iat, 0).single()?,
+            expires_at: Utc.timestamp_opt(claims.exp, 0).single()?,
+            last_seen_at: None,
+            revoked_at: None,
+        })
+    }
+}
+
+/// Inventory of issued tokens plus the revocation list
+#[derive(Debug)]
+pub struct SessionStore {
+    db: Option<Database>,
+    tokens: RwLock<HashMap<String, IssuedToken>>,
+    revoked: RwLock<HashSet<String>>,
+    last_seen: RwLock<HashMap<String, Instant>>,
+}
+
+impl SessionStore {
+    pub fn new(db: Option<Database>) -> Self {
+        Self {
+            db,
+            tokens: RwLock::new(HashMap::new()),
+            revoked: RwLock::new(HashSet::new()),
+            last_seen: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Record a newly issued token
+    pub async fn record(&self, claims: &Claims) -> Result<(), AppError> {
+        let Some(token) = IssuedToken::from_claims(claims) else {
+            return Ok(());
+        };
+
+        if let Some(db) = &self.db {
+            return db.insert_issued_token(&token).await;
+        }
+
+        if let Ok(mut tokens) = self.tokens.write() {
+            if tokens.len() >= MAX_IN_MEMORY_TOKENS {
+                evict_one(&mut tokens);
+            }
+            tokens.insert(token.jti.clone(), token);
+        }
+        Ok(())
+    }
+
+    /// Active (unexpired) tokens, optionally for one subject
+    pub async fn list(&self, subject: Option<&str>) -> Result<Vec<IssuedToken>, AppError> {
+        if let Some(db) = &self.db {
+            return db.list_issued_tokens(subject).await;
+        }
+
+        let now = Utc::now();
+        let mut tokens: Vec<IssuedToken> = self
+            .tokens
+            .read()
+            .map(|tokens| {
+                tokens
+                    .values()
+                    .filter(|t| t.expires_at > now)
+                    .filter(|t| subject.is_none_or(|s| t.subject == s))
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default();
+        tokens.sort_by_key(|t| std::cmp::Reverse(t.issued_at));
+        Ok(tokens)
+    }
+
+    /// Revoke a token; returns `false` if it is not in the inventory
+    pub async fn revoke(&self, jti: &str) -> Result<bool, AppError> {
+        let found = if let Some(db) = &self.db {
+            db.revoke_issued_token(jti).await?
+        } else {
+            self.tokens
+                .write()
+                .ok()
+                .and_then(|mut tokens| {
+                    tokens.get_mut(jti).map(|t| {
+                        t.revoked_at.get_or_insert_with(Utc::now);
+                    })
+                })
+                .is_some()
+        };
+
+        if found {
+            if let Ok(mut revoked) = self.revoked.write() {
+                revoked.insert(jti.to_string());
+            }
+        }
+        Ok(found)
+    }
+
+    /// Whether a token id has been revoked
+    pub fn is_revoked(&self, jti: &str) -> bool {
+        self.revoked
+            .read()
+            .map(|revoked| revoked.contains(jti))
+            .unwrap_or(false)
+    }
+
+    /// Note that a token was used. At most one update per token per minute;
+    /// the database write is spawned and never awaited by the caller.
+    pub fn touch(self: &Arc<Self>, jti: &str) {
+        let now = Instant::now();
+        {
+            let Ok(mut last_seen) = self.last_seen.write() else {
+                return;
+            };
+            if last_seen
+                .get(jti)
+                .is_some_and(|at| now.duration_since(*at) < LAST_SEEN_INTERVAL)
+            {
+                return;
+            }
+            last_seen.insert(jti.to_string(), now);
+        }
+
+        let seen_at = Utc::now();
+        if let Some(db) = &self.db {
+            let db = db.clone();
+            let jti = jti.to_string();
+            tokio::spawn(async move {
+                if let Err(e) = db.touch_issued_token(&jti, seen_at).await {
+                    tracing::debug!(error = ?e, "Failed to update token last_seen");
+                }
+            });
+        } else if let Ok(mut tokens) = self.tokens.write() {
+            if let Some(token) = tokens.get_mut(jti) {
+                token.last_seen_at = Some(seen_at);
+            }
+        }
+    }
+
+    /// Drop expired tokens from the inventory and the revocation list
+    pub async fn cleanup_expired(&self) -> Result<u64, AppError> {
+        let removed = if let Some(db) = &self.db {
+            db.delete_expired_tokens().await?
+        } else {
+            let now = Utc::now();
+            self.tokens
+                .write()
+                .map(|mut tokens| {
+                    let before = tokens.len();
+                    tokens.retain(|_, t| t.expires_at > now);
+                    (before - tokens.len()) as u64
+                })
+                .unwrap_or(0)
+        };
+
+        self.reload_revoked().await?;
+        if let Ok(mut last_seen) = self.last_seen.write() {
+            last_seen.retain(|_, at| at.elapsed() < LAST_SEEN_INTERVAL);
+        }
+        Ok(removed)
+    }
+
+    /// Rebuild the revocation list from the inventory
+    pub async fn reload_revoked(&self) -> Result<(), AppError> {
+        let revoked: HashSet<String> = if let Some(db) = &self.db {
+            db.list_revoked_jtis().await?.into_iter().collect()
+        } else {
+            self.tokens
+                .read()
+                .map(|tokens| {
+                    tokens
+                        .values()
+                        .filter(|t| t.revoked_at.is_some())
+                        .map(|t| t.jti.clone())
+                        .collect()
+                })
+                .unwrap_or_default()
+        };
+
+        if let Ok(mut current) = self.revoked.write() {
+            *current = revoked;
+        }
+        Ok(())
+    }
+
+    /// Periodically clean up expired tokens and resync the revocation list
+    pub fn spawn_cleanup(self: &Arc<Self>, interval: Duration) {
+        let store = self.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                match store.cleanup_expired().await {
+                    Ok(removed) if removed > 0 => {
+                        tracing::info!(removed, "Removed expired tokens from inventory")
+                    }
+                    Ok(_) => {}
+                    Err(e) => tracing::warn!(error = ?e, "Token inventory cleanup failed"),
+                }
+            }
+        });
+    }
+}
+
+/// Make room in a full in-memory inventory: drop an expired entry if there is
+/// one, otherwise the entry closest to expiry
+fn evict_one(tokens: &mut HashMap<String, IssuedToken>) {
+    let victim = tokens
+        .values()
+        .min_by_key(|t| t.expires_at)
+        .map(|t| t.jti.clone());
+    if let Some(jti) = victim {
+        tokens.remove(&jti);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_record_list_and_revoke() {
+        let store = SessionStore::new(None);
+        let alice = Claims::new("alice".into(), "user".into(), None, 1);
+        let bob = Claims::new("bob".into(), "user".into(), None, 1);
+        store.record(&alice).await.unwrap();
+        store.record(&bob).await.unwrap();
+
+        let sessions = store.list(Some("alice")).await.unwrap();
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].jti, alice.jti.clone().unwrap());
+        assert_eq!(store.list(None).await.unwrap().len(), 2);
+
+        let jti = alice.jti.unwrap();
+        assert!(!store.is_revoked(&jti));
+        assert!(store.revoke(&jti).await.unwrap());
+        assert!(store.is_revoked(&jti));
+        assert!(!store.revoke("unknown").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_cleanup_removes_expired_tokens() {
+        let store = SessionStore::new(None);
+        let mut expired = Claims::new("alice".into(), "user".into(), None, 1);
+        expired.iat -= 7200;
+        expired.exp -= 7200;
+        let active = Claims::new("alice".into(), "user".into(), None, 1);
+        store.record(&expired).await.unwrap();
+        store.record(&active).await.unwrap();
+        store.revoke(expired.jti.as_deref().unwrap()).await.unwrap();
+
+        assert_eq!(store.cleanup_expired().await.unwrap(), 1);
+        assert_eq!(store.list(None).await.unwrap().len(), 1);
+        assert!(!store.is_revoked(expired.jti.as_deref().unwrap()));
+    }
+
+    #[tokio::test]
+    async fn test_touch_is_rate_limited() {
+        let store = Arc::new(SessionStore::new(None));
+        let claims = Claims::new("alice".into(), "user".into(), None, 1);
+        store.record(&claims).await.unwrap();
+        let jti = claims.jti.unwrap();
+
+        store.touch(&jti);
+        let first = store.list(None).await.unwrap()[0].last_seen_at.unwrap();
+        store.touch(&jti);
+        let second = store.list(None).await.unwrap()[0].last_seen_at.unwrap();
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn test_bounded_eviction_prefers_soonest_expiry() {
+        let mut tokens = HashMap::new();
+        for hours in [3, 1, 2] {
+            let claims = Claims::new("alice".into(), "user".into(), None, hours);
+            let token = IssuedToken::from_claims(&claims).unwrap();
+            tokens.insert(token.jti.clone(), token);
+        }
+
+        evict_one(&mut tokens);
+
+        assert_eq!(tokens.len(), 2);
+        assert!(tokens
+            .values()
+            .all(|t| t.expires_at > Utc::now() + chrono::Duration::minutes(90)));
+    }
+}
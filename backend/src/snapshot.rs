@@ -0,0 +1,238 @@
+/// In-Memory Snapshots
+///
+/// Without a database every reading lives in memory and is gone after a
+/// restart. With `SNAPSHOT_PATH` set, the readings are written to that file
+/// as CBOR every `SNAPSHOT_INTERVAL_SECS` (and on demand), and loaded back
+/// on startup with `LOAD_SNAPSHOT=true`. Files are replaced whole, so a
+/// crash mid-write leaves the previous snapshot intact.
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use utoipa::ToSchema;
+
+use crate::domain::models::StoredReading;
+use crate::domain::store::AppState;
+use crate::errors::AppError;
+
+const DEFAULT_INTERVAL_SECS: u64 = 60;
+
+/// Contents of a snapshot file
+#[derive(Debug, Serialize, Deserialize)]
+struct SnapshotFile {
+    taken_at: DateTime<Utc>,
+    readings: Vec<StoredReading>,
+}
+
+/// The most recent snapshot
+#[derive(Debug, Clone, Default, Serialize, ToSchema)]
+pub struct SnapshotMeta {
+    /// Unset until the first snapshot is written or loaded
+    pub last_snapshot_at: Option<DateTime<Utc>>,
+    pub reading_count: usize,
+}
+
+#[derive(Debug)]
+pub struct SnapshotManager {
+    path: PathBuf,
+    meta: Mutex<SnapshotMeta>,
+}
+
+impl SnapshotManager {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            meta: Mutex::new(SnapshotMeta::default()),
+        }
+    }
+
+    /// Snapshots to `SNAPSHOT_PATH`; `None` if it isn't set
+    pub fn from_env() -> Option<Self> {
+        std::env::var("SNAPSHOT_PATH")
+            .ok()
+            .filter(|path| !path.trim().is_empty())
+            .map(Self::new)
+    }
+
+    /// Snapshot interval from `SNAPSHOT_INTERVAL_SECS`
+    pub fn interval_from_env() -> Duration {
+        let secs = std::env::var("SNAPSHOT_INTERVAL_SECS")
+            .ok()
+            .and_then(|v| v.parse::<u64>().ok())
+            .filter(|v| *v > 0)
+            .unwrap_or(DEFAULT_INTERVAL_SECS);
+        Duration::from_secs(secs)
+    }
+
+    /// Whether `LOAD_SNAPSHOT=true` asks for the snapshot to be restored
+    pub fn load_from_env() -> bool {
+        std::env::var("LOAD_SNAPSHOT")
+            .map(|v| v.eq_ignore_ascii_case("true"))
+            .unwrap_or(false)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub async fn meta(&self) -> SnapshotMeta {
+        self.meta.lock().await.clone()
+    }
+
+    /// Write `readings` as the current snapshot
+    pub async fn save(&self, readings: Vec<StoredReading>) -> Result<SnapshotMeta, AppError> {
+        let file = SnapshotFile {
+            taken_at: Utc::now(),
+            readings,
+        };
+        let meta = SnapshotMeta {
+            last_snapshot_at: Some(file.taken_at),
+            reading_count: file.readings.len(),
+        };
+
+        let path = self.path.clone();
+        tokio::task::spawn_blocking(move || write_file(&path, &file))
+            .await
+            .map_err(|_| AppError::Internal)?
+            .inspect_err(|e| {
+                tracing::error!(error = %e, path = %self.path.display(), "Failed to write snapshot")
+            })
+            .map_err(|_| AppError::Internal)?;
+
+        *self.meta.lock().await = meta.clone();
+        Ok(meta)
+    }
+
+    /// Snapshot the readings held by `state`
+    pub async fn take(&self, state: &Mutex<AppState>) -> Result<SnapshotMeta, AppError> {
+        let readings = state.lock().await.snapshot();
+        self.save(readings).await
+    }
+
+    /// Readings of the snapshot file, or `None` if there is none yet
+    pub async fn load(&self) -> std::io::Result<Option<Vec<StoredReading>>> {
+        let path = self.path.clone();
+        let file = tokio::task::spawn_blocking(move || read_file(&path))
+            .await
+            .map_err(std::io::Error::other)??;
+
+        let Some(file) = file else {
+            return Ok(None);
+        };
+        *self.meta.lock().await = SnapshotMeta {
+            last_snapshot_at: Some(file.taken_at),
+            reading_count: file.readings.len(),
+        };
+        Ok(Some(file.readings))
+    }
+
+    /// Periodically snapshot the readings held by `state`
+    pub fn spawn(self: &Arc<Self>, state: Arc<Mutex<AppState>>, interval: Duration) {
+        let manager = self.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            // The first tick is immediate; there is nothing new to save yet
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                if let Ok(meta) = manager.take(&state).await {
+                    tracing::debug!(count = meta.reading_count, "Wrote snapshot");
+                }
+            }
+        });
+    }
+}
+
+/// Write `file` next to `path`, then move it over `path`
+fn write_file(path: &Path, file: &SnapshotFile) -> std::io::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+
+    let mut out = std::io::BufWriter::new(std::fs::File::create(&tmp)?);
+    ciborium::into_writer(file, &mut out).map_err(std::io::Error::other)?;
+    out.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+    std::fs::rename(&tmp, path)
+}
+
+fn read_file(path: &Path) -> std::io::Result<Option<SnapshotFile>> {
+    let file = match std::fs::File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    ciborium::from_reader(std::io::BufReader::new(file))
+        .map(Some)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::models::{SensorReading, SignalCode, TimestampSource};
+    use crate::fhir::ObservationStatus;
+
+    fn temp_path() -> PathBuf {
+        std::env::temp_dir().join(format!("soundsense-snapshot-{}.cbor", uuid::Uuid::new_v4()))
+    }
+
+    fn stored(value: f64) -> StoredReading {
+        let mut metadata = serde_json::Map::new();
+        metadata.insert("location".into(), "ward 3".into());
+        StoredReading::new(SensorReading {
+            patient_id: "p1".into(),
+            device_id: "d1".into(),
+            code: SignalCode::Sound,
+            value,
+            unit: "dB".into(),
+            ts: Utc::now(),
+            ts_source: TimestampSource::Server,
+            metadata,
+        })
+    }
+
+    #[tokio::test]
+    async fn test_save_and_load_round_trip() {
+        let path = temp_path();
+        let manager = SnapshotManager::new(&path);
+        assert!(manager.load().await.unwrap().is_none());
+        assert!(manager.meta().await.last_snapshot_at.is_none());
+
+        let mut amended = stored(71.5);
+        amended.status = ObservationStatus::Amended;
+        amended.note = Some("recalibrated".into());
+        let readings = vec![stored(60.0), amended];
+        let meta = manager.save(readings.clone()).await.unwrap();
+        assert_eq!(meta.reading_count, 2);
+
+        let reopened = SnapshotManager::new(&path);
+        let loaded = reopened.load().await.unwrap().unwrap();
+        assert_eq!(loaded.len(), 2);
+        for (loaded, saved) in loaded.iter().zip(&readings) {
+            assert_eq!(loaded.id, saved.id);
+            assert_eq!(loaded.reading.value, saved.reading.value);
+            assert_eq!(loaded.reading.ts, saved.reading.ts);
+            assert_eq!(loaded.reading.ts_source, saved.reading.ts_source);
+            assert_eq!(loaded.reading.metadata, saved.reading.metadata);
+            assert_eq!(loaded.status, saved.status);
+            assert_eq!(loaded.note, saved.note);
+            assert_eq!(loaded.category, saved.category);
+        }
+        assert_eq!(
+            reopened.meta().await.last_snapshot_at,
+            meta.last_snapshot_at
+        );
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_corrupt_file_is_an_error() {
+        let path = temp_path();
+        std::fs::write(&path, b"not cbor at all").unwrap();
+        assert!(SnapshotManager::new(&path).load().await.is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
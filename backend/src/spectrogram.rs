@@ -0,0 +1,181 @@
+/// Spectrogram Frames
+///
+/// Professional sound level meters report the frequency content of the sound
+/// as a series of FFT frames: one magnitude per frequency bin, every
+/// `frame_duration_ms`. Frames are stored as they arrive and returned in time
+/// order for frequency-domain analysis.
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+/// Most bins accepted in one frame (a 16k-point FFT)
+pub const MAX_SPECTROGRAM_BINS: usize = 8192;
+
+/// Default and largest number of frames returned by one query
+pub const DEFAULT_MAX_FRAMES: usize = 100;
+pub const MAX_FRAMES_LIMIT: usize = 1000;
+
+/// One FFT frame from a sound level meter
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct SpectrogramFrame {
+    pub patient_id: String,
+    pub device_id: String,
+    pub ts: DateTime<Utc>,
+    pub sample_rate_hz: u32,
+    pub frame_duration_ms: u32,
+    /// Centre frequency of each bin in Hz
+    pub frequency_bins: Vec<f32>,
+    /// Magnitude of each bin, in `frequency_bins` order
+    pub magnitudes: Vec<f32>,
+}
+
+impl SpectrogramFrame {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.patient_id.trim().is_empty() {
+            return Err("patient_id required".into());
+        }
+        if self.device_id.trim().is_empty() {
+            return Err("device_id required".into());
+        }
+        if self.sample_rate_hz == 0 {
+            return Err("sample_rate_hz must be positive".into());
+        }
+        if self.frame_duration_ms == 0 {
+            return Err("frame_duration_ms must be positive".into());
+        }
+        if self.frequency_bins.len() != self.magnitudes.len() {
+            return Err(format!(
+                "frequency_bins has {} entries but magnitudes has {}",
+                self.frequency_bins.len(),
+                self.magnitudes.len()
+            ));
+        }
+        if self.frequency_bins.is_empty() {
+            return Err("frame has no bins".into());
+        }
+        if self.frequency_bins.len() > MAX_SPECTROGRAM_BINS {
+            return Err(format!("frame has more than {} bins", MAX_SPECTROGRAM_BINS));
+        }
+        if let Some(i) = self
+            .frequency_bins
+            .iter()
+            .position(|f| !f.is_finite() || *f < 0.0)
+        {
+            return Err(format!(
+                "frequency_bins[{}] must be a non-negative frequency",
+                i
+            ));
+        }
+        if let Some(i) = self.magnitudes.iter().position(|m| !m.is_finite()) {
+            return Err(format!("magnitudes[{}] must be finite", i));
+        }
+        Ok(())
+    }
+
+    /// Frequency (Hz) of the bin with the highest magnitude; the lowest such
+    /// bin on a tie, 0 for a frame without bins
+    pub fn dominant_frequency(&self) -> f64 {
+        self.frequency_bins
+            .iter()
+            .zip(&self.magnitudes)
+            .fold(None, |best: Option<(f32, f32)>, (&freq, &mag)| match best {
+                Some((_, best_mag)) if best_mag >= mag => best,
+                _ => Some((freq, mag)),
+            })
+            .map_or(0.0, |(freq, _)| freq as f64)
+    }
+}
+
+/// `patient_id`'s frames from `start` to `end` (inclusive), oldest first,
+/// at most `max_frames`. Mirrors the query used with a database.
+pub fn frames_in_range<'a>(
+    frames: impl IntoIterator<Item = &'a SpectrogramFrame>,
+    patient_id: &str,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    max_frames: usize,
+) -> Vec<SpectrogramFrame> {
+    let mut frames: Vec<SpectrogramFrame> = frames
+        .into_iter()
+        .filter(|f| f.patient_id == patient_id && f.ts >= start && f.ts <= end)
+        .cloned()
+        .collect();
+    frames.sort_by_key(|f| f.ts);
+    frames.truncate(max_frames);
+    frames
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, TimeZone};
+
+    fn frame(frequency_bins: Vec<f32>, magnitudes: Vec<f32>) -> SpectrogramFrame {
+        SpectrogramFrame {
+            patient_id: "p1".into(),
+            device_id: "slm-1".into(),
+            ts: Utc.with_ymd_and_hms(2026, 3, 1, 12, 0, 0).unwrap(),
+            sample_rate_hz: 48_000,
+            frame_duration_ms: 125,
+            frequency_bins,
+            magnitudes,
+        }
+    }
+
+    #[test]
+    fn test_validation() {
+        assert!(frame(vec![0.0, 1000.0], vec![0.5, 0.9]).validate().is_ok());
+
+        let err = frame(vec![0.0, 1000.0], vec![0.5]).validate().unwrap_err();
+        assert!(err.contains("2 entries but magnitudes has 1"), "{err}");
+
+        let err = frame(vec![0.0, 1000.0], vec![0.5, f32::NAN])
+            .validate()
+            .unwrap_err();
+        assert_eq!(err, "magnitudes[1] must be finite");
+        assert!(frame(vec![0.0], vec![f32::INFINITY]).validate().is_err());
+
+        assert!(frame(vec![], vec![]).validate().is_err());
+        assert!(frame(vec![-1.0], vec![0.5]).validate().is_err());
+        let too_many = vec![0.0; MAX_SPECTROGRAM_BINS + 1];
+        assert!(frame(too_many.clone(), too_many).validate().is_err());
+
+        let mut f = frame(vec![0.0], vec![0.5]);
+        f.sample_rate_hz = 0;
+        assert!(f.validate().is_err());
+    }
+
+    #[test]
+    fn test_dominant_frequency() {
+        let f = frame(vec![250.0, 500.0, 1000.0, 2000.0], vec![0.1, 0.4, 0.9, 0.3]);
+        assert_eq!(f.dominant_frequency(), 1000.0);
+
+        // Ties go to the lowest bin
+        let f = frame(vec![250.0, 500.0, 1000.0], vec![0.7, 0.2, 0.7]);
+        assert_eq!(f.dominant_frequency(), 250.0);
+
+        assert_eq!(frame(vec![], vec![]).dominant_frequency(), 0.0);
+    }
+
+    #[test]
+    fn test_frames_in_range() {
+        let t0 = Utc.with_ymd_and_hms(2026, 3, 1, 12, 0, 0).unwrap();
+        let at = |secs: i64, patient_id: &str| {
+            let mut f = frame(vec![1000.0], vec![0.5]);
+            f.ts = t0 + Duration::seconds(secs);
+            f.patient_id = patient_id.into();
+            f
+        };
+        let frames = [
+            at(3, "p1"),
+            at(1, "p1"),
+            at(2, "p2"),
+            at(2, "p1"),
+            at(9, "p1"),
+        ];
+
+        let found = frames_in_range(&frames, "p1", t0, t0 + Duration::seconds(5), 2);
+        let secs: Vec<i64> = found.iter().map(|f| (f.ts - t0).num_seconds()).collect();
+        assert_eq!(secs, [1, 2]);
+    }
+}
@@ -0,0 +1,208 @@
+/// Streaming NDJSON Ingest
+///
+/// Backfills arrive as one `application/x-ndjson` body with a reading per
+/// line, read chunk by chunk instead of buffered whole. Lines are cut here;
+/// invalid ones are skipped and counted, valid ones inserted in batches.
+use serde::Serialize;
+use utoipa::ToSchema;
+
+/// Default longest line accepted (bytes)
+pub const DEFAULT_STREAM_MAX_LINE_BYTES: usize = 4 * 1024;
+
+/// Default most lines in one stream
+pub const DEFAULT_STREAM_MAX_LINES: usize = 1_000_000;
+
+/// Default readings per database insert
+pub const DEFAULT_STREAM_BATCH_ROWS: usize = 500;
+
+/// Default share of streamed readings sent to WebSocket clients
+pub const DEFAULT_STREAM_BROADCAST_EVERY: usize = 1000;
+
+/// Limits of `POST /api/ingest/stream`
+#[derive(Debug, Clone, Copy)]
+pub struct StreamIngestConfig {
+    pub max_line_bytes: usize,
+    pub max_lines: usize,
+    /// Capped at `db::MAX_INSERT_ROWS`
+    pub batch_rows: usize,
+    /// Only every Nth accepted reading is published to WebSocket clients
+    pub broadcast_every: usize,
+}
+
+impl Default for StreamIngestConfig {
+    fn default() -> Self {
+        Self {
+            max_line_bytes: DEFAULT_STREAM_MAX_LINE_BYTES,
+            max_lines: DEFAULT_STREAM_MAX_LINES,
+            batch_rows: DEFAULT_STREAM_BATCH_ROWS,
+            broadcast_every: DEFAULT_STREAM_BROADCAST_EVERY,
+        }
+    }
+}
+
+impl StreamIngestConfig {
+    /// Create from `STREAM_INGEST_MAX_LINE_BYTES`, `STREAM_INGEST_MAX_LINES`,
+    /// `STREAM_INGEST_BATCH_ROWS` and `STREAM_BROADCAST_EVERY`
+    pub fn from_env() -> Self {
+        let limit = |name: &str, default: usize| {
+            std::env::var(name)
+                .ok()
+                .and_then(|v| v.parse::<usize>().ok())
+                .filter(|v| *v > 0)
+                .unwrap_or(default)
+        };
+
+        Self {
+            max_line_bytes: limit(
+                "STREAM_INGEST_MAX_LINE_BYTES",
+                DEFAULT_STREAM_MAX_LINE_BYTES,
+            ),
+            max_lines: limit("STREAM_INGEST_MAX_LINES", DEFAULT_STREAM_MAX_LINES),
+            batch_rows: limit("STREAM_INGEST_BATCH_ROWS", DEFAULT_STREAM_BATCH_ROWS)
+                .min(crate::db::MAX_INSERT_ROWS),
+            broadcast_every: limit("STREAM_BROADCAST_EVERY", DEFAULT_STREAM_BROADCAST_EVERY),
+        }
+    }
+}
+
+/// A line cut from the stream, without its line break
+#[derive(Debug, PartialEq)]
+pub enum Line {
+    Complete(Vec<u8>),
+    /// Longer than the limit; its content is dropped
+    TooLong,
+}
+
+/// Cuts a byte stream into lines, holding at most one line in memory
+#[derive(Debug)]
+pub struct LineSplitter {
+    buf: Vec<u8>,
+    max_line_bytes: usize,
+    /// The current line already exceeded the limit and is being skipped
+    overflow: bool,
+}
+
+impl LineSplitter {
+    pub fn new(max_line_bytes: usize) -> Self {
+        Self {
+            buf: Vec::new(),
+            max_line_bytes,
+            overflow: false,
+        }
+    }
+
+    /// Lines completed by `chunk`
+    pub fn push(&mut self, mut chunk: &[u8]) -> Vec<Line> {
+        let mut lines = Vec::new();
+        while !chunk.is_empty() {
+            let (part, rest, complete) = match chunk.iter().position(|b| *b == b'\n') {
+                Some(pos) => (&chunk[..pos], &chunk[pos + 1..], true),
+                None => (chunk, &[][..], false),
+            };
+
+            if !self.overflow {
+                if self.buf.len() + part.len() > self.max_line_bytes {
+                    self.overflow = true;
+                    self.buf.clear();
+                } else {
+                    self.buf.extend_from_slice(part);
+                }
+            }
+            if complete {
+                lines.push(self.take());
+            }
+            chunk = rest;
+        }
+        lines
+    }
+
+    /// The last line, if the stream didn't end with a line break
+    pub fn finish(mut self) -> Option<Line> {
+        if self.overflow || !self.buf.is_empty() {
+            Some(self.take())
+        } else {
+            None
+        }
+    }
+
+    fn take(&mut self) -> Line {
+        if std::mem::take(&mut self.overflow) {
+            Line::TooLong
+        } else {
+            Line::Complete(std::mem::take(&mut self.buf))
+        }
+    }
+}
+
+/// Outcome of a stream ingest
+#[derive(Debug, Default, Serialize, ToSchema)]
+pub struct StreamIngestSummary {
+    /// Readings stored
+    pub accepted: usize,
+    /// Readings of patients without consent, held back in quarantine
+    pub quarantined: usize,
+    /// Lines skipped as invalid
+    pub rejected: usize,
+    /// 1-based number of the first skipped line
+    pub first_error_line: Option<usize>,
+    /// Why that line was skipped
+    pub first_error: Option<String>,
+}
+
+impl StreamIngestSummary {
+    pub fn reject(&mut self, line: usize, error: impl Into<String>) {
+        self.rejected += 1;
+        if self.first_error_line.is_none() {
+            self.first_error_line = Some(line);
+            self.first_error = Some(error.into());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn complete(line: &str) -> Line {
+        Line::Complete(line.as_bytes().to_vec())
+    }
+
+    #[test]
+    fn test_lines_split_across_chunks() {
+        let mut splitter = LineSplitter::new(16);
+        assert_eq!(splitter.push(b"ab"), vec![]);
+        assert_eq!(
+            splitter.push(b"c\nde\n\nf"),
+            vec![complete("abc"), complete("de"), complete("")]
+        );
+        assert_eq!(splitter.push(b"g"), vec![]);
+        assert_eq!(splitter.finish(), Some(complete("fg")));
+
+        let mut splitter = LineSplitter::new(16);
+        assert_eq!(splitter.push(b"a\n"), vec![complete("a")]);
+        assert_eq!(splitter.finish(), None);
+    }
+
+    #[test]
+    fn test_long_lines_are_dropped() {
+        let mut splitter = LineSplitter::new(4);
+        assert_eq!(splitter.push(b"abcd\nabc"), vec![complete("abcd")]);
+        assert_eq!(splitter.push(b"de"), vec![]);
+        assert_eq!(
+            splitter.push(b"fgh\nok\n"),
+            vec![Line::TooLong, complete("ok")]
+        );
+        assert_eq!(splitter.push(b"toolong"), vec![]);
+        assert_eq!(splitter.finish(), Some(Line::TooLong));
+    }
+
+    #[test]
+    fn test_first_error_is_kept() {
+        let mut summary = StreamIngestSummary::default();
+        summary.reject(3, "bad");
+        summary.reject(7, "worse");
+        assert_eq!(summary.rejected, 2);
+        assert_eq!(summary.first_error_line, Some(3));
+        assert_eq!(summary.first_error.as_deref(), Some("bad"));
+    }
+}
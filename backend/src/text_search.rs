@@ -0,0 +1,228 @@
+/// Observation Text Search
+///
+/// Finds readings whose note or metadata mention a term. The database ranks
+/// matches with Postgres full-text search (`ts_rank` over an English
+/// `tsvector` of note and metadata); without a database the in-memory
+/// readings are matched word by word, without stemming. Tags are the
+/// strings in a reading's `tags` metadata array.
+use std::collections::BTreeSet;
+
+use crate::domain::models::{ReadingFilter, StoredReading};
+use crate::errors::AppError;
+
+/// Most observations a text search returns
+pub const MAX_TEXT_SEARCH_RESULTS: usize = 200;
+
+/// Most tags a suggestion lists
+pub const MAX_TAG_SUGGESTIONS: usize = 10;
+
+/// Longest search text accepted (characters)
+pub const MAX_SEARCH_TEXT_CHARS: usize = 256;
+
+/// Metadata key holding a reading's tags
+pub const TAGS_METADATA_KEY: &str = "tags";
+
+/// Words to search for in `raw`, space separated. Anything but letters and
+/// digits separates words, so tsquery operators (`&`, `|`, `!`, `:*`, ...)
+/// never reach the query parser.
+pub fn search_terms(raw: &str) -> Result<String, AppError> {
+    if raw.chars().count() > MAX_SEARCH_TEXT_CHARS {
+        return Err(AppError::BadRequest(format!(
+            "_text must be at most {} characters",
+            MAX_SEARCH_TEXT_CHARS
+        )));
+    }
+    let terms = words(raw).collect::<Vec<_>>().join(" ");
+    if terms.is_empty() {
+        return Err(AppError::BadRequest(
+            "_text must contain a letter or digit".into(),
+        ));
+    }
+    Ok(terms)
+}
+
+/// `LIKE` pattern matching values that start with `prefix`
+pub fn like_prefix(prefix: &str) -> String {
+    let mut pattern = String::with_capacity(prefix.len() + 1);
+    for c in prefix.chars() {
+        if matches!(c, '%' | '_' | '\\') {
+            pattern.push('\\');
+        }
+        pattern.push(c);
+    }
+    pattern.push('%');
+    pattern
+}
+
+/// Lowercased words of `text`
+fn words(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(str::to_lowercase)
+}
+
+/// Text a reading is searched by: its note and its metadata as JSON
+fn document(r: &StoredReading) -> String {
+    format!(
+        "{} {}",
+        r.note.as_deref().unwrap_or_default(),
+        serde_json::Value::Object(r.reading.metadata.clone())
+    )
+}
+
+/// Readings passing `filter` that contain every word of `terms`, best first
+/// (ties newest first), with their score: the share of the reading's words
+/// that are search words. `readings` are oldest first.
+pub fn search_readings<'a>(
+    readings: impl DoubleEndedIterator<Item = &'a StoredReading>,
+    terms: &str,
+    limit: usize,
+    filter: &ReadingFilter<'_>,
+) -> Vec<(StoredReading, f64)> {
+    let terms: BTreeSet<String> = words(terms).collect();
+    let mut matches: Vec<(StoredReading, f64)> = readings
+        .rev()
+        .filter(|r| filter.matches(r))
+        .filter_map(|r| {
+            let document: Vec<String> = words(&document(r)).collect();
+            if !terms.iter().all(|t| document.contains(t)) {
+                return None;
+            }
+            let hits = document.iter().filter(|w| terms.contains(*w)).count();
+            Some((r.clone(), hits as f64 / document.len() as f64))
+        })
+        .collect();
+    // Stable, so equal scores stay newest first
+    matches.sort_by(|a, b| b.1.total_cmp(&a.1));
+    matches.truncate(limit);
+    matches
+}
+
+/// Distinct tags of readings passing `filter` that start with `prefix`
+/// (ignoring case), in order, at most `limit`
+pub fn suggest_tags<'a>(
+    readings: impl IntoIterator<Item = &'a StoredReading>,
+    prefix: &str,
+    limit: usize,
+    filter: &ReadingFilter<'_>,
+) -> Vec<String> {
+    let prefix = prefix.to_lowercase();
+    let tags: BTreeSet<&str> = readings
+        .into_iter()
+        .filter(|r| filter.matches(r))
+        .filter_map(|r| r.reading.metadata.get(TAGS_METADATA_KEY)?.as_array())
+        .flatten()
+        .filter_map(|tag| tag.as_str())
+        .filter(|tag| tag.to_lowercase().starts_with(&prefix))
+        .collect();
+    tags.into_iter().take(limit).map(String::from).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::models::{SensorReading, SignalCode, TimestampSource};
+    use chrono::Utc;
+
+    fn stored(patient_id: &str, note: Option<&str>, metadata: serde_json::Value) -> StoredReading {
+        let mut stored = StoredReading::new(SensorReading {
+            patient_id: patient_id.into(),
+            device_id: "wearable-1".into(),
+            code: SignalCode::Sound,
+            value: 70.0,
+            unit: "dB".into(),
+            ts: Utc::now(),
+            ts_source: TimestampSource::Device,
+            metadata: metadata.as_object().unwrap().clone(),
+        });
+        stored.note = note.map(String::from);
+        stored
+    }
+
+    const ALL: ReadingFilter<'static> = ReadingFilter {
+        code: None,
+        category: None,
+        patients: None,
+        include_deleted: false,
+        units: None,
+    };
+
+    #[test]
+    fn test_search_terms_drop_operators() {
+        assert_eq!(search_terms("hearing aid").unwrap(), "hearing aid");
+        assert_eq!(search_terms("hear:* & !aid | (x)").unwrap(), "hear aid x");
+        assert_eq!(search_terms("Ward-3's").unwrap(), "ward 3 s");
+        assert!(search_terms(" &|! ").is_err());
+        assert!(search_terms(&"a".repeat(MAX_SEARCH_TEXT_CHARS + 1)).is_err());
+    }
+
+    #[test]
+    fn test_like_prefix_escapes_wildcards() {
+        assert_eq!(like_prefix("hear"), "hear%");
+        assert_eq!(like_prefix("50%_a\\b"), "50\\%\\_a\\\\b%");
+    }
+
+    #[test]
+    fn test_search_matches_every_word_best_first() {
+        let readings = [
+            stored("p1", Some("Wears a hearing aid"), serde_json::json!({})),
+            stored(
+                "p2",
+                None,
+                serde_json::json!({ "tags": ["hearing aid"], "site": "clinic" }),
+            ),
+            stored("p1", Some("hearing test"), serde_json::json!({})),
+        ];
+
+        let found = search_readings(readings.iter(), "Hearing AID", 10, &ALL);
+        let ids: Vec<_> = found.iter().map(|(r, _)| r.id).collect();
+        assert_eq!(ids.len(), 2);
+        assert!(ids.contains(&readings[0].id) && ids.contains(&readings[1].id));
+        assert!(found[0].1 >= found[1].1);
+
+        let patients = vec!["p1".to_string()];
+        let filter = ReadingFilter {
+            patients: Some(&patients),
+            ..ALL
+        };
+        let found = search_readings(readings.iter(), "hearing", 10, &filter);
+        assert_eq!(found.len(), 2);
+        assert!(found.iter().all(|(r, _)| r.reading.patient_id == "p1"));
+        assert_eq!(
+            search_readings(readings.iter(), "hearing", 1, &ALL).len(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_suggest_tags() {
+        let readings = [
+            stored(
+                "p1",
+                None,
+                serde_json::json!({ "tags": ["hearing aid", "Headset"] }),
+            ),
+            stored(
+                "p2",
+                None,
+                serde_json::json!({ "tags": ["hearing aid", 3, "night"] }),
+            ),
+            stored("p2", None, serde_json::json!({ "tags": "hearing loss" })),
+        ];
+        assert_eq!(
+            suggest_tags(&readings, "HE", 10, &ALL),
+            vec!["Headset", "hearing aid"]
+        );
+        assert_eq!(suggest_tags(&readings, "he", 1, &ALL), vec!["Headset"]);
+
+        let patients = vec!["p2".to_string()];
+        let filter = ReadingFilter {
+            patients: Some(&patients),
+            ..ALL
+        };
+        assert_eq!(
+            suggest_tags(&readings, "", 10, &filter),
+            vec!["hearing aid", "night"]
+        );
+    }
+}
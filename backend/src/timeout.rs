@@ -0,0 +1,214 @@
+/// Request Timeout Module
+///
+/// Bounds how long an `/api` request may take to produce its response. A
+/// handler still running at the deadline is dropped, releasing the store
+/// lock and any pool connection it held, and the client gets 504 with the
+/// request id to quote. Queries themselves are cut off by the database's
+/// statement timeout (`DB_READ_TIMEOUT_MS`, `DB_WRITE_TIMEOUT_MS`), so an
+/// abandoned handler leaves nothing running behind it. Only the time to the
+/// response head counts; streamed bodies are not cut off.
+///
+/// Writes (any method but GET, HEAD and OPTIONS) are never dropped: an
+/// ingest aborted between its insert and its response would be stored and
+/// then sent again by the client, so they run to completion, bounded by the
+/// statement timeout. Routes given a deadline of their own keep it.
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::Method;
+use actix_web::middleware::Next;
+use actix_web::{web, Error};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use std::time::Duration;
+use uuid::Uuid;
+
+use crate::calibration::MAX_DURATION_SECS;
+use crate::errors::AppError;
+use crate::metrics::Exposition;
+
+/// Default deadline of an `/api` request
+pub const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Header a request id is taken from and returned in
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Longest client-supplied request id kept; longer ones are replaced
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+/// Route label of requests that matched no route
+const UNMATCHED_ROUTE: &str = "unmatched";
+
+/// Deadlines of the `/api` routes, and how often each ran out
+#[derive(Debug)]
+pub struct RequestTimeout {
+    deadline: Duration,
+    /// Deadlines differing from `deadline` by route pattern; `None` for none
+    routes: HashMap<String, Option<Duration>>,
+    /// Timeouts so far by route pattern
+    timeouts: Mutex<BTreeMap<String, u64>>,
+}
+
+impl Default for RequestTimeout {
+    fn default() -> Self {
+        Self::new(DEFAULT_REQUEST_TIMEOUT)
+    }
+}
+
+impl RequestTimeout {
+    /// `deadline` for reads of every route; NDJSON backfills and compliance
+    /// reports, which may legitimately take longer, are left unbounded (the
+    /// latter to the database's report timeout), and calibration
+    /// checks, which wait out their measurement window first, get longer
+    pub fn new(deadline: Duration) -> Self {
+        let calibration = Duration::from_secs(MAX_DURATION_SECS.into()) + deadline;
+        Self {
+            deadline,
+            routes: HashMap::new(),
+            timeouts: Mutex::new(BTreeMap::new()),
+        }
+        .with_route("/api/ingest/stream", None)
+        // Bounded by DB_REPORT_TIMEOUT_MS instead
+        .with_route("/api/admin/compliance-report/{year}/{month}", None)
+        .with_route("/api/calibrate/device/{id}/verify", Some(calibration))
+    }
+
+    /// Read `REQUEST_TIMEOUT_SECS` (default 10)
+    pub fn from_env() -> Self {
+        let secs = std::env::var("REQUEST_TIMEOUT_SECS")
+            .ok()
+            .and_then(|v| v.parse::<u64>().ok())
+            .filter(|v| *v > 0);
+        Self::new(secs.map_or(DEFAULT_REQUEST_TIMEOUT, Duration::from_secs))
+    }
+
+    /// Give the route with `pattern` its own deadline, or none at all
+    pub fn with_route(mut self, pattern: &str, deadline: Option<Duration>) -> Self {
+        self.routes.insert(pattern.to_string(), deadline);
+        self
+    }
+
+    /// Deadline of a `method` request to the route with `pattern`; writes
+    /// have none unless the route was given one
+    pub fn deadline_for(&self, method: &Method, pattern: &str) -> Option<Duration> {
+        if let Some(deadline) = self.routes.get(pattern) {
+            return *deadline;
+        }
+        let read = [Method::GET, Method::HEAD, Method::OPTIONS].contains(method);
+        read.then_some(self.deadline)
+    }
+
+    fn record(&self, pattern: &str) {
+        let mut timeouts = self.timeouts.lock().unwrap_or_else(|e| e.into_inner());
+        *timeouts.entry(pattern.to_string()).or_default() += 1;
+    }
+
+    /// Render the timeout counter, one sample per route that timed out
+    pub fn render_metrics(&self, exp: &mut Exposition) {
+        let timeouts = self.timeouts.lock().unwrap_or_else(|e| e.into_inner());
+        let labels: Vec<_> = timeouts
+            .iter()
+            .map(|(route, count)| (format!("route=\"{}\"", route), *count))
+            .collect();
+        let samples: Vec<_> = labels.iter().map(|(l, c)| (l.as_str(), *c)).collect();
+        exp.family(
+            "soundsense_request_timeouts_total",
+            "counter",
+            "Requests answered 504 because their handler ran past the deadline",
+            &samples,
+        );
+    }
+}
+
+/// The request's `X-Request-Id` if it is usable, otherwise a fresh one
+fn request_id(req: &ServiceRequest) -> String {
+    req.headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .map(str::trim)
+        .filter(|id| {
+            !id.is_empty()
+                && id.len() <= MAX_REQUEST_ID_LEN
+                && id.bytes().all(|b| b.is_ascii_graphic())
+        })
+        .map_or_else(|| Uuid::new_v4().to_string(), str::to_string)
+}
+
+/// Middleware for the `/api` scope. Without a registered `RequestTimeout`
+/// requests run as long as their handler takes. The 504 is returned as an
+/// error, since the request cannot be kept for a response while routing.
+pub async fn enforce_request_timeout<B: MessageBody + 'static>(
+    req: ServiceRequest,
+    next: Next<B>,
+) -> Result<ServiceResponse<B>, Error> {
+    let Some(timeouts) = req.app_data::<web::Data<RequestTimeout>>().cloned() else {
+        return next.call(req).await;
+    };
+    let pattern = req
+        .match_pattern()
+        .unwrap_or_else(|| UNMATCHED_ROUTE.to_string());
+    let Some(deadline) = timeouts.deadline_for(req.method(), &pattern) else {
+        return next.call(req).await;
+    };
+
+    let request_id = request_id(&req);
+    match tokio::time::timeout(deadline, next.call(req)).await {
+        Ok(resp) => resp,
+        Err(_) => {
+            timeouts.record(&pattern);
+            tracing::warn!(
+                route = %pattern,
+                %request_id,
+                deadline_ms = deadline.as_millis() as u64,
+                "Request timed out, handler aborted"
+            );
+            Err(AppError::RequestTimeout(request_id).into())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_routes_can_override_the_deadline() {
+        let timeouts = RequestTimeout::new(Duration::from_secs(3))
+            .with_route("/api/slow", Some(Duration::from_secs(60)));
+
+        assert_eq!(
+            timeouts.deadline_for(&Method::GET, "/api/patients"),
+            Some(Duration::from_secs(3))
+        );
+        assert_eq!(
+            timeouts.deadline_for(&Method::GET, "/api/slow"),
+            Some(Duration::from_secs(60))
+        );
+        assert_eq!(
+            timeouts.deadline_for(&Method::POST, "/api/ingest/stream"),
+            None
+        );
+
+        // Writes run to completion unless their route says otherwise
+        assert_eq!(timeouts.deadline_for(&Method::POST, "/api/ingest"), None);
+        assert_eq!(
+            timeouts.deadline_for(&Method::DELETE, "/api/patients"),
+            None
+        );
+        assert_eq!(
+            timeouts.deadline_for(&Method::POST, "/api/calibrate/device/{id}/verify"),
+            Some(Duration::from_secs(MAX_DURATION_SECS.into()) + Duration::from_secs(3))
+        );
+        assert_eq!(
+            timeouts.deadline_for(&Method::GET, "/api/admin/compliance-report/{year}/{month}"),
+            None
+        );
+
+        timeouts.record("/api/patients");
+        timeouts.record("/api/patients");
+        let mut exp = Exposition::new();
+        timeouts.render_metrics(&mut exp);
+        assert!(exp
+            .finish()
+            .contains("soundsense_request_timeouts_total{route=\"/api/patients\"} 2\n"));
+    }
+}
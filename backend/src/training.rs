@@ -0,0 +1,316 @@
+/// ML Training Jobs Module
+///
+/// Records training runs triggered through the API and follows their progress
+/// by polling the ML service in the background. Only one run may be in
+/// progress at a time; the database enforces this with a partial unique index.
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::RwLock;
+use uuid::Uuid;
+
+use crate::db::Database;
+use crate::errors::AppError;
+use crate::ml_client::{MlClient, TrainingStatusResponse};
+
+/// How often the ML service is asked for progress
+const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Consecutive failed status checks before a job is given up on
+const MAX_POLL_FAILURES: u32 = 12;
+
+/// Jobs listed by `GET /api/ml/train`
+const LIST_LIMIT: usize = 20;
+
+/// Jobs kept when running without a database
+const MAX_IN_MEMORY_JOBS: usize = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TrainingStatus {
+    InProgress,
+    Completed,
+    Failed,
+}
+
+impl TrainingStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TrainingStatus::InProgress => "in_progress",
+            TrainingStatus::Completed => "completed",
+            TrainingStatus::Failed => "failed",
+        }
+    }
+
+    /// Whether the job has reached a final state
+    pub fn is_finished(&self) -> bool {
+        !matches!(self, TrainingStatus::InProgress)
+    }
+}
+
+impl TryFrom<String> for TrainingStatus {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        match value.as_str() {
+            "in_progress" => Ok(TrainingStatus::InProgress),
+            "completed" => Ok(TrainingStatus::Completed),
+            "failed" => Ok(TrainingStatus::Failed),
+            other => Err(format!("unknown training status '{}'", other)),
+        }
+    }
+}
+
+/// A training run and its last known progress
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct TrainingJob {
+    pub job_id: Uuid,
+    pub started_at: DateTime<Utc>,
+    #[sqlx(try_from = "String")]
+    pub status: TrainingStatus,
+    pub progress_percent: f32,
+    pub message: String,
+}
+
+/// Training job store backed by the database, or memory when none is configured
+#[derive(Debug)]
+pub struct TrainingJobStore {
+    db: Option<Database>,
+    memory: RwLock<Vec<TrainingJob>>,
+    poll_interval: Duration,
+}
+
+impl TrainingJobStore {
+    pub fn new(db: Option<Database>) -> Self {
+        Self {
+            db,
+            memory: RwLock::new(Vec::new()),
+            poll_interval: DEFAULT_POLL_INTERVAL,
+        }
+    }
+
+    /// Override how often progress is polled
+    pub fn with_poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    /// Record a new in-progress job; 409 if one is already running
+    pub async fn start(&self) -> Result<TrainingJob, AppError> {
+        let job = TrainingJob {
+            job_id: Uuid::new_v4(),
+            started_at: Utc::now(),
+            status: TrainingStatus::InProgress,
+            progress_percent: 0.0,
+            message: "Training requested".into(),
+        };
+
+        let inserted = if let Some(db) = &self.db {
+            db.insert_training_job(&job).await?
+        } else {
+            let mut memory = self.memory.write().await;
+            if memory
+                .iter()
+                .any(|j| j.status == TrainingStatus::InProgress)
+            {
+                false
+            } else {
+                if memory.len() >= MAX_IN_MEMORY_JOBS {
+                    memory.remove(0);
+                }
+                memory.push(job.clone());
+                true
+            }
+        };
+
+        if !inserted {
+            return Err(AppError::Conflict(
+                "a training job is already in progress".into(),
+            ));
+        }
+        Ok(job)
+    }
+
+    /// Find a job by id
+    pub async fn get(&self, job_id: Uuid) -> Result<Option<TrainingJob>, AppError> {
+        if let Some(db) = &self.db {
+            return db.get_training_job(job_id).await;
+        }
+        Ok(self
+            .memory
+            .read()
+            .await
+            .iter()
+            .find(|j| j.job_id == job_id)
+            .cloned())
+    }
+
+    /// The most recent jobs, newest first
+    pub async fn list(&self) -> Result<Vec<TrainingJob>, AppError> {
+        if let Some(db) = &self.db {
+            return db.list_training_jobs(LIST_LIMIT as i64).await;
+        }
+        Ok(self
+            .memory
+            .read()
+            .await
+            .iter()
+            .rev()
+            .take(LIST_LIMIT)
+            .cloned()
+            .collect())
+    }
+
+    async fn save(&self, job: &TrainingJob) -> Result<(), AppError> {
+        if let Some(db) = &self.db {
+            return db.update_training_job(job).await;
+        }
+        if let Some(stored) = self
+            .memory
+            .write()
+            .await
+            .iter_mut()
+            .find(|j| j.job_id == job.job_id)
+        {
+            *stored = job.clone();
+        }
+        Ok(())
+    }
+
+    /// Mark a job failed
+    pub async fn fail(&self, job_id: Uuid, message: &str) -> Result<(), AppError> {
+        let mut job = self
+            .get(job_id)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("training job {}", job_id)))?;
+        job.status = TrainingStatus::Failed;
+        job.message = message.to_string();
+        self.save(&job).await
+    }
+
+    /// Apply a progress report from the ML service. Unknown statuses leave the
+    /// job in progress.
+    pub async fn apply_status(
+        &self,
+        job_id: Uuid,
+        report: TrainingStatusResponse,
+    ) -> Result<TrainingJob, AppError> {
+        let mut job = self
+            .get(job_id)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("training job {}", job_id)))?;
+
+        job.status = TrainingStatus::try_from(report.status).unwrap_or(TrainingStatus::InProgress);
+        job.progress_percent = report.progress_percent.clamp(0.0, 100.0);
+        job.message = report.message;
+        if job.status == TrainingStatus::Completed {
+            job.progress_percent = 100.0;
+        }
+
+        self.save(&job).await?;
+        Ok(job)
+    }
+
+    /// Fail jobs left in progress by a previous run of the backend, so they
+    /// don't block new training requests forever
+    pub async fn fail_interrupted(&self) -> Result<u64, AppError> {
+        const MESSAGE: &str = "backend restarted while training was in progress";
+
+        if let Some(db) = &self.db {
+            return db.fail_in_progress_training_jobs(MESSAGE).await;
+        }
+
+        let mut failed = 0;
+        for job in self.memory.write().await.iter_mut() {
+            if job.status == TrainingStatus::InProgress {
+                job.status = TrainingStatus::Failed;
+                job.message = MESSAGE.into();
+                failed += 1;
+            }
+        }
+        Ok(failed)
+    }
+
+    /// Poll the ML service for a job's progress until it finishes, or until
+    /// the service has been unreachable for `MAX_POLL_FAILURES` checks
+    pub fn spawn_poller(self: &Arc<Self>, client: Arc<MlClient>, job_id: Uuid) {
+        let store = self.clone();
+        tokio::spawn(async move {
+            let mut failures = 0;
+            loop {
+                tokio::time::sleep(store.poll_interval).await;
+
+                let report = match client.training_status(job_id).await {
+                    Ok(report) => report,
+                    Err(e) => {
+                        failures += 1;
+                        tracing::warn!(%job_id, failures, "Training status check failed: {}", e);
+                        if failures >= MAX_POLL_FAILURES {
+                            let message = format!("lost contact with ML service: {}", e);
+                            if let Err(e) = store.fail(job_id, &message).await {
+                                tracing::error!(%job_id, error = ?e, "Failed to mark training job failed");
+                            }
+                            break;
+                        }
+                        continue;
+                    }
+                };
+                failures = 0;
+
+                match store.apply_status(job_id, report).await {
+                    Ok(job) if job.status.is_finished() => {
+                        tracing::info!(%job_id, status = job.status.as_str(), "Training job finished");
+                        break;
+                    }
+                    Ok(_) => {}
+                    Err(e) => tracing::warn!(%job_id, error = ?e, "Failed to update training job"),
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(status: &str, progress_percent: f32) -> TrainingStatusResponse {
+        TrainingStatusResponse {
+            status: status.into(),
+            progress_percent,
+            message: String::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_only_one_job_in_progress() {
+        let store = TrainingJobStore::new(None);
+        let job = store.start().await.unwrap();
+
+        assert!(matches!(store.start().await, Err(AppError::Conflict(_))));
+
+        store
+            .apply_status(job.job_id, report("completed", 80.0))
+            .await
+            .unwrap();
+        let next = store.start().await.unwrap();
+
+        let jobs = store.list().await.unwrap();
+        assert_eq!(jobs.len(), 2);
+        assert_eq!(jobs[0].job_id, next.job_id);
+        assert_eq!(jobs[1].status, TrainingStatus::Completed);
+        assert_eq!(jobs[1].progress_percent, 100.0);
+    }
+
+    #[tokio::test]
+    async fn test_interrupted_jobs_are_failed() {
+        let store = TrainingJobStore::new(None);
+        let job = store.start().await.unwrap();
+
+        assert_eq!(store.fail_interrupted().await.unwrap(), 1);
+        let job = store.get(job.job_id).await.unwrap().unwrap();
+        assert_eq!(job.status, TrainingStatus::Failed);
+        assert!(store.start().await.is_ok());
+    }
+}
@@ -0,0 +1,366 @@
+/// Unit Conversion
+///
+/// Readings are stored in the unit their device sent (`raw` ADC counts,
+/// `dB`, `dBA`), so consumers asking for one unit get values converted on
+/// the way out. Raw readings are converted by their device's calibration
+/// record: the raw output its latest passing verification measured while
+/// the reference tone played (see `calibration`). Raw output is taken to be
+/// proportional to sound pressure, so a reading's level is the reference
+/// level plus 20·log10 of its ratio to that output. Other units are
+/// converted by a fixed table. What neither covers, such as A-weighted
+/// levels or raw readings of devices never calibrated, is left unconverted
+/// and marked, or left out when the caller is strict.
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use crate::calibration::CalibrationVerification;
+use crate::domain::models::{normalize_unit, SensorReading, StoredReading, UNIT_ALIASES};
+use crate::fhir::{FhirObservation, FhirQuantity};
+
+/// Code system of UCUM unit codes
+pub const UCUM_SYSTEM: &str = "http://unitsofmeasure.org";
+
+/// Unit of readings in a device's own output scale
+pub const RAW_UNIT: &str = "raw";
+
+/// Sound pressure of 0 dB SPL, in pascals
+pub const REFERENCE_PRESSURE_PA: f64 = 20e-6;
+
+/// Marker of an observation whose value could not be converted
+pub const CONVERSION_NONE: &str = "none";
+
+/// A unit query responses can be converted to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TargetUnit {
+    Decibel,
+}
+
+impl TargetUnit {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TargetUnit::Decibel => "dB",
+        }
+    }
+
+    /// The unit's UCUM code
+    pub fn ucum_code(&self) -> &'static str {
+        match self {
+            TargetUnit::Decibel => "dB",
+        }
+    }
+}
+
+impl FromStr for TargetUnit {
+    type Err = String;
+
+    /// Spellings accepted on ingest are accepted here too
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match normalize_unit(s).as_str() {
+            "dB" => Ok(TargetUnit::Decibel),
+            _ => Err(format!("unit must be dB, got '{}'", s)),
+        }
+    }
+}
+
+/// Lowercase aliases `normalize_unit` turns into `unit`
+pub fn unit_aliases(unit: &str) -> Vec<&'static str> {
+    UNIT_ALIASES
+        .iter()
+        .filter(|(_, canonical)| *canonical == unit)
+        .map(|(alias, _)| *alias)
+        .collect()
+}
+
+/// A value's conversion; `None` for values it isn't defined for
+type Conversion = fn(f64) -> Option<f64>;
+
+/// Units other than `raw` convertible to dB, with their conversion
+pub const DECIBEL_CONVERSIONS: [(&str, Conversion); 2] = [("dB", Some), ("Pa", pascal_to_db)];
+
+/// A device's raw output at a known level
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RawCalibration {
+    pub reference_db: f64,
+    /// Average raw output while the reference played
+    pub reference_raw: f64,
+}
+
+impl RawCalibration {
+    /// The reference of a verification that passed and measured a positive
+    /// raw output
+    pub fn from_verification(v: &CalibrationVerification) -> Option<Self> {
+        let reference_raw = v
+            .measured_avg_raw
+            .filter(|raw| *raw > 0.0 && raw.is_finite())?;
+        v.passed.then_some(Self {
+            reference_db: v.reference_db,
+            reference_raw,
+        })
+    }
+
+    /// Each device's reference from its latest verification that has one,
+    /// of verifications by device, newest first. Mirrors the database query.
+    pub fn latest<'a>(
+        verifications: impl IntoIterator<Item = (&'a String, &'a Vec<CalibrationVerification>)>,
+    ) -> HashMap<String, Self> {
+        verifications
+            .into_iter()
+            .filter_map(|(device_id, checks)| {
+                let calibration = checks.iter().find_map(Self::from_verification)?;
+                Some((device_id.clone(), calibration))
+            })
+            .collect()
+    }
+}
+
+/// Level of `raw` output by `calibration`; `None` unless it is positive
+pub fn raw_to_db(raw: f64, calibration: &RawCalibration) -> Option<f64> {
+    (raw > 0.0 && raw.is_finite())
+        .then(|| calibration.reference_db + 20.0 * (raw / calibration.reference_raw).log10())
+}
+
+/// Raw output at a level of `db` by `calibration`; the inverse of
+/// `raw_to_db`
+pub fn db_to_raw(db: f64, calibration: &RawCalibration) -> f64 {
+    calibration.reference_raw * 10f64.powf((db - calibration.reference_db) / 20.0)
+}
+
+/// Sound pressure level of `pa` pascals; `None` unless it is positive
+pub fn pascal_to_db(pa: f64) -> Option<f64> {
+    (pa > 0.0 && pa.is_finite()).then(|| 20.0 * (pa / REFERENCE_PRESSURE_PA).log10())
+}
+
+/// Sound pressure in pascals of a level of `db` dB SPL
+pub fn db_to_pascal(db: f64) -> f64 {
+    REFERENCE_PRESSURE_PA * 10f64.powf(db / 20.0)
+}
+
+/// `value` in `unit` converted to dB, with `calibration` for raw values;
+/// `None` if it can't be
+pub fn to_db(value: f64, unit: &str, calibration: Option<&RawCalibration>) -> Option<f64> {
+    let unit = normalize_unit(unit);
+    let db = if unit == RAW_UNIT {
+        calibration.and_then(|c| raw_to_db(value, c))
+    } else {
+        DECIBEL_CONVERSIONS
+            .iter()
+            .find(|(from, _)| *from == unit)
+            .and_then(|(_, convert)| convert(value))
+    };
+    db.filter(|db| db.is_finite())
+}
+
+/// Conversion of the observations of one response
+#[derive(Debug, Clone)]
+pub struct UnitConversion {
+    pub target: TargetUnit,
+    /// Leave out observations that can't be converted, rather than marking
+    /// them
+    pub strict: bool,
+    /// Raw output references by device
+    pub calibrations: HashMap<String, RawCalibration>,
+}
+
+impl UnitConversion {
+    /// `value` in `unit` sent by `device_id`, in the target unit; `None` if
+    /// it can't be converted
+    pub fn convert(&self, value: f64, unit: &str, device_id: &str) -> Option<f64> {
+        match self.target {
+            TargetUnit::Decibel => to_db(value, unit, self.calibrations.get(device_id)),
+        }
+    }
+
+    /// Value of `reading` in the target unit; `None` if it can't be
+    /// converted
+    pub fn value(&self, reading: &SensorReading) -> Option<f64> {
+        self.convert(reading.value, &reading.unit, &reading.device_id)
+    }
+
+    /// Whether `reading` is in the response: always, unless the conversion
+    /// is strict and it can't be converted
+    pub fn keeps(&self, reading: &SensorReading) -> bool {
+        !self.strict || self.value(reading).is_some()
+    }
+
+    /// Observation of `stored` in the target unit. `None` if it can't be
+    /// converted and the conversion is strict.
+    pub fn observation(&self, stored: StoredReading) -> Option<FhirObservation> {
+        match self.value(&stored.reading) {
+            Some(value) => {
+                let mut obs = FhirObservation::from_stored(stored);
+                obs.value_quantity =
+                    FhirQuantity::ucum(value, self.target.as_str(), self.target.ucum_code());
+                Some(obs)
+            }
+            None if self.strict => None,
+            None => {
+                let mut obs = FhirObservation::from_stored(stored);
+                obs.conversion = Some(CONVERSION_NONE);
+                Some(obs)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::models::{SensorReading, SignalCode};
+    use chrono::Utc;
+    use rand::Rng;
+
+    fn calibration(reference_db: f64, reference_raw: f64) -> RawCalibration {
+        RawCalibration {
+            reference_db,
+            reference_raw,
+        }
+    }
+
+    fn stored(device_id: &str, value: f64, unit: &str) -> StoredReading {
+        StoredReading::new(SensorReading {
+            patient_id: "p1".into(),
+            device_id: device_id.into(),
+            code: SignalCode::Sound,
+            value,
+            unit: unit.into(),
+            ts: Utc::now(),
+            ts_source: Default::default(),
+            metadata: Default::default(),
+        })
+    }
+
+    fn verification(passed: bool, measured_avg_raw: Option<f64>) -> CalibrationVerification {
+        CalibrationVerification {
+            device_id: "d1".into(),
+            verified_at: Utc::now(),
+            reference_db: 94.0,
+            measured_avg_db: None,
+            deviation_db: None,
+            measured_avg_raw,
+            passed,
+        }
+    }
+
+    #[test]
+    fn test_raw_db_round_trip() {
+        let mut rng = rand::thread_rng();
+        for _ in 0..10_000 {
+            let c = calibration(rng.gen_range(0.0..200.0), rng.gen_range(1e-3..1e6));
+            let raw = rng.gen_range(1e-3..1e6);
+            let db = raw_to_db(raw, &c).unwrap();
+            let back = db_to_raw(db, &c);
+            assert!(
+                (back - raw).abs() <= 1e-9 * raw.max(1.0),
+                "{:?}: {} -> {} -> {}",
+                c,
+                raw,
+                db,
+                back
+            );
+        }
+    }
+
+    #[test]
+    fn test_raw_levels_follow_the_reference() {
+        let c = calibration(94.0, 500.0);
+        assert_eq!(raw_to_db(500.0, &c), Some(94.0));
+        // Twice the output is 6 dB louder, a tenth of it 20 dB quieter
+        assert!((raw_to_db(1000.0, &c).unwrap() - 100.0206).abs() < 1e-4);
+        assert!((raw_to_db(50.0, &c).unwrap() - 74.0).abs() < 1e-9);
+        assert_eq!(raw_to_db(0.0, &c), None);
+        assert_eq!(raw_to_db(-1.0, &c), None);
+    }
+
+    #[test]
+    fn test_pascal_db_round_trip() {
+        let mut rng = rand::thread_rng();
+        for _ in 0..10_000 {
+            let db: f64 = rng.gen_range(-20.0..200.0);
+            let back = pascal_to_db(db_to_pascal(db)).unwrap();
+            assert!((back - db).abs() < 1e-9, "{} -> {}", db, back);
+        }
+        assert!((pascal_to_db(1.0).unwrap() - 93.979).abs() < 1e-3);
+        assert_eq!(pascal_to_db(0.0), None);
+        assert_eq!(pascal_to_db(-1.0), None);
+    }
+
+    #[test]
+    fn test_to_db_by_unit() {
+        let c = calibration(94.0, 500.0);
+        assert_eq!(to_db(500.0, "raw", Some(&c)), Some(94.0));
+        assert_eq!(to_db(500.0, "au", Some(&c)), Some(94.0));
+        assert_eq!(to_db(500.0, "raw", None), None);
+        assert_eq!(to_db(72.0, "db", None), Some(72.0));
+        // A-weighting can't be undone
+        assert_eq!(to_db(72.0, "dBA", Some(&c)), None);
+        assert_eq!(to_db(1.0, "mV", None), None);
+
+        assert_eq!("dB".parse(), Ok(TargetUnit::Decibel));
+        assert_eq!("DB".parse(), Ok(TargetUnit::Decibel));
+        assert!("dBA".parse::<TargetUnit>().is_err());
+        assert_eq!(unit_aliases(RAW_UNIT), ["au", "a.u.", "counts"]);
+    }
+
+    #[test]
+    fn test_latest_usable_calibration_applies() {
+        assert_eq!(
+            RawCalibration::from_verification(&verification(true, Some(500.0))),
+            Some(calibration(94.0, 500.0))
+        );
+        assert_eq!(
+            RawCalibration::from_verification(&verification(false, Some(500.0))),
+            None
+        );
+        assert_eq!(
+            RawCalibration::from_verification(&verification(true, None)),
+            None
+        );
+
+        let by_device = HashMap::from([
+            (
+                "d1".to_string(),
+                vec![
+                    verification(false, Some(900.0)),
+                    verification(true, None),
+                    verification(true, Some(500.0)),
+                    verification(true, Some(400.0)),
+                ],
+            ),
+            ("d2".to_string(), vec![verification(true, None)]),
+        ]);
+        let latest = RawCalibration::latest(&by_device);
+        assert_eq!(latest.len(), 1);
+        assert_eq!(latest["d1"], calibration(94.0, 500.0));
+    }
+
+    #[test]
+    fn test_observations_are_converted_or_marked() {
+        let conversion = UnitConversion {
+            target: TargetUnit::Decibel,
+            strict: false,
+            calibrations: HashMap::from([("d1".to_string(), calibration(94.0, 500.0))]),
+        };
+
+        let obs = conversion.observation(stored("d1", 500.0, "raw")).unwrap();
+        assert_eq!(obs.value_quantity.value, 94.0);
+        assert_eq!(obs.value_quantity.unit, "dB");
+        assert_eq!(obs.value_quantity.system.as_deref(), Some(UCUM_SYSTEM));
+        assert_eq!(obs.value_quantity.code.as_deref(), Some("dB"));
+        assert_eq!(obs.conversion, None);
+
+        let obs = conversion.observation(stored("d2", 200.0, "raw")).unwrap();
+        assert_eq!(obs.value_quantity.value, 200.0);
+        assert_eq!(obs.value_quantity.unit, "raw");
+        assert_eq!(obs.value_quantity.code, None);
+        assert_eq!(obs.conversion, Some(CONVERSION_NONE));
+        assert!(conversion.keeps(&stored("d2", 200.0, "raw").reading));
+
+        let strict = UnitConversion {
+            strict: true,
+            ..conversion
+        };
+        assert!(strict.observation(stored("d2", 200.0, "raw")).is_none());
+        assert!(!strict.keeps(&stored("d2", 200.0, "raw").reading));
+        assert!(strict.keeps(&stored("d2", 70.0, "dB").reading));
+    }
+}
@@ -0,0 +1,435 @@
+/// User Account Module
+///
+/// Stores dashboard users with bcrypt-hashed passwords, either in PostgreSQL
+/// or (without a database) in memory. Password material is never logged; audit
+/// entries only record which account changed and how.
+use chrono::{DateTime, Utc};
+use rand::distributions::{Alphanumeric, DistString};
+use serde::Serialize;
+use std::collections::HashMap;
+use tokio::sync::{OnceCell, RwLock};
+use uuid::Uuid;
+
+use crate::audit::AuditLogEntry;
+use crate::db::Database;
+use crate::errors::AppError;
+use crate::security::{DEFAULT_AUTH_PASSWORD, DEFAULT_AUTH_USERNAME};
+
+/// Minimum accepted password length
+pub const MIN_PASSWORD_LENGTH: usize = 10;
+
+/// bcrypt only uses the first 72 bytes of its input
+const MAX_PASSWORD_BYTES: usize = 72;
+
+/// A user account
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct User {
+    pub id: Uuid,
+    pub username: String,
+    #[serde(skip_serializing)]
+    pub password_hash: String,
+    pub role: String,
+    pub must_change_password: bool,
+    pub created_at: DateTime<Utc>,
+    pub password_changed_at: Option<DateTime<Utc>>,
+}
+
+/// Check a new password against the minimal strength policy
+pub fn validate_password_strength(password: &str, username: &str) -> Result<(), String> {
+    if password.chars().count() < MIN_PASSWORD_LENGTH {
+        return Err(format!(
+            "password must be at least {} characters",
+            MIN_PASSWORD_LENGTH
+        ));
+    }
+    if password.len() > MAX_PASSWORD_BYTES {
+        return Err(format!(
+            "password must be at most {} bytes",
+            MAX_PASSWORD_BYTES
+        ));
+    }
+    if !password.chars().any(|c| c.is_alphabetic()) || !password.chars().any(|c| c.is_numeric()) {
+        return Err("password must contain both letters and digits".into());
+    }
+    if !username.is_empty() && password.to_lowercase().contains(&username.to_lowercase()) {
+        return Err("password must not contain the username".into());
+    }
+    Ok(())
+}
+
+/// Generate a random temporary password that satisfies the strength policy
+pub fn generate_temporary_password() -> String {
+    loop {
+        let candidate = Alphanumeric.sample_string(&mut rand::thread_rng(), 16);
+        if validate_password_strength(&candidate, "").is_ok() {
+            return candidate;
+        }
+    }
+}
+
+/// User account store backed by the database, or memory when none is configured
+#[derive(Debug)]
+pub struct UserStore {
+    db: Option<Database>,
+    memory: RwLock<HashMap<Uuid, User>>,
+    /// By account: a user's id, or the `sub` of an OIDC subject
+    permitted_patients: RwLock<HashMap<String, Vec<String>>>,
+    bcrypt_cost: u32,
+    bootstrapped: OnceCell<()>,
+    /// Hash checked for unknown usernames, so a login takes as long whether
+    /// or not the account exists
+    dummy_hash: OnceCell<String>,
+}
+
+impl UserStore {
+    /// Create a store; the bcrypt cost comes from `BCRYPT_COST` (default 12)
+    pub fn new(db: Option<Database>) -> Self {
+        let bcrypt_cost = std::env::var("BCRYPT_COST")
+            .ok()
+            .and_then(|v| v.parse::<u32>().ok())
+            .unwrap_or(bcrypt::DEFAULT_COST)
+            .clamp(4, 31);
+
+        Self {
+            db,
+            memory: RwLock::new(HashMap::new()),
+            permitted_patients: RwLock::new(HashMap::new()),
+            bcrypt_cost,
+            bootstrapped: OnceCell::new(),
+            dummy_hash: OnceCell::new(),
+        }
+    }
+
+    /// Hash passwords at `cost` (clamped to bcrypt's 4..=31) instead
+    pub fn with_bcrypt_cost(mut self, cost: u32) -> Self {
+        self.bcrypt_cost = cost.clamp(4, 31);
+        self
+    }
+
+    /// Create the initial admin from `AUTH_USERNAME`/`AUTH_PASSWORD` if that
+    /// account does not exist yet. Runs once, on first use of the store.
+    async fn ensure_bootstrap(&self) -> Result<(), AppError> {
+        self.bootstrapped
+            .get_or_try_init(|| async {
+                let username = std::env::var("AUTH_USERNAME")
+                    .unwrap_or_else(|_| DEFAULT_AUTH_USERNAME.to_string());
+                let password = std::env::var("AUTH_PASSWORD")
+                    .unwrap_or_else(|_| DEFAULT_AUTH_PASSWORD.to_string());
+
+                if self.lookup_username(&username).await?.is_none() {
+                    tracing::info!("Creating bootstrap admin account: {}", username);
+                    let user = self
+                        .build_user(&username, &password, "admin", false)
+                        .await?;
+                    self.insert(user).await?;
+                }
+                Ok(())
+            })
+            .await
+            .map(|_| ())
+    }
+
+    async fn lookup_username(&self, username: &str) -> Result<Option<User>, AppError> {
+        if let Some(db) = &self.db {
+            return db.find_user_by_username(username).await;
+        }
+
+        Ok(self
+            .memory
+            .read()
+            .await
+            .values()
+            .find(|u| u.username == username)
+            .cloned())
+    }
+
+    async fn insert(&self, user: User) -> Result<bool, AppError> {
+        if let Some(db) = &self.db {
+            return db.insert_user(&user).await;
+        }
+
+        let mut memory = self.memory.write().await;
+        if memory.values().any(|u| u.username == user.username) {
+            return Ok(false);
+        }
+        memory.insert(user.id, user);
+        Ok(true)
+    }
+
+    async fn build_user(
+        &self,
+        username: &str,
+        password: &str,
+        role: &str,
+        must_change_password: bool,
+    ) -> Result<User, AppError> {
+        Ok(User {
+            id: Uuid::new_v4(),
+            username: username.to_string(),
+            password_hash: self.hash_password(password).await?,
+            role: role.to_string(),
+            must_change_password,
+            created_at: Utc::now(),
+            password_changed_at: None,
+        })
+    }
+
+    /// Find a user by username
+    pub async fn find_by_username(&self, username: &str) -> Result<Option<User>, AppError> {
+        self.ensure_bootstrap().await?;
+        self.lookup_username(username).await
+    }
+
+    /// Find a user by id
+    pub async fn get(&self, id: Uuid) -> Result<Option<User>, AppError> {
+        self.ensure_bootstrap().await?;
+        if let Some(db) = &self.db {
+            return db.get_user(id).await;
+        }
+        Ok(self.memory.read().await.get(&id).cloned())
+    }
+
+    /// List all users ordered by username
+    pub async fn list(&self) -> Result<Vec<User>, AppError> {
+        self.ensure_bootstrap().await?;
+        if let Some(db) = &self.db {
+            return db.list_users().await;
+        }
+
+        let mut users: Vec<User> = self.memory.read().await.values().cloned().collect();
+        users.sort_by(|a, b| a.username.cmp(&b.username));
+        Ok(users)
+    }
+
+    /// Create a new account
+    pub async fn create(
+        &self,
+        username: &str,
+        password: &str,
+        role: &str,
+        must_change_password: bool,
+    ) -> Result<User, AppError> {
+        self.ensure_bootstrap().await?;
+        let user = self
+            .build_user(username, password, role, must_change_password)
+            .await?;
+
+        if !self.insert(user.clone()).await? {
+            return Err(AppError::Conflict(format!(
+                "username '{}' already exists",
+                username
+            )));
+        }
+        Ok(user)
+    }
+
+    /// Hash and store a new password, setting the rotation flag
+    pub async fn set_password(
+        &self,
+        id: Uuid,
+        password: &str,
+        must_change_password: bool,
+    ) -> Result<(), AppError> {
+        let hash = self.hash_password(password).await?;
+
+        let updated = if let Some(db) = &self.db {
+            db.update_user_password(id, &hash, must_change_password)
+                .await?
+        } else {
+            match self.memory.write().await.get_mut(&id) {
+                Some(user) => {
+                    user.password_hash = hash;
+                    user.must_change_password = must_change_password;
+                    user.password_changed_at = Some(Utc::now());
+                    true
+                }
+                None => false,
+            }
+        };
+
+        if !updated {
+            return Err(AppError::NotFound(format!("user {}", id)));
+        }
+        Ok(())
+    }
+
+    /// Patients a `user` or `viewer` account may read, ordered by patient
+    /// id. `account` is a local user's id, or the `sub` of an OIDC subject.
+    pub async fn permitted_patients(&self, account: &str) -> Result<Vec<String>, AppError> {
+        if let Some(db) = &self.db {
+            return db.user_patients(account).await;
+        }
+        Ok(self
+            .permitted_patients
+            .read()
+            .await
+            .get(account)
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    /// Allow an account to read a patient's data; returns `false` if it
+    /// already could
+    pub async fn permit_patient(&self, account: &str, patient_id: &str) -> Result<bool, AppError> {
+        if let Some(db) = &self.db {
+            return db.add_user_patient(account, patient_id).await;
+        }
+        let mut permitted = self.permitted_patients.write().await;
+        let patients = permitted.entry(account.to_string()).or_default();
+        if patients.iter().any(|p| p == patient_id) {
+            return Ok(false);
+        }
+        patients.push(patient_id.to_string());
+        patients.sort();
+        Ok(true)
+    }
+
+    /// Hash a password with the current bcrypt cost (off the async runtime)
+    pub async fn hash_password(&self, password: &str) -> Result<String, AppError> {
+        let password = password.to_string();
+        let cost = self.bcrypt_cost;
+
+        tokio::task::spawn_blocking(move || bcrypt::hash(password, cost))
+            .await
+            .map_err(|_| AppError::Internal)?
+            .map_err(|e| {
+                tracing::error!("Failed to hash password: {}", e);
+                AppError::Internal
+            })
+    }
+
+    /// Check a password against the user's stored hash
+    pub async fn verify_password(&self, user: &User, password: &str) -> bool {
+        let password = password.to_string();
+        let hash = user.password_hash.clone();
+
+        tokio::task::spawn_blocking(move || bcrypt::verify(password, &hash).unwrap_or(false))
+            .await
+            .unwrap_or(false)
+    }
+
+    /// The user with `username` if `password` is theirs. Unknown usernames
+    /// are checked against a dummy hash of the same cost, so response times
+    /// do not tell which accounts exist.
+    pub async fn authenticate(
+        &self,
+        username: &str,
+        password: &str,
+    ) -> Result<Option<User>, AppError> {
+        match self.find_by_username(username).await? {
+            Some(user) => Ok(self.verify_password(&user, password).await.then_some(user)),
+            None => {
+                let hash = self
+                    .dummy_hash
+                    .get_or_try_init(|| async {
+                        self.hash_password(&generate_temporary_password()).await
+                    })
+                    .await?
+                    .clone();
+                let password = password.to_string();
+                let _ = tokio::task::spawn_blocking(move || bcrypt::verify(password, &hash)).await;
+                Ok(None)
+            }
+        }
+    }
+
+    /// Whether a hash was produced with different parameters than the current ones
+    pub fn needs_rehash(&self, user: &User) -> bool {
+        user.password_hash
+            .parse::<bcrypt::HashParts>()
+            .map(|parts| parts.get_cost() != self.bcrypt_cost)
+            .unwrap_or(true)
+    }
+
+    /// Record a password event in the audit log (or the tracing log without a database)
+    pub async fn audit(&self, entry: AuditLogEntry) {
+        if let Some(db) = &self.db {
+            if let Err(e) = entry.log(db.pool()).await {
+                tracing::warn!(error = ?e, "Failed to log audit event");
+            }
+        } else {
+            tracing::info!(
+                user_id = ?entry.user_id,
+                action = %entry.action,
+                resource_type = %entry.resource_type,
+                resource_id = ?entry.resource_id,
+                metadata = ?entry.metadata,
+                "Audit event"
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_password_strength_policy() {
+        assert!(validate_password_strength("short1", "alice").is_err());
+        assert!(validate_password_strength("onlyletterslong", "alice").is_err());
+        assert!(validate_password_strength("1234567890123", "alice").is_err());
+        assert!(validate_password_strength("Alice-2026-pass", "alice").is_err());
+        assert!(validate_password_strength(&"a1".repeat(40), "alice").is_err());
+        assert!(validate_password_strength("Sturdy-horse-42", "alice").is_ok());
+    }
+
+    #[test]
+    fn test_temporary_password_meets_policy() {
+        for _ in 0..20 {
+            let password = generate_temporary_password();
+            assert!(validate_password_strength(&password, "").is_ok());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_in_memory_store_password_lifecycle() {
+        let store = UserStore {
+            bcrypt_cost: 4,
+            ..UserStore::new(None)
+        };
+
+        let user = store
+            .create("alice", "Initial-pass-1", "user", false)
+            .await
+            .unwrap();
+        assert!(store.verify_password(&user, "Initial-pass-1").await);
+        assert!(!store.verify_password(&user, "wrong-pass-1").await);
+        assert!(!store.needs_rehash(&user));
+
+        assert!(matches!(
+            store.create("alice", "Another-pass-2", "user", false).await,
+            Err(AppError::Conflict(_))
+        ));
+
+        store
+            .set_password(user.id, "Rotated-pass-2", true)
+            .await
+            .unwrap();
+        let updated = store.get(user.id).await.unwrap().unwrap();
+        assert!(updated.must_change_password);
+        assert!(updated.password_changed_at.is_some());
+        assert!(store.verify_password(&updated, "Rotated-pass-2").await);
+
+        assert!(store
+            .authenticate("alice", "Rotated-pass-2")
+            .await
+            .unwrap()
+            .is_some());
+        assert!(store
+            .authenticate("alice", "Initial-pass-1")
+            .await
+            .unwrap()
+            .is_none());
+        // Unknown users go through the dummy hash and are refused the same way
+        assert!(store
+            .authenticate("mallory", "Rotated-pass-2")
+            .await
+            .unwrap()
+            .is_none());
+        assert!(store.dummy_hash.get().is_some());
+
+        // The bootstrap admin exists alongside
+        assert!(store.find_by_username("admin").await.unwrap().is_some());
+    }
+}
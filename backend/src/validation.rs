@@ -0,0 +1,578 @@
+/// Per-Deployment Reading Validation
+///
+/// Every reading passes the built-in checks of `SensorReading::validate`.
+/// On top of those, a deployment can enable rules of its own in the
+/// `validation_rules` table, e.g. a range check on calibrated dB SPL at a
+/// clinical site that a research site taking raw counts leaves off. A rule
+/// of severity `error` refuses the reading; one of severity `warning` lets it
+/// through and reports the finding in the ingest response.
+///
+/// Rules are loaded at startup; edit the table and restart to change them.
+/// `ALLOWED_SIGNAL_CODES` narrows the signals a deployment ingests, ahead of
+/// the table's rules.
+use chrono::{Duration, Utc};
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashSet;
+use std::sync::{Arc, RwLock};
+
+use crate::db::Database;
+use crate::domain::models::{normalize_unit, SensorReading, SignalCode};
+use crate::errors::AppError;
+
+/// Default tolerance of `future_ts` for device clocks running ahead
+pub const DEFAULT_MAX_SKEW_SECS: i64 = 60;
+
+/// Why a rule refused a reading
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValidationErrorCode {
+    NonFiniteValue,
+    OutOfRange,
+    UnitNotAllowed,
+    FutureTimestamp,
+    UnknownPatient,
+    SignalCodeNotAllowed,
+}
+
+impl ValidationErrorCode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ValidationErrorCode::NonFiniteValue => "non_finite_value",
+            ValidationErrorCode::OutOfRange => "out_of_range",
+            ValidationErrorCode::UnitNotAllowed => "unit_not_allowed",
+            ValidationErrorCode::FutureTimestamp => "future_timestamp",
+            ValidationErrorCode::UnknownPatient => "unknown_patient",
+            ValidationErrorCode::SignalCodeNotAllowed => "signal_code_not_allowed",
+        }
+    }
+}
+
+/// A check a deployment can enable
+pub trait ValidationRule: Send + Sync {
+    /// The rule and its settings, as reported with a finding
+    fn describe(&self) -> String;
+
+    fn check(&self, reading: &SensorReading) -> Result<(), ValidationErrorCode>;
+}
+
+/// The value must be a finite number
+pub struct FiniteValueRule;
+
+impl ValidationRule for FiniteValueRule {
+    fn describe(&self) -> String {
+        "finite_value".into()
+    }
+
+    fn check(&self, reading: &SensorReading) -> Result<(), ValidationErrorCode> {
+        if reading.value.is_finite() {
+            Ok(())
+        } else {
+            Err(ValidationErrorCode::NonFiniteValue)
+        }
+    }
+}
+
+/// The value must lie within `min..=max`; either bound may be left open
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RangeRule {
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+}
+
+impl ValidationRule for RangeRule {
+    fn describe(&self) -> String {
+        let bound = |b: Option<f64>| b.map_or("*".to_string(), |b| b.to_string());
+        format!("range [{}, {}]", bound(self.min), bound(self.max))
+    }
+
+    fn check(&self, reading: &SensorReading) -> Result<(), ValidationErrorCode> {
+        let v = reading.value;
+        if self.min.is_some_and(|min| v < min) || self.max.is_some_and(|max| v > max) {
+            return Err(ValidationErrorCode::OutOfRange);
+        }
+        Ok(())
+    }
+}
+
+/// The unit must be one of `units`, compared after normalization
+#[derive(Debug)]
+pub struct UnitWhitelistRule {
+    pub units: Vec<String>,
+}
+
+impl ValidationRule for UnitWhitelistRule {
+    fn describe(&self) -> String {
+        format!("unit_whitelist [{}]", self.units.join(", "))
+    }
+
+    fn check(&self, reading: &SensorReading) -> Result<(), ValidationErrorCode> {
+        let unit = normalize_unit(&reading.unit);
+        if self.units.contains(&unit) {
+            Ok(())
+        } else {
+            Err(ValidationErrorCode::UnitNotAllowed)
+        }
+    }
+}
+
+/// The reading must not be timestamped more than `max_skew_secs` ahead of
+/// the server clock. A skew reaching past the latest representable time
+/// lets every timestamp through.
+#[derive(Debug)]
+pub struct FutureTsRule {
+    pub max_skew_secs: i64,
+}
+
+impl ValidationRule for FutureTsRule {
+    fn describe(&self) -> String {
+        format!("future_ts (max skew {}s)", self.max_skew_secs)
+    }
+
+    fn check(&self, reading: &SensorReading) -> Result<(), ValidationErrorCode> {
+        let latest = Duration::try_seconds(self.max_skew_secs)
+            .and_then(|skew| Utc::now().checked_add_signed(skew));
+        match latest {
+            Some(latest) if reading.ts > latest => Err(ValidationErrorCode::FutureTimestamp),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The patient must be in the registry
+pub struct PatientExistsRule {
+    patients: PatientRegistry,
+}
+
+impl ValidationRule for PatientExistsRule {
+    fn describe(&self) -> String {
+        "patient_exists".into()
+    }
+
+    fn check(&self, reading: &SensorReading) -> Result<(), ValidationErrorCode> {
+        if self.patients.contains(&reading.patient_id) {
+            Ok(())
+        } else {
+            Err(ValidationErrorCode::UnknownPatient)
+        }
+    }
+}
+
+/// The signal must be one this deployment ingests
+#[derive(Debug)]
+pub struct SignalCodeRule {
+    pub codes: Vec<SignalCode>,
+}
+
+impl ValidationRule for SignalCodeRule {
+    fn describe(&self) -> String {
+        let codes: Vec<_> = self.codes.iter().map(|c| c.as_str()).collect();
+        format!("signal_codes [{}]", codes.join(", "))
+    }
+
+    fn check(&self, reading: &SensorReading) -> Result<(), ValidationErrorCode> {
+        if self.codes.contains(&reading.code) {
+            Ok(())
+        } else {
+            Err(ValidationErrorCode::SignalCodeNotAllowed)
+        }
+    }
+}
+
+/// Signal codes of a comma-separated list such as `sound,heart_rate`; every
+/// known code for an empty one
+pub fn parse_signal_codes(list: &str) -> Result<Vec<SignalCode>, String> {
+    let mut codes = Vec::new();
+    for name in list.split(',').map(str::trim).filter(|n| !n.is_empty()) {
+        let code = name.parse::<SignalCode>()?;
+        if !codes.contains(&code) {
+            codes.push(code);
+        }
+    }
+    if codes.is_empty() {
+        codes = SignalCode::ALL.to_vec();
+    }
+    Ok(codes)
+}
+
+/// Signal codes allowed by `ALLOWED_SIGNAL_CODES`, every known code when
+/// unset. An unknown code is an error rather than ignored, so a typo can't
+/// silently refuse a whole signal.
+pub fn allowed_signal_codes_from_env() -> Result<Vec<SignalCode>, String> {
+    let list = std::env::var("ALLOWED_SIGNAL_CODES").unwrap_or_default();
+    parse_signal_codes(&list).map_err(|e| format!("ALLOWED_SIGNAL_CODES: {}", e))
+}
+
+/// Ids of registered patients, loaded with the rules and kept up to date as
+/// patients are registered through this backend
+#[derive(Debug, Clone, Default)]
+pub struct PatientRegistry(Arc<RwLock<HashSet<String>>>);
+
+impl PatientRegistry {
+    pub fn new(patient_ids: impl IntoIterator<Item = String>) -> Self {
+        Self(Arc::new(RwLock::new(patient_ids.into_iter().collect())))
+    }
+
+    pub fn insert(&self, patient_id: &str) {
+        self.0
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(patient_id.to_string());
+    }
+
+    pub fn contains(&self, patient_id: &str) -> bool {
+        self.0
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .contains(patient_id)
+    }
+}
+
+/// What happens to a reading that fails a rule
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    /// The reading is refused
+    Error,
+    /// The reading is stored and the finding reported
+    Warning,
+}
+
+impl TryFrom<&str> for Severity {
+    type Error = String;
+
+    fn try_from(value: &str) -> Result<Self, String> {
+        match value {
+            "error" => Ok(Severity::Error),
+            "warning" => Ok(Severity::Warning),
+            other => Err(format!("unknown severity '{}'", other)),
+        }
+    }
+}
+
+/// An active row of the `validation_rules` table
+#[derive(Debug, Clone)]
+pub struct RuleConfig {
+    pub rule_name: String,
+    pub config: Value,
+    pub severity: Severity,
+}
+
+/// Findings of a pipeline run, as messages naming the rule and its settings
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ValidationResult {
+    pub errors: Vec<String>,
+    pub warnings: Vec<String>,
+}
+
+impl ValidationResult {
+    /// 400 with every error if there were any; the warnings otherwise
+    pub fn into_warnings(self) -> Result<Vec<String>, AppError> {
+        if self.errors.is_empty() {
+            Ok(self.warnings)
+        } else {
+            Err(AppError::BadRequest(self.errors.join("; ")))
+        }
+    }
+}
+
+/// The rules a deployment enabled, run in table order
+#[derive(Clone, Default)]
+pub struct ValidationPipeline {
+    rules: Vec<(Severity, Arc<dyn ValidationRule>)>,
+    patients: PatientRegistry,
+}
+
+impl std::fmt::Debug for ValidationPipeline {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list()
+            .entries(
+                self.rules
+                    .iter()
+                    .map(|(severity, rule)| (severity, rule.describe())),
+            )
+            .finish()
+    }
+}
+
+impl ValidationPipeline {
+    /// Build the rules of `configs`; `patients` backs `patient_exists`
+    pub fn from_configs(configs: &[RuleConfig], patients: PatientRegistry) -> Result<Self, String> {
+        let rules = configs
+            .iter()
+            .map(|c| {
+                build_rule(&c.rule_name, &c.config, &patients)
+                    .map(|rule| (c.severity, rule))
+                    .map_err(|e| format!("validation rule '{}': {}", c.rule_name, e))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self { rules, patients })
+    }
+
+    /// The active rules in the database, with the patient registry they need
+    pub async fn load(db: &Database) -> Result<Self, String> {
+        let configs = db
+            .list_validation_rules()
+            .await
+            .map_err(|e| format!("failed to load validation rules: {}", e))?;
+        let patients = if configs.iter().any(|c| c.rule_name == "patient_exists") {
+            let ids = db
+                .list_patient_ids()
+                .await
+                .map_err(|e| format!("failed to load patient ids: {}", e))?;
+            PatientRegistry::new(ids)
+        } else {
+            PatientRegistry::default()
+        };
+        Self::from_configs(&configs, patients)
+    }
+
+    /// Refuse readings of a signal not in `codes`, before any other rule.
+    /// Adds nothing when every known code is allowed.
+    pub fn with_allowed_signal_codes(mut self, codes: &[SignalCode]) -> Self {
+        if SignalCode::ALL.iter().all(|c| codes.contains(c)) {
+            return self;
+        }
+        let rule = SignalCodeRule {
+            codes: codes.to_vec(),
+        };
+        self.rules.insert(0, (Severity::Error, Arc::new(rule)));
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.rules.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Note a newly registered patient for `patient_exists`
+    pub fn register_patient(&self, patient_id: &str) {
+        self.patients.insert(patient_id);
+    }
+
+    pub fn run(&self, reading: &SensorReading) -> ValidationResult {
+        let mut result = ValidationResult::default();
+        for (severity, rule) in &self.rules {
+            if let Err(code) = rule.check(reading) {
+                let message = format!("{} ({})", code.as_str(), rule.describe());
+                match severity {
+                    Severity::Error => result.errors.push(message),
+                    Severity::Warning => result.warnings.push(message),
+                }
+            }
+        }
+        result
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct UnitWhitelistConfig {
+    units: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FutureTsConfig {
+    max_skew_secs: Option<i64>,
+}
+
+/// A rule from its name and JSON settings (`null` for none)
+fn build_rule(
+    name: &str,
+    config: &Value,
+    patients: &PatientRegistry,
+) -> Result<Arc<dyn ValidationRule>, String> {
+    let config = match config {
+        Value::Null => Value::Object(Default::default()),
+        other => other.clone(),
+    };
+    let parse_err = |e: serde_json::Error| format!("invalid config: {}", e);
+
+    Ok(match name {
+        "finite_value" => Arc::new(FiniteValueRule),
+        "range" => {
+            let rule: RangeRule = serde_json::from_value(config).map_err(parse_err)?;
+            if rule.min.is_none() && rule.max.is_none() {
+                return Err("needs a min, a max or both".into());
+            }
+            if let (Some(min), Some(max)) = (rule.min, rule.max) {
+                if min > max {
+                    return Err("min is above max".into());
+                }
+            }
+            Arc::new(rule)
+        }
+        "unit_whitelist" => {
+            let c: UnitWhitelistConfig = serde_json::from_value(config).map_err(parse_err)?;
+            if c.units.is_empty() {
+                return Err("units must not be empty".into());
+            }
+            Arc::new(UnitWhitelistRule {
+                units: c.units.iter().map(|u| normalize_unit(u)).collect(),
+            })
+        }
+        "future_ts" => {
+            let c: FutureTsConfig = serde_json::from_value(config).map_err(parse_err)?;
+            let max_skew_secs = c.max_skew_secs.unwrap_or(DEFAULT_MAX_SKEW_SECS);
+            if max_skew_secs < 0 {
+                return Err("max_skew_secs must not be negative".into());
+            }
+            Arc::new(FutureTsRule { max_skew_secs })
+        }
+        "patient_exists" => Arc::new(PatientExistsRule {
+            patients: patients.clone(),
+        }),
+        other => return Err(format!("unknown rule '{}'", other)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::models::{SignalCode, TimestampSource};
+    use chrono::DateTime;
+    use serde_json::json;
+
+    fn reading(value: f64, unit: &str) -> SensorReading {
+        SensorReading {
+            patient_id: "p1".into(),
+            device_id: "d1".into(),
+            code: SignalCode::Sound,
+            value,
+            unit: unit.into(),
+            ts: Utc::now(),
+            ts_source: TimestampSource::Device,
+            metadata: Default::default(),
+        }
+    }
+
+    fn rule(rule_name: &str, config: Value, severity: Severity) -> RuleConfig {
+        RuleConfig {
+            rule_name: rule_name.into(),
+            config,
+            severity,
+        }
+    }
+
+    #[test]
+    fn test_rules() {
+        let finite = FiniteValueRule;
+        assert!(finite.check(&reading(1.0, "dB")).is_ok());
+        assert_eq!(
+            finite.check(&reading(f64::NAN, "dB")),
+            Err(ValidationErrorCode::NonFiniteValue)
+        );
+
+        let range = RangeRule {
+            min: Some(0.0),
+            max: Some(140.0),
+        };
+        assert!(range.check(&reading(140.0, "dB")).is_ok());
+        assert_eq!(
+            range.check(&reading(-0.5, "dB")),
+            Err(ValidationErrorCode::OutOfRange)
+        );
+
+        let units = UnitWhitelistRule {
+            units: vec!["dB".into()],
+        };
+        assert!(units.check(&reading(1.0, "db")).is_ok());
+        assert!(units.check(&reading(1.0, "raw")).is_err());
+
+        let future = FutureTsRule { max_skew_secs: 60 };
+        let mut r = reading(1.0, "dB");
+        r.ts = Utc::now() + Duration::seconds(30);
+        assert!(future.check(&r).is_ok());
+        r.ts = Utc::now() + Duration::minutes(5);
+        assert_eq!(future.check(&r), Err(ValidationErrorCode::FutureTimestamp));
+        for max_skew_secs in [i64::MAX, Duration::MAX.num_seconds()] {
+            r.ts = DateTime::<Utc>::MAX_UTC;
+            assert!(FutureTsRule { max_skew_secs }.check(&r).is_ok());
+        }
+
+        let registry = PatientRegistry::default();
+        let exists = PatientExistsRule {
+            patients: registry.clone(),
+        };
+        assert_eq!(
+            exists.check(&reading(1.0, "dB")),
+            Err(ValidationErrorCode::UnknownPatient)
+        );
+        registry.insert("p1");
+        assert!(exists.check(&reading(1.0, "dB")).is_ok());
+    }
+
+    #[test]
+    fn test_pipeline_splits_errors_and_warnings() {
+        let pipeline = ValidationPipeline::from_configs(
+            &[
+                rule("range", json!({"min": 0, "max": 140}), Severity::Error),
+                rule(
+                    "unit_whitelist",
+                    json!({"units": ["dB"]}),
+                    Severity::Warning,
+                ),
+                rule("patient_exists", Value::Null, Severity::Warning),
+            ],
+            PatientRegistry::new(["p1".to_string()]),
+        )
+        .unwrap();
+        assert_eq!(pipeline.len(), 3);
+
+        assert_eq!(pipeline.run(&reading(60.0, "dB")), Default::default());
+
+        let result = pipeline.run(&reading(60.0, "raw"));
+        assert!(result.errors.is_empty());
+        assert_eq!(result.warnings, ["unit_not_allowed (unit_whitelist [dB])"]);
+
+        let result = pipeline.run(&reading(150.0, "raw"));
+        assert_eq!(result.errors, ["out_of_range (range [0, 140])"]);
+        assert_eq!(result.warnings.len(), 1);
+        assert!(result.into_warnings().is_err());
+    }
+
+    #[test]
+    fn test_signal_code_allowlist() {
+        assert_eq!(parse_signal_codes("").unwrap(), SignalCode::ALL);
+        assert_eq!(
+            parse_signal_codes(" Sound, sound,").unwrap(),
+            [SignalCode::Sound]
+        );
+        assert!(parse_signal_codes("sound,heart_beat").is_err());
+
+        let all = ValidationPipeline::default().with_allowed_signal_codes(&SignalCode::ALL);
+        assert!(all.is_empty());
+
+        let none = ValidationPipeline::default().with_allowed_signal_codes(&[]);
+        assert_eq!(
+            none.run(&reading(60.0, "dB")).errors,
+            ["signal_code_not_allowed (signal_codes [])"]
+        );
+        let rule = SignalCodeRule {
+            codes: vec![SignalCode::Sound],
+        };
+        assert!(rule.check(&reading(60.0, "dB")).is_ok());
+    }
+
+    #[test]
+    fn test_bad_configs_are_refused() {
+        let build = |name: &str, config: Value| {
+            ValidationPipeline::from_configs(
+                &[rule(name, config, Severity::Error)],
+                PatientRegistry::default(),
+            )
+        };
+        assert!(build("finite_value", Value::Null).is_ok());
+        assert!(build("future_ts", json!({})).is_ok());
+
+        assert!(build("no_such_rule", Value::Null).is_err());
+        assert!(build("range", json!({})).is_err());
+        assert!(build("range", json!({"min": 10, "max": 0})).is_err());
+        assert!(build("range", json!({"mni": 0})).is_err());
+        assert!(build("unit_whitelist", json!({"units": []})).is_err());
+        assert!(build("future_ts", json!({"max_skew_secs": -1})).is_err());
+    }
+}
@@ -1,42 +1,855 @@
 use actix::{Actor, ActorContext, AsyncContext, StreamHandler};
 use actix_web::{web, Error, HttpRequest, HttpResponse};
 use actix_web_actors::ws;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::TryRecvError;
+use tokio::sync::Mutex;
 
-use crate::fhir::FhirObservation;
+use crate::alert_suppression::AlertSuppressions;
+use crate::anomaly::{AnomalyEvent, AnomalyLog, AnomalyScorer};
+use crate::audit::{AuditAction, AuditLogEntry};
+use crate::auth::{require_token, Permission};
+use crate::device_watchdog::DeviceAlert;
+use crate::domain::models::SignalCode;
+use crate::domain::store::AppState;
+use crate::errors::AppError;
+use crate::fhir::{subject_patient_id, FhirObservation};
+use crate::live_stats::{LiveStats, StatsFrame};
+use crate::log_sampling::LogSampler;
+use crate::metrics::Exposition;
+use crate::users::UserStore;
+
+/// Messages buffered per stream for subscribers that have not read them yet
+pub const DEFAULT_CHANNEL_CAPACITY: usize = 4096;
+
+/// Unacknowledged frames past which a session starts coalescing
+pub const DEFAULT_COALESCE_ABOVE: u64 = 1000;
+
+/// Unacknowledged frames at or below which a coalescing session flushes
+pub const DEFAULT_RESUME_AT: u64 = 100;
+
+/// How often live sessions get `stats` frames
+pub const DEFAULT_STATS_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How far a WebSocket client may fall behind before its session coalesces.
+///
+/// Every tick with new frames out, the session pings the client with the
+/// number of frames sent so far; the pong echoes it once the client has read
+/// up to there. A client that leaves more than `coalesce_above` frames
+/// unacknowledged gets nothing new; its session keeps only the latest message
+/// per patient and code (per device for alerts) until the client is back to
+/// `resume_at`, then sends a `coalesced` notice with the number of messages
+/// skipped, followed by the kept messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Backpressure {
+    pub coalesce_above: u64,
+    pub resume_at: u64,
+}
+
+impl Default for Backpressure {
+    fn default() -> Self {
+        Self {
+            coalesce_above: DEFAULT_COALESCE_ABOVE,
+            resume_at: DEFAULT_RESUME_AT,
+        }
+    }
+}
+
+impl Backpressure {
+    /// Read `WS_COALESCE_ABOVE` (default 1000) and `WS_RESUME_AT` (default
+    /// 100, at most `WS_COALESCE_ABOVE`)
+    pub fn from_env() -> Self {
+        let frames = |name: &str, default: u64| {
+            std::env::var(name)
+                .ok()
+                .and_then(|v| v.parse::<u64>().ok())
+                .unwrap_or(default)
+        };
+        let coalesce_above = frames("WS_COALESCE_ABOVE", DEFAULT_COALESCE_ABOVE).max(1);
+        Self {
+            coalesce_above,
+            resume_at: frames("WS_RESUME_AT", DEFAULT_RESUME_AT).min(coalesce_above),
+        }
+    }
+}
+
+/// Messages of a lagging session that share a key replace each other
+pub trait Coalesce {
+    fn coalesce_key(&self) -> String;
+}
+
+impl Coalesce for FhirObservation {
+    fn coalesce_key(&self) -> String {
+        let code = self
+            .code
+            .coding
+            .first()
+            .map_or(self.code.text, |c| c.code.as_str());
+        format!("{}|{}", self.subject.reference, code)
+    }
+}
+
+impl Coalesce for AnomalyEvent {
+    fn coalesce_key(&self) -> String {
+        format!("{}|{}", self.subject, self.code)
+    }
+}
+
+impl Coalesce for DeviceAlert {
+    fn coalesce_key(&self) -> String {
+        self.device_id().to_string()
+    }
+}
+
+/// Patient and signal a session is limited to; unset, every one
+#[derive(Debug, Clone, Default)]
+pub struct StreamFilter {
+    pub patient_id: Option<String>,
+    pub code: Option<SignalCode>,
+    /// Patients the subscriber's token is limited to
+    pub permitted: Option<Vec<String>>,
+}
+
+impl StreamFilter {
+    /// Whether a message about `patient_id`'s signal `code` passes
+    pub fn matches(&self, patient_id: Option<&str>, code: &str) -> bool {
+        self.patient_id
+            .as_deref()
+            .is_none_or(|id| patient_id == Some(id))
+            && self.code.is_none_or(|c| c.as_str() == code)
+            && patient_id.map_or(self.permitted.is_none(), |id| self.permits(id))
+    }
+
+    /// Whether the subscriber may see `patient_id`'s messages
+    pub fn permits(&self, patient_id: &str) -> bool {
+        self.permitted
+            .as_ref()
+            .is_none_or(|ids| ids.iter().any(|id| id == patient_id))
+    }
+}
+
+/// Messages a session's filter applies to
+pub trait Filtered {
+    fn passes(&self, filter: &StreamFilter) -> bool;
+}
+
+impl Filtered for FhirObservation {
+    fn passes(&self, filter: &StreamFilter) -> bool {
+        let code = self.code.coding.first().map_or("", |c| c.code.as_str());
+        filter.matches(subject_patient_id(&self.subject.reference), code)
+    }
+}
+
+impl Filtered for AnomalyEvent {
+    fn passes(&self, filter: &StreamFilter) -> bool {
+        filter.matches(subject_patient_id(&self.subject), &self.code)
+    }
+}
+
+/// Device alerts are about a device rather than a patient's signal, so
+/// every filter passes them
+impl Filtered for DeviceAlert {
+    fn passes(&self, _filter: &StreamFilter) -> bool {
+        true
+    }
+}
 
 #[derive(Clone)]
 pub struct WsHub {
     pub tx: broadcast::Sender<FhirObservation>,
+    pub anomalies: broadcast::Sender<AnomalyEvent>,
+    pub alerts: broadcast::Sender<DeviceAlert>,
+    scorer: Option<Arc<AnomalyScorer>>,
+    /// Anomalies of the last day, whether or not anyone was subscribed
+    anomaly_log: Arc<AnomalyLog>,
+    /// Live observations subscribers fell too far behind to receive
+    live_dropped: Arc<AtomicU64>,
+    /// Likewise for the anomaly stream
+    anomalies_dropped: Arc<AtomicU64>,
+    /// Likewise for the device alert stream
+    alerts_dropped: Arc<AtomicU64>,
+    /// When sessions of lagging clients coalesce
+    backpressure: Backpressure,
+    /// How often live sessions get `stats` frames
+    stats_interval: Duration,
+    /// Windows during which patients' and devices' alerts are not raised
+    suppressions: Option<Arc<AlertSuppressions>>,
+    /// Where raised anomalies and device alerts are audited
+    audit: Option<Arc<UserStore>>,
+}
+
+impl WsHub {
+    /// Create the hub; without a scorer the anomaly stream stays silent
+    pub fn new(scorer: Option<AnomalyScorer>) -> Self {
+        Self::with_capacity(scorer, DEFAULT_CHANNEL_CAPACITY)
+    }
+
+    /// Create the hub buffering `WS_CHANNEL_CAPACITY` messages per stream
+    /// (default 4096), with sessions coalescing as `Backpressure::from_env`
+    /// says and live sessions getting `stats` frames every
+    /// `WS_STATS_INTERVAL_SECS` (default 5)
+    pub fn from_env(scorer: Option<AnomalyScorer>) -> Self {
+        let capacity = std::env::var("WS_CHANNEL_CAPACITY")
+            .ok()
+            .and_then(|v| v.parse::<usize>().ok())
+            .filter(|v| *v > 0)
+            .unwrap_or(DEFAULT_CHANNEL_CAPACITY);
+        let stats_interval = std::env::var("WS_STATS_INTERVAL_SECS")
+            .ok()
+            .and_then(|v| v.parse::<u64>().ok())
+            .filter(|v| *v > 0)
+            .map_or(DEFAULT_STATS_INTERVAL, Duration::from_secs);
+        Self::with_capacity(scorer, capacity)
+            .with_backpressure(Backpressure::from_env())
+            .with_stats_interval(stats_interval)
+    }
+
+    /// Create the hub buffering `capacity` messages per stream; a subscriber
+    /// further behind than that loses the oldest ones
+    pub fn with_capacity(scorer: Option<AnomalyScorer>, capacity: usize) -> Self {
+        Self {
+            tx: broadcast::channel(capacity).0,
+            anomalies: broadcast::channel(capacity).0,
+            alerts: broadcast::channel(capacity).0,
+            scorer: scorer.map(Arc::new),
+            anomaly_log: Arc::new(AnomalyLog::default()),
+            live_dropped: Arc::new(AtomicU64::new(0)),
+            anomalies_dropped: Arc::new(AtomicU64::new(0)),
+            alerts_dropped: Arc::new(AtomicU64::new(0)),
+            backpressure: Backpressure::default(),
+            stats_interval: DEFAULT_STATS_INTERVAL,
+            suppressions: None,
+            audit: None,
+        }
+    }
+
+    /// Coalesce sessions of lagging clients as `backpressure` says
+    pub fn with_backpressure(mut self, backpressure: Backpressure) -> Self {
+        self.backpressure = backpressure;
+        self
+    }
+
+    /// Send live sessions `stats` frames every `interval`
+    pub fn with_stats_interval(mut self, interval: Duration) -> Self {
+        self.stats_interval = interval;
+        self
+    }
+
+    /// Hold back anomalies and device alerts covered by a window in
+    /// `suppressions`. Checking takes a query, so alerts are then raised in
+    /// the background rather than as they are detected.
+    pub fn with_suppressions(mut self, suppressions: Arc<AlertSuppressions>) -> Self {
+        self.suppressions = Some(suppressions);
+        self
+    }
+
+    /// Audit every anomaly and device alert raised through `users`, for the
+    /// monthly compliance reports
+    pub fn with_audit(mut self, users: Arc<UserStore>) -> Self {
+        self.audit = Some(users);
+        self
+    }
+
+    fn audit_raised(&self, entry: AuditLogEntry) {
+        if let Some(users) = self.audit.clone() {
+            tokio::spawn(async move { users.audit(entry).await });
+        }
+    }
+
+    /// Subscribe to every published observation
+    pub fn subscribe_live(&self) -> Subscription<FhirObservation> {
+        Subscription {
+            rx: self.tx.subscribe(),
+            dropped: self.live_dropped.clone(),
+            missed: 0,
+        }
+    }
+
+    /// Subscribe to anomalous observations only
+    pub fn subscribe_anomalies(&self) -> Subscription<AnomalyEvent> {
+        Subscription {
+            rx: self.anomalies.subscribe(),
+            dropped: self.anomalies_dropped.clone(),
+            missed: 0,
+        }
+    }
+
+    /// Subscribe to device offline/recovered alerts
+    pub fn subscribe_alerts(&self) -> Subscription<DeviceAlert> {
+        Subscription {
+            rx: self.alerts.subscribe(),
+            dropped: self.alerts_dropped.clone(),
+            missed: 0,
+        }
+    }
+
+    /// Connected live-stream subscribers
+    pub fn subscriber_count(&self) -> usize {
+        self.tx.receiver_count()
+    }
+
+    /// Connected anomaly-stream subscribers
+    pub fn anomaly_subscriber_count(&self) -> usize {
+        self.anomalies.receiver_count()
+    }
+
+    /// Connected alert-stream subscribers
+    pub fn alert_subscriber_count(&self) -> usize {
+        self.alerts.receiver_count()
+    }
+
+    /// Whether published observations go anywhere: to live subscribers, or
+    /// to the anomaly scorer, whose backlog dashboards read without
+    /// subscribing. Observations built only to be published can be skipped
+    /// otherwise.
+    pub fn wants_observations(&self) -> bool {
+        self.scorer.is_some() || self.subscriber_count() > 0
+    }
+
+    /// Messages lost to slow subscribers since startup, over all streams
+    pub fn dropped_messages(&self) -> u64 {
+        self.live_dropped.load(Ordering::Relaxed)
+            + self.anomalies_dropped.load(Ordering::Relaxed)
+            + self.alerts_dropped.load(Ordering::Relaxed)
+    }
+
+    pub fn render_metrics(&self, exp: &mut Exposition) {
+        exp.family(
+            "soundsense_ws_subscribers",
+            "gauge",
+            "Connected WebSocket subscribers",
+            &[
+                ("stream=\"live\"", self.subscriber_count() as u64),
+                (
+                    "stream=\"anomalies\"",
+                    self.anomaly_subscriber_count() as u64,
+                ),
+                ("stream=\"alerts\"", self.alert_subscriber_count() as u64),
+            ],
+        );
+        exp.family(
+            "soundsense_ws_dropped_total",
+            "counter",
+            "Messages WebSocket subscribers fell too far behind to receive",
+            &[
+                ("stream=\"live\"", self.live_dropped.load(Ordering::Relaxed)),
+                (
+                    "stream=\"anomalies\"",
+                    self.anomalies_dropped.load(Ordering::Relaxed),
+                ),
+                (
+                    "stream=\"alerts\"",
+                    self.alerts_dropped.load(Ordering::Relaxed),
+                ),
+            ],
+        );
+    }
+
+    /// Broadcast an observation to live subscribers and score it for the
+    /// anomaly stream. ML scoring runs in the background so ingest never waits
+    /// on the ML service. Nothing is copied for a stream nobody listens to.
+    pub fn publish(&self, obs: &FhirObservation) {
+        let Some(scorer) = &self.scorer else {
+            self.send_live(obs);
+            return;
+        };
+
+        if scorer.uses_ml() {
+            self.send_live(obs);
+            let scorer = scorer.clone();
+            let hub = self.clone();
+            let obs = obs.clone();
+            tokio::spawn(async move {
+                if let Some(event) = scorer.score(&obs).await {
+                    hub.raise_anomaly(event).await;
+                }
+            });
+        } else {
+            let (event, _) = scorer.score_builtin(obs);
+            self.send_live(obs);
+            match event {
+                Some(event) if self.suppressions.is_some() => {
+                    let hub = self.clone();
+                    tokio::spawn(async move { hub.raise_anomaly(event).await });
+                }
+                Some(event) => self.send_anomaly(event),
+                None => {}
+            }
+        }
+    }
+
+    /// Record and broadcast `event` unless its patient's alerts are
+    /// suppressed
+    async fn raise_anomaly(&self, event: AnomalyEvent) {
+        if let Some(suppressions) = &self.suppressions {
+            let patient_id = subject_patient_id(&event.subject).unwrap_or(&event.subject);
+            if suppressions.is_patient_suppressed(patient_id).await {
+                tracing::debug!(patient_id, "Anomaly suppressed");
+                return;
+            }
+        }
+        self.send_anomaly(event);
+    }
+
+    fn send_anomaly(&self, event: AnomalyEvent) {
+        let patient_id = subject_patient_id(&event.subject).unwrap_or(&event.subject);
+        self.audit_raised(
+            AuditLogEntry::new(AuditAction::Create, "Anomaly".to_string())
+                .with_resource_id(event.observation_id.clone())
+                .with_patient_id(patient_id.to_string())
+                .with_metadata(serde_json::json!({
+                    "code": event.code,
+                    "score": event.score,
+                    "category": event.category,
+                    "detector": event.detector,
+                })),
+        );
+        self.anomaly_log.record(event.clone());
+        // Fails only without subscribers
+        let _ = self.anomalies.send(event);
+    }
+
+    /// Anomalies of `patient_id`'s readings taken since `since`
+    pub fn recent_anomalies(&self, patient_id: &str, since: DateTime<Utc>) -> Vec<AnomalyEvent> {
+        self.anomaly_log.for_patient(patient_id, since)
+    }
+
+    /// Drop `patient_id`'s anomalies from the backlog served to dashboards
+    pub fn forget_patient(&self, patient_id: &str) {
+        self.anomaly_log.forget_patient(patient_id);
+    }
+
+    /// Broadcast a device alert to alert subscribers, unless a suppression
+    /// window names the device
+    pub fn publish_alert(&self, alert: DeviceAlert) {
+        let Some(suppressions) = self.suppressions.clone() else {
+            self.send_alert(alert);
+            return;
+        };
+        let hub = self.clone();
+        tokio::spawn(async move {
+            if suppressions.is_device_suppressed(alert.device_id()).await {
+                tracing::debug!(device_id = alert.device_id(), "Device alert suppressed");
+                return;
+            }
+            hub.send_alert(alert);
+        });
+    }
+
+    fn send_alert(&self, alert: DeviceAlert) {
+        self.audit_raised(
+            AuditLogEntry::new(AuditAction::Create, "DeviceAlert".to_string())
+                .with_resource_id(alert.device_id().to_string())
+                .with_metadata(serde_json::to_value(&alert).unwrap_or_default()),
+        );
+        // Fails only without subscribers
+        let _ = self.alerts.send(alert);
+    }
+
+    fn send_live(&self, obs: &FhirObservation) {
+        if self.tx.receiver_count() > 0 {
+            // A subscriber leaving in between only makes this a no-op
+            let _ = self.tx.send(obs.clone());
+        }
+    }
 }
 
-pub struct WsSession {
-    rx: broadcast::Receiver<FhirObservation>,
+/// Frame of an enveloped session: `{"type": ..., "data": {...}}`, so that
+/// clients tell messages of the stream from control frames
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", content = "data", rename_all = "snake_case")]
+pub enum Envelope<'a> {
+    /// On `/ws/live`
+    Observation(&'a FhirObservation),
+    /// On `/ws/anomalies`
+    Anomaly(&'a AnomalyEvent),
+    /// On `/ws/alerts`
+    Alert(&'a DeviceAlert),
+    /// The client fell further behind than the stream buffers; `missed`
+    /// messages before the next one were dropped
+    Lagged { missed: u64 },
+    /// A lagging session caught up; `skipped` messages were replaced by a
+    /// later one with their key, which follow
+    Coalesced { skipped: u64 },
+    /// On `/ws/live`, every stats interval: rolling statistics of a
+    /// patient's signal
+    Stats(&'a StatsFrame),
 }
 
-impl Actor for WsSession {
+/// Messages of a stream, as wrapped for enveloped sessions
+pub trait Enveloped {
+    fn envelope(&self) -> Envelope<'_>;
+}
+
+impl Enveloped for FhirObservation {
+    fn envelope(&self) -> Envelope<'_> {
+        Envelope::Observation(self)
+    }
+}
+
+impl Enveloped for AnomalyEvent {
+    fn envelope(&self) -> Envelope<'_> {
+        Envelope::Anomaly(self)
+    }
+}
+
+impl Enveloped for DeviceAlert {
+    fn envelope(&self) -> Envelope<'_> {
+        Envelope::Alert(self)
+    }
+}
+
+/// Query parameters of the WebSocket streams
+#[derive(Debug, Deserialize)]
+pub struct WsQuery {
+    /// `false` sends messages bare and no `lagged` frames, as before
+    /// envelopes
+    #[serde(default = "default_envelope")]
+    pub envelope: bool,
+    /// Only messages about this patient
+    pub patient_id: Option<String>,
+    /// Only messages about this signal, or an alias of it accepted on
+    /// ingest
+    pub code: Option<String>,
+    /// Token for `/ws/live`, for browsers that can't send an
+    /// `Authorization` header with the handshake
+    pub access_token: Option<String>,
+}
+
+impl WsQuery {
+    /// The session's filter; 400 for an unknown code
+    fn filter(&self) -> Result<StreamFilter, AppError> {
+        let code = self
+            .code
+            .as_deref()
+            .map(str::parse::<SignalCode>)
+            .transpose()
+            .map_err(AppError::BadRequest)?;
+        Ok(StreamFilter {
+            patient_id: self.patient_id.clone(),
+            code,
+            permitted: None,
+        })
+    }
+}
+
+fn default_envelope() -> bool {
+    true
+}
+
+/// A subscriber's end of a hub stream. Messages it fell too far behind to
+/// receive are skipped and counted rather than lost silently.
+pub struct Subscription<T> {
+    rx: broadcast::Receiver<T>,
+    dropped: Arc<AtomicU64>,
+    /// Dropped since `take_missed` last ran
+    missed: u64,
+}
+
+impl<T: Clone> Subscription<T> {
+    /// Next buffered message, or `None` if there is none yet
+    pub fn try_next(&mut self) -> Option<T> {
+        loop {
+            match self.rx.try_recv() {
+                Ok(msg) => return Some(msg),
+                Err(TryRecvError::Lagged(missed)) => {
+                    tracing::warn!(missed, "WebSocket subscriber too slow, messages dropped");
+                    self.dropped.fetch_add(missed, Ordering::Relaxed);
+                    self.missed += missed;
+                }
+                Err(TryRecvError::Empty | TryRecvError::Closed) => return None,
+            }
+        }
+    }
+
+    /// Messages dropped right before the one `try_next` last returned (or
+    /// since, if it returned none), counting from the last call
+    pub fn take_missed(&mut self) -> u64 {
+        std::mem::take(&mut self.missed)
+    }
+}
+
+/// Messages sent to subscribers are logged at `LOG_SAMPLE_RATE`
+static SEND_LOG: LogSampler = LogSampler::new();
+
+/// Sent ahead of the messages a coalescing session kept, without envelopes
+#[derive(Debug, Serialize)]
+struct CoalescedNotice {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    /// Messages replaced by a later one and never sent
+    skipped: u64,
+}
+
+/// A `stats` frame, without envelopes
+#[derive(Debug, Serialize)]
+struct StatsNotice<'a> {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    #[serde(flatten)]
+    frame: &'a StatsFrame,
+}
+
+/// Outbound side of a session: counts frames sent and acknowledged, and
+/// holds the latest message per key while the client is too far behind
+#[derive(Debug)]
+pub struct Outbox<T> {
+    backpressure: Backpressure,
+    sent: u64,
+    acked: u64,
+    /// Frames sent when the last ping went out
+    pinged: u64,
+    /// Set while coalescing
+    held: Option<Held<T>>,
+}
+
+#[derive(Debug)]
+struct Held<T> {
+    latest: BTreeMap<String, T>,
+    skipped: u64,
+}
+
+impl<T: Coalesce> Outbox<T> {
+    pub fn new(backpressure: Backpressure) -> Self {
+        Self {
+            backpressure,
+            sent: 0,
+            acked: 0,
+            pinged: 0,
+            held: None,
+        }
+    }
+
+    /// Frames sent that the client has not acknowledged yet
+    pub fn pending(&self) -> u64 {
+        self.sent - self.acked
+    }
+
+    /// Messages held back, at most one per key
+    pub fn held(&self) -> usize {
+        self.held.as_ref().map_or(0, |h| h.latest.len())
+    }
+
+    /// `msg` if it should be sent now; otherwise it is held, replacing any
+    /// held message with its key
+    pub fn push(&mut self, msg: T) -> Option<T> {
+        if self.held.is_none() && self.pending() > self.backpressure.coalesce_above {
+            tracing::warn!(
+                pending = self.pending(),
+                "WebSocket client falling behind, coalescing its messages"
+            );
+            self.held = Some(Held {
+                latest: BTreeMap::new(),
+                skipped: 0,
+            });
+        }
+        let Some(held) = &mut self.held else {
+            self.sent += 1;
+            return Some(msg);
+        };
+        if held.latest.insert(msg.coalesce_key(), msg).is_some() {
+            held.skipped += 1;
+        }
+        None
+    }
+
+    /// A frame from outside the stream, such as a `stats` frame, went out
+    pub fn sent_other(&mut self) {
+        self.sent += 1;
+    }
+
+    /// `missed` messages were dropped before reaching the outbox: whether to
+    /// tell the client now. While coalescing they count as skipped instead.
+    pub fn lagged(&mut self, missed: u64) -> bool {
+        match &mut self.held {
+            Some(held) => {
+                held.skipped += missed;
+                false
+            }
+            None => {
+                self.sent += 1;
+                true
+            }
+        }
+    }
+
+    /// The client has read the first `frames` frames
+    pub fn ack(&mut self, frames: u64) {
+        self.acked = self.acked.max(frames.min(self.sent));
+    }
+
+    /// Frames sent so far, if any went out since the last ping
+    pub fn ping(&mut self) -> Option<u64> {
+        (self.sent > self.pinged).then(|| {
+            self.pinged = self.sent;
+            self.sent
+        })
+    }
+
+    /// Once a coalescing client has caught up: how many messages were
+    /// skipped, and the held ones to send after the notice
+    pub fn flush(&mut self) -> Option<(u64, Vec<T>)> {
+        if self.pending() > self.backpressure.resume_at {
+            return None;
+        }
+        let held = self.held.take()?;
+        self.sent += held.latest.len() as u64 + 1;
+        Some((held.skipped, held.latest.into_values().collect()))
+    }
+}
+
+pub struct WsSession<T> {
+    sub: Subscription<T>,
+    outbox: Outbox<T>,
+    /// Whether frames are wrapped in an `Envelope`
+    envelope: bool,
+    /// Messages outside it are not sent
+    filter: StreamFilter,
+    /// Statistics sent as `stats` frames, and how often
+    stats: Option<(Arc<LiveStats>, Duration)>,
+}
+
+impl<T: Coalesce> WsSession<T> {
+    pub fn new(sub: Subscription<T>, backpressure: Backpressure, envelope: bool) -> Self {
+        Self {
+            sub,
+            outbox: Outbox::new(backpressure),
+            envelope,
+            filter: StreamFilter::default(),
+            stats: None,
+        }
+    }
+
+    /// Send only the messages passing `filter`
+    pub fn with_filter(mut self, filter: StreamFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// Send a `stats` frame per window of each stream in `stats` passing
+    /// the filter, every `interval`
+    pub fn with_stats(mut self, stats: Arc<LiveStats>, interval: Duration) -> Self {
+        self.stats = Some((stats, interval));
+        self
+    }
+}
+
+/// Bound of the messages sessions send
+pub trait StreamMessage:
+    Serialize + Clone + Send + Coalesce + Enveloped + Filtered + 'static
+{
+}
+
+impl<T: Serialize + Clone + Send + Coalesce + Enveloped + Filtered + 'static> StreamMessage for T {}
+
+impl<T: StreamMessage> WsSession<T> {
+    fn send_message(&self, ctx: &mut ws::WebsocketContext<Self>, msg: &T) {
+        if self.envelope {
+            Self::send(ctx, &msg.envelope());
+        } else {
+            Self::send(ctx, msg);
+        }
+    }
+
+    fn send(ctx: &mut ws::WebsocketContext<Self>, msg: &impl Serialize) {
+        if let Ok(txt) = serde_json::to_string(msg) {
+            if SEND_LOG.sample() {
+                tracing::debug!(bytes = txt.len(), "Sent message to WebSocket subscriber");
+            }
+            ctx.text(txt);
+        }
+    }
+
+    fn send_stats(&mut self, ctx: &mut ws::WebsocketContext<Self>, stats: &LiveStats) {
+        let frames = stats.frames(
+            Utc::now(),
+            self.filter.patient_id.as_deref(),
+            self.filter.code,
+        );
+        for frame in frames.iter().filter(|f| self.filter.permits(&f.patient)) {
+            if self.envelope {
+                Self::send(ctx, &Envelope::Stats(frame));
+            } else {
+                let notice = StatsNotice {
+                    kind: "stats",
+                    frame,
+                };
+                Self::send(ctx, &notice);
+            }
+            self.outbox.sent_other();
+        }
+    }
+}
+
+impl<T: StreamMessage> Actor for WsSession<T> {
     type Context = ws::WebsocketContext<Self>;
 
     fn started(&mut self, ctx: &mut Self::Context) {
-        // clone/resubscribe so we can move it into the closure
-        let mut rx = self.rx.resubscribe();
-
-        ctx.run_interval(std::time::Duration::from_millis(250), move |_, ctx| {
+        if let Some((stats, interval)) = self.stats.clone() {
+            ctx.run_interval(interval, move |session, ctx| {
+                session.send_stats(ctx, &stats)
+            });
+        }
+        ctx.run_interval(std::time::Duration::from_millis(250), |session, ctx| {
+            if let Some((skipped, held)) = session.outbox.flush() {
+                tracing::info!(
+                    skipped,
+                    "WebSocket client caught up, sending coalesced messages"
+                );
+                if session.envelope {
+                    Self::send(ctx, &Envelope::Coalesced { skipped });
+                } else {
+                    let notice = CoalescedNotice {
+                        kind: "coalesced",
+                        skipped,
+                    };
+                    Self::send(ctx, &notice);
+                }
+                for msg in &held {
+                    session.send_message(ctx, msg);
+                }
+            }
             // Drain all queued messages quickly each tick
-            while let Ok(obs) = rx.try_recv() {
-                if let Ok(txt) = serde_json::to_string(&obs) {
-                    ctx.text(txt);
+            loop {
+                let msg = session.sub.try_next();
+                let missed = session.sub.take_missed();
+                if missed > 0 && session.envelope && session.outbox.lagged(missed) {
+                    Self::send(ctx, &Envelope::Lagged { missed });
+                }
+                let Some(msg) = msg else { break };
+                if !msg.passes(&session.filter) {
+                    continue;
                 }
+                if let Some(msg) = session.outbox.push(msg) {
+                    session.send_message(ctx, &msg);
+                }
+            }
+            if let Some(sent) = session.outbox.ping() {
+                ctx.ping(sent.to_string().as_bytes());
             }
         });
     }
 }
 
-impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WsSession {
+impl<T: StreamMessage> StreamHandler<Result<ws::Message, ws::ProtocolError>> for WsSession<T> {
     fn handle(&mut self, msg: Result<ws::Message, ws::ProtocolError>, ctx: &mut Self::Context) {
         match msg {
             Ok(ws::Message::Ping(m)) => ctx.pong(&m),
-            Ok(ws::Message::Pong(_)) => {}
+            // Echo of a ping: the client has read every frame sent before it
+            Ok(ws::Message::Pong(m)) => {
+                if let Some(frames) = std::str::from_utf8(&m)
+                    .ok()
+                    .and_then(|s| s.parse::<u64>().ok())
+                {
+                    self.outbox.ack(frames);
+                }
+            }
             Ok(ws::Message::Close(r)) => {
                 ctx.close(r);
                 ctx.stop();
@@ -47,11 +860,105 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WsSession {
     }
 }
 
+/// Stream of every published observation, with `stats` frames of the
+/// rolling statistics kept in `state`. Needs a token that may read data;
+/// one limited to some patients only hears about those.
 pub async fn ws_live(
     req: HttpRequest,
     stream: web::Payload,
     hub: web::Data<WsHub>,
+    state: web::Data<Arc<Mutex<AppState>>>,
+    query: web::Query<WsQuery>,
+) -> Result<HttpResponse, Error> {
+    let claims = require_token(&req, query.access_token.as_deref()).await?;
+    if !claims.can(Permission::ReadData) {
+        return Err(AppError::Forbidden("token may not read data".into()).into());
+    }
+    let filter = StreamFilter {
+        permitted: claims.patient_filter().map(<[String]>::to_vec),
+        ..query.filter()?
+    };
+    let stats = state.lock().await.live_stats();
+    let sub = hub.subscribe_live();
+    ws::start(
+        WsSession::new(sub, hub.backpressure, query.envelope)
+            .with_filter(filter)
+            .with_stats(stats, hub.stats_interval),
+        &req,
+        stream,
+    )
+}
+
+/// Stream of anomalous observations only, with their score and category
+pub async fn ws_anomalies(
+    req: HttpRequest,
+    stream: web::Payload,
+    hub: web::Data<WsHub>,
+    query: web::Query<WsQuery>,
+) -> Result<HttpResponse, Error> {
+    let filter = query.filter()?;
+    let sub = hub.subscribe_anomalies();
+    ws::start(
+        WsSession::new(sub, hub.backpressure, query.envelope).with_filter(filter),
+        &req,
+        stream,
+    )
+}
+
+/// Stream of device offline and recovered alerts
+pub async fn ws_alerts(
+    req: HttpRequest,
+    stream: web::Payload,
+    hub: web::Data<WsHub>,
+    query: web::Query<WsQuery>,
 ) -> Result<HttpResponse, Error> {
-    let rx = hub.tx.subscribe();
-    ws::start(WsSession { rx }, &req, stream)
+    let filter = query.filter()?;
+    let sub = hub.subscribe_alerts();
+    ws::start(
+        WsSession::new(sub, hub.backpressure, query.envelope).with_filter(filter),
+        &req,
+        stream,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Clone, PartialEq)]
+    struct Msg(&'static str, u32);
+
+    impl Coalesce for Msg {
+        fn coalesce_key(&self) -> String {
+            self.0.to_string()
+        }
+    }
+
+    #[test]
+    fn test_lagging_outbox_holds_one_message_per_key() {
+        let mut outbox = Outbox::new(Backpressure {
+            coalesce_above: 2,
+            resume_at: 1,
+        });
+        let sent: Vec<_> = (0..10_000)
+            .filter_map(|i| outbox.push(Msg(["a", "b"][i as usize % 2], i)))
+            .collect();
+        assert_eq!(sent.len(), 3);
+        assert_eq!(outbox.held(), 2);
+        assert_eq!(outbox.ping(), Some(3));
+        assert_eq!(outbox.ping(), None);
+
+        // Not caught up far enough yet
+        outbox.ack(1);
+        assert!(outbox.flush().is_none());
+
+        outbox.ack(3);
+        let (skipped, held) = outbox.flush().unwrap();
+        assert_eq!(skipped, 10_000 - 3 - 2);
+        assert_eq!(held, [Msg("a", 9998), Msg("b", 9999)]);
+        // The notice and both messages are frames to acknowledge
+        assert_eq!(outbox.pending(), 3);
+        outbox.ack(6);
+        assert_eq!(outbox.push(Msg("a", 0)), Some(Msg("a", 0)));
+    }
 }
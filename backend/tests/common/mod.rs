@@ -0,0 +1,248 @@
+//! Harness for the HTTP tests.
+//!
+//! Apps are built from explicit configuration rather than the process
+//! environment, so tests running in parallel never race on `set_var` and
+//! one run can cover several configurations.
+use actix_http::Request;
+use actix_web::body::MessageBody;
+use actix_web::dev::{Service, ServiceResponse};
+use actix_web::{test, web, App};
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::Mutex;
+
+use soundsense_backend::auth::{Claims, JwtAlgorithm, JwtExpiryConfig, JwtManager};
+use soundsense_backend::bulk_export::BulkExports;
+use soundsense_backend::dashboard::Dashboards;
+use soundsense_backend::domain::store::AppState;
+use soundsense_backend::routes::{self, PublicIngest, RouteDeps};
+use soundsense_backend::timeout::RequestTimeout;
+use soundsense_backend::users::UserStore;
+use soundsense_backend::ws::WsHub;
+
+/// Secret test tokens are signed with
+pub const TEST_JWT_SECRET: &str = "test-secret-key";
+
+pub type SharedState = web::Data<Arc<Mutex<AppState>>>;
+
+/// Route handles with defaults, checking tokens signed with `TEST_JWT_SECRET`
+pub fn deps() -> RouteDeps {
+    RouteDeps::new(JwtManager::new(JwtAlgorithm::HS256(
+        TEST_JWT_SECRET.to_string(),
+    )))
+}
+
+/// `routes::configure` with `deps()` instead of the environment
+pub fn routes(cfg: &mut web::ServiceConfig) {
+    routes::configure_with(cfg, &deps());
+}
+
+/// Token for `sub` with `role`, valid for a day
+pub fn token_for(sub: &str, role: &str) -> String {
+    JwtManager::new(JwtAlgorithm::HS256(TEST_JWT_SECRET.to_string()))
+        .generate_token(Claims::new(sub.into(), role.into(), None, 24))
+        .unwrap()
+}
+
+/// Token for the test user with `role`
+pub fn token(role: &str) -> String {
+    token_for("test-user", role)
+}
+
+/// `Authorization` header line with a `role` token, for WebSocket handshakes
+pub fn bearer_header(role: &str) -> String {
+    format!("Authorization: Bearer {}", token(role))
+}
+
+/// Demo state whose accounts hash passwords at the cheapest bcrypt cost
+pub fn demo_state() -> AppState {
+    AppState::new_demo().with_users(Arc::new(UserStore::new(None).with_bcrypt_cost(4)))
+}
+
+/// An app under test: its state and the configuration it is served with
+pub struct TestApp {
+    pub state: SharedState,
+    pub deps: RouteDeps,
+}
+
+impl Default for TestApp {
+    fn default() -> Self {
+        Self::with_state(demo_state())
+    }
+}
+
+impl TestApp {
+    /// Demo state, default configuration
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_state(state: AppState) -> Self {
+        Self {
+            state: web::Data::new(Arc::new(Mutex::new(state))),
+            deps: deps(),
+        }
+    }
+
+    pub fn public_ingest(mut self, mode: PublicIngest) -> Self {
+        self.deps.public_ingest = mode;
+        self
+    }
+
+    pub fn debug_endpoints(mut self, enabled: bool) -> Self {
+        self.deps.debug_endpoints = enabled;
+        self
+    }
+
+    pub fn dashboards(mut self, dashboards: Dashboards) -> Self {
+        self.deps.dashboards = Arc::new(dashboards);
+        self
+    }
+
+    pub fn exports(mut self, exports: BulkExports) -> Self {
+        self.deps.exports = Arc::new(exports);
+        self
+    }
+
+    pub fn token_expiry(mut self, expiry: JwtExpiryConfig) -> Self {
+        self.deps.token_expiry = expiry;
+        self
+    }
+
+    pub fn request_timeout(mut self, timeout: RequestTimeout) -> Self {
+        self.deps.request_timeout = Arc::new(timeout);
+        self
+    }
+
+    pub fn hub(mut self, hub: WsHub) -> Self {
+        self.deps.hub = hub;
+        self
+    }
+
+    /// Initialise the app in process. The app keeps its own handles, so
+    /// the `TestApp` need not outlive it.
+    pub fn service(
+        &self,
+    ) -> impl Future<
+        Output = impl Service<
+            Request,
+            Response = ServiceResponse<impl MessageBody>,
+            Error = actix_web::Error,
+        >,
+    > {
+        let state = self.state.clone();
+        let deps = self.deps.clone();
+        async move {
+            test::init_service(
+                App::new()
+                    .app_data(state)
+                    .configure(move |cfg| routes::configure_with(cfg, &deps)),
+            )
+            .await
+        }
+    }
+
+    /// Serve the app on a free local port with `workers` workers
+    pub fn serve(&self, workers: usize) -> SocketAddr {
+        let state = self.state.clone();
+        let deps = self.deps.clone();
+        let server = actix_web::HttpServer::new(move || {
+            App::new()
+                .app_data(state.clone())
+                .configure(|cfg| routes::configure_with(cfg, &deps))
+        })
+        .workers(workers)
+        .bind(("127.0.0.1", 0))
+        .unwrap();
+        let addr = server.addrs()[0];
+        actix_web::rt::spawn(server.run());
+        addr
+    }
+
+    /// Token for the test user with `role`, signed as this app expects
+    pub fn token(&self, role: &str) -> String {
+        self.deps
+            .jwt_manager
+            .generate_token(Claims::new("test-user".into(), role.into(), None, 24))
+            .unwrap()
+    }
+
+    /// `POST /api/ingest` of `reading` with a device token
+    pub fn ingest(&self, reading: &impl serde::Serialize) -> Request {
+        test::TestRequest::post()
+            .uri("/api/ingest")
+            .insert_header(("authorization", format!("Bearer {}", self.token("device"))))
+            .set_json(reading)
+            .to_request()
+    }
+}
+
+/// WebSocket client speaking just enough of the protocol to read the
+/// server's unmasked text frames and answer its pings
+pub struct WsClient {
+    stream: TcpStream,
+    /// Status line and headers of the upgrade response, lowercased
+    pub handshake: String,
+}
+
+impl WsClient {
+    /// Upgrade `path` (with any query filters) on the server at `addr`,
+    /// sending `headers` (`Name: value` lines) too
+    pub async fn connect(addr: SocketAddr, path: &str, headers: &[&str]) -> Self {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let mut request = format!(
+            "GET {} HTTP/1.1\r\nHost: {}\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+             Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n",
+            path, addr
+        );
+        for header in headers {
+            request.push_str(header);
+            request.push_str("\r\n");
+        }
+        request.push_str("\r\n");
+        stream.write_all(request.as_bytes()).await.unwrap();
+
+        let mut response = Vec::new();
+        while !response.ends_with(b"\r\n\r\n") {
+            response.push(stream.read_u8().await.unwrap());
+        }
+        let handshake = String::from_utf8(response).unwrap().to_ascii_lowercase();
+        assert!(handshake.starts_with("http/1.1 101"), "{}", handshake);
+        Self { stream, handshake }
+    }
+
+    /// Payload of the next data frame; pings on the way are answered
+    pub async fn next_frame(&mut self) -> Vec<u8> {
+        loop {
+            let mut head = [0u8; 2];
+            self.stream.read_exact(&mut head).await.unwrap();
+            let len = match head[1] & 0x7f {
+                126 => self.stream.read_u16().await.unwrap() as usize,
+                127 => self.stream.read_u64().await.unwrap() as usize,
+                len => len as usize,
+            };
+            let mut payload = vec![0u8; len];
+            self.stream.read_exact(&mut payload).await.unwrap();
+            if head[0] & 0x0f != 0x9 {
+                return payload;
+            }
+            // Pong with the ping's payload; client frames must be masked,
+            // and an all-zero mask leaves the payload as is
+            let mut pong = vec![0x8a, 0x80 | payload.len() as u8, 0, 0, 0, 0];
+            pong.extend_from_slice(&payload);
+            self.stream.write_all(&pong).await.unwrap();
+        }
+    }
+
+    /// The next frame as JSON, failing the test after five seconds
+    pub async fn next_json(&mut self) -> serde_json::Value {
+        let frame = tokio::time::timeout(Duration::from_secs(5), self.next_frame())
+            .await
+            .expect("frame not delivered");
+        serde_json::from_slice(&frame).unwrap()
+    }
+}
@@ -1,28 +1,44 @@
 use actix_web::{test, web, App};
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::Mutex;
 
-use soundsense_backend::auth::{Claims, JwtManager};
-use soundsense_backend::domain::models::{SensorReading, SignalCode};
+use soundsense_backend::anomaly::{AnomalyDetector, AnomalyScorer};
+use soundsense_backend::auth::{Claims, JwtAlgorithm, JwtExpiryConfig, JwtManager};
+use soundsense_backend::bulk_export::BulkExports;
+use soundsense_backend::consent::ConsentMode;
+use soundsense_backend::dashboard::Dashboards;
+use soundsense_backend::decimation::Decimator;
+use soundsense_backend::device_auth::DeviceAuthStore;
+use soundsense_backend::device_watchdog::DeviceStatus;
+use soundsense_backend::dlq::DeadLetterQueue;
+use soundsense_backend::domain::models::{
+    SensorReading, SignalCode, StoredReading, TimestampSource,
+};
 use soundsense_backend::domain::store::AppState;
+use soundsense_backend::fhir::FhirObservation;
+use soundsense_backend::ingest_queue::IngestQueue;
+use soundsense_backend::iot_auth::IotAuthStore;
+use soundsense_backend::load_shed::IngestLimiter;
+use soundsense_backend::ml_client::{MlAuth, MlClient};
+use soundsense_backend::oidc::{parse_role_mapping, OidcConfig, OidcValidator};
 use soundsense_backend::routes;
+use soundsense_backend::security::DEFAULT_DEVICE_TOKEN_SECRET;
+use soundsense_backend::serial_ingest::{SerialLink, SerialStatus};
+use soundsense_backend::sessions::SessionStore;
+use soundsense_backend::snapshot::SnapshotManager;
+use soundsense_backend::stream_ingest::StreamIngestConfig;
+use soundsense_backend::timeout::RequestTimeout;
+use soundsense_backend::training::TrainingJobStore;
+use soundsense_backend::validation::{PatientRegistry, RuleConfig, Severity, ValidationPipeline};
+use soundsense_backend::ws::{Backpressure, WsHub};
 
-/// Helper function to generate JWT token for testing
-fn generate_test_token(role: &str) -> String {
-    let jwt_manager = JwtManager::new("test-secret-key".to_string());
-    let claims = Claims::new(
-        "test-user".to_string(),
-        role.to_string(),
-        None,
-        24, // 24 hours
-    );
-    jwt_manager.generate_token(claims).unwrap()
-}
+mod common;
+use common::{TestApp, WsClient};
 
 #[actix_web::test]
 async fn healthz_works() {
-    let state = web::Data::new(Arc::new(Mutex::new(AppState::new_demo())));
-    let app = test::init_service(App::new().app_data(state).configure(routes::configure)).await;
+    let app = TestApp::new().service().await;
 
     let req = test::TestRequest::get().uri("/healthz").to_request();
     let resp = test::call_service(&app, req).await;
@@ -30,13 +46,207 @@ async fn healthz_works() {
 }
 
 #[actix_web::test]
-async fn ingest_and_query_bundle() {
-    std::env::set_var("JWT_SECRET", "test-secret-key");
+async fn readyz_reports_each_check_and_fails_on_any() {
+    let harness = TestApp::new();
+    let app = harness.service().await;
+
+    let req = test::TestRequest::get().uri("/readyz").to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 200);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["ok"], true);
+    let status = |name: &str| {
+        body["checks"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|c| c["name"] == name)
+            .map(|c| c["status"].clone())
+    };
+    assert_eq!(status("database"), Some("skipped".into()));
+    assert_eq!(status("ml_service"), Some("skipped".into()));
+    assert_eq!(status("jwt"), Some("ok".into()));
+    assert_eq!(status("export_dir"), Some("ok".into()));
+    // Held open by the serial reader, so not tried
+    assert_eq!(status("serial_port"), None);
+    // Paths and errors are only shown with a token
+    assert!(body["checks"][0].get("detail").is_none());
+    let req = test::TestRequest::get()
+        .uri("/readyz")
+        .insert_header(("authorization", format!("Bearer {}", harness.token("user"))))
+        .to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    let export_dir = body["checks"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|c| c["name"] == "export_dir")
+        .unwrap();
+    assert!(export_dir["detail"]
+        .as_str()
+        .unwrap()
+        .contains("/soundsense-exports"));
+    let req = test::TestRequest::get()
+        .uri("/readyz")
+        .insert_header(("authorization", "Bearer not-a-jwt"))
+        .to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert!(body["checks"][0].get("detail").is_none());
+
+    // A dead-letter queue whose directory is gone can't take readings
+    let path = std::env::temp_dir()
+        .join(format!("soundsense-gone-{}", uuid::Uuid::new_v4()))
+        .join("dlq.jsonl");
+    let dlq = Arc::new(DeadLetterQueue::open(&path, 10).unwrap());
+    let state = web::Data::new(Arc::new(Mutex::new(common::demo_state().with_dlq(dlq))));
+    let app = test::init_service(App::new().app_data(state).configure(common::routes)).await;
+
+    let req = test::TestRequest::get().uri("/readyz").to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 503);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["ok"], false);
+    let dlq_dir = body["checks"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|c| c["name"] == "dlq_dir")
+        .unwrap();
+    assert_eq!(dlq_dir["status"], "failed");
+}
+
+#[actix_web::test]
+async fn healthz_reports_write_ahead_buffer_depth() {
+    let path = std::env::temp_dir().join(format!("soundsense-wal-{}.jsonl", uuid::Uuid::new_v4()));
+    let dlq = Arc::new(DeadLetterQueue::open(&path, 10).unwrap());
+    let state = web::Data::new(Arc::new(Mutex::new(
+        common::demo_state().with_dlq(dlq.clone()),
+    )));
+    let app = test::init_service(App::new().app_data(state).configure(common::routes)).await;
+
+    let reading = SensorReading {
+        patient_id: "p1".into(),
+        device_id: "d1".into(),
+        code: SignalCode::Sound,
+        value: 60.0,
+        unit: "dB".into(),
+        ts: chrono::Utc::now(),
+        ts_source: TimestampSource::Device,
+        metadata: Default::default(),
+    };
+    dlq.enqueue(&reading).await.unwrap();
+    dlq.enqueue(&reading).await.unwrap();
+
+    let req = test::TestRequest::get().uri("/healthz").to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(body["write_ahead_buffer"]["pending"], 2);
+
+    std::fs::remove_file(path).ok();
+}
+
+#[actix_web::test]
+async fn version_reports_the_build() {
+    let app = TestApp::new().service().await;
+
+    let req = test::TestRequest::get().uri("/version").to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 200);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["version"], env!("CARGO_PKG_VERSION"));
+    assert!(body["git_commit"].is_string());
+    assert!(body["built_at"].is_string());
+    assert_eq!(body["fhir_version"], "4.0.1");
+    assert_eq!(body["database_configured"], false);
+    assert_eq!(body["ml_configured"], false);
+}
+
+#[actix_web::test]
+async fn signal_codes_are_published_as_a_value_set() {
+    let app = TestApp::new().service().await;
+    let get = |uri: &str| test::TestRequest::get().uri(uri).to_request();
+
+    // No token needed
+    let resp = test::call_service(&app, get("/fhir/ValueSet/soundsense-signal-codes")).await;
+    assert_eq!(resp.status(), 200);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["resourceType"], "ValueSet");
+    assert_eq!(body["id"], "soundsense-signal-codes");
+    assert_eq!(body["status"], "active");
+    assert_eq!(body["compose"]["include"][0]["system"], "http://loinc.org");
+    assert_eq!(body["compose"]["include"][0]["concept"][0]["code"], "sound");
+
+    let validate = |query: &str| {
+        get(&format!(
+            "/fhir/ValueSet/soundsense-signal-codes/$validate-code?{}",
+            query
+        ))
+    };
+    for (query, result) in [
+        ("code=sound&system=http://loinc.org", true),
+        ("code=sound", true),
+        ("code=noise&system=http://loinc.org", false),
+        ("code=sound&system=http://snomed.info/sct", false),
+    ] {
+        let body: serde_json::Value = test::call_and_read_body_json(&app, validate(query)).await;
+        assert_eq!(body, serde_json::json!({ "result": result }), "{}", query);
+    }
+    let resp = test::call_service(&app, validate("system=http://loinc.org")).await;
+    assert_eq!(resp.status(), 400);
+}
 
-    let state = web::Data::new(Arc::new(Mutex::new(AppState::new_demo())));
-    let app = test::init_service(App::new().app_data(state).configure(routes::configure)).await;
+#[actix_web::test]
+async fn serial_link_status_is_served_to_admins() {
+    let status = Arc::new(std::sync::RwLock::new(SerialStatus::new("/dev/ttyACM0")));
+    let mut link = SerialLink::new(status.clone());
+    link.read(
+        std::io::Cursor::new(&b"SOUND:512\nboot banner\nSOUND:600\n"[..]),
+        |_, _| Ok(()),
+    );
+
+    let state = web::Data::new(Arc::new(Mutex::new(common::demo_state())));
+    let app = test::init_service(
+        App::new()
+            .app_data(state.clone())
+            .app_data(web::Data::from(status))
+            .configure(common::routes),
+    )
+    .await;
+    let get = |role: &str| {
+        test::TestRequest::get()
+            .uri("/api/serial/status")
+            .insert_header(("authorization", format!("Bearer {}", common::token(role))))
+            .to_request()
+    };
+
+    let body: serde_json::Value = test::call_and_read_body_json(&app, get("admin")).await;
+    assert_eq!(body["port"], "/dev/ttyACM0");
+    assert_eq!(body["connected"], false);
+    assert_eq!(body["lines_parsed"], 2);
+    assert_eq!(body["lines_rejected"], 1);
+    assert_eq!(body["retry_queue"]["queued_count"], 0);
+    assert_eq!(body["reconnect_count"], 0);
+    assert_eq!(body["last_error"], "serial port closed");
+    assert!(body["last_reading_at"].is_string());
+    assert_eq!(test::call_service(&app, get("user")).await.status(), 401);
+
+    let req = test::TestRequest::get().uri("/healthz").to_request();
+    let health: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(health["serial"]["port"], "/dev/ttyACM0");
+    assert_eq!(health["serial"]["connected"], false);
+
+    // No serial port configured
+    let app = test::init_service(App::new().app_data(state).configure(common::routes)).await;
+    assert_eq!(test::call_service(&app, get("admin")).await.status(), 503);
+    let req = test::TestRequest::get().uri("/healthz").to_request();
+    let health: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert!(health.get("serial").is_none());
+}
+
+#[actix_web::test]
+async fn ingest_and_query_bundle() {
+    let app = TestApp::new().service().await;
 
-    let token = generate_test_token("user");
+    let token = common::token("user");
 
     let reading = SensorReading {
         patient_id: "p1".into(),
@@ -45,6 +255,8 @@ async fn ingest_and_query_bundle() {
         value: 200.0,
         unit: "raw".into(),
         ts: chrono::Utc::now(),
+        ts_source: TimestampSource::Device,
+        metadata: Default::default(),
     };
 
     let req = test::TestRequest::post()
@@ -55,6 +267,13 @@ async fn ingest_and_query_bundle() {
     let resp = test::call_service(&app, req).await;
     assert!(resp.status().is_success());
 
+    // Without a database the reading is only kept in memory
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["receipt"]["id"], body["id"]);
+    assert_eq!(body["receipt"]["persisted"], false);
+    assert_eq!(body["receipt"]["storage"], "memory");
+    assert!(body["receipt"]["received_at"].is_string());
+
     let req = test::TestRequest::get()
         .uri("/api/fhir/Observation?code=sound&limit=10")
         .insert_header(("authorization", format!("Bearer {}", token)))
@@ -69,12 +288,8 @@ async fn ingest_and_query_bundle() {
 
 #[actix_web::test]
 async fn ingest_rejects_empty_patient_id() {
-    std::env::set_var("JWT_SECRET", "test-secret-key");
-
-    let state = web::Data::new(Arc::new(Mutex::new(AppState::new_demo())));
-    let app = test::init_service(App::new().app_data(state).configure(routes::configure)).await;
-
-    let token = generate_test_token("user");
+    let harness = TestApp::new();
+    let app = harness.service().await;
 
     let reading = SensorReading {
         patient_id: "".into(),
@@ -83,25 +298,19 @@ async fn ingest_rejects_empty_patient_id() {
         value: 200.0,
         unit: "raw".into(),
         ts: chrono::Utc::now(),
+        ts_source: TimestampSource::Device,
+        metadata: Default::default(),
     };
 
-    let req = test::TestRequest::post()
-        .uri("/api/ingest")
-        .insert_header(("authorization", format!("Bearer {}", token)))
-        .set_json(&reading)
-        .to_request();
+    let req = harness.ingest(&reading);
     let resp = test::call_service(&app, req).await;
     assert_eq!(resp.status(), 400);
 }
 
 #[actix_web::test]
 async fn ingest_rejects_empty_device_id() {
-    std::env::set_var("JWT_SECRET", "test-secret-key");
-
-    let state = web::Data::new(Arc::new(Mutex::new(AppState::new_demo())));
-    let app = test::init_service(App::new().app_data(state).configure(routes::configure)).await;
-
-    let token = generate_test_token("user");
+    let harness = TestApp::new();
+    let app = harness.service().await;
 
     let reading = SensorReading {
         patient_id: "p1".into(),
@@ -110,25 +319,19 @@ async fn ingest_rejects_empty_device_id() {
         value: 200.0,
         unit: "raw".into(),
         ts: chrono::Utc::now(),
+        ts_source: TimestampSource::Device,
+        metadata: Default::default(),
     };
 
-    let req = test::TestRequest::post()
-        .uri("/api/ingest")
-        .insert_header(("authorization", format!("Bearer {}", token)))
-        .set_json(&reading)
-        .to_request();
+    let req = harness.ingest(&reading);
     let resp = test::call_service(&app, req).await;
     assert_eq!(resp.status(), 400);
 }
 
 #[actix_web::test]
 async fn ingest_rejects_nan_value() {
-    std::env::set_var("JWT_SECRET", "test-secret-key");
-
-    let state = web::Data::new(Arc::new(Mutex::new(AppState::new_demo())));
-    let app = test::init_service(App::new().app_data(state).configure(routes::configure)).await;
-
-    let token = generate_test_token("user");
+    let harness = TestApp::new();
+    let app = harness.service().await;
 
     let reading = SensorReading {
         patient_id: "p1".into(),
@@ -137,25 +340,19 @@ async fn ingest_rejects_nan_value() {
         value: f64::NAN,
         unit: "raw".into(),
         ts: chrono::Utc::now(),
+        ts_source: TimestampSource::Device,
+        metadata: Default::default(),
     };
 
-    let req = test::TestRequest::post()
-        .uri("/api/ingest")
-        .insert_header(("authorization", format!("Bearer {}", token)))
-        .set_json(&reading)
-        .to_request();
+    let req = harness.ingest(&reading);
     let resp = test::call_service(&app, req).await;
     assert_eq!(resp.status(), 400);
 }
 
 #[actix_web::test]
 async fn ingest_rejects_infinity_value() {
-    std::env::set_var("JWT_SECRET", "test-secret-key");
-
-    let state = web::Data::new(Arc::new(Mutex::new(AppState::new_demo())));
-    let app = test::init_service(App::new().app_data(state).configure(routes::configure)).await;
-
-    let token = generate_test_token("user");
+    let harness = TestApp::new();
+    let app = harness.service().await;
 
     let reading = SensorReading {
         patient_id: "p1".into(),
@@ -164,23 +361,18 @@ async fn ingest_rejects_infinity_value() {
         value: f64::INFINITY,
         unit: "raw".into(),
         ts: chrono::Utc::now(),
+        ts_source: TimestampSource::Device,
+        metadata: Default::default(),
     };
 
-    let req = test::TestRequest::post()
-        .uri("/api/ingest")
-        .insert_header(("authorization", format!("Bearer {}", token)))
-        .set_json(&reading)
-        .to_request();
+    let req = harness.ingest(&reading);
     let resp = test::call_service(&app, req).await;
     assert_eq!(resp.status(), 400);
 }
 
 #[actix_web::test]
 async fn ingest_requires_auth_when_token_set() {
-    std::env::set_var("JWT_SECRET", "test-secret-key");
-
-    let state = web::Data::new(Arc::new(Mutex::new(AppState::new_demo())));
-    let app = test::init_service(App::new().app_data(state).configure(routes::configure)).await;
+    let app = TestApp::new().service().await;
 
     let reading = SensorReading {
         patient_id: "p1".into(),
@@ -189,6 +381,8 @@ async fn ingest_requires_auth_when_token_set() {
         value: 200.0,
         unit: "raw".into(),
         ts: chrono::Utc::now(),
+        ts_source: TimestampSource::Device,
+        metadata: Default::default(),
     };
 
     // Request without token should fail
@@ -201,13 +395,157 @@ async fn ingest_requires_auth_when_token_set() {
 }
 
 #[actix_web::test]
-async fn ingest_accepts_valid_token() {
-    std::env::set_var("JWT_SECRET", "test-secret-key");
+async fn public_and_authenticated_ingest_share_one_code_path() {
+    let pipeline = ValidationPipeline::from_configs(
+        &[RuleConfig {
+            rule_name: "unit_whitelist".into(),
+            config: serde_json::json!({"units": ["dB"]}),
+            severity: Severity::Warning,
+        }],
+        PatientRegistry::default(),
+    )
+    .unwrap();
+    let state = web::Data::new(Arc::new(Mutex::new(
+        common::demo_state()
+            .with_consent_mode(ConsentMode::Strict)
+            .with_validation(pipeline),
+    )));
+    let app = test::init_service(App::new().app_data(state).configure(common::routes)).await;
+    let token = format!("Bearer {}", common::token("device"));
+    for req in consent_registration() {
+        assert_eq!(
+            test::call_service(&app, req.to_request()).await.status(),
+            200
+        );
+    }
+
+    let post = |uri: &str, reading: serde_json::Value| {
+        let mut req = test::TestRequest::post().uri(uri).set_json(reading);
+        if uri.starts_with("/api") {
+            req = req.insert_header(("authorization", token.clone()));
+        }
+        req.to_request()
+    };
+
+    for uri in ["/ingest", "/api/ingest"] {
+        // Same validation, warnings and consent check on both routes
+        let resp = test::call_service(&app, post(uri, consent_reading("p-granted"))).await;
+        assert_eq!(resp.status(), 200, "{}", uri);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["resourceType"], "Observation");
+        assert_eq!(body["warnings"].as_array().unwrap().len(), 1, "{}", uri);
+
+        let resp = test::call_service(&app, post(uri, consent_reading("p-revoked"))).await;
+        assert_eq!(resp.status(), 403, "{}", uri);
+
+        let mut invalid = consent_reading("p-granted");
+        invalid["device_id"] = serde_json::json!("");
+        let resp = test::call_service(&app, post(uri, invalid)).await;
+        assert_eq!(resp.status(), 400, "{}", uri);
+    }
+
+    // Both routes count against the device
+    let req = test::TestRequest::get()
+        .uri("/api/devices/wearable-1/stats")
+        .insert_header((
+            "authorization",
+            format!("Bearer {}", common::token("admin")),
+        ))
+        .to_request();
+    let stats: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(stats["accepted"], 2);
+    assert_eq!(stats["rejected"], 2);
+}
+
+#[actix_web::test]
+async fn public_ingest_can_be_turned_off() {
+    for (mode, public_status) in [
+        (routes::PublicIngest::Disabled, 404),
+        (routes::PublicIngest::Demo, 200),
+    ] {
+        let app = TestApp::new().public_ingest(mode).service().await;
+
+        let req = test::TestRequest::post()
+            .uri("/ingest")
+            .set_json(consent_reading("p1"))
+            .to_request();
+        assert_eq!(
+            test::call_service(&app, req).await.status(),
+            public_status,
+            "{:?}",
+            mode
+        );
+
+        // The authenticated route is there either way
+        let req = test::TestRequest::post()
+            .uri("/api/ingest")
+            .insert_header((
+                "authorization",
+                format!("Bearer {}", common::token("device")),
+            ))
+            .set_json(consent_reading("p1"))
+            .to_request();
+        assert_eq!(
+            test::call_service(&app, req).await.status(),
+            200,
+            "{:?}",
+            mode
+        );
+    }
+}
+
+#[actix_web::test]
+async fn public_ingest_setting_is_parsed_strictly() {
+    assert_eq!(
+        routes::PublicIngest::parse("disabled"),
+        Some(routes::PublicIngest::Disabled)
+    );
+    assert_eq!(
+        routes::PublicIngest::parse(" Demo "),
+        Some(routes::PublicIngest::Demo)
+    );
+    assert_eq!(routes::PublicIngest::parse("off"), None);
+    assert_eq!(routes::PublicIngest::parse(""), None);
+}
+
+#[actix_web::test]
+async fn whoami_returns_the_token_claims() {
+    let app = TestApp::new().service().await;
+
+    let jwt_manager = JwtManager::new(JwtAlgorithm::HS256(common::TEST_JWT_SECRET.to_string()));
+    let claims = Claims::new("sensor-7".into(), "device".into(), Some("d7".into()), 1);
+    let token = jwt_manager.generate_token(claims.clone()).unwrap();
+
+    let req = test::TestRequest::get()
+        .uri("/auth/whoami")
+        .insert_header(("authorization", format!("Bearer {}", token)))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 200);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["sub"], "sensor-7");
+    assert_eq!(body["role"], "device");
+    assert_eq!(body["device_id"], "d7");
+    assert_eq!(body["exp"], claims.exp);
+    assert!(!body.to_string().contains(&token));
+
+    let req = test::TestRequest::get().uri("/auth/whoami").to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 401);
+
+    let req = test::TestRequest::get()
+        .uri("/auth/whoami")
+        .insert_header(("authorization", "Bearer not-a-token"))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 401);
+}
 
-    let state = web::Data::new(Arc::new(Mutex::new(AppState::new_demo())));
-    let app = test::init_service(App::new().app_data(state).configure(routes::configure)).await;
+#[actix_web::test]
+async fn ingest_accepts_valid_token() {
+    let app = TestApp::new().service().await;
 
-    let token = generate_test_token("user");
+    let token = common::token("user");
 
     let reading = SensorReading {
         patient_id: "p1".into(),
@@ -216,6 +554,8 @@ async fn ingest_accepts_valid_token() {
         value: 200.0,
         unit: "raw".into(),
         ts: chrono::Utc::now(),
+        ts_source: TimestampSource::Device,
+        metadata: Default::default(),
     };
 
     // Request with correct JWT token should succeed
@@ -229,13 +569,105 @@ async fn ingest_accepts_valid_token() {
 }
 
 #[actix_web::test]
-async fn query_with_code_filter() {
-    std::env::set_var("JWT_SECRET", "test-secret-key");
+async fn json_endpoints_refuse_other_media_types() {
+    let app = TestApp::new().service().await;
+    let auth = format!("Bearer {}", common::token("user"));
+    let body = serde_json::json!({
+        "patient_id": "p1",
+        "device_id": "d1",
+        "code": "sound",
+        "value": 62.0,
+        "unit": "dB",
+    })
+    .to_string();
+    let ingest = |content_type: Option<&str>, accept: Option<&str>| {
+        let mut req = test::TestRequest::post()
+            .uri("/api/ingest")
+            .insert_header(("authorization", auth.clone()))
+            .set_payload(body.clone());
+        if let Some(content_type) = content_type {
+            req = req.insert_header(("content-type", content_type));
+        }
+        if let Some(accept) = accept {
+            req = req.insert_header(("accept", accept));
+        }
+        req.to_request()
+    };
+
+    for (content_type, accept, status) in [
+        (Some("application/json"), None, 200),
+        (Some("application/json; charset=utf-8"), Some("*/*"), 200),
+        (
+            Some("application/fhir+json"),
+            Some("application/fhir+json"),
+            200,
+        ),
+        (None, None, 415),
+        (Some("text/plain"), None, 415),
+        (Some("application/x-www-form-urlencoded"), None, 415),
+        (Some("application/json"), Some("text/html"), 406),
+        (
+            Some("application/json"),
+            Some("application/json;q=0, text/csv"),
+            406,
+        ),
+    ] {
+        let resp = test::call_service(&app, ingest(content_type, accept)).await;
+        assert_eq!(resp.status(), status, "{:?} {:?}", content_type, accept);
+        if status != 200 {
+            let body: serde_json::Value = test::read_body_json(resp).await;
+            let code = if status == 415 {
+                "unsupported_media_type"
+            } else {
+                "not_acceptable"
+            };
+            assert_eq!(body["code"], code);
+        }
+    }
+
+    // Public JSON routes too, once the token check has passed on /api
+    let req = test::TestRequest::post()
+        .uri("/auth/login")
+        .insert_header(("content-type", "text/plain"))
+        .set_payload(r#"{"username": "admin", "password": "x"}"#)
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 415);
+    let req = test::TestRequest::post()
+        .uri("/api/ingest")
+        .insert_header(("content-type", "text/plain"))
+        .set_payload(body.clone())
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 401);
+
+    // Bodiless requests, and the routes that serve other types, pass
+    let req = test::TestRequest::post()
+        .uri("/api/admin/snapshot")
+        .insert_header((
+            "authorization",
+            format!("Bearer {}", common::token("admin")),
+        ))
+        .to_request();
+    // Not configured, as far as the handler is concerned
+    assert_eq!(test::call_service(&app, req).await.status(), 503);
+    let req = test::TestRequest::get()
+        .uri("/api/reports/patient-summary/p1.pdf")
+        .insert_header(("authorization", auth.clone()))
+        .insert_header(("accept", "application/pdf"))
+        .to_request();
+    assert_ne!(test::call_service(&app, req).await.status(), 406);
+    let req = test::TestRequest::get()
+        .uri("/api/reports/patient-summary/p1")
+        .insert_header(("authorization", auth))
+        .insert_header(("accept", "application/pdf"))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 406);
+}
 
-    let state = web::Data::new(Arc::new(Mutex::new(AppState::new_demo())));
-    let app = test::init_service(App::new().app_data(state).configure(routes::configure)).await;
+#[actix_web::test]
+async fn query_with_code_filter() {
+    let app = TestApp::new().service().await;
 
-    let token = generate_test_token("user");
+    let token = common::token("user");
 
     let reading = SensorReading {
         patient_id: "p1".into(),
@@ -244,6 +676,8 @@ async fn query_with_code_filter() {
         value: 200.0,
         unit: "raw".into(),
         ts: chrono::Utc::now(),
+        ts_source: TimestampSource::Device,
+        metadata: Default::default(),
     };
 
     let req = test::TestRequest::post()
@@ -263,16 +697,26 @@ async fn query_with_code_filter() {
     let body: serde_json::Value = test::read_body_json(resp).await;
     assert_eq!(body["resourceType"], "Bundle");
     assert_eq!(body["total"], 1);
+
+    // Aliases accepted on ingest filter the same; unknown codes are refused
+    let get = |uri: &str| {
+        test::TestRequest::get()
+            .uri(uri)
+            .insert_header(("authorization", format!("Bearer {}", token)))
+            .to_request()
+    };
+    let body: serde_json::Value =
+        test::call_and_read_body_json(&app, get("/api/fhir/Observation?code=SoundLevel")).await;
+    assert_eq!(body["total"], 1);
+    let resp = test::call_service(&app, get("/api/fhir/Observation?code=heart_rate")).await;
+    assert_eq!(resp.status(), 400);
 }
 
 #[actix_web::test]
 async fn query_with_limit() {
-    std::env::set_var("JWT_SECRET", "test-secret-key");
-
-    let state = web::Data::new(Arc::new(Mutex::new(AppState::new_demo())));
-    let app = test::init_service(App::new().app_data(state).configure(routes::configure)).await;
+    let app = TestApp::new().service().await;
 
-    let token = generate_test_token("user");
+    let token = common::token("user");
 
     // Insert multiple readings
     for i in 0..5 {
@@ -283,6 +727,8 @@ async fn query_with_limit() {
             value: 200.0 + i as f64,
             unit: "raw".into(),
             ts: chrono::Utc::now(),
+            ts_source: TimestampSource::Device,
+            metadata: Default::default(),
         };
 
         let req = test::TestRequest::post()
@@ -304,3 +750,6533 @@ async fn query_with_limit() {
     assert_eq!(body["resourceType"], "Bundle");
     assert_eq!(body["total"], 2);
 }
+
+#[actix_web::test]
+async fn observation_pages_carry_total_count_and_links() {
+    let app = TestApp::new().service().await;
+    let auth = format!("Bearer {}", common::token("user"));
+
+    for i in 0..5 {
+        let req = test::TestRequest::post()
+            .uri("/api/ingest")
+            .insert_header(("authorization", auth.clone()))
+            .set_json(serde_json::json!({
+                "patient_id": format!("p{}", i),
+                "device_id": "d1",
+                "code": "sound",
+                "value": 200.0 + i as f64,
+                "unit": "raw",
+            }))
+            .to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+    }
+
+    let page = |uri: &str| {
+        test::TestRequest::get()
+            .uri(uri)
+            .insert_header(("authorization", auth.clone()))
+            .to_request()
+    };
+    // Target of the link with relation `rel`, as a path and query
+    let link = |header: Option<&str>, rel: &str| -> Option<String> {
+        header?
+            .split(", ")
+            .find(|l| l.ends_with(&format!("rel=\"{}\"", rel)))
+            .map(|l| {
+                let url = &l[1..l.find('>').unwrap()];
+                url[url.find("/api").unwrap()..].to_string()
+            })
+    };
+
+    let resp = test::call_service(&app, page("/api/fhir/Observation?code=sound&limit=2")).await;
+    assert_eq!(resp.headers().get("x-total-count").unwrap(), "5");
+    let links = resp
+        .headers()
+        .get("link")
+        .map(|v| v.to_str().unwrap().to_string());
+    let next = link(links.as_deref(), "next").unwrap();
+    assert_eq!(next, "/api/fhir/Observation?code=sound&limit=2&offset=2");
+    assert!(link(links.as_deref(), "prev").is_none());
+    let first: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(first["total"], 2);
+
+    // The second page links both ways and holds different observations
+    let resp = test::call_service(&app, page(&next)).await;
+    let links = resp
+        .headers()
+        .get("link")
+        .map(|v| v.to_str().unwrap().to_string());
+    assert_eq!(
+        link(links.as_deref(), "next").unwrap(),
+        "/api/fhir/Observation?code=sound&limit=2&offset=4"
+    );
+    assert_eq!(
+        link(links.as_deref(), "prev").unwrap(),
+        "/api/fhir/Observation?code=sound&limit=2&offset=0"
+    );
+    let second: serde_json::Value = test::read_body_json(resp).await;
+    assert_ne!(
+        second["entry"][0]["resource"]["id"],
+        first["entry"][0]["resource"]["id"]
+    );
+
+    // The last page has no next link
+    let resp = test::call_service(
+        &app,
+        page("/api/fhir/Observation?code=sound&limit=2&offset=4"),
+    )
+    .await;
+    let links = resp
+        .headers()
+        .get("link")
+        .map(|v| v.to_str().unwrap().to_string());
+    assert!(link(links.as_deref(), "next").is_none());
+    let last: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(last["total"], 1);
+
+    // Everything on one page: count but no links
+    let resp = test::call_service(&app, page("/api/fhir/Observation")).await;
+    assert_eq!(resp.headers().get("x-total-count").unwrap(), "5");
+    assert!(resp.headers().get("link").is_none());
+
+    // Far past the end there is only a way back
+    let resp = test::call_service(
+        &app,
+        page("/api/fhir/Observation?code=sound&limit=2&offset=9223372036854775807"),
+    )
+    .await;
+    assert_eq!(resp.status(), 200);
+    let links = resp
+        .headers()
+        .get("link")
+        .map(|v| v.to_str().unwrap().to_string());
+    assert!(link(links.as_deref(), "next").is_none());
+    assert_eq!(
+        link(links.as_deref(), "prev").unwrap(),
+        "/api/fhir/Observation?code=sound&limit=2&offset=3"
+    );
+    for offset in ["-1", "18446744073709551615"] {
+        let uri = format!("/api/fhir/Observation?limit=2&offset={}", offset);
+        let resp = test::call_service(&app, page(&uri)).await;
+        assert_eq!(resp.status(), 400, "offset {}", offset);
+    }
+}
+
+#[actix_web::test]
+async fn observations_are_categorized_and_filterable_by_category() {
+    let app = TestApp::new().service().await;
+    let auth = format!("Bearer {}", common::token("user"));
+
+    let ingest = |metadata: serde_json::Value| {
+        test::TestRequest::post()
+            .uri("/api/ingest")
+            .insert_header(("authorization", auth.clone()))
+            .set_json(serde_json::json!({
+                "patient_id": "p1",
+                "device_id": "d1",
+                "code": "sound",
+                "value": 200.0,
+                "unit": "raw",
+                "metadata": metadata,
+            }))
+            .to_request()
+    };
+    for metadata in [
+        serde_json::json!(null),
+        serde_json::json!({}),
+        serde_json::json!({ "fhir_category": "survey" }),
+    ] {
+        assert!(test::call_service(&app, ingest(metadata))
+            .await
+            .status()
+            .is_success());
+    }
+    let resp = test::call_service(
+        &app,
+        ingest(serde_json::json!({ "fhir_category": "research" })),
+    )
+    .await;
+    assert_eq!(resp.status(), 400);
+
+    let observations = |query: &str| {
+        test::TestRequest::get()
+            .uri(&format!("/api/fhir/Observation{}", query))
+            .insert_header(("authorization", auth.clone()))
+            .to_request()
+    };
+    let categories = |bundle: &serde_json::Value| -> Vec<String> {
+        bundle["entry"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|e| {
+                let coding = &e["resource"]["category"][0]["coding"][0];
+                assert_eq!(
+                    coding["system"],
+                    "http://terminology.hl7.org/CodeSystem/observation-category"
+                );
+                coding["code"].as_str().unwrap().to_string()
+            })
+            .collect()
+    };
+
+    // Sound readings default to activity
+    let all: serde_json::Value = test::call_and_read_body_json(&app, observations("")).await;
+    assert_eq!(categories(&all), ["survey", "activity", "activity"]);
+
+    let activity: serde_json::Value =
+        test::call_and_read_body_json(&app, observations("?category=activity")).await;
+    assert_eq!(categories(&activity), ["activity", "activity"]);
+
+    let survey: serde_json::Value = test::call_and_read_body_json(
+        &app,
+        observations(
+            "?category=http://terminology.hl7.org/CodeSystem/observation-category%7Csurvey",
+        ),
+    )
+    .await;
+    assert_eq!(categories(&survey), ["survey"]);
+
+    let resp = test::call_service(&app, observations("?category=exam")).await;
+    assert_eq!(resp.headers().get("x-total-count").unwrap(), "0");
+}
+
+#[actix_web::test]
+async fn include_subject_appends_patients_outside_the_total() {
+    let app = TestApp::new().service().await;
+    let admin = format!("Bearer {}", common::token("admin"));
+
+    let req = test::TestRequest::put()
+        .uri("/api/patients/p1/consent")
+        .insert_header(("authorization", admin.clone()))
+        .set_json(serde_json::json!({ "status": "granted" }))
+        .to_request();
+    assert!(test::call_service(&app, req).await.status().is_success());
+
+    // Two readings of registered p1, one of p2 which is not in the registry
+    for patient_id in ["p1", "p1", "p2"] {
+        let req = test::TestRequest::post()
+            .uri("/api/ingest")
+            .insert_header(("authorization", admin.clone()))
+            .set_json(consent_reading(patient_id))
+            .to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+    }
+
+    let req = test::TestRequest::get()
+        .uri("/api/fhir/Observation?_include=Observation:subject")
+        .insert_header(("authorization", admin.clone()))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.headers().get("X-Total-Count").unwrap(), "3");
+    let bundle: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(bundle["total"], 3);
+
+    let entries = bundle["entry"].as_array().unwrap();
+    assert_eq!(entries.len(), 4);
+    let included: Vec<_> = entries
+        .iter()
+        .filter(|e| e["search"]["mode"] == "include")
+        .collect();
+    assert_eq!(included.len(), 1);
+    assert_eq!(included[0]["resource"]["resourceType"], "Patient");
+    assert_eq!(included[0]["resource"]["id"], "p1");
+
+    // Without _include only observations come back
+    let req = test::TestRequest::get()
+        .uri("/api/fhir/Observation")
+        .insert_header(("authorization", admin.clone()))
+        .to_request();
+    let bundle: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(bundle["entry"].as_array().unwrap().len(), 3);
+
+    let req = test::TestRequest::get()
+        .uri("/api/fhir/Observation?_include=Observation:performer")
+        .insert_header(("authorization", admin))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 400);
+}
+
+#[actix_web::test]
+async fn include_device_appends_registered_devices_and_references_resolve() {
+    let app = TestApp::new().service().await;
+    let admin = format!("Bearer {}", common::token("admin"));
+
+    let req = test::TestRequest::put()
+        .uri("/api/patients/p1/consent")
+        .insert_header(("authorization", admin.clone()))
+        .set_json(serde_json::json!({ "status": "granted" }))
+        .to_request();
+    assert!(test::call_service(&app, req).await.status().is_success());
+    // wearable-1 is registered through its settings, wearable-2 never is
+    let req = test::TestRequest::put()
+        .uri("/api/devices/wearable-1")
+        .insert_header(("authorization", admin.clone()))
+        .set_json(serde_json::json!({ "status": "decommissioned" }))
+        .to_request();
+    assert!(test::call_service(&app, req).await.status().is_success());
+
+    for device_id in ["wearable-1", "wearable-2", "wearable-1"] {
+        let mut reading = consent_reading("p1");
+        reading["device_id"] = device_id.into();
+        let req = test::TestRequest::post()
+            .uri("/api/ingest")
+            .insert_header(("authorization", admin.clone()))
+            .set_json(reading)
+            .to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+    }
+
+    let req = test::TestRequest::get()
+        .uri("/api/fhir/Observation?_include=Observation:device&_include=Observation:subject")
+        .insert_header(("authorization", admin.clone()))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 200);
+    let bundle: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(bundle["total"], 3);
+
+    let entries = bundle["entry"].as_array().unwrap();
+    let (matched, included): (Vec<_>, Vec<_>) = entries
+        .iter()
+        .partition(|e| e["search"]["mode"] != "include");
+    assert_eq!(matched.len(), 3);
+    for entry in &matched {
+        let device = entry["resource"]["device"]["reference"].as_str().unwrap();
+        assert!(device.starts_with("Device/wearable-"), "{}", device);
+    }
+    let included: Vec<_> = included
+        .iter()
+        .map(|e| {
+            (
+                e["resource"]["resourceType"].as_str().unwrap(),
+                e["resource"]["id"].as_str().unwrap(),
+            )
+        })
+        .collect();
+    assert_eq!(included, [("Patient", "p1"), ("Device", "wearable-1")]);
+    let device = entries.last().unwrap();
+    assert_eq!(device["resource"]["status"], "inactive");
+
+    // The references resolve, except to what was never registered
+    for (uri, status) in [
+        ("/api/fhir/Patient/p1", 200),
+        ("/api/fhir/Patient/p2", 404),
+        ("/api/fhir/Device/wearable-1", 200),
+        ("/api/fhir/Device/wearable-2", 404),
+    ] {
+        let req = test::TestRequest::get()
+            .uri(uri)
+            .insert_header(("authorization", admin.clone()))
+            .to_request();
+        assert_eq!(
+            test::call_service(&app, req).await.status(),
+            status,
+            "{}",
+            uri
+        );
+    }
+    let req = test::TestRequest::get()
+        .uri("/api/fhir/Device/wearable-1")
+        .insert_header(("authorization", admin))
+        .to_request();
+    let device: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(
+        device,
+        serde_json::json!({ "resourceType": "Device", "id": "wearable-1", "status": "inactive" })
+    );
+
+    // Users only read the patients assigned to them
+    let restricted = JwtManager::new(JwtAlgorithm::HS256(common::TEST_JWT_SECRET.to_string()))
+        .generate_token(
+            Claims::new("u1".into(), "user".into(), None, 1).with_permitted_patients(vec![]),
+        )
+        .unwrap();
+    let req = test::TestRequest::get()
+        .uri("/api/fhir/Patient/p1")
+        .insert_header(("authorization", format!("Bearer {}", restricted)))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 403);
+}
+
+#[actix_web::test]
+async fn validation_rules_refuse_or_warn_by_severity() {
+    let rule = |rule_name: &str, config: serde_json::Value, severity| RuleConfig {
+        rule_name: rule_name.into(),
+        config,
+        severity,
+    };
+    let pipeline = ValidationPipeline::from_configs(
+        &[
+            rule(
+                "range",
+                serde_json::json!({"min": 0, "max": 140}),
+                Severity::Error,
+            ),
+            rule(
+                "unit_whitelist",
+                serde_json::json!({"units": ["dB"]}),
+                Severity::Warning,
+            ),
+        ],
+        PatientRegistry::default(),
+    )
+    .unwrap();
+    let state = web::Data::new(Arc::new(Mutex::new(
+        common::demo_state().with_validation(pipeline),
+    )));
+    let app = test::init_service(App::new().app_data(state).configure(common::routes)).await;
+    let token = format!("Bearer {}", common::token("device"));
+
+    let post = |uri: &str, value: f64, unit: &str| {
+        test::TestRequest::post()
+            .uri(uri)
+            .insert_header(("authorization", token.clone()))
+            .set_json(serde_json::json!({
+                "patient_id": "p1",
+                "device_id": "d1",
+                "code": "sound",
+                "value": value,
+                "unit": unit,
+            }))
+            .to_request()
+    };
+
+    // A clean reading carries no warnings field
+    let resp = test::call_service(&app, post("/api/ingest", 60.0, "dB")).await;
+    assert_eq!(resp.status(), 200);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["resourceType"], "Observation");
+    assert!(body.get("warnings").is_none());
+
+    // A warning-level finding is reported, the reading still stored
+    for uri in ["/api/ingest", "/ingest"] {
+        let resp = test::call_service(&app, post(uri, 60.0, "raw")).await;
+        assert_eq!(resp.status(), 200);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["valueQuantity"]["unit"], "raw");
+        assert_eq!(
+            body["warnings"],
+            serde_json::json!(["unit_not_allowed (unit_whitelist [dB])"])
+        );
+    }
+
+    // An error-level finding refuses it
+    for uri in ["/api/ingest", "/ingest"] {
+        let resp = test::call_service(&app, post(uri, 150.0, "dB")).await;
+        assert_eq!(resp.status(), 400);
+        let body = test::read_body(resp).await;
+        assert!(String::from_utf8_lossy(&body).contains("out_of_range"));
+    }
+
+    let req = test::TestRequest::get()
+        .uri("/api/fhir/Observation")
+        .insert_header(("authorization", format!("Bearer {}", common::token("user"))))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.headers().get("X-Total-Count").unwrap(), "3");
+}
+
+#[actix_web::test]
+async fn signal_codes_outside_the_allowlist_are_refused() {
+    let token = format!("Bearer {}", common::token("device"));
+    let reading = serde_json::json!({
+        "patient_id": "p1",
+        "device_id": "d1",
+        "code": "sound",
+        "value": 60.0,
+        "unit": "dB",
+    });
+
+    for (allowed, status) in [(vec![SignalCode::Sound], 200), (vec![], 400)] {
+        let pipeline = ValidationPipeline::default().with_allowed_signal_codes(&allowed);
+        let state = web::Data::new(Arc::new(Mutex::new(
+            common::demo_state().with_validation(pipeline),
+        )));
+        let app = test::init_service(App::new().app_data(state).configure(common::routes)).await;
+
+        for (uri, body) in [
+            ("/api/ingest", reading.clone()),
+            ("/api/ingest/batch", serde_json::json!([reading])),
+        ] {
+            let req = test::TestRequest::post()
+                .uri(uri)
+                .insert_header(("authorization", token.clone()))
+                .set_json(body)
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), status, "{} allowing {:?}", uri, allowed);
+            if status == 400 {
+                let body: serde_json::Value = test::read_body_json(resp).await;
+                assert!(body["error"]
+                    .as_str()
+                    .unwrap()
+                    .contains("signal_code_not_allowed"));
+            }
+        }
+    }
+}
+
+#[actix_web::test]
+async fn ingest_without_ts_gets_server_timestamp() {
+    let app = TestApp::new().service().await;
+
+    let token = common::token("device");
+    let before = chrono::Utc::now();
+
+    let req = test::TestRequest::post()
+        .uri("/api/ingest")
+        .insert_header(("authorization", format!("Bearer {}", token)))
+        .set_json(serde_json::json!({
+            "patient_id": "p1",
+            "device_id": "cheap-sensor",
+            "code": "sound",
+            "value": 300.0,
+            "unit": "raw"
+        }))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert!(resp.status().is_success());
+
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    let effective: chrono::DateTime<chrono::Utc> =
+        body["effectiveDateTime"].as_str().unwrap().parse().unwrap();
+    assert!(effective >= before && effective <= chrono::Utc::now());
+    assert_eq!(body["meta"]["tag"][0]["code"], "server-assigned");
+}
+
+#[actix_web::test]
+async fn ingest_accepts_epoch_millisecond_timestamps() {
+    let app = TestApp::new().service().await;
+
+    let token = common::token("device");
+
+    let req = test::TestRequest::post()
+        .uri("/api/ingest")
+        .insert_header(("authorization", format!("Bearer {}", token)))
+        .set_json(serde_json::json!({
+            "patient_id": "p1",
+            "device_id": "d1",
+            "code": "sound",
+            "value": 300.0,
+            "unit": "raw",
+            "ts": 1714653300123i64
+        }))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert!(resp.status().is_success());
+
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    let effective: chrono::DateTime<chrono::Utc> =
+        body["effectiveDateTime"].as_str().unwrap().parse().unwrap();
+    assert_eq!(effective.timestamp_millis(), 1714653300123);
+    assert!(body.get("meta").is_none());
+
+    let req = test::TestRequest::get()
+        .uri("/api/fhir/Observation?limit=10")
+        .insert_header((
+            "authorization",
+            format!("Bearer {}", common::token("admin")),
+        ))
+        .to_request();
+    let bundle: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(
+        bundle["entry"][0]["resource"]["effectiveDateTime"],
+        body["effectiveDateTime"]
+    );
+
+    let req = test::TestRequest::post()
+        .uri("/api/ingest")
+        .insert_header(("authorization", format!("Bearer {}", token)))
+        .set_json(serde_json::json!({
+            "patient_id": "p1",
+            "device_id": "d1",
+            "code": "sound",
+            "value": 300.0,
+            "unit": "raw",
+            "ts": "last tuesday"
+        }))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 400);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert!(body["error"].as_str().unwrap().contains("field `ts`"));
+}
+
+#[actix_web::test]
+async fn ingest_with_ts_preserves_device_timestamp() {
+    let app = TestApp::new().service().await;
+
+    let token = common::token("device");
+
+    let req = test::TestRequest::post()
+        .uri("/api/ingest")
+        .insert_header(("authorization", format!("Bearer {}", token)))
+        .set_json(serde_json::json!({
+            "patient_id": "p1",
+            "device_id": "d1",
+            "code": "sound",
+            "value": 300.0,
+            "unit": "raw",
+            "ts": "2026-01-15T08:30:00Z"
+        }))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert!(resp.status().is_success());
+
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["effectiveDateTime"], "2026-01-15T08:30:00Z");
+    assert!(body.get("meta").is_none());
+}
+
+#[actix_web::test]
+async fn negative_fractional_values_are_stored_with_normalized_units() {
+    let app = TestApp::new().service().await;
+
+    let token = common::token("device");
+
+    // dBFS from a calibrated serial device, and a simulator's legacy unit
+    for (value, unit, stored_unit) in [(-12.5, "db", "dB"), (200.25, "au", "raw")] {
+        let req = test::TestRequest::post()
+            .uri("/api/ingest")
+            .insert_header(("authorization", format!("Bearer {}", token)))
+            .set_json(serde_json::json!({
+                "patient_id": "p1",
+                "device_id": "arduino-COM6",
+                "code": "sound",
+                "value": value,
+                "unit": unit,
+                "ts": "2026-01-15T08:30:00Z"
+            }))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_success());
+
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["valueQuantity"]["value"], value);
+        assert_eq!(body["valueQuantity"]["unit"], stored_unit);
+    }
+
+    let req = test::TestRequest::get()
+        .uri("/api/fhir/Observation?patient=p1&code=sound&limit=10")
+        .insert_header(("authorization", format!("Bearer {}", common::token("user"))))
+        .to_request();
+    let bundle: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    let units: Vec<_> = bundle["entry"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|e| e["resource"]["valueQuantity"]["unit"].as_str().unwrap())
+        .collect();
+    assert!(units.contains(&"dB") && units.contains(&"raw"));
+    assert!(!units.contains(&"au"));
+}
+
+#[actix_web::test]
+async fn oidc_and_local_tokens_validate_side_by_side() {
+    let issuer = "https://idp.example.org/realms/hospital";
+    let state = web::Data::new(Arc::new(Mutex::new(common::demo_state())));
+    let users = state.lock().await.users();
+    let oidc = Arc::new(
+        OidcValidator::new(OidcConfig {
+            issuer: issuer.to_string(),
+            jwks_url: Some("http://127.0.0.1:9/certs".to_string()),
+            audience: None,
+            role_claim: "realm_access.roles".to_string(),
+            role_mapping: parse_role_mapping("clinician:user"),
+            refresh_interval: std::time::Duration::from_secs(300),
+        })
+        .unwrap()
+        .with_users(users),
+    );
+    let jwks = serde_json::from_str(include_str!("fixtures/test_jwks.json")).unwrap();
+    oidc.set_jwks(&jwks);
+
+    let app = test::init_service(
+        App::new()
+            .app_data(state)
+            .app_data(web::Data::new(oidc))
+            .configure(common::routes),
+    )
+    .await;
+
+    // Token signed by the fake IdP
+    let now = chrono::Utc::now().timestamp();
+    let mut header = jsonwebtoken::Header::new(jsonwebtoken::Algorithm::RS256);
+    header.kid = Some("test-idp-key".to_string());
+    let idp_token = jsonwebtoken::encode(
+        &header,
+        &serde_json::json!({
+            "iss": issuer,
+            "sub": "alice",
+            "iat": now,
+            "exp": now + 300,
+            "realm_access": { "roles": ["clinician"] }
+        }),
+        &jsonwebtoken::EncodingKey::from_rsa_pem(include_bytes!("fixtures/test_rsa_private.pem"))
+            .unwrap(),
+    )
+    .unwrap();
+
+    for token in [&idp_token, &common::token("user")] {
+        let req = test::TestRequest::get()
+            .uri("/api/fhir/Observation")
+            .insert_header(("authorization", format!("Bearer {}", token)))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 200);
+    }
+
+    // Mapped to `user`, alice only reads the patients assigned to her sub
+    let admin = format!("Bearer {}", common::token("admin"));
+    for patient_id in ["p1", "p2"] {
+        let req = test::TestRequest::post()
+            .uri("/api/ingest")
+            .insert_header(("authorization", admin.clone()))
+            .set_json(serde_json::json!({
+                "patient_id": patient_id,
+                "device_id": "d1",
+                "code": "sound",
+                "value": 200.0,
+                "unit": "raw",
+            }))
+            .to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+    }
+    let observations = |uri: &str| {
+        test::TestRequest::get()
+            .uri(uri)
+            .insert_header(("authorization", format!("Bearer {}", idp_token)))
+            .to_request()
+    };
+    let bundle: serde_json::Value =
+        test::call_and_read_body_json(&app, observations("/api/fhir/Observation")).await;
+    assert_eq!(bundle["total"], 0);
+
+    let req = test::TestRequest::post()
+        .uri("/api/admin/oidc-subjects/alice/patients")
+        .insert_header(("authorization", admin.clone()))
+        .set_json(serde_json::json!({ "patient_id": "p1" }))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 201);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["patient_ids"], serde_json::json!(["p1"]));
+
+    let bundle: serde_json::Value =
+        test::call_and_read_body_json(&app, observations("/api/fhir/Observation")).await;
+    assert_eq!(bundle["total"], 1);
+    assert_eq!(
+        bundle["entry"][0]["resource"]["subject"]["reference"],
+        "Patient/p1"
+    );
+    let req = observations("/api/patients/p2/rollups?from=2026-03-01&to=2026-03-31");
+    assert_eq!(test::call_service(&app, req).await.status(), 403);
+}
+
+/// App state with a single password-backed account (cheap bcrypt cost for tests)
+async fn state_with_user(
+    username: &str,
+    password: &str,
+    role: &str,
+) -> web::Data<Arc<Mutex<AppState>>> {
+    let state = common::demo_state();
+    state
+        .users()
+        .create(username, password, role, false)
+        .await
+        .unwrap();
+    web::Data::new(Arc::new(Mutex::new(state)))
+}
+
+#[actix_web::test]
+async fn change_password_rejects_wrong_current_password() {
+    let state = state_with_user("bob", "Correct-horse-42", "user").await;
+    let app = test::init_service(App::new().app_data(state).configure(common::routes)).await;
+
+    let req = test::TestRequest::post()
+        .uri("/api/auth/password")
+        .insert_header((
+            "authorization",
+            format!("Bearer {}", common::token_for("bob", "user")),
+        ))
+        .set_json(serde_json::json!({
+            "current_password": "Wrong-horse-42",
+            "new_password": "Battery-staple-77"
+        }))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 401);
+}
+
+#[actix_web::test]
+async fn change_password_rejects_weak_password() {
+    let state = state_with_user("carol", "Correct-horse-42", "user").await;
+    let app = test::init_service(App::new().app_data(state).configure(common::routes)).await;
+
+    for weak in ["short1", "no-digits-at-all", "carol-12345678"] {
+        let req = test::TestRequest::post()
+            .uri("/api/auth/password")
+            .insert_header((
+                "authorization",
+                format!("Bearer {}", common::token_for("carol", "user")),
+            ))
+            .set_json(serde_json::json!({
+                "current_password": "Correct-horse-42",
+                "new_password": weak
+            }))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 400, "accepted weak password {weak}");
+    }
+}
+
+#[actix_web::test]
+async fn forced_rotation_token_is_restricted_until_password_changed() {
+    let state = state_with_user("dave", "Correct-horse-42", "user").await;
+    let app = test::init_service(App::new().app_data(state).configure(common::routes)).await;
+    let admin_token = common::token_for("root", "admin");
+
+    let login = |password: &str| {
+        test::TestRequest::post()
+            .uri("/auth/login")
+            .set_json(serde_json::json!({ "username": "dave", "password": password }))
+            .to_request()
+    };
+
+    // Find dave's id and reset his password
+    let req = test::TestRequest::get()
+        .uri("/api/users")
+        .insert_header(("authorization", format!("Bearer {}", admin_token)))
+        .to_request();
+    let users: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    let dave = users
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|u| u["username"] == "dave")
+        .unwrap();
+    assert!(dave.get("password_hash").is_none());
+
+    let req = test::TestRequest::post()
+        .uri(&format!(
+            "/api/users/{}/reset-password",
+            dave["id"].as_str().unwrap()
+        ))
+        .insert_header(("authorization", format!("Bearer {}", admin_token)))
+        .to_request();
+    let reset: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    let temporary = reset["temporary_password"].as_str().unwrap().to_string();
+
+    // The old password no longer works; the temporary one yields a restricted token
+    let resp = test::call_service(&app, login("Correct-horse-42")).await;
+    assert_eq!(resp.status(), 401);
+
+    let body: serde_json::Value = test::call_and_read_body_json(&app, login(&temporary)).await;
+    assert_eq!(body["must_change_password"], true);
+    let restricted = body["token"].as_str().unwrap().to_string();
+
+    let req = test::TestRequest::get()
+        .uri("/api/fhir/Observation")
+        .insert_header(("authorization", format!("Bearer {}", restricted)))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 403);
+
+    let req = test::TestRequest::post()
+        .uri("/api/auth/password")
+        .insert_header(("authorization", format!("Bearer {}", restricted)))
+        .set_json(serde_json::json!({
+            "current_password": temporary,
+            "new_password": "Battery-staple-77"
+        }))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert!(resp.status().is_success());
+
+    // A normal login works again and the token is unrestricted
+    let body: serde_json::Value =
+        test::call_and_read_body_json(&app, login("Battery-staple-77")).await;
+    assert_eq!(body["must_change_password"], false);
+
+    let req = test::TestRequest::get()
+        .uri("/api/fhir/Observation")
+        .insert_header((
+            "authorization",
+            format!("Bearer {}", body["token"].as_str().unwrap()),
+        ))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert!(resp.status().is_success());
+}
+
+#[actix_web::test]
+async fn login_tokens_expire_after_their_roles_lifetime() {
+    let state = common::demo_state();
+    let users = state.users();
+    users
+        .create("ada", "Correct-horse-42", "admin", false)
+        .await
+        .unwrap();
+    users
+        .create("bea", "Correct-horse-42", "user", false)
+        .await
+        .unwrap();
+    let app = TestApp::with_state(state)
+        .token_expiry(JwtExpiryConfig {
+            admin_hours: 2,
+            user_hours: 12,
+            device_hours: 48,
+            ..JwtExpiryConfig::default()
+        })
+        .service()
+        .await;
+
+    for (username, hours) in [("ada", 2), ("bea", 12)] {
+        let req = test::TestRequest::post()
+            .uri("/auth/login")
+            .set_json(serde_json::json!({ "username": username, "password": "Correct-horse-42" }))
+            .to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["expires_in"], hours * 3600, "{username}");
+        let claims = JwtManager::new(JwtAlgorithm::HS256(common::TEST_JWT_SECRET.into()))
+            .validate_token(body["token"].as_str().unwrap())
+            .unwrap();
+        assert_eq!(claims.exp - claims.iat, hours * 3600);
+    }
+
+    let req = test::TestRequest::post()
+        .uri("/auth/token")
+        .set_json(serde_json::json!({ "device_id": "d1", "secret": DEFAULT_DEVICE_TOKEN_SECRET }))
+        .to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(body["expires_in"], 48 * 3600);
+}
+
+#[actix_web::test]
+async fn users_only_see_their_permitted_patients() {
+    let state = state_with_user("erin", "Correct-horse-42", "user").await;
+    let users = state.lock().await.users();
+    users
+        .create("frank", "Correct-horse-42", "user", false)
+        .await
+        .unwrap();
+    let erin = users.find_by_username("erin").await.unwrap().unwrap();
+    let app = test::init_service(App::new().app_data(state).configure(common::routes)).await;
+    let admin_auth = format!("Bearer {}", common::token_for("root", "admin"));
+
+    for patient_id in ["p1", "p2"] {
+        let req = test::TestRequest::post()
+            .uri("/api/ingest")
+            .insert_header(("authorization", admin_auth.clone()))
+            .set_json(serde_json::json!({
+                "patient_id": patient_id,
+                "device_id": "d1",
+                "code": "sound",
+                "value": 200.0,
+                "unit": "raw",
+            }))
+            .to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+    }
+
+    let add_patient = |patient_id: &str| {
+        test::TestRequest::post()
+            .uri(&format!("/api/admin/users/{}/patients", erin.id))
+            .insert_header(("authorization", admin_auth.clone()))
+            .set_json(serde_json::json!({ "patient_id": patient_id }))
+            .to_request()
+    };
+    let resp = test::call_service(&app, add_patient("p1")).await;
+    assert_eq!(resp.status(), 201);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["patient_ids"], serde_json::json!(["p1"]));
+    // Adding twice is harmless
+    assert_eq!(
+        test::call_service(&app, add_patient("p1")).await.status(),
+        200
+    );
+
+    // Only admins manage the list
+    let req = test::TestRequest::post()
+        .uri(&format!("/api/admin/users/{}/patients", erin.id))
+        .insert_header((
+            "authorization",
+            format!("Bearer {}", common::token_for("erin", "user")),
+        ))
+        .set_json(serde_json::json!({ "patient_id": "p2" }))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 401);
+
+    let login = |username: &str| {
+        test::TestRequest::post()
+            .uri("/auth/login")
+            .set_json(serde_json::json!({ "username": username, "password": "Correct-horse-42" }))
+            .to_request()
+    };
+    let subjects = |bundle: &serde_json::Value| -> Vec<String> {
+        let mut subjects: Vec<String> = bundle["entry"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|e| {
+                e["resource"]["subject"]["reference"]
+                    .as_str()
+                    .unwrap()
+                    .to_string()
+            })
+            .collect();
+        subjects.sort();
+        subjects
+    };
+    let observations = |auth: &str| {
+        test::TestRequest::get()
+            .uri("/api/fhir/Observation")
+            .insert_header(("authorization", auth.to_string()))
+            .to_request()
+    };
+
+    // Admins see every patient
+    let bundle: serde_json::Value =
+        test::call_and_read_body_json(&app, observations(&admin_auth)).await;
+    assert_eq!(subjects(&bundle), ["Patient/p1", "Patient/p2"]);
+
+    // erin only sees p1, and is refused p2's rollups
+    let body: serde_json::Value = test::call_and_read_body_json(&app, login("erin")).await;
+    let erin_auth = format!("Bearer {}", body["token"].as_str().unwrap());
+    let bundle: serde_json::Value =
+        test::call_and_read_body_json(&app, observations(&erin_auth)).await;
+    assert_eq!(subjects(&bundle), ["Patient/p1"]);
+
+    let req = test::TestRequest::get()
+        .uri("/api/patients/p2/rollups?from=2026-03-01&to=2026-03-31")
+        .insert_header(("authorization", erin_auth.clone()))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 403);
+
+    // frank has no patients yet and sees nothing
+    let body: serde_json::Value = test::call_and_read_body_json(&app, login("frank")).await;
+    let frank_auth = format!("Bearer {}", body["token"].as_str().unwrap());
+    let bundle: serde_json::Value =
+        test::call_and_read_body_json(&app, observations(&frank_auth)).await;
+    assert_eq!(bundle["total"], 0);
+}
+
+/// What a role gets from an endpoint in the permission matrix
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Access {
+    /// Past the permission check: anything but 401 and 403
+    Allowed,
+    Denied(u16),
+}
+
+/// Method, URI, body with its content type, and the access of admin, user,
+/// viewer and device tokens
+type MatrixRow = (
+    &'static str,
+    String,
+    Option<(String, &'static str)>,
+    [Access; 4],
+);
+
+#[actix_web::test]
+async fn roles_are_enforced_across_endpoints() {
+    use Access::{Allowed, Denied};
+
+    let app = TestApp::new().service().await;
+
+    let id = uuid::Uuid::new_v4();
+    let reading = consent_reading("p1");
+    let json = |body: serde_json::Value| Some((body.to_string(), "application/json"));
+
+    // Admin-only endpoints have always answered other tokens with 401;
+    // read-only tokens get 403 everywhere
+    const WRITE: [Access; 4] = [Allowed, Allowed, Denied(403), Allowed];
+    const AMEND: [Access; 4] = [Allowed, Allowed, Denied(403), Denied(403)];
+    const ADMIN: [Access; 4] = [Allowed, Denied(401), Denied(403), Denied(401)];
+    const READ: [Access; 4] = [Allowed, Allowed, Allowed, Denied(403)];
+    let matrix: Vec<MatrixRow> = vec![
+        ("POST", "/api/ingest".into(), json(reading.clone()), WRITE),
+        (
+            "POST",
+            "/api/ingest/batch".into(),
+            json(serde_json::json!([reading])),
+            WRITE,
+        ),
+        (
+            "POST",
+            "/api/ingest/packed".into(),
+            json(serde_json::json!({
+                "patient_id": "p1",
+                "device_id": "d1",
+                "code": "sound",
+                "unit": "dB",
+                "start_ts": 1772409600,
+                "interval_ms": 100,
+                "values": [60.0, 61.0],
+            })),
+            WRITE,
+        ),
+        (
+            "POST",
+            "/api/ingest/stream".into(),
+            Some((format!("{}\n", reading), "application/x-ndjson")),
+            WRITE,
+        ),
+        (
+            "POST",
+            "/api/ingest/octave-bands".into(),
+            json(serde_json::json!({
+                "patient_id": "p1",
+                "device_id": "mic-1",
+                "ts": "2026-02-06T10:00:00Z",
+                "bands": [40.0, 41.0, 42.0, 43.0, 44.0, 45.0, 46.0, 47.0],
+                "overall_db": 55.2,
+            })),
+            WRITE,
+        ),
+        (
+            "POST",
+            "/api/ingest/spectrogram".into(),
+            json(serde_json::json!({
+                "patient_id": "p1",
+                "device_id": "slm-1",
+                "ts": "2026-03-01T12:00:02Z",
+                "sample_rate_hz": 48000,
+                "frame_duration_ms": 125,
+                "frequency_bins": [500.0, 1000.0, 2000.0],
+                "magnitudes": [0.1, 0.2, 0.9],
+            })),
+            WRITE,
+        ),
+        (
+            "PUT",
+            format!("/api/fhir/Observation/{}", id),
+            json(serde_json::json!({ "status": "amended" })),
+            AMEND,
+        ),
+        (
+            "DELETE",
+            format!("/api/fhir/Observation/{}", id),
+            None,
+            ADMIN,
+        ),
+        (
+            "POST",
+            "/api/ml/train".into(),
+            json(serde_json::json!({})),
+            ADMIN,
+        ),
+        (
+            "POST",
+            "/api/users".into(),
+            json(serde_json::json!({
+                "username": "matrix",
+                "password": "Correct-horse-42",
+                "role": "viewer",
+            })),
+            ADMIN,
+        ),
+        (
+            "POST",
+            format!("/api/users/{}/reset-password", id),
+            None,
+            ADMIN,
+        ),
+        (
+            "POST",
+            format!("/api/admin/users/{}/patients", id),
+            json(serde_json::json!({ "patient_id": "p1" })),
+            ADMIN,
+        ),
+        (
+            "PUT",
+            "/api/patients/p1/consent".into(),
+            json(serde_json::json!({ "status": "granted" })),
+            ADMIN,
+        ),
+        (
+            "POST",
+            "/api/admin/cors".into(),
+            json(serde_json::json!({ "origin": "https://ward.example.org" })),
+            ADMIN,
+        ),
+        (
+            "POST",
+            "/api/admin/hearing-protectors".into(),
+            json(serde_json::json!({ "name": "Foam plug", "nrr_db": 33, "category": "earplug" })),
+            ADMIN,
+        ),
+        (
+            "DELETE",
+            format!("/api/admin/hearing-protectors/{}", id),
+            None,
+            ADMIN,
+        ),
+        ("POST", "/api/devices/d1/secret".into(), None, ADMIN),
+        ("GET", "/api/fhir/Observation".into(), None, READ),
+        ("GET", "/api/fhir/Observation/stream".into(), None, READ),
+        ("GET", "/api/devices/d1/stats".into(), None, READ),
+        ("GET", "/api/fhir/Patient/p1".into(), None, READ),
+        ("GET", "/api/fhir/Device/d1".into(), None, READ),
+        (
+            "GET",
+            "/api/devices/d1/calibration-history".into(),
+            None,
+            READ,
+        ),
+        (
+            "GET",
+            "/api/patients/p1/rollups?from=2026-03-01&to=2026-03-02".into(),
+            None,
+            READ,
+        ),
+        ("GET", "/api/users".into(), None, ADMIN),
+    ];
+
+    let mut mismatches = Vec::new();
+    for (method, uri, body, expected) in &matrix {
+        for (role, expected) in ["admin", "user", "viewer", "device"].iter().zip(expected) {
+            let mut req = test::TestRequest::default()
+                .method(method.parse().unwrap())
+                .uri(uri)
+                .insert_header(("authorization", format!("Bearer {}", common::token(role))));
+            if let Some((body, content_type)) = body {
+                req = req
+                    .insert_header(("content-type", *content_type))
+                    .set_payload(body.clone());
+            }
+            let status = test::call_service(&app, req.to_request())
+                .await
+                .status()
+                .as_u16();
+            let actual = match status {
+                401 | 403 => Denied(status),
+                _ => Allowed,
+            };
+            if actual != *expected {
+                mismatches.push(format!(
+                    "{} {} as {}: {} ({:?} expected)",
+                    method, uri, role, status, expected
+                ));
+            }
+        }
+    }
+    assert!(mismatches.is_empty(), "{:#?}", mismatches);
+}
+
+#[actix_web::test]
+async fn viewer_accounts_log_in_to_read_only_tokens() {
+    let state = state_with_user("wanda", "Correct-horse-42", "viewer").await;
+    let app = test::init_service(App::new().app_data(state).configure(common::routes)).await;
+
+    let req = test::TestRequest::post()
+        .uri("/auth/login")
+        .set_json(serde_json::json!({ "username": "wanda", "password": "Correct-horse-42" }))
+        .to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    let auth = format!("Bearer {}", body["token"].as_str().unwrap());
+
+    let req = test::TestRequest::get()
+        .uri("/auth/whoami")
+        .insert_header(("authorization", auth.clone()))
+        .to_request();
+    let claims: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(claims["role"], "viewer");
+    // Like users, viewers see only the patients assigned to them
+    assert_eq!(claims["permitted_patients"], serde_json::json!([]));
+
+    let req = test::TestRequest::get()
+        .uri("/api/fhir/Observation")
+        .insert_header(("authorization", auth.clone()))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 200);
+
+    let req = test::TestRequest::post()
+        .uri("/api/ingest")
+        .insert_header(("authorization", auth.clone()))
+        .set_json(consent_reading("p1"))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 403);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert!(body["error"]
+        .as_str()
+        .unwrap()
+        .contains("viewer tokens cannot submit readings"));
+}
+
+#[actix_web::test]
+async fn ingest_rejects_oversized_body_with_413() {
+    let app = TestApp::new().service().await;
+
+    // Well over the 8KB single-reading limit
+    let oversized = serde_json::json!({
+        "patient_id": "p".repeat(16 * 1024),
+        "device_id": "d1",
+        "code": "sound",
+        "value": 1.0,
+        "unit": "raw"
+    });
+
+    for (uri, token) in [
+        ("/ingest", None),
+        ("/api/ingest", Some(common::token("device"))),
+    ] {
+        let mut req = test::TestRequest::post().uri(uri).set_json(&oversized);
+        if let Some(token) = token {
+            req = req.insert_header(("authorization", format!("Bearer {}", token)));
+        }
+        let resp = test::call_service(&app, req.to_request()).await;
+        assert_eq!(resp.status(), 413, "{uri}");
+
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert!(body["error"].as_str().unwrap().contains("limit"));
+    }
+
+    let req = test::TestRequest::get().uri("/metrics").to_request();
+    let body = test::call_and_read_body(&app, req).await;
+    let text = String::from_utf8(body.to_vec()).unwrap();
+    let rejected: u64 = text
+        .lines()
+        .find_map(|l| {
+            l.strip_prefix("soundsense_ingest_rejected_total{reason=\"body_too_large\"} ")
+        })
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!(rejected >= 2);
+}
+
+#[actix_web::test]
+async fn malformed_ingest_returns_field_specific_error() {
+    let app = TestApp::new().service().await;
+
+    let req = test::TestRequest::post()
+        .uri("/ingest")
+        .set_json(serde_json::json!({ "value": "abc" }))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 400);
+
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["code"], "bad_request");
+    let error = body["error"].as_str().unwrap();
+    assert!(error.contains("`patient_id`"), "{error}");
+    assert!(error.contains("field `value`"), "{error}");
+    assert!(error.contains("expected f64"), "{error}");
+
+    // Syntax errors are reported as such, in the same shape
+    let req = test::TestRequest::post()
+        .uri("/auth/login")
+        .insert_header(("content-type", "application/json"))
+        .set_payload("{\"username\": ")
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 400);
+
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["code"], "bad_request");
+    assert!(body["error"].as_str().unwrap().contains("malformed JSON"));
+}
+
+#[actix_web::test]
+async fn batch_ingest_accepts_bodies_above_single_limit() {
+    let app = TestApp::new().service().await;
+
+    let batch: Vec<_> = (0..200)
+        .map(|i| {
+            serde_json::json!({
+                "patient_id": "p1",
+                "device_id": "d1",
+                "code": "sound",
+                "value": i as f64,
+                "unit": "raw"
+            })
+        })
+        .collect();
+    assert!(serde_json::to_vec(&batch).unwrap().len() > 8 * 1024);
+
+    let req = test::TestRequest::post()
+        .uri("/api/ingest/batch")
+        .insert_header((
+            "authorization",
+            format!("Bearer {}", common::token("device")),
+        ))
+        .set_json(&batch)
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert!(resp.status().is_success());
+
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["total"], 200);
+}
+
+#[actix_web::test]
+async fn queued_ingest_returns_202_and_reports_depth() {
+    let state = web::Data::new(Arc::new(Mutex::new(common::demo_state())));
+    // No worker running, so readings stay queued
+    let (queue, _worker) = IngestQueue::new(100);
+    let app = test::init_service(
+        App::new()
+            .app_data(state)
+            .app_data(web::Data::new(queue))
+            .configure(common::routes),
+    )
+    .await;
+
+    let reading = serde_json::json!({
+        "patient_id": "p1",
+        "device_id": "d1",
+        "code": "sound",
+        "value": 1.0,
+        "unit": "raw"
+    });
+
+    let req = test::TestRequest::post()
+        .uri("/api/ingest")
+        .insert_header((
+            "authorization",
+            format!("Bearer {}", common::token("device")),
+        ))
+        .set_json(&reading)
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 202);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["receipt"]["storage"], "queued");
+    assert_eq!(body["receipt"]["persisted"], false);
+
+    let req = test::TestRequest::post()
+        .uri("/ingest")
+        .set_json(&reading)
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 202);
+
+    let req = test::TestRequest::get().uri("/metrics").to_request();
+    let body = test::call_and_read_body(&app, req).await;
+    let text = String::from_utf8(body.to_vec()).unwrap();
+    assert!(text.contains("soundsense_ingest_queue_depth{priority=\"high\"} 1"));
+    assert!(text.contains("soundsense_ingest_queue_depth{priority=\"low\"} 1"));
+}
+
+#[actix_web::test]
+async fn saturated_ingest_is_shed_with_429() {
+    let state = web::Data::new(Arc::new(Mutex::new(common::demo_state())));
+    let limiter = IngestLimiter::new(1, 3);
+    let app = test::init_service(
+        App::new()
+            .app_data(state)
+            .app_data(web::Data::new(limiter.clone()))
+            .configure(common::routes),
+    )
+    .await;
+
+    let reading = serde_json::json!({
+        "patient_id": "p1",
+        "device_id": "d1",
+        "code": "sound",
+        "value": 1.0,
+        "unit": "raw"
+    });
+
+    // Another request holds the only permit
+    let permit = limiter.try_acquire().unwrap();
+
+    let req = test::TestRequest::post()
+        .uri("/ingest")
+        .set_json(&reading)
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 429);
+    assert_eq!(resp.headers().get("retry-after").unwrap(), "3");
+
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["code"], "too_many_requests");
+
+    let req = test::TestRequest::get().uri("/metrics").to_request();
+    let body = test::call_and_read_body(&app, req).await;
+    let text = String::from_utf8(body.to_vec()).unwrap();
+    assert!(text.contains("soundsense_ingest_in_flight 1"));
+    assert!(text.contains("soundsense_ingest_rejected_total{reason=\"overloaded\"}"));
+
+    // Capacity frees up once the in-flight request finishes
+    drop(permit);
+    let req = test::TestRequest::post()
+        .uri("/ingest")
+        .set_json(&reading)
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert!(resp.status().is_success());
+    assert_eq!(limiter.in_flight(), 0);
+}
+
+#[actix_web::test]
+async fn stuck_api_requests_time_out_with_504() {
+    let app = TestApp::new().request_timeout(RequestTimeout::new(Duration::from_millis(200)));
+    let addr = app.serve(1);
+    let client = reqwest::Client::new();
+    let token = app.token("admin");
+    let get = |request_id: Option<&str>| {
+        let mut req = client
+            .get(format!("http://{}/api/fhir/Observation", addr))
+            .bearer_auth(&token);
+        if let Some(id) = request_id {
+            req = req.header("x-request-id", id);
+        }
+        req.send()
+    };
+
+    // A handler stuck behind the store, as on a hung database connection
+    let guard = app.state.lock().await;
+    let started = std::time::Instant::now();
+    let resp = get(Some("req-42")).await.unwrap();
+    assert_eq!(resp.status(), 504);
+    assert!(started.elapsed() < Duration::from_secs(5));
+    assert_eq!(resp.headers()["x-request-id"], "req-42");
+    let body: serde_json::Value = resp.json().await.unwrap();
+    assert_eq!(body["code"], "request_timeout");
+    assert!(body["error"].as_str().unwrap().contains("req-42"));
+
+    // Without one of its own, the request is given an id
+    let resp = get(None).await.unwrap();
+    assert_eq!(resp.status(), 504);
+    assert!(!resp.headers()["x-request-id"].is_empty());
+
+    // Writes wait their turn instead: aborted after storing, the reading
+    // would be stored again when the client retried
+    let ingest = client
+        .post(format!("http://{}/api/ingest", addr))
+        .bearer_auth(&token)
+        .json(&serde_json::json!({
+            "patient_id": "p1",
+            "device_id": "d1",
+            "code": "sound",
+            "value": 60.0,
+            "unit": "dB",
+        }))
+        .send();
+    let ingest = tokio::spawn(ingest);
+    tokio::time::sleep(Duration::from_millis(400)).await;
+    assert!(!ingest.is_finished());
+
+    // The aborted handlers did not keep the store
+    drop(guard);
+    assert_eq!(ingest.await.unwrap().unwrap().status(), 200);
+    assert_eq!(get(None).await.unwrap().status(), 200);
+    assert_eq!(app.state.lock().await.snapshot().len(), 1);
+
+    let metrics = client
+        .get(format!("http://{}/metrics", addr))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(
+        metrics.contains("soundsense_request_timeouts_total{route=\"/api/fhir/Observation\"} 2\n")
+    );
+}
+
+#[actix_web::test]
+async fn revoked_session_is_rejected_on_next_request() {
+    let state = state_with_user("frank", "Correct-horse-42", "user").await;
+    let sessions = Arc::new(SessionStore::new(None));
+    let app = test::init_service(
+        App::new()
+            .app_data(state)
+            .app_data(web::Data::new(sessions.clone()))
+            .configure(common::routes),
+    )
+    .await;
+    let admin_token = common::token_for("root", "admin");
+
+    let req = test::TestRequest::post()
+        .uri("/auth/login")
+        .set_json(serde_json::json!({ "username": "frank", "password": "Correct-horse-42" }))
+        .to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    let frank_token = body["token"].as_str().unwrap().to_string();
+
+    let observations = |token: &str| {
+        test::TestRequest::get()
+            .uri("/api/fhir/Observation")
+            .insert_header(("authorization", format!("Bearer {}", token)))
+            .to_request()
+    };
+    let resp = test::call_service(&app, observations(&frank_token)).await;
+    assert!(resp.status().is_success());
+
+    // The admin sees frank's session, with last_seen filled in by the request above
+    let req = test::TestRequest::get()
+        .uri("/api/auth/sessions?user=frank")
+        .insert_header(("authorization", format!("Bearer {}", admin_token)))
+        .to_request();
+    let listed: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    let listed = listed.as_array().unwrap();
+    assert_eq!(listed.len(), 1);
+    assert_eq!(listed[0]["subject"], "frank");
+    assert!(!listed[0]["last_seen_at"].is_null());
+    let jti = listed[0]["jti"].as_str().unwrap().to_string();
+
+    // Non-admins cannot revoke
+    let req = test::TestRequest::delete()
+        .uri(&format!("/api/auth/sessions/{}", jti))
+        .insert_header(("authorization", format!("Bearer {}", frank_token)))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 401);
+
+    let req = test::TestRequest::delete()
+        .uri(&format!("/api/auth/sessions/{}", jti))
+        .insert_header(("authorization", format!("Bearer {}", admin_token)))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 204);
+
+    let resp = test::call_service(&app, observations(&frank_token)).await;
+    assert_eq!(resp.status(), 401);
+
+    // Unknown sessions are a 404
+    let req = test::TestRequest::delete()
+        .uri("/api/auth/sessions/does-not-exist")
+        .insert_header(("authorization", format!("Bearer {}", admin_token)))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 404);
+}
+
+/// Start a stand-in for the Python ML service whose training finishes at once
+async fn spawn_mock_ml_service() -> String {
+    let server = actix_web::HttpServer::new(|| {
+        App::new()
+            .route(
+                "/train",
+                web::post().to(|| async {
+                    actix_web::HttpResponse::Ok().json(serde_json::json!({
+                        "success": true,
+                        "message": "Training started in background",
+                        "samples_used": 100
+                    }))
+                }),
+            )
+            .route(
+                "/training/status/{job_id}",
+                web::get().to(|| async {
+                    actix_web::HttpResponse::Ok().json(serde_json::json!({
+                        "status": "completed",
+                        "progress_percent": 100.0,
+                        "message": "Model training completed"
+                    }))
+                }),
+            )
+    })
+    .workers(1)
+    .bind(("127.0.0.1", 0))
+    .unwrap();
+
+    let addr = server.addrs()[0];
+    actix_web::rt::spawn(server.run());
+    format!("http://{}", addr)
+}
+
+/// Mock ML service answering `/train` and `/health`, refusing requests
+/// without an `Authorization` header with 401; returns its URL and the
+/// headers received
+async fn spawn_authenticating_ml_service() -> (String, Arc<std::sync::Mutex<Vec<String>>>) {
+    let headers = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let recorded = headers.clone();
+    let server = actix_web::HttpServer::new(move || {
+        let recorded = recorded.clone();
+        App::new().default_service(web::to(move |req: actix_web::HttpRequest| {
+            let recorded = recorded.clone();
+            async move {
+                let Some(auth) = req.headers().get("authorization") else {
+                    return actix_web::HttpResponse::Unauthorized().finish();
+                };
+                recorded
+                    .lock()
+                    .unwrap()
+                    .push(auth.to_str().unwrap().to_string());
+                match req.path() {
+                    "/health" => actix_web::HttpResponse::Ok().json(serde_json::json!({
+                        "status": "healthy",
+                        "database_connected": true,
+                        "classifier_loaded": true,
+                        "anomaly_detector_loaded": true
+                    })),
+                    _ => actix_web::HttpResponse::Ok()
+                        .json(serde_json::json!({ "message": "Training started" })),
+                }
+            }
+        }))
+    })
+    .workers(1)
+    .bind(("127.0.0.1", 0))
+    .unwrap();
+
+    let addr = server.addrs()[0];
+    actix_web::rt::spawn(server.run());
+    (format!("http://{}", addr), headers)
+}
+
+#[actix_web::test]
+async fn ml_client_sends_its_static_token() {
+    let (url, headers) = spawn_authenticating_ml_service().await;
+    let client = MlClient::new(url).with_auth(MlAuth::Static("s3cret".into()));
+
+    client.health_check().await.unwrap();
+    client.health_check().await.unwrap();
+    assert_eq!(*headers.lock().unwrap(), ["Bearer s3cret", "Bearer s3cret"]);
+}
+
+#[actix_web::test]
+async fn ml_client_renews_its_service_token_near_expiry() {
+    let (url, headers) = spawn_authenticating_ml_service().await;
+    let jwt = Arc::new(JwtManager::new(JwtAlgorithm::HS256(
+        common::TEST_JWT_SECRET.to_string(),
+    )));
+    let client = MlClient::new(url).with_auth(MlAuth::ServiceJwt {
+        jwt: jwt.clone(),
+        ttl: chrono::Duration::seconds(2),
+    });
+
+    client.health_check().await.unwrap();
+    client.health_check().await.unwrap();
+    // Within the last fifth of its lifetime the token is replaced
+    tokio::time::sleep(Duration::from_millis(1700)).await;
+    client.health_check().await.unwrap();
+
+    let headers = headers.lock().unwrap().clone();
+    assert_eq!(headers.len(), 3);
+    assert_eq!(headers[0], headers[1], "a fresh token is reused");
+    assert_ne!(headers[1], headers[2], "a token near expiry is renewed");
+    for header in &headers {
+        let claims = jwt
+            .validate_token(header.strip_prefix("Bearer ").unwrap())
+            .unwrap();
+        assert_eq!(claims.role, "backend");
+        assert_eq!(claims.scope.as_deref(), Some("ml_service"));
+        assert!(claims.exp - claims.iat <= 2);
+    }
+
+    // Signed with our key, but not a way into our own API
+    let app = TestApp::new().service().await;
+    let req = test::TestRequest::get()
+        .uri("/api/fhir/Observation")
+        .insert_header(("authorization", headers[2].clone()))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 401);
+}
+
+#[actix_web::test]
+async fn ml_service_refusing_credentials_is_a_bad_gateway() {
+    let (url, _) = spawn_authenticating_ml_service().await;
+    // Sends no credentials, so the mock answers 401
+    let ml_client = Arc::new(MlClient::new(url));
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(Arc::new(Mutex::new(common::demo_state()))))
+            .configure(common::routes)
+            .app_data(web::Data::new(ml_client)),
+    )
+    .await;
+
+    let req = test::TestRequest::post()
+        .uri("/api/ml/train")
+        .insert_header((
+            "authorization",
+            format!("Bearer {}", common::token("admin")),
+        ))
+        .set_json(serde_json::json!({ "min_samples": 10 }))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 502);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["code"], "bad_gateway");
+
+    let req = test::TestRequest::get()
+        .uri("/api/ml/predict")
+        .insert_header((
+            "authorization",
+            format!("Bearer {}", common::token("admin")),
+        ))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 502);
+}
+
+#[actix_web::test]
+async fn training_conflicts_while_a_job_is_in_progress() {
+    let state = web::Data::new(Arc::new(Mutex::new(common::demo_state())));
+    let running = state.lock().await.training_jobs().start().await.unwrap();
+    // Never contacted: the conflict is detected first
+    let ml_client = Arc::new(MlClient::new("http://127.0.0.1:9".into()));
+    let app = test::init_service(
+        App::new()
+            .app_data(state)
+            .configure(common::routes)
+            .app_data(web::Data::new(ml_client)),
+    )
+    .await;
+
+    let req = test::TestRequest::post()
+        .uri("/api/ml/train")
+        .insert_header((
+            "authorization",
+            format!("Bearer {}", common::token("admin")),
+        ))
+        .set_json(serde_json::json!({}))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 409);
+
+    let req = test::TestRequest::get()
+        .uri("/api/ml/train")
+        .insert_header((
+            "authorization",
+            format!("Bearer {}", common::token("admin")),
+        ))
+        .to_request();
+    let jobs: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(jobs.as_array().unwrap().len(), 1);
+    assert_eq!(jobs[0]["job_id"], running.job_id.to_string());
+    assert_eq!(jobs[0]["status"], "in_progress");
+
+    // Training stays admin-only
+    let req = test::TestRequest::get()
+        .uri(format!("/api/ml/train/{}", running.job_id).as_str())
+        .insert_header(("authorization", format!("Bearer {}", common::token("user"))))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 401);
+}
+
+#[actix_web::test]
+async fn training_progress_is_polled_from_ml_service() {
+    let training_jobs =
+        Arc::new(TrainingJobStore::new(None).with_poll_interval(Duration::from_millis(20)));
+    let state = web::Data::new(Arc::new(Mutex::new(
+        common::demo_state().with_training_jobs(training_jobs),
+    )));
+    let ml_client = Arc::new(MlClient::new(spawn_mock_ml_service().await));
+    let app = test::init_service(
+        App::new()
+            .app_data(state)
+            .configure(common::routes)
+            .app_data(web::Data::new(ml_client)),
+    )
+    .await;
+    let auth = format!("Bearer {}", common::token("admin"));
+
+    let req = test::TestRequest::post()
+        .uri("/api/ml/train")
+        .insert_header(("authorization", auth.clone()))
+        .set_json(serde_json::json!({ "min_samples": 10 }))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 202);
+    let job: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(job["status"], "in_progress");
+    let job_id = job["job_id"].as_str().unwrap().to_string();
+
+    let mut status = serde_json::Value::Null;
+    for _ in 0..100 {
+        let req = test::TestRequest::get()
+            .uri(format!("/api/ml/train/{}", job_id).as_str())
+            .insert_header(("authorization", auth.clone()))
+            .to_request();
+        status = test::call_and_read_body_json(&app, req).await;
+        if status["status"] == "completed" {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+
+    assert_eq!(status["status"], "completed");
+    assert_eq!(status["progress_percent"], 100.0);
+    assert_eq!(status["message"], "Model training completed");
+}
+
+#[actix_web::test]
+async fn openapi_document_describes_ingest_and_observations() {
+    let app = TestApp::new().service().await;
+
+    // Public, like the routes it describes that need no token
+    let req = test::TestRequest::get()
+        .uri("/api/openapi.json")
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 200);
+    let doc: serde_json::Value = test::read_body_json(resp).await;
+
+    assert!(doc["openapi"].as_str().unwrap().starts_with("3."));
+    let paths = &doc["paths"];
+    assert!(paths["/ingest"]["post"].is_object());
+    assert!(paths["/api/ingest"]["post"].is_object());
+    assert!(paths["/api/ingest/batch"]["post"].is_object());
+    assert!(paths["/api/fhir/Observation"]["get"].is_object());
+    assert!(paths["/api/fhir/Observation"]["head"].is_object());
+    assert!(paths["/api/fhir/Observation"]["post"].is_null());
+    assert!(paths["/api/alert-suppressions"]["post"].is_object());
+    for method in ["get", "put", "delete"] {
+        assert!(paths["/api/alert-suppressions/{id}"][method].is_object());
+    }
+
+    // Protected paths reference the bearer scheme
+    assert_eq!(
+        doc["components"]["securitySchemes"]["bearer_auth"]["scheme"],
+        "bearer"
+    );
+    assert!(paths["/api/ingest"]["post"]["security"][0]["bearer_auth"].is_array());
+    assert!(paths["/ingest"]["post"]["security"].is_null());
+
+    let schemas = &doc["components"]["schemas"];
+    for name in [
+        "IngestReading",
+        "FhirObservation",
+        "LoginRequest",
+        "ErrBody",
+        "SuppressionRequest",
+        "SuppressionWindow",
+    ] {
+        assert!(schemas[name].is_object(), "missing schema {name}");
+    }
+}
+
+#[actix_web::test]
+async fn outlier_reading_produces_anomaly_frame() {
+    let hub = WsHub::new(Some(AnomalyScorer::new(
+        AnomalyDetector::new(100, 10, 3.0),
+        None,
+    )));
+    let mut anomalies = hub.anomalies.subscribe();
+    let live = hub.tx.subscribe();
+    let app = TestApp::new().hub(hub).service().await;
+
+    let ingest = |value: f64| {
+        test::TestRequest::post()
+            .uri("/ingest")
+            .set_json(serde_json::json!({
+                "patient_id": "p1",
+                "device_id": "d1",
+                "code": "sound",
+                "value": value,
+                "unit": "raw"
+            }))
+            .to_request()
+    };
+
+    for i in 0..30 {
+        let resp = test::call_service(&app, ingest(200.0 + (i % 7) as f64)).await;
+        assert!(resp.status().is_success());
+    }
+    // Every reading goes to the live stream, none to the anomaly stream
+    assert_eq!(live.len(), 30);
+    assert!(anomalies.try_recv().is_err());
+
+    let resp = test::call_service(&app, ingest(950.0)).await;
+    assert!(resp.status().is_success());
+
+    let frame = serde_json::to_value(anomalies.try_recv().unwrap()).unwrap();
+    assert_eq!(frame["subject"], "Patient/p1");
+    assert_eq!(frame["value"], 950.0);
+    assert_eq!(frame["category"], "above_baseline");
+    assert_eq!(frame["detector"], "builtin");
+    assert!(frame["score"].as_f64().unwrap() > 3.0);
+
+    // A normal reading afterwards is not flagged
+    let resp = test::call_service(&app, ingest(203.0)).await;
+    assert!(resp.status().is_success());
+    assert!(anomalies.try_recv().is_err());
+}
+
+#[actix_web::test]
+async fn suppression_windows_silence_a_patients_anomalies() {
+    let state = common::demo_state();
+    let hub = WsHub::new(Some(AnomalyScorer::new(
+        AnomalyDetector::new(100, 10, 3.0),
+        None,
+    )))
+    .with_suppressions(state.alert_suppressions());
+    let mut anomalies = hub.anomalies.subscribe();
+    let app = TestApp::with_state(state).hub(hub).service().await;
+    let user = format!("Bearer {}", common::token("user"));
+
+    let ingest = |patient_id: &str, value: f64| {
+        test::TestRequest::post()
+            .uri("/ingest")
+            .set_json(serde_json::json!({
+                "patient_id": patient_id,
+                "device_id": "d1",
+                "code": "sound",
+                "value": value,
+                "unit": "raw"
+            }))
+            .to_request()
+    };
+    // Anomalies raised within a moment of the readings, oldest first
+    let raised = |anomalies: &mut tokio::sync::broadcast::Receiver<_>| {
+        let mut subjects = Vec::new();
+        while let Ok(event) = anomalies.try_recv() {
+            subjects.push(serde_json::to_value(event).unwrap()["subject"].clone());
+        }
+        subjects
+    };
+    let settle = || tokio::time::sleep(Duration::from_millis(200));
+
+    for patient_id in ["p1", "p2"] {
+        for i in 0..30 {
+            let resp = test::call_service(&app, ingest(patient_id, 200.0 + (i % 7) as f64)).await;
+            assert!(resp.status().is_success());
+        }
+    }
+
+    // Without a window the outlier is raised
+    assert!(test::call_service(&app, ingest("p1", 950.0))
+        .await
+        .status()
+        .is_success());
+    settle().await;
+    assert_eq!(raised(&mut anomalies), ["Patient/p1"]);
+
+    let suppress = |body: serde_json::Value| {
+        test::TestRequest::post()
+            .uri("/api/alert-suppressions")
+            .insert_header(("authorization", user.clone()))
+            .set_json(body)
+            .to_request()
+    };
+    let end_at = chrono::Utc::now() + chrono::Duration::hours(1);
+    let resp = test::call_service(
+        &app,
+        suppress(serde_json::json!({
+            "patient_id": "p1",
+            "end_at": end_at,
+            "reason": "hearing test"
+        })),
+    )
+    .await;
+    assert_eq!(resp.status(), 201);
+    let window: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(window["created_by"], "test-user");
+
+    // During the window p1's outliers are silent; p2's are not
+    assert!(test::call_service(&app, ingest("p1", 960.0))
+        .await
+        .status()
+        .is_success());
+    assert!(test::call_service(&app, ingest("p2", 950.0))
+        .await
+        .status()
+        .is_success());
+    settle().await;
+    assert_eq!(raised(&mut anomalies), ["Patient/p2"]);
+
+    // Once the window is deleted they are raised again
+    let req = test::TestRequest::delete()
+        .uri(&format!(
+            "/api/alert-suppressions/{}",
+            window["id"].as_str().unwrap()
+        ))
+        .insert_header(("authorization", user.clone()))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 204);
+    assert!(test::call_service(&app, ingest("p1", 970.0))
+        .await
+        .status()
+        .is_success());
+    settle().await;
+    assert_eq!(raised(&mut anomalies), ["Patient/p1"]);
+}
+
+#[actix_web::test]
+async fn alert_suppressions_are_managed_by_admins_and_users() {
+    let app = TestApp::new().service().await;
+    let now = chrono::Utc::now();
+    let request = |method: test::TestRequest, uri: &str, role: &str| {
+        method
+            .uri(uri)
+            .insert_header(("authorization", format!("Bearer {}", common::token(role))))
+    };
+    let create = |body: serde_json::Value, role: &str| {
+        request(test::TestRequest::post(), "/api/alert-suppressions", role)
+            .set_json(body)
+            .to_request()
+    };
+
+    let current = serde_json::json!({
+        "patient_id": "p1",
+        "device_id": "d1",
+        "end_at": now + chrono::Duration::hours(1),
+        "reason": "hearing test"
+    });
+    let resp = test::call_service(&app, create(current.clone(), "admin")).await;
+    assert_eq!(resp.status(), 201);
+    let window: serde_json::Value = test::read_body_json(resp).await;
+    let uri = format!("/api/alert-suppressions/{}", window["id"].as_str().unwrap());
+
+    let upcoming = serde_json::json!({
+        "patient_id": "p2",
+        "start_at": now + chrono::Duration::hours(2),
+        "end_at": now + chrono::Duration::hours(3),
+        "reason": "audiology visit"
+    });
+    assert_eq!(
+        test::call_service(&app, create(upcoming, "user"))
+            .await
+            .status(),
+        201
+    );
+
+    // Ends before it starts, or without a reason
+    let backwards = serde_json::json!({
+        "patient_id": "p1",
+        "end_at": now - chrono::Duration::hours(1),
+        "reason": "oops"
+    });
+    assert_eq!(
+        test::call_service(&app, create(backwards, "admin"))
+            .await
+            .status(),
+        400
+    );
+    let mut unexplained = current.clone();
+    unexplained["reason"] = "".into();
+    assert_eq!(
+        test::call_service(&app, create(unexplained, "admin"))
+            .await
+            .status(),
+        400
+    );
+    // Viewers and devices cannot manage windows
+    for role in ["viewer", "device"] {
+        assert_eq!(
+            test::call_service(&app, create(current.clone(), role))
+                .await
+                .status(),
+            403
+        );
+    }
+
+    let list = |uri: &str| request(test::TestRequest::get(), uri, "user").to_request();
+    let all: serde_json::Value =
+        test::call_and_read_body_json(&app, list("/api/alert-suppressions")).await;
+    assert_eq!(all.as_array().unwrap().len(), 2);
+    let active: serde_json::Value =
+        test::call_and_read_body_json(&app, list("/api/alert-suppressions/active")).await;
+    assert_eq!(active.as_array().unwrap().len(), 1);
+    assert_eq!(active[0]["id"], window["id"]);
+    assert_eq!(active[0]["device_id"], "d1");
+
+    // Replacing keeps the id and creator
+    let mut extended = current.clone();
+    extended["end_at"] = serde_json::json!(now + chrono::Duration::hours(4));
+    let req = request(test::TestRequest::put(), &uri, "user")
+        .set_json(&extended)
+        .to_request();
+    let updated: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(updated["id"], window["id"]);
+    assert_eq!(updated["created_by"], "test-user");
+    let fetched: serde_json::Value = test::call_and_read_body_json(&app, list(&uri)).await;
+    assert_eq!(fetched["end_at"], updated["end_at"]);
+
+    let req = request(test::TestRequest::delete(), &uri, "admin").to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 204);
+    assert_eq!(test::call_service(&app, list(&uri)).await.status(), 404);
+}
+
+#[actix_web::test]
+async fn slow_live_subscriber_drops_are_counted() {
+    let hub = WsHub::with_capacity(None, 16);
+    // Subscribed but not reading until the batch is in
+    let mut slow = hub.subscribe_live();
+    let app = TestApp::new().hub(hub.clone()).service().await;
+
+    let batch: Vec<_> = (0..100)
+        .map(|i| {
+            serde_json::json!({
+                "patient_id": "p1",
+                "device_id": "d1",
+                "code": "sound",
+                "value": i as f64,
+                "unit": "raw"
+            })
+        })
+        .collect();
+    let req = test::TestRequest::post()
+        .uri("/api/ingest/batch")
+        .insert_header((
+            "authorization",
+            format!("Bearer {}", common::token("device")),
+        ))
+        .set_json(&batch)
+        .to_request();
+    assert!(test::call_service(&app, req).await.status().is_success());
+
+    // The subscriber gets the newest 16; the rest are counted, not lost silently
+    let mut received = Vec::new();
+    while let Some(obs) = slow.try_next() {
+        received.push(obs);
+    }
+    assert_eq!(received.len(), 16);
+    assert_eq!(received[0].value_quantity.value, 84.0);
+    assert_eq!(hub.dropped_messages(), 84);
+
+    let req = test::TestRequest::get().uri("/healthz").to_request();
+    let health: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(health["websocket"]["live_subscribers"], 1);
+    assert_eq!(health["websocket"]["dropped_messages"], 84);
+
+    let req = test::TestRequest::get().uri("/metrics").to_request();
+    let body = test::call_and_read_body(&app, req).await;
+    let text = String::from_utf8(body.to_vec()).unwrap();
+    assert!(text.contains("soundsense_ws_dropped_total{stream=\"live\"} 84\n"));
+    assert!(text.contains("soundsense_ws_subscribers{stream=\"live\"} 1\n"));
+
+    drop(slow);
+    assert_eq!(hub.subscriber_count(), 0);
+}
+
+#[actix_web::test]
+async fn live_subscriber_gauge_follows_connected_sessions() {
+    // No anomaly scorer, so observations only matter to live subscribers
+    let hub = WsHub::new(None);
+    let addr = TestApp::new().hub(hub.clone()).serve(1);
+    let client = reqwest::Client::new();
+    let ingest = |value: f64| {
+        client
+            .post(format!("http://{}/ingest", addr))
+            .json(&serde_json::json!({
+                "patient_id": "p1",
+                "device_id": "d1",
+                "code": "sound",
+                "value": value,
+                "unit": "raw",
+            }))
+            .send()
+    };
+    let live_subscribers = || async {
+        let health: serde_json::Value = client
+            .get(format!("http://{}/healthz", addr))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        health["websocket"]["live_subscribers"].as_u64().unwrap()
+    };
+
+    // Publishing to nobody is fine
+    assert!(!hub.wants_observations());
+    assert!(ingest(1.0).await.unwrap().status().is_success());
+    assert_eq!(live_subscribers().await, 0);
+
+    let mut ws = WsClient::connect(addr, "/ws/live", &[&common::bearer_header("user")]).await;
+    assert_eq!(live_subscribers().await, 1);
+    assert!(hub.wants_observations());
+    let metrics = client
+        .get(format!("http://{}/metrics", addr))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(metrics.contains("soundsense_ws_subscribers{stream=\"live\"} 1\n"));
+
+    assert!(ingest(2.0).await.unwrap().status().is_success());
+    assert_eq!(ws.next_json().await["data"]["valueQuantity"]["value"], 2.0);
+
+    drop(ws);
+    for _ in 0..100 {
+        if hub.subscriber_count() == 0 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(live_subscribers().await, 0);
+    assert!(!hub.wants_observations());
+}
+
+#[actix_web::test]
+async fn octave_bands_are_a_weighted() {
+    let state = web::Data::new(Arc::new(Mutex::new(
+        common::demo_state().with_a_weighting(true),
+    )));
+    let app = test::init_service(App::new().app_data(state).configure(common::routes)).await;
+    let token = format!("Bearer {}", common::token("user"));
+    let flat = [70.0; 8];
+
+    // A flat 70 dB spectrum is about 77 dB(A): the 1-4 kHz bands dominate
+    let req = test::TestRequest::post()
+        .uri("/api/analysis/a-weight")
+        .insert_header(("authorization", token.clone()))
+        .set_json(serde_json::json!({
+            "bands": flat,
+            "frequencies": [63.0, 125.0, 250.0, 500.0, 1000.0, 2000.0, 4000.0, 8000.0],
+        }))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 200);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    let level = body["level_db_a"].as_f64().unwrap();
+    assert!((level - 76.96).abs() < 0.01, "{}", level);
+    let per_band = body["per_band_weighted"].as_array().unwrap();
+    assert_eq!(per_band.len(), 8);
+    assert!((per_band[0].as_f64().unwrap() - 43.8).abs() < 0.1);
+
+    let req = test::TestRequest::post()
+        .uri("/api/analysis/a-weight")
+        .insert_header(("authorization", token.clone()))
+        .set_json(serde_json::json!({ "bands": flat, "frequencies": [-1.0, 125.0, 250.0, 500.0, 1000.0, 2000.0, 4000.0, 8000.0] }))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 400);
+    // Numbers past f64 don't parse, and those that weigh to no number are
+    // refused before weighing
+    for body in [
+        r#"{"bands": [1e999, 70, 70, 70, 70, 70, 70, 70]}"#,
+        r#"{"bands": [1e300, 70, 70, 70, 70, 70, 70, 70]}"#,
+        r#"{"bands": [70, 70, 70, 70, 70, 70, 70, 70], "frequencies": [1e-200, 125, 250, 500, 1000, 2000, 4000, 8000]}"#,
+    ] {
+        let req = test::TestRequest::post()
+            .uri("/api/analysis/a-weight")
+            .insert_header(("authorization", token.clone()))
+            .insert_header(("content-type", "application/json"))
+            .set_payload(body)
+            .to_request();
+        assert_eq!(
+            test::call_service(&app, req).await.status(),
+            400,
+            "{}",
+            body
+        );
+    }
+    let req = test::TestRequest::post()
+        .uri("/api/analysis/a-weight")
+        .set_json(serde_json::json!({ "bands": flat }))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 401);
+
+    // Stored readings carry the A-weighted level next to the raw bands; a
+    // client can't supply it
+    let req = test::TestRequest::post()
+        .uri("/api/ingest/octave-bands")
+        .insert_header(("authorization", token.clone()))
+        .set_json(serde_json::json!({
+            "patient_id": "p-octave",
+            "device_id": "mic-1",
+            "bands": flat,
+            "overall_db": 79.0,
+            "calibrated_value": 1.0,
+            "ts": "2026-02-06T10:00:00Z",
+        }))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 200);
+    let obs: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(obs["valueQuantity"]["value"], 79.0);
+    let components = obs["component"].as_array().unwrap();
+    assert_eq!(components.len(), 9);
+    assert_eq!(
+        components[8]["code"]["coding"][0]["code"],
+        "sound-level-a-weighted"
+    );
+    assert_eq!(components[8]["valueQuantity"]["unit"], "dB(A)");
+    let weighted = components[8]["valueQuantity"]["value"].as_f64().unwrap();
+    assert!((weighted - level).abs() < 1e-9);
+}
+
+#[actix_web::test]
+async fn octave_band_ingest_and_spectrum() {
+    let app = TestApp::new().service().await;
+    let token = common::token("user");
+
+    let bands = [41.0, 42.5, 44.0, 47.5, 50.0, 48.0, 43.5, 38.0];
+    let req = test::TestRequest::post()
+        .uri("/api/ingest/octave-bands")
+        .insert_header(("authorization", format!("Bearer {}", token)))
+        .set_json(serde_json::json!({
+            "patient_id": "p-octave",
+            "device_id": "mic-1",
+            "ts": "2026-02-06T10:00:00Z",
+            "bands": bands,
+            "overall_db": 55.2,
+        }))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 200);
+
+    let obs: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(obs["valueQuantity"]["value"], 55.2);
+    let components = obs["component"].as_array().unwrap();
+    assert_eq!(components.len(), 8);
+    assert_eq!(
+        components[0]["code"]["coding"][0]["code"],
+        "sound-octave-63hz"
+    );
+    assert_eq!(
+        components[7]["code"]["coding"][0]["code"],
+        "sound-octave-8khz"
+    );
+    for (component, band) in components.iter().zip(bands) {
+        assert_eq!(component["valueQuantity"]["value"], band);
+    }
+
+    // Wrong number of bands is rejected
+    let req = test::TestRequest::post()
+        .uri("/api/ingest/octave-bands")
+        .insert_header(("authorization", format!("Bearer {}", token)))
+        .set_json(serde_json::json!({
+            "patient_id": "p-octave",
+            "device_id": "mic-1",
+            "ts": "2026-02-06T11:00:00Z",
+            "bands": [40.0, 41.0],
+            "overall_db": 50.0,
+        }))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 400);
+
+    let req = test::TestRequest::get()
+        .uri("/api/analysis/octave-spectrum?patient_id=p-octave&date=2026-02-06")
+        .insert_header(("authorization", format!("Bearer {}", token)))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 200);
+
+    let spectrum: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(spectrum["reading_count"], 1);
+    assert_eq!(spectrum["bands"][4]["center_hz"], 1000.0);
+    let level = spectrum["bands"][4]["level_db"].as_f64().unwrap();
+    assert!((level - 50.0).abs() < 1e-9);
+
+    // No readings on another day
+    let req = test::TestRequest::get()
+        .uri("/api/analysis/octave-spectrum?patient_id=p-octave&date=2026-02-07")
+        .insert_header(("authorization", format!("Bearer {}", token)))
+        .to_request();
+    let spectrum: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(spectrum["reading_count"], 0);
+    assert!(spectrum["bands"][0]["level_db"].is_null());
+
+    // Only tokens that may read the patient see the spectrum
+    let limited = JwtManager::new(JwtAlgorithm::HS256(common::TEST_JWT_SECRET.to_string()))
+        .generate_token(
+            Claims::new("erin".into(), "viewer".into(), None, 1)
+                .with_permitted_patients(vec!["p-other".into()]),
+        )
+        .unwrap();
+    for token in [common::token("device"), limited] {
+        let req = test::TestRequest::get()
+            .uri("/api/analysis/octave-spectrum?patient_id=p-octave&date=2026-02-06")
+            .insert_header(("authorization", format!("Bearer {}", token)))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 403);
+    }
+}
+
+/// Start a stand-in ML service whose `/analysis` records the query strings it
+/// was called with
+async fn spawn_recording_analysis_service() -> (String, Arc<std::sync::Mutex<Vec<String>>>) {
+    let queries = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let recorded = queries.clone();
+    let server = actix_web::HttpServer::new(move || {
+        let recorded = recorded.clone();
+        App::new().route(
+            "/analysis",
+            web::get().to(move |req: actix_web::HttpRequest| {
+                let recorded = recorded.clone();
+                async move {
+                    recorded
+                        .lock()
+                        .unwrap()
+                        .push(req.query_string().to_string());
+                    actix_web::HttpResponse::Ok().json(serde_json::json!({
+                        "success": true,
+                        "analysis": {
+                            "total_readings": 1200,
+                            "avg_level": 310.0,
+                            "std_level": 42.0,
+                            "min_level": 120.0,
+                            "max_level": 880.0
+                        }
+                    }))
+                }
+            }),
+        )
+    })
+    .workers(1)
+    .bind(("127.0.0.1", 0))
+    .unwrap();
+
+    let addr = server.addrs()[0];
+    actix_web::rt::spawn(server.run());
+    (format!("http://{}", addr), queries)
+}
+
+#[actix_web::test]
+async fn analysis_date_range_is_forwarded_to_ml_service() {
+    let state = web::Data::new(Arc::new(Mutex::new(common::demo_state())));
+    let (url, queries) = spawn_recording_analysis_service().await;
+    let ml_client = Arc::new(MlClient::new(url));
+    let app = test::init_service(
+        App::new()
+            .app_data(state)
+            .configure(common::routes)
+            .app_data(web::Data::new(ml_client)),
+    )
+    .await;
+    let auth = format!("Bearer {}", common::token("user"));
+
+    let req = test::TestRequest::get()
+        .uri("/api/ml/analysis?start=2026-02-01&end=2026-02-07")
+        .insert_header(("authorization", auth.clone()))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 200);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["analysis"]["total_readings"], 1200);
+    assert_eq!(body["source"], "ml");
+
+    assert_eq!(
+        queries.lock().unwrap().as_slice(),
+        ["start=2026-02-01T00%3A00%3A00Z&end=2026-02-08T00%3A00%3A00Z"]
+    );
+
+    // Inverted and overlong ranges never reach the ML service
+    for uri in [
+        "/api/ml/analysis?start=2026-02-07&end=2026-02-01",
+        "/api/ml/analysis?start=2026-01-01&end=2026-03-01",
+        "/api/ml/analysis?start=2026-02-01",
+        "/api/ml/analysis?start=yesterday&end=2026-02-01",
+    ] {
+        let req = test::TestRequest::get()
+            .uri(uri)
+            .insert_header(("authorization", auth.clone()))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 400, "{uri}");
+    }
+    assert_eq!(queries.lock().unwrap().len(), 1);
+}
+
+#[actix_web::test]
+async fn analysis_date_range_falls_back_to_local_stats() {
+    let state = web::Data::new(Arc::new(Mutex::new(common::demo_state())));
+    // The last reading is in the final second of the range's last day, and
+    // the next one just past it
+    for (ts, value) in [
+        ("2026-02-03T09:00:00Z", 200.0),
+        ("2026-02-03T09:00:00Z", 300.0),
+        ("2026-02-07T23:59:59.500Z", 100.0),
+        ("2026-02-08T00:00:00Z", 900.0),
+    ] {
+        let reading = SensorReading {
+            patient_id: "p1".into(),
+            device_id: "d1".into(),
+            code: SignalCode::Sound,
+            value,
+            unit: "raw".into(),
+            ts: ts.parse().unwrap(),
+            ts_source: TimestampSource::Device,
+            metadata: Default::default(),
+        };
+        state.lock().await.push(reading, None).await.unwrap();
+    }
+    // Nothing listens here
+    let ml_client = Arc::new(MlClient::new("http://127.0.0.1:9".into()));
+    let app = test::init_service(
+        App::new()
+            .app_data(state)
+            .configure(common::routes)
+            .app_data(web::Data::new(ml_client)),
+    )
+    .await;
+
+    let req = test::TestRequest::get()
+        .uri("/api/ml/analysis?start=2026-02-01&end=2026-02-07")
+        .insert_header(("authorization", format!("Bearer {}", common::token("user"))))
+        .to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+
+    assert_eq!(body["source"], "local");
+    assert_eq!(body["analysis"]["total_readings"], 3);
+    assert_eq!(body["analysis"]["avg_level"], 200.0);
+    assert_eq!(body["analysis"]["peak_hour"], 9);
+    assert_eq!(body["analysis"]["quietest_hour"], 23);
+}
+
+#[actix_web::test]
+async fn observation_stream_sends_a_well_formed_bundle_in_chunks() {
+    let mut state = common::demo_state().with_max_readings(1000);
+    let start: chrono::DateTime<chrono::Utc> = "2026-03-01T00:00:00Z".parse().unwrap();
+    let readings = (0..1000)
+        .map(|i| {
+            StoredReading::new(SensorReading {
+                patient_id: format!("p{}", i % 3),
+                device_id: "d1".into(),
+                code: SignalCode::Sound,
+                value: 40.0 + (i % 50) as f64,
+                unit: "dB".into(),
+                ts: start + chrono::Duration::seconds(i),
+                ts_source: TimestampSource::Device,
+                metadata: Default::default(),
+            })
+        })
+        .collect();
+    state.push_many(readings, None).await.unwrap();
+    let app = TestApp::with_state(state).service().await;
+
+    let req = test::TestRequest::get()
+        .uri("/api/fhir/Observation/stream")
+        .insert_header(("authorization", format!("Bearer {}", common::token("user"))))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.headers().get("x-total-count").unwrap(), "1000");
+    // Sent as it is produced, not with a precomputed length
+    assert_eq!(
+        actix_web::body::MessageBody::size(resp.response().body()),
+        actix_web::body::BodySize::Stream
+    );
+
+    let body = test::read_body(resp).await;
+    assert!(body.starts_with(br#"{"resourceType":"Bundle","type":"collection","entry":["#));
+    let bundle: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(bundle["total"], 1000);
+    let entries = bundle["entry"].as_array().unwrap();
+    assert_eq!(entries.len(), 1000);
+    // Newest first, every reading once
+    let times: Vec<&str> = entries
+        .iter()
+        .map(|e| e["resource"]["effectiveDateTime"].as_str().unwrap())
+        .collect();
+    assert!(times.windows(2).all(|w| w[0] > w[1]));
+
+    // Unknown codes are refused, and a token is required
+    let req = test::TestRequest::get()
+        .uri("/api/fhir/Observation/stream?code=bogus")
+        .insert_header(("authorization", format!("Bearer {}", common::token("user"))))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 400);
+    let req = test::TestRequest::get()
+        .uri("/api/fhir/Observation/stream")
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 401);
+
+    // Patient permissions apply, and devices cannot read
+    let limited = JwtManager::new(JwtAlgorithm::HS256(common::TEST_JWT_SECRET.to_string()))
+        .generate_token(
+            Claims::new("erin".into(), "viewer".into(), None, 1)
+                .with_permitted_patients(vec!["p1".into()]),
+        )
+        .unwrap();
+    let req = test::TestRequest::get()
+        .uri("/api/fhir/Observation/stream")
+        .insert_header(("authorization", format!("Bearer {}", limited)))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.headers().get("x-total-count").unwrap(), "333");
+    let bundle: serde_json::Value = test::read_body_json(resp).await;
+    let entries = bundle["entry"].as_array().unwrap();
+    assert_eq!(entries.len(), 333);
+    assert!(entries
+        .iter()
+        .all(|e| e["resource"]["subject"]["reference"] == "Patient/p1"));
+    let req = test::TestRequest::get()
+        .uri("/api/fhir/Observation/stream")
+        .insert_header((
+            "authorization",
+            format!("Bearer {}", common::token("device")),
+        ))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 403);
+}
+
+#[actix_web::test]
+async fn observations_are_found_by_text_and_tags() {
+    let app = TestApp::new().service().await;
+    let auth = format!("Bearer {}", common::token("user"));
+
+    let mut ids = Vec::new();
+    for (patient_id, metadata) in [
+        (
+            "p-aid",
+            serde_json::json!({ "tags": ["hearing aid"], "location": "clinic" }),
+        ),
+        (
+            "p-aid",
+            serde_json::json!({ "tags": ["Headset"], "location": "ward 3" }),
+        ),
+        (
+            "p-night",
+            serde_json::json!({ "tags": ["night shift", "hearing test"] }),
+        ),
+    ] {
+        let req = test::TestRequest::post()
+            .uri("/api/ingest")
+            .insert_header(("authorization", auth.clone()))
+            .set_json(serde_json::json!({
+                "patient_id": patient_id,
+                "device_id": "d1",
+                "code": "sound",
+                "value": 200.0,
+                "unit": "raw",
+                "metadata": metadata,
+            }))
+            .to_request();
+        let obs: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        ids.push(obs["id"].as_str().unwrap().to_string());
+    }
+    // Notes are searched too
+    let req = test::TestRequest::put()
+        .uri(&format!("/api/fhir/Observation/{}", ids[2]))
+        .insert_header(("authorization", auth.clone()))
+        .set_json(
+            serde_json::json!({ "status": "amended", "note": "Patient forgot the hearing aid" }),
+        )
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 200);
+
+    let search = |query: &str, auth: &str| {
+        test::TestRequest::get()
+            .uri(&format!("/api/fhir/Observation?{}", query))
+            .insert_header(("authorization", auth.to_string()))
+            .to_request()
+    };
+
+    let bundle: serde_json::Value =
+        test::call_and_read_body_json(&app, search("_text=hearing+aid", &auth)).await;
+    assert_eq!(bundle["type"], "searchset");
+    assert_eq!(bundle["total"], 2);
+    let mut found: Vec<&str> = bundle["entry"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|e| e["resource"]["id"].as_str().unwrap())
+        .collect();
+    found.sort();
+    let mut expected = vec![ids[0].as_str(), ids[2].as_str()];
+    expected.sort();
+    assert_eq!(found, expected);
+    for entry in bundle["entry"].as_array().unwrap() {
+        assert_eq!(entry["search"]["mode"], "match");
+        assert!(entry["search"]["score"].as_f64().unwrap() > 0.0);
+    }
+    let scores: Vec<f64> = bundle["entry"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|e| e["search"]["score"].as_f64().unwrap())
+        .collect();
+    assert!(scores[0] >= scores[1]);
+
+    let bundle: serde_json::Value =
+        test::call_and_read_body_json(&app, search("_text=ward", &auth)).await;
+    assert_eq!(bundle["total"], 1);
+    assert_eq!(bundle["entry"][0]["resource"]["id"], ids[1]);
+
+    // tsquery operators are just separators
+    let bundle: serde_json::Value =
+        test::call_and_read_body_json(&app, search("_text=hearing%3A*%20%26%20!aid", &auth)).await;
+    assert_eq!(bundle["total"], 2);
+    for query in ["_text=%26%7C!", "_text=aid&offset=10"] {
+        let resp = test::call_service(&app, search(query, &auth)).await;
+        assert_eq!(resp.status(), 400, "{}", query);
+    }
+
+    // Patient permissions still apply
+    let limited = JwtManager::new(JwtAlgorithm::HS256(common::TEST_JWT_SECRET.to_string()))
+        .generate_token(
+            Claims::new("erin".into(), "viewer".into(), None, 1)
+                .with_permitted_patients(vec!["p-aid".into()]),
+        )
+        .unwrap();
+    let limited = format!("Bearer {}", limited);
+    let bundle: serde_json::Value =
+        test::call_and_read_body_json(&app, search("_text=hearing+aid", &limited)).await;
+    assert_eq!(bundle["total"], 1);
+    assert_eq!(bundle["entry"][0]["resource"]["id"], ids[0]);
+
+    // Tag suggestions
+    let suggest = |prefix: &str, auth: &str| {
+        test::TestRequest::get()
+            .uri(&format!("/api/fhir/Observation/$suggest?prefix={}", prefix))
+            .insert_header(("authorization", auth.to_string()))
+            .to_request()
+    };
+    let tags: Vec<String> = test::call_and_read_body_json(&app, suggest("hea", &auth)).await;
+    assert_eq!(tags, vec!["Headset", "hearing aid", "hearing test"]);
+    let tags: Vec<String> = test::call_and_read_body_json(&app, suggest("hea", &limited)).await;
+    assert_eq!(tags, vec!["Headset", "hearing aid"]);
+    let tags: Vec<String> = test::call_and_read_body_json(&app, suggest("%25", &auth)).await;
+    assert!(tags.is_empty());
+    let resp = test::call_service(&app, suggest("hea", "Bearer nope")).await;
+    assert_eq!(resp.status(), 401);
+
+    // Device tokens may write readings but not read them back
+    let device = format!("Bearer {}", common::token("device"));
+    let resp = test::call_service(&app, suggest("hea", &device)).await;
+    assert_eq!(resp.status(), 403);
+    let resp = test::call_service(&app, search("_text=hearing+aid", &device)).await;
+    assert_eq!(resp.status(), 403);
+    let req = test::TestRequest::get()
+        .uri("/api/fhir/Observation?limit=10")
+        .insert_header(("authorization", device))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 403);
+}
+
+#[actix_web::test]
+async fn observation_amendment_and_status_transitions() {
+    let app = TestApp::new().service().await;
+    let auth = format!("Bearer {}", common::token("user"));
+
+    let mut ids = Vec::new();
+    for value in [200.0, 1000.0] {
+        let req = test::TestRequest::post()
+            .uri("/api/ingest")
+            .insert_header(("authorization", auth.clone()))
+            .set_json(serde_json::json!({
+                "patient_id": "p-amend",
+                "device_id": "d1",
+                "code": "sound",
+                "value": value,
+                "unit": "raw",
+                "ts": "2026-02-03T10:00:00Z",
+            }))
+            .to_request();
+        let obs: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(obs["status"], "final");
+        ids.push(obs["id"].as_str().unwrap().to_string());
+    }
+
+    let amend = |id: &str, body: serde_json::Value| {
+        test::TestRequest::put()
+            .uri(&format!("/api/fhir/Observation/{}", id))
+            .insert_header(("authorization", auth.clone()))
+            .set_json(body)
+            .to_request()
+    };
+
+    // The sensor was knocked over during the second reading
+    let resp = test::call_service(
+        &app,
+        amend(
+            &ids[1],
+            serde_json::json!({ "status": "entered-in-error", "note": "sensor knocked over" }),
+        ),
+    )
+    .await;
+    assert_eq!(resp.status(), 200);
+    let obs: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(obs["id"], ids[1]);
+    assert_eq!(obs["status"], "entered-in-error");
+    assert_eq!(obs["note"][0]["text"], "sensor knocked over");
+
+    // The change is reflected in queries
+    let req = test::TestRequest::get()
+        .uri("/api/fhir/Observation?limit=10")
+        .insert_header(("authorization", auth.clone()))
+        .to_request();
+    let bundle: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    let stored = bundle["entry"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|e| e["resource"]["id"] == ids[1])
+        .unwrap();
+    assert_eq!(stored["resource"]["status"], "entered-in-error");
+
+    // ...and the reading no longer counts towards statistics
+    let req = test::TestRequest::get()
+        .uri("/api/ml/analysis?start=2026-02-03&end=2026-02-03")
+        .insert_header(("authorization", auth.clone()))
+        .to_request();
+    let analysis: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(analysis["source"], "local");
+    assert_eq!(analysis["analysis"]["total_readings"], 1);
+    assert_eq!(analysis["analysis"]["max_level"], 200.0);
+
+    // entered-in-error is terminal, and nothing goes back to final
+    let resp = test::call_service(
+        &app,
+        amend(&ids[1], serde_json::json!({ "status": "amended" })),
+    )
+    .await;
+    assert_eq!(resp.status(), 409);
+    let resp = test::call_service(
+        &app,
+        amend(&ids[0], serde_json::json!({ "status": "final" })),
+    )
+    .await;
+    assert_eq!(resp.status(), 409);
+
+    let resp = test::call_service(
+        &app,
+        amend(
+            &uuid::Uuid::new_v4().to_string(),
+            serde_json::json!({ "status": "amended" }),
+        ),
+    )
+    .await;
+    assert_eq!(resp.status(), 404);
+
+    // Only the status (and a note) may be changed
+    let resp = test::call_service(
+        &app,
+        amend(
+            &ids[0],
+            serde_json::json!({ "status": "amended", "value": 1.0 }),
+        ),
+    )
+    .await;
+    assert_eq!(resp.status(), 400);
+    let resp = test::call_service(
+        &app,
+        amend("not-a-uuid", serde_json::json!({ "status": "amended" })),
+    )
+    .await;
+    assert_eq!(resp.status(), 400);
+
+    // Patient permissions apply to amendments too
+    let limited = JwtManager::new(JwtAlgorithm::HS256(common::TEST_JWT_SECRET.to_string()))
+        .generate_token(
+            Claims::new("erin".into(), "user".into(), None, 1)
+                .with_permitted_patients(vec!["p-other".into()]),
+        )
+        .unwrap();
+    let req = test::TestRequest::put()
+        .uri(&format!("/api/fhir/Observation/{}", ids[0]))
+        .insert_header(("authorization", format!("Bearer {}", limited)))
+        .set_json(serde_json::json!({ "status": "amended" }))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 403);
+}
+
+#[actix_web::test]
+async fn deleted_observations_are_hidden_unless_requested_by_admin() {
+    let app = TestApp::new().service().await;
+    let user = format!("Bearer {}", common::token("user"));
+    let admin = format!("Bearer {}", common::token("admin"));
+
+    let mut ids = Vec::new();
+    for value in [60.0, 70.0] {
+        let req = test::TestRequest::post()
+            .uri("/api/ingest")
+            .insert_header(("authorization", user.clone()))
+            .set_json(serde_json::json!({
+                "patient_id": "p-del",
+                "device_id": "d1",
+                "code": "sound",
+                "value": value,
+                "unit": "dB",
+            }))
+            .to_request();
+        let obs: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        ids.push(obs["id"].as_str().unwrap().to_string());
+    }
+
+    let delete = |id: &str, auth: &str| {
+        test::TestRequest::delete()
+            .uri(&format!("/api/fhir/Observation/{}", id))
+            .insert_header(("authorization", auth.to_string()))
+            .to_request()
+    };
+    let list = |query: &str, auth: &str| {
+        test::TestRequest::get()
+            .uri(&format!("/api/fhir/Observation{}", query))
+            .insert_header(("authorization", auth.to_string()))
+            .to_request()
+    };
+
+    // Only admins delete, and only once
+    let resp = test::call_service(&app, delete(&ids[0], &user)).await;
+    assert_eq!(resp.status(), 401);
+    let resp = test::call_service(&app, delete(&ids[0], &admin)).await;
+    assert_eq!(resp.status(), 204);
+    let resp = test::call_service(&app, delete(&ids[0], &admin)).await;
+    assert_eq!(resp.status(), 404);
+
+    // Gone from normal queries, and no longer amendable
+    let resp = test::call_service(&app, list("", &user)).await;
+    assert_eq!(resp.headers().get("x-total-count").unwrap(), "1");
+    let bundle: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(bundle["entry"][0]["resource"]["id"], ids[1].as_str());
+    let req = test::TestRequest::put()
+        .uri(&format!("/api/fhir/Observation/{}", ids[0]))
+        .insert_header(("authorization", user.clone()))
+        .set_json(serde_json::json!({ "status": "amended" }))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 404);
+
+    // Admins can still see it, flagged entered-in-error
+    let resp = test::call_service(&app, list("?include_deleted=true", &user)).await;
+    assert_eq!(resp.status(), 403);
+    let resp = test::call_service(&app, list("?include_deleted=true", &admin)).await;
+    assert_eq!(resp.headers().get("x-total-count").unwrap(), "2");
+    let bundle: serde_json::Value = test::read_body_json(resp).await;
+    let deleted = bundle["entry"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|e| e["resource"]["id"] == ids[0].as_str())
+        .unwrap();
+    assert_eq!(deleted["resource"]["status"], "entered-in-error");
+}
+
+#[actix_web::test]
+async fn patient_erasure_removes_readings_everywhere() {
+    let hub = WsHub::new(Some(AnomalyScorer::new(
+        AnomalyDetector::new(100, 10, 3.0),
+        None,
+    )));
+    let dir = std::env::temp_dir().join(format!("soundsense-erase-test-{}", uuid::Uuid::new_v4()));
+    let app = TestApp::new()
+        .hub(hub.clone())
+        .exports(BulkExports::new(&dir))
+        .service()
+        .await;
+    let user = format!("Bearer {}", common::token("user"));
+    let admin = format!("Bearer {}", common::token("admin"));
+
+    let ingest = |patient_id: &str, device_id: &str, value: f64| {
+        test::TestRequest::post()
+            .uri("/api/ingest")
+            .insert_header(("authorization", user.clone()))
+            .set_json(serde_json::json!({
+                "patient_id": patient_id,
+                "device_id": device_id,
+                "code": "sound",
+                "value": value,
+                "unit": "raw",
+            }))
+            .to_request()
+    };
+    // An outlier after a steady baseline leaves an anomaly in the backlog
+    for i in 0..30 {
+        let resp =
+            test::call_service(&app, ingest("p-gone", "d-gone", 200.0 + (i % 7) as f64)).await;
+        assert!(resp.status().is_success());
+    }
+    let resp = test::call_service(&app, ingest("p-gone", "d-gone", 950.0)).await;
+    assert!(resp.status().is_success());
+    let resp = test::call_service(&app, ingest("p-gone", "d-shared", 210.0)).await;
+    assert!(resp.status().is_success());
+    let resp = test::call_service(&app, ingest("p-stays", "d-shared", 220.0)).await;
+    assert!(resp.status().is_success());
+    let since = chrono::Utc::now() - chrono::Duration::hours(1);
+    assert_eq!(hub.recent_anomalies("p-gone", since).len(), 1);
+
+    // A finished bulk export holding the patient's readings
+    let req = test::TestRequest::post()
+        .uri("/api/fhir/$export")
+        .insert_header(("authorization", admin.clone()))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 202);
+    let location = resp.headers().get("content-location").unwrap();
+    let export_path = format!(
+        "/api/{}",
+        location.to_str().unwrap().split_once("/api/").unwrap().1
+    );
+    let export_status = || {
+        test::TestRequest::get()
+            .uri(&export_path)
+            .insert_header(("authorization", admin.clone()))
+            .to_request()
+    };
+    for _ in 0..200 {
+        if test::call_service(&app, export_status()).await.status() == 200 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(
+        test::call_service(&app, export_status()).await.status(),
+        200
+    );
+
+    let erase = |patient_id: &str, confirm: &str, auth: &str| {
+        test::TestRequest::delete()
+            .uri(&format!("/api/patients/{}/observations", patient_id))
+            .insert_header(("authorization", auth.to_string()))
+            .set_json(serde_json::json!({ "confirm": confirm }))
+            .to_request()
+    };
+
+    // Admins only, and only with the patient id repeated
+    let resp = test::call_service(&app, erase("p-gone", "p-gone", &user)).await;
+    assert_eq!(resp.status(), 401);
+    let resp = test::call_service(&app, erase("p-gone", "p-stays", &admin)).await;
+    assert_eq!(resp.status(), 400);
+    let resp = test::call_service(&app, erase("p-nobody", "p-nobody", &admin)).await;
+    assert_eq!(resp.status(), 404);
+
+    let resp = test::call_service(&app, erase("p-gone", "p-gone", &admin)).await;
+    assert_eq!(resp.status(), 200);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["deleted"], 32);
+    // Nothing is left to erase
+    let resp = test::call_service(&app, erase("p-gone", "p-gone", &admin)).await;
+    assert_eq!(resp.status(), 404);
+
+    // Gone from the backlog, exports and the bundle; the other patient is
+    // untouched
+    assert!(hub.recent_anomalies("p-gone", since).is_empty());
+    assert_eq!(
+        test::call_service(&app, export_status()).await.status(),
+        404
+    );
+    assert!(std::fs::read_dir(&dir).unwrap().next().is_none());
+    let req = test::TestRequest::get()
+        .uri("/api/fhir/Observation?_count=100")
+        .insert_header(("authorization", admin.clone()))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.headers().get("x-total-count").unwrap(), "1");
+    let bundle: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(
+        bundle["entry"][0]["resource"]["subject"]["reference"],
+        "Patient/p-stays"
+    );
+
+    // Only the device no one else used is forgotten
+    let stats = |device_id: &str| {
+        test::TestRequest::get()
+            .uri(&format!("/api/devices/{}/stats", device_id))
+            .insert_header(("authorization", admin.clone()))
+            .to_request()
+    };
+    assert_eq!(
+        test::call_service(&app, stats("d-gone")).await.status(),
+        404
+    );
+    assert_eq!(
+        test::call_service(&app, stats("d-shared")).await.status(),
+        200
+    );
+}
+
+#[actix_web::test]
+async fn bulk_export_runs_in_the_background_and_serves_ndjson() {
+    let dir = std::env::temp_dir().join(format!("soundsense-export-test-{}", uuid::Uuid::new_v4()));
+    // Small pages, so the export takes several steps
+    let app = TestApp::new()
+        .exports(BulkExports::new(&dir).with_page_size(2))
+        .service()
+        .await;
+    let admin = format!("Bearer {}", common::token("admin"));
+    let other = format!("Bearer {}", common::token_for("someone-else", "user"));
+
+    for (i, patient_id) in ["p1", "p1", "p2", "p2", "p3"].iter().enumerate() {
+        let req = test::TestRequest::post()
+            .uri("/api/ingest")
+            .insert_header(("authorization", admin.clone()))
+            .set_json(serde_json::json!({
+                "patient_id": patient_id,
+                "device_id": "d1",
+                "code": "sound",
+                "value": 60.0 + i as f64,
+                "unit": "dB",
+                "ts": format!("2026-03-01T08:00:0{}Z", i),
+            }))
+            .to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+    }
+
+    let kick_off = |query: &str| {
+        test::TestRequest::post()
+            .uri(&format!("/api/fhir/$export{}", query))
+            .insert_header(("authorization", admin.clone()))
+            .to_request()
+    };
+    assert_eq!(
+        test::call_service(&app, kick_off("?_type=Patient"))
+            .await
+            .status(),
+        400
+    );
+    let resp = test::call_service(&app, kick_off("")).await;
+    assert_eq!(resp.status(), 202);
+    let location = resp
+        .headers()
+        .get("content-location")
+        .unwrap()
+        .to_str()
+        .unwrap();
+    let status_path = format!("/api/{}", location.split_once("/api/").unwrap().1);
+
+    let get = |path: &str, auth: &str| {
+        test::TestRequest::get()
+            .uri(path)
+            .insert_header(("authorization", auth.to_string()))
+            .to_request()
+    };
+    // Someone else's export is not visible
+    let resp = test::call_service(&app, get(&status_path, &other)).await;
+    assert_eq!(resp.status(), 404);
+
+    let mut manifest = serde_json::Value::Null;
+    for _ in 0..200 {
+        let resp = test::call_service(&app, get(&status_path, &admin)).await;
+        if resp.status() == 200 {
+            manifest = test::read_body_json(resp).await;
+            break;
+        }
+        assert_eq!(resp.status(), 202);
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(manifest["requiresAccessToken"], true);
+    assert_eq!(manifest["output"][0]["type"], "Observation");
+    assert_eq!(manifest["output"][0]["count"], 5);
+
+    let url = manifest["output"][0]["url"].as_str().unwrap();
+    let file_path = format!("/api/{}", url.split_once("/api/").unwrap().1);
+    let resp = test::call_service(&app, get(&file_path, &admin)).await;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(
+        resp.headers().get("content-type").unwrap(),
+        "application/fhir+ndjson"
+    );
+    assert_eq!(resp.headers().get("content-encoding").unwrap(), "gzip");
+    let body = gunzip(&test::read_body(resp).await);
+    let lines: Vec<serde_json::Value> = body
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    // Oldest first, each reading once
+    let values: Vec<f64> = lines
+        .iter()
+        .map(|obs| {
+            assert_eq!(obs["resourceType"], "Observation");
+            obs["valueQuantity"]["value"].as_f64().unwrap()
+        })
+        .collect();
+    assert_eq!(values, vec![60.0, 61.0, 62.0, 63.0, 64.0]);
+
+    // `_since` limits the export to later readings
+    let resp = test::call_service(&app, kick_off("?_since=2026-03-01T08:00:03Z")).await;
+    let location = resp
+        .headers()
+        .get("content-location")
+        .unwrap()
+        .to_str()
+        .unwrap();
+    let status_path = format!("/api/{}", location.split_once("/api/").unwrap().1);
+    let mut manifest = serde_json::Value::Null;
+    for _ in 0..200 {
+        let resp = test::call_service(&app, get(&status_path, &admin)).await;
+        if resp.status() == 200 {
+            manifest = test::read_body_json(resp).await;
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(manifest["output"][0]["count"], 2);
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+fn gunzip(compressed: &[u8]) -> String {
+    use std::io::Read;
+    let mut text = String::new();
+    flate2::read::GzDecoder::new(compressed)
+        .read_to_string(&mut text)
+        .unwrap();
+    text
+}
+
+#[actix_web::test]
+async fn admins_export_observations_in_gzip_files_and_can_cancel() {
+    let dir = std::env::temp_dir().join(format!("soundsense-export-test-{}", uuid::Uuid::new_v4()));
+    let app = TestApp::new()
+        .exports(BulkExports::new(&dir).with_page_size(2).with_file_size(3))
+        .service()
+        .await;
+    let admin = format!("Bearer {}", common::token("admin"));
+    let user = format!("Bearer {}", common::token("user"));
+
+    for (i, patient_id) in ["p1", "p2", "p3", "p1", "p2", "p3", "p1"]
+        .iter()
+        .enumerate()
+    {
+        let req = test::TestRequest::post()
+            .uri("/api/ingest")
+            .insert_header(("authorization", admin.clone()))
+            .set_json(serde_json::json!({
+                "patient_id": patient_id,
+                "device_id": "d1",
+                "code": "sound",
+                "value": 60.0 + i as f64,
+                "unit": "dB",
+                "ts": format!("2026-03-01T08:00:0{}Z", i),
+            }))
+            .to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+    }
+
+    let request = |method: test::TestRequest, path: &str, auth: &str| {
+        method
+            .uri(path)
+            .insert_header(("authorization", auth.to_string()))
+            .to_request()
+    };
+    let kick_off = "/api/fhir/Observation/$export?_since=2026-03-01T08:00:01Z";
+    let resp = test::call_service(&app, request(test::TestRequest::get(), kick_off, &user)).await;
+    assert_eq!(resp.status(), 401);
+
+    let resp = test::call_service(&app, request(test::TestRequest::get(), kick_off, &admin)).await;
+    assert_eq!(resp.status(), 202);
+    let location = resp.headers().get("content-location").unwrap();
+    let status_path = format!(
+        "/api/{}",
+        location.to_str().unwrap().split_once("/api/").unwrap().1
+    );
+
+    let mut manifest = serde_json::Value::Null;
+    for _ in 0..200 {
+        let resp = test::call_service(
+            &app,
+            request(test::TestRequest::get(), &status_path, &admin),
+        )
+        .await;
+        if resp.status() == 200 {
+            manifest = test::read_body_json(resp).await;
+            break;
+        }
+        assert_eq!(resp.status(), 202);
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    // Six readings from `_since` on, three per file
+    let output = manifest["output"].as_array().unwrap();
+    assert_eq!(output.len(), 2);
+    let mut values = Vec::new();
+    for file in output {
+        assert_eq!(file["type"], "Observation");
+        let url = file["url"].as_str().unwrap();
+        let file_path = format!("/api/{}", url.split_once("/api/").unwrap().1);
+        let resp =
+            test::call_service(&app, request(test::TestRequest::get(), &file_path, &admin)).await;
+        assert_eq!(resp.status(), 200);
+        let body = gunzip(&test::read_body(resp).await);
+        let lines: Vec<serde_json::Value> = body
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len() as u64, file["count"].as_u64().unwrap());
+        for obs in lines {
+            assert_eq!(obs["resourceType"], "Observation");
+            assert!(obs["subject"]["reference"]
+                .as_str()
+                .unwrap()
+                .starts_with("Patient/"));
+            values.push(obs["valueQuantity"]["value"].as_f64().unwrap());
+        }
+    }
+    assert_eq!(values, vec![61.0, 62.0, 63.0, 64.0, 65.0, 66.0]);
+
+    // Only files listed in the manifest are served
+    let resp = test::call_service(
+        &app,
+        request(
+            test::TestRequest::get(),
+            &format!("{}/..%2F..%2Fsecrets", status_path),
+            &admin,
+        ),
+    )
+    .await;
+    assert_eq!(resp.status(), 404);
+
+    // Deleting a finished export removes it and its files
+    let job_dir = dir.join(status_path.rsplit('/').next().unwrap());
+    assert!(job_dir.exists());
+    let resp = test::call_service(
+        &app,
+        request(test::TestRequest::delete(), &status_path, &admin),
+    )
+    .await;
+    assert_eq!(resp.status(), 202);
+    let resp = test::call_service(
+        &app,
+        request(test::TestRequest::get(), &status_path, &admin),
+    )
+    .await;
+    assert_eq!(resp.status(), 404);
+    assert!(!job_dir.exists());
+
+    // A running export stops and cleans up after itself
+    let resp = test::call_service(&app, request(test::TestRequest::get(), kick_off, &admin)).await;
+    assert_eq!(resp.status(), 202);
+    let location = resp.headers().get("content-location").unwrap();
+    let status_path = format!(
+        "/api/{}",
+        location.to_str().unwrap().split_once("/api/").unwrap().1
+    );
+    let resp = test::call_service(
+        &app,
+        request(test::TestRequest::delete(), &status_path, &admin),
+    )
+    .await;
+    assert_eq!(resp.status(), 202);
+    let resp = test::call_service(
+        &app,
+        request(test::TestRequest::get(), &status_path, &admin),
+    )
+    .await;
+    assert_eq!(resp.status(), 404);
+    let job_dir = dir.join(status_path.rsplit('/').next().unwrap());
+    for _ in 0..200 {
+        if !job_dir.exists() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert!(!job_dir.exists());
+
+    // A new export may start once the cancelled one is gone
+    let resp = test::call_service(&app, request(test::TestRequest::get(), kick_off, &admin)).await;
+    assert_eq!(resp.status(), 202);
+
+    std::fs::remove_dir_all(dir).ok();
+}
+
+#[actix_web::test]
+async fn measure_report_counts_exposed_patients() {
+    let app = TestApp::new().service().await;
+    let user = format!("Bearer {}", common::token("user"));
+    let admin = format!("Bearer {}", common::token("admin"));
+
+    let readings = [
+        // Loud on average (energy mean of 90 and 80 dB is ~87.4 dB)
+        ("p-loud", 90.0, "dB", "2026-03-02T08:00:00Z"),
+        ("p-loud", 80.0, "dB", "2026-03-02T09:00:00Z"),
+        ("p-quiet", 60.0, "dBA", "2026-03-02T08:00:00Z"),
+        // Raw ADC counts can't be compared with the limit
+        ("p-raw", 900.0, "raw", "2026-03-02T08:00:00Z"),
+        // Outside the period
+        ("p-later", 100.0, "dB", "2026-03-05T08:00:00Z"),
+    ];
+    let mut ids = Vec::new();
+    for (patient_id, value, unit, ts) in readings {
+        let req = test::TestRequest::post()
+            .uri("/api/ingest")
+            .insert_header(("authorization", user.clone()))
+            .set_json(serde_json::json!({
+                "patient_id": patient_id,
+                "device_id": "d1",
+                "code": "sound",
+                "value": value,
+                "unit": unit,
+                "ts": ts,
+            }))
+            .to_request();
+        let obs: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        ids.push(obs["id"].as_str().unwrap().to_string());
+    }
+
+    // A reading entered in error doesn't make the quiet patient loud
+    let req = test::TestRequest::post()
+        .uri("/api/ingest")
+        .insert_header(("authorization", user.clone()))
+        .set_json(serde_json::json!({
+            "patient_id": "p-quiet",
+            "device_id": "d1",
+            "code": "sound",
+            "value": 120.0,
+            "unit": "dB",
+            "ts": "2026-03-02T10:00:00Z",
+        }))
+        .to_request();
+    let obs: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    let req = test::TestRequest::put()
+        .uri(&format!(
+            "/api/fhir/Observation/{}",
+            obs["id"].as_str().unwrap()
+        ))
+        .insert_header(("authorization", user.clone()))
+        .set_json(serde_json::json!({ "status": "entered-in-error" }))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 200);
+
+    let report = |auth: &str, query: &str| {
+        test::TestRequest::get()
+            .uri(&format!("/api/fhir/MeasureReport?{}", query))
+            .insert_header(("authorization", auth.to_string()))
+            .to_request()
+    };
+
+    let resp = test::call_service(
+        &app,
+        report(&admin, "period_start=2026-03-01&period_end=2026-03-03"),
+    )
+    .await;
+    assert_eq!(resp.status(), 200);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["resourceType"], "MeasureReport");
+    assert_eq!(body["status"], "complete");
+    assert_eq!(body["type"], "summary");
+    assert_eq!(body["period"]["start"], "2026-03-01T00:00:00Z");
+    assert_eq!(body["period"]["end"], "2026-03-03T23:59:59Z");
+
+    let group = &body["group"][0];
+    assert_eq!(group["code"]["coding"][0]["code"], "sound");
+    let count = |population: &str| {
+        group["population"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|p| p["code"]["coding"][0]["code"] == population)
+            .unwrap()["count"]
+            .as_i64()
+            .unwrap()
+    };
+    assert_eq!(count("initial-population"), 3);
+    assert_eq!(count("denominator"), 2);
+    assert_eq!(count("numerator"), 1);
+    assert_eq!(group["measureScore"]["value"], 0.5);
+
+    // Admins only
+    let resp = test::call_service(
+        &app,
+        report(&user, "period_start=2026-03-01&period_end=2026-03-03"),
+    )
+    .await;
+    assert_eq!(resp.status(), 401);
+
+    let resp = test::call_service(
+        &app,
+        report(&admin, "period_start=2026-03-03&period_end=2026-03-01"),
+    )
+    .await;
+    assert_eq!(resp.status(), 400);
+    let resp = test::call_service(
+        &app,
+        report(&admin, "period_start=yesterday&period_end=2026-03-01"),
+    )
+    .await;
+    assert_eq!(resp.status(), 400);
+}
+
+/// Fake ML service for per-patient analysis. It records each request, GET
+/// query strings as strings and POST bodies as JSON; with `filters_patients`
+/// unset it behaves like a service predating the `patient_id` filter.
+async fn spawn_patient_analysis_service(
+    filters_patients: bool,
+) -> (String, Arc<std::sync::Mutex<Vec<serde_json::Value>>>) {
+    let requests = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let recorded = requests.clone();
+    let server = actix_web::HttpServer::new(move || {
+        let (on_get, on_post) = (recorded.clone(), recorded.clone());
+        App::new().service(
+            web::resource("/analysis")
+                .route(web::get().to(
+                    move |q: web::Query<std::collections::HashMap<String, String>>,
+                          req: actix_web::HttpRequest| {
+                        let recorded = on_get.clone();
+                        async move {
+                            recorded
+                                .lock()
+                                .unwrap()
+                                .push(req.query_string().to_string().into());
+                            let patient_id = q.get("patient_id").filter(|_| filters_patients);
+                            actix_web::HttpResponse::Ok().json(serde_json::json!({
+                                "success": true,
+                                "analysis": {
+                                    "total_readings": 1200,
+                                    "avg_level": 310.0,
+                                    "std_level": 42.0,
+                                    "min_level": 120.0,
+                                    "max_level": 880.0
+                                },
+                                "patient_id": patient_id,
+                            }))
+                        }
+                    },
+                ))
+                .route(web::post().to(move |body: web::Json<serde_json::Value>| {
+                    let recorded = on_post.clone();
+                    async move {
+                        let total = body["readings"].as_array().unwrap().len();
+                        let patient_id = body["patient_id"].clone();
+                        recorded.lock().unwrap().push(body.into_inner());
+                        actix_web::HttpResponse::Ok().json(serde_json::json!({
+                            "success": true,
+                            "analysis": {
+                                "total_readings": total,
+                                "avg_level": 0.0,
+                                "std_level": 0.0,
+                                "min_level": 0.0,
+                                "max_level": 0.0
+                            },
+                            "patient_id": patient_id,
+                        }))
+                    }
+                })),
+        )
+    })
+    .workers(1)
+    .bind(("127.0.0.1", 0))
+    .unwrap();
+
+    let addr = server.addrs()[0];
+    actix_web::rt::spawn(server.run());
+    (format!("http://{}", addr), requests)
+}
+
+#[actix_web::test]
+async fn patient_analysis_is_scoped_and_authorized() {
+    let state = web::Data::new(Arc::new(Mutex::new(common::demo_state())));
+    let (url, requests) = spawn_patient_analysis_service(true).await;
+    let app = test::init_service(
+        App::new()
+            .app_data(state)
+            .configure(common::routes)
+            .app_data(web::Data::new(Arc::new(MlClient::new(url)))),
+    )
+    .await;
+    let analysis = |role: &str, query: &str| {
+        test::TestRequest::get()
+            .uri(&format!("/api/ml/analysis?{}", query))
+            .insert_header(("authorization", format!("Bearer {}", common::token(role))))
+            .to_request()
+    };
+
+    let resp = test::call_service(&app, analysis("user", "limit=50&patient_id=p%2F1")).await;
+    assert_eq!(resp.status(), 200);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["patient_id"], "p/1");
+    assert_eq!(body["source"], "ml");
+    assert_eq!(
+        requests.lock().unwrap().as_slice(),
+        [serde_json::json!("limit=50&patient_id=p%2F1")]
+    );
+
+    // Devices only report readings; the request never reaches the ML service
+    let resp = test::call_service(&app, analysis("device", "patient_id=p1")).await;
+    assert_eq!(resp.status(), 403);
+    let resp = test::call_service(&app, analysis("user", "patient_id=%20")).await;
+    assert_eq!(resp.status(), 400);
+    assert_eq!(requests.lock().unwrap().len(), 1);
+
+    let req = test::TestRequest::get()
+        .uri("/api/ml/analysis?patient_id=p1")
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 401);
+}
+
+#[actix_web::test]
+async fn patient_analysis_sends_readings_to_unfiltered_ml_service() {
+    let state = web::Data::new(Arc::new(Mutex::new(common::demo_state())));
+    let (url, requests) = spawn_patient_analysis_service(false).await;
+    let app = test::init_service(
+        App::new()
+            .app_data(state)
+            .configure(common::routes)
+            .app_data(web::Data::new(Arc::new(MlClient::new(url)))),
+    )
+    .await;
+    let auth = format!("Bearer {}", common::token("user"));
+
+    for (patient_id, value, ts) in [
+        ("p1", 200.0, "2026-02-03T10:00:00Z"),
+        ("p2", 900.0, "2026-02-03T10:00:00Z"),
+        ("p1", 300.0, "2026-02-03T11:00:00Z"),
+        ("p1", 400.0, "2026-02-05T11:00:00Z"),
+    ] {
+        let req = test::TestRequest::post()
+            .uri("/api/ingest")
+            .insert_header(("authorization", auth.clone()))
+            .set_json(serde_json::json!({
+                "patient_id": patient_id,
+                "device_id": "d1",
+                "code": "sound",
+                "value": value,
+                "unit": "raw",
+                "ts": ts,
+            }))
+            .to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+    }
+
+    let req = test::TestRequest::get()
+        .uri("/api/ml/analysis?start=2026-02-03&end=2026-02-04&patient_id=p1")
+        .insert_header(("authorization", auth.clone()))
+        .to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(body["patient_id"], "p1");
+    assert_eq!(body["analysis"]["total_readings"], 2);
+
+    // The service ignored the filter, so only p1's readings in range were posted
+    let requests = requests.lock().unwrap();
+    assert_eq!(requests.len(), 2);
+    assert_eq!(requests[1]["patient_id"], "p1");
+    let values: Vec<_> = requests[1]["readings"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|r| r["value"].as_f64().unwrap())
+        .collect();
+    assert_eq!(values, [200.0, 300.0]);
+}
+
+/// Fake ML service whose `/predict` fails while `up` is false
+async fn spawn_flaky_prediction_service() -> (String, Arc<std::sync::atomic::AtomicBool>) {
+    let up = Arc::new(std::sync::atomic::AtomicBool::new(true));
+    let flag = up.clone();
+    let server = actix_web::HttpServer::new(move || {
+        let flag = flag.clone();
+        App::new().route(
+            "/predict",
+            web::post().to(move || {
+                let up = flag.load(std::sync::atomic::Ordering::SeqCst);
+                async move {
+                    if !up {
+                        return actix_web::HttpResponse::ServiceUnavailable().finish();
+                    }
+                    actix_web::HttpResponse::Ok().json(serde_json::json!({
+                        "success": true,
+                        "total_readings": 1,
+                        "predictions": [{
+                            "value": 420.0,
+                            "timestamp": "2026-02-03T10:00:00Z",
+                            "category_rule": "moderate"
+                        }],
+                        "summary": {
+                            "total_readings": 1,
+                            "avg_value": 420.0,
+                            "max_value": 420.0,
+                            "min_value": 420.0,
+                            "anomaly_count": 0
+                        }
+                    }))
+                }
+            }),
+        )
+    })
+    .workers(1)
+    .bind(("127.0.0.1", 0))
+    .unwrap();
+
+    let addr = server.addrs()[0];
+    actix_web::rt::spawn(server.run());
+    (format!("http://{}", addr), up)
+}
+
+#[actix_web::test]
+async fn ml_predictions_fall_back_to_cached_result() {
+    use soundsense_backend::ml_cache::MlResultCache;
+    use std::sync::atomic::Ordering;
+
+    let (url, up) = spawn_flaky_prediction_service().await;
+    let ml_client = Arc::new(MlClient::new(url));
+    let app_with_cache = |max_age| {
+        App::new()
+            .app_data(web::Data::new(Arc::new(Mutex::new(common::demo_state()))))
+            .configure(common::routes)
+            .app_data(web::Data::new(ml_client.clone()))
+            .app_data(web::Data::new(Arc::new(MlResultCache::new(max_age))))
+    };
+    let predict = || {
+        test::TestRequest::get()
+            .uri("/api/ml/predict?limit=10")
+            .insert_header(("authorization", format!("Bearer {}", common::token("user"))))
+            .to_request()
+    };
+
+    let app = test::init_service(app_with_cache(chrono::Duration::minutes(15))).await;
+
+    // A live answer is passed through and remembered
+    let resp = test::call_service(&app, predict()).await;
+    assert_eq!(resp.status(), 200);
+    assert!(resp.headers().get("warning").is_none());
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["summary"]["avg_value"], 420.0);
+    assert!(body.get("stale").is_none());
+
+    // While the service is down the remembered answer is served, flagged
+    up.store(false, Ordering::SeqCst);
+    let resp = test::call_service(&app, predict()).await;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(
+        resp.headers().get("warning").unwrap(),
+        "110 - \"Response is Stale\""
+    );
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["summary"]["avg_value"], 420.0);
+    assert_eq!(body["stale"], true);
+    assert!(body["stale_as_of"].is_string());
+
+    // Other queries were never answered, so there's nothing to fall back to
+    let req = test::TestRequest::get()
+        .uri("/api/ml/predict?limit=20")
+        .insert_header(("authorization", format!("Bearer {}", common::token("user"))))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 500);
+
+    // Results older than the staleness window aren't served
+    up.store(true, Ordering::SeqCst);
+    let app = test::init_service(app_with_cache(chrono::Duration::milliseconds(10))).await;
+    assert_eq!(test::call_service(&app, predict()).await.status(), 200);
+    up.store(false, Ordering::SeqCst);
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(test::call_service(&app, predict()).await.status(), 500);
+}
+
+#[actix_web::test]
+async fn noise_map_groups_readings_by_location() {
+    let app = TestApp::new().service().await;
+    let device = format!("Bearer {}", common::token("device"));
+
+    for (patient_id, location, value) in [
+        ("p1", Some("Ward 3B"), 70.0),
+        ("p2", Some("Ward 3B"), 80.0),
+        ("p1", Some("Ward 3B"), 75.0),
+        ("p3", Some("ICU"), 55.0),
+        ("p3", None, 99.0),
+    ] {
+        let req = test::TestRequest::post()
+            .uri("/api/ingest")
+            .insert_header(("authorization", device.clone()))
+            .set_json(serde_json::json!({
+                "patient_id": patient_id,
+                "device_id": "d1",
+                "code": "sound",
+                "value": value,
+                "unit": "dB",
+                "ts": "2026-03-01T12:00:00Z",
+                "metadata": { "location": location },
+            }))
+            .to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+    }
+
+    let noise_map = |query: &str, role: &str| {
+        test::TestRequest::get()
+            .uri(&format!("/api/analysis/noise-map?{}", query))
+            .insert_header(("authorization", format!("Bearer {}", common::token(role))))
+            .to_request()
+    };
+
+    let body: serde_json::Value = test::call_and_read_body_json(
+        &app,
+        noise_map("date_from=2026-03-01&date_to=2026-03-01", "admin"),
+    )
+    .await;
+    let locations = body.as_array().unwrap();
+    assert_eq!(locations.len(), 2);
+    assert_eq!(locations[0]["location"], "ICU");
+    assert_eq!(locations[0]["count"], 1);
+    assert_eq!(locations[1]["location"], "Ward 3B");
+    assert_eq!(locations[1]["avg_db"], 75.0);
+    assert_eq!(locations[1]["max_db"], 80.0);
+    assert_eq!(locations[1]["count"], 3);
+    assert_eq!(locations[1]["patient_count"], 2);
+
+    let body: serde_json::Value = test::call_and_read_body_json(
+        &app,
+        noise_map(
+            "date_from=2026-03-01&date_to=2026-03-01&min_count=2",
+            "admin",
+        ),
+    )
+    .await;
+    assert_eq!(body.as_array().unwrap().len(), 1);
+    assert_eq!(body[0]["location"], "Ward 3B");
+
+    let body: serde_json::Value = test::call_and_read_body_json(
+        &app,
+        noise_map("date_from=2026-03-02&date_to=2026-03-02", "admin"),
+    )
+    .await;
+    assert!(body.as_array().unwrap().is_empty());
+
+    let resp = test::call_service(
+        &app,
+        noise_map("date_from=2026-03-01&date_to=2026-03-01", "user"),
+    )
+    .await;
+    assert_eq!(resp.status(), 401);
+    for query in [
+        "date_from=2026-03-02&date_to=2026-03-01",
+        "date_from=2026-03-01&date_to=2026-03-01&min_count=-1",
+    ] {
+        let resp = test::call_service(&app, noise_map(query, "admin")).await;
+        assert_eq!(resp.status(), 400, "{}", query);
+    }
+}
+
+#[actix_web::test]
+async fn reading_gaps_are_detected_and_filled() {
+    let app = TestApp::new().service().await;
+    let auth = format!("Bearer {}", common::token("user"));
+
+    // Ten readings at 1 Hz; the wearable was off for 30 seconds after the fifth
+    let t0 = chrono::DateTime::parse_from_rfc3339("2026-03-10T08:00:00Z")
+        .unwrap()
+        .with_timezone(&chrono::Utc);
+    for i in 0..10 {
+        let offset = if i < 5 { i } else { i + 30 };
+        let req = test::TestRequest::post()
+            .uri("/api/ingest")
+            .insert_header(("authorization", auth.clone()))
+            .set_json(serde_json::json!({
+                "patient_id": "p-gap",
+                "device_id": "wearable-1",
+                "code": "sound",
+                "value": 100.0 + i as f64,
+                "unit": "raw",
+                "ts": t0 + chrono::Duration::seconds(offset),
+            }))
+            .to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+    }
+
+    let gaps = |query: &str, role: &str| {
+        test::TestRequest::get()
+            .uri(&format!(
+                "/api/analysis/gaps?patient_id=p-gap&date_from=2026-03-10&date_to=2026-03-10{}",
+                query
+            ))
+            .insert_header(("authorization", format!("Bearer {}", common::token(role))))
+            .to_request()
+    };
+
+    let body: serde_json::Value = test::call_and_read_body_json(&app, gaps("", "user")).await;
+    let found = body.as_array().unwrap();
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0]["start"], "2026-03-10T08:00:04Z");
+    assert_eq!(found[0]["end"], "2026-03-10T08:00:35Z");
+    assert_eq!(found[0]["duration_secs"], 31.0);
+    assert!(found[0].get("synthetic_readings").is_none());
+
+    // The gap is shorter than a minute
+    let body: serde_json::Value =
+        test::call_and_read_body_json(&app, gaps("&max_gap_secs=60", "user")).await;
+    assert!(body.as_array().unwrap().is_empty());
+
+    let body: serde_json::Value =
+        test::call_and_read_body_json(&app, gaps("&fill=linear", "user")).await;
+    let filled = body[0]["synthetic_readings"].as_array().unwrap();
+    assert_eq!(filled.len(), 30);
+    assert_eq!(filled[0]["ts"], "2026-03-10T08:00:05Z");
+    assert_eq!(filled[0]["metadata"]["synthetic"], true);
+    assert_eq!(filled[0]["device_id"], "wearable-1");
+
+    // Synthetic readings are not stored
+    let req = test::TestRequest::get()
+        .uri("/api/fhir/Observation?limit=100")
+        .insert_header(("authorization", auth.clone()))
+        .to_request();
+    let bundle: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(bundle["entry"].as_array().unwrap().len(), 10);
+
+    for (query, role, status) in [
+        ("&fill=cubic", "user", 400),
+        ("&max_gap_secs=0", "user", 400),
+        ("&max_gap_secs=2678401", "user", 400),
+        ("&max_gap_secs=18446744073709551615", "user", 400),
+        ("", "device", 403),
+    ] {
+        let resp = test::call_service(&app, gaps(query, role)).await;
+        assert_eq!(resp.status(), status, "{query} as {role}");
+    }
+}
+
+/// Reading for `patient_id` as posted to /ingest and /api/ingest
+fn consent_reading(patient_id: &str) -> serde_json::Value {
+    serde_json::json!({
+        "patient_id": patient_id,
+        "device_id": "wearable-1",
+        "code": "sound",
+        "value": 200.0,
+        "unit": "raw",
+    })
+}
+
+/// Admin requests registering `p-granted` with consent and `p-revoked` with
+/// consent revoked; `p-unknown` is never registered
+fn consent_registration() -> Vec<test::TestRequest> {
+    let admin = format!("Bearer {}", common::token("admin"));
+    [
+        ("p-granted", "granted"),
+        ("p-revoked", "granted"),
+        ("p-revoked", "revoked"),
+    ]
+    .into_iter()
+    .map(|(patient_id, status)| {
+        test::TestRequest::put()
+            .uri(&format!("/api/patients/{}/consent", patient_id))
+            .insert_header(("authorization", admin.clone()))
+            .set_json(serde_json::json!({ "status": status, "label": "Ward 3" }))
+    })
+    .collect()
+}
+
+#[actix_web::test]
+async fn consent_is_enforced_at_ingest_in_strict_mode() {
+    let state = web::Data::new(Arc::new(Mutex::new(
+        common::demo_state().with_consent_mode(ConsentMode::Strict),
+    )));
+    let app = test::init_service(App::new().app_data(state).configure(common::routes)).await;
+    let auth = format!("Bearer {}", common::token("user"));
+    let admin = format!("Bearer {}", common::token("admin"));
+
+    // Patient administration is admin only
+    let req = test::TestRequest::put()
+        .uri("/api/patients/p-granted/consent")
+        .insert_header(("authorization", auth.clone()))
+        .set_json(serde_json::json!({ "status": "granted" }))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 401);
+    let req = test::TestRequest::get()
+        .uri("/api/patients/p-unknown")
+        .insert_header(("authorization", admin.clone()))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 404);
+
+    for req in consent_registration() {
+        assert_eq!(
+            test::call_service(&app, req.to_request()).await.status(),
+            200
+        );
+    }
+
+    let req = test::TestRequest::get()
+        .uri("/api/patients/p-revoked")
+        .insert_header(("authorization", admin.clone()))
+        .to_request();
+    let patient: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(patient["consent_status"], "revoked");
+    assert_eq!(patient["label"], "Ward 3");
+    assert!(patient["consented_at"].is_null());
+
+    for (uri, authorization) in [("/api/ingest", Some(&auth)), ("/ingest", None)] {
+        for (patient_id, expected) in [("p-granted", 200), ("p-revoked", 403), ("p-unknown", 403)] {
+            let mut req = test::TestRequest::post()
+                .uri(uri)
+                .set_json(consent_reading(patient_id));
+            if let Some(authorization) = authorization {
+                req = req.insert_header(("authorization", authorization.clone()));
+            }
+            let resp = test::call_service(&app, req.to_request()).await;
+            assert_eq!(resp.status(), expected, "{} for {}", uri, patient_id);
+        }
+    }
+
+    // One patient without consent fails the whole batch
+    let req = test::TestRequest::post()
+        .uri("/api/ingest/batch")
+        .insert_header(("authorization", auth.clone()))
+        .set_json(vec![
+            consent_reading("p-granted"),
+            consent_reading("p-unknown"),
+        ])
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 403);
+
+    let req = test::TestRequest::get()
+        .uri("/api/fhir/Observation?limit=100")
+        .insert_header(("authorization", auth.clone()))
+        .to_request();
+    let bundle: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    let subjects: Vec<_> = bundle["entry"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|e| e["resource"]["subject"]["reference"].as_str().unwrap())
+        .collect();
+    assert_eq!(subjects, vec!["Patient/p-granted", "Patient/p-granted"]);
+}
+
+#[actix_web::test]
+async fn consent_quarantine_mode_holds_back_readings() {
+    let state = Arc::new(Mutex::new(
+        common::demo_state().with_consent_mode(ConsentMode::Quarantine),
+    ));
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(state.clone()))
+            .configure(common::routes),
+    )
+    .await;
+    let auth = format!("Bearer {}", common::token("user"));
+
+    for req in consent_registration() {
+        assert_eq!(
+            test::call_service(&app, req.to_request()).await.status(),
+            200
+        );
+    }
+
+    for (patient_id, expected) in [("p-granted", 200), ("p-revoked", 202), ("p-unknown", 202)] {
+        let req = test::TestRequest::post()
+            .uri("/ingest")
+            .set_json(consent_reading(patient_id))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), expected);
+    }
+
+    // The batch is accepted, but only the reading with consent is stored
+    let req = test::TestRequest::post()
+        .uri("/api/ingest/batch")
+        .insert_header(("authorization", auth.clone()))
+        .set_json(vec![
+            consent_reading("p-granted"),
+            consent_reading("p-unknown"),
+        ])
+        .to_request();
+    let bundle: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(bundle["entry"].as_array().unwrap().len(), 1);
+
+    let req = test::TestRequest::get()
+        .uri("/api/fhir/Observation?limit=100")
+        .insert_header(("authorization", auth.clone()))
+        .to_request();
+    let bundle: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    let entries = bundle["entry"].as_array().unwrap();
+    assert_eq!(entries.len(), 2);
+    assert!(entries
+        .iter()
+        .all(|e| e["resource"]["subject"]["reference"] == "Patient/p-granted"));
+
+    // A stream counts what it quarantined apart from what it stored
+    let ndjson = format!(
+        "{}\n{}\n",
+        consent_reading("p-granted"),
+        consent_reading("p-unknown")
+    );
+    let req = stream_request(ndjson, &auth).to_request();
+    let summary: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(summary["accepted"], 1);
+    assert_eq!(summary["quarantined"], 1);
+    assert_eq!(summary["rejected"], 0);
+
+    let st = state.lock().await;
+    let quarantined: Vec<_> = st
+        .quarantined()
+        .map(|q| (q.patient_id.as_str(), q.reason))
+        .collect();
+    assert_eq!(
+        quarantined,
+        vec![
+            ("p-revoked", "revoked"),
+            ("p-unknown", "unknown"),
+            ("p-unknown", "unknown"),
+            ("p-unknown", "unknown"),
+        ]
+    );
+}
+
+#[actix_web::test]
+async fn observation_head_and_options() {
+    // Over a real connection: HEAD bodies are dropped by the HTTP/1 encoder
+    let state = web::Data::new(Arc::new(Mutex::new(common::demo_state())));
+    let server = actix_web::HttpServer::new(move || {
+        App::new().app_data(state.clone()).configure(common::routes)
+    })
+    .workers(1)
+    .bind(("127.0.0.1", 0))
+    .unwrap();
+    let base = format!("http://{}", server.addrs()[0]);
+    actix_web::rt::spawn(server.run());
+
+    let client = reqwest::Client::new();
+    let auth = format!("Bearer {}", common::token("user"));
+    let resp = client
+        .post(format!("{}/api/ingest", base))
+        .header("authorization", &auth)
+        .json(&serde_json::json!({
+            "patient_id": "p1",
+            "device_id": "d1",
+            "code": "sound",
+            "value": 200.0,
+            "unit": "raw",
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 200);
+
+    let url = format!("{}/api/fhir/Observation?limit=10", base);
+    let get = client
+        .get(&url)
+        .header("authorization", &auth)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(get.status(), 200);
+    let etag = get.headers()["etag"].clone();
+    let length = get.headers()["content-length"].clone();
+    let body = get.bytes().await.unwrap();
+    assert!(!body.is_empty());
+    // The SHA-256 of the body, so it survives restarts and upgrades
+    assert_eq!(
+        etag.to_str().unwrap(),
+        format!(
+            "\"{}\"",
+            hex::encode(<sha2::Sha256 as sha2::Digest>::digest(&body))
+        )
+    );
+
+    let head = client
+        .head(&url)
+        .header("authorization", &auth)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(head.status(), 200);
+    assert_eq!(head.headers()["etag"], etag);
+    assert_eq!(head.headers()["content-length"], length);
+    assert!(head.bytes().await.unwrap().is_empty());
+
+    // HEAD needs a token like GET
+    let head = client.head(&url).send().await.unwrap();
+    assert_eq!(head.status(), 401);
+
+    // The tag follows the content
+    client
+        .post(format!("{}/ingest", base))
+        .json(&serde_json::json!({
+            "patient_id": "p1",
+            "device_id": "d1",
+            "code": "sound",
+            "value": 300.0,
+            "unit": "raw",
+        }))
+        .send()
+        .await
+        .unwrap();
+    let head = client
+        .head(&url)
+        .header("authorization", &auth)
+        .send()
+        .await
+        .unwrap();
+    assert_ne!(head.headers()["etag"], etag);
+
+    for (path, allow) in [
+        ("/api/fhir/Observation", "GET, HEAD, OPTIONS"),
+        (
+            "/api/fhir/Observation/00000000-0000-0000-0000-000000000000",
+            "PUT, DELETE, OPTIONS",
+        ),
+    ] {
+        let resp = client
+            .request(reqwest::Method::OPTIONS, format!("{}{}", base, path))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), 204, "{}", path);
+        assert_eq!(resp.headers()["allow"], allow);
+    }
+}
+
+#[actix_web::test]
+async fn hearing_protection_is_recommended_from_daily_dose() {
+    let app = TestApp::new().service().await;
+    let auth = format!("Bearer {}", common::token("user"));
+    let admin = format!("Bearer {}", common::token("admin"));
+
+    // The protector database is admin only
+    let req = test::TestRequest::post()
+        .uri("/api/admin/hearing-protectors")
+        .insert_header(("authorization", auth.clone()))
+        .set_json(serde_json::json!({ "name": "Foam plug", "nrr_db": 33, "category": "earplug" }))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 401);
+
+    let mut ids = Vec::new();
+    for (name, nrr_db, category) in [
+        ("Foam plug", 33, "earplug"),
+        ("Flange plug", 22, "earplug"),
+        ("Earmuff", 25, "earmuff"),
+        ("Banded plug", 18, "earplug"),
+    ] {
+        let req = test::TestRequest::post()
+            .uri("/api/admin/hearing-protectors")
+            .insert_header(("authorization", admin.clone()))
+            .set_json(serde_json::json!({ "name": name, "nrr_db": nrr_db, "category": category }))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 201);
+        let created: serde_json::Value = test::read_body_json(resp).await;
+        ids.push(created["id"].as_str().unwrap().to_string());
+    }
+
+    // The earmuff is replaced by a stronger model; the banded plug is retired
+    let req = test::TestRequest::put()
+        .uri(&format!("/api/admin/hearing-protectors/{}", ids[2]))
+        .insert_header(("authorization", admin.clone()))
+        .set_json(serde_json::json!({ "name": "Earmuff", "nrr_db": 29, "category": "earmuff" }))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 200);
+    let req = test::TestRequest::delete()
+        .uri(&format!("/api/admin/hearing-protectors/{}", ids[3]))
+        .insert_header(("authorization", admin.clone()))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 204);
+    let req = test::TestRequest::delete()
+        .uri(&format!("/api/admin/hearing-protectors/{}", ids[3]))
+        .insert_header(("authorization", admin.clone()))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 404);
+
+    let req = test::TestRequest::get()
+        .uri("/api/admin/hearing-protectors")
+        .insert_header(("authorization", admin.clone()))
+        .to_request();
+    let list: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    let nrrs: Vec<_> = list
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|p| p["nrr_db"].as_i64().unwrap())
+        .collect();
+    assert_eq!(nrrs, vec![22, 29, 33]);
+
+    // 95 dB all day; the raw reading doesn't count towards the TWA
+    for (patient_id, value, unit) in [
+        ("p-loud", 95.0, "dB"),
+        ("p-loud", 95.0, "dBA"),
+        ("p-loud", 400.0, "raw"),
+        ("p-quiet", 70.0, "dB"),
+    ] {
+        let req = test::TestRequest::post()
+            .uri("/api/ingest")
+            .insert_header(("authorization", auth.clone()))
+            .set_json(serde_json::json!({
+                "patient_id": patient_id,
+                "device_id": "d1",
+                "code": "sound",
+                "value": value,
+                "unit": unit,
+                "ts": "2026-03-12T10:00:00Z",
+            }))
+            .to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+    }
+
+    let recommendation = |patient_id: &str| {
+        test::TestRequest::get()
+            .uri(&format!(
+                "/api/analysis/recommendation?patient_id={}&date=2026-03-12",
+                patient_id
+            ))
+            .insert_header(("authorization", auth.clone()))
+            .to_request()
+    };
+
+    // 95 dB needs more than 10 dB of attenuation: NRR 22 gives 7.5, NRR 29 gives 11
+    let body: serde_json::Value =
+        test::call_and_read_body_json(&app, recommendation("p-loud")).await;
+    assert!((body["twa_db"].as_f64().unwrap() - 95.0).abs() < 1e-9);
+    assert!((body["dose_percent"].as_f64().unwrap() - 1000.0).abs() < 1e-6);
+    let rec = &body["recommendation"];
+    assert_eq!(rec["name"], "Earmuff");
+    assert_eq!(rec["nrr_db"], 29);
+    assert_eq!(rec["category"], "earmuff");
+    assert_eq!(rec["effective_attenuation"], 11.0);
+    assert!((rec["projected_twa_db"].as_f64().unwrap() - 84.0).abs() < 1e-9);
+
+    let body: serde_json::Value =
+        test::call_and_read_body_json(&app, recommendation("p-quiet")).await;
+    assert!(body["recommendation"].is_null());
+    assert!(body["dose_percent"].as_f64().unwrap() < 100.0);
+
+    let resp = test::call_service(&app, recommendation("p-nobody")).await;
+    assert_eq!(resp.status(), 404);
+}
+
+#[actix_web::test]
+async fn daily_rollups_are_served_per_patient() {
+    let app = TestApp::new().service().await;
+    let auth = format!("Bearer {}", common::token("user"));
+
+    for (patient_id, value, ts) in [
+        ("p-roll", 80.0, "2026-03-01T08:00:00Z"),
+        ("p-roll", 90.0, "2026-03-01T08:00:30Z"),
+        ("p-roll", 100.0, "2026-03-01T09:00:00Z"),
+        ("p-roll", 70.0, "2026-03-03T08:00:00Z"),
+        ("p-other", 99.0, "2026-03-01T08:00:00Z"),
+    ] {
+        let req = test::TestRequest::post()
+            .uri("/api/ingest")
+            .insert_header(("authorization", auth.clone()))
+            .set_json(serde_json::json!({
+                "patient_id": patient_id,
+                "device_id": "d1",
+                "code": "sound",
+                "value": value,
+                "unit": "dB",
+                "ts": ts,
+            }))
+            .to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+    }
+
+    let rollups = |query: &str, role: &str| {
+        test::TestRequest::get()
+            .uri(&format!("/api/patients/p-roll/rollups?{}", query))
+            .insert_header(("authorization", format!("Bearer {}", common::token(role))))
+            .to_request()
+    };
+
+    let body: serde_json::Value =
+        test::call_and_read_body_json(&app, rollups("from=2026-03-01&to=2026-03-31", "user")).await;
+    let days = body.as_array().unwrap();
+    assert_eq!(days.len(), 2);
+    assert_eq!(days[0]["date"], "2026-03-01");
+    assert_eq!(days[0]["code"], "sound");
+    assert_eq!(days[0]["count"], 3);
+    assert_eq!(days[0]["mean"], 90.0);
+    assert_eq!(days[0]["max"], 100.0);
+    assert!((days[0]["p95"].as_f64().unwrap() - 99.0).abs() < 1e-9);
+    // 08:00 (90 dB) and 09:00 (100 dB)
+    assert_eq!(days[0]["minutes_above_threshold"], 2);
+    assert_eq!(days[1]["date"], "2026-03-03");
+    assert_eq!(days[1]["count"], 1);
+
+    let body: serde_json::Value =
+        test::call_and_read_body_json(&app, rollups("from=2026-03-02&to=2026-03-02", "user")).await;
+    assert!(body.as_array().unwrap().is_empty());
+
+    let resp = test::call_service(&app, rollups("from=2026-03-31&to=2026-03-01", "user")).await;
+    assert_eq!(resp.status(), 400);
+    let resp = test::call_service(&app, rollups("from=2025-01-01&to=2026-03-01", "user")).await;
+    assert_eq!(resp.status(), 400);
+    let resp = test::call_service(&app, rollups("from=2026-03-01&to=2026-03-31", "device")).await;
+    assert_eq!(resp.status(), 403);
+}
+
+#[actix_web::test]
+async fn device_stats_count_accepted_and_refused_readings() {
+    let app = TestApp::with_state(common::demo_state().with_consent_mode(ConsentMode::Quarantine))
+        .service()
+        .await;
+    let auth = format!("Bearer {}", common::token("device"));
+    for req in consent_registration() {
+        assert_eq!(
+            test::call_service(&app, req.to_request()).await.status(),
+            200
+        );
+    }
+
+    let reading = |ts: &str, category: &str| {
+        serde_json::json!({
+            "patient_id": "p-granted",
+            "device_id": "d-stats",
+            "code": "sound",
+            "value": 60.0,
+            "unit": "dB",
+            "ts": ts,
+            "metadata": { "fhir_category": category },
+        })
+    };
+    // Two readings with the same timestamp, one with an unknown category
+    for (ts, category, ok) in [
+        ("2026-03-01T08:00:00Z", "activity", true),
+        ("2026-03-01T08:00:00Z", "activity", true),
+        ("2026-03-01T08:00:01Z", "bogus", false),
+    ] {
+        let req = test::TestRequest::post()
+            .uri("/api/ingest")
+            .insert_header(("authorization", auth.clone()))
+            .set_json(reading(ts, category))
+            .to_request();
+        assert_eq!(
+            test::call_service(&app, req).await.status().is_success(),
+            ok
+        );
+    }
+    let req = test::TestRequest::post()
+        .uri("/api/ingest/batch")
+        .insert_header(("authorization", auth.clone()))
+        .set_json(serde_json::json!([
+            reading("2026-03-01T08:00:02Z", "activity"),
+            reading("2026-03-01T08:00:03Z", "activity"),
+        ]))
+        .to_request();
+    assert!(test::call_service(&app, req).await.status().is_success());
+
+    // A reading of a patient without consent is quarantined, not accepted
+    let mut quarantined = reading("2026-03-01T08:00:04Z", "activity");
+    quarantined["patient_id"] = serde_json::json!("p-unknown");
+    let req = test::TestRequest::post()
+        .uri("/api/ingest")
+        .insert_header(("authorization", auth.clone()))
+        .set_json(quarantined)
+        .to_request();
+    assert!(test::call_service(&app, req).await.status().is_success());
+
+    let stats = |device_id: &str, role: &str| {
+        test::TestRequest::get()
+            .uri(&format!("/api/devices/{}/stats", device_id))
+            .insert_header(("authorization", format!("Bearer {}", common::token(role))))
+            .to_request()
+    };
+
+    let resp = test::call_service(&app, stats("d-stats", "user")).await;
+    assert_eq!(resp.status(), 200);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["device_id"], "d-stats");
+    assert_eq!(body["accepted"], 4);
+    assert_eq!(body["quarantined"], 1);
+    assert_eq!(body["rejected"], 1);
+    assert_eq!(body["duplicates"], 1);
+    assert!(body["last_error"]["message"]
+        .as_str()
+        .unwrap()
+        .contains("bogus"));
+    assert_eq!(body["readings_last_5m"], 4);
+    assert_eq!(body["readings_last_60m"], 4);
+    assert!(body["process_started_at"].is_string());
+    assert!(body["database"].is_null());
+
+    let resp = test::call_service(&app, stats("d-unknown", "user")).await;
+    assert_eq!(resp.status(), 404);
+    let resp = test::call_service(&app, stats("d-stats", "device")).await;
+    assert_eq!(resp.status(), 403);
+}
+
+#[actix_web::test]
+async fn gzip_bundle_decompresses_to_plain_bundle() {
+    use std::io::Read;
+
+    let state = web::Data::new(Arc::new(Mutex::new(common::demo_state())));
+    let app = test::init_service(
+        App::new()
+            .app_data(state)
+            .wrap(actix_web::middleware::Compress::default())
+            .configure(common::routes),
+    )
+    .await;
+    let auth = format!("Bearer {}", common::token("user"));
+
+    for i in 0..50 {
+        let req = test::TestRequest::post()
+            .uri("/api/ingest")
+            .insert_header(("authorization", auth.clone()))
+            .set_json(serde_json::json!({
+                "patient_id": "p1",
+                "device_id": "d1",
+                "code": "sound",
+                "value": 200.0 + i as f64,
+                "unit": "raw",
+            }))
+            .to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+    }
+
+    let bundle = |encoding: Option<&str>| {
+        let mut req = test::TestRequest::get()
+            .uri("/api/fhir/Observation?limit=500")
+            .insert_header(("authorization", auth.clone()));
+        if let Some(encoding) = encoding {
+            req = req.insert_header(("accept-encoding", encoding));
+        }
+        req.to_request()
+    };
+
+    let plain = test::call_service(&app, bundle(None)).await;
+    assert!(plain.headers().get("content-encoding").is_none());
+    let plain = test::read_body(plain).await;
+
+    let resp = test::call_service(&app, bundle(Some("gzip"))).await;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.headers().get("content-encoding").unwrap(), "gzip");
+    let compressed = test::read_body(resp).await;
+    assert!(compressed.len() < plain.len() / 2);
+
+    let mut decompressed = Vec::new();
+    flate2::read::GzDecoder::new(&compressed[..])
+        .read_to_end(&mut decompressed)
+        .unwrap();
+    assert_eq!(decompressed, plain.to_vec());
+}
+
+#[actix_web::test]
+async fn live_stream_is_not_buffered_by_compression() {
+    let state = web::Data::new(Arc::new(Mutex::new(common::demo_state())));
+    let server = actix_web::HttpServer::new(move || {
+        App::new()
+            .app_data(state.clone())
+            .wrap(actix_web::middleware::Compress::default())
+            .configure(common::routes)
+    })
+    .workers(1)
+    .bind(("127.0.0.1", 0))
+    .unwrap();
+    let addr = server.addrs()[0];
+    actix_web::rt::spawn(server.run());
+
+    // Upgrade advertising gzip like a browser would
+    let mut ws = WsClient::connect(
+        addr,
+        "/ws/live",
+        &["Accept-Encoding: gzip", &common::bearer_header("user")],
+    )
+    .await;
+    assert!(!ws.handshake.contains("content-encoding"));
+
+    // Each reading arrives as its own frame as soon as it is ingested
+    let client = reqwest::Client::new();
+    for value in [210.0, 220.0] {
+        let resp = client
+            .post(format!("http://{}/ingest", addr))
+            .json(&serde_json::json!({
+                "patient_id": "p1",
+                "device_id": "d1",
+                "code": "sound",
+                "value": value,
+                "unit": "raw",
+            }))
+            .send()
+            .await
+            .unwrap();
+        assert!(resp.status().is_success());
+
+        let obs = ws.next_json().await;
+        assert_eq!(obs["type"], "observation");
+        assert_eq!(obs["data"]["valueQuantity"]["value"], value);
+    }
+}
+
+#[actix_web::test]
+async fn live_clients_see_readings_ingested_on_any_worker() {
+    let addr = TestApp::new().serve(4);
+    // Bare observations, as clients from before envelopes expect
+    let mut ws = WsClient::connect(
+        addr,
+        &format!(
+            "/ws/live?envelope=false&access_token={}",
+            common::token("viewer")
+        ),
+        &[],
+    )
+    .await;
+
+    // A fresh connection per reading, so the ingests land on other workers
+    // than the one holding the WebSocket
+    for i in 0..8 {
+        let value = 200.0 + i as f64;
+        let client = reqwest::Client::builder()
+            .pool_max_idle_per_host(0)
+            .build()
+            .unwrap();
+        let resp = client
+            .post(format!("http://{}/ingest", addr))
+            .json(&serde_json::json!({
+                "patient_id": "p1",
+                "device_id": "d1",
+                "code": "sound",
+                "value": value,
+                "unit": "raw",
+            }))
+            .send()
+            .await
+            .unwrap();
+        assert!(resp.status().is_success());
+
+        let obs = ws.next_json().await;
+        assert_eq!(obs["valueQuantity"]["value"], value);
+    }
+}
+
+#[actix_web::test]
+async fn lagging_live_clients_get_coalesced_readings() {
+    let hub = WsHub::new(None).with_backpressure(Backpressure {
+        coalesce_above: 10,
+        resume_at: 0,
+    });
+    let addr = TestApp::new().hub(hub.clone()).serve(1);
+    let mut ws = WsClient::connect(addr, "/ws/live", &[&common::bearer_header("user")]).await;
+    let observation = |patient_id: String, value: f64| {
+        FhirObservation::from_reading(SensorReading {
+            patient_id,
+            device_id: "d1".into(),
+            code: SignalCode::Sound,
+            value,
+            unit: "dB".into(),
+            ts: chrono::Utc::now(),
+            ts_source: TimestampSource::Device,
+            metadata: Default::default(),
+        })
+    };
+
+    // Not reading while 300 readings of three patients go out
+    for i in 0..300 {
+        hub.publish(&observation(format!("p{}", i % 3), i as f64));
+    }
+    tokio::time::sleep(Duration::from_millis(600)).await;
+
+    // Only the frames sent before the session noticed the lag are queued...
+    for i in 0..11 {
+        assert_eq!(
+            ws.next_json().await["data"]["valueQuantity"]["value"],
+            i as f64
+        );
+    }
+    // ...then, once they are acknowledged, the latest reading per patient
+    let notice = ws.next_json().await;
+    assert_eq!(notice["type"], "coalesced");
+    assert_eq!(notice["data"]["skipped"], 300 - 11 - 3);
+    let mut latest = Vec::new();
+    for _ in 0..3 {
+        latest.push(
+            ws.next_json().await["data"]["valueQuantity"]["value"]
+                .as_f64()
+                .unwrap(),
+        );
+    }
+    latest.sort_by(f64::total_cmp);
+    assert_eq!(latest, [297.0, 298.0, 299.0]);
+
+    // Caught up: readings flow one by one again
+    hub.publish(&observation("p0".into(), 1000.0));
+    assert_eq!(
+        ws.next_json().await["data"]["valueQuantity"]["value"],
+        1000.0
+    );
+}
+
+#[actix_web::test]
+async fn live_frames_are_enveloped_with_their_type() {
+    let hub = WsHub::with_capacity(None, 4);
+    let addr = TestApp::new().hub(hub.clone()).serve(1);
+    let mut ws = WsClient::connect(addr, "/ws/live", &[&common::bearer_header("user")]).await;
+    let observation = |value: f64| {
+        FhirObservation::from_reading(SensorReading {
+            patient_id: "p1".into(),
+            device_id: "d1".into(),
+            code: SignalCode::Sound,
+            value,
+            unit: "dB".into(),
+            ts: chrono::Utc::now(),
+            ts_source: TimestampSource::Device,
+            metadata: Default::default(),
+        })
+    };
+
+    hub.publish(&observation(1.0));
+    let frame = ws.next_json().await;
+    assert_eq!(frame["type"], "observation");
+    assert_eq!(frame["data"]["resourceType"], "Observation");
+    assert_eq!(frame["data"]["valueQuantity"]["value"], 1.0);
+    assert_eq!(frame.as_object().unwrap().len(), 2);
+
+    // More than the stream buffers between two ticks of the session
+    for i in 0..10 {
+        hub.publish(&observation(100.0 + i as f64));
+    }
+    let frame = ws.next_json().await;
+    assert_eq!(
+        frame,
+        serde_json::json!({"type": "lagged", "data": {"missed": 6}})
+    );
+    for i in 6..10 {
+        let frame = ws.next_json().await;
+        assert_eq!(frame["type"], "observation");
+        assert_eq!(frame["data"]["valueQuantity"]["value"], 100.0 + i as f64);
+    }
+}
+
+#[actix_web::test]
+async fn live_sessions_get_filtered_stats_frames_agreeing_with_the_gauge() {
+    let hub = WsHub::new(None).with_stats_interval(Duration::from_millis(200));
+    let app = TestApp::new().hub(hub);
+    let addr = app.serve(1);
+    let mut ws = WsClient::connect(
+        addr,
+        "/ws/live?patient_id=p1&code=SoundLevel",
+        &[&common::bearer_header("user")],
+    )
+    .await;
+
+    let client = reqwest::Client::new();
+    for (patient_id, value) in [("p1", 60.0), ("p2", 90.0), ("p1", 70.0)] {
+        let resp = client
+            .post(format!("http://{}/ingest", addr))
+            .json(&serde_json::json!({
+                "patient_id": patient_id,
+                "device_id": "d1",
+                "code": "sound",
+                "value": value,
+                "unit": "dB",
+            }))
+            .send()
+            .await
+            .unwrap();
+        assert!(resp.status().is_success());
+    }
+
+    // p2 is filtered out of both. Stats ticks run apart from the stream, so
+    // read on until both observations and a tick counting them are in.
+    let mut values = Vec::new();
+    let mut stats = None;
+    while values.len() < 2 || stats.is_none() {
+        let frame = ws.next_json().await;
+        match frame["type"].as_str().unwrap() {
+            "observation" => values.push(frame["data"]["valueQuantity"]["value"].clone()),
+            "stats" if frame["data"]["count"] == 2 => {
+                assert_eq!(frame["data"]["window"], "1m");
+                stats = Some([frame["data"].clone(), ws.next_json().await["data"].clone()]);
+            }
+            "stats" => assert_eq!(frame["data"]["patient"], "p1"),
+            other => panic!("unexpected {} frame", other),
+        }
+    }
+    let stats = stats.unwrap();
+    assert_eq!(values, [60.0, 70.0]);
+    assert_eq!(
+        stats[0],
+        serde_json::json!({
+            "patient": "p1", "code": "sound", "window": "1m",
+            "count": 2, "mean": 65.0, "max": 70.0,
+        })
+    );
+    assert_eq!(stats[1]["window"], "5m");
+    assert_eq!(stats[1]["count"], 2);
+
+    let gauge: serde_json::Value = client
+        .get(format!("http://{}/api/patients/p1/gauge", addr))
+        .bearer_auth(app.token("user"))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(gauge, serde_json::json!(stats));
+
+    // Bare sessions get the frame flat
+    let mut bare = WsClient::connect(
+        addr,
+        "/ws/live?envelope=false&patient_id=p2",
+        &[&common::bearer_header("user")],
+    )
+    .await;
+    assert_eq!(
+        bare.next_json().await,
+        serde_json::json!({
+            "type": "stats", "patient": "p2", "code": "sound", "window": "1m",
+            "count": 1, "mean": 90.0, "max": 90.0,
+        })
+    );
+
+    // A token limited to p2 hears nothing about p1, unfiltered or not
+    let token = JwtManager::new(JwtAlgorithm::HS256(common::TEST_JWT_SECRET.to_string()))
+        .generate_token(
+            Claims::new("erin".into(), "viewer".into(), None, 1)
+                .with_permitted_patients(vec!["p2".into()]),
+        )
+        .unwrap();
+    let mut limited = WsClient::connect(
+        addr,
+        &format!("/ws/live?envelope=false&access_token={}", token),
+        &[],
+    )
+    .await;
+    for window in ["1m", "5m", "1m", "5m"] {
+        let frame = limited.next_json().await;
+        assert_eq!(
+            (&frame["patient"], &frame["window"]),
+            (&"p2".into(), &window.into())
+        );
+    }
+}
+
+#[actix_web::test]
+async fn patient_gauges_need_read_access() {
+    let app = TestApp::new().service().await;
+    let gauge = |patient_id: &str, role: &str| {
+        test::TestRequest::get()
+            .uri(&format!("/api/patients/{}/gauge", patient_id))
+            .insert_header(("authorization", format!("Bearer {}", common::token(role))))
+            .to_request()
+    };
+
+    let resp = test::call_service(&app, gauge("p1", "viewer")).await;
+    assert_eq!(resp.status(), 200);
+    let frames: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(frames, serde_json::json!([]));
+    assert_eq!(
+        test::call_service(&app, gauge("p1", "device"))
+            .await
+            .status(),
+        403
+    );
+
+    // Filters name known codes
+    let req = test::TestRequest::get()
+        .uri("/ws/live?code=noise")
+        .insert_header(("authorization", format!("Bearer {}", common::token("user"))))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 400);
+
+    // The live stream needs a token that may read data
+    let live = |token: Option<String>| {
+        let uri = match token {
+            Some(token) => format!("/ws/live?access_token={}", token),
+            None => "/ws/live".to_string(),
+        };
+        test::TestRequest::get().uri(&uri).to_request()
+    };
+    assert_eq!(test::call_service(&app, live(None)).await.status(), 401);
+    let resp = test::call_service(&app, live(Some("not-a-jwt".into()))).await;
+    assert_eq!(resp.status(), 401);
+    let resp = test::call_service(&app, live(Some(common::token("device")))).await;
+    assert_eq!(resp.status(), 403);
+}
+
+#[actix_web::test]
+async fn calibration_verification_averages_readings_during_the_window() {
+    let app = TestApp::new().service().await;
+    let admin = format!("Bearer {}", common::token("admin"));
+    let device = format!("Bearer {}", common::token("device"));
+
+    let verify = |reference_db: f64, duration_secs: u32| {
+        test::TestRequest::post()
+            .uri("/api/calibrate/device/d-cal/verify")
+            .insert_header(("authorization", admin.clone()))
+            .set_json(serde_json::json!({
+                "reference_db": reference_db,
+                "duration_secs": duration_secs,
+            }))
+            .to_request()
+    };
+    // The tone plays while the verification listens
+    let send_tone = |values: &'static [f64]| {
+        let app = &app;
+        let device = device.clone();
+        async move {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            for value in values {
+                let req = test::TestRequest::post()
+                    .uri("/api/ingest")
+                    .insert_header(("authorization", device.clone()))
+                    .set_json(serde_json::json!({
+                        "patient_id": "p1",
+                        "device_id": "d-cal",
+                        "code": "sound",
+                        "value": value,
+                        "unit": "dB",
+                        "ts": chrono::Utc::now().to_rfc3339(),
+                    }))
+                    .to_request();
+                assert!(test::call_service(app, req).await.status().is_success());
+            }
+        }
+    };
+
+    let (resp, _) = tokio::join!(
+        test::call_service(&app, verify(94.0, 1)),
+        send_tone(&[94.5, 95.5])
+    );
+    assert_eq!(resp.status(), 200);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["measured_avg_db"], 95.0);
+    assert_eq!(body["deviation_db"], 1.0);
+    assert_eq!(body["passed"], true);
+
+    let (resp, _) = tokio::join!(
+        test::call_service(&app, verify(94.0, 1)),
+        send_tone(&[91.0, 91.0])
+    );
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["deviation_db"], -3.0);
+    assert_eq!(body["passed"], false);
+
+    // Nothing heard from the device
+    let resp = test::call_service(&app, verify(94.0, 1)).await;
+    assert_eq!(resp.status(), 409);
+
+    let resp = test::call_service(&app, verify(94.0, 0)).await;
+    assert_eq!(resp.status(), 400);
+
+    let history = |role: &str| {
+        test::TestRequest::get()
+            .uri("/api/devices/d-cal/calibration-history")
+            .insert_header(("authorization", format!("Bearer {}", common::token(role))))
+            .to_request()
+    };
+    let resp = test::call_service(&app, history("user")).await;
+    assert_eq!(resp.status(), 200);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["calibration_status"], "failed");
+    let verifications = body["verifications"].as_array().unwrap();
+    assert_eq!(verifications.len(), 2);
+    assert_eq!(verifications[0]["passed"], false);
+    assert_eq!(verifications[1]["passed"], true);
+
+    let resp = test::call_service(&app, history("device")).await;
+    assert_eq!(resp.status(), 403);
+
+    // Only admins run verifications
+    let req = test::TestRequest::post()
+        .uri("/api/calibrate/device/d-cal/verify")
+        .insert_header(("authorization", device.clone()))
+        .set_json(serde_json::json!({ "reference_db": 94.0, "duration_secs": 1 }))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 401);
+}
+
+#[actix_web::test]
+async fn admins_snapshot_in_memory_readings_on_demand() {
+    let path =
+        std::env::temp_dir().join(format!("soundsense-snapshot-{}.cbor", uuid::Uuid::new_v4()));
+    let snapshots = web::Data::new(SnapshotManager::new(&path));
+    let state = web::Data::new(Arc::new(Mutex::new(common::demo_state())));
+    let app = test::init_service(
+        App::new()
+            .app_data(state.clone())
+            .app_data(snapshots.clone())
+            .configure(common::routes),
+    )
+    .await;
+    let admin = format!("Bearer {}", common::token("admin"));
+
+    for patient_id in ["p1", "p2"] {
+        let req = test::TestRequest::post()
+            .uri("/api/ingest")
+            .insert_header(("authorization", admin.clone()))
+            .set_json(consent_reading(patient_id))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 200);
+    }
+
+    let meta = || {
+        test::TestRequest::get()
+            .uri("/api/admin/snapshot/meta")
+            .insert_header(("authorization", admin.clone()))
+            .to_request()
+    };
+    let body: serde_json::Value = test::call_and_read_body_json(&app, meta()).await;
+    assert!(body["last_snapshot_at"].is_null());
+    assert_eq!(body["reading_count"], 0);
+
+    let req = test::TestRequest::post()
+        .uri("/api/admin/snapshot")
+        .insert_header(("authorization", admin.clone()))
+        .to_request();
+    let taken: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(taken["reading_count"], 2);
+    assert!(taken["last_snapshot_at"].is_string());
+    let body: serde_json::Value = test::call_and_read_body_json(&app, meta()).await;
+    assert_eq!(body, taken);
+
+    // What was written restores into a fresh backend
+    let restored = SnapshotManager::new(&path).load().await.unwrap().unwrap();
+    let mut fresh = common::demo_state();
+    fresh.restore_from_snapshot(restored);
+    assert_eq!(fresh.snapshot().len(), 2);
+    assert_eq!(fresh.snapshot()[0].id, state.lock().await.snapshot()[0].id);
+
+    // Admin only
+    let req = test::TestRequest::post()
+        .uri("/api/admin/snapshot")
+        .insert_header(("authorization", format!("Bearer {}", common::token("user"))))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 401);
+    std::fs::remove_file(&path).unwrap();
+
+    // Without SNAPSHOT_PATH there is nothing to snapshot to
+    let app = test::init_service(App::new().app_data(state).configure(common::routes)).await;
+    let req = test::TestRequest::post()
+        .uri("/api/admin/snapshot")
+        .insert_header(("authorization", admin.clone()))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 503);
+}
+
+#[actix_web::test]
+async fn compliance_reports_are_admin_only_and_need_a_database() {
+    let state = web::Data::new(Arc::new(Mutex::new(common::demo_state())));
+    let app = test::init_service(App::new().app_data(state).configure(common::routes)).await;
+    let get = |uri: &str, role: &str| {
+        test::TestRequest::get()
+            .uri(uri)
+            .insert_header(("authorization", format!("Bearer {}", common::token(role))))
+            .to_request()
+    };
+
+    let resp = test::call_service(&app, get("/api/admin/compliance-report/2026/02", "admin")).await;
+    assert_eq!(resp.status(), 503);
+    let resp = test::call_service(&app, get("/api/admin/compliance-report/2026/13", "admin")).await;
+    assert_eq!(resp.status(), 400);
+    let resp = test::call_service(&app, get("/api/admin/compliance-report/2026/02", "user")).await;
+    assert_eq!(resp.status(), 401);
+}
+
+#[actix_web::test]
+async fn audit_logs_are_admin_only_and_need_a_database() {
+    let app = TestApp::new().service().await;
+    let get = |role: &str| {
+        test::TestRequest::get()
+            .uri("/api/audit/patient/p1?limit=10")
+            .insert_header(("authorization", format!("Bearer {}", common::token(role))))
+            .to_request()
+    };
+
+    assert_eq!(test::call_service(&app, get("admin")).await.status(), 503);
+    assert_eq!(test::call_service(&app, get("user")).await.status(), 401);
+}
+
+#[actix_web::test]
+async fn debug_store_reports_the_in_memory_buffer() {
+    let app = TestApp::new().debug_endpoints(true);
+    let service = app.service().await;
+    let store = |role: &str| {
+        test::TestRequest::get()
+            .uri("/api/debug/store")
+            .insert_header(("authorization", format!("Bearer {}", common::token(role))))
+            .to_request()
+    };
+
+    let body: serde_json::Value = test::call_and_read_body_json(&service, store("admin")).await;
+    assert_eq!(body["len"], 0);
+    assert_eq!(body["capacity"], 500);
+    assert!(body["oldest_ts"].is_null());
+
+    for ts in [
+        "2026-03-01T12:00:02Z",
+        "2026-03-01T12:00:00Z",
+        "2026-03-01T12:00:01Z",
+    ] {
+        let req = app.ingest(&serde_json::json!({
+            "patient_id": "p1",
+            "device_id": "d1",
+            "code": "sound",
+            "value": 55.0,
+            "unit": "dB",
+            "ts": ts
+        }));
+        assert_eq!(test::call_service(&service, req).await.status(), 200);
+    }
+
+    let body: serde_json::Value = test::call_and_read_body_json(&service, store("admin")).await;
+    assert_eq!(body["len"], 3);
+    assert_eq!(body["per_code"], serde_json::json!({ "sound": 3 }));
+    assert_eq!(body["oldest_ts"], "2026-03-01T12:00:00Z");
+    assert_eq!(body["newest_ts"], "2026-03-01T12:00:02Z");
+
+    // Admin only, and absent unless enabled
+    assert_eq!(
+        test::call_service(&service, store("user")).await.status(),
+        401
+    );
+    let service = TestApp::new().service().await;
+    assert_eq!(
+        test::call_service(&service, store("admin")).await.status(),
+        404
+    );
+}
+
+#[actix_web::test]
+async fn unauthorized_responses_carry_a_bearer_challenge() {
+    let app = TestApp::new().service().await;
+
+    let challenge = |auth: Option<String>| {
+        let mut req = test::TestRequest::get().uri("/api/admin/dlq/stats");
+        if let Some(auth) = auth {
+            req = req.insert_header(("authorization", auth));
+        }
+        let app = &app;
+        async move {
+            let resp = test::call_service(app, req.to_request()).await;
+            assert_eq!(resp.status(), 401);
+            resp.headers()
+                .get("www-authenticate")
+                .expect("401 without WWW-Authenticate")
+                .to_str()
+                .unwrap()
+                .to_string()
+        }
+    };
+
+    // No token: only how to authenticate
+    assert_eq!(challenge(None).await, r#"Bearer realm="soundsense""#);
+
+    let expired = JwtManager::new(JwtAlgorithm::HS256(common::TEST_JWT_SECRET.to_string()))
+        .generate_token(Claims::new("test-user".into(), "admin".into(), None, -1))
+        .unwrap();
+    assert_eq!(
+        challenge(Some(format!("Bearer {}", expired))).await,
+        r#"Bearer realm="soundsense", error="invalid_token", error_description="Token expired""#
+    );
+
+    let forged = JwtManager::new(JwtAlgorithm::HS256("another-secret".to_string()))
+        .generate_token(Claims::new("test-user".into(), "admin".into(), None, 1))
+        .unwrap();
+    assert_eq!(
+        challenge(Some(format!("Bearer {}", forged))).await,
+        r#"Bearer realm="soundsense", error="invalid_token", error_description="Invalid token""#
+    );
+
+    assert_eq!(
+        challenge(Some("Bearer not-a-jwt".into())).await,
+        r#"Bearer realm="soundsense", error="invalid_token", error_description="Malformed token""#
+    );
+
+    // A valid token without the admin role is refused by the handler
+    let user = format!("Bearer {}", common::token("user"));
+    assert_eq!(challenge(Some(user)).await, r#"Bearer realm="soundsense""#);
+}
+
+#[actix_web::test]
+async fn spectrogram_frames_are_stored_and_queried_in_time_order() {
+    let app = TestApp::new().service().await;
+    let device = format!("Bearer {}", common::token("device"));
+
+    let frame = |ts: &str, magnitudes: serde_json::Value| {
+        serde_json::json!({
+            "patient_id": "p1",
+            "device_id": "slm-1",
+            "ts": ts,
+            "sample_rate_hz": 48000,
+            "frame_duration_ms": 125,
+            "frequency_bins": [500.0, 1000.0, 2000.0],
+            "magnitudes": magnitudes,
+        })
+    };
+    for (ts, magnitudes, status) in [
+        (
+            "2026-03-01T12:00:02Z",
+            serde_json::json!([0.1, 0.2, 0.9]),
+            201,
+        ),
+        (
+            "2026-03-01T12:00:01Z",
+            serde_json::json!([0.1, 0.9, 0.2]),
+            201,
+        ),
+        ("2026-03-01T12:00:03Z", serde_json::json!([0.1, 0.2]), 400),
+    ] {
+        let req = test::TestRequest::post()
+            .uri("/api/ingest/spectrogram")
+            .insert_header(("authorization", device.clone()))
+            .set_json(frame(ts, magnitudes))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), status);
+    }
+
+    let query = |params: &str, role: &str| {
+        test::TestRequest::get()
+            .uri(&format!(
+                "/api/analysis/spectrogram?patient_id=p1&{}",
+                params
+            ))
+            .insert_header(("authorization", format!("Bearer {}", common::token(role))))
+            .to_request()
+    };
+
+    let resp = test::call_service(&app, query("ts_from=2026-03-01&ts_to=2026-03-01", "user")).await;
+    assert_eq!(resp.status(), 200);
+    let frames: serde_json::Value = test::read_body_json(resp).await;
+    let frames = frames.as_array().unwrap();
+    assert_eq!(frames.len(), 2);
+    assert_eq!(frames[0]["ts"], "2026-03-01T12:00:01Z");
+    assert_eq!(frames[0]["magnitudes"][1], 0.9);
+    assert_eq!(frames[1]["ts"], "2026-03-01T12:00:02Z");
+
+    let resp = test::call_service(
+        &app,
+        query("ts_from=2026-03-01&ts_to=2026-03-01&max_frames=1", "user"),
+    )
+    .await;
+    let frames: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(frames.as_array().unwrap().len(), 1);
+
+    let resp = test::call_service(
+        &app,
+        query("ts_from=2026-03-01&ts_to=2026-03-01&max_frames=0", "user"),
+    )
+    .await;
+    assert_eq!(resp.status(), 400);
+
+    let resp =
+        test::call_service(&app, query("ts_from=2026-03-01&ts_to=2026-03-01", "device")).await;
+    assert_eq!(resp.status(), 403);
+}
+
+#[actix_web::test]
+async fn packed_readings_are_expanded_and_subsampled_for_live_clients() {
+    let hub = WsHub::new(None);
+    let live = hub.tx.subscribe();
+    let app = TestApp::new().hub(hub).service().await;
+    let device = format!("Bearer {}", common::token("device"));
+
+    // One minute at 10 Hz: more samples than the 500-reading demo buffer
+    let values: Vec<f64> = (0..600).map(|i| 60.0 + (i % 10) as f64).collect();
+    let req = test::TestRequest::post()
+        .uri("/api/ingest/packed")
+        .insert_header(("authorization", device.clone()))
+        .set_json(serde_json::json!({
+            "patient_id": "p1",
+            "device_id": "d1",
+            "code": "sound",
+            "unit": "dB",
+            "start_ts": "2026-03-01T23:59:30Z",
+            "interval_ms": 100,
+            "values": values,
+        }))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert!(resp.status().is_success());
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["stored"], 600);
+    assert_eq!(body["first_ts"], "2026-03-01T23:59:30Z");
+    assert_eq!(body["last_ts"], "2026-03-02T00:00:29.900Z");
+
+    // Only every 10th sample reaches live clients
+    assert_eq!(live.len(), 60);
+
+    // The buffer keeps the newest 500: samples 100 to 599
+    let req = test::TestRequest::get()
+        .uri("/api/fhir/Observation?limit=1000")
+        .insert_header((
+            "authorization",
+            format!("Bearer {}", common::token("admin")),
+        ))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.headers().get("X-Total-Count").unwrap(), "500");
+    let bundle: serde_json::Value = test::read_body_json(resp).await;
+    let mut times: Vec<chrono::DateTime<chrono::Utc>> = bundle["entry"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|e| {
+            e["resource"]["effectiveDateTime"]
+                .as_str()
+                .unwrap()
+                .parse()
+                .unwrap()
+        })
+        .collect();
+    times.sort();
+    assert_eq!(times[0].to_rfc3339(), "2026-03-01T23:59:40+00:00");
+    assert_eq!(
+        times.last().unwrap().to_rfc3339(),
+        "2026-03-02T00:00:29.900+00:00"
+    );
+
+    // The last case starts at the latest representable millisecond
+    for (packet, start_ts, problem) in [
+        (serde_json::json!([]), 1772409600i64, "values"),
+        (serde_json::json!(vec![60.0; 1001]), 1772409600, "1000"),
+        (
+            serde_json::json!([60.0, 61.0]),
+            8210266876799999,
+            "latest supported timestamp",
+        ),
+    ] {
+        let req = test::TestRequest::post()
+            .uri("/api/ingest/packed")
+            .insert_header(("authorization", device.clone()))
+            .set_json(serde_json::json!({
+                "patient_id": "p1",
+                "device_id": "d1",
+                "code": "sound",
+                "unit": "dB",
+                "start_ts": start_ts,
+                "interval_ms": 100,
+                "values": packet,
+            }))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 400);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert!(body["error"].as_str().unwrap().contains(problem), "{body}");
+    }
+}
+
+/// POST of an NDJSON `body` to /api/ingest/stream
+fn stream_request(body: impl Into<actix_web::web::Bytes>, token: &str) -> test::TestRequest {
+    test::TestRequest::post()
+        .uri("/api/ingest/stream")
+        .insert_header(("authorization", token.to_string()))
+        .insert_header(("content-type", "application/x-ndjson"))
+        .set_payload(body.into())
+}
+
+#[actix_web::test]
+async fn stream_ingest_skips_corrupt_lines_of_a_large_backfill() {
+    let hub = WsHub::new(None);
+    let live = hub.tx.subscribe();
+    let app = TestApp::new().hub(hub).service().await;
+    let token = format!("Bearer {}", common::token("device"));
+
+    // 40,000 lines (about 5 MB), every 997th corrupt in one of four ways
+    let start = chrono::DateTime::parse_from_rfc3339("2026-03-01T00:00:00Z").unwrap();
+    let mut body = String::new();
+    let mut corrupt = 0;
+    for i in 0..40_000usize {
+        let line = if i % 997 == 500 {
+            corrupt += 1;
+            match corrupt % 4 {
+                0 => "{\"patient_id\": \"p1\", \"device_id\": ".to_string(),
+                1 => serde_json::json!({"patient_id": "p1", "device_id": "d-backfill"}).to_string(),
+                2 => format!("{{\"padding\": \"{}\"}}", "x".repeat(5000)),
+                _ => "not json at all".to_string(),
+            }
+        } else {
+            serde_json::json!({
+                "patient_id": "p1",
+                "device_id": "d-backfill",
+                "code": "sound",
+                "value": 40.0 + (i % 50) as f64,
+                "unit": "dB",
+                "ts": (start + chrono::Duration::seconds(i as i64)).to_rfc3339(),
+                "metadata": {"source": "export"},
+            })
+            .to_string()
+        };
+        body.push_str(&line);
+        // Windows line endings and blank lines are tolerated
+        body.push_str(if i % 2 == 0 { "\n" } else { "\r\n\n" });
+    }
+    assert!(body.len() > 4 * 1024 * 1024);
+
+    let resp = test::call_service(&app, stream_request(body, &token).to_request()).await;
+    assert_eq!(resp.status(), 200);
+    let summary: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(summary["accepted"], 40_000 - corrupt);
+    assert_eq!(summary["rejected"], corrupt);
+    // Reading 500 is the first corrupt one; the blank lines after odd
+    // readings put it on line 751
+    assert_eq!(summary["first_error_line"], 751);
+    assert!(summary["first_error"].as_str().unwrap().contains("missing"));
+
+    // Dashboards only see every 1000th reading
+    assert_eq!(live.len(), (40_000 - corrupt as usize).div_ceil(1000));
+
+    let req = test::TestRequest::get()
+        .uri("/api/devices/d-backfill/stats")
+        .insert_header((
+            "authorization",
+            format!("Bearer {}", common::token("admin")),
+        ))
+        .to_request();
+    let stats: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(stats["accepted"], 40_000 - corrupt);
+}
+
+#[actix_web::test]
+async fn stream_ingest_enforces_its_limits_and_consent() {
+    let state = web::Data::new(Arc::new(Mutex::new(
+        common::demo_state().with_consent_mode(ConsentMode::Strict),
+    )));
+    let app = test::init_service(
+        App::new()
+            .app_data(state)
+            .app_data(web::Data::new(StreamIngestConfig {
+                max_line_bytes: 512,
+                max_lines: 5,
+                batch_rows: 2,
+                broadcast_every: 1,
+            }))
+            .configure(common::routes),
+    )
+    .await;
+    let token = format!("Bearer {}", common::token("user"));
+    for req in consent_registration() {
+        assert_eq!(
+            test::call_service(&app, req.to_request()).await.status(),
+            200
+        );
+    }
+
+    let lines = |patients: &[&str]| -> String {
+        patients
+            .iter()
+            .map(|p| format!("{}\n", consent_reading(p)))
+            .collect()
+    };
+
+    // Only NDJSON is accepted
+    let req = test::TestRequest::post()
+        .uri("/api/ingest/stream")
+        .insert_header(("authorization", token.clone()))
+        .set_json(consent_reading("p-granted"))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 400);
+    let req = stream_request(lines(&["p-granted"]), "Bearer bogus").to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 401);
+
+    // Readings of patients without consent are skipped, not the stream
+    let body = lines(&[
+        "p-granted",
+        "p-revoked",
+        "p-granted",
+        "p-unknown",
+        "p-granted",
+    ]);
+    let resp = test::call_service(&app, stream_request(body, &token).to_request()).await;
+    assert_eq!(resp.status(), 200);
+    let summary: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(summary["accepted"], 3);
+    assert_eq!(summary["rejected"], 2);
+    assert_eq!(summary["first_error_line"], 2);
+    assert!(summary["first_error"]
+        .as_str()
+        .unwrap()
+        .contains("no consent"));
+
+    // Past the line limit the stream is cut off; earlier lines are kept
+    let body = lines(&["p-granted"; 7]);
+    let resp = test::call_service(&app, stream_request(body, &token).to_request()).await;
+    assert_eq!(resp.status(), 413);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert!(
+        body["error"]
+            .as_str()
+            .unwrap()
+            .contains("first 5 lines were processed (5 accepted, 0 quarantined, 0 rejected)"),
+        "{body}"
+    );
+
+    let req = test::TestRequest::get()
+        .uri("/api/fhir/Observation")
+        .insert_header((
+            "authorization",
+            format!("Bearer {}", common::token("admin")),
+        ))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.headers().get("X-Total-Count").unwrap(), "8");
+}
+
+#[actix_web::test]
+async fn validator_uses_the_configured_jwt_manager() {
+    let state = state_with_user("erin", "Correct-horse-42", "user").await;
+    let manager = |secret: &str| {
+        web::Data::new(Arc::new(JwtManager::new(JwtAlgorithm::HS256(
+            secret.to_string(),
+        ))))
+    };
+    let token = |secret: &str| {
+        JwtManager::new(JwtAlgorithm::HS256(secret.to_string()))
+            .generate_token(Claims::new("erin".into(), "user".into(), None, 1))
+            .unwrap()
+    };
+    let observations = |token: String| {
+        test::TestRequest::get()
+            .uri("/api/fhir/Observation")
+            .insert_header(("authorization", format!("Bearer {}", token)))
+            .to_request()
+    };
+
+    let app = test::init_service(
+        App::new()
+            .app_data(state.clone())
+            .configure(common::routes)
+            .app_data(manager(common::TEST_JWT_SECRET)),
+    )
+    .await;
+    let resp = test::call_service(&app, observations(token(common::TEST_JWT_SECRET))).await;
+    assert_eq!(resp.status(), 200);
+
+    // A rotated secret takes effect once the app is rebuilt with it: old
+    // tokens are refused and new logins are signed with it
+    let rotated = "rotated-secret-key-0123456789abcdef";
+    let app = test::init_service(
+        App::new()
+            .app_data(state)
+            .configure(common::routes)
+            .app_data(manager(rotated)),
+    )
+    .await;
+    let resp = test::call_service(&app, observations(token(common::TEST_JWT_SECRET))).await;
+    assert_eq!(resp.status(), 401);
+    let resp = test::call_service(&app, observations(token(rotated))).await;
+    assert_eq!(resp.status(), 200);
+
+    let req = test::TestRequest::post()
+        .uri("/auth/login")
+        .set_json(serde_json::json!({"username": "erin", "password": "Correct-horse-42"}))
+        .to_request();
+    let login: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    let issued = login["token"].as_str().unwrap();
+    assert!(JwtManager::new(JwtAlgorithm::HS256(rotated.to_string()))
+        .validate_token(issued)
+        .is_ok());
+    let resp = test::call_service(&app, observations(issued.to_string())).await;
+    assert_eq!(resp.status(), 200);
+}
+
+/// A device's answer to `nonce`: hex HMAC-SHA256 keyed with its secret
+fn device_answer(secret: &str, nonce: &str) -> String {
+    use hmac::Mac;
+
+    let mut mac = hmac::Hmac::<sha2::Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+    mac.update(&hex::decode(nonce).unwrap());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+#[actix_web::test]
+async fn devices_get_tokens_by_answering_challenges() {
+    let state = web::Data::new(Arc::new(Mutex::new(common::demo_state())));
+    let app =
+        test::init_service(App::new().app_data(state.clone()).configure(common::routes)).await;
+    let admin = format!("Bearer {}", common::token("admin"));
+
+    let challenge = |device_id: &str| {
+        test::TestRequest::post()
+            .uri("/auth/device/challenge")
+            .set_json(serde_json::json!({ "device_id": device_id }))
+            .to_request()
+    };
+    let answer = |device_id: &str, nonce: &str, response: &str| {
+        test::TestRequest::post()
+            .uri("/auth/device/token")
+            .set_json(serde_json::json!({
+                "device_id": device_id,
+                "nonce": nonce,
+                "response": response,
+            }))
+            .to_request()
+    };
+
+    // Unprovisioned devices get a challenge like any other, which no answer
+    // redeems
+    let resp = test::call_service(&app, challenge("d1")).await;
+    assert_eq!(resp.status(), 200);
+    let open: serde_json::Value = test::read_body_json(resp).await;
+    let nonce = open["nonce"].as_str().unwrap();
+    assert_eq!(nonce.len(), 64);
+    assert_eq!(
+        test::call_service(&app, answer("d1", nonce, &device_answer("guess", nonce)))
+            .await
+            .status(),
+        401
+    );
+
+    let req = test::TestRequest::post()
+        .uri("/api/devices/d1/secret")
+        .insert_header(("authorization", format!("Bearer {}", common::token("user"))))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 401);
+    let req = test::TestRequest::post()
+        .uri("/api/devices/d1/secret")
+        .insert_header(("authorization", admin.clone()))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 201);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    let secret = body["device_secret"].as_str().unwrap().to_string();
+
+    // Correct answer: a device token, even after someone else asked for
+    // another challenge in the device's name
+    let open: serde_json::Value = test::call_and_read_body_json(&app, challenge("d1")).await;
+    let nonce = open["nonce"].as_str().unwrap();
+    assert_eq!(nonce.len(), 64);
+    let other: serde_json::Value = test::call_and_read_body_json(&app, challenge("d1")).await;
+    assert_ne!(other["nonce"], open["nonce"]);
+    let response = device_answer(&secret, nonce);
+    let resp = test::call_service(&app, answer("d1", nonce, &response)).await;
+    assert_eq!(resp.status(), 200);
+    let login: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(login["role"], "device");
+    let token = login["token"].as_str().unwrap();
+    let req = test::TestRequest::post()
+        .uri("/api/ingest")
+        .insert_header(("authorization", format!("Bearer {}", token)))
+        .set_json(serde_json::json!({
+            "patient_id": "p1",
+            "device_id": "d1",
+            "code": "sound",
+            "value": 300.0,
+            "unit": "raw",
+        }))
+        .to_request();
+    assert!(test::call_service(&app, req).await.status().is_success());
+
+    // Replaying the same answer fails: the challenge is gone
+    let resp = test::call_service(&app, answer("d1", nonce, &response)).await;
+    assert_eq!(resp.status(), 401);
+
+    // A wrong answer fails and uses the challenge up
+    let open: serde_json::Value = test::call_and_read_body_json(&app, challenge("d1")).await;
+    let nonce = open["nonce"].as_str().unwrap();
+    let wrong = device_answer("not-the-secret", nonce);
+    assert_eq!(
+        test::call_service(&app, answer("d1", nonce, &wrong))
+            .await
+            .status(),
+        401
+    );
+    let right = device_answer(&secret, nonce);
+    assert_eq!(
+        test::call_service(&app, answer("d1", nonce, &right))
+            .await
+            .status(),
+        401
+    );
+
+    // An expired challenge fails
+    let expiring = common::demo_state().with_device_auth(Arc::new(
+        DeviceAuthStore::new(None).with_challenge_ttl(chrono::Duration::zero()),
+    ));
+    let secret = expiring.device_auth().provision("d2").await.unwrap();
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(Arc::new(Mutex::new(expiring))))
+            .configure(common::routes),
+    )
+    .await;
+    let open: serde_json::Value = test::call_and_read_body_json(&app, challenge("d2")).await;
+    let nonce = open["nonce"].as_str().unwrap();
+    let response = device_answer(&secret, nonce);
+    assert_eq!(
+        test::call_service(&app, answer("d2", nonce, &response))
+            .await
+            .status(),
+        401
+    );
+}
+
+/// An IoT device's signed token request for `nonce`, timestamped now
+fn iot_token_request(factory_key: &str, device_id: &str, nonce: &str) -> serde_json::Value {
+    use hmac::Mac;
+
+    let timestamp = chrono::Utc::now().timestamp();
+    let mut mac = hmac::Hmac::<sha2::Sha256>::new_from_slice(factory_key.as_bytes()).unwrap();
+    mac.update(format!("{}{}{}", nonce, device_id, timestamp).as_bytes());
+    serde_json::json!({
+        "device_id": device_id,
+        "timestamp": timestamp,
+        "nonce": nonce,
+        "hmac": hex::encode(mac.finalize().into_bytes()),
+    })
+}
+
+#[actix_web::test]
+async fn iot_devices_trade_signed_nonces_for_short_lived_tokens() {
+    let app = TestApp::new().service().await;
+    let challenge = |device_id: &str| {
+        test::TestRequest::post()
+            .uri("/auth/iot/challenge")
+            .set_json(serde_json::json!({ "device_id": device_id }))
+            .to_request()
+    };
+    let token = |body: &serde_json::Value| {
+        test::TestRequest::post()
+            .uri("/auth/iot/token")
+            .set_json(body)
+            .to_request()
+    };
+    let provision = |role: &str| {
+        test::TestRequest::post()
+            .uri("/api/admin/iot-devices")
+            .insert_header(("authorization", format!("Bearer {}", common::token(role))))
+            .set_json(serde_json::json!({ "device_id": "iot-1" }))
+            .to_request()
+    };
+
+    assert_eq!(
+        test::call_service(&app, challenge("iot-1")).await.status(),
+        404
+    );
+    assert_eq!(
+        test::call_service(&app, provision("user")).await.status(),
+        401
+    );
+    let resp = test::call_service(&app, provision("admin")).await;
+    assert_eq!(resp.status(), 201);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    let factory_key = body["factory_key"].as_str().unwrap().to_string();
+    assert_eq!(
+        test::call_service(&app, provision("admin")).await.status(),
+        409
+    );
+
+    // Valid handshake: a device token good for an hour
+    let open: serde_json::Value = test::call_and_read_body_json(&app, challenge("iot-1")).await;
+    let request = iot_token_request(&factory_key, "iot-1", open["nonce"].as_str().unwrap());
+    let resp = test::call_service(&app, token(&request)).await;
+    assert_eq!(resp.status(), 200);
+    let login: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(login["role"], "device");
+    assert_eq!(login["expires_in"], 3600);
+    let claims = JwtManager::new(JwtAlgorithm::HS256(common::TEST_JWT_SECRET.into()))
+        .validate_token(login["token"].as_str().unwrap())
+        .unwrap();
+    assert_eq!(claims.device_id.as_deref(), Some("iot-1"));
+
+    // Replay of the same request
+    let resp = test::call_service(&app, token(&request)).await;
+    assert_eq!(resp.status(), 401);
+
+    // A wrong key, or a nonce issued to another device
+    let open: serde_json::Value = test::call_and_read_body_json(&app, challenge("iot-1")).await;
+    let nonce = open["nonce"].as_str().unwrap();
+    let wrong = iot_token_request("not-the-factory-key", "iot-1", nonce);
+    assert_eq!(test::call_service(&app, token(&wrong)).await.status(), 401);
+    let other = iot_token_request(&factory_key, "iot-2", nonce);
+    assert_eq!(test::call_service(&app, token(&other)).await.status(), 401);
+}
+
+#[actix_web::test]
+async fn expired_iot_nonces_are_refused() {
+    let iot_auth = IotAuthStore::new(None).with_nonce_ttl(chrono::Duration::zero());
+    let factory_key = iot_auth.provision("iot-1").await.unwrap();
+    let app = TestApp::with_state(common::demo_state().with_iot_auth(Arc::new(iot_auth)))
+        .service()
+        .await;
+
+    let req = test::TestRequest::post()
+        .uri("/auth/iot/challenge")
+        .set_json(serde_json::json!({ "device_id": "iot-1" }))
+        .to_request();
+    let open: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    let req = test::TestRequest::post()
+        .uri("/auth/iot/token")
+        .set_json(iot_token_request(
+            &factory_key,
+            "iot-1",
+            open["nonce"].as_str().unwrap(),
+        ))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 401);
+}
+
+#[actix_web::test]
+async fn admins_read_log_sampling_rates() {
+    let app = TestApp::new().service().await;
+    let get = |role: &str| {
+        test::TestRequest::get()
+            .uri("/api/admin/log-stats")
+            .insert_header(("authorization", format!("Bearer {}", common::token(role))))
+            .to_request()
+    };
+
+    let body: serde_json::Value = test::call_and_read_body_json(&app, get("admin")).await;
+    assert!(body["sample_rate"].as_u64().unwrap() >= 1);
+    assert!(body["sampled_events_per_sec"].as_f64().unwrap() >= 0.0);
+    assert!(body["dropped_events_per_sec"].as_f64().unwrap() >= 0.0);
+    assert!(body["window_secs"].is_number());
+    assert_eq!(test::call_service(&app, get("user")).await.status(), 401);
+}
+
+#[actix_web::test]
+async fn bursts_are_decimated_to_one_reading_per_interval() {
+    let app = TestApp::with_state(common::demo_state().with_decimator(Decimator::new(Some(1000))));
+    let service = app.service().await;
+    let auth = format!("Bearer {}", common::token("device"));
+    let t0 = "2026-03-01T08:00:00Z"
+        .parse::<chrono::DateTime<chrono::Utc>>()
+        .unwrap();
+    let reading = |device_id: &str, ms: i64| {
+        serde_json::json!({
+            "patient_id": "p1",
+            "device_id": device_id,
+            "code": "sound",
+            "value": 60.0,
+            "unit": "dB",
+            "ts": t0 + chrono::Duration::milliseconds(ms),
+        })
+    };
+
+    // 25 readings 100 ms apart: one per second survives
+    let burst: Vec<_> = (0..25).map(|i| reading("d-loop", i * 100)).collect();
+    let req = test::TestRequest::post()
+        .uri("/api/ingest/batch")
+        .insert_header(("authorization", auth.clone()))
+        .set_json(&burst)
+        .to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&service, req).await;
+    assert_eq!(body["total"], 3);
+
+    let req = test::TestRequest::post()
+        .uri("/api/ingest")
+        .insert_header(("authorization", auth.clone()))
+        .set_json(reading("d-loop", 2500))
+        .to_request();
+    let resp = test::call_service(&service, req).await;
+    assert_eq!(resp.status(), 200);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["receipt"]["storage"], "decimated");
+
+    let stored: Vec<_> = app
+        .state
+        .lock()
+        .await
+        .snapshot()
+        .into_iter()
+        .map(|r| r.reading.ts)
+        .collect();
+    assert_eq!(
+        stored,
+        [0, 1000, 2000].map(|ms| t0 + chrono::Duration::milliseconds(ms))
+    );
+
+    let req = test::TestRequest::get()
+        .uri("/api/devices/d-loop/stats")
+        .insert_header((
+            "authorization",
+            format!("Bearer {}", common::token("admin")),
+        ))
+        .to_request();
+    let stats: serde_json::Value = test::call_and_read_body_json(&service, req).await;
+    assert_eq!(stats["accepted"], 26);
+    assert_eq!(stats["decimated"], 23);
+    assert_eq!(stats["decimation_interval_ms"], 1000);
+
+    // A device of its own interval 0 keeps every reading
+    let req = test::TestRequest::put()
+        .uri("/api/devices/d-fast")
+        .insert_header((
+            "authorization",
+            format!("Bearer {}", common::token("admin")),
+        ))
+        .set_json(serde_json::json!({ "decimation_interval_ms": 0 }))
+        .to_request();
+    assert_eq!(test::call_service(&service, req).await.status(), 200);
+    let burst: Vec<_> = (0..5).map(|i| reading("d-fast", i * 100)).collect();
+    let req = test::TestRequest::post()
+        .uri("/api/ingest/batch")
+        .insert_header(("authorization", auth))
+        .set_json(&burst)
+        .to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&service, req).await;
+    assert_eq!(body["total"], 5);
+}
+
+#[actix_web::test]
+async fn readings_are_normalized_by_their_device_profile() {
+    // Readings are checked once scaled: raw counts would be out of range
+    let pipeline = ValidationPipeline::from_configs(
+        &[RuleConfig {
+            rule_name: "range".into(),
+            config: serde_json::json!({"min": 0, "max": 140}),
+            severity: Severity::Error,
+        }],
+        PatientRegistry::default(),
+    )
+    .unwrap();
+    let hub = WsHub::new(None);
+    let mut live = hub.tx.subscribe();
+    let app = TestApp::with_state(common::demo_state().with_validation(pipeline)).hub(hub);
+    let service = app.service().await;
+    let admin = format!("Bearer {}", common::token("admin"));
+    let device = format!("Bearer {}", common::token("device"));
+    let put = |body: serde_json::Value| {
+        test::TestRequest::put()
+            .uri("/api/devices/d-adc")
+            .insert_header(("authorization", admin.clone()))
+            .set_json(body)
+            .to_request()
+    };
+
+    // A 10-bit ADC onto 30-130
+    let resp = test::call_service(
+        &service,
+        put(serde_json::json!({
+            "scale_min": 0.0,
+            "scale_max": 1023.0,
+            "target_min": 30.0,
+            "target_max": 130.0,
+        })),
+    )
+    .await;
+    assert_eq!(resp.status(), 200);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["scale_max"], 1023.0);
+
+    for bad in [
+        serde_json::json!({ "scale_min": 0.0, "scale_max": 1023.0 }),
+        serde_json::json!({
+            "scale_min": 5.0,
+            "scale_max": 5.0,
+            "target_min": 30.0,
+            "target_max": 130.0,
+        }),
+    ] {
+        assert_eq!(test::call_service(&service, put(bad)).await.status(), 400);
+    }
+
+    let batch: Vec<_> = [("d-adc", 1023.0), ("d-adc", 2000.0), ("d-plain", 120.0)]
+        .iter()
+        .map(|(device_id, value)| {
+            serde_json::json!({
+                "patient_id": "p1",
+                "device_id": device_id,
+                "code": "sound",
+                "value": value,
+                "unit": "raw",
+            })
+        })
+        .collect();
+    let req = test::TestRequest::post()
+        .uri("/api/ingest/batch")
+        .insert_header(("authorization", device.clone()))
+        .set_json(&batch)
+        .to_request();
+    assert!(test::call_service(&service, req)
+        .await
+        .status()
+        .is_success());
+
+    // The response, the broadcast and the gauge carry the scaled value too
+    let req = test::TestRequest::post()
+        .uri("/api/ingest")
+        .insert_header(("authorization", device))
+        .set_json(serde_json::json!({
+            "patient_id": "p2",
+            "device_id": "d-adc",
+            "code": "sound",
+            "value": 511.5,
+            "unit": "raw",
+        }))
+        .to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&service, req).await;
+    assert_eq!(body["valueQuantity"]["value"], 80.0);
+    assert_eq!(body["valueQuantity"]["unit"], "dB");
+
+    let mut broadcast = Vec::new();
+    while let Ok(obs) = live.try_recv() {
+        let quantity = serde_json::to_value(obs).unwrap()["valueQuantity"].clone();
+        broadcast.push((
+            quantity["value"].as_f64().unwrap(),
+            quantity["unit"].clone(),
+        ));
+    }
+    assert_eq!(
+        broadcast,
+        [
+            (130.0, "dB".into()),
+            (130.0, "dB".into()),
+            (120.0, "raw".into()),
+            (80.0, "dB".into()),
+        ]
+    );
+
+    let req = test::TestRequest::get()
+        .uri("/api/patients/p2/gauge")
+        .insert_header(("authorization", format!("Bearer {}", common::token("user"))))
+        .to_request();
+    let gauge: serde_json::Value = test::call_and_read_body_json(&service, req).await;
+    assert_eq!(gauge[0]["mean"], 80.0);
+
+    let stored: Vec<_> = app
+        .state
+        .lock()
+        .await
+        .snapshot()
+        .into_iter()
+        .map(|r| (r.reading.device_id, r.reading.value, r.raw_value))
+        .collect();
+    assert_eq!(
+        stored,
+        [
+            ("d-adc".to_string(), 130.0, Some(1023.0)),
+            // Out of scale: clamped
+            ("d-adc".to_string(), 130.0, Some(2000.0)),
+            // No profile: stored as sent
+            ("d-plain".to_string(), 120.0, Some(120.0)),
+            ("d-adc".to_string(), 80.0, Some(511.5)),
+        ]
+    );
+}
+
+#[actix_web::test]
+async fn observations_are_converted_to_the_requested_unit() {
+    let app = TestApp::new();
+    let service = app.service().await;
+    let admin = format!("Bearer {}", common::token("admin"));
+    let device = format!("Bearer {}", common::token("device"));
+    let ingest = |device_id: &str, value: f64, unit: &str, ts: chrono::DateTime<chrono::Utc>| {
+        test::TestRequest::post()
+            .uri("/api/ingest")
+            .insert_header(("authorization", device.clone()))
+            .set_json(serde_json::json!({
+                "patient_id": "p1",
+                "device_id": device_id,
+                "code": "sound",
+                "value": value,
+                "unit": unit,
+                "ts": ts,
+            }))
+            .to_request()
+    };
+    let get = |path: &str| {
+        test::TestRequest::get()
+            .uri(path)
+            .insert_header(("authorization", admin.clone()))
+            .to_request()
+    };
+
+    // d-adc only sends raw output; its calibration records 50 at 94 dB
+    let verify = test::TestRequest::post()
+        .uri("/api/calibrate/device/d-adc/verify")
+        .insert_header(("authorization", admin.clone()))
+        .set_json(serde_json::json!({ "reference_db": 94.0, "duration_secs": 1 }))
+        .to_request();
+    let tone = async {
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        for _ in 0..2 {
+            let req = ingest("d-adc", 50.0, "raw", chrono::Utc::now());
+            assert!(test::call_service(&service, req)
+                .await
+                .status()
+                .is_success());
+        }
+    };
+    let (resp, _) = tokio::join!(test::call_service(&service, verify), tone);
+    assert_eq!(resp.status(), 200);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["measured_avg_raw"], 50.0);
+    assert!(body["measured_avg_db"].is_null());
+    assert_eq!(body["passed"], true);
+
+    // A tenfold output is 20 dB louder; d-plain was never calibrated.
+    // Stamped in the past so every reading falls inside "the last day"
+    let now = chrono::Utc::now() - chrono::Duration::milliseconds(100);
+    let readings = [
+        ("d-meter", 70.0, "dB"),
+        ("d-plain", 512.0, "raw"),
+        ("d-adc", 500.0, "raw"),
+        ("d-meter", 68.0, "dBA"),
+    ];
+    for (i, (device_id, value, unit)) in readings.into_iter().enumerate() {
+        let ts = now + chrono::Duration::milliseconds(10 * i as i64);
+        let resp = test::call_service(&service, ingest(device_id, value, unit, ts)).await;
+        assert!(resp.status().is_success());
+    }
+
+    let quantities = |body: &serde_json::Value| {
+        let mut quantities: Vec<_> = body["entry"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|e| {
+                let obs = &e["resource"];
+                (
+                    obs["valueQuantity"]["value"].as_f64().unwrap(),
+                    obs["valueQuantity"]["unit"].as_str().unwrap().to_string(),
+                    obs["valueQuantity"]["code"].as_str().map(str::to_string),
+                    obs["conversion"].as_str().map(str::to_string),
+                )
+            })
+            .collect();
+        quantities.sort_by(|a, b| a.0.total_cmp(&b.0));
+        quantities
+    };
+    let db = |value: f64| (value, "dB".to_string(), Some("dB".to_string()), None);
+    let none = |value: f64, unit: &str| (value, unit.to_string(), None, Some("none".to_string()));
+
+    let resp = test::call_service(&service, get("/api/fhir/Observation?unit=dB")).await;
+    assert_eq!(resp.status(), 200);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(
+        quantities(&body),
+        [
+            none(68.0, "dBA"),
+            db(70.0),
+            db(94.0),
+            db(94.0),
+            db(114.0),
+            none(512.0, "raw"),
+        ]
+    );
+    for entry in body["entry"].as_array().unwrap() {
+        let quantity = &entry["resource"]["valueQuantity"];
+        if quantity["unit"] == "dB" {
+            assert_eq!(quantity["system"], "http://unitsofmeasure.org");
+        } else {
+            assert!(quantity.get("system").is_none());
+        }
+    }
+
+    let resp = test::call_service(&service, get("/api/fhir/Observation?unit=db&strict=true")).await;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.headers().get("x-total-count").unwrap(), "4");
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(quantities(&body), [db(70.0), db(94.0), db(94.0), db(114.0)]);
+
+    // Left out before the page is cut, so a page is full
+    let resp = test::call_service(
+        &service,
+        get("/api/fhir/Observation?unit=dB&strict=true&limit=2"),
+    )
+    .await;
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(quantities(&body), [db(70.0), db(114.0)]);
+
+    // Without a unit, values are as stored
+    let body: serde_json::Value =
+        test::call_and_read_body_json(&service, get("/api/fhir/Observation")).await;
+    let values: Vec<_> = quantities(&body)
+        .into_iter()
+        .map(|q| (q.0, q.2, q.3))
+        .collect();
+    assert_eq!(
+        values,
+        [
+            (50.0, None, None),
+            (50.0, None, None),
+            (68.0, None, None),
+            (70.0, None, None),
+            (500.0, None, None),
+            (512.0, None, None),
+        ]
+    );
+
+    // The dashboard's last reading, converted or the latest that can be
+    let last_reading = |query: &str| {
+        let req = get(&format!("/api/dashboard/patient/p1{}", query));
+        async {
+            let body: serde_json::Value = test::call_and_read_body_json(&service, req).await;
+            body["last_reading"].clone()
+        }
+    };
+    let last = last_reading("?unit=dB").await;
+    assert_eq!(
+        (&last["value"], &last["unit"]),
+        (&68.0.into(), &"dBA".into())
+    );
+    assert_eq!(last["conversion"], "none");
+    let last = last_reading("?unit=dB&strict=true").await;
+    assert_eq!(
+        (&last["value"], &last["unit"]),
+        (&114.0.into(), &"dB".into())
+    );
+    assert_eq!(last["device_id"], "d-adc");
+    assert!(last.get("conversion").is_none());
+    assert_eq!(last_reading("").await["value"], 68.0);
+
+    // The hourly pattern in dB takes the converted readings
+    let body: serde_json::Value = test::call_and_read_body_json(
+        &service,
+        get("/api/observations/hourly-pattern?patient=p1&days=1"),
+    )
+    .await;
+    let hours = body["hours"].as_array().unwrap();
+    let count: u64 = hours.iter().map(|h| h["count"].as_u64().unwrap()).sum();
+    assert_eq!(count, 4);
+    let max = hours
+        .iter()
+        .filter_map(|h| h["max"].as_f64())
+        .fold(0.0, f64::max);
+    assert_eq!(max, 114.0);
+
+    for path in [
+        "/api/fhir/Observation?unit=volts",
+        "/api/dashboard/patient/p1?unit=volts",
+    ] {
+        assert_eq!(test::call_service(&service, get(path)).await.status(), 400);
+    }
+}
+
+#[actix_web::test]
+async fn admins_set_device_offline_grace_and_status() {
+    let state = web::Data::new(Arc::new(Mutex::new(common::demo_state())));
+    let app =
+        test::init_service(App::new().app_data(state.clone()).configure(common::routes)).await;
+    let put = |role: &str, body: serde_json::Value| {
+        test::TestRequest::put()
+            .uri("/api/devices/dev-7")
+            .insert_header(("authorization", format!("Bearer {}", common::token(role))))
+            .set_json(body)
+            .to_request()
+    };
+
+    let resp = test::call_service(
+        &app,
+        put("admin", serde_json::json!({ "offline_grace_secs": 3600 })),
+    )
+    .await;
+    assert_eq!(resp.status(), 200);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["device_id"], "dev-7");
+    assert_eq!(body["status"], "active");
+    assert_eq!(body["offline_grace_secs"], 3600);
+
+    let resp = test::call_service(
+        &app,
+        put("admin", serde_json::json!({ "status": "decommissioned" })),
+    )
+    .await;
+    assert_eq!(resp.status(), 200);
+    let settings = state.lock().await.device_settings().all().await.unwrap();
+    assert_eq!(settings["dev-7"].status, DeviceStatus::Decommissioned);
+    assert_eq!(settings["dev-7"].offline_grace_secs, None);
+
+    for bad in [
+        serde_json::json!({ "offline_grace_secs": 0 }),
+        serde_json::json!({ "status": "retired" }),
+    ] {
+        let resp = test::call_service(&app, put("admin", bad)).await;
+        assert_eq!(resp.status(), 400);
+    }
+    let resp = test::call_service(&app, put("user", serde_json::json!({}))).await;
+    assert_eq!(resp.status(), 401);
+}
+
+#[actix_web::test]
+async fn weekly_patient_summary_as_json_and_pdf() {
+    let app = TestApp::new().service().await;
+    let auth = format!("Bearer {}", common::token("user"));
+
+    // 80 dB the week before; 90 dB twice and 82 dB once this week, peaking
+    // at 97 dB
+    for (value, ts) in [
+        (80.0, "2024-01-10T09:00:00Z"),
+        (90.0, "2024-01-15T09:00:00Z"),
+        (90.0, "2024-01-16T09:00:00Z"),
+        (82.0, "2024-01-17T09:00:00Z"),
+        (97.0, "2024-01-16T14:30:00Z"),
+        (120.0, "2024-01-22T09:00:00Z"),
+    ] {
+        let req = test::TestRequest::post()
+            .uri("/api/ingest")
+            .insert_header(("authorization", auth.clone()))
+            .set_json(serde_json::json!({
+                "patient_id": "p-week",
+                "device_id": "d1",
+                "code": "sound",
+                "value": value,
+                "unit": "dB",
+                "ts": ts,
+            }))
+            .to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+    }
+
+    let get = |uri: &str| {
+        test::TestRequest::get()
+            .uri(uri)
+            .insert_header(("authorization", auth.clone()))
+            .to_request()
+    };
+
+    let body: serde_json::Value = test::call_and_read_body_json(
+        &app,
+        get("/api/reports/patient-summary/p-week?week_start=2024-01-15"),
+    )
+    .await;
+    assert_eq!(body["patient_id"], "p-week");
+    assert_eq!(body["week_start"], "2024-01-15");
+    assert_eq!(body["week_end"], "2024-01-21");
+    assert_eq!(body["days_exceeding_limit"], 2);
+    assert_eq!(body["peak_reading_db"], 97.0);
+    assert_eq!(body["peak_reading_time"], "2024-01-16T14:30:00Z");
+    assert_eq!(body["trend"], "Worsening");
+    assert!(body["avg_daily_twa_db"].as_f64().unwrap() > 85.0);
+    assert!(body["recommendation_text"]
+        .as_str()
+        .unwrap()
+        .contains("hearing protection"));
+
+    let resp = test::call_service(
+        &app,
+        get("/api/reports/patient-summary/p-week.pdf?week_start=2024-01-15"),
+    )
+    .await;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(
+        resp.headers().get("content-type").unwrap(),
+        "application/pdf"
+    );
+    let pdf = test::read_body(resp).await;
+    assert!(pdf.starts_with(b"%PDF-"));
+
+    let resp = test::call_service(
+        &app,
+        get("/api/reports/patient-summary/p-week?week_start=2023-01-02"),
+    )
+    .await;
+    assert_eq!(resp.status(), 404);
+    let resp = test::call_service(
+        &app,
+        get("/api/reports/patient-summary/p-week?week_start=last-monday"),
+    )
+    .await;
+    assert_eq!(resp.status(), 400);
+    // Weeks running past the last or first representable day
+    for week_start in [chrono::NaiveDate::MAX, chrono::NaiveDate::MIN] {
+        let uri = format!(
+            "/api/reports/patient-summary/p-week?week_start={}",
+            week_start.to_string().replace('+', "%2B")
+        );
+        let resp = test::call_service(&app, get(&uri)).await;
+        assert_eq!(resp.status(), 400, "{}", week_start);
+        let body = test::read_body(resp).await;
+        assert!(String::from_utf8_lossy(&body).contains("out of range"));
+        let resp = test::call_service(&app, get(&uri.replace("p-week?", "p-week.pdf?"))).await;
+        assert_eq!(resp.status(), 400, "{}", week_start);
+    }
+
+    let device = test::TestRequest::get()
+        .uri("/api/reports/patient-summary/p-week?week_start=2024-01-15")
+        .insert_header((
+            "authorization",
+            format!("Bearer {}", common::token("device")),
+        ))
+        .to_request();
+    assert_eq!(test::call_service(&app, device).await.status(), 403);
+}
+
+#[actix_web::test]
+async fn patient_dashboard_has_every_field_in_range() {
+    let harness = TestApp::new().dashboards(Dashboards::new(
+        std::time::Duration::from_secs(5),
+        3,
+        std::time::Duration::from_secs(300),
+    ));
+    let app = harness.service().await;
+    let auth = format!("Bearer {}", common::token("user"));
+
+    let now = chrono::Utc::now();
+    for (value, ago) in [(70.0, 30), (76.0, 1)] {
+        let req = harness.ingest(&serde_json::json!({
+            "patient_id": "p-dash",
+            "device_id": "d1",
+            "code": "sound",
+            "value": value,
+            "unit": "dB",
+            "ts": now - chrono::Duration::seconds(ago),
+        }));
+        assert!(test::call_service(&app, req).await.status().is_success());
+    }
+
+    let get = |token: &str| {
+        test::TestRequest::get()
+            .uri("/api/dashboard/patient/p-dash")
+            .insert_header(("authorization", token.to_string()))
+            .to_request()
+    };
+    let body: serde_json::Value = test::call_and_read_body_json(&app, get(&auth)).await;
+    for key in [
+        "patient_id",
+        "last_reading",
+        "current_hour_avg",
+        "noise_dose_today_percent",
+        "alert_count_today",
+        "active_alerts",
+        "device_status",
+        "trend_24h",
+    ] {
+        assert!(body.get(key).is_some(), "missing {}", key);
+    }
+    assert_eq!(body["patient_id"], "p-dash");
+    assert_eq!(body["last_reading"]["value"], 76.0);
+    assert_eq!(body["last_reading"]["quality"], "good");
+    if let Some(avg) = body["current_hour_avg"].as_f64() {
+        assert!((70.0..=76.0).contains(&avg));
+    }
+    let dose = body["noise_dose_today_percent"].as_f64().unwrap();
+    assert!(dose > 0.0 && dose < 100.0);
+    assert!(
+        body["alert_count_today"].as_u64().unwrap()
+            >= body["active_alerts"].as_array().unwrap().len() as u64
+    );
+    assert_eq!(body["device_status"][0]["device_id"], "d1");
+    assert_eq!(body["device_status"][0]["online"], true);
+    let trend = body["trend_24h"].as_array().unwrap();
+    assert_eq!(trend.len(), 24);
+    assert!(trend.iter().any(|h| h["avg_db"].is_number()));
+    assert!(trend
+        .iter()
+        .filter_map(|h| h["avg_db"].as_f64())
+        .all(|db| (70.0..=76.0).contains(&db)));
+
+    let device = format!("Bearer {}", common::token("device"));
+    assert_eq!(test::call_service(&app, get(&device)).await.status(), 403);
+
+    // Both tokens are for the same account, so this is its third request
+    // of the minute and the last one allowed
+    assert_eq!(test::call_service(&app, get(&auth)).await.status(), 200);
+    let resp = test::call_service(&app, get(&auth)).await;
+    assert_eq!(resp.status(), 429);
+    assert!(resp.headers().contains_key("retry-after"));
+}
+
+#[actix_web::test]
+async fn hourly_pattern_uses_local_hours() {
+    use chrono::Timelike;
+
+    let harness = TestApp::new();
+    let app = harness.service().await;
+    let auth = format!("Bearer {}", common::token("user"));
+
+    // Two readings two hours ago, one five hours ago
+    let now = chrono::Utc::now();
+    let earlier = now - chrono::Duration::hours(2);
+    let earliest = now - chrono::Duration::hours(5);
+    // and, in other units, ones that must not be averaged with them
+    for (value, unit, ts) in [
+        (60.0, "dB", earlier),
+        (80.0, "dB", earlier),
+        (40.0, "dB", earliest),
+        (900.0, "raw", earlier),
+        (95.0, "dBA", earlier),
+    ] {
+        let req = harness.ingest(&serde_json::json!({
+            "patient_id": "p-hours",
+            "device_id": "d1",
+            "code": "sound",
+            "value": value,
+            "unit": unit,
+            "ts": ts,
+        }));
+        assert!(test::call_service(&app, req).await.status().is_success());
+    }
+
+    let get = |query: &str| {
+        test::TestRequest::get()
+            .uri(&format!(
+                "/api/observations/hourly-pattern?patient=p-hours{}",
+                query
+            ))
+            .insert_header(("authorization", auth.clone()))
+            .to_request()
+    };
+    let body: serde_json::Value =
+        test::call_and_read_body_json(&app, get("&days=1&tz=%2B03:00&min_count=2")).await;
+    assert_eq!(body["tz"], "+03:00");
+    assert_eq!(body["unit"], "dB");
+    let hours = body["hours"].as_array().unwrap();
+    assert_eq!(hours.len(), 24);
+    let local_hour = |ts: chrono::DateTime<chrono::Utc>| ((ts.hour() + 3) % 24) as usize;
+    let busy = &hours[local_hour(earlier)];
+    assert_eq!(busy["count"], 2);
+    assert_eq!(busy["mean"], 70.0);
+    assert_eq!(busy["max"], 80.0);
+    assert_eq!(hours[local_hour(earliest)]["count"], 1);
+    // The sparse hour is too thin to be the quietest
+    assert_eq!(body["loudest_hour"], local_hour(earlier));
+    assert_eq!(body["quietest_hour"], local_hour(earlier));
+    let total: u64 = hours.iter().map(|h| h["count"].as_u64().unwrap()).sum();
+    assert_eq!(total, 3);
+
+    // Each unit has its own pattern
+    let body: serde_json::Value =
+        test::call_and_read_body_json(&app, get("&days=1&tz=%2B03:00&unit=raw")).await;
+    assert_eq!(body["unit"], "raw");
+    assert_eq!(body["hours"][local_hour(earlier)]["mean"], 900.0);
+    let total: u64 = body["hours"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|h| h["count"].as_u64().unwrap())
+        .sum();
+    assert_eq!(total, 1);
+
+    for bad in [
+        "&tz=03:00",
+        "&tz=Africa/Nairobi",
+        "&days=91",
+        "&days=0",
+        "&unit=%20",
+    ] {
+        let resp = test::call_service(&app, get(bad)).await;
+        assert_eq!(resp.status(), 400, "{}", bad);
+    }
+}
@@ -3,10 +3,12 @@ FastAPI service for ML predictions and training.
 
 Endpoints:
 - POST /predict - Get predictions for recent readings
+- POST /score - Score a single reading for anomalies
 - POST /train - Trigger model training
 - GET /health - Health check
 - GET /stats - Get model statistics
 - GET /analysis - Get pattern analysis
+- POST /analysis - Pattern analysis of supplied readings
 """
 
 from fastapi import FastAPI, HTTPException, BackgroundTasks
@@ -15,6 +17,7 @@ from pydantic import BaseModel, Field
 from typing import List, Optional, Dict, Any
 from datetime import datetime
 import os
+import pandas as pd
 import uvicorn
 from loguru import logger
 
@@ -52,6 +55,9 @@ db = SoundDatabase(DATABASE_URL)
 classifier.load_classifier()
 classifier.load_anomaly_detector()
 
+# Progress of training runs started with a job_id, polled by the backend
+training_jobs: Dict[str, Dict[str, Any]] = {}
+
 
 # Pydantic models
 class PredictionRequest(BaseModel):
@@ -74,6 +80,9 @@ class TrainingRequest(BaseModel):
     min_samples: int = Field(
         default=100, ge=10, description="Minimum samples for training"
     )
+    job_id: Optional[str] = Field(
+        default=None, description="Backend job id used to report progress"
+    )
 
 
 class TrainingResponse(BaseModel):
@@ -82,9 +91,41 @@ class TrainingResponse(BaseModel):
     samples_used: int
 
 
+class ScoreSample(BaseModel):
+    value: float
+    timestamp: datetime
+
+
+class ScoreRequest(BaseModel):
+    readings: List[ScoreSample] = Field(
+        ..., min_length=1, max_length=50, description="Recent readings, oldest first"
+    )
+
+
+class ScoreResponse(BaseModel):
+    is_anomaly: bool
+    anomaly_score: float
+    category: str
+
+
+class TrainingStatusResponse(BaseModel):
+    status: str
+    progress_percent: float
+    message: str
+
+
+class AnalysisRequest(BaseModel):
+    patient_id: Optional[str] = None
+    readings: List[ScoreSample] = Field(
+        ..., min_length=1, max_length=10000, description="Readings to analyze"
+    )
+
+
 class AnalysisResponse(BaseModel):
     success: bool
     analysis: Dict[str, Any]
+    # Echoed so callers can tell the readings were filtered by patient
+    patient_id: Optional[str] = None
 
 
 class HealthResponse(BaseModel):
@@ -169,6 +210,31 @@ async def predict(request: PredictionRequest):
         raise HTTPException(status_code=500, detail=str(e))
 
 
+@app.post("/score", response_model=ScoreResponse)
+async def score_reading(request: ScoreRequest):
+    """
+    Score the last of the given readings for anomalies.
+
+    Earlier readings only provide context for the rolling features.
+    """
+    if classifier.anomaly_detector is None:
+        raise HTTPException(status_code=503, detail="Anomaly detector not trained")
+
+    try:
+        df = pd.DataFrame([r.model_dump() for r in request.readings])
+        result = classifier.predict(df).sort_values("timestamp").iloc[-1]
+
+        return ScoreResponse(
+            is_anomaly=bool(result["is_anomaly"]),
+            anomaly_score=float(result["anomaly_score"]),
+            category=result["category_ml"] or result["category_rule"],
+        )
+
+    except Exception as e:
+        logger.error(f"Scoring failed: {e}")
+        raise HTTPException(status_code=500, detail=str(e))
+
+
 @app.post("/train", response_model=TrainingResponse)
 async def train_models(request: TrainingRequest, background_tasks: BackgroundTasks):
     """
@@ -193,12 +259,31 @@ async def train_models(request: TrainingRequest, background_tasks: BackgroundTas
                 status_code=400, detail="Failed to create training dataset"
             )
 
+        job_id = request.job_id
+
+        def report(status: str, progress_percent: float, message: str):
+            if job_id:
+                training_jobs[job_id] = {
+                    "status": status,
+                    "progress_percent": progress_percent,
+                    "message": message,
+                }
+
+        report("in_progress", 0.0, "Training queued")
+
         # Train models in background
         def train_task():
-            logger.info("Starting model training...")
-            classifier.train_classifier(training_df)
-            classifier.train_anomaly_detector(training_df[["value", "timestamp"]])
-            logger.info("Model training completed")
+            try:
+                logger.info("Starting model training...")
+                report("in_progress", 10.0, "Training classifier")
+                classifier.train_classifier(training_df)
+                report("in_progress", 60.0, "Training anomaly detector")
+                classifier.train_anomaly_detector(training_df[["value", "timestamp"]])
+                report("completed", 100.0, "Model training completed")
+                logger.info("Model training completed")
+            except Exception as e:
+                logger.error(f"Background training failed: {e}")
+                report("failed", 0.0, str(e))
 
         background_tasks.add_task(train_task)
 
@@ -215,21 +300,54 @@ async def train_models(request: TrainingRequest, background_tasks: BackgroundTas
         raise HTTPException(status_code=500, detail=str(e))
 
 
+@app.get("/training/status/{job_id}", response_model=TrainingStatusResponse)
+async def training_status(job_id: str):
+    """
+    Report progress of a training run started with this job_id.
+    """
+    job = training_jobs.get(job_id)
+    if job is None:
+        raise HTTPException(status_code=404, detail=f"Unknown training job {job_id}")
+    return TrainingStatusResponse(**job)
+
+
 @app.get("/analysis", response_model=AnalysisResponse)
-async def get_analysis(limit: int = 1000, hours_back: Optional[int] = None):
+async def get_analysis(
+    limit: int = 1000,
+    hours_back: Optional[int] = None,
+    start: Optional[datetime] = None,
+    end: Optional[datetime] = None,
+    patient_id: Optional[str] = None,
+):
     """
     Get comprehensive pattern analysis of sound data.
 
     Args:
         limit: Maximum readings to analyze
         hours_back: Only analyze last N hours
+        start: Start of a date range; with end, analyze every reading in it
+            instead of the latest `limit`
+        end: End of the date range
+        patient_id: Only analyze this patient's readings
 
     Returns:
         Detailed pattern analysis including trends, peaks, anomalies
     """
+    if (start is None) != (end is None):
+        raise HTTPException(
+            status_code=400, detail="start and end must be given together"
+        )
+    if start is not None and end <= start:
+        raise HTTPException(status_code=400, detail="end must be after start")
+
     try:
         # Fetch data
-        df = db.fetch_recent_readings(limit=limit, hours_back=hours_back)
+        if start is not None:
+            df = db.fetch_readings_by_time_range(start, end, patient_id=patient_id)
+        else:
+            df = db.fetch_recent_readings(
+                limit=limit, hours_back=hours_back, patient_id=patient_id
+            )
 
         if len(df) == 0:
             raise HTTPException(status_code=404, detail="No readings found")
@@ -240,7 +358,27 @@ async def get_analysis(limit: int = 1000, hours_back: Optional[int] = None):
         # Analyze patterns
         analysis = classifier.analyze_patterns(predictions_df)
 
-        return AnalysisResponse(success=True, analysis=analysis)
+        return AnalysisResponse(success=True, analysis=analysis, patient_id=patient_id)
+
+    except Exception as e:
+        logger.error(f"Analysis failed: {e}")
+        raise HTTPException(status_code=500, detail=str(e))
+
+
+@app.post("/analysis", response_model=AnalysisResponse)
+async def analyze_readings(request: AnalysisRequest):
+    """
+    Pattern analysis of readings supplied by the caller, e.g. one patient's
+    readings selected by the backend.
+    """
+    try:
+        df = pd.DataFrame([r.model_dump() for r in request.readings])
+        predictions_df = classifier.predict(df)
+        analysis = classifier.analyze_patterns(predictions_df)
+
+        return AnalysisResponse(
+            success=True, analysis=analysis, patient_id=request.patient_id
+        )
 
     except Exception as e:
         logger.error(f"Analysis failed: {e}")
@@ -28,7 +28,10 @@ class SoundDatabase:
         logger.info("Database connection initialized")
 
     def fetch_recent_readings(
-        self, limit: int = 1000, hours_back: Optional[int] = None
+        self,
+        limit: int = 1000,
+        hours_back: Optional[int] = None,
+        patient_id: Optional[str] = None,
     ) -> pd.DataFrame:
         """
         Fetch recent sensor readings.
@@ -36,6 +39,7 @@ class SoundDatabase:
         Args:
             limit: Maximum number of readings to fetch
             hours_back: If set, only fetch readings from last N hours
+            patient_id: If set, only fetch this patient's readings
 
         Returns:
             DataFrame with columns: id, patient_id, device_id, code, value, unit, timestamp
@@ -51,17 +55,20 @@ class SoundDatabase:
                 timestamp,
                 created_at
             FROM sensor_readings
+            WHERE status <> 'entered-in-error'
         """
 
         if hours_back is not None:
             cutoff = datetime.utcnow() - timedelta(hours=hours_back)
-            query += f" WHERE timestamp >= '{cutoff.isoformat()}'"
+            query += f" AND timestamp >= '{cutoff.isoformat()}'"
+        if patient_id is not None:
+            query += " AND patient_id = :patient_id"
 
         query += " ORDER BY timestamp DESC"
         query += f" LIMIT {limit}"
 
         with self.engine.connect() as conn:
-            df = pd.read_sql(text(query), conn)
+            df = pd.read_sql(text(query), conn, params={"patient_id": patient_id})
 
         logger.info(f"Fetched {len(df)} readings from database")
         return df
@@ -90,7 +97,7 @@ class SoundDatabase:
                 timestamp,
                 created_at
             FROM sensor_readings
-            WHERE patient_id = :patient_id
+            WHERE patient_id = :patient_id AND status <> 'entered-in-error'
             ORDER BY timestamp DESC
             LIMIT :limit
         """
@@ -104,7 +111,10 @@ class SoundDatabase:
         return df
 
     def fetch_readings_by_time_range(
-        self, start_time: datetime, end_time: datetime
+        self,
+        start_time: datetime,
+        end_time: datetime,
+        patient_id: Optional[str] = None,
     ) -> pd.DataFrame:
         """
         Fetch readings within a time range.
@@ -112,6 +122,7 @@ class SoundDatabase:
         Args:
             start_time: Start of time range
             end_time: End of time range
+            patient_id: If set, only fetch this patient's readings
 
         Returns:
             DataFrame with readings
@@ -128,6 +139,8 @@ class SoundDatabase:
                 created_at
             FROM sensor_readings
             WHERE timestamp BETWEEN :start_time AND :end_time
+                AND status <> 'entered-in-error'
+                AND (CAST(:patient_id AS TEXT) IS NULL OR patient_id = :patient_id)
             ORDER BY timestamp ASC
         """
 
@@ -135,7 +148,11 @@ class SoundDatabase:
             df = pd.read_sql(
                 text(query),
                 conn,
-                params={"start_time": start_time, "end_time": end_time},
+                params={
+                    "start_time": start_time,
+                    "end_time": end_time,
+                    "patient_id": patient_id,
+                },
             )
 
         logger.info(f"Fetched {len(df)} readings between {start_time} and {end_time}")
@@ -158,6 +175,7 @@ class SoundDatabase:
                 AVG(value) as avg_value,
                 STDDEV(value) as stddev_value
             FROM sensor_readings
+            WHERE status <> 'entered-in-error'
         """
 
         with self.engine.connect() as conn: